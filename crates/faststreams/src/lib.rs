@@ -15,10 +15,41 @@ pub const FLAG_LZ4: u8 = 0x01;
 pub const FLAG_RKYV: u8 = 0x02;
 /// Header checksum present (CRC16 over bytes [0..8) is set in header)
 pub const FLAG_HAS_CHECKSUM: u8 = 0x04;
+/// Record was reconstructed after the fact (e.g. RPC backfill) rather than streamed live
+pub const FLAG_BACKFILL: u8 = 0x08;
+/// Payload starts with the producer's origin timestamp (u64 BE unix nanos, see [`EncodeOptions::with_origin`])
+pub const FLAG_ORIGIN_TS: u8 = 0x10;
+/// Payload is a zstd frame (cache feed frames only, see [`compress_cache_feed_frame`])
+pub const FLAG_ZSTD: u8 = 0x20;
 /// Endianness indicator: if set, fields are little-endian (reserved; we currently write BE)
 pub const FLAG_ENDIAN_LE: u8 = 0x80;
 
-pub const FRAME_VERSION: u8 = 1;
+/// v2 added `BlockMeta::block_height`. Upgrade readers before producers:
+/// readers decode both layouts, v1 readers reject v2 frames.
+pub const FRAME_VERSION: u8 = 2;
+/// Oldest header version the decoders still accept, so mixed producer builds
+/// can feed one reader during rolling upgrades.
+pub const MIN_FRAME_VERSION: u8 = 1;
+/// Last version whose records use the [`legacy`] layout.
+const LEGACY_RECORD_VERSION: u8 = 1;
+pub const FRAME_HEADER_LEN: usize = 12;
+pub const ORIGIN_TS_LEN: usize = 8;
+/// Header type of a connection's opening auth frame; the payload is the raw shared token.
+pub const RECORD_TYPE_AUTH: u16 = 0x7F01;
+/// Header types of the snapshot and delta streams a bridge serves to an RPC cache (see [`CacheFeed`]).
+pub const RECORD_TYPE_SNAPSHOT_SEGMENT: u16 = 0x20;
+pub const RECORD_TYPE_SNAPSHOT_COMPLETE: u16 = 0x21;
+pub const RECORD_TYPE_DELTA_BATCH: u16 = 0x22;
+pub const RECORD_TYPE_SLOT_BATCH: u16 = 0x23;
+pub const RECORD_TYPE_TX_BATCH: u16 = 0x24;
+pub const RECORD_TYPE_FEED_HELLO: u16 = 0x25;
+pub const RECORD_TYPE_FEED_HELLO_ACK: u16 = 0x26;
+/// Version of the [`CacheFeed`] protocol, exchanged in `Hello`/`HelloAck`.
+/// v2 added compression negotiation.
+pub const CACHE_FEED_VERSION: u16 = 2;
+// Cache feed frames are compressed on the hot path; favour speed over ratio
+#[cfg(feature = "zstd")]
+const CACHE_FEED_ZSTD_LEVEL: i32 = 1;
 
 // New 12-byte header layout:
 // [0]  u8  version
@@ -100,7 +131,7 @@ pub struct AccountUpdate {
     derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
 )]
 #[cfg_attr(feature = "rkyv", archive_attr(derive(bytecheck::CheckBytes)))]
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TxUpdate {
     pub slot: u64,
     #[serde(with = "serde_bytes")]
@@ -124,6 +155,7 @@ pub struct BlockMeta {
     pub block_time_unix: Option<i64>,
     #[serde(with = "serde_bytes")]
     pub leader: Option<[u8; 32]>,
+    pub block_height: Option<u64>,
 }
 
 #[cfg_attr(
@@ -144,6 +176,183 @@ pub enum Record {
     EndOfStartup,
 }
 
+/// Record layout of v1 frames, still written by older producers and found
+/// in older captures.
+mod legacy {
+    use super::{AccountUpdate, BlockMeta, Record, TxUpdate};
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub(super) struct BlockMetaV1 {
+        slot: u64,
+        #[serde(with = "serde_bytes")]
+        blockhash: Option<[u8; 32]>,
+        parent_slot: Option<u64>,
+        rewards_len: u32,
+        block_time_unix: Option<i64>,
+        #[serde(with = "serde_bytes")]
+        leader: Option<[u8; 32]>,
+    }
+
+    #[derive(Deserialize)]
+    pub(super) enum RecordV1 {
+        Account(AccountUpdate),
+        Tx(TxUpdate),
+        Block(BlockMetaV1),
+        Slot {
+            slot: u64,
+            parent: Option<u64>,
+            status: u8,
+        },
+        EndOfStartup,
+    }
+
+    impl From<RecordV1> for Record {
+        fn from(rec: RecordV1) -> Self {
+            match rec {
+                RecordV1::Account(account) => Record::Account(account),
+                RecordV1::Tx(tx) => Record::Tx(tx),
+                RecordV1::Block(b) => Record::Block(BlockMeta {
+                    slot: b.slot,
+                    blockhash: b.blockhash,
+                    parent_slot: b.parent_slot,
+                    rewards_len: b.rewards_len,
+                    block_time_unix: b.block_time_unix,
+                    leader: b.leader,
+                    block_height: None,
+                }),
+                RecordV1::Slot {
+                    slot,
+                    parent,
+                    status,
+                } => Record::Slot {
+                    slot,
+                    parent,
+                    status,
+                },
+                RecordV1::EndOfStartup => Record::EndOfStartup,
+            }
+        }
+    }
+}
+
+/// Account contents as an RPC cache stores them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    pub pubkey: [u8; 32],
+    pub lamports: u64,
+    pub owner: [u8; 32],
+    pub executable: bool,
+    pub rent_epoch: u64,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// A change to one account at `slot`; `None` removes it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountDelta {
+    pub pubkey: [u8; 32],
+    pub slot: u64,
+    pub account: Option<AccountState>,
+}
+
+/// A slot status change, as in [`Record::Slot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotStatus {
+    pub slot: u64,
+    pub parent: Option<u64>,
+    pub status: u8,
+}
+
+/// Messages on the snapshot and delta streams from a bridge to an RPC cache.
+/// The snapshot stream carries `SnapshotSegment`s until it closes. The delta
+/// stream opens with the bridge's `Hello`, answered by the RPC's `HelloAck`,
+/// then `SnapshotComplete`, and then carries `Deltas`, `Slots` and
+/// `Transactions` in the order the bridge saw them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CacheFeed {
+    SnapshotSegment {
+        base_slot: u64,
+        accounts: Vec<AccountState>,
+    },
+    SnapshotComplete {
+        slot: u64,
+    },
+    Deltas(Vec<AccountDelta>),
+    Slots(Vec<SlotStatus>),
+    Transactions(Vec<TxUpdate>),
+    /// What the bridge will send: protocol version, whether slot and
+    /// transaction statuses are included, the last snapshot served, and the
+    /// compressions it offers, preferred first.
+    Hello {
+        version: u16,
+        slots: bool,
+        transactions: bool,
+        base_slot: Option<u64>,
+        compression: Vec<FeedCompression>,
+    },
+    /// The client's answer to `Hello`, with the version it speaks and the
+    /// offered compression it picked.
+    HelloAck {
+        version: u16,
+        compression: FeedCompression,
+    },
+}
+
+/// Payload compression of a delta stream, agreed in `Hello`/`HelloAck`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeedCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl FeedCompression {
+    /// Whether this build can decode frames compressed this way.
+    pub fn is_supported(self) -> bool {
+        match self {
+            FeedCompression::None | FeedCompression::Lz4 => true,
+            FeedCompression::Zstd => cfg!(feature = "zstd"),
+        }
+    }
+
+    /// The first of `offered` this build supports, else `None`.
+    pub fn choose(offered: &[FeedCompression]) -> FeedCompression {
+        offered
+            .iter()
+            .copied()
+            .find(|c| c.is_supported())
+            .unwrap_or(FeedCompression::None)
+    }
+}
+
+impl std::str::FromStr for FeedCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FeedCompression::None),
+            "lz4" => Ok(FeedCompression::Lz4),
+            "zstd" => Ok(FeedCompression::Zstd),
+            other => Err(format!("unknown compression {other:?} (none, lz4, zstd)")),
+        }
+    }
+}
+
+impl CacheFeed {
+    fn type_tag(&self) -> u16 {
+        match self {
+            CacheFeed::SnapshotSegment { .. } => RECORD_TYPE_SNAPSHOT_SEGMENT,
+            CacheFeed::SnapshotComplete { .. } => RECORD_TYPE_SNAPSHOT_COMPLETE,
+            CacheFeed::Deltas(_) => RECORD_TYPE_DELTA_BATCH,
+            CacheFeed::Slots(_) => RECORD_TYPE_SLOT_BATCH,
+            CacheFeed::Transactions(_) => RECORD_TYPE_TX_BATCH,
+            CacheFeed::Hello { .. } => RECORD_TYPE_FEED_HELLO,
+            CacheFeed::HelloAck { .. } => RECORD_TYPE_FEED_HELLO_ACK,
+        }
+    }
+}
+
 // Borrowing variants for zero-copy encoding on producers
 #[derive(Debug, Serialize)]
 pub struct AccountUpdateRef<'a> {
@@ -181,6 +390,9 @@ pub struct EncodeOptions {
     pub compress_threshold: usize,
     pub payload_hint: Option<usize>,
     pub format: PayloadFormat,
+    /// When set, the frame is written with this producer timestamp ahead of
+    /// the payload (see [`FLAG_ORIGIN_TS`]).
+    pub origin_unix_ns: Option<u64>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -197,6 +409,7 @@ impl EncodeOptions {
             compress_threshold: COMPRESS_THRESHOLD,
             payload_hint: Some(AVG_LEN.load(Ordering::Relaxed)),
             format: PayloadFormat::Bincode,
+            origin_unix_ns: None,
         }
     }
     pub fn latency_uds() -> Self {
@@ -209,6 +422,7 @@ impl EncodeOptions {
             format: PayloadFormat::Rkyv,
             #[cfg(not(feature = "rkyv"))]
             format: PayloadFormat::Bincode,
+            origin_unix_ns: None,
         }
     }
     /// Throughput-oriented remote hop: enable LZ4 with a low threshold to
@@ -219,8 +433,15 @@ impl EncodeOptions {
             compress_threshold: 512,
             payload_hint: Some(AVG_LEN.load(Ordering::Relaxed)),
             format: PayloadFormat::Bincode,
+            origin_unix_ns: None,
         }
     }
+    /// Stamp frames encoded with these options with the time the producer
+    /// first saw the data, so readers can measure end-to-end lag.
+    pub fn with_origin(mut self, origin_unix_ns: u64) -> Self {
+        self.origin_unix_ns = Some(origin_unix_ns);
+        self
+    }
 }
 
 pub fn encode_record_with(rec: &Record, opts: EncodeOptions) -> Result<Vec<u8>, StreamError> {
@@ -283,14 +504,22 @@ fn encode_value_with_type<T: Serialize>(
             flags |= FLAG_RKYV;
         }
         flags |= FLAG_HAS_CHECKSUM;
-        buf.reserve(12 + body.len());
+        let mut payload_len = body.len();
+        if opts.origin_unix_ns.is_some() {
+            flags |= FLAG_ORIGIN_TS;
+            payload_len += ORIGIN_TS_LEN;
+        }
+        buf.reserve(12 + payload_len);
         buf.extend_from_slice(&FRAME_HEADER_TEMPLATE);
         // version already set at [0]
         buf[1] = flags; // flags (includes checksum bit)
         buf[2..4].copy_from_slice(&typ.to_be_bytes());
-        buf[4..8].copy_from_slice(&(body.len() as u32).to_be_bytes());
+        buf[4..8].copy_from_slice(&(payload_len as u32).to_be_bytes());
         let crc = crc16_ccitt(&buf[0..8]);
         buf[8..10].copy_from_slice(&crc.to_be_bytes());
+        if let Some(origin) = opts.origin_unix_ns {
+            buf.extend_from_slice(&origin.to_be_bytes());
+        }
         buf.extend_from_slice(&body);
         return Ok(());
     }
@@ -306,6 +535,10 @@ fn encode_value_with_type<T: Serialize>(
         flags |= FLAG_RKYV;
     }
     flags |= FLAG_HAS_CHECKSUM;
+    if let Some(origin) = opts.origin_unix_ns {
+        flags |= FLAG_ORIGIN_TS;
+        buf.extend_from_slice(&origin.to_be_bytes());
+    }
     buf[1] = flags;
     buf[2..4].copy_from_slice(&typ.to_be_bytes());
     bincode_opts.serialize_into(&mut *buf, val)?;
@@ -320,6 +553,271 @@ fn encode_value_with_type<T: Serialize>(
     Ok(())
 }
 
+/// Flags byte of an encoded frame, or `None` if the header is short or fails its checksum.
+pub fn frame_flags(frame: &[u8]) -> Option<u8> {
+    if frame.len() < 12 || !(MIN_FRAME_VERSION..=FRAME_VERSION).contains(&frame[0]) {
+        return None;
+    }
+    let hdr_crc = u16::from_be_bytes([frame[8], frame[9]]);
+    (crc16_ccitt(&frame[0..8]) == hdr_crc).then_some(frame[1])
+}
+
+/// OR `extra` into the flags of an already-encoded frame and refresh the header checksum.
+pub fn set_frame_flags(frame: &mut [u8], extra: u8) -> Result<(), StreamError> {
+    if frame_flags(frame).is_none() {
+        return Err(StreamError::BadHeader);
+    }
+    frame[1] |= extra;
+    let crc = crc16_ccitt(&frame[0..8]);
+    frame[8..10].copy_from_slice(&crc.to_be_bytes());
+    Ok(())
+}
+
+/// Prefix the payload of an already-encoded frame with `origin_unix_ns`, the
+/// time the producer first saw the data, so readers can measure end-to-end lag.
+/// A frame that is already stamped has its timestamp replaced in place; an
+/// unstamped one has its payload moved, so hot paths should encode with
+/// [`EncodeOptions::with_origin`] instead.
+pub fn stamp_origin(frame: &mut Vec<u8>, origin_unix_ns: u64) -> Result<(), StreamError> {
+    let Some(flags) = frame_flags(frame) else {
+        return Err(StreamError::BadHeader);
+    };
+    let ts = origin_unix_ns.to_be_bytes();
+    if (flags & FLAG_ORIGIN_TS) != 0 {
+        let at = FRAME_HEADER_LEN..FRAME_HEADER_LEN + ORIGIN_TS_LEN;
+        let Some(slot) = frame.get_mut(at) else {
+            return Err(StreamError::BadHeader);
+        };
+        slot.copy_from_slice(&ts);
+        return Ok(());
+    }
+    let len = u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]) as usize + ORIGIN_TS_LEN;
+    frame.splice(FRAME_HEADER_LEN..FRAME_HEADER_LEN, ts);
+    frame[1] |= FLAG_ORIGIN_TS;
+    frame[4..8].copy_from_slice(&(len as u32).to_be_bytes());
+    let crc = crc16_ccitt(&frame[0..8]);
+    frame[8..10].copy_from_slice(&crc.to_be_bytes());
+    Ok(())
+}
+
+/// Frame a producer sends first on a connection to a reader that requires a
+/// shared token (see [`RECORD_TYPE_AUTH`]). Readers that do not expect one fail
+/// to decode it as a record and skip it.
+pub fn encode_auth_frame(token: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + token.len());
+    buf.extend_from_slice(&FRAME_HEADER_TEMPLATE);
+    buf[1] = FLAG_HAS_CHECKSUM;
+    buf[2..4].copy_from_slice(&RECORD_TYPE_AUTH.to_be_bytes());
+    buf[4..8].copy_from_slice(&(token.len() as u32).to_be_bytes());
+    let crc = crc16_ccitt(&buf[0..8]);
+    buf[8..10].copy_from_slice(&crc.to_be_bytes());
+    buf.extend_from_slice(token);
+    buf
+}
+
+/// Encode a cache feed message as one uncompressed, checksummed frame.
+pub fn encode_cache_feed(msg: &CacheFeed) -> Result<Vec<u8>, StreamError> {
+    let opts = EncodeOptions {
+        enable_compression: false,
+        compress_threshold: usize::MAX,
+        payload_hint: None,
+        format: PayloadFormat::Bincode,
+        origin_unix_ns: None,
+    };
+    let mut buf = Vec::new();
+    encode_value_with_type(msg, &mut buf, opts, msg.type_tag())?;
+    Ok(buf)
+}
+
+/// Recompress the payload of an uncompressed [`encode_cache_feed`] frame of at
+/// least `min_bytes`; `None` when the frame is better sent as is.
+pub fn compress_cache_feed_frame(
+    frame: &[u8],
+    compression: FeedCompression,
+    min_bytes: usize,
+) -> Result<Option<Vec<u8>>, StreamError> {
+    let hdr = FrameHeader::parse(frame)?.ok_or(StreamError::BadHeader)?;
+    if !hdr.is_cache_feed() || frame.len() != hdr.frame_len() {
+        return Err(StreamError::BadHeader);
+    }
+    let payload = &frame[FRAME_HEADER_LEN..];
+    if payload.len() < min_bytes || (hdr.flags & (FLAG_LZ4 | FLAG_ZSTD)) != 0 {
+        return Ok(None);
+    }
+    let (flag, body) = match compression {
+        FeedCompression::None => return Ok(None),
+        FeedCompression::Lz4 => (FLAG_LZ4, lz4_flex::block::compress_prepend_size(payload)),
+        #[cfg(feature = "zstd")]
+        FeedCompression::Zstd => (
+            FLAG_ZSTD,
+            zstd::bulk::compress(payload, CACHE_FEED_ZSTD_LEVEL)?,
+        ),
+        #[cfg(not(feature = "zstd"))]
+        FeedCompression::Zstd => return Ok(None),
+    };
+    if body.len() >= payload.len() {
+        return Ok(None);
+    }
+    let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    buf.extend_from_slice(&frame[..FRAME_HEADER_LEN]);
+    buf[1] = hdr.flags | flag;
+    buf[4..8].copy_from_slice(&(body.len() as u32).to_be_bytes());
+    let crc = crc16_ccitt(&buf[0..8]);
+    buf[8..10].copy_from_slice(&crc.to_be_bytes());
+    buf.extend_from_slice(&body);
+    Ok(Some(buf))
+}
+
+/// Parsed and verified frame header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub flags: u8,
+    pub record_type: u16,
+    pub payload_len: u32,
+}
+
+impl FrameHeader {
+    /// Parse the header at the start of `src`; `Ok(None)` until a full header is buffered.
+    pub fn parse(src: &[u8]) -> Result<Option<Self>, StreamError> {
+        Self::parse_with(src, false)
+    }
+
+    /// Like [`FrameHeader::parse`], but also accepts headers from producers that
+    /// predate header checksums (`FLAG_HAS_CHECKSUM` clear, bytes [8..12) zero).
+    pub fn parse_lenient(src: &[u8]) -> Result<Option<Self>, StreamError> {
+        Self::parse_with(src, true)
+    }
+
+    fn parse_with(src: &[u8], allow_unchecked: bool) -> Result<Option<Self>, StreamError> {
+        if src.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let version = src[0];
+        if !(MIN_FRAME_VERSION..=FRAME_VERSION).contains(&version) {
+            return Err(StreamError::BadHeader);
+        }
+        let flags = src[1];
+        if allow_unchecked && (flags & FLAG_HAS_CHECKSUM) == 0 {
+            if src[8..12] != [0; 4] {
+                return Err(StreamError::BadHeader);
+            }
+        } else if u16::from_be_bytes([src[8], src[9]]) != crc16_ccitt(&src[0..8]) {
+            return Err(StreamError::BadHeader);
+        }
+        Ok(Some(Self {
+            version,
+            flags,
+            record_type: u16::from_be_bytes([src[2], src[3]]),
+            payload_len: u32::from_be_bytes([src[4], src[5], src[6], src[7]]),
+        }))
+    }
+
+    pub fn is_checksummed(&self) -> bool {
+        (self.flags & FLAG_HAS_CHECKSUM) != 0
+    }
+
+    /// An auth frame (see [`encode_auth_frame`]) rather than a record.
+    pub fn is_auth(&self) -> bool {
+        self.record_type == RECORD_TYPE_AUTH
+    }
+
+    /// A [`CacheFeed`] frame rather than a record.
+    pub fn is_cache_feed(&self) -> bool {
+        matches!(
+            self.record_type,
+            RECORD_TYPE_SNAPSHOT_SEGMENT
+                | RECORD_TYPE_SNAPSHOT_COMPLETE
+                | RECORD_TYPE_DELTA_BATCH
+                | RECORD_TYPE_SLOT_BATCH
+                | RECORD_TYPE_TX_BATCH
+                | RECORD_TYPE_FEED_HELLO
+                | RECORD_TYPE_FEED_HELLO_ACK
+        )
+    }
+
+    /// Header plus payload.
+    pub fn frame_len(&self) -> usize {
+        FRAME_HEADER_LEN + self.payload_len as usize
+    }
+
+    /// Split a payload into the origin timestamp (if stamped) and the record body.
+    pub fn split_origin<'a>(&self, payload: &'a [u8]) -> (Option<u64>, &'a [u8]) {
+        split_origin(self.flags, payload)
+    }
+}
+
+fn split_origin(flags: u8, payload: &[u8]) -> (Option<u64>, &[u8]) {
+    if (flags & FLAG_ORIGIN_TS) == 0 || payload.len() < ORIGIN_TS_LEN {
+        return (None, payload);
+    }
+    let (ts, body) = payload.split_at(ORIGIN_TS_LEN);
+    (Some(u64::from_be_bytes(ts.try_into().unwrap())), body)
+}
+
+/// Decode the payload of a frame whose header was parsed separately;
+/// `body` must be exactly `hdr.payload_len` bytes. An origin timestamp is skipped.
+/// v1 frames are read with the legacy layout, without a block height.
+pub fn decode_record_body(
+    hdr: &FrameHeader,
+    body: &[u8],
+    scratch: &mut Vec<u8>,
+) -> Result<Record, StreamError> {
+    if hdr.version <= LEGACY_RECORD_VERSION {
+        return decode_body::<legacy::RecordV1>(hdr, body, scratch).map(Record::from);
+    }
+    decode_body(hdr, body, scratch)
+}
+
+/// Like [`decode_record_body`], for a frame from [`encode_cache_feed`].
+pub fn decode_cache_feed_body(
+    hdr: &FrameHeader,
+    body: &[u8],
+    scratch: &mut Vec<u8>,
+) -> Result<CacheFeed, StreamError> {
+    if !hdr.is_cache_feed() {
+        return Err(StreamError::BadHeader);
+    }
+    decode_body(hdr, body, scratch)
+}
+
+fn decode_body<T: serde::de::DeserializeOwned>(
+    hdr: &FrameHeader,
+    body: &[u8],
+    scratch: &mut Vec<u8>,
+) -> Result<T, StreamError> {
+    let (_, body) = hdr.split_origin(body);
+    let bincode_opts = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes();
+    if (hdr.flags & FLAG_LZ4) != 0 {
+        match lz4_flex::block::decompress_size_prepended(body) {
+            Ok(mut decompressed) => {
+                // Move decompressed buffer into scratch to avoid a copy
+                std::mem::swap(scratch, &mut decompressed);
+                Ok(bincode_opts.deserialize::<T>(&scratch[..])?)
+            }
+            Err(e) => Err(StreamError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                e,
+            ))),
+        }
+    } else if (hdr.flags & FLAG_ZSTD) != 0 {
+        #[cfg(feature = "zstd")]
+        {
+            *scratch = zstd::stream::decode_all(body)?;
+            Ok(bincode_opts.deserialize::<T>(&scratch[..])?)
+        }
+        #[cfg(not(feature = "zstd"))]
+        Err(StreamError::Io(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "zstd frame, but faststreams was built without the zstd feature",
+        )))
+    } else {
+        Ok(bincode_opts.deserialize::<T>(body)?)
+    }
+}
+
 pub fn encode_record(rec: &Record) -> Result<Vec<u8>, StreamError> {
     encode_record_with(rec, EncodeOptions::default_throughput())
 }
@@ -350,7 +848,7 @@ pub fn decode_record_archived_from_slice(
     if (flags & FLAG_LZ4) != 0 {
         return Err(StreamError::De(Box::new(bincode::ErrorKind::SizeLimit)));
     }
-    let body = &src[12..total];
+    let (_, body) = split_origin(flags, &src[12..total]);
     let rec = rkyv::check_archived_root::<Record>(body)
         .map_err(|e| StreamError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?;
     Ok((rec, total))
@@ -383,39 +881,14 @@ pub fn decode_record_archived_trusted_from_slice(
     if (flags & FLAG_LZ4) != 0 {
         return Err(StreamError::De(Box::new(bincode::ErrorKind::SizeLimit)));
     }
-    let body = &src[12..total];
+    let (_, body) = split_origin(flags, &src[12..total]);
     let rec = rkyv::check_archived_root::<Record>(body)
         .map_err(|e| StreamError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string())))?;
     Ok((rec, total))
 }
 
-pub fn decode_record(mut src: impl Read) -> Result<Record, StreamError> {
-    let mut hdr = [0u8; 12];
-    src.read_exact(&mut hdr)?;
-    let ver = hdr[0];
-    if ver != FRAME_VERSION {
-        return Err(StreamError::BadHeader);
-    }
-    let hdr_crc = u16::from_be_bytes([hdr[8], hdr[9]]);
-    let calc = crc16_ccitt(&hdr[0..8]);
-    if hdr_crc != calc {
-        return Err(StreamError::BadHeader);
-    }
-    let flags = hdr[1];
-    let _typ = u16::from_be_bytes([hdr[2], hdr[3]]);
-    let len = u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]) as usize;
-    let mut body = vec![0u8; len];
-    src.read_exact(&mut body)?;
-    let bincode_opts = bincode::DefaultOptions::new()
-        .with_fixint_encoding()
-        .allow_trailing_bytes();
-    let payload = if (flags & FLAG_LZ4) != 0 {
-        lz4_flex::block::decompress_size_prepended(&body)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
-    } else {
-        body
-    };
-    Ok(bincode_opts.deserialize::<Record>(&payload)?)
+pub fn decode_record(src: impl Read) -> Result<Record, StreamError> {
+    decode_record_with_scratch(src, &mut Vec::new())
 }
 
 /// Decode without copying the body when uncompressed; returns (record, bytes_consumed).
@@ -423,46 +896,15 @@ pub fn decode_record_from_slice(
     src: &[u8],
     scratch: &mut Vec<u8>,
 ) -> Result<(Record, usize), StreamError> {
-    if src.len() < 12 {
+    let Some(hdr) = FrameHeader::parse(src)? else {
         return Err(StreamError::De(Box::new(bincode::ErrorKind::SizeLimit)));
-    }
-    let ver = src[0];
-    if ver != FRAME_VERSION {
-        return Err(StreamError::BadHeader);
-    }
-    let hdr_crc = u16::from_be_bytes([src[8], src[9]]);
-    let calc = crc16_ccitt(&src[0..8]);
-    if hdr_crc != calc {
-        return Err(StreamError::BadHeader);
-    }
-    let flags = src[1];
-    let _typ = u16::from_be_bytes([src[2], src[3]]);
-    let len = u32::from_be_bytes([src[4], src[5], src[6], src[7]]) as usize;
-    let total = 12 + len;
+    };
+    let total = hdr.frame_len();
     if src.len() < total {
         return Err(StreamError::De(Box::new(bincode::ErrorKind::SizeLimit)));
     }
-    let body = &src[12..total];
-    let bincode_opts = bincode::DefaultOptions::new()
-        .with_fixint_encoding()
-        .allow_trailing_bytes();
-    if (flags & FLAG_LZ4) != 0 {
-        match lz4_flex::block::decompress_size_prepended(body) {
-            Ok(mut decompressed) => {
-                // Move decompressed buffer into scratch to avoid a copy
-                std::mem::swap(scratch, &mut decompressed);
-                let rec = bincode_opts.deserialize::<Record>(&scratch[..])?;
-                Ok((rec, total))
-            }
-            Err(e) => Err(StreamError::Io(io::Error::new(
-                io::ErrorKind::InvalidData,
-                e,
-            ))),
-        }
-    } else {
-        let rec = bincode_opts.deserialize::<Record>(body)?;
-        Ok((rec, total))
-    }
+    let rec = decode_record_body(&hdr, &src[FRAME_HEADER_LEN..total], scratch)?;
+    Ok((rec, total))
 }
 
 /// Decode using a caller-provided buffer for the body to avoid per-record allocations.
@@ -470,40 +912,14 @@ pub fn decode_record_with_scratch(
     mut src: impl Read,
     body_buf: &mut Vec<u8>,
 ) -> Result<Record, StreamError> {
-    let mut hdr = [0u8; 12];
+    let mut hdr = [0u8; FRAME_HEADER_LEN];
     src.read_exact(&mut hdr)?;
-    let ver = hdr[0];
-    if ver != FRAME_VERSION {
-        return Err(StreamError::BadHeader);
-    }
-    let hdr_crc = u16::from_be_bytes([hdr[8], hdr[9]]);
-    let calc = crc16_ccitt(&hdr[0..8]);
-    if hdr_crc != calc {
-        return Err(StreamError::BadHeader);
-    }
-    let flags = hdr[1];
-    let _typ = u16::from_be_bytes([hdr[2], hdr[3]]);
-    let len = u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]) as usize;
+    let hdr = FrameHeader::parse(&hdr)?.ok_or(StreamError::BadHeader)?;
     body_buf.clear();
-    body_buf.resize(len, 0);
+    body_buf.resize(hdr.payload_len as usize, 0);
     src.read_exact(body_buf)?;
-    let bincode_opts = bincode::DefaultOptions::new()
-        .with_fixint_encoding()
-        .allow_trailing_bytes();
-    if (flags & FLAG_LZ4) != 0 {
-        match lz4_flex::block::decompress_size_prepended(body_buf) {
-            Ok(mut decompressed) => {
-                std::mem::swap(body_buf, &mut decompressed);
-            }
-            Err(e) => {
-                return Err(StreamError::Io(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    e,
-                )));
-            }
-        }
-    }
-    Ok(bincode_opts.deserialize::<Record>(&body_buf[..])?)
+    // Only compressed bodies need scratch space, and they allocate anyway
+    decode_record_body(&hdr, body_buf, &mut Vec::new())
 }
 
 /// Reusable decoder that keeps internal buffers to avoid per-record allocations across batches.
@@ -623,6 +1039,100 @@ pub fn write_all_vectored_slices(
     Ok(())
 }
 
+/// Leading bytes of a capture file. Entries follow back to back as
+/// `[u64 LE capture unix nanos][u32 LE frame len][frame]`.
+pub const CAPTURE_MAGIC: [u8; 8] = *b"FSCAP\0\0\x01";
+
+/// Largest frame a capture entry may hold; a longer length prefix means the
+/// file is corrupt, so the reader refuses it instead of allocating it.
+pub const MAX_CAPTURE_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// Appends encoded frames to a capture file with the time they were seen.
+pub struct CaptureWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Writes the file magic; `inner` must be positioned at the start of an empty file.
+    pub fn new(mut inner: W) -> io::Result<Self> {
+        inner.write_all(&CAPTURE_MAGIC)?;
+        Ok(Self { inner })
+    }
+
+    /// Continue an existing capture; `inner` must append after its last entry.
+    pub fn resume(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn append(&mut self, captured_at_ns: u64, frame: &[u8]) -> io::Result<()> {
+        if frame.len() > MAX_CAPTURE_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame too large",
+            ));
+        }
+        let len = frame.len() as u32;
+        let mut prefix = [0u8; 12];
+        prefix[0..8].copy_from_slice(&captured_at_ns.to_le_bytes());
+        prefix[8..12].copy_from_slice(&len.to_le_bytes());
+        self.inner.write_all(&prefix)?;
+        self.inner.write_all(frame)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// Reads frames back from a capture file in recorded order.
+pub struct CaptureReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut inner: R) -> Result<Self, StreamError> {
+        let mut magic = [0u8; 8];
+        inner.read_exact(&mut magic)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(StreamError::BadHeader);
+        }
+        Ok(Self { inner })
+    }
+
+    /// Read the next frame into `frame` and return its capture timestamp, or
+    /// `None` at a clean end of file. A truncated entry is an `UnexpectedEof`
+    /// error, a length over `MAX_CAPTURE_FRAME_LEN` an `InvalidData` one.
+    pub fn next_into(&mut self, frame: &mut Vec<u8>) -> io::Result<Option<u64>> {
+        let mut prefix = [0u8; 12];
+        let mut got = 0;
+        while got < prefix.len() {
+            match self.inner.read(&mut prefix[got..]) {
+                Ok(0) if got == 0 => return Ok(None),
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => got += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let ts = u64::from_le_bytes(prefix[0..8].try_into().unwrap());
+        let len = u32::from_le_bytes(prefix[8..12].try_into().unwrap()) as usize;
+        if len > MAX_CAPTURE_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("capture entry of {len} bytes"),
+            ));
+        }
+        frame.clear();
+        frame.resize(len, 0);
+        self.inner.read_exact(frame)?;
+        Ok(Some(ts))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -660,6 +1170,277 @@ mod tests {
         }
     }
 
+    #[test]
+    fn set_frame_flags_keeps_frame_decodable() {
+        let record = Record::Slot {
+            slot: 5,
+            parent: Some(4),
+            status: 1,
+        };
+        let mut buf = Vec::new();
+        encode_into_with(&record, &mut buf, EncodeOptions::latency_uds()).unwrap();
+        assert_eq!(frame_flags(&buf).map(|f| f & FLAG_BACKFILL), Some(0));
+        set_frame_flags(&mut buf, FLAG_BACKFILL).unwrap();
+        assert_eq!(
+            frame_flags(&buf).map(|f| f & FLAG_BACKFILL),
+            Some(FLAG_BACKFILL)
+        );
+        let mut scratch = Vec::new();
+        let (decoded, used) = decode_record_from_slice(&buf, &mut scratch).unwrap();
+        assert_eq!(used, buf.len());
+        assert!(matches!(decoded, Record::Slot { slot: 5, .. }));
+        assert!(set_frame_flags(&mut buf[..4], FLAG_BACKFILL).is_err());
+    }
+
+    #[test]
+    fn origin_stamp_is_skipped_by_decoders() {
+        let record = sample_account(9);
+        for opts in [
+            EncodeOptions::latency_uds(),
+            EncodeOptions::throughput_lz4_low(),
+        ] {
+            let mut buf = Vec::new();
+            encode_into_with(&record, &mut buf, opts).unwrap();
+            let unstamped = buf.len();
+            stamp_origin(&mut buf, 1_700_000_000_000_000_001).unwrap();
+            stamp_origin(&mut buf, 1_700_000_000_000_000_002).unwrap();
+            assert_eq!(buf.len(), unstamped + ORIGIN_TS_LEN);
+
+            let hdr = FrameHeader::parse(&buf).unwrap().unwrap();
+            let (origin, _) = hdr.split_origin(&buf[FRAME_HEADER_LEN..]);
+            assert_eq!(origin, Some(1_700_000_000_000_000_002));
+            let (decoded, used) = decode_record_from_slice(&buf, &mut Vec::new()).unwrap();
+            assert_eq!(used, buf.len());
+            assert!(matches!(decoded, Record::Account(ref a) if a.slot == 9));
+            let decoded = decode_record(io::Cursor::new(&buf)).unwrap();
+            assert!(matches!(decoded, Record::Account(ref a) if a.slot == 9));
+            let decoded =
+                decode_record_with_scratch(io::Cursor::new(&buf), &mut Vec::new()).unwrap();
+            assert!(matches!(decoded, Record::Account(ref a) if a.slot == 9));
+
+            let mut direct = Vec::new();
+            let stamped = opts.with_origin(1_700_000_000_000_000_002);
+            encode_into_with(&record, &mut direct, stamped).unwrap();
+            assert_eq!(direct, buf, "encode-time stamp matches stamp_origin");
+        }
+    }
+
+    #[test]
+    fn auth_frame_carries_the_token() {
+        let frame = encode_auth_frame(b"s3cret");
+        let hdr = FrameHeader::parse(&frame).unwrap().unwrap();
+        assert!(hdr.is_auth() && hdr.is_checksummed());
+        assert_eq!(hdr.frame_len(), frame.len());
+        assert_eq!(&frame[FRAME_HEADER_LEN..], b"s3cret");
+        assert!(
+            !FrameHeader::parse(&encode_record(&sample_account(1)).unwrap())
+                .unwrap()
+                .unwrap()
+                .is_auth()
+        );
+    }
+
+    #[test]
+    fn cache_feed_roundtrip() {
+        let msg = CacheFeed::Deltas(vec![AccountDelta {
+            pubkey: [7u8; 32],
+            slot: 9,
+            account: Some(AccountState {
+                pubkey: [7u8; 32],
+                lamports: 1,
+                owner: [2u8; 32],
+                executable: false,
+                rent_epoch: 0,
+                data: vec![1, 2, 3],
+            }),
+        }]);
+        let frame = encode_cache_feed(&msg).unwrap();
+        let hdr = FrameHeader::parse(&frame).unwrap().unwrap();
+        assert!(hdr.is_cache_feed() && hdr.is_checksummed());
+        assert_eq!(hdr.record_type, RECORD_TYPE_DELTA_BATCH);
+        let mut scratch = Vec::new();
+        let body = &frame[FRAME_HEADER_LEN..];
+        assert_eq!(
+            decode_cache_feed_body(&hdr, body, &mut scratch).unwrap(),
+            msg
+        );
+
+        let txs = CacheFeed::Transactions(vec![TxUpdate {
+            slot: 9,
+            signature: [3u8; 64],
+            err: None,
+            vote: false,
+        }]);
+        let frame = encode_cache_feed(&txs).unwrap();
+        let hdr = FrameHeader::parse(&frame).unwrap().unwrap();
+        assert_eq!(hdr.record_type, RECORD_TYPE_TX_BATCH);
+        assert_eq!(
+            decode_cache_feed_body(&hdr, &frame[FRAME_HEADER_LEN..], &mut scratch).unwrap(),
+            txs
+        );
+
+        let rec = encode_record(&sample_account(1)).unwrap();
+        let rec_hdr = FrameHeader::parse(&rec).unwrap().unwrap();
+        assert!(decode_cache_feed_body(&rec_hdr, &rec[FRAME_HEADER_LEN..], &mut scratch).is_err());
+    }
+
+    #[test]
+    fn cache_feed_frames_compress_and_decode() {
+        let msg = CacheFeed::Deltas(
+            (0..64u8)
+                .map(|n| AccountDelta {
+                    pubkey: [n; 32],
+                    slot: 9,
+                    account: Some(AccountState {
+                        pubkey: [n; 32],
+                        lamports: 1,
+                        owner: [2u8; 32],
+                        executable: false,
+                        rent_epoch: 0,
+                        data: vec![0; 256],
+                    }),
+                })
+                .collect(),
+        );
+        let frame = encode_cache_feed(&msg).unwrap();
+        let mut methods = vec![FeedCompression::Lz4];
+        if cfg!(feature = "zstd") {
+            methods.push(FeedCompression::Zstd);
+        }
+        for method in methods {
+            let packed = compress_cache_feed_frame(&frame, method, 1024)
+                .unwrap()
+                .expect("a compressible batch is compressed");
+            assert!(packed.len() < frame.len() / 2);
+            let hdr = FrameHeader::parse(&packed).unwrap().unwrap();
+            assert_eq!(hdr.frame_len(), packed.len());
+            let body = &packed[FRAME_HEADER_LEN..];
+            assert_eq!(
+                decode_cache_feed_body(&hdr, body, &mut Vec::new()).unwrap(),
+                msg
+            );
+            // Already compressed, or under the threshold: sent as is
+            assert!(compress_cache_feed_frame(&packed, method, 1024)
+                .unwrap()
+                .is_none());
+            assert!(compress_cache_feed_frame(&frame, method, frame.len())
+                .unwrap()
+                .is_none());
+        }
+        assert_eq!(
+            FeedCompression::choose(&[FeedCompression::Zstd, FeedCompression::Lz4]),
+            if cfg!(feature = "zstd") {
+                FeedCompression::Zstd
+            } else {
+                FeedCompression::Lz4
+            }
+        );
+    }
+
+    #[test]
+    fn frame_header_parse_and_legacy_frames() {
+        let frame = encode_record(&sample_account(7)).unwrap();
+        assert_eq!(
+            FrameHeader::parse(&frame[..FRAME_HEADER_LEN - 1]).unwrap(),
+            None
+        );
+        let hdr = FrameHeader::parse(&frame).unwrap().unwrap();
+        assert!(hdr.is_checksummed());
+        assert_eq!((hdr.version, hdr.record_type), (FRAME_VERSION, 1));
+        assert_eq!(hdr.frame_len(), frame.len());
+        let mut scratch = Vec::new();
+        let body = &frame[FRAME_HEADER_LEN..];
+        assert!(matches!(
+            decode_record_body(&hdr, body, &mut scratch).unwrap(),
+            Record::Account(a) if a.slot == 7
+        ));
+
+        // A pre-checksum producer: flag clear, CRC/reserved bytes zero.
+        let mut legacy = frame.clone();
+        legacy[1] &= !FLAG_HAS_CHECKSUM;
+        legacy[8..12].fill(0);
+        assert!(FrameHeader::parse(&legacy).is_err());
+        let hdr = FrameHeader::parse_lenient(&legacy).unwrap().unwrap();
+        assert!(!hdr.is_checksummed());
+        assert!(decode_record_body(&hdr, &legacy[FRAME_HEADER_LEN..], &mut scratch).is_ok());
+        legacy[0] = FRAME_VERSION + 1;
+        assert!(FrameHeader::parse_lenient(&legacy).is_err());
+    }
+
+    #[test]
+    fn v1_block_frames_decode_without_height() {
+        let rec = Record::Block(BlockMeta {
+            slot: 12,
+            blockhash: Some([3u8; 32]),
+            parent_slot: Some(11),
+            rewards_len: 2,
+            block_time_unix: Some(1_700_000_000),
+            leader: None,
+            block_height: Some(9),
+        });
+        let frame = encode_record(&rec).unwrap();
+        assert_eq!(frame[0], FRAME_VERSION);
+
+        // The v1 layout is the v2 one minus the trailing Some(u64) height.
+        let mut v1 = frame[..frame.len() - 9].to_vec();
+        let len = (v1.len() - FRAME_HEADER_LEN) as u32;
+        v1[0] = 1;
+        v1[4..8].copy_from_slice(&len.to_be_bytes());
+        let crc = crc16_ccitt(&v1[0..8]);
+        v1[8..10].copy_from_slice(&crc.to_be_bytes());
+
+        let check = |rec: Record| match rec {
+            Record::Block(b) => {
+                assert_eq!((b.slot, b.parent_slot, b.rewards_len), (12, Some(11), 2));
+                assert_eq!(b.block_time_unix, Some(1_700_000_000));
+                assert_eq!(b.block_height, None);
+            }
+            other => panic!("unexpected record {other:?}"),
+        };
+        let mut scratch = Vec::new();
+        let (rec, used) = decode_record_from_slice(&v1, &mut scratch).unwrap();
+        assert_eq!(used, v1.len());
+        check(rec);
+        check(decode_record(&v1[..]).unwrap());
+        assert_eq!(frame_flags(&v1), Some(v1[1]));
+    }
+
+    #[test]
+    fn capture_roundtrip_and_truncation() {
+        let frames = [
+            encode_record(&sample_account(1)).unwrap(),
+            encode_record(&sample_account(2)).unwrap(),
+        ];
+        let mut w = CaptureWriter::new(Vec::new()).unwrap();
+        w.append(10, &frames[0]).unwrap();
+        w.append(25, &frames[1]).unwrap();
+        let file = w.into_inner();
+
+        let mut r = CaptureReader::new(&file[..]).unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(r.next_into(&mut buf).unwrap(), Some(10));
+        assert_eq!(buf, frames[0]);
+        assert_eq!(r.next_into(&mut buf).unwrap(), Some(25));
+        assert_eq!(buf, frames[1]);
+        assert_eq!(r.next_into(&mut buf).unwrap(), None);
+
+        let mut r = CaptureReader::new(&file[..file.len() - 1]).unwrap();
+        r.next_into(&mut buf).unwrap();
+        let err = r.next_into(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            CaptureReader::new(&frames[0][..]),
+            Err(StreamError::BadHeader)
+        ));
+
+        let mut corrupt = CAPTURE_MAGIC.to_vec();
+        corrupt.extend_from_slice(&10u64.to_le_bytes());
+        corrupt.extend_from_slice(&u32::MAX.to_le_bytes());
+        let mut r = CaptureReader::new(&corrupt[..]).unwrap();
+        let err = r.next_into(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
     #[test]
     fn encode_sets_lz4_flag_when_threshold_exceeded() {
         // Prepare a payload that will certainly exceed 512 bytes when serialized.
@@ -670,12 +1451,14 @@ mod tests {
             rewards_len: 1024,
             block_time_unix: Some(123456789),
             leader: Some([7u8; 32]),
+            block_height: Some(77),
         });
         let opts = EncodeOptions {
             enable_compression: true,
             compress_threshold: 1,
             payload_hint: None,
             format: PayloadFormat::Bincode,
+            origin_unix_ns: None,
         };
         let mut buf = Vec::new();
         encode_into_with(&record, &mut buf, opts).expect("encode succeeds");
@@ -690,6 +1473,7 @@ mod tests {
                 assert_eq!(meta.slot, 99);
                 assert_eq!(meta.rewards_len, 1024);
                 assert_eq!(meta.leader, Some([7u8; 32]));
+                assert_eq!(meta.block_height, Some(77));
             }
             other => panic!("unexpected record variant: {other:?}"),
         }
@@ -703,6 +1487,7 @@ mod tests {
             compress_threshold: 1,
             payload_hint: None,
             format: PayloadFormat::Bincode,
+            origin_unix_ns: None,
         };
         let encoded = encode_record_with(&record, opts).expect("encode succeeds");
         let mut scratch = Vec::new();
@@ -68,6 +68,10 @@ pub struct Config {
     /// If true (Linux only), call mlockall(MCL_CURRENT|MCL_FUTURE) and prefault buffers
     #[serde(default)]
     pub lock_memory: bool,
+    /// Token for aggregators with a `producer_auth` token; sent as a faststreams
+    /// auth frame first on every connection
+    #[serde(default)]
+    pub auth_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -170,6 +174,7 @@ pub struct ValidatedConfig {
     pub use_seqpacket: bool,
     #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
     pub lock_memory: bool,
+    pub auth_token: Option<String>,
 }
 
 impl Config {
@@ -207,6 +212,11 @@ impl Config {
             ));
         }
 
+        anyhow::ensure!(
+            self.auth_token.as_deref() != Some(""),
+            "auth_token must not be empty"
+        );
+
         anyhow::ensure!(
             (1..=64).contains(&self.writer_threads),
             "writer_threads must be in 1..=64"
@@ -322,6 +332,7 @@ impl Config {
                     false
                 }
             },
+            auth_token: self.auth_token.clone(),
         })
     }
 }
@@ -244,6 +244,7 @@ impl GeyserPlugin for Ultra {
                 match addr.parse::<std::net::SocketAddr>() {
                     Ok(sock) => {
                         match PrometheusBuilder::new()
+                            .add_global_label("producer", "geyser-plugin-ultra")
                             .with_http_listener(sock)
                             .install_recorder()
                         {
@@ -580,6 +581,7 @@ impl GeyserPlugin for Ultra {
                 rewards_len: b.rewards.len() as u32,
                 block_time_unix: b.block_time,
                 leader: None, // Leader info not available in new API
+                block_height: b.block_height,
             });
             let idx = match self.writer_index_for_u64(b.slot) {
                 Some(i) => i,
@@ -871,6 +873,7 @@ mod tests {
             write_sleep_backoff_us: 750,
             use_seqpacket: cfg!(target_os = "linux"),
             lock_memory: false,
+            auth_token: None,
         }
     }
 
@@ -894,6 +897,20 @@ mod tests {
         assert!(err.to_string().contains("socket_path must be absolute"));
     }
 
+    #[test]
+    fn config_validate_rejects_empty_auth_token() {
+        let dir = tempdir().expect("tempdir");
+        let mut cfg = build_config(dir.path().join("ultra.sock").to_string_lossy().to_string());
+        cfg.auth_token = Some(String::new());
+        let err = cfg.validate().expect_err("empty token must fail");
+        assert!(err.to_string().contains("auth_token"));
+        cfg.auth_token = Some("s3cret".to_string());
+        assert_eq!(
+            cfg.validate().expect("token").auth_token.as_deref(),
+            Some("s3cret")
+        );
+    }
+
     #[test]
     fn config_validate_rejects_small_batch_bytes() {
         let dir = tempdir().expect("tempdir");
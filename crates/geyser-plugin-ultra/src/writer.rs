@@ -4,14 +4,14 @@ use crate::config::ValidatedConfig;
 use crate::meter::Meter;
 use crate::pool::PooledBuf;
 use crate::queue::Consumer;
-use faststreams::write_all_vectored_slices;
+use faststreams::{encode_auth_frame, write_all_vectored_slices};
 #[cfg(target_os = "linux")]
 use libc;
 use metrics::{counter, gauge, histogram};
 use smallvec::SmallVec;
 use socket2::SockRef;
 use std::cell::Cell;
-use std::io::IoSlice;
+use std::io::{IoSlice, Write};
 #[cfg(target_os = "linux")]
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixStream;
@@ -117,6 +117,10 @@ pub fn run_writer(
     let mut backoff_seq: u64 = 0;
     let mut last_connect_log: Option<Instant> = None;
     let mut last_logged_backoff: Duration = Duration::from_millis(0);
+    let auth_frame = cfg
+        .auth_token
+        .as_deref()
+        .map(|token| encode_auth_frame(token.as_bytes()));
     #[cfg(target_os = "linux")]
     if cfg.lock_memory {
         unsafe {
@@ -207,6 +211,18 @@ pub fn run_writer(
                         send_fd = Some(s.as_raw_fd());
                     }
                 }
+                // The aggregator closes producers whose first frame is not the auth frame
+                if let Some(frame) = &auth_frame {
+                    if let Err(e) = stream.send_auth(frame) {
+                        error!(target = "ultra.writer", "auth frame write failed: {e}");
+                        counter!("ultra_write_errors_total", "shard" => writer_index.to_string())
+                            .increment(1);
+                        meter.inc_reconnects(1);
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(Duration::from_secs(2));
+                        continue;
+                    }
+                }
                 // Batch & drain loop
                 let mut batch: Vec<PooledBuf> = Vec::with_capacity(cfg.batch_max);
                 let mut cur_flush_after_ms = cfg.flush_after_ms;
@@ -539,6 +555,26 @@ enum EitherSocket {
     Seqpacket(socket2::Socket),
 }
 
+impl EitherSocket {
+    /// Write the auth frame whole, as one datagram on SEQPACKET.
+    fn send_auth(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        match self {
+            EitherSocket::Stream(s) => s.write_all(frame),
+            #[cfg(target_os = "linux")]
+            EitherSocket::Seqpacket(s) => {
+                let sent = s.send(frame)?;
+                if sent != frame.len() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::WriteZero,
+                        "short auth frame write",
+                    ));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 struct SendBatchScratch {
     iovecs: Vec<libc::iovec>,
@@ -0,0 +1,153 @@
+// Numan Thabit 2025
+//! Admin API, served on its own loopback listener (`admin_listen`).
+//!
+//! - `GET /upstreams` lists the QUIC upstreams of every route with their
+//!   health, latency and circuit state.
+//! - `POST /upstreams` adds one: `{"addr": "10.0.0.3:8899", "weight": 1}`,
+//!   optionally with `server_name`.
+//! - `DELETE /upstreams/{addr}` removes one.
+//! - `POST /upstreams/{addr}/drain` takes one out of rotation while it keeps
+//!   being health-checked; `DELETE` on the same path puts it back.
+//! - `POST /cache/flush` empties the response cache.
+//!
+//! Upstream changes apply to the default route unless `?route=<name>` names
+//! another, and last until restart: the config file is not rewritten.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::cache::ResponseCache;
+use crate::config::{UpstreamConfig, DEFAULT_ROUTE};
+use crate::route::Routes;
+use crate::upstream::{MembershipError, UpstreamPool, UpstreamStatus};
+
+#[derive(Clone)]
+struct AdminState {
+    routes: Arc<Routes>,
+    cache: Arc<ResponseCache>,
+}
+
+pub fn router(routes: Arc<Routes>, cache: Arc<ResponseCache>) -> Router {
+    Router::new()
+        .route("/upstreams", get(list_upstreams).post(add_upstream))
+        .route("/upstreams/:addr", delete(remove_upstream))
+        .route("/upstreams/:addr/drain", post(drain).delete(undrain))
+        .route("/cache/flush", post(flush_cache))
+        .with_state(AdminState { routes, cache })
+}
+
+#[derive(Serialize)]
+struct RouteUpstreams {
+    route: String,
+    upstreams: Vec<UpstreamStatus>,
+}
+
+#[derive(Deserialize)]
+struct RouteQuery {
+    route: Option<String>,
+}
+
+async fn list_upstreams(State(state): State<AdminState>) -> Json<Vec<RouteUpstreams>> {
+    let routes = state
+        .routes
+        .pools()
+        .map(|(route, pool)| RouteUpstreams {
+            route: route.to_string(),
+            upstreams: pool.status(),
+        })
+        .collect();
+    Json(routes)
+}
+
+async fn add_upstream(
+    State(state): State<AdminState>,
+    Query(query): Query<RouteQuery>,
+    Json(upstream): Json<UpstreamConfig>,
+) -> Response {
+    with_pool(
+        &state,
+        &query,
+        |pool| pool.add(&upstream),
+        StatusCode::CREATED,
+    )
+}
+
+async fn remove_upstream(
+    State(state): State<AdminState>,
+    Path(addr): Path<String>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    with_pool(
+        &state,
+        &query,
+        |pool| pool.remove(&addr),
+        StatusCode::NO_CONTENT,
+    )
+}
+
+async fn drain(
+    State(state): State<AdminState>,
+    Path(addr): Path<String>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    with_pool(
+        &state,
+        &query,
+        |pool| pool.set_draining(&addr, true),
+        StatusCode::NO_CONTENT,
+    )
+}
+
+async fn undrain(
+    State(state): State<AdminState>,
+    Path(addr): Path<String>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    with_pool(
+        &state,
+        &query,
+        |pool| pool.set_draining(&addr, false),
+        StatusCode::NO_CONTENT,
+    )
+}
+
+async fn flush_cache(State(state): State<AdminState>) -> Response {
+    let flushed = state.cache.flush();
+    Json(serde_json::json!({ "flushed": flushed })).into_response()
+}
+
+/// Apply `change` to the pool of the queried route; `ok` on success.
+fn with_pool(
+    state: &AdminState,
+    query: &RouteQuery,
+    change: impl FnOnce(&UpstreamPool) -> Result<(), MembershipError>,
+    ok: StatusCode,
+) -> Response {
+    let route = query.route.as_deref().unwrap_or(DEFAULT_ROUTE);
+    let Some(pool) = state.routes.pool(route) else {
+        return error(StatusCode::NOT_FOUND, &format!("no QUIC route {route}"));
+    };
+    match change(pool) {
+        Ok(()) => ok.into_response(),
+        Err(err) => {
+            let status = match err {
+                MembershipError::Exists(_) | MembershipError::LastUpstream => StatusCode::CONFLICT,
+                MembershipError::NotFound(_) => StatusCode::NOT_FOUND,
+                MembershipError::Setup(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            error(status, &err.to_string())
+        }
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({ "error": message }))).into_response()
+}
@@ -0,0 +1,67 @@
+// Numan Thabit 2025
+//! Sticky upstream affinity.
+//!
+//! A request carrying a session hint (the `affinity_header`, or else the API
+//! key) goes to the upstream that last answered the same hint, as long as the
+//! pairing is younger than `affinity_ttl` and that upstream can still take
+//! requests. Otherwise the pool picks as usual and the new upstream becomes
+//! the session's. Clients alternating `sendTransaction` and
+//! `getSignatureStatuses` thus see one node's view of the chain.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+// Past this many tracked sessions, expired ones are dropped on the next insert
+const SWEEP_AT: usize = 10_000;
+
+pub struct Affinity {
+    ttl: Duration,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+struct Session {
+    upstream: String,
+    expires: Instant,
+}
+
+impl Affinity {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The upstream label `session` is pinned to, if the pairing is live.
+    pub fn get(&self, session: &str, now: Instant) -> Option<String> {
+        let sessions = self.sessions.lock().unwrap_or_else(|p| p.into_inner());
+        sessions
+            .get(session)
+            .filter(|pinned| pinned.expires > now)
+            .map(|pinned| pinned.upstream.clone())
+    }
+
+    /// Pin `session` to `upstream` for another `ttl`.
+    pub fn pin(&self, session: &str, upstream: &str, now: Instant) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|p| p.into_inner());
+        let expires = now + self.ttl;
+        if let Some(pinned) = sessions.get_mut(session) {
+            if pinned.upstream != upstream {
+                pinned.upstream = upstream.to_string();
+            }
+            pinned.expires = expires;
+            return;
+        }
+        if sessions.len() >= SWEEP_AT {
+            sessions.retain(|_, pinned| pinned.expires > now);
+        }
+        sessions.insert(
+            session.to_string(),
+            Session {
+                upstream: upstream.to_string(),
+                expires,
+            },
+        );
+    }
+}
@@ -0,0 +1,147 @@
+// Numan Thabit 2025
+//! Batch (array) JSON-RPC requests.
+//!
+//! A batch is taken apart so limits apply per item: an item that is invalid,
+//! too large, past `max_batch_size` or over its client's rate limit gets its
+//! own error response while the rest of the batch still goes upstream. The
+//! admitted items are either forwarded together as one smaller batch, with
+//! the upstream's responses matched back by id, or split into individual
+//! requests spread over the upstreams. Either way the batch response lists
+//! the responses in request order; notifications get none.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::rpc::{self, RpcRequest};
+
+pub struct Batch<'a> {
+    items: Vec<Item<'a>>,
+}
+
+pub struct Item<'a> {
+    pub raw: &'a RawValue,
+    /// `None` if the item is not a valid request.
+    pub request: Option<RpcRequest<'a>>,
+    response: Option<Bytes>,
+}
+
+#[derive(Deserialize)]
+struct ResponseId<'a> {
+    #[serde(default, borrow)]
+    id: Option<&'a RawValue>,
+}
+
+impl<'a> Item<'a> {
+    pub fn id(&self) -> Option<&'a RawValue> {
+        self.request.as_ref().and_then(|r| r.id)
+    }
+
+    /// A valid request without an id: it expects no response.
+    pub fn is_notification(&self) -> bool {
+        self.request.as_ref().is_some_and(|r| r.id.is_none())
+    }
+
+    /// Answer the item with an error instead of forwarding it.
+    pub fn reject(&mut self, code: i64, message: &str) {
+        if !self.is_notification() {
+            self.response = Some(rpc::error_response(code, message, self.id()));
+        }
+    }
+
+    /// Answer the item with the upstream's `response` to it alone.
+    pub fn respond(&mut self, response: Bytes) {
+        if !self.is_notification() {
+            self.response = Some(response);
+        }
+    }
+}
+
+impl<'a> Batch<'a> {
+    /// The batch in `body`; `None` if it is not a JSON array.
+    pub fn parse(body: &'a [u8]) -> Option<Self> {
+        let raws: Vec<&RawValue> = serde_json::from_slice(body).ok()?;
+        let items = raws
+            .into_iter()
+            .map(|raw| Item {
+                raw,
+                request: RpcRequest::parse(raw.get().as_bytes()),
+                response: None,
+            })
+            .collect();
+        Some(Self { items })
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[Item<'a>] {
+        &self.items
+    }
+
+    pub fn items_mut(&mut self) -> &mut [Item<'a>] {
+        &mut self.items
+    }
+
+    /// The items at `indices` as one batch request.
+    pub fn encode(&self, indices: &[usize]) -> Bytes {
+        join(indices.iter().map(|&i| self.items[i].raw.get().as_bytes()))
+    }
+
+    /// Answer the items at `indices` from the upstream's `response` to them as
+    /// one batch, matching responses by id. Items the upstream did not answer
+    /// get an error; so do all of them if `response` is not a batch response.
+    pub fn fill(&mut self, indices: &[usize], response: &[u8]) {
+        let responses: Vec<&RawValue> = serde_json::from_slice(response).unwrap_or_default();
+        for raw in responses {
+            let Ok(ResponseId { id }) = serde_json::from_str(raw.get()) else {
+                continue;
+            };
+            let id = id.map(|id| id.get());
+            let slot = indices.iter().copied().find(|&i| {
+                let item = &self.items[i];
+                item.response.is_none()
+                    && !item.is_notification()
+                    && item.id().map(|id| id.get()) == id
+            });
+            if let Some(i) = slot {
+                self.items[i].response = Some(Bytes::copy_from_slice(raw.get().as_bytes()));
+            }
+        }
+        for &i in indices {
+            let item = &mut self.items[i];
+            if item.response.is_none() {
+                item.reject(-32603, "upstream returned no response for this request");
+            }
+        }
+    }
+
+    /// The batch response; `None` when every item was a notification.
+    pub fn response(&self) -> Option<Bytes> {
+        let mut responses = self
+            .items
+            .iter()
+            .filter_map(|item| item.response.as_deref())
+            .peekable();
+        responses.peek()?;
+        Some(join(responses))
+    }
+}
+
+fn join<'b>(parts: impl Iterator<Item = &'b [u8]>) -> Bytes {
+    let mut buf = BytesMut::new();
+    buf.put_u8(b'[');
+    for (i, part) in parts.enumerate() {
+        if i > 0 {
+            buf.put_u8(b',');
+        }
+        buf.put_slice(part);
+    }
+    buf.put_u8(b']');
+    buf.freeze()
+}
@@ -0,0 +1,146 @@
+// Numan Thabit 2025
+//! Per-upstream circuit breaker.
+//!
+//! The breaker keeps the outcomes of an upstream's last `window` requests.
+//! Once the window is full and the share of failures, or of requests slower
+//! than `slow_after`, reaches `threshold`, the circuit opens: the upstream gets
+//! no requests for `open_for`. It then half-opens and lets a single trial
+//! request through; success closes the circuit with a fresh window, failure
+//! opens it again. A trial that never reports back is given up after
+//! `open_for` so another can take its place.
+
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use serde::Serialize;
+use tokio::time::Instant;
+
+use crate::config::BreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Mutex<State>,
+}
+
+struct State {
+    phase: Phase,
+    // (failed, slow) per request, newest last
+    outcomes: VecDeque<(bool, bool)>,
+}
+
+enum Phase {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { trial_since: Option<Instant> },
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            state: Mutex::new(State {
+                phase: Phase::Closed,
+                outcomes: VecDeque::with_capacity(config.window),
+            }),
+            config,
+        }
+    }
+
+    /// Whether a request may be sent now, without claiming the trial slot.
+    pub fn available(&self, now: Instant) -> bool {
+        match self.lock().phase {
+            Phase::Closed => true,
+            Phase::Open { until } => now >= until,
+            Phase::HalfOpen { trial_since } => self.trial_free(trial_since, now),
+        }
+    }
+
+    /// Claim the right to send a request; a half-open circuit admits one trial
+    /// at a time.
+    pub fn acquire(&self, now: Instant) -> bool {
+        let mut state = self.lock();
+        match state.phase {
+            Phase::Closed => true,
+            Phase::Open { until } if now < until => false,
+            Phase::Open { .. } => {
+                state.phase = Phase::HalfOpen {
+                    trial_since: Some(now),
+                };
+                true
+            }
+            Phase::HalfOpen { trial_since } => {
+                let free = self.trial_free(trial_since, now);
+                if free {
+                    state.phase = Phase::HalfOpen {
+                        trial_since: Some(now),
+                    };
+                }
+                free
+            }
+        }
+    }
+
+    /// Record a request's outcome; the new state if it changed.
+    pub fn record(&self, failed: bool, latency: Duration) -> Option<CircuitState> {
+        let slow = self.config.slow_after.is_some_and(|limit| latency > limit);
+        let now = Instant::now();
+        let mut state = self.lock();
+        match state.phase {
+            Phase::HalfOpen { .. } if failed || slow => {
+                state.phase = Phase::Open {
+                    until: now + self.config.open_for,
+                };
+                Some(CircuitState::Open)
+            }
+            Phase::HalfOpen { .. } => {
+                state.phase = Phase::Closed;
+                state.outcomes.clear();
+                Some(CircuitState::Closed)
+            }
+            // Late results from before the circuit opened
+            Phase::Open { .. } => None,
+            Phase::Closed => {
+                if state.outcomes.len() == self.config.window {
+                    state.outcomes.pop_front();
+                }
+                state.outcomes.push_back((failed, slow));
+                if state.outcomes.len() < self.config.window {
+                    return None;
+                }
+                let window = state.outcomes.len() as f64;
+                let failures = state.outcomes.iter().filter(|o| o.0).count() as f64;
+                let slow = state.outcomes.iter().filter(|o| o.1).count() as f64;
+                let threshold = self.config.threshold;
+                if failures / window < threshold && slow / window < threshold {
+                    return None;
+                }
+                state.phase = Phase::Open {
+                    until: now + self.config.open_for,
+                };
+                Some(CircuitState::Open)
+            }
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.lock().phase {
+            Phase::Closed => CircuitState::Closed,
+            Phase::Open { .. } => CircuitState::Open,
+            Phase::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn trial_free(&self, trial_since: Option<Instant>, now: Instant) -> bool {
+        trial_since.is_none_or(|since| now.duration_since(since) >= self.config.open_for)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
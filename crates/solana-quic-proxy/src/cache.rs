@@ -0,0 +1,106 @@
+// Numan Thabit 2025
+//! Response cache for idempotent methods.
+//!
+//! Methods listed in `cache_ttl_ms` are answered from memory for their TTL,
+//! keyed by method and params. Only the `result` of a successful response is
+//! kept; each hit is re-wrapped with the caller's own request id. Once
+//! `cache_max_entries` is reached, expired entries are swept and new ones are
+//! skipped until there is room.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use serde_json::value::RawValue;
+use tokio::time::Instant;
+
+use crate::metrics::ProxyMetrics;
+use crate::rpc::{self, RpcRequest};
+
+pub struct ResponseCache {
+    ttls: HashMap<String, Duration>,
+    max_entries: usize,
+    entries: Mutex<HashMap<u64, Entry>>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+struct Entry {
+    result: Box<RawValue>,
+    expires: Instant,
+}
+
+/// Where a cacheable request's response lives in the cache.
+pub struct CacheKey {
+    key: u64,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(
+        ttls: HashMap<String, Duration>,
+        max_entries: usize,
+        metrics: Arc<ProxyMetrics>,
+    ) -> Self {
+        Self {
+            ttls,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// The cache key for `request`, if its method is cached.
+    pub fn key(&self, request: &RpcRequest<'_>) -> Option<CacheKey> {
+        let ttl = *self.ttls.get(request.method.as_ref())?;
+        Some(CacheKey {
+            key: request.fingerprint(),
+            ttl,
+        })
+    }
+
+    /// A cached response for `request`, with its id.
+    pub fn get(&self, key: &CacheKey, request: &RpcRequest<'_>) -> Option<Bytes> {
+        let entries = self.entries.lock().unwrap_or_else(|p| p.into_inner());
+        let hit = entries
+            .get(&key.key)
+            .filter(|entry| entry.expires > Instant::now());
+        self.metrics
+            .record_cache_lookup(&request.method, hit.is_some());
+        hit.map(|entry| rpc::success_response(&entry.result, request.id))
+    }
+
+    /// Drop every entry; how many there were.
+    pub fn flush(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap_or_else(|p| p.into_inner());
+        let flushed = entries.len();
+        entries.clear();
+        self.metrics.set_cache_entries(0);
+        flushed
+    }
+
+    /// Keep `response` if it is a success.
+    pub fn insert(&self, key: &CacheKey, response: &[u8]) {
+        let Some(result) = rpc::result_of(response) else {
+            return;
+        };
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap_or_else(|p| p.into_inner());
+        if entries.len() >= self.max_entries && !entries.contains_key(&key.key) {
+            entries.retain(|_, entry| entry.expires > now);
+            if entries.len() >= self.max_entries {
+                return;
+            }
+        }
+        entries.insert(
+            key.key,
+            Entry {
+                result: result.to_owned(),
+                expires: now + key.ttl,
+            },
+        );
+        self.metrics.set_cache_entries(entries.len());
+    }
+}
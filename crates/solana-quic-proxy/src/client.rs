@@ -1,9 +1,18 @@
 // Numan Thabit 2025
-use std::{io::IoSlice, net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    io::IoSlice,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{bail, Context, Result};
 use arc_swap::ArcSwapOption;
 use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
 use quinn::congestion::BbrConfig;
 use quinn::crypto::rustls::QuicClientConfig;
 use quinn::rustls::{
@@ -11,26 +20,33 @@ use quinn::rustls::{
     pki_types::CertificateDer,
     ClientConfig as RustlsClientConfig, RootCertStore,
 };
-use quinn::{ClientConfig, Connection, Endpoint, IdleTimeout, VarInt};
+use quinn::{ClientConfig, Connection, ConnectionError, Endpoint, IdleTimeout, RecvStream, VarInt};
 use rustls_native_certs::load_native_certs;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio::time::Instant;
-use tracing::warn;
+use tracing::{debug, warn};
 
-use crate::config::Config;
+use crate::config::{Config, UpstreamConfig};
 use crate::metrics::ProxyMetrics;
+use crate::stream::{Chunks, ResponseStream};
 
 const FRAME_HEADER: usize = 4;
+// Pause before a background reconnect retries a failed handshake
+const STANDBY_RETRY: Duration = Duration::from_secs(1);
 
 pub struct QuicRpcClient {
     endpoint: Endpoint,
     server_addr: SocketAddr,
+    // `server_addr` as reported with responses
+    label: Arc<str>,
     server_name: String,
     max_response_bytes: usize,
+    max_stream_bytes: usize,
     metrics: Arc<ProxyMetrics>,
-    connection: ArcSwapOption<Connection>,
-    connect_lock: Mutex<()>,
+    // `connections_per_upstream` connections, taken in turn
+    slots: Box<[Slot]>,
+    next_slot: AtomicUsize,
     recv_buf: Mutex<BytesMut>,
     request_timeout: Option<Duration>,
     hedged_attempts: u32,
@@ -39,13 +55,40 @@ pub struct QuicRpcClient {
     config: Arc<Config>,
 }
 
+#[derive(Default)]
+struct Slot {
+    connection: ArcSwapOption<Connection>,
+    // Handshakes run one at a time under this lock, which keeps the error
+    // of the last one
+    handshake: Mutex<Option<ConnectionError>>,
+    // Handshakes finished, so a waiter can tell whether one ended meanwhile
+    handshakes: AtomicU64,
+}
+
+#[derive(Clone)]
 pub struct ClientResponse {
     pub payload: Bytes,
     pub latency: Duration,
+    /// The upstream that answered.
+    pub upstream: Arc<str>,
 }
 
 impl QuicRpcClient {
+    /// A client for the first configured upstream.
     pub fn new(config: Arc<Config>, metrics: Arc<ProxyMetrics>) -> Result<Self> {
+        let upstream = config
+            .upstreams
+            .first()
+            .cloned()
+            .context("no upstream configured")?;
+        Self::with_upstream(config, &upstream, metrics)
+    }
+
+    pub fn with_upstream(
+        config: Arc<Config>,
+        upstream: &UpstreamConfig,
+        metrics: Arc<ProxyMetrics>,
+    ) -> Result<Self> {
         let client_config = build_client_config(&config)?;
         let bind_addr = SocketAddr::from(([0, 0, 0, 0], 0));
         let mut endpoint = Endpoint::client(bind_addr).context("failed to create QUIC endpoint")?;
@@ -54,12 +97,19 @@ impl QuicRpcClient {
 
         Ok(Self {
             endpoint,
-            server_addr: config.upstream,
-            server_name: config.server_name.clone(),
+            server_addr: upstream.addr,
+            label: Arc::from(upstream.addr.to_string()),
+            server_name: upstream
+                .server_name
+                .clone()
+                .unwrap_or_else(|| config.server_name.clone()),
             max_response_bytes: config.max_response_bytes,
+            max_stream_bytes: config.max_stream_bytes,
             metrics,
-            connection: ArcSwapOption::from(None),
-            connect_lock: Mutex::new(()),
+            slots: (0..config.connections_per_upstream)
+                .map(|_| Slot::default())
+                .collect(),
+            next_slot: AtomicUsize::new(0),
             recv_buf: Mutex::new(BytesMut::with_capacity(initial_recv_capacity)),
             request_timeout: config.request_timeout,
             hedged_attempts: config.hedged_attempts,
@@ -69,23 +119,67 @@ impl QuicRpcClient {
         })
     }
 
+    /// Open every connection up front.
     pub async fn warmup(&self) -> Result<(), ProxyError> {
-        let conn = self.connection().await?;
-        // Optionally pre-open a small number of bi-directional streams to warm up path/allocations.
-        let streams = self.config.preopen_streams;
-        for _ in 0..streams {
-            let (_send, _recv) = conn
-                .open_bi()
-                .await
-                .map_err(ProxyError::Connection)?;
-            // Immediately finish to return credits
-            // Drop streams; we only care about handshake/allocation warmup.
+        for slot in self.slots.iter() {
+            let conn = self.connect(slot).await?;
+            // Optionally pre-open a small number of bi-directional streams to warm up path/allocations.
+            let streams = self.config.preopen_streams;
+            for _ in 0..streams {
+                let (_send, _recv) = conn.open_bi().await.map_err(ProxyError::Connection)?;
+                // Immediately finish to return credits
+                // Drop streams; we only care about handshake/allocation warmup.
+            }
         }
         Ok(())
     }
 
-    pub async fn request(&self, payload: &[u8]) -> Result<ClientResponse, ProxyError> {
+    /// Keep every connection open in the background: one that closes (idle
+    /// timeout, upstream restart) is re-established right away rather than
+    /// by the next request. Stops once the client is dropped.
+    pub fn spawn_standby(self: &Arc<Self>) {
+        for index in 0..self.slots.len() {
+            let client = Arc::downgrade(self);
+            tokio::spawn(async move {
+                let mut reconnect = false;
+                loop {
+                    // Only hold the client while connecting, so dropping it ends the task
+                    let Some(this) = client.upgrade() else {
+                        return;
+                    };
+                    let slot = &this.slots[index];
+                    let result = this.connect(slot).await;
+                    match result {
+                        Ok(connection) => {
+                            if reconnect {
+                                this.metrics.record_reconnect();
+                            }
+                            drop(this);
+                            let reason = connection.closed().await;
+                            let upstream = connection.remote_address();
+                            debug!(%upstream, %reason, "upstream connection closed");
+                            let Some(this) = client.upgrade() else {
+                                return;
+                            };
+                            this.forget(&connection);
+                            reconnect = true;
+                        }
+                        Err(err) => {
+                            drop(this);
+                            debug!(error = %err, "upstream standby connect failed");
+                            tokio::time::sleep(STANDBY_RETRY).await;
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    /// Send `payload`; with `hedge`, a second attempt races the first after
+    /// `hedge_jitter` when `hedged_attempts` allows it.
+    pub async fn request(&self, payload: &[u8], hedge: bool) -> Result<ClientResponse, ProxyError> {
         let connection = self.connection().await?;
+        let mut hedge_connection = None;
         let fut = self.request_inner(&connection, payload);
         let attempt = async {
             match self.request_with_timeout(fut).await {
@@ -93,16 +187,18 @@ impl QuicRpcClient {
                 Err(err) => Err(err),
             }
         };
-        let result = if self.hedged_attempts <= 1 {
+        let result = if !hedge || self.hedged_attempts <= 1 {
             attempt.await
         } else {
             // Two-attempt hedging: launch second after jitter; first Ok wins.
             let first = attempt;
             let connection2 = self.connection().await?;
+            hedge_connection = Some(connection2.clone());
             let payload2 = Bytes::copy_from_slice(payload);
             let jitter = self.hedge_jitter;
             let second = async move {
                 tokio::time::sleep(jitter).await;
+                self.metrics.record_hedge();
                 self.request_with_timeout(self.request_inner(&connection2, &payload2))
                     .await
             };
@@ -138,23 +234,47 @@ impl QuicRpcClient {
             }
         };
 
-        match result {
-            Ok(response) => Ok(response),
-            Err(err @ ProxyError::Connection(_))
-            | Err(err @ ProxyError::Read(_))
-            | Err(err @ ProxyError::Write(_))
-            | Err(err @ ProxyError::IoWrite(_))
-            | Err(err @ ProxyError::Protocol(_)) => {
-                self.invalidate();
-                Err(err)
+        if let Err(err) = &result {
+            for connection in std::iter::once(&connection).chain(&hedge_connection) {
+                self.invalidate_after(err, connection);
+            }
+        }
+        result
+    }
+
+    /// Send `payload` and hand back the response body as it arrives instead of
+    /// buffering it; never hedged. The request timeout covers the wait for the
+    /// response to start, not the transfer.
+    pub async fn request_stream(&self, payload: &[u8]) -> Result<ResponseStream, ProxyError> {
+        let start = Instant::now();
+        let connection = self.connection().await?;
+        let (recv, len) = match self
+            .request_with_timeout(self.send_request(&connection, payload))
+            .await
+        {
+            Ok(opened) => opened,
+            Err(err) => {
+                self.invalidate_after(&err, &connection);
+                return Err(err);
             }
-            Err(err) => Err(err),
+        };
+        if len > self.max_stream_bytes {
+            return Err(ProxyError::ResponseTooLarge {
+                size: len,
+                max: self.max_stream_bytes,
+            });
         }
+        Ok(ResponseStream::new(
+            self.label.clone(),
+            Some(len),
+            start.elapsed(),
+            read_chunks(recv, len),
+        ))
     }
 
-    async fn request_with_timeout<F>(&self, fut: F) -> Result<ClientResponse, ProxyError>
+    async fn request_with_timeout<T, F>(&self, fut: F) -> Result<T, ProxyError>
     where
-        F: std::future::Future<Output = Result<ClientResponse, ProxyError>>,
+        F: std::future::Future<Output = Result<T, ProxyError>>,
     {
         if let Some(deadline) = self.request_timeout {
             match tokio::time::timeout(deadline, fut).await {
@@ -166,15 +286,35 @@ impl QuicRpcClient {
         }
     }
 
+    /// The next open connection in turn; connects one if none is open.
     async fn connection(&self) -> Result<Connection, ProxyError> {
-        if let Some(conn) = self.connection.load_full() {
-            return Ok((*conn).clone());
+        let first = self.next_slot.fetch_add(1, Ordering::Relaxed);
+        for k in 0..self.slots.len() {
+            let slot = &self.slots[(first + k) % self.slots.len()];
+            if let Some(conn) = slot.connection.load_full() {
+                if conn.close_reason().is_none() {
+                    return Ok((*conn).clone());
+                }
+            }
         }
+        self.connect(&self.slots[first % self.slots.len()]).await
+    }
 
-        let _guard = self.connect_lock.lock().await;
+    /// The connection of `slot`, opening it unless it is open already.
+    async fn connect(&self, slot: &Slot) -> Result<Connection, ProxyError> {
+        let seen = slot.handshakes.load(Ordering::Acquire);
+        let mut failure = slot.handshake.lock().await;
 
-        if let Some(conn) = self.connection.load_full() {
-            return Ok((*conn).clone());
+        if let Some(conn) = slot.connection.load_full() {
+            if conn.close_reason().is_none() {
+                return Ok((*conn).clone());
+            }
+        }
+        // The handshake this caller waited on failed; don't queue another behind it
+        if slot.handshakes.load(Ordering::Acquire) != seen {
+            if let Some(err) = failure.clone() {
+                return Err(ProxyError::Connection(err));
+            }
         }
 
         let connecting = self
@@ -184,29 +324,84 @@ impl QuicRpcClient {
         // Try 0-RTT only if enabled; otherwise, perform full handshake.
         let connection = if self.enable_early_data {
             match connecting.into_0rtt() {
-                Ok((conn, _zero_rtt)) => conn,
-                Err(connecting) => connecting.await.map_err(ProxyError::Connection)?,
+                Ok((conn, _zero_rtt)) => Ok(conn),
+                Err(connecting) => connecting.await,
             }
         } else {
-            connecting.await.map_err(ProxyError::Connection)?
+            connecting.await
         };
-        self.connection.store(Some(Arc::new(connection.clone())));
+        *failure = connection.as_ref().err().cloned();
+        slot.handshakes.fetch_add(1, Ordering::AcqRel);
+        let connection = connection.map_err(ProxyError::Connection)?;
+        slot.connection.store(Some(Arc::new(connection.clone())));
         Ok(connection)
     }
 
-    fn invalidate(&self) {
-        if let Some(conn) = self.connection.swap(None) {
-            conn.close(0u32.into(), b"proxy reset");
+    /// Drop `connection` if `err` suggests it is broken.
+    fn invalidate_after(&self, err: &ProxyError, connection: &Connection) {
+        if matches!(
+            err,
+            ProxyError::Connection(_)
+                | ProxyError::Read(_)
+                | ProxyError::Write(_)
+                | ProxyError::IoWrite(_)
+                | ProxyError::Protocol(_)
+        ) && self.forget(connection)
+        {
+            connection.close(0u32.into(), b"proxy reset");
             self.metrics.record_connection_reset();
         }
     }
 
+    /// Take `connection` out of its slot; false if it was no longer there.
+    fn forget(&self, connection: &Connection) -> bool {
+        let id = connection.stable_id();
+        self.slots.iter().any(|slot| {
+            let previous = slot
+                .connection
+                .rcu(|current| current.clone().filter(|conn| conn.stable_id() != id));
+            previous.is_some_and(|conn| conn.stable_id() == id)
+        })
+    }
+
     async fn request_inner(
         &self,
         connection: &Connection,
         payload: &[u8],
     ) -> Result<ClientResponse, ProxyError> {
         let start = Instant::now();
+        let (mut recv, len) = self.send_request(connection, payload).await?;
+        if len > self.max_response_bytes {
+            return Err(ProxyError::ResponseTooLarge {
+                size: len,
+                max: self.max_response_bytes,
+            });
+        }
+
+        let mut buf = self.recv_buf.lock().await;
+        let capacity = buf.capacity();
+        if capacity < len {
+            buf.reserve(len - capacity);
+        }
+        buf.resize(len, 0);
+        recv.read_exact(&mut buf[..])
+            .await
+            .map_err(ProxyError::from)?;
+
+        let payload = buf.split_to(len).freeze();
+        Ok(ClientResponse {
+            payload,
+            latency: start.elapsed(),
+            upstream: self.label.clone(),
+        })
+    }
+
+    /// Write one request frame; the response stream and announced body length.
+    async fn send_request(
+        &self,
+        connection: &Connection,
+        payload: &[u8],
+    ) -> Result<(RecvStream, usize), ProxyError> {
         let (mut send, mut recv) = connection.open_bi().await.map_err(ProxyError::Connection)?;
 
         // Write header + payload with a single vectored syscall, handling partials explicitly.
@@ -261,30 +456,40 @@ impl QuicRpcClient {
         recv.read_exact(&mut header)
             .await
             .map_err(ProxyError::from)?;
-        let len = u32::from_be_bytes(header) as usize;
-        if len > self.max_response_bytes {
-            return Err(ProxyError::ResponseTooLarge {
-                size: len,
-                max: self.max_response_bytes,
-            });
+        Ok((recv, u32::from_be_bytes(header) as usize))
+    }
+}
+
+impl Drop for QuicRpcClient {
+    fn drop(&mut self) {
+        // Wakes the standby tasks waiting on these connections
+        for slot in self.slots.iter() {
+            if let Some(conn) = slot.connection.swap(None) {
+                conn.close(0u32.into(), b"client dropped");
+            }
         }
+    }
+}
 
-        let mut buf = self.recv_buf.lock().await;
-        let capacity = buf.capacity();
-        if capacity < len {
-            buf.reserve(len - capacity);
+/// The `len` body bytes left on `recv`, chunk by chunk.
+fn read_chunks(recv: RecvStream, len: usize) -> Chunks {
+    futures::stream::try_unfold((recv, len), |(mut recv, remaining)| async move {
+        if remaining == 0 {
+            return Ok(None);
         }
-        buf.resize(len, 0);
-        recv.read_exact(&mut buf[..])
+        match recv
+            .read_chunk(remaining, true)
             .await
-            .map_err(ProxyError::from)?;
-
-        let payload = buf.split_to(len).freeze();
-        Ok(ClientResponse {
-            payload,
-            latency: start.elapsed(),
-        })
-    }
+            .map_err(ProxyError::Read)?
+        {
+            Some(chunk) => {
+                let left = remaining.saturating_sub(chunk.bytes.len());
+                Ok(Some((chunk.bytes, (recv, left))))
+            }
+            None => Err(ProxyError::Protocol("stream finished early".into())),
+        }
+    })
+    .boxed()
 }
 
 fn build_client_config(config: &Config) -> Result<ClientConfig> {
@@ -380,6 +585,10 @@ pub enum ProxyError {
     ResponseTooLarge { size: usize, max: usize },
     #[error("protocol violation: {0}")]
     Protocol(String),
+    #[error("upstream HTTP request failed: {0}")]
+    Http(reqwest::Error),
+    #[error("circuit open: no upstream is accepting requests")]
+    CircuitOpen,
 }
 
 impl From<quinn::ReadExactError> for ProxyError {
@@ -0,0 +1,114 @@
+// Numan Thabit 2025
+//! Coalescing of identical in-flight requests.
+//!
+//! The first request for a given method and params goes upstream; requests
+//! with the same method and params that arrive while it is in flight wait for
+//! its response instead of sending their own, and each gets it re-addressed to
+//! its own request id. Methods in `never_coalesce` (transaction submission and
+//! the like) always go upstream. If the leading request is dropped before it
+//! completes, its waiters send their own requests.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use serde_json::value::RawValue;
+use tokio::sync::broadcast;
+
+use crate::client::{ClientResponse, ProxyError};
+use crate::metrics::ProxyMetrics;
+use crate::rpc::{self, RpcRequest};
+
+/// What the leading request hands to its waiters.
+pub type Outcome = Result<ClientResponse, Arc<ProxyError>>;
+
+pub struct Coalescer {
+    never: HashSet<String>,
+    inflight: Mutex<HashMap<u64, broadcast::Sender<Outcome>>>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl Coalescer {
+    pub fn new(never: HashSet<String>, metrics: Arc<ProxyMetrics>) -> Self {
+        Self {
+            never,
+            inflight: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// The coalescing key for `request`, unless its method is never coalesced.
+    pub fn key(&self, request: &RpcRequest<'_>) -> Option<u64> {
+        (!self.never.contains(request.method.as_ref())).then(|| request.fingerprint())
+    }
+
+    /// Run `fetch` for the request with `key` and `id`, or share the response
+    /// of an identical request already in flight. Without a key `fetch` always
+    /// runs.
+    pub async fn run<F, Fut>(&self, key: Option<u64>, id: Option<&RawValue>, fetch: F) -> Outcome
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<ClientResponse, ProxyError>>,
+    {
+        let Some(key) = key else {
+            return fetch().await.map_err(Arc::new);
+        };
+
+        let waiting = {
+            let mut inflight = lock(&self.inflight);
+            match inflight.get(&key) {
+                Some(tx) => Some(tx.subscribe()),
+                None => {
+                    let (tx, _) = broadcast::channel(1);
+                    inflight.insert(key, tx);
+                    None
+                }
+            }
+        };
+        if let Some(mut rx) = waiting {
+            self.metrics.record_coalesced();
+            return match rx.recv().await {
+                Ok(outcome) => outcome.map(|response| ClientResponse {
+                    payload: rpc::with_id(&response.payload, id).unwrap_or(response.payload),
+                    ..response
+                }),
+                // The leading request was dropped before it completed
+                Err(_) => fetch().await.map_err(Arc::new),
+            };
+        }
+
+        let leader = Leader {
+            inflight: &self.inflight,
+            key,
+        };
+        let outcome = fetch().await.map_err(Arc::new);
+        let tx = lock(&self.inflight).get(&key).cloned();
+        // Unregister first so later requests go upstream for a fresh answer
+        drop(leader);
+        if let Some(tx) = tx {
+            let _ = tx.send(outcome.clone());
+        }
+        outcome
+    }
+}
+
+/// Unregisters the leading request however it ends, so a cancelled one
+/// releases its waiters.
+struct Leader<'a> {
+    inflight: &'a Mutex<HashMap<u64, broadcast::Sender<Outcome>>>,
+    key: u64,
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        lock(self.inflight).remove(&self.key);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
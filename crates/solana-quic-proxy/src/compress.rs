@@ -0,0 +1,251 @@
+// Numan Thabit 2025
+//! Response compression negotiated from the client's `Accept-Encoding`.
+//!
+//! JSON responses of at least `compression_min_bytes` go out as brotli or
+//! gzip, whichever the client weighs higher (brotli on a tie). Buffered
+//! responses are compressed in one go; streamed ones chunk by chunk, so a
+//! multi-megabyte `getProgramAccounts` answer is never held in full. Both
+//! codecs run at fast settings: the proxy sits on the latency path.
+
+use std::{
+    io::{self, Write},
+    mem,
+    sync::Arc,
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody as _},
+    extract::{Request, State},
+    http::{
+        header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderValue, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use flate2::write::GzEncoder;
+use futures::TryStreamExt;
+use tracing::error;
+
+use crate::metrics::ProxyMetrics;
+
+const GZIP_LEVEL: u32 = 1;
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW: u32 = 22;
+const BROTLI_BUFFER: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The encoding to answer a request accepting `accept` with; `None` when
+    /// it accepts neither.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let mut brotli = None;
+        let mut gzip = None;
+        let mut any = None;
+        for item in accept.split(',') {
+            let mut parts = item.split(';');
+            let coding = parts.next().unwrap_or_default().trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if coding.eq_ignore_ascii_case("br") {
+                brotli = Some(quality);
+            } else if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+                gzip = Some(quality);
+            } else if coding == "*" {
+                any = Some(quality);
+            }
+        }
+        // A wildcard covers the codings not named on their own
+        let brotli = brotli.or(any).unwrap_or(0.0);
+        let gzip = gzip.or(any).unwrap_or(0.0);
+        if brotli <= 0.0 && gzip <= 0.0 {
+            None
+        } else if brotli >= gzip {
+            Some(Encoding::Brotli)
+        } else {
+            Some(Encoding::Gzip)
+        }
+    }
+
+    /// `Content-Encoding` value and metrics label.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Incremental compressor handing out its output as it is produced.
+pub struct Encoder {
+    encoding: Encoding,
+    codec: Codec,
+    bytes_in: usize,
+    bytes_out: usize,
+}
+
+enum Codec {
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Gzip(GzEncoder<Vec<u8>>),
+}
+
+impl Encoder {
+    pub fn new(encoding: Encoding) -> Self {
+        let codec = match encoding {
+            Encoding::Brotli => Codec::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            ))),
+            Encoding::Gzip => Codec::Gzip(GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(GZIP_LEVEL),
+            )),
+        };
+        Self {
+            encoding,
+            codec,
+            bytes_in: 0,
+            bytes_out: 0,
+        }
+    }
+
+    /// Feed `data`; returns whatever compressed output is ready, possibly none.
+    pub fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        self.bytes_in += data.len();
+        let out = match &mut self.codec {
+            Codec::Brotli(writer) => {
+                writer.write_all(data)?;
+                mem::take(writer.get_mut())
+            }
+            Codec::Gzip(writer) => {
+                writer.write_all(data)?;
+                mem::take(writer.get_mut())
+            }
+        };
+        self.bytes_out += out.len();
+        Ok(Bytes::from(out))
+    }
+
+    /// End the compressed stream, recording the bytes it saved.
+    pub fn finish(self, metrics: &ProxyMetrics) -> io::Result<Bytes> {
+        let out = match self.codec {
+            Codec::Brotli(writer) => writer.into_inner(),
+            Codec::Gzip(writer) => writer.finish()?,
+        };
+        metrics.record_compression(
+            self.encoding.name(),
+            self.bytes_in,
+            self.bytes_out + out.len(),
+        );
+        Ok(Bytes::from(out))
+    }
+}
+
+pub struct Compression {
+    min_bytes: usize,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl Compression {
+    pub fn new(min_bytes: usize, metrics: Arc<ProxyMetrics>) -> Self {
+        Self { min_bytes, metrics }
+    }
+
+    /// `response` encoded for a client that sent `accept`, if worth it.
+    pub async fn apply(&self, accept: Option<&HeaderValue>, mut response: Response) -> Response {
+        let headers = response.headers();
+        let json = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("application/json"));
+        if !json || headers.contains_key(CONTENT_ENCODING) {
+            return response;
+        }
+        response
+            .headers_mut()
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+        let Some(encoding) = accept
+            .and_then(|value| value.to_str().ok())
+            .and_then(Encoding::negotiate)
+        else {
+            return response;
+        };
+        // Streamed bodies carry their length in the header, if known at all
+        let len = response.body().size_hint().exact().or_else(|| {
+            let value = response.headers().get(CONTENT_LENGTH)?;
+            value.to_str().ok()?.parse().ok()
+        });
+        if len.is_some_and(|len| len < self.min_bytes as u64) {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+        let mut encoder = Encoder::new(encoding);
+        if body.size_hint().exact().is_some() {
+            let compressed = match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(data) => encoder.write(&data).and_then(|head| {
+                    let tail = encoder.finish(&self.metrics)?;
+                    Ok([head, tail].concat())
+                }),
+                Err(err) => Err(io::Error::other(err)),
+            };
+            return match compressed {
+                Ok(compressed) => {
+                    parts
+                        .headers
+                        .insert(CONTENT_LENGTH, compressed.len().into());
+                    Response::from_parts(parts, Body::from(compressed))
+                }
+                Err(err) => {
+                    error!(error = %err, "failed to compress response");
+                    let message = "response compression failed";
+                    (StatusCode::INTERNAL_SERVER_ERROR, message).into_response()
+                }
+            };
+        }
+
+        let metrics = self.metrics.clone();
+        let state = Some((body.into_data_stream(), encoder));
+        let chunks = futures::stream::try_unfold(state, move |state| {
+            let metrics = metrics.clone();
+            async move {
+                let Some((mut data, mut encoder)) = state else {
+                    return Ok(None);
+                };
+                while let Some(chunk) = data.try_next().await? {
+                    let out = encoder.write(&chunk).map_err(axum::Error::new)?;
+                    if !out.is_empty() {
+                        return Ok(Some((out, Some((data, encoder)))));
+                    }
+                }
+                let out = encoder.finish(&metrics).map_err(axum::Error::new)?;
+                Ok::<_, axum::Error>(Some((out, None)))
+            }
+        });
+        Response::from_parts(parts, Body::from_stream(chunks))
+    }
+}
+
+/// Middleware compressing the responses of the routes it wraps.
+pub async fn middleware(
+    State(compression): State<Arc<Compression>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accept = request.headers().get(ACCEPT_ENCODING).cloned();
+    let response = next.run(request).await;
+    compression.apply(accept.as_ref(), response).await
+}
@@ -1,13 +1,15 @@
 // Numan Thabit 2025
 use std::{
+    collections::{HashMap, HashSet},
     fs,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     time::Duration,
 };
 
-use anyhow::{bail, Context, Result};
-use clap::Parser;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Parser, ValueEnum};
+use ipnet::IpNet;
 use quinn::VarInt;
 use serde::Deserialize;
 use tracing::info;
@@ -17,7 +19,11 @@ const DEFAULT_UPSTREAM: &str = "127.0.0.1:8899";
 const DEFAULT_SERVER_NAME: &str = "solana-ultra-rpc";
 const DEFAULT_MAX_REQUEST_BYTES: usize = 4 * 1024 * 1024;
 const DEFAULT_MAX_RESPONSE_BYTES: usize = 8 * 1024 * 1024;
+const DEFAULT_MAX_STREAM_BYTES: usize = 1024 * 1024 * 1024;
+const DEFAULT_COMPRESSION_MIN_BYTES: usize = 1024;
+const DEFAULT_MAX_PARAMS_BYTES: usize = 1024 * 1024;
 const DEFAULT_MAX_STREAMS: u32 = 1024;
+const DEFAULT_CONNECTIONS_PER_UPSTREAM: usize = 1;
 const DEFAULT_KEEP_ALIVE_MS: u64 = 500;
 const DEFAULT_MAX_IDLE_TIMEOUT_MS: u64 = 15_000;
 const DEFAULT_INITIAL_MTU: u16 = 1_400;
@@ -27,10 +33,43 @@ const DEFAULT_SEND_WINDOW: u64 = 64 * 1024 * 1024;
 const DEFAULT_DATAGRAM_BUFFER: usize = 2 * 1024 * 1024;
 const DEFAULT_CONFIG_PATH: &str = "ops/solana-quic-proxy.toml";
 const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 1200;
+const DEFAULT_SLOW_REQUEST_MS: u64 = 1000;
 const DEFAULT_HEDGED_ATTEMPTS: u32 = 1;
 const DEFAULT_HEDGE_JITTER_MS: u64 = 25;
 const DEFAULT_ENABLE_EARLY_DATA: bool = true;
 const DEFAULT_PREOPEN_STREAMS: u32 = 0;
+const DEFAULT_HEALTH_CHECK_INTERVAL_MS: u64 = 1000;
+const DEFAULT_EJECT_AFTER_FAILURES: u32 = 3;
+const DEFAULT_EJECT_MS: u64 = 5000;
+const DEFAULT_BREAKER_WINDOW: usize = 20;
+const DEFAULT_BREAKER_THRESHOLD: f64 = 0.5;
+const DEFAULT_BREAKER_SLOW_MS: u64 = 0;
+const DEFAULT_BREAKER_OPEN_MS: u64 = 10_000;
+const DEFAULT_AFFINITY_TTL_MS: u64 = 0;
+const DEFAULT_AFFINITY_HEADER: &str = "x-session-id";
+const DEFAULT_CACHE_MAX_ENTRIES: usize = 10_000;
+/// Methods cached by default and their TTLs in milliseconds.
+const DEFAULT_CACHE_TTLS_MS: &[(&str, u64)] = &[
+    ("getGenesisHash", 3_600_000),
+    ("getVersion", 60_000),
+    ("getEpochSchedule", 3_600_000),
+    ("getLatestBlockhash", 400),
+];
+const DEFAULT_API_KEY_HEADER: &str = "x-api-key";
+/// Rate-limit tokens charged per call of these methods; every other call costs 1.
+const DEFAULT_METHOD_WEIGHTS: &[(&str, u32)] = &[("getProgramAccounts", 10), ("getBlock", 5)];
+const DEFAULT_TLS_RELOAD_INTERVAL_MS: u64 = 10_000;
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 20;
+/// Methods sent exactly once: never retried or hedged.
+const DEFAULT_NEVER_RETRY: &[&str] = &["sendTransaction", "requestAirdrop"];
+/// Metrics label of the route taken by every method without its own.
+pub const DEFAULT_ROUTE: &str = "default";
+/// Methods that are never coalesced: every call must reach the upstream.
+const DEFAULT_NEVER_COALESCE: &[&str] = &["sendTransaction", "requestAirdrop"];
+/// Methods whose responses are streamed through rather than buffered.
+const DEFAULT_STREAM_METHODS: &[&str] = &["getProgramAccounts"];
 
 #[derive(Parser, Debug, Clone)]
 #[command(
@@ -48,9 +87,74 @@ pub struct CliArgs {
     #[arg(long)]
     pub listen: Option<SocketAddr>,
 
-    /// QUIC upstream (solana-ultra-rpc) socket address.
+    /// Loopback address for the admin API (upstream management, cache
+    /// flush); unset disables it.
     #[arg(long)]
-    pub upstream: Option<SocketAddr>,
+    pub admin_listen: Option<SocketAddr>,
+
+    /// PEM certificate chain served to clients; enables TLS (with HTTP/2) on the listener.
+    #[arg(long, value_name = "PATH")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for `--tls-cert`.
+    #[arg(long, value_name = "PATH")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Interval for checking the TLS certificate and key for changes in
+    /// milliseconds (0 disables reloading).
+    #[arg(long)]
+    pub tls_reload_interval_ms: Option<u64>,
+
+    /// QUIC upstream (solana-ultra-rpc) socket address, as ADDR or ADDR@WEIGHT;
+    /// repeat for several upstreams.
+    #[arg(long, value_parser = parse_upstream)]
+    pub upstream: Vec<UpstreamConfig>,
+
+    /// How requests are spread over upstreams.
+    #[arg(long, value_enum)]
+    pub upstream_policy: Option<UpstreamPolicy>,
+
+    /// Interval between upstream health probes in milliseconds (0 disables probing).
+    #[arg(long)]
+    pub health_check_interval_ms: Option<u64>,
+
+    /// Consecutive failures after which an upstream is ejected.
+    #[arg(long)]
+    pub eject_after_failures: Option<u32>,
+
+    /// How long an ejected upstream is skipped in milliseconds, unless a probe
+    /// readmits it sooner.
+    #[arg(long)]
+    pub eject_ms: Option<u64>,
+
+    /// Requests per upstream the circuit breaker judges at a time (0 disables
+    /// the breaker).
+    #[arg(long)]
+    pub breaker_window: Option<usize>,
+
+    /// Share of failed (or slow) requests in the window that opens the circuit.
+    #[arg(long)]
+    pub breaker_threshold: Option<f64>,
+
+    /// Requests slower than this many milliseconds count as slow (0: latency
+    /// never opens the circuit).
+    #[arg(long)]
+    pub breaker_slow_ms: Option<u64>,
+
+    /// How long an open circuit rejects requests before a trial request, in
+    /// milliseconds.
+    #[arg(long)]
+    pub breaker_open_ms: Option<u64>,
+
+    /// How long a session stays pinned to the upstream that last answered it,
+    /// in milliseconds (0 disables sticky routing).
+    #[arg(long)]
+    pub affinity_ttl_ms: Option<u64>,
+
+    /// Request header carrying the session hint; clients without one are
+    /// pinned by API key.
+    #[arg(long)]
+    pub affinity_header: Option<String>,
 
     /// TLS server name used for SNI when connecting upstream.
     #[arg(long)]
@@ -64,14 +168,48 @@ pub struct CliArgs {
     #[arg(long)]
     pub max_request_bytes: Option<usize>,
 
+    /// Maximum number of requests in a batch; later ones get an error (0 for
+    /// no limit). For batches `max_request_bytes` applies to each request.
+    #[arg(long)]
+    pub max_batch_size: Option<usize>,
+
+    /// Send each request of a batch on its own, spread over the upstreams,
+    /// instead of forwarding the batch as one.
+    #[arg(long, default_value_t = false)]
+    pub split_batches: bool,
+
     /// Maximum JSON-RPC response body size in bytes.
     #[arg(long)]
     pub max_response_bytes: Option<usize>,
 
+    /// Methods whose responses are streamed to the client as they arrive
+    /// instead of being buffered (comma-separated; replaces the default list).
+    #[arg(long, value_delimiter = ',')]
+    pub stream_methods: Vec<String>,
+
+    /// Maximum size in bytes of a streamed response.
+    #[arg(long)]
+    pub max_stream_bytes: Option<usize>,
+
+    /// Never compress responses, whatever the client accepts.
+    #[arg(long, default_value_t = false)]
+    pub no_compression: bool,
+
+    /// Smallest response in bytes compressed for clients that accept gzip or
+    /// brotli; streamed responses of unknown length are always compressed.
+    #[arg(long)]
+    pub compression_min_bytes: Option<usize>,
+
     /// Maximum number of concurrent bi-directional streams per QUIC connection.
     #[arg(long)]
     pub max_streams: Option<u32>,
 
+    /// QUIC connections kept open to each upstream; requests rotate across
+    /// them, and unless `lazy_connect` is set a closed one is re-established
+    /// right away instead of on the next request.
+    #[arg(long)]
+    pub connections_per_upstream: Option<usize>,
+
     /// Interval for QUIC keep-alive pings in milliseconds (0 disables keep-alives).
     #[arg(long)]
     pub keep_alive_ms: Option<u64>,
@@ -111,11 +249,28 @@ pub struct CliArgs {
     #[arg(long, default_value_t = false)]
     pub http_trace: bool,
 
+    /// Log requests taking longer than this many milliseconds, with method,
+    /// upstream, sizes and request ID (0 disables the slow-request log).
+    #[arg(long)]
+    pub slow_request_ms: Option<u64>,
+
     /// Per-request deadline in milliseconds (0 disables timeout).
     #[arg(long)]
     pub request_timeout_ms: Option<u64>,
 
-    /// Number of hedged attempts per request (1 disables hedging).
+    /// Retries after a transient upstream failure (0 disables retries).
+    #[arg(long)]
+    pub max_retries: Option<u32>,
+
+    /// Backoff before the first retry in milliseconds; doubles on each retry.
+    #[arg(long)]
+    pub retry_backoff_ms: Option<u64>,
+
+    /// Methods never retried or hedged (comma-separated; replaces the default list).
+    #[arg(long, value_delimiter = ',')]
+    pub never_retry: Vec<String>,
+
+    /// Number of hedged attempts per read-only request (1 disables hedging).
     #[arg(long)]
     pub hedged_attempts: Option<u32>,
 
@@ -130,17 +285,241 @@ pub struct CliArgs {
     /// Number of bi-directional streams to pre-open during warmup.
     #[arg(long)]
     pub preopen_streams: Option<u32>,
+
+    /// Cache responses to METHOD for MS milliseconds, as METHOD=MS; repeat for
+    /// several methods. A TTL of 0 turns caching off for that method.
+    #[arg(long = "cache-ttl", value_parser = parse_cache_ttl)]
+    pub cache_ttl: Vec<(String, u64)>,
+
+    /// Maximum number of cached responses.
+    #[arg(long)]
+    pub cache_max_entries: Option<usize>,
+
+    /// Requests per second allowed to each client (API key, or IP without one).
+    /// Unset or 0 disables rate limiting.
+    #[arg(long)]
+    pub rate_limit_rps: Option<f64>,
+
+    /// Requests a client may burst above its rate; defaults to one second's worth.
+    #[arg(long)]
+    pub rate_limit_burst: Option<f64>,
+
+    /// Rate-limit cost of a call to METHOD, as METHOD=N; repeat for several methods.
+    #[arg(long = "method-weight", value_parser = parse_method_weight)]
+    pub method_weight: Vec<(String, u32)>,
+
+    /// Request header carrying the API key.
+    #[arg(long)]
+    pub api_key_header: Option<String>,
+
+    /// Reject requests without a known API key.
+    #[arg(long, default_value_t = false)]
+    pub require_api_key: bool,
+
+    /// Only accept clients from these addresses or CIDR ranges
+    /// (comma-separated; empty accepts everyone).
+    #[arg(long, value_delimiter = ',', value_parser = parse_ip_net)]
+    pub ip_allow: Vec<IpNet>,
+
+    /// Refuse clients from these addresses or CIDR ranges, even if allowed
+    /// (comma-separated).
+    #[arg(long, value_delimiter = ',', value_parser = parse_ip_net)]
+    pub ip_deny: Vec<IpNet>,
+
+    /// Only serve these JSON-RPC methods (comma-separated; empty serves all).
+    #[arg(long, value_delimiter = ',')]
+    pub allowed_methods: Vec<String>,
+
+    /// Maximum size in bytes of a request's params (0 for no limit).
+    #[arg(long)]
+    pub max_params_bytes: Option<usize>,
+
+    /// Forward malformed requests (not JSON-RPC 2.0) instead of rejecting them.
+    #[arg(long, default_value_t = false)]
+    pub no_validate_requests: bool,
+
+    /// Send every request upstream, even while an identical one is in flight.
+    #[arg(long, default_value_t = false)]
+    pub no_coalesce: bool,
+
+    /// Methods never coalesced with identical in-flight requests
+    /// (comma-separated; replaces the default list).
+    #[arg(long, value_delimiter = ',')]
+    pub never_coalesce: Vec<String>,
+
+    /// Upstream WebSocket URL (ws://host:port) that WS clients are relayed to.
+    #[arg(long)]
+    pub ws_upstream: Option<String>,
+}
+
+/// How requests are spread over healthy upstreams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamPolicy {
+    /// Smooth weighted round-robin.
+    Weighted,
+    /// The upstream with the lowest recent round-trip latency.
+    LeastLatency,
+}
+
+/// Circuit breaker thresholds, shared by every upstream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BreakerConfig {
+    pub window: usize,
+    pub threshold: f64,
+    pub slow_after: Option<Duration>,
+    pub open_for: Duration,
+}
+
+/// One QUIC upstream.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct UpstreamConfig {
+    pub addr: SocketAddr,
+    /// SNI name; defaults to the global `server_name`.
+    #[serde(default)]
+    pub server_name: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// Requests for `methods` go to the route's own upstreams instead of the
+/// default ones: either a pool of QUIC `upstreams` or a plain HTTP JSON-RPC
+/// `url`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RouteConfig {
+    /// Label for the route's metrics.
+    pub name: String,
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub upstreams: Vec<UpstreamConfig>,
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+/// A token bucket: `rps` tokens a second, holding at most `burst`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub rps: f64,
+    pub burst: f64,
+}
+
+/// A known API key. Its requests are counted under `name` and limited to
+/// `rps`/`burst` when set, the global rate limit otherwise.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub name: String,
+    #[serde(default)]
+    pub rps: Option<f64>,
+    #[serde(default)]
+    pub burst: Option<f64>,
+}
+
+impl ApiKeyConfig {
+    /// This key's own limit, if it sets one.
+    pub fn limit(&self) -> Option<RateLimit> {
+        let rps = self.rps?;
+        Some(RateLimit {
+            rps,
+            burst: self.burst.unwrap_or(rps),
+        })
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn parse_cache_ttl(s: &str) -> Result<(String, u64)> {
+    let (method, ttl) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected METHOD=MS, got {s}"))?;
+    let ttl = ttl
+        .parse()
+        .map_err(|_| anyhow!("invalid cache TTL in {s}"))?;
+    Ok((method.to_string(), ttl))
+}
+
+fn parse_method_weight(s: &str) -> Result<(String, u32)> {
+    let (method, weight) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected METHOD=N, got {s}"))?;
+    let weight = weight
+        .parse()
+        .map_err(|_| anyhow!("invalid method weight in {s}"))?;
+    Ok((method.to_string(), weight))
+}
+
+/// A CIDR range, or a single address as a range of one.
+fn parse_ip_net(s: &str) -> Result<IpNet> {
+    s.parse()
+        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| anyhow!("expected an IP address or CIDR range, got {s}"))
+}
+
+/// Networks from the CLI, else the config file.
+fn ip_nets(cli: &[IpNet], file: Option<Vec<String>>) -> Result<Vec<IpNet>> {
+    if !cli.is_empty() {
+        return Ok(cli.to_vec());
+    }
+    file.unwrap_or_default()
+        .iter()
+        .map(|s| parse_ip_net(s))
+        .collect()
+}
+
+fn parse_upstream(s: &str) -> Result<UpstreamConfig> {
+    let (addr, weight) = match s.split_once('@') {
+        Some((addr, weight)) => (
+            addr,
+            weight
+                .parse()
+                .map_err(|_| anyhow!("invalid upstream weight in {s}"))?,
+        ),
+        None => (s, default_weight()),
+    };
+    Ok(UpstreamConfig {
+        addr: addr
+            .parse()
+            .map_err(|_| anyhow!("invalid upstream address in {s}"))?,
+        server_name: None,
+        weight,
+    })
 }
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub listen: SocketAddr,
-    pub upstream: SocketAddr,
+    pub admin_listen: Option<SocketAddr>,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub tls_reload_interval: Option<Duration>,
+    pub upstreams: Vec<UpstreamConfig>,
+    pub upstream_policy: UpstreamPolicy,
+    pub health_check_interval: Option<Duration>,
+    pub eject_after_failures: u32,
+    pub eject_duration: Duration,
+    /// `None` when the circuit breaker is disabled.
+    pub breaker: Option<BreakerConfig>,
+    /// `None` when sticky routing is disabled.
+    pub affinity_ttl: Option<Duration>,
+    pub affinity_header: String,
     pub server_name: String,
     pub ca_cert: Option<PathBuf>,
     pub max_request_bytes: usize,
     pub max_response_bytes: usize,
+    /// Methods answered by streaming; single requests only, never cached or
+    /// coalesced.
+    pub stream_methods: HashSet<String>,
+    pub max_stream_bytes: usize,
+    /// Compress responses per `Accept-Encoding`.
+    pub compression: bool,
+    pub compression_min_bytes: usize,
+    /// `None` for no limit.
+    pub max_batch_size: Option<usize>,
+    pub split_batches: bool,
     pub max_streams: u32,
+    pub connections_per_upstream: usize,
     pub keep_alive: Option<Duration>,
     pub max_idle_timeout: Option<Duration>,
     pub initial_mtu: u16,
@@ -152,22 +531,71 @@ pub struct Config {
     pub lazy_connect: bool,
     pub config_path: Option<PathBuf>,
     pub http_trace: bool,
+    /// `None` when the slow-request log is off.
+    pub slow_request: Option<Duration>,
     pub request_timeout: Option<Duration>,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+    pub never_retry: HashSet<String>,
     pub hedged_attempts: u32,
     pub hedge_jitter: Duration,
     pub enable_early_data: bool,
     pub preopen_streams: u32,
+    pub ws_upstream: Option<String>,
+    /// Cached methods and their TTLs.
+    pub cache_ttls: HashMap<String, Duration>,
+    pub cache_max_entries: usize,
+    pub coalesce: bool,
+    pub never_coalesce: HashSet<String>,
+    /// Method routes; methods not listed go to `upstreams`.
+    pub routes: Vec<RouteConfig>,
+    /// Per-client limit; `None` leaves clients without a limit of their own unlimited.
+    pub rate_limit: Option<RateLimit>,
+    pub method_weights: HashMap<String, u32>,
+    pub api_key_header: String,
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub require_api_key: bool,
+    /// Client networks accepted; empty accepts everyone.
+    pub ip_allow: Vec<IpNet>,
+    pub ip_deny: Vec<IpNet>,
+    /// `None` serves every method.
+    pub allowed_methods: Option<HashSet<String>>,
+    /// `None` for no limit.
+    pub max_params_bytes: Option<usize>,
+    pub validate_requests: bool,
 }
 
 #[derive(Debug, Deserialize, Default)]
 struct FileConfig {
     listen: Option<SocketAddr>,
+    admin_listen: Option<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    tls_reload_interval_ms: Option<u64>,
     upstream: Option<SocketAddr>,
+    upstreams: Option<Vec<UpstreamConfig>>,
+    upstream_policy: Option<UpstreamPolicy>,
+    health_check_interval_ms: Option<u64>,
+    eject_after_failures: Option<u32>,
+    eject_ms: Option<u64>,
+    breaker_window: Option<usize>,
+    breaker_threshold: Option<f64>,
+    breaker_slow_ms: Option<u64>,
+    breaker_open_ms: Option<u64>,
+    affinity_ttl_ms: Option<u64>,
+    affinity_header: Option<String>,
     server_name: Option<String>,
     ca_cert: Option<PathBuf>,
     max_request_bytes: Option<usize>,
     max_response_bytes: Option<usize>,
+    stream_methods: Option<Vec<String>>,
+    max_stream_bytes: Option<usize>,
+    compression: Option<bool>,
+    compression_min_bytes: Option<usize>,
+    max_batch_size: Option<usize>,
+    split_batches: Option<bool>,
     max_streams: Option<u32>,
+    connections_per_upstream: Option<usize>,
     keep_alive_ms: Option<u64>,
     max_idle_timeout_ms: Option<u64>,
     initial_mtu: Option<u16>,
@@ -178,11 +606,32 @@ struct FileConfig {
     datagram_recv_buffer: Option<usize>,
     lazy_connect: Option<bool>,
     http_trace: Option<bool>,
+    slow_request_ms: Option<u64>,
     request_timeout_ms: Option<u64>,
+    max_retries: Option<u32>,
+    retry_backoff_ms: Option<u64>,
+    never_retry: Option<Vec<String>>,
     hedged_attempts: Option<u32>,
     hedge_jitter_ms: Option<u64>,
     enable_early_data: Option<bool>,
     preopen_streams: Option<u32>,
+    ws_upstream: Option<String>,
+    cache_ttl_ms: Option<HashMap<String, u64>>,
+    cache_max_entries: Option<usize>,
+    coalesce: Option<bool>,
+    never_coalesce: Option<Vec<String>>,
+    routes: Option<Vec<RouteConfig>>,
+    rate_limit_rps: Option<f64>,
+    rate_limit_burst: Option<f64>,
+    method_weights: Option<HashMap<String, u32>>,
+    api_key_header: Option<String>,
+    api_keys: Option<Vec<ApiKeyConfig>>,
+    require_api_key: Option<bool>,
+    ip_allow: Option<Vec<String>>,
+    ip_deny: Option<Vec<String>>,
+    allowed_methods: Option<Vec<String>>,
+    max_params_bytes: Option<usize>,
+    validate_requests: Option<bool>,
 }
 
 impl Config {
@@ -196,6 +645,29 @@ impl Config {
     }
 
     fn validate(&self) -> Result<()> {
+        if self
+            .admin_listen
+            .is_some_and(|addr| !addr.ip().is_loopback())
+        {
+            bail!("admin_listen must be a loopback address");
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            bail!("tls_cert and tls_key must be set together");
+        }
+        if self.upstreams.is_empty() {
+            bail!("at least one upstream is required");
+        }
+        if self.upstreams.iter().all(|u| u.weight == 0) {
+            bail!("at least one upstream must have a non-zero weight");
+        }
+        if self.eject_after_failures == 0 {
+            bail!("eject_after_failures must be greater than 0");
+        }
+        if let Some(breaker) = &self.breaker {
+            if !(breaker.threshold > 0.0 && breaker.threshold <= 1.0) {
+                bail!("breaker_threshold must be in (0, 1]");
+            }
+        }
         if self.max_request_bytes == 0 {
             bail!("max_request_bytes must be greater than 0");
         }
@@ -208,9 +680,18 @@ impl Config {
         if self.max_response_bytes > u32::MAX as usize {
             bail!("max_response_bytes must not exceed 4GiB (u32 frame limit)");
         }
+        if self.max_stream_bytes == 0 {
+            bail!("max_stream_bytes must be greater than 0");
+        }
+        if self.max_stream_bytes > u32::MAX as usize {
+            bail!("max_stream_bytes must not exceed 4GiB (u32 frame limit)");
+        }
         if self.max_streams == 0 {
             bail!("max_streams must be greater than 0");
         }
+        if self.connections_per_upstream == 0 {
+            bail!("connections_per_upstream must be greater than 0");
+        }
         if self.initial_mtu < 1200 {
             bail!("initial_mtu must be at least 1200 bytes");
         }
@@ -233,17 +714,86 @@ impl Config {
                 bail!("datagram_recv_buffer must be greater than 0 when specified");
             }
         }
+        let mut routed = HashSet::new();
+        let mut names = HashSet::from([DEFAULT_ROUTE]);
+        for route in &self.routes {
+            if !names.insert(route.name.as_str()) {
+                bail!("route name {} is used more than once", route.name);
+            }
+            match (&route.url, route.upstreams.is_empty()) {
+                (Some(url), true) => {
+                    if !url.starts_with("http://") && !url.starts_with("https://") {
+                        bail!(
+                            "route {} url must be an http:// or https:// URL",
+                            route.name
+                        );
+                    }
+                }
+                (None, false) => {
+                    if route.upstreams.iter().all(|u| u.weight == 0) {
+                        bail!(
+                            "route {} needs an upstream with a non-zero weight",
+                            route.name
+                        );
+                    }
+                }
+                _ => bail!(
+                    "route {} needs either upstreams or a url, not both",
+                    route.name
+                ),
+            }
+            for method in &route.methods {
+                if !routed.insert(method.as_str()) {
+                    bail!("method {method} is routed more than once");
+                }
+            }
+        }
+        let mut limits = self
+            .rate_limit
+            .into_iter()
+            .chain(self.api_keys.iter().filter_map(ApiKeyConfig::limit));
+        if limits.any(|l| !l.rps.is_finite() || l.rps <= 0.0 || l.burst < 1.0) {
+            bail!("rate limits need a positive rps and a burst of at least 1");
+        }
+        let mut keys = HashSet::new();
+        for api_key in &self.api_keys {
+            if !keys.insert(api_key.key.as_str()) {
+                bail!("API key {} is configured more than once", api_key.name);
+            }
+        }
+        if self.require_api_key && self.api_keys.is_empty() {
+            bail!("require_api_key needs at least one API key");
+        }
+        if let Some(url) = &self.ws_upstream {
+            if !url.starts_with("ws://") {
+                bail!("ws_upstream must be a ws:// URL");
+            }
+        }
         Ok(())
     }
 
     fn log_summary(&self) {
         info!(
             listen = %self.listen,
-            upstream = %self.upstream,
+            admin_listen = ?self.admin_listen,
+            tls = self.tls_cert.is_some(),
+            upstreams = ?self.upstreams.iter().map(|u| u.addr).collect::<Vec<_>>(),
+            upstream_policy = ?self.upstream_policy,
+            health_check_interval = ?self.health_check_interval,
+            breaker = ?self.breaker,
+            affinity_ttl = ?self.affinity_ttl,
+            affinity_header = %self.affinity_header,
             server_name = %self.server_name,
             keep_alive = ?self.keep_alive,
             idle_timeout = ?self.max_idle_timeout,
+            max_batch_size = ?self.max_batch_size,
+            split_batches = self.split_batches,
+            stream_methods = ?self.stream_methods,
+            max_stream_bytes = self.max_stream_bytes,
+            compression = self.compression,
+            compression_min_bytes = self.compression_min_bytes,
             max_streams = self.max_streams,
+            connections_per_upstream = self.connections_per_upstream,
             mtu = self.initial_mtu,
             stream_window = self.stream_receive_window,
             connection_window = self.connection_receive_window,
@@ -252,10 +802,25 @@ impl Config {
             datagram_recv = ?self.datagram_recv_buffer,
             lazy_connect = self.lazy_connect,
             http_trace = self.http_trace,
+            slow_request = ?self.slow_request,
             request_timeout = ?self.request_timeout,
+            max_retries = self.max_retries,
+            retry_backoff_ms = self.retry_backoff.as_millis(),
             hedged_attempts = self.hedged_attempts,
             hedge_jitter_ms = self.hedge_jitter.as_millis(),
             enable_early_data = self.enable_early_data,
+            ws_upstream = ?self.ws_upstream,
+            cached_methods = self.cache_ttls.len(),
+            coalesce = self.coalesce,
+            ip_allow = self.ip_allow.len(),
+            ip_deny = self.ip_deny.len(),
+            allowed_methods = ?self.allowed_methods.as_ref().map(HashSet::len),
+            max_params_bytes = ?self.max_params_bytes,
+            validate_requests = self.validate_requests,
+            routes = ?self.routes.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            rate_limit = ?self.rate_limit,
+            api_keys = self.api_keys.len(),
+            require_api_key = self.require_api_key,
             "solana-quic-proxy configuration"
         );
     }
@@ -266,16 +831,101 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
     let file_cfg = file_cfg.unwrap_or_default();
 
     let listen = pick(cli.listen, file_cfg.listen, DEFAULT_LISTEN.parse().unwrap());
-    let upstream = pick(
-        cli.upstream,
-        file_cfg.upstream,
-        DEFAULT_UPSTREAM.parse().unwrap(),
+    let admin_listen = cli.admin_listen.or(file_cfg.admin_listen);
+    let tls_cert = cli.tls_cert.clone().or(file_cfg.tls_cert);
+    let tls_key = cli.tls_key.clone().or(file_cfg.tls_key);
+    let tls_reload_interval_ms = pick(
+        cli.tls_reload_interval_ms,
+        file_cfg.tls_reload_interval_ms,
+        DEFAULT_TLS_RELOAD_INTERVAL_MS,
     );
+    let tls_reload_interval = if tls_reload_interval_ms == 0 {
+        None
+    } else {
+        Some(Duration::from_millis(tls_reload_interval_ms))
+    };
     let server_name = pick(
         cli.server_name.clone(),
         file_cfg.server_name,
         DEFAULT_SERVER_NAME.to_string(),
     );
+    let mut upstreams = if !cli.upstream.is_empty() {
+        cli.upstream.clone()
+    } else if let Some(upstreams) = file_cfg.upstreams {
+        upstreams
+    } else {
+        let addr = pick(None, file_cfg.upstream, DEFAULT_UPSTREAM.parse().unwrap());
+        vec![UpstreamConfig {
+            addr,
+            server_name: None,
+            weight: default_weight(),
+        }]
+    };
+    let mut routes = file_cfg.routes.unwrap_or_default();
+    for upstream in upstreams
+        .iter_mut()
+        .chain(routes.iter_mut().flat_map(|r| r.upstreams.iter_mut()))
+    {
+        upstream
+            .server_name
+            .get_or_insert_with(|| server_name.clone());
+    }
+    let upstream_policy = pick(
+        cli.upstream_policy,
+        file_cfg.upstream_policy,
+        UpstreamPolicy::Weighted,
+    );
+    let health_check_interval_ms = pick(
+        cli.health_check_interval_ms,
+        file_cfg.health_check_interval_ms,
+        DEFAULT_HEALTH_CHECK_INTERVAL_MS,
+    );
+    let health_check_interval = if health_check_interval_ms == 0 {
+        None
+    } else {
+        Some(Duration::from_millis(health_check_interval_ms))
+    };
+    let eject_after_failures = pick(
+        cli.eject_after_failures,
+        file_cfg.eject_after_failures,
+        DEFAULT_EJECT_AFTER_FAILURES,
+    );
+    let eject_ms = pick(cli.eject_ms, file_cfg.eject_ms, DEFAULT_EJECT_MS);
+    let breaker_window = pick(
+        cli.breaker_window,
+        file_cfg.breaker_window,
+        DEFAULT_BREAKER_WINDOW,
+    );
+    let breaker_slow_ms = pick(
+        cli.breaker_slow_ms,
+        file_cfg.breaker_slow_ms,
+        DEFAULT_BREAKER_SLOW_MS,
+    );
+    let breaker = (breaker_window > 0).then(|| BreakerConfig {
+        window: breaker_window,
+        threshold: pick(
+            cli.breaker_threshold,
+            file_cfg.breaker_threshold,
+            DEFAULT_BREAKER_THRESHOLD,
+        ),
+        slow_after: (breaker_slow_ms > 0).then(|| Duration::from_millis(breaker_slow_ms)),
+        open_for: Duration::from_millis(pick(
+            cli.breaker_open_ms,
+            file_cfg.breaker_open_ms,
+            DEFAULT_BREAKER_OPEN_MS,
+        )),
+    });
+    let affinity_ttl_ms = pick(
+        cli.affinity_ttl_ms,
+        file_cfg.affinity_ttl_ms,
+        DEFAULT_AFFINITY_TTL_MS,
+    );
+    let affinity_ttl = (affinity_ttl_ms > 0).then(|| Duration::from_millis(affinity_ttl_ms));
+    let affinity_header = pick(
+        cli.affinity_header.clone(),
+        file_cfg.affinity_header,
+        DEFAULT_AFFINITY_HEADER.to_string(),
+    );
     let ca_cert = cli.ca_cert.clone().or(file_cfg.ca_cert);
     let max_request_bytes = pick(
         cli.max_request_bytes,
@@ -287,7 +937,19 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
         file_cfg.max_response_bytes,
         DEFAULT_MAX_RESPONSE_BYTES,
     );
+    let max_batch_size = Some(pick(
+        cli.max_batch_size,
+        file_cfg.max_batch_size,
+        DEFAULT_MAX_BATCH_SIZE,
+    ))
+    .filter(|&size| size > 0);
+    let split_batches = cli.split_batches || file_cfg.split_batches.unwrap_or(false);
     let max_streams = pick(cli.max_streams, file_cfg.max_streams, DEFAULT_MAX_STREAMS);
+    let connections_per_upstream = pick(
+        cli.connections_per_upstream,
+        file_cfg.connections_per_upstream,
+        DEFAULT_CONNECTIONS_PER_UPSTREAM,
+    );
 
     let keep_alive_ms = pick(
         cli.keep_alive_ms,
@@ -331,6 +993,12 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
 
     let lazy_connect = cli.lazy_connect || file_cfg.lazy_connect.unwrap_or(false);
     let http_trace = cli.http_trace || file_cfg.http_trace.unwrap_or(false);
+    let slow_request_ms = pick(
+        cli.slow_request_ms,
+        file_cfg.slow_request_ms,
+        DEFAULT_SLOW_REQUEST_MS,
+    );
+    let slow_request = (slow_request_ms > 0).then(|| Duration::from_millis(slow_request_ms));
 
     let request_timeout_ms = pick(
         cli.request_timeout_ms,
@@ -342,6 +1010,22 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
     } else {
         Some(Duration::from_millis(request_timeout_ms))
     };
+    let max_retries = pick(cli.max_retries, file_cfg.max_retries, DEFAULT_MAX_RETRIES);
+    let retry_backoff_ms = pick(
+        cli.retry_backoff_ms,
+        file_cfg.retry_backoff_ms,
+        DEFAULT_RETRY_BACKOFF_MS,
+    );
+    let never_retry = if !cli.never_retry.is_empty() {
+        cli.never_retry.iter().cloned().collect()
+    } else if let Some(methods) = file_cfg.never_retry {
+        methods.into_iter().collect()
+    } else {
+        DEFAULT_NEVER_RETRY
+            .iter()
+            .map(|method| method.to_string())
+            .collect()
+    };
     let hedged_attempts = pick(
         cli.hedged_attempts,
         file_cfg.hedged_attempts,
@@ -362,15 +1046,123 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
         file_cfg.preopen_streams,
         DEFAULT_PREOPEN_STREAMS,
     );
+    let ws_upstream = cli.ws_upstream.clone().or(file_cfg.ws_upstream);
+    let mut cache_ttls_ms: HashMap<String, u64> = DEFAULT_CACHE_TTLS_MS
+        .iter()
+        .map(|&(method, ttl)| (method.to_string(), ttl))
+        .collect();
+    cache_ttls_ms.extend(file_cfg.cache_ttl_ms.unwrap_or_default());
+    cache_ttls_ms.extend(cli.cache_ttl.iter().cloned());
+    let cache_ttls = cache_ttls_ms
+        .into_iter()
+        .filter(|&(_, ttl)| ttl > 0)
+        .map(|(method, ttl)| (method, Duration::from_millis(ttl)))
+        .collect();
+    let cache_max_entries = pick(
+        cli.cache_max_entries,
+        file_cfg.cache_max_entries,
+        DEFAULT_CACHE_MAX_ENTRIES,
+    );
+    let rate_limit = cli
+        .rate_limit_rps
+        .or(file_cfg.rate_limit_rps)
+        .filter(|&rps| rps != 0.0)
+        .map(|rps| RateLimit {
+            rps,
+            burst: cli
+                .rate_limit_burst
+                .or(file_cfg.rate_limit_burst)
+                .unwrap_or(rps),
+        });
+    let mut method_weights: HashMap<String, u32> = DEFAULT_METHOD_WEIGHTS
+        .iter()
+        .map(|&(method, weight)| (method.to_string(), weight))
+        .collect();
+    method_weights.extend(file_cfg.method_weights.unwrap_or_default());
+    method_weights.extend(cli.method_weight.iter().cloned());
+    let api_key_header = pick(
+        cli.api_key_header.clone(),
+        file_cfg.api_key_header,
+        DEFAULT_API_KEY_HEADER.to_string(),
+    );
+    let api_keys = file_cfg.api_keys.unwrap_or_default();
+    let require_api_key = cli.require_api_key || file_cfg.require_api_key.unwrap_or(false);
+    let ip_allow = ip_nets(&cli.ip_allow, file_cfg.ip_allow).context("invalid ip_allow")?;
+    let ip_deny = ip_nets(&cli.ip_deny, file_cfg.ip_deny).context("invalid ip_deny")?;
+    let allowed_methods = if !cli.allowed_methods.is_empty() {
+        Some(cli.allowed_methods.iter().cloned().collect())
+    } else {
+        file_cfg
+            .allowed_methods
+            .filter(|methods| !methods.is_empty())
+            .map(|methods| methods.into_iter().collect())
+    };
+    let max_params_bytes = Some(pick(
+        cli.max_params_bytes,
+        file_cfg.max_params_bytes,
+        DEFAULT_MAX_PARAMS_BYTES,
+    ))
+    .filter(|&max| max > 0);
+    let validate_requests = !cli.no_validate_requests && file_cfg.validate_requests.unwrap_or(true);
+    let coalesce = !cli.no_coalesce && file_cfg.coalesce.unwrap_or(true);
+    let never_coalesce = if !cli.never_coalesce.is_empty() {
+        cli.never_coalesce.iter().cloned().collect()
+    } else if let Some(methods) = file_cfg.never_coalesce {
+        methods.into_iter().collect()
+    } else {
+        DEFAULT_NEVER_COALESCE
+            .iter()
+            .map(|method| method.to_string())
+            .collect()
+    };
+    let stream_methods = if !cli.stream_methods.is_empty() {
+        cli.stream_methods.iter().cloned().collect()
+    } else if let Some(methods) = file_cfg.stream_methods {
+        methods.into_iter().collect()
+    } else {
+        DEFAULT_STREAM_METHODS
+            .iter()
+            .map(|method| method.to_string())
+            .collect()
+    };
+    let max_stream_bytes = pick(
+        cli.max_stream_bytes,
+        file_cfg.max_stream_bytes,
+        DEFAULT_MAX_STREAM_BYTES,
+    );
+    let compression = !cli.no_compression && file_cfg.compression.unwrap_or(true);
+    let compression_min_bytes = pick(
+        cli.compression_min_bytes,
+        file_cfg.compression_min_bytes,
+        DEFAULT_COMPRESSION_MIN_BYTES,
+    );
 
     Ok(Config {
         listen,
-        upstream,
+        admin_listen,
+        tls_cert,
+        tls_key,
+        tls_reload_interval,
+        upstreams,
+        upstream_policy,
+        health_check_interval,
+        eject_after_failures,
+        eject_duration: Duration::from_millis(eject_ms),
+        breaker,
+        affinity_ttl,
+        affinity_header,
         server_name,
         ca_cert,
         max_request_bytes,
         max_response_bytes,
+        stream_methods,
+        max_stream_bytes,
+        compression,
+        compression_min_bytes,
+        max_batch_size,
+        split_batches,
         max_streams,
+        connections_per_upstream,
         keep_alive,
         max_idle_timeout,
         initial_mtu,
@@ -382,11 +1174,31 @@ fn merge(cli: &CliArgs, file_cfg: Option<(PathBuf, FileConfig)>) -> Result<Confi
         lazy_connect,
         config_path: cfg_path,
         http_trace,
+        slow_request,
         request_timeout,
+        max_retries,
+        retry_backoff: Duration::from_millis(retry_backoff_ms),
+        never_retry,
         hedged_attempts,
         hedge_jitter: Duration::from_millis(hedge_jitter_ms),
         enable_early_data,
         preopen_streams,
+        ws_upstream,
+        cache_ttls,
+        cache_max_entries,
+        coalesce,
+        never_coalesce,
+        routes,
+        rate_limit,
+        method_weights,
+        api_key_header,
+        api_keys,
+        require_api_key,
+        ip_allow,
+        ip_deny,
+        allowed_methods,
+        max_params_bytes,
+        validate_requests,
     })
 }
 
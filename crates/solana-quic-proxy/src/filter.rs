@@ -0,0 +1,125 @@
+// Numan Thabit 2025
+//! Cheap pre-filter run before any upstream work.
+//!
+//! Clients are checked against `ip_deny` and, when set, `ip_allow`; a denied
+//! range wins over an allowed one. Each request must then be JSON-RPC 2.0
+//! with array or object params (unless `validate_requests` is off), call a
+//! method in `allowed_methods` when that is set, and keep its params within
+//! `max_params_bytes`. Rejections are counted by reason.
+
+use std::{collections::HashSet, net::IpAddr, sync::Arc};
+
+use ipnet::IpNet;
+
+use crate::config::Config;
+use crate::metrics::ProxyMetrics;
+use crate::rpc::RpcRequest;
+
+pub struct RequestFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    methods: Option<HashSet<String>>,
+    max_params: Option<usize>,
+    validate: bool,
+    metrics: Arc<ProxyMetrics>,
+}
+
+/// Why a request was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// Not a JSON-RPC 2.0 request.
+    Invalid,
+    MethodNotAllowed,
+    ParamsTooLarge,
+}
+
+impl Rejection {
+    /// JSON-RPC error code.
+    pub fn code(self) -> i64 {
+        match self {
+            Rejection::Invalid => -32600,
+            Rejection::MethodNotAllowed => -32601,
+            Rejection::ParamsTooLarge => -32602,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            Rejection::Invalid => "invalid request",
+            Rejection::MethodNotAllowed => "method not allowed",
+            Rejection::ParamsTooLarge => "params exceed configured limit",
+        }
+    }
+
+    /// Metrics label.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Rejection::Invalid => "invalid",
+            Rejection::MethodNotAllowed => "method_not_allowed",
+            Rejection::ParamsTooLarge => "params_too_large",
+        }
+    }
+}
+
+impl RequestFilter {
+    pub fn new(config: &Config, metrics: Arc<ProxyMetrics>) -> Self {
+        Self {
+            allow: config.ip_allow.clone(),
+            deny: config.ip_deny.clone(),
+            methods: config.allowed_methods.clone(),
+            max_params: config.max_params_bytes,
+            validate: config.validate_requests,
+            metrics,
+        }
+    }
+
+    /// Whether the client at `ip` may send requests at all.
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // Dual-stack listeners report IPv4 clients as mapped IPv6 addresses
+        let ip = ip.to_canonical();
+        let allowed = !self.deny.iter().any(|net| net.contains(&ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip)));
+        if !allowed {
+            self.metrics.record_filtered("ip_denied");
+        }
+        allowed
+    }
+
+    /// Check a single request; `None` if its body did not parse.
+    pub fn check(&self, request: Option<&RpcRequest<'_>>) -> Result<(), Rejection> {
+        let result = self.inspect(request);
+        if let Err(rejection) = result {
+            self.metrics.record_filtered(rejection.reason());
+        }
+        result
+    }
+
+    fn inspect(&self, request: Option<&RpcRequest<'_>>) -> Result<(), Rejection> {
+        let Some(request) = request else {
+            // The method of an unparseable body cannot be checked either
+            if self.validate || self.methods.is_some() {
+                return Err(Rejection::Invalid);
+            }
+            return Ok(());
+        };
+        if self.validate {
+            // Params, when present, are by position or by name
+            let params_shaped = request
+                .params
+                .is_none_or(|params| matches!(params.get().as_bytes().first(), Some(b'[' | b'{')));
+            if request.jsonrpc.as_deref() != Some("2.0") || !params_shaped {
+                return Err(Rejection::Invalid);
+            }
+        }
+        if let Some(methods) = &self.methods {
+            if !methods.contains(request.method.as_ref()) {
+                return Err(Rejection::MethodNotAllowed);
+            }
+        }
+        let params_len = request.params.map_or(0, |params| params.get().len());
+        if self.max_params.is_some_and(|max| params_len > max) {
+            return Err(Rejection::ParamsTooLarge);
+        }
+        Ok(())
+    }
+}
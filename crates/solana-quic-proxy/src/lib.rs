@@ -1,4 +1,21 @@
 // Numan Thabit 2023
+pub mod admin;
+pub mod affinity;
+pub mod batch;
+pub mod breaker;
+pub mod cache;
 pub mod client;
+pub mod compress;
+pub mod coalesce;
 pub mod config;
+pub mod filter;
 pub mod metrics;
+pub mod ratelimit;
+pub mod retry;
+pub mod route;
+pub mod rpc;
+pub mod stream;
+pub mod tls;
+pub mod trace;
+pub mod upstream;
+pub mod ws;
@@ -1,11 +1,19 @@
 // Numan Thabit 2022
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
 use anyhow::Context;
 use axum::{
     body::{Body, Bytes},
-    extract::State,
-    http::{header::CONTENT_TYPE, StatusCode},
+    extract::{ConnectInfo, State, WebSocketUpgrade},
+    http::{
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+        HeaderMap, HeaderValue, StatusCode,
+    },
+    middleware,
     response::Response,
     routing::{get, post},
     Router,
@@ -14,9 +22,21 @@ use clap::Parser;
 use serde::ser::{SerializeStruct, Serializer};
 use serde::Serialize;
 use solana_quic_proxy::{
-    client::{ProxyError, QuicRpcClient},
+    admin,
+    batch::Batch,
+    cache::ResponseCache,
+    client::ProxyError,
+    coalesce::Coalescer,
+    compress::{self, Compression},
     config::{CliArgs, Config},
+    filter::{Rejection, RequestFilter},
     metrics::ProxyMetrics,
+    ratelimit::{RateLimiter, Verdict},
+    route::{RequestContext, Routes, REQUEST_ID_HEADER},
+    rpc::RpcRequest,
+    tls::{self, TlsConfig},
+    trace::{Completed, RequestIds, SlowLog},
+    ws,
 };
 use tokio::signal;
 use tower_http::trace::TraceLayer;
@@ -26,9 +46,22 @@ use serde_json::Serializer as JsonSerializer;
 
 #[derive(Clone)]
 struct AppState {
-    client: Arc<QuicRpcClient>,
+    routes: Arc<Routes>,
+    cache: Arc<ResponseCache>,
+    coalescer: Option<Arc<Coalescer>>,
+    filter: Arc<RequestFilter>,
+    limiter: Arc<RateLimiter>,
+    api_key_header: Arc<str>,
+    affinity_header: Arc<str>,
+    request_ids: Arc<RequestIds>,
+    slow_log: Arc<SlowLog>,
     metrics: Arc<ProxyMetrics>,
     max_request_bytes: usize,
+    max_batch_size: Option<usize>,
+    split_batches: bool,
+    stream_methods: Arc<HashSet<String>>,
+    max_stream_bytes: usize,
+    ws_upstream: Option<Arc<str>>,
 }
 
 #[tokio::main]
@@ -38,39 +71,101 @@ async fn main() -> anyhow::Result<()> {
     let cli = CliArgs::parse();
     let config = Arc::new(Config::from_cli(&cli)?);
     let metrics = Arc::new(ProxyMetrics::new()?);
-    let client = Arc::new(QuicRpcClient::new(config.clone(), metrics.clone())?);
+    let routes = Arc::new(Routes::new(config.clone(), metrics.clone())?);
 
     if !config.lazy_connect {
-        if let Err(err) = client.warmup().await {
-            warn!(error = %err, "upstream preconnect failed; continuing with lazy dial");
-        }
+        routes.warmup().await;
+    }
+    if let Some(interval) = config.health_check_interval {
+        routes.spawn_health_checks(interval);
+    }
+
+    let cache = Arc::new(ResponseCache::new(
+        config.cache_ttls.clone(),
+        config.cache_max_entries,
+        metrics.clone(),
+    ));
+    let coalescer = config.coalesce.then(|| {
+        Arc::new(Coalescer::new(
+            config.never_coalesce.clone(),
+            metrics.clone(),
+        ))
+    });
+    if let Some(addr) = config.admin_listen {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .context("failed to bind admin socket")?;
+        let admin = admin::router(routes.clone(), cache.clone());
+        info!(listen = %addr, "admin API listening");
+        tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, admin).await {
+                error!(error = %err, "admin server exited with error");
+            }
+        });
     }
 
     let state = AppState {
-        client,
+        routes,
+        cache,
+        coalescer,
+        filter: Arc::new(RequestFilter::new(&config, metrics.clone())),
+        limiter: Arc::new(RateLimiter::new(&config, metrics.clone())),
+        api_key_header: Arc::from(config.api_key_header.as_str()),
+        affinity_header: Arc::from(config.affinity_header.as_str()),
+        request_ids: Arc::default(),
+        slow_log: Arc::new(SlowLog::new(config.slow_request, metrics.clone())),
         metrics: metrics.clone(),
         max_request_bytes: config.max_request_bytes,
+        max_batch_size: config.max_batch_size,
+        split_batches: config.split_batches,
+        stream_methods: Arc::new(config.stream_methods.clone()),
+        max_stream_bytes: config.max_stream_bytes,
+        ws_upstream: config.ws_upstream.as_deref().map(Arc::from),
     };
 
     let mut app = Router::new()
-        .route("/", post(proxy_handler))
-        .route("/rpc", post(proxy_handler))
+        .route("/", post(proxy_handler).get(ws_handler))
+        .route("/rpc", post(proxy_handler).get(ws_handler))
         .route("/metrics", get(metrics_handler))
         .with_state(state);
+    if config.compression {
+        let compression = Arc::new(Compression::new(
+            config.compression_min_bytes,
+            metrics.clone(),
+        ));
+        app = app.layer(middleware::from_fn_with_state(
+            compression,
+            compress::middleware,
+        ));
+    }
     if config.http_trace {
         app = app.layer(TraceLayer::new_for_http());
     }
 
-    info!(listen = %config.listen, upstream = %config.upstream, lazy_connect = config.lazy_connect, "solana-quic-proxy listening");
+    info!(listen = %config.listen, tls = config.tls_cert.is_some(), upstreams = config.upstreams.len(), lazy_connect = config.lazy_connect, "solana-quic-proxy listening");
 
     let listener = tokio::net::TcpListener::bind(config.listen)
         .await
         .context("failed to bind listen socket")?;
 
-    axum::serve(listener, app)
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let tls_config = Arc::new(TlsConfig::load(cert, key, metrics.clone())?);
+            if let Some(interval) = config.tls_reload_interval {
+                tls_config.clone().spawn_reloader(interval);
+            }
+            tls::serve(listener, app, tls_config, shutdown_signal())
+                .await
+                .context("TLS server exited with error")?;
+        }
+        _ => axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
         .with_graceful_shutdown(shutdown_signal())
         .await
-        .context("axum server exited with error")?;
+        .context("axum server exited with error")?,
+    }
 
     Ok(())
 }
@@ -82,11 +177,50 @@ async fn shutdown_signal() {
     info!("shutdown signal received");
 }
 
-async fn proxy_handler(State(state): State<AppState>, body: Bytes) -> Response {
+async fn proxy_handler(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request_id = state.request_ids.assign(headers.get(REQUEST_ID_HEADER));
+    let mut response = handle(&state, peer, &headers, body, &request_id).await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+async fn handle(
+    state: &AppState,
+    peer: SocketAddr,
+    headers: &HeaderMap,
+    body: Bytes,
+    request_id: &str,
+) -> Response {
+    if !state.filter.allows(peer.ip()) {
+        return error_response(StatusCode::FORBIDDEN, "client address not allowed");
+    }
     if body.is_empty() {
         return error_response(StatusCode::BAD_REQUEST, "empty request body");
     }
 
+    let api_key = headers
+        .get(state.api_key_header.as_ref())
+        .and_then(|value| value.to_str().ok());
+    // Sticky routing hint; clients without one stick by API key
+    let session = headers
+        .get(state.affinity_header.as_ref())
+        .and_then(|value| value.to_str().ok())
+        .or(api_key);
+    let context = RequestContext {
+        session,
+        request_id: Some(request_id),
+    };
+    if let Some(batch) = Batch::parse(&body) {
+        return batch_handler(state, api_key, context, peer.ip(), &body, batch).await;
+    }
+
     if body.len() > state.max_request_bytes {
         return error_response(
             StatusCode::PAYLOAD_TOO_LARGE,
@@ -94,42 +228,290 @@ async fn proxy_handler(State(state): State<AppState>, body: Bytes) -> Response {
         );
     }
 
+    let request = RpcRequest::parse(&body);
+    if let Err(rejection) = state.filter.check(request.as_ref()) {
+        let status = match rejection {
+            Rejection::MethodNotAllowed => StatusCode::FORBIDDEN,
+            Rejection::Invalid | Rejection::ParamsTooLarge => StatusCode::BAD_REQUEST,
+        };
+        return json_rpc_error_response(status, rejection.code(), rejection.message());
+    }
+    let method = request.as_ref().map(|r| r.method.as_ref());
+    match state.limiter.check(api_key, peer.ip(), method) {
+        Verdict::Allow => {}
+        Verdict::Limited => {
+            return json_rpc_error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                -32005,
+                "rate limit exceeded",
+            )
+        }
+        Verdict::Unauthorized => {
+            return json_rpc_error_response(StatusCode::UNAUTHORIZED, -32001, "unknown API key")
+        }
+    }
+
+    if let Some(method) = method.filter(|method| state.stream_methods.contains(*method)) {
+        return stream(state, method, &body, context).await;
+    }
+    match forward(state, request.as_ref(), &body, context).await {
+        Ok(payload) => json_response(payload),
+        Err(err) => json_rpc_error_response(status_for_error(&err), -32000, &err.to_string()),
+    }
+}
+
+/// Answer each request of `batch` on its own terms: items over a limit get an
+/// error, the rest go upstream as one batch or one by one.
+async fn batch_handler(
+    state: &AppState,
+    api_key: Option<&str>,
+    context: RequestContext<'_>,
+    ip: IpAddr,
+    body: &Bytes,
+    mut batch: Batch<'_>,
+) -> Response {
+    state.metrics.record_batch();
+    if batch.is_empty() {
+        return json_rpc_error_response(StatusCode::BAD_REQUEST, -32600, "empty batch");
+    }
+
+    let mut admitted = Vec::with_capacity(batch.len());
+    for (i, item) in batch.items_mut().iter_mut().enumerate() {
+        let method = item.request.as_ref().map(|r| r.method.as_ref());
+        let rejection = if method.is_none() {
+            Some(("invalid", -32600, "invalid request".to_string()))
+        } else if let Some(max) = state.max_batch_size.filter(|&max| i >= max) {
+            Some((
+                "batch_size",
+                -32600,
+                format!("batch exceeds {max} requests"),
+            ))
+        } else if item.raw.get().len() > state.max_request_bytes {
+            let message = "request exceeds configured limit".to_string();
+            Some(("request_size", -32600, message))
+        } else if let Err(rejection) = state.filter.check(item.request.as_ref()) {
+            let message = rejection.message().to_string();
+            Some((rejection.reason(), rejection.code(), message))
+        } else {
+            match state.limiter.check(api_key, ip, method) {
+                Verdict::Allow => None,
+                Verdict::Limited => Some(("rate_limited", -32005, "rate limit exceeded".into())),
+                Verdict::Unauthorized => Some(("unauthorized", -32001, "unknown API key".into())),
+            }
+        };
+        match rejection {
+            Some((reason, code, message)) => {
+                state.metrics.record_batch_item(reason);
+                item.reject(code, &message);
+            }
+            None => {
+                state.metrics.record_batch_item("forwarded");
+                admitted.push(i);
+            }
+        }
+    }
+
+    if state.split_batches {
+        let items = batch.items();
+        let results = futures::future::join_all(admitted.iter().map(|&i| {
+            let item = &items[i];
+            forward(
+                state,
+                item.request.as_ref(),
+                item.raw.get().as_bytes(),
+                context,
+            )
+        }))
+        .await;
+        for (&i, result) in admitted.iter().zip(results) {
+            let item = &mut batch.items_mut()[i];
+            match result {
+                Ok(payload) => item.respond(payload),
+                Err(err) => item.reject(-32000, &err.to_string()),
+            }
+        }
+    } else if !admitted.is_empty() {
+        let upstream_body = if admitted.len() == batch.len() {
+            body.clone()
+        } else {
+            batch.encode(&admitted)
+        };
+        match forward(state, None, &upstream_body, context).await {
+            Ok(payload) => batch.fill(&admitted, &payload),
+            Err(err) => {
+                for &i in &admitted {
+                    batch.items_mut()[i].reject(-32000, &err.to_string());
+                }
+            }
+        }
+    }
+
+    match batch.response() {
+        Some(payload) => json_response(payload),
+        None => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap_or_else(|err| {
+                error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
+            }),
+    }
+}
+
+/// Answer `body` (the single `request` if it parsed) from the cache or upstream.
+async fn forward(
+    state: &AppState,
+    request: Option<&RpcRequest<'_>>,
+    body: &[u8],
+    context: RequestContext<'_>,
+) -> Result<Bytes, Arc<ProxyError>> {
+    let cache_key = request.and_then(|r| state.cache.key(r));
+    if let (Some(key), Some(request)) = (&cache_key, request) {
+        if let Some(hit) = state.cache.get(key, request) {
+            return Ok(hit);
+        }
+    }
+
     state.metrics.in_flight_inc();
     let start = tokio::time::Instant::now();
-    let result = state.client.request(body.as_ref()).await;
+    let method = request.map(|r| r.method.as_ref());
+    let label = method.unwrap_or(match body.trim_ascii_start().first() {
+        Some(b'[') => "batch",
+        _ => "unknown",
+    });
+    let fetch = || state.routes.request(method, body, context);
+    let result = match &state.coalescer {
+        Some(coalescer) => {
+            let key = request.and_then(|r| coalescer.key(r));
+            let id = request.and_then(|r| r.id);
+            coalescer.run(key, id, fetch).await
+        }
+        None => fetch().await.map_err(Arc::new),
+    };
     state.metrics.in_flight_dec();
+    let total = start.elapsed();
+    state.slow_log.observe(&Completed {
+        request_id: context.request_id.unwrap_or_default(),
+        method: label,
+        upstream: result.as_ref().ok().map(|response| &*response.upstream),
+        total,
+        upstream_latency: result.as_ref().ok().map(|response| response.latency),
+        request_bytes: body.len(),
+        response_bytes: result.as_ref().map_or(0, |response| response.payload.len()),
+    });
 
     match result {
         Ok(response) => {
+            if let Some(key) = &cache_key {
+                state.cache.insert(key, &response.payload);
+            }
             state.metrics.record_success(
-                start.elapsed(),
+                label,
+                total,
                 response.latency,
                 body.len(),
                 response.payload.len(),
             );
-            Response::builder()
-                .status(StatusCode::OK)
-                .header(CONTENT_TYPE, "application/json")
-                .body(Body::from(response.payload))
-                .unwrap_or_else(|err| {
-                    error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string())
-                })
+            Ok(response.payload)
         }
         Err(err) => {
-            state.metrics.record_failure();
+            state.metrics.record_failure(label);
             error!(error = %err, "upstream request failed");
-            let status = status_for_error(&err);
-            let message = err.to_string();
-            let payload = json_rpc_error_bytes(-32000, &message);
-            Response::builder()
-                .status(status)
-                .header(CONTENT_TYPE, "application/json")
-                .body(Body::from(payload))
-                .unwrap_or_else(|builder_err| {
-                    error_response(StatusCode::INTERNAL_SERVER_ERROR, &builder_err.to_string())
-                })
+            Err(err)
+        }
+    }
+}
+
+/// Relay the upstream's answer to `body` as it arrives, without caching,
+/// coalescing or buffering it.
+async fn stream(
+    state: &AppState,
+    method: &str,
+    body: &[u8],
+    context: RequestContext<'_>,
+) -> Response {
+    state.metrics.in_flight_inc();
+    let start = tokio::time::Instant::now();
+    let result = state
+        .routes
+        .request_stream(Some(method), body, context)
+        .await;
+    state.metrics.in_flight_dec();
+    // Measured to the first byte: the body streams out after this returns
+    let total = start.elapsed();
+    state.slow_log.observe(&Completed {
+        request_id: context.request_id.unwrap_or_default(),
+        method,
+        upstream: result.as_ref().ok().map(|response| &*response.upstream),
+        total,
+        upstream_latency: result.as_ref().ok().map(|response| response.latency),
+        request_bytes: body.len(),
+        response_bytes: result
+            .as_ref()
+            .map_or(0, |response| response.len.unwrap_or(0)),
+    });
+
+    let response = match result {
+        Ok(response) => response,
+        Err(err) => {
+            state.metrics.record_failure(method);
+            error!(error = %err, "upstream request failed");
+            return json_rpc_error_response(status_for_error(&err), -32000, &err.to_string());
         }
+    };
+    state.metrics.record_success(
+        method,
+        total,
+        response.latency,
+        body.len(),
+        response.len.unwrap_or(0),
+    );
+    let mut builder = Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json");
+    if let Some(len) = response.len {
+        builder = builder.header(CONTENT_LENGTH, len);
+    }
+    builder
+        .body(response.into_body(state.max_stream_bytes, state.metrics.clone()))
+        .unwrap_or_else(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()))
+}
+
+fn json_response(payload: Bytes) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(payload))
+        .unwrap_or_else(|err| error_response(StatusCode::INTERNAL_SERVER_ERROR, &err.to_string()))
+}
+
+async fn ws_handler(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let api_key = headers
+        .get(state.api_key_header.as_ref())
+        .and_then(|value| value.to_str().ok());
+    if let Err((status, message)) = ws::admit(&state.filter, &state.limiter, peer.ip(), api_key) {
+        return error_response(status, message);
     }
+    let Some(upstream) = state.ws_upstream.clone() else {
+        return error_response(
+            StatusCode::NOT_IMPLEMENTED,
+            "no websocket upstream configured",
+        );
+    };
+    let metrics = state.metrics.clone();
+    let gate = ws::Gate {
+        filter: state.filter.clone(),
+        limiter: state.limiter.clone(),
+        ip: peer.ip(),
+        api_key: api_key.map(str::to_owned),
+    };
+    upgrade
+        .max_message_size(state.max_request_bytes)
+        .on_upgrade(move |socket| ws::pass_through(socket, upstream, metrics, gate))
 }
 
 async fn metrics_handler(State(state): State<AppState>) -> Response {
@@ -170,9 +552,21 @@ fn status_for_error(err: &ProxyError) -> StatusCode {
             StatusCode::BAD_GATEWAY
         }
         ProxyError::Protocol(_) => StatusCode::BAD_GATEWAY,
+        ProxyError::Http(_) => StatusCode::BAD_GATEWAY,
+        ProxyError::CircuitOpen => StatusCode::SERVICE_UNAVAILABLE,
     }
 }
 
+fn json_rpc_error_response(status: StatusCode, code: i64, message: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(json_rpc_error_bytes(code, message)))
+        .unwrap_or_else(|builder_err| {
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, &builder_err.to_string())
+        })
+}
+
 fn json_rpc_error_bytes(code: i64, message: &str) -> Bytes {
     let mut buf = Vec::with_capacity(128 + message.len());
     let mut serializer = JsonSerializer::new(&mut buf);
@@ -1,12 +1,16 @@
 // Numii
-use std::time::Duration;
+use std::{collections::HashSet, sync::Mutex, time::Duration};
 
 use anyhow::{anyhow, Context, Result};
 use prometheus::{
-    exponential_buckets, opts, Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry,
-    TextEncoder,
+    exponential_buckets, opts, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
 };
 
+use crate::breaker::CircuitState;
+
+const MAX_METHOD_LABELS: usize = 128;
+
 pub struct ProxyMetrics {
     registry: Registry,
     requests: IntCounter,
@@ -17,6 +21,44 @@ pub struct ProxyMetrics {
     bytes_in: Histogram,
     bytes_out: Histogram,
     connection_resets: IntCounter,
+    reconnects: IntCounter,
+    ws_connections: IntGauge,
+    ws_messages: IntCounterVec,
+    ws_upstream_failures: IntCounter,
+    upstream_requests: IntCounterVec,
+    upstream_failures: IntCounterVec,
+    upstream_ejections: IntCounterVec,
+    upstream_healthy: IntGaugeVec,
+    cache_lookups: IntCounterVec,
+    cache_entries: IntGauge,
+    coalesced: IntCounter,
+    route_requests: IntCounterVec,
+    route_failures: IntCounterVec,
+    route_latency: HistogramVec,
+    client_requests: IntCounterVec,
+    client_limited: IntCounterVec,
+    retries: IntCounterVec,
+    hedges: IntCounter,
+    batches: IntCounter,
+    batch_items: IntCounterVec,
+    tls_reloads: IntCounterVec,
+    tls_handshake_failures: IntCounter,
+    circuit_state: IntGaugeVec,
+    circuit_opens: IntCounterVec,
+    circuit_rejections: IntCounter,
+    method_requests: IntCounterVec,
+    method_failures: IntCounterVec,
+    method_latency: HistogramVec,
+    method_bytes_in: HistogramVec,
+    method_bytes_out: HistogramVec,
+    slow_requests: IntCounterVec,
+    streams: IntCounterVec,
+    filtered: IntCounterVec,
+    streamed_bytes: IntCounter,
+    compressed: IntCounterVec,
+    compression_saved: IntCounterVec,
+    // Methods with their own label; the rest are counted as `other`
+    methods: Mutex<HashSet<String>>,
 }
 
 impl ProxyMetrics {
@@ -34,6 +76,11 @@ impl ProxyMetrics {
             "Total upstream QUIC connection resets"
         ))
         .context("failed to build connection resets counter")?;
+        let reconnects = IntCounter::with_opts(opts!(
+            "upstream_reconnects_total",
+            "Upstream QUIC connections re-established in the background after closing"
+        ))
+        .context("failed to build reconnects counter")?;
         let inflight = IntGauge::with_opts(opts!(
             "inflight_requests",
             "Number of in-flight proxy requests"
@@ -51,7 +98,7 @@ impl ProxyMetrics {
                 "upstream_latency_seconds",
                 "Upstream QUIC round-trip latency",
             )
-            .buckets(latency_buckets),
+            .buckets(latency_buckets.clone()),
         )
         .context("failed to build upstream latency histogram")?;
         let bytes_in = Histogram::with_opts(HistogramOpts::new(
@@ -64,6 +111,246 @@ impl ProxyMetrics {
             "Size of upstream JSON-RPC responses",
         ))
         .context("failed to build response bytes histogram")?;
+        let method_requests = IntCounterVec::new(
+            opts!(
+                "method_requests_total",
+                "Requests forwarded, by JSON-RPC method"
+            ),
+            &["method"],
+        )
+        .context("failed to build method requests counter")?;
+        let method_failures = IntCounterVec::new(
+            opts!(
+                "method_requests_failed_total",
+                "Failed requests, by JSON-RPC method"
+            ),
+            &["method"],
+        )
+        .context("failed to build method failures counter")?;
+        let method_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "method_latency_seconds",
+                "Total request latency, by JSON-RPC method",
+            )
+            .buckets(latency_buckets.clone()),
+            &["method"],
+        )
+        .context("failed to build method latency histogram")?;
+        let slow_requests = IntCounterVec::new(
+            opts!(
+                "slow_requests_total",
+                "Requests slower than the slow-request threshold, by method"
+            ),
+            &["method"],
+        )
+        .context("failed to build slow requests counter")?;
+        let streams = IntCounterVec::new(
+            opts!(
+                "streamed_responses_total",
+                "Streamed responses, by how they ended"
+            ),
+            &["result"],
+        )
+        .context("failed to build streamed responses counter")?;
+        let filtered = IntCounterVec::new(
+            opts!(
+                "filtered_requests_total",
+                "Requests turned away before reaching an upstream, by reason"
+            ),
+            &["reason"],
+        )
+        .context("failed to build filtered requests counter")?;
+        let streamed_bytes = IntCounter::with_opts(opts!(
+            "streamed_bytes_total",
+            "Response bytes relayed by streaming"
+        ))
+        .context("failed to build streamed bytes counter")?;
+        let compressed = IntCounterVec::new(
+            opts!(
+                "compressed_responses_total",
+                "Responses compressed for the client, by encoding"
+            ),
+            &["encoding"],
+        )
+        .context("failed to build compressed responses counter")?;
+        let compression_saved = IntCounterVec::new(
+            opts!(
+                "compression_saved_bytes_total",
+                "Response bytes saved by compression, by encoding"
+            ),
+            &["encoding"],
+        )
+        .context("failed to build compression savings counter")?;
+        let size_buckets =
+            exponential_buckets(128.0, 4.0, 10).context("failed to build size buckets")?;
+        let method_bytes_in = HistogramVec::new(
+            HistogramOpts::new(
+                "method_request_bytes",
+                "Size of incoming JSON-RPC payloads, by method",
+            )
+            .buckets(size_buckets.clone()),
+            &["method"],
+        )
+        .context("failed to build method request bytes histogram")?;
+        let method_bytes_out = HistogramVec::new(
+            HistogramOpts::new(
+                "method_response_bytes",
+                "Size of upstream JSON-RPC responses, by method",
+            )
+            .buckets(size_buckets),
+            &["method"],
+        )
+        .context("failed to build method response bytes histogram")?;
+
+        let ws_connections = IntGauge::with_opts(opts!(
+            "ws_connections",
+            "Number of open WebSocket pass-through sessions"
+        ))
+        .context("failed to build ws connections gauge")?;
+        let ws_messages = IntCounterVec::new(
+            opts!("ws_messages_total", "WebSocket messages relayed, by origin"),
+            &["from"],
+        )
+        .context("failed to build ws messages counter")?;
+        let ws_upstream_failures = IntCounter::with_opts(opts!(
+            "ws_upstream_failures_total",
+            "Total failed upstream WebSocket connects"
+        ))
+        .context("failed to build ws upstream failures counter")?;
+        let upstream_requests = IntCounterVec::new(
+            opts!("upstream_requests_total", "Requests sent, by upstream"),
+            &["upstream"],
+        )
+        .context("failed to build upstream requests counter")?;
+        let upstream_failures = IntCounterVec::new(
+            opts!(
+                "upstream_failures_total",
+                "Failed requests and health probes, by upstream"
+            ),
+            &["upstream"],
+        )
+        .context("failed to build upstream failures counter")?;
+        let upstream_ejections = IntCounterVec::new(
+            opts!(
+                "upstream_ejections_total",
+                "Upstream ejections, by upstream"
+            ),
+            &["upstream"],
+        )
+        .context("failed to build upstream ejections counter")?;
+        let upstream_healthy = IntGaugeVec::new(
+            opts!(
+                "upstream_healthy",
+                "Whether an upstream is in rotation (1) or ejected (0)"
+            ),
+            &["upstream"],
+        )
+        .context("failed to build upstream healthy gauge")?;
+        let cache_lookups = IntCounterVec::new(
+            opts!(
+                "cache_lookups_total",
+                "Response cache lookups, by method and result (hit or miss)"
+            ),
+            &["method", "result"],
+        )
+        .context("failed to build cache lookups counter")?;
+        let cache_entries = IntGauge::with_opts(opts!("cache_entries", "Cached responses"))
+            .context("failed to build cache entries gauge")?;
+        let coalesced = IntCounter::with_opts(opts!(
+            "coalesced_requests_total",
+            "Requests answered by an identical request already in flight"
+        ))
+        .context("failed to build coalesced requests counter")?;
+        let route_requests = IntCounterVec::new(
+            opts!("route_requests_total", "Requests sent, by route"),
+            &["route"],
+        )
+        .context("failed to build route requests counter")?;
+        let route_failures = IntCounterVec::new(
+            opts!("route_failures_total", "Failed requests, by route"),
+            &["route"],
+        )
+        .context("failed to build route failures counter")?;
+        let route_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "route_latency_seconds",
+                "Upstream round-trip latency, by route",
+            )
+            .buckets(latency_buckets),
+            &["route"],
+        )
+        .context("failed to build route latency histogram")?;
+        let client_requests = IntCounterVec::new(
+            opts!(
+                "client_requests_total",
+                "Requests received, by API key name (anonymous without one)"
+            ),
+            &["client"],
+        )
+        .context("failed to build client requests counter")?;
+        let client_limited = IntCounterVec::new(
+            opts!(
+                "client_rate_limited_total",
+                "Requests rejected by the rate limit, by API key name"
+            ),
+            &["client"],
+        )
+        .context("failed to build client rate limited counter")?;
+        let retries = IntCounterVec::new(
+            opts!(
+                "retries_total",
+                "Requests retried after a transient failure, by route"
+            ),
+            &["route"],
+        )
+        .context("failed to build retries counter")?;
+        let hedges = IntCounter::with_opts(opts!(
+            "hedged_requests_total",
+            "Hedged second attempts launched"
+        ))
+        .context("failed to build hedged requests counter")?;
+        let batches =
+            IntCounter::with_opts(opts!("batch_requests_total", "Batch requests received"))
+                .context("failed to build batch requests counter")?;
+        let batch_items = IntCounterVec::new(
+            opts!(
+                "batch_items_total",
+                "Requests within batches, by outcome (forwarded or why rejected)"
+            ),
+            &["result"],
+        )
+        .context("failed to build batch items counter")?;
+        let tls_reloads = IntCounterVec::new(
+            opts!(
+                "tls_reloads_total",
+                "TLS certificate reloads, by result (ok or error)"
+            ),
+            &["result"],
+        )
+        .context("failed to build tls reloads counter")?;
+        let tls_handshake_failures = IntCounter::with_opts(opts!(
+            "tls_handshake_failures_total",
+            "Client connections that failed the TLS handshake"
+        ))
+        .context("failed to build tls handshake failures counter")?;
+        let circuit_state = IntGaugeVec::new(
+            opts!(
+                "circuit_state",
+                "Circuit breaker state per upstream (0 closed, 1 open, 2 half-open)"
+            ),
+            &["upstream"],
+        )
+        .context("failed to build circuit state gauge")?;
+        let circuit_opens = IntCounterVec::new(
+            opts!("circuit_opens_total", "Circuit breaker trips, by upstream"),
+            &["upstream"],
+        )
+        .context("failed to build circuit opens counter")?;
+        let circuit_rejections = IntCounter::with_opts(opts!(
+            "circuit_rejections_total",
+            "Requests failed fast because every circuit was open"
+        ))
+        .context("failed to build circuit rejections counter")?;
 
         registry
             .register(Box::new(requests.clone()))
@@ -74,6 +361,9 @@ impl ProxyMetrics {
         registry
             .register(Box::new(connection_resets.clone()))
             .context("register connection resets")?;
+        registry
+            .register(Box::new(reconnects.clone()))
+            .context("register reconnects")?;
         registry
             .register(Box::new(inflight.clone()))
             .context("register inflight")?;
@@ -89,6 +379,111 @@ impl ProxyMetrics {
         registry
             .register(Box::new(bytes_out.clone()))
             .context("register response bytes")?;
+        registry
+            .register(Box::new(streams.clone()))
+            .context("register streamed responses")?;
+        registry
+            .register(Box::new(streamed_bytes.clone()))
+            .context("register streamed bytes")?;
+        registry
+            .register(Box::new(compressed.clone()))
+            .context("register compressed responses")?;
+        registry
+            .register(Box::new(compression_saved.clone()))
+            .context("register compression savings")?;
+        registry
+            .register(Box::new(filtered.clone()))
+            .context("register filtered requests")?;
+        registry
+            .register(Box::new(method_requests.clone()))
+            .context("register method requests")?;
+        registry
+            .register(Box::new(method_failures.clone()))
+            .context("register method failures")?;
+        registry
+            .register(Box::new(slow_requests.clone()))
+            .context("register slow requests")?;
+        registry
+            .register(Box::new(method_latency.clone()))
+            .context("register method latency")?;
+        registry
+            .register(Box::new(method_bytes_in.clone()))
+            .context("register method request bytes")?;
+        registry
+            .register(Box::new(method_bytes_out.clone()))
+            .context("register method response bytes")?;
+        registry
+            .register(Box::new(ws_connections.clone()))
+            .context("register ws connections")?;
+        registry
+            .register(Box::new(ws_messages.clone()))
+            .context("register ws messages")?;
+        registry
+            .register(Box::new(ws_upstream_failures.clone()))
+            .context("register ws upstream failures")?;
+        registry
+            .register(Box::new(upstream_requests.clone()))
+            .context("register upstream requests")?;
+        registry
+            .register(Box::new(upstream_failures.clone()))
+            .context("register upstream failures")?;
+        registry
+            .register(Box::new(upstream_ejections.clone()))
+            .context("register upstream ejections")?;
+        registry
+            .register(Box::new(upstream_healthy.clone()))
+            .context("register upstream healthy")?;
+        registry
+            .register(Box::new(cache_lookups.clone()))
+            .context("register cache lookups")?;
+        registry
+            .register(Box::new(cache_entries.clone()))
+            .context("register cache entries")?;
+        registry
+            .register(Box::new(coalesced.clone()))
+            .context("register coalesced requests")?;
+        registry
+            .register(Box::new(route_requests.clone()))
+            .context("register route requests")?;
+        registry
+            .register(Box::new(route_failures.clone()))
+            .context("register route failures")?;
+        registry
+            .register(Box::new(route_latency.clone()))
+            .context("register route latency")?;
+        registry
+            .register(Box::new(client_requests.clone()))
+            .context("register client requests")?;
+        registry
+            .register(Box::new(client_limited.clone()))
+            .context("register client rate limited")?;
+        registry
+            .register(Box::new(retries.clone()))
+            .context("register retries")?;
+        registry
+            .register(Box::new(hedges.clone()))
+            .context("register hedged requests")?;
+        registry
+            .register(Box::new(batches.clone()))
+            .context("register batch requests")?;
+        registry
+            .register(Box::new(batch_items.clone()))
+            .context("register batch items")?;
+        registry
+            .register(Box::new(tls_reloads.clone()))
+            .context("register tls reloads")?;
+        registry
+            .register(Box::new(tls_handshake_failures.clone()))
+            .context("register tls handshake failures")?;
+        registry
+            .register(Box::new(circuit_state.clone()))
+            .context("register circuit state")?;
+        registry
+            .register(Box::new(circuit_opens.clone()))
+            .context("register circuit opens")?;
+        registry
+            .register(Box::new(circuit_rejections.clone()))
+            .context("register circuit rejections")?;
 
         Ok(Self {
             registry,
@@ -100,6 +495,43 @@ impl ProxyMetrics {
             bytes_in,
             bytes_out,
             connection_resets,
+            reconnects,
+            ws_connections,
+            ws_messages,
+            ws_upstream_failures,
+            upstream_requests,
+            upstream_failures,
+            upstream_ejections,
+            upstream_healthy,
+            cache_lookups,
+            cache_entries,
+            coalesced,
+            route_requests,
+            route_failures,
+            route_latency,
+            client_requests,
+            client_limited,
+            retries,
+            hedges,
+            batches,
+            batch_items,
+            tls_reloads,
+            tls_handshake_failures,
+            circuit_state,
+            circuit_opens,
+            circuit_rejections,
+            method_requests,
+            method_failures,
+            method_latency,
+            method_bytes_in,
+            method_bytes_out,
+            slow_requests,
+            streams,
+            streamed_bytes,
+            compressed,
+            compression_saved,
+            filtered,
+            methods: Mutex::default(),
         })
     }
 
@@ -113,6 +545,7 @@ impl ProxyMetrics {
 
     pub fn record_success(
         &self,
+        method: &str,
         total: Duration,
         upstream: Duration,
         bytes_in: usize,
@@ -123,16 +556,189 @@ impl ProxyMetrics {
         self.upstream_latency.observe(upstream.as_secs_f64());
         self.bytes_in.observe(bytes_in as f64);
         self.bytes_out.observe(bytes_out as f64);
+
+        let labels = [self.method_label(method)];
+        self.method_requests.with_label_values(&labels).inc();
+        self.method_latency
+            .with_label_values(&labels)
+            .observe(total.as_secs_f64());
+        self.method_bytes_in
+            .with_label_values(&labels)
+            .observe(bytes_in as f64);
+        self.method_bytes_out
+            .with_label_values(&labels)
+            .observe(bytes_out as f64);
     }
 
-    pub fn record_failure(&self) {
+    pub fn record_failure(&self, method: &str) {
         self.failures.inc();
+        self.method_failures
+            .with_label_values(&[self.method_label(method)])
+            .inc();
+    }
+
+    pub fn record_slow_request(&self, method: &str) {
+        self.slow_requests
+            .with_label_values(&[self.method_label(method)])
+            .inc();
+    }
+
+    pub fn record_stream(&self, result: &str, bytes: usize) {
+        self.streams.with_label_values(&[result]).inc();
+        self.streamed_bytes.inc_by(bytes as u64);
+    }
+
+    /// A response of `bytes_in` bytes sent as `bytes_out` with `encoding`.
+    pub fn record_compression(&self, encoding: &str, bytes_in: usize, bytes_out: usize) {
+        self.compressed.with_label_values(&[encoding]).inc();
+        self.compression_saved
+            .with_label_values(&[encoding])
+            .inc_by(bytes_in.saturating_sub(bytes_out) as u64);
+    }
+
+    pub fn record_filtered(&self, reason: &str) {
+        self.filtered.with_label_values(&[reason]).inc();
     }
 
     pub fn record_connection_reset(&self) {
         self.connection_resets.inc();
     }
 
+    pub fn record_reconnect(&self) {
+        self.reconnects.inc();
+    }
+
+    pub fn ws_connected(&self) {
+        self.ws_connections.inc();
+    }
+
+    pub fn ws_disconnected(&self) {
+        self.ws_connections.dec();
+    }
+
+    pub fn record_ws_message(&self, from: &str) {
+        self.ws_messages.with_label_values(&[from]).inc();
+    }
+
+    pub fn record_ws_upstream_failure(&self) {
+        self.ws_upstream_failures.inc();
+    }
+
+    pub fn record_upstream_request(&self, upstream: &str) {
+        self.upstream_requests.with_label_values(&[upstream]).inc();
+    }
+
+    pub fn record_upstream_failure(&self, upstream: &str) {
+        self.upstream_failures.with_label_values(&[upstream]).inc();
+    }
+
+    pub fn record_upstream_ejection(&self, upstream: &str) {
+        self.upstream_ejections.with_label_values(&[upstream]).inc();
+    }
+
+    /// Drop the gauges of an upstream that left its pool.
+    pub fn forget_upstream(&self, upstream: &str) {
+        let _ = self.upstream_healthy.remove_label_values(&[upstream]);
+        let _ = self.circuit_state.remove_label_values(&[upstream]);
+    }
+
+    pub fn set_upstream_healthy(&self, upstream: &str, healthy: bool) {
+        self.upstream_healthy
+            .with_label_values(&[upstream])
+            .set(i64::from(healthy));
+    }
+
+    pub fn record_cache_lookup(&self, method: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.cache_lookups
+            .with_label_values(&[method, result])
+            .inc();
+    }
+
+    pub fn set_cache_entries(&self, entries: usize) {
+        self.cache_entries.set(entries as i64);
+    }
+
+    pub fn record_coalesced(&self) {
+        self.coalesced.inc();
+    }
+
+    pub fn record_route_success(&self, route: &str, latency: Duration) {
+        self.route_requests.with_label_values(&[route]).inc();
+        self.route_latency
+            .with_label_values(&[route])
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn record_retry(&self, route: &str) {
+        self.retries.with_label_values(&[route]).inc();
+    }
+
+    pub fn record_hedge(&self) {
+        self.hedges.inc();
+    }
+
+    pub fn record_batch(&self) {
+        self.batches.inc();
+    }
+
+    pub fn record_batch_item(&self, result: &str) {
+        self.batch_items.with_label_values(&[result]).inc();
+    }
+
+    pub fn record_tls_reload(&self, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        self.tls_reloads.with_label_values(&[result]).inc();
+    }
+
+    pub fn record_tls_handshake_failure(&self) {
+        self.tls_handshake_failures.inc();
+    }
+
+    pub fn set_circuit_state(&self, upstream: &str, state: CircuitState) {
+        let value = match state {
+            CircuitState::Closed => 0,
+            CircuitState::Open => 1,
+            CircuitState::HalfOpen => 2,
+        };
+        self.circuit_state.with_label_values(&[upstream]).set(value);
+    }
+
+    pub fn record_circuit_open(&self, upstream: &str) {
+        self.circuit_opens.with_label_values(&[upstream]).inc();
+    }
+
+    pub fn record_circuit_rejection(&self) {
+        self.circuit_rejections.inc();
+    }
+
+    pub fn record_client_request(&self, client: &str) {
+        self.client_requests.with_label_values(&[client]).inc();
+    }
+
+    pub fn record_client_limited(&self, client: &str) {
+        self.client_limited.with_label_values(&[client]).inc();
+    }
+
+    pub fn record_route_failure(&self, route: &str) {
+        self.route_requests.with_label_values(&[route]).inc();
+        self.route_failures.with_label_values(&[route]).inc();
+    }
+
+    /// `method` as a label value, or `other` once `MAX_METHOD_LABELS` distinct
+    /// methods have been seen, so clients cannot grow the series without bound.
+    fn method_label<'a>(&self, method: &'a str) -> &'a str {
+        let mut methods = self.methods.lock().unwrap_or_else(|p| p.into_inner());
+        if methods.contains(method) {
+            return method;
+        }
+        if methods.len() < MAX_METHOD_LABELS {
+            methods.insert(method.to_string());
+            return method;
+        }
+        "other"
+    }
+
     pub fn render(&self) -> Result<String> {
         let encoder = TextEncoder::new();
         let metric_families = self.registry.gather();
@@ -0,0 +1,164 @@
+// Numan Thabit 2025
+//! Per-client rate limiting.
+//!
+//! Clients are identified by the API key header when it carries a known key
+//! and by IP address otherwise. Each client drains its own token bucket; a
+//! call costs its method's weight (1 unless configured). Known keys may carry
+//! their own limit; everyone else gets the global one. Requests are counted
+//! per key name, with IP clients counted together as `anonymous`.
+//!
+//! Buckets that have refilled are swept out at most once a second once many
+//! clients are tracked. Past `MAX_CLIENTS`, IPs without a bucket share one
+//! overflow bucket, so a flood of fresh addresses cannot grow the map.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::config::{Config, RateLimit};
+use crate::metrics::ProxyMetrics;
+
+const ANONYMOUS: &str = "anonymous";
+// Past this many tracked clients, full buckets are dropped, at most once a `SWEEP_EVERY`
+const SWEEP_AT: usize = 10_000;
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+/// Tracked clients past which new IPs share the overflow bucket.
+pub const MAX_CLIENTS: usize = 100_000;
+
+/// What to do with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Over the client's limit.
+    Limited,
+    /// No known API key while one is required.
+    Unauthorized,
+}
+
+pub struct RateLimiter {
+    default: Option<RateLimit>,
+    keys: HashMap<String, Key>,
+    weights: HashMap<String, u32>,
+    require_key: bool,
+    buckets: Mutex<Buckets>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+struct Buckets {
+    map: HashMap<Client, Bucket>,
+    next_sweep: Instant,
+}
+
+struct Key {
+    name: Arc<str>,
+    limit: Option<RateLimit>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Client {
+    Key(Arc<str>),
+    Ip(IpAddr),
+    /// IPs that arrived while the map was full
+    Overflow,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    limit: RateLimit,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config, metrics: Arc<ProxyMetrics>) -> Self {
+        let keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| {
+                let key = Key {
+                    name: Arc::from(api_key.name.as_str()),
+                    limit: api_key.limit().or(config.rate_limit),
+                };
+                (api_key.key.clone(), key)
+            })
+            .collect();
+        Self {
+            default: config.rate_limit,
+            keys,
+            weights: config.method_weights.clone(),
+            require_key: config.require_api_key,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                next_sweep: Instant::now(),
+            }),
+            metrics,
+        }
+    }
+
+    /// Charge a call of `method` to the client with `api_key` or, without a
+    /// known key, `ip`.
+    pub fn check(&self, api_key: Option<&str>, ip: IpAddr, method: Option<&str>) -> Verdict {
+        let (client, label, limit) = match api_key.and_then(|key| self.keys.get(key)) {
+            Some(key) => (Client::Key(key.name.clone()), &*key.name, key.limit),
+            None if self.require_key => return Verdict::Unauthorized,
+            None => (Client::Ip(ip), ANONYMOUS, self.default),
+        };
+        self.metrics.record_client_request(label);
+        let Some(limit) = limit else {
+            return Verdict::Allow;
+        };
+        let weight = method
+            .and_then(|method| self.weights.get(method))
+            .copied()
+            .unwrap_or(1);
+        // A call can never cost more than a full bucket
+        let cost = f64::from(weight).min(limit.burst);
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|p| p.into_inner());
+        let client = buckets.admit(client, now);
+        let bucket = buckets.map.entry(client).or_insert(Bucket {
+            tokens: limit.burst,
+            updated: now,
+            limit,
+        });
+        bucket.tokens = bucket.refilled(now);
+        bucket.updated = now;
+        if bucket.tokens < cost {
+            self.metrics.record_client_limited(label);
+            return Verdict::Limited;
+        }
+        bucket.tokens -= cost;
+        Verdict::Allow
+    }
+}
+
+impl Buckets {
+    /// The client whose bucket a new call is charged to: its own, unless it
+    /// has none and the map is full.
+    fn admit(&mut self, client: Client, now: Instant) -> Client {
+        if self.map.contains_key(&client) {
+            return client;
+        }
+        if self.map.len() >= SWEEP_AT && now >= self.next_sweep {
+            self.next_sweep = now + SWEEP_EVERY;
+            self.map
+                .retain(|_, bucket| bucket.refilled(now) < bucket.limit.burst);
+        }
+        if matches!(client, Client::Ip(_)) && self.map.len() >= MAX_CLIENTS {
+            return Client::Overflow;
+        }
+        client
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * self.limit.rps).min(self.limit.burst)
+    }
+}
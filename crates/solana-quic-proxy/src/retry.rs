@@ -0,0 +1,68 @@
+// Numan Thabit 2025
+//! Retry and hedging policy per JSON-RPC method.
+//!
+//! A request that fails with a transient error (connect, read or write
+//! failure, timeout) is retried up to `max_retries` times with exponential
+//! backoff, and read-only methods may be hedged with a second attempt after
+//! `hedge_jitter`. Methods in `never_retry` (`sendTransaction` by default)
+//! and batches, whose contents are not inspected, are sent exactly once and
+//! never hedged, so a transaction is never submitted twice. Failing over to
+//! another upstream that was never reached still applies to them, since
+//! nothing was sent.
+
+use std::{collections::HashSet, time::Duration};
+
+use crate::client::ProxyError;
+use crate::config::Config;
+
+pub struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+    never_retry: HashSet<String>,
+}
+
+/// How one request may be attempted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempts {
+    pub retries: u32,
+    pub hedge: bool,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            backoff: config.retry_backoff,
+            never_retry: config.never_retry.clone(),
+        }
+    }
+
+    /// Attempts allowed for `method`; `None` for a batch or unparseable body.
+    pub fn attempts(&self, method: Option<&str>) -> Attempts {
+        match method {
+            Some(method) if !self.never_retry.contains(method) => Attempts {
+                retries: self.max_retries,
+                hedge: true,
+            },
+            _ => Attempts {
+                retries: 0,
+                hedge: false,
+            },
+        }
+    }
+
+    /// Delay before retry number `retry` (from 1), doubling each time.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+    }
+}
+
+/// Whether `err` is worth another attempt.
+pub fn is_transient(err: &ProxyError) -> bool {
+    match err {
+        ProxyError::ResponseTooLarge { .. } | ProxyError::CircuitOpen => false,
+        ProxyError::Http(err) => err.is_connect() || err.is_timeout() || err.is_request(),
+        _ => true,
+    }
+}
@@ -0,0 +1,319 @@
+// Numan Thabit 2025
+//! Method-based routing.
+//!
+//! Each configured route takes the JSON-RPC methods it lists to its own
+//! upstreams: a pool of QUIC upstreams (e.g. a staked endpoint for
+//! `sendTransaction`) or a plain HTTP JSON-RPC node (e.g. an archival node for
+//! `getProgramAccounts`). Every other method, batches and unparseable bodies
+//! go to the default pool. Requests, failures and latency are recorded per
+//! route. Transient failures are retried on the same route under the
+//! [`RetryPolicy`]. HTTP routes get the request's ID as `X-Request-Id`; the
+//! QUIC framing has no room for headers.
+
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use axum::http::header::CONTENT_TYPE;
+use futures::StreamExt;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::client::{ClientResponse, ProxyError};
+use crate::config::{Config, DEFAULT_ROUTE};
+use crate::metrics::ProxyMetrics;
+use crate::retry::{self, RetryPolicy};
+use crate::stream::ResponseStream;
+use crate::upstream::UpstreamPool;
+
+/// Header carrying the request ID to HTTP upstreams and back to the client.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub struct Routes {
+    // The default route first
+    routes: Vec<Route>,
+    by_method: HashMap<String, usize>,
+    retry: RetryPolicy,
+    metrics: Arc<ProxyMetrics>,
+}
+
+struct Route {
+    name: String,
+    backend: Backend,
+}
+
+/// What travels with a request besides its payload.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestContext<'a> {
+    /// Affinity hint pinning the client to one upstream of a QUIC route.
+    pub session: Option<&'a str>,
+    pub request_id: Option<&'a str>,
+}
+
+enum Backend {
+    Quic(Arc<UpstreamPool>),
+    Http(HttpUpstream),
+}
+
+impl Routes {
+    pub fn new(config: Arc<Config>, metrics: Arc<ProxyMetrics>) -> Result<Self> {
+        let mut routes = vec![Route {
+            name: DEFAULT_ROUTE.to_string(),
+            backend: Backend::Quic(Arc::new(UpstreamPool::new(
+                config.clone(),
+                metrics.clone(),
+            )?)),
+        }];
+        let mut by_method = HashMap::new();
+        for route in &config.routes {
+            let backend = match &route.url {
+                Some(url) => Backend::Http(HttpUpstream::new(&config, url)?),
+                None => Backend::Quic(Arc::new(UpstreamPool::with_upstreams(
+                    config.clone(),
+                    &route.upstreams,
+                    metrics.clone(),
+                )?)),
+            };
+            for method in &route.methods {
+                by_method.insert(method.clone(), routes.len());
+            }
+            routes.push(Route {
+                name: route.name.clone(),
+                backend,
+            });
+        }
+        Ok(Self {
+            routes,
+            by_method,
+            retry: RetryPolicy::new(&config),
+            metrics,
+        })
+    }
+
+    /// Connect to every QUIC upstream up front.
+    pub async fn warmup(&self) {
+        for route in &self.routes {
+            if let Backend::Quic(pool) = &route.backend {
+                if let Err(err) = pool.warmup().await {
+                    warn!(route = %route.name, error = %err, "upstream preconnect failed; continuing with lazy dial");
+                }
+            }
+        }
+    }
+
+    /// Health-check every QUIC pool each `interval`.
+    pub fn spawn_health_checks(&self, interval: Duration) {
+        for route in &self.routes {
+            if let Backend::Quic(pool) = &route.backend {
+                pool.clone().spawn_health_checks(interval);
+            }
+        }
+    }
+
+    /// The QUIC pool of the route called `name`; `None` for an unknown route
+    /// or one served over HTTP.
+    pub fn pool(&self, name: &str) -> Option<&Arc<UpstreamPool>> {
+        self.pools()
+            .find(|&(route, _)| route == name)
+            .map(|(_, pool)| pool)
+    }
+
+    /// Every QUIC pool with the name of its route, the default route first.
+    pub fn pools(&self) -> impl Iterator<Item = (&str, &Arc<UpstreamPool>)> {
+        self.routes.iter().filter_map(|route| match &route.backend {
+            Backend::Quic(pool) => Some((route.name.as_str(), pool)),
+            Backend::Http(_) => None,
+        })
+    }
+
+    /// Send `payload` over the route for `method`; `None` takes the default route.
+    pub async fn request(
+        &self,
+        method: Option<&str>,
+        payload: &[u8],
+        context: RequestContext<'_>,
+    ) -> Result<ClientResponse, ProxyError> {
+        let hedge = self.retry.attempts(method).hedge;
+        self.send(
+            method,
+            |backend| async move {
+                match backend {
+                    Backend::Quic(pool) => pool.request(payload, hedge, context.session).await,
+                    Backend::Http(http) => http.request(payload, context.request_id).await,
+                }
+            },
+            |r| r.latency,
+        )
+        .await
+    }
+
+    /// Like [`request`](Self::request), with the response body streamed.
+    /// Retries stop once the response has started.
+    pub async fn request_stream(
+        &self,
+        method: Option<&str>,
+        payload: &[u8],
+        context: RequestContext<'_>,
+    ) -> Result<ResponseStream, ProxyError> {
+        self.send(
+            method,
+            |backend| async move {
+                match backend {
+                    Backend::Quic(pool) => pool.request_stream(payload, context.session).await,
+                    Backend::Http(http) => http.request_stream(payload, context.request_id).await,
+                }
+            },
+            |r| r.latency,
+        )
+        .await
+    }
+
+    async fn send<'a, T, F, Fut>(
+        &'a self,
+        method: Option<&str>,
+        attempt: F,
+        latency: fn(&T) -> Duration,
+    ) -> Result<T, ProxyError>
+    where
+        F: Fn(&'a Backend) -> Fut,
+        Fut: Future<Output = Result<T, ProxyError>>,
+    {
+        let idx = method
+            .and_then(|method| self.by_method.get(method))
+            .copied()
+            .unwrap_or(0);
+        let route = &self.routes[idx];
+        let retry_limit = self.retry.attempts(method).retries;
+        let mut retries = 0;
+        let result = loop {
+            match attempt(&route.backend).await {
+                Err(err) if retries < retry_limit && retry::is_transient(&err) => {
+                    retries += 1;
+                    debug!(route = %route.name, retry = retries, error = %err, "retrying upstream request");
+                    self.metrics.record_retry(&route.name);
+                    tokio::time::sleep(self.retry.backoff(retries)).await;
+                }
+                result => break result,
+            }
+        };
+        match &result {
+            Ok(response) => self
+                .metrics
+                .record_route_success(&route.name, latency(response)),
+            Err(_) => self.metrics.record_route_failure(&route.name),
+        }
+        result
+    }
+}
+
+struct HttpUpstream {
+    client: reqwest::Client,
+    url: String,
+    // `url` as reported with responses
+    label: Arc<str>,
+    timeout: Option<Duration>,
+    max_response_bytes: usize,
+    max_stream_bytes: usize,
+}
+
+impl HttpUpstream {
+    fn new(config: &Config, url: &str) -> Result<Self> {
+        Ok(Self {
+            client: reqwest::Client::builder()
+                .build()
+                .context("failed to build HTTP client")?,
+            url: url.to_string(),
+            label: Arc::from(url),
+            timeout: config.request_timeout,
+            max_response_bytes: config.max_response_bytes,
+            max_stream_bytes: config.max_stream_bytes,
+        })
+    }
+
+    async fn request(
+        &self,
+        payload: &[u8],
+        request_id: Option<&str>,
+    ) -> Result<ClientResponse, ProxyError> {
+        let start = Instant::now();
+        let mut request = self.post(payload, request_id);
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        let response = checked(request.send().await.map_err(ProxyError::Http)?)?;
+        let too_large = |size: usize| ProxyError::ResponseTooLarge {
+            size,
+            max: self.max_response_bytes,
+        };
+        if let Some(len) = response.content_length() {
+            if len as usize > self.max_response_bytes {
+                return Err(too_large(len as usize));
+            }
+        }
+        let payload = response.bytes().await.map_err(ProxyError::Http)?;
+        if payload.len() > self.max_response_bytes {
+            return Err(too_large(payload.len()));
+        }
+        Ok(ClientResponse {
+            payload,
+            latency: start.elapsed(),
+            upstream: self.label.clone(),
+        })
+    }
+
+    /// The timeout covers the wait for the response headers only.
+    async fn request_stream(
+        &self,
+        payload: &[u8],
+        request_id: Option<&str>,
+    ) -> Result<ResponseStream, ProxyError> {
+        let start = Instant::now();
+        let send = self.post(payload, request_id).send();
+        let response = match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, send)
+                .await
+                .map_err(|_| ProxyError::Protocol("request timed out".into()))?,
+            None => send.await,
+        };
+        let response = checked(response.map_err(ProxyError::Http)?)?;
+        let len = response.content_length().map(|len| len as usize);
+        if let Some(size) = len.filter(|&len| len > self.max_stream_bytes) {
+            return Err(ProxyError::ResponseTooLarge {
+                size,
+                max: self.max_stream_bytes,
+            });
+        }
+        let chunks = futures::stream::try_unfold(response, |mut response| async move {
+            let chunk = response.chunk().await.map_err(ProxyError::Http)?;
+            Ok(chunk.map(|chunk| (chunk, response)))
+        });
+        Ok(ResponseStream::new(
+            self.label.clone(),
+            len,
+            start.elapsed(),
+            chunks.boxed(),
+        ))
+    }
+
+    fn post(&self, payload: &[u8], request_id: Option<&str>) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(payload.to_vec());
+        match request_id {
+            Some(id) => request.header(REQUEST_ID_HEADER, id),
+            None => request,
+        }
+    }
+}
+
+/// `response`, or an error for a non-success status.
+fn checked(response: reqwest::Response) -> Result<reqwest::Response, ProxyError> {
+    if !response.status().is_success() {
+        return Err(ProxyError::Protocol(format!(
+            "upstream HTTP status {}",
+            response.status()
+        )));
+    }
+    Ok(response)
+}
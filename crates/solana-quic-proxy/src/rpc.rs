@@ -0,0 +1,89 @@
+// Numan Thabit 2025
+//! Just enough JSON-RPC to look inside a request: its method, params and id.
+//! Params and ids stay raw JSON, so nothing is re-encoded on the way through.
+
+use std::{
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use bytes::Bytes;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+#[derive(Debug, Deserialize)]
+pub struct RpcRequest<'a> {
+    #[serde(default, borrow)]
+    pub jsonrpc: Option<Cow<'a, str>>,
+    #[serde(borrow)]
+    pub method: Cow<'a, str>,
+    #[serde(default, borrow)]
+    pub params: Option<&'a RawValue>,
+    #[serde(default, borrow)]
+    pub id: Option<&'a RawValue>,
+}
+
+impl<'a> RpcRequest<'a> {
+    /// A single request; `None` for a batch or anything that is not JSON-RPC.
+    pub fn parse(body: &'a [u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+
+    /// Hash of method and params: requests with the same one get the same answer.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.method.hash(&mut hasher);
+        self.params.map_or("", |p| p.get()).hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<'a> {
+    #[serde(default, borrow)]
+    result: Option<&'a RawValue>,
+    #[serde(default, borrow)]
+    error: Option<&'a RawValue>,
+}
+
+/// The raw `result` of a successful response; `None` for an error response or
+/// anything unparseable.
+pub fn result_of(response: &[u8]) -> Option<&RawValue> {
+    let response: RpcResponse = serde_json::from_slice(response).ok()?;
+    match response.error {
+        Some(_) => None,
+        None => response.result,
+    }
+}
+
+/// A success response carrying `result` for the request with `id`.
+pub fn success_response(result: &RawValue, id: Option<&RawValue>) -> Bytes {
+    wrap("result", result, id)
+}
+
+/// `response` re-addressed to the request with `id`; `None` if it is not a
+/// JSON-RPC response.
+pub fn with_id(response: &[u8], id: Option<&RawValue>) -> Option<Bytes> {
+    let response: RpcResponse = serde_json::from_slice(response).ok()?;
+    match (response.error, response.result) {
+        (Some(error), _) => Some(wrap("error", error, id)),
+        (None, Some(result)) => Some(wrap("result", result, id)),
+        (None, None) => None,
+    }
+}
+
+/// An error response with `code` and `message` for the request with `id`.
+pub fn error_response(code: i64, message: &str, id: Option<&RawValue>) -> Bytes {
+    let error = serde_json::json!({ "code": code, "message": message }).to_string();
+    let id = id.map_or("null", |id| id.get());
+    Bytes::from(format!(r#"{{"jsonrpc":"2.0","error":{error},"id":{id}}}"#))
+}
+
+fn wrap(field: &str, value: &RawValue, id: Option<&RawValue>) -> Bytes {
+    let id = id.map_or("null", |id| id.get());
+    Bytes::from(format!(
+        r#"{{"jsonrpc":"2.0","{field}":{},"id":{id}}}"#,
+        value.get()
+    ))
+}
@@ -0,0 +1,123 @@
+// Numan Thabit 2025
+//! Streamed responses.
+//!
+//! Responses to methods in `stream_methods` (`getProgramAccounts` by default)
+//! are not buffered: the upstream body is relayed to the client chunk by chunk
+//! as it arrives, so a result set of hundreds of megabytes costs the proxy a
+//! few chunks of memory rather than the whole response. A response is cut off
+//! once it passes `max_stream_bytes`. When the client goes away, or the
+//! upstream fails part-way through, the upstream stream is dropped, which
+//! stops it, and the abort is counted along with the bytes relayed so far.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use axum::body::Body;
+use bytes::Bytes;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use tracing::warn;
+
+use crate::client::ProxyError;
+use crate::metrics::ProxyMetrics;
+
+/// Body chunks in the order the upstream sent them.
+pub type Chunks = BoxStream<'static, Result<Bytes, ProxyError>>;
+
+pub struct ResponseStream {
+    /// Body size announced by the upstream, if it did.
+    pub len: Option<usize>,
+    /// Time until the upstream started answering.
+    pub latency: Duration,
+    /// The upstream answering.
+    pub upstream: Arc<str>,
+    chunks: Chunks,
+}
+
+impl ResponseStream {
+    pub fn new(upstream: Arc<str>, len: Option<usize>, latency: Duration, chunks: Chunks) -> Self {
+        Self {
+            upstream,
+            len,
+            latency,
+            chunks,
+        }
+    }
+
+    /// The response body, ending in an error once more than `max_bytes` went
+    /// through.
+    pub fn into_body(self, max_bytes: usize, metrics: Arc<ProxyMetrics>) -> Body {
+        Body::from_stream(Relay {
+            chunks: self.chunks,
+            max_bytes,
+            relayed: 0,
+            done: false,
+            metrics,
+        })
+    }
+}
+
+/// Relays chunks and records how the stream ended.
+struct Relay {
+    chunks: Chunks,
+    max_bytes: usize,
+    relayed: usize,
+    done: bool,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl Relay {
+    fn finish(&mut self, result: &str) {
+        self.done = true;
+        self.metrics.record_stream(result, self.relayed);
+    }
+}
+
+impl Stream for Relay {
+    type Item = Result<Bytes, ProxyError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match ready!(self.chunks.poll_next_unpin(cx)) {
+            Some(Ok(chunk)) if self.relayed + chunk.len() > self.max_bytes => {
+                let size = self.relayed + chunk.len();
+                warn!(
+                    size,
+                    max = self.max_bytes,
+                    "streamed response too large; cutting it off"
+                );
+                self.finish("too_large");
+                Poll::Ready(Some(Err(ProxyError::ResponseTooLarge {
+                    size,
+                    max: self.max_bytes,
+                })))
+            }
+            Some(Ok(chunk)) => {
+                self.relayed += chunk.len();
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Some(Err(err)) => {
+                warn!(relayed = self.relayed, error = %err, "upstream failed mid-stream");
+                self.finish("upstream_error");
+                Poll::Ready(Some(Err(err)))
+            }
+            None => {
+                self.finish("complete");
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        if !self.done {
+            self.finish("client_gone");
+        }
+    }
+}
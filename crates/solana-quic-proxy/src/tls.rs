@@ -0,0 +1,187 @@
+// Numan Thabit 2025
+//! TLS termination on the HTTP listener.
+//!
+//! Connections are accepted with rustls and served over HTTP/1.1 or HTTP/2,
+//! whichever ALPN negotiates, so clients can reach the proxy without a TLS
+//! terminator in front of it. The certificate and key files are re-read
+//! every `tls_reload_interval`; when either changed, new connections get the
+//! new certificate while open ones keep theirs. A pair that fails to load is
+//! logged and the previous one stays in use.
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use arc_swap::ArcSwap;
+use axum::{extract::ConnectInfo, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto::Builder,
+};
+use quinn::rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+
+use crate::metrics::ProxyMetrics;
+
+pub struct TlsConfig {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: ArcSwap<ServerConfig>,
+    // The file contents `current` was built from
+    loaded: Mutex<(Vec<u8>, Vec<u8>)>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl TlsConfig {
+    pub fn load(cert_path: &Path, key_path: &Path, metrics: Arc<ProxyMetrics>) -> Result<Self> {
+        let (cert, key) = read_pair(cert_path, key_path)?;
+        let config = server_config(&cert, &key)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: ArcSwap::from_pointee(config),
+            loaded: Mutex::new((cert, key)),
+            metrics,
+        })
+    }
+
+    /// Swap in the certificate on disk if it changed; whether it did.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let (cert, key) = read_pair(&self.cert_path, &self.key_path)?;
+        let mut loaded = self.loaded.lock().unwrap_or_else(|p| p.into_inner());
+        if loaded.0 == cert && loaded.1 == key {
+            return Ok(false);
+        }
+        let config = server_config(&cert, &key)?;
+        self.current.store(Arc::new(config));
+        *loaded = (cert, key);
+        Ok(true)
+    }
+
+    /// Check the certificate files for changes each `interval`.
+    pub fn spawn_reloader(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => {
+                        info!(cert = %self.cert_path.display(), "reloaded TLS certificate");
+                        self.metrics.record_tls_reload(true);
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        warn!(error = %err, "TLS certificate reload failed; keeping the previous one");
+                        self.metrics.record_tls_reload(false);
+                    }
+                }
+            }
+        })
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.load_full())
+    }
+}
+
+/// Serve `app` over TLS on `listener` until `shutdown` completes.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    tls: Arc<TlsConfig>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!(error = %err, "failed to accept connection");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+        let metrics = tls.metrics.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!(%peer, error = %err, "TLS handshake failed");
+                    metrics.record_tls_handshake_failure();
+                    return;
+                }
+            };
+            let service =
+                hyper::service::service_fn(move |mut request: hyper::Request<Incoming>| {
+                    request
+                        .extensions_mut()
+                        .insert(ConnectInfo::<SocketAddr>(peer));
+                    app.clone().oneshot(request)
+                });
+            if let Err(err) = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                debug!(%peer, error = %err, "connection closed with error");
+            }
+        });
+    }
+}
+
+fn read_pair(cert_path: &Path, key_path: &Path) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cert = std::fs::read(cert_path)
+        .with_context(|| format!("failed to read TLS certificate {}", cert_path.display()))?;
+    let key = std::fs::read(key_path)
+        .with_context(|| format!("failed to read TLS key {}", key_path.display()))?;
+    Ok((cert, key))
+}
+
+fn server_config(cert_pem: &[u8], key_pem: &[u8]) -> Result<ServerConfig> {
+    let certs: Vec<CertificateDer<'static>> =
+        rustls_pemfile::certs(&mut std::io::Cursor::new(cert_pem))
+            .context("failed to parse TLS certificate")?
+            .into_iter()
+            .map(CertificateDer::from)
+            .collect();
+    if certs.is_empty() {
+        return Err(anyhow!("no certificate found in TLS certificate file"));
+    }
+    let key = rustls_pemfile::read_all(&mut std::io::Cursor::new(key_pem))
+        .context("failed to parse TLS key")?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(der) => Some(PrivateKeyDer::Pkcs8(der.into())),
+            rustls_pemfile::Item::RSAKey(der) => Some(PrivateKeyDer::Pkcs1(der.into())),
+            rustls_pemfile::Item::ECKey(der) => Some(PrivateKeyDer::Sec1(der.into())),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("no private key found in TLS key file"))?;
+
+    let provider = Arc::new(quinn::rustls::crypto::ring::default_provider());
+    let mut config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .context("failed to select TLS versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("TLS certificate and key do not match")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(config)
+}
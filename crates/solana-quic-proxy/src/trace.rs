@@ -0,0 +1,103 @@
+// Numan Thabit 2025
+//! Request IDs and the slow-request log.
+//!
+//! Every request gets an ID: the client's own `X-Request-Id` when it sent a
+//! usable one, a fresh one otherwise. The ID goes back on the response and,
+//! on HTTP routes, upstream, so one request can be followed through proxy
+//! and node logs. Requests slower than `slow_request_ms` are logged with
+//! their method, upstream, timings and sizes.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::http::HeaderValue;
+use tracing::warn;
+
+use crate::metrics::ProxyMetrics;
+
+// Longer client IDs are replaced rather than logged and forwarded
+const MAX_CLIENT_ID_LEN: usize = 128;
+
+pub struct RequestIds {
+    // Tells apart the IDs of different proxy processes
+    prefix: u32,
+    next: AtomicU64,
+}
+
+impl Default for RequestIds {
+    fn default() -> Self {
+        Self {
+            prefix: RandomState::new().build_hasher().finish() as u32,
+            next: AtomicU64::new(1),
+        }
+    }
+}
+
+impl RequestIds {
+    /// The ID for a request whose client sent `client`.
+    pub fn assign(&self, client: Option<&HeaderValue>) -> String {
+        let usable = client
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_CLIENT_ID_LEN)
+            .filter(|id| id.bytes().all(|b| b.is_ascii_graphic()));
+        match usable {
+            Some(id) => id.to_string(),
+            None => {
+                let seq = self.next.fetch_add(1, Ordering::Relaxed);
+                format!("{:08x}-{seq}", self.prefix)
+            }
+        }
+    }
+}
+
+/// One finished request, as the slow-request log sees it.
+pub struct Completed<'a> {
+    pub request_id: &'a str,
+    pub method: &'a str,
+    /// `None` when no upstream answered.
+    pub upstream: Option<&'a str>,
+    pub total: Duration,
+    pub upstream_latency: Option<Duration>,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+}
+
+pub struct SlowLog {
+    threshold: Option<Duration>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl SlowLog {
+    /// `None` turns the log off.
+    pub fn new(threshold: Option<Duration>, metrics: Arc<ProxyMetrics>) -> Self {
+        Self { threshold, metrics }
+    }
+
+    /// Log `request` if it took longer than the threshold.
+    pub fn observe(&self, request: &Completed<'_>) {
+        match self.threshold {
+            Some(threshold) if request.total > threshold => {}
+            _ => return,
+        }
+        self.metrics.record_slow_request(request.method);
+        warn!(
+            request_id = request.request_id,
+            method = request.method,
+            upstream = request.upstream.unwrap_or("none"),
+            duration_ms = request.total.as_millis() as u64,
+            upstream_ms = request
+                .upstream_latency
+                .map(|latency| latency.as_millis() as u64),
+            request_bytes = request.request_bytes,
+            response_bytes = request.response_bytes,
+            "slow request"
+        );
+    }
+}
@@ -0,0 +1,531 @@
+// Numan Thabit 2025
+//! Pool of QUIC upstreams behind one proxy.
+//!
+//! Requests go to a healthy upstream picked by smooth weighted round-robin or
+//! by lowest recent round-trip latency. An upstream that fails
+//! `eject_after_failures` times in a row is ejected for `eject_duration`; the
+//! background prober sends `getHealth` to every upstream and readmits one as
+//! soon as it answers. A request whose upstream could not be reached (nothing
+//! was sent) fails over to the next pick. When every upstream is ejected the
+//! pool keeps using them all rather than failing outright. Upstreams whose
+//! circuit breaker is open are skipped entirely; if every circuit is open the
+//! request fails fast instead.
+//!
+//! Upstreams can be added, removed or drained at runtime (see the admin API).
+//! A drained upstream gets no new requests but is still health-checked;
+//! requests already sent to a removed one run to completion.
+//!
+//! With `affinity_ttl` set, a request carrying a session hint goes back to
+//! the upstream that last answered that session while it can take requests.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::{debug, info, warn};
+
+use crate::affinity::Affinity;
+use crate::breaker::{CircuitBreaker, CircuitState};
+use crate::client::{ClientResponse, ProxyError, QuicRpcClient};
+use crate::config::{Config, UpstreamConfig, UpstreamPolicy};
+use crate::metrics::ProxyMetrics;
+use crate::stream::ResponseStream;
+
+const HEALTH_PROBE: &[u8] = br#"{"jsonrpc":"2.0","id":0,"method":"getHealth"}"#;
+// Weight of the newest sample in the latency average
+const LATENCY_ALPHA: f64 = 0.2;
+
+pub struct UpstreamPool {
+    config: Arc<Config>,
+    upstreams: ArcSwap<Vec<Arc<Upstream>>>,
+    // Serializes add, remove and drain
+    membership: Mutex<()>,
+    policy: UpstreamPolicy,
+    eject_after: u32,
+    eject_for: Duration,
+    // Serializes smooth weighted round-robin picks
+    round_robin: Mutex<()>,
+    affinity: Option<Affinity>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+struct Upstream {
+    label: String,
+    server_name: String,
+    client: Arc<QuicRpcClient>,
+    weight: u32,
+    draining: AtomicBool,
+    health: Mutex<Health>,
+    breaker: Option<CircuitBreaker>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    ejected_until: Option<Instant>,
+    // Moving average of the round-trip latency in seconds
+    latency: Option<f64>,
+    // Smooth weighted round-robin state
+    current: i64,
+}
+
+impl Health {
+    fn available(&self, now: Instant) -> bool {
+        !matches!(self.ejected_until, Some(until) if now < until)
+    }
+}
+
+impl Upstream {
+    fn new(
+        config: &Arc<Config>,
+        upstream: &UpstreamConfig,
+        metrics: &Arc<ProxyMetrics>,
+    ) -> Result<Self> {
+        let label = upstream.addr.to_string();
+        let client = QuicRpcClient::with_upstream(config.clone(), upstream, metrics.clone())?;
+        let client = Arc::new(client);
+        if !config.lazy_connect {
+            client.spawn_standby();
+        }
+        metrics.set_upstream_healthy(&label, true);
+        Ok(Self {
+            label,
+            server_name: upstream
+                .server_name
+                .clone()
+                .unwrap_or_else(|| config.server_name.clone()),
+            client,
+            weight: upstream.weight,
+            draining: AtomicBool::new(false),
+            health: Mutex::default(),
+            breaker: config.breaker.map(CircuitBreaker::new),
+        })
+    }
+
+    /// Whether the upstream is in regular rotation.
+    fn accepting(&self) -> bool {
+        self.weight > 0 && !self.draining.load(Ordering::Relaxed)
+    }
+}
+
+/// One upstream as reported by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub addr: String,
+    pub server_name: String,
+    pub weight: u32,
+    pub healthy: bool,
+    pub draining: bool,
+    pub consecutive_failures: u32,
+    pub latency_ms: Option<f64>,
+    /// `None` when the circuit breaker is disabled.
+    pub circuit: Option<CircuitState>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MembershipError {
+    #[error("upstream {0} is already in the pool")]
+    Exists(String),
+    #[error("no upstream {0} in the pool")]
+    NotFound(String),
+    #[error("the pool needs at least one upstream taking requests")]
+    LastUpstream,
+    #[error("failed to set up upstream: {0:#}")]
+    Setup(anyhow::Error),
+}
+
+impl UpstreamPool {
+    /// A pool of the default upstreams.
+    pub fn new(config: Arc<Config>, metrics: Arc<ProxyMetrics>) -> Result<Self> {
+        let upstreams = config.upstreams.clone();
+        Self::with_upstreams(config, &upstreams, metrics)
+    }
+
+    pub fn with_upstreams(
+        config: Arc<Config>,
+        upstreams: &[UpstreamConfig],
+        metrics: Arc<ProxyMetrics>,
+    ) -> Result<Self> {
+        let upstreams = upstreams
+            .iter()
+            .map(|upstream| Upstream::new(&config, upstream, &metrics).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            upstreams: ArcSwap::from_pointee(upstreams),
+            membership: Mutex::new(()),
+            policy: config.upstream_policy,
+            eject_after: config.eject_after_failures,
+            eject_for: config.eject_duration,
+            round_robin: Mutex::new(()),
+            affinity: config.affinity_ttl.map(Affinity::new),
+            config,
+            metrics,
+        })
+    }
+
+    /// Connect to every upstream up front; an error only if none could be reached.
+    pub async fn warmup(&self) -> Result<(), ProxyError> {
+        let upstreams = self.upstreams.load_full();
+        let results = futures::future::join_all(upstreams.iter().map(|u| u.client.warmup())).await;
+        let mut reached = false;
+        let mut last_err = None;
+        for (upstream, res) in upstreams.iter().zip(results) {
+            match res {
+                Ok(()) => reached = true,
+                Err(err) => {
+                    warn!(upstream = %upstream.label, error = %err, "upstream preconnect failed");
+                    self.record_failure(upstream);
+                    last_err = Some(err);
+                }
+            }
+        }
+        match last_err {
+            Some(err) if !reached => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Send `payload`; `session` is the client's affinity hint, if any.
+    pub async fn request(
+        &self,
+        payload: &[u8],
+        hedge: bool,
+        session: Option<&str>,
+    ) -> Result<ClientResponse, ProxyError> {
+        self.send(
+            |client| async move { client.request(payload, hedge).await },
+            |r| r.latency,
+            session,
+        )
+        .await
+    }
+
+    /// Like [`request`](Self::request), with the response body streamed.
+    pub async fn request_stream(
+        &self,
+        payload: &[u8],
+        session: Option<&str>,
+    ) -> Result<ResponseStream, ProxyError> {
+        self.send(
+            |client| async move { client.request_stream(payload).await },
+            |r| r.latency,
+            session,
+        )
+        .await
+    }
+
+    /// Run `attempt` against picked upstreams until one answers or failing
+    /// over is no longer safe.
+    async fn send<T, F, Fut>(
+        &self,
+        attempt: F,
+        latency: fn(&T) -> Duration,
+        session: Option<&str>,
+    ) -> Result<T, ProxyError>
+    where
+        F: Fn(Arc<QuicRpcClient>) -> Fut,
+        Fut: Future<Output = Result<T, ProxyError>>,
+    {
+        let upstreams = self.upstreams.load_full();
+        let affinity = self.affinity.as_ref().zip(session);
+        let mut pinned = affinity
+            .and_then(|(affinity, session)| affinity.get(session, Instant::now()))
+            .and_then(|label| self.pinned(&upstreams, &label));
+        let mut tried = Vec::with_capacity(1);
+        let mut last_err = None;
+        loop {
+            // Only the first attempt goes to the pinned upstream
+            let Some(idx) = pinned.take().or_else(|| self.pick(&upstreams, &tried)) else {
+                return Err(last_err.unwrap_or_else(|| {
+                    self.metrics.record_circuit_rejection();
+                    ProxyError::CircuitOpen
+                }));
+            };
+            let upstream = &upstreams[idx];
+            tried.push(idx);
+            if let Some(breaker) = &upstream.breaker {
+                // Another request took the half-open trial first
+                if !breaker.acquire(Instant::now()) {
+                    continue;
+                }
+            }
+            self.metrics.record_upstream_request(&upstream.label);
+            let start = Instant::now();
+            let result = attempt(upstream.client.clone()).await;
+            self.record_outcome(upstream, result.is_err(), start.elapsed());
+            match result {
+                Ok(response) => {
+                    self.record_success(upstream, latency(&response));
+                    if let Some((affinity, session)) = affinity {
+                        affinity.pin(session, &upstream.label, Instant::now());
+                    }
+                    return Ok(response);
+                }
+                Err(err) => {
+                    self.record_failure(upstream);
+                    let unsent = matches!(err, ProxyError::Connect(_) | ProxyError::Connection(_));
+                    if !unsent || tried.len() == upstreams.len() {
+                        return Err(err);
+                    }
+                    warn!(upstream = %upstream.label, error = %err, "upstream unreachable; failing over");
+                    last_err = Some(err);
+                }
+            }
+        }
+    }
+
+    /// Probe every upstream with `getHealth` each `interval`.
+    pub fn spawn_health_checks(self: Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                let upstreams = self.upstreams.load_full();
+                futures::future::join_all(upstreams.iter().map(|u| self.probe(u))).await;
+            }
+        })
+    }
+
+    /// Every upstream with its health, in the order they were added.
+    pub fn status(&self) -> Vec<UpstreamStatus> {
+        let now = Instant::now();
+        self.upstreams
+            .load()
+            .iter()
+            .map(|upstream| {
+                let health = lock(&upstream.health);
+                UpstreamStatus {
+                    addr: upstream.label.clone(),
+                    server_name: upstream.server_name.clone(),
+                    weight: upstream.weight,
+                    healthy: health.available(now),
+                    draining: upstream.draining.load(Ordering::Relaxed),
+                    consecutive_failures: health.failures,
+                    latency_ms: health.latency.map(|secs| secs * 1e3),
+                    circuit: upstream.breaker.as_ref().map(CircuitBreaker::state),
+                }
+            })
+            .collect()
+    }
+
+    /// Start sending requests to `upstream` too.
+    pub fn add(&self, upstream: &UpstreamConfig) -> Result<(), MembershipError> {
+        let _guard = lock(&self.membership);
+        let label = upstream.addr.to_string();
+        let current = self.upstreams.load_full();
+        if current.iter().any(|u| u.label == label) {
+            return Err(MembershipError::Exists(label));
+        }
+        let added =
+            Upstream::new(&self.config, upstream, &self.metrics).map_err(MembershipError::Setup)?;
+        let mut next = current.as_ref().clone();
+        next.push(Arc::new(added));
+        self.upstreams.store(Arc::new(next));
+        info!(upstream = %label, weight = upstream.weight, "upstream added");
+        Ok(())
+    }
+
+    /// Stop sending requests to the upstream at `addr`.
+    pub fn remove(&self, addr: &str) -> Result<(), MembershipError> {
+        let _guard = lock(&self.membership);
+        let current = self.upstreams.load_full();
+        let idx = current
+            .iter()
+            .position(|u| u.label == addr)
+            .ok_or_else(|| MembershipError::NotFound(addr.to_string()))?;
+        let mut next = current.as_ref().clone();
+        let removed = next.remove(idx);
+        if !next.iter().any(|u| u.accepting()) {
+            return Err(MembershipError::LastUpstream);
+        }
+        self.upstreams.store(Arc::new(next));
+        self.metrics.forget_upstream(&removed.label);
+        info!(upstream = %removed.label, "upstream removed");
+        Ok(())
+    }
+
+    /// Take the upstream at `addr` out of rotation, or put it back.
+    pub fn set_draining(&self, addr: &str, draining: bool) -> Result<(), MembershipError> {
+        let _guard = lock(&self.membership);
+        let current = self.upstreams.load_full();
+        let upstream = current
+            .iter()
+            .find(|u| u.label == addr)
+            .ok_or_else(|| MembershipError::NotFound(addr.to_string()))?;
+        let others_accepting = current
+            .iter()
+            .any(|u| !Arc::ptr_eq(u, upstream) && u.accepting());
+        if draining && !others_accepting {
+            return Err(MembershipError::LastUpstream);
+        }
+        upstream.draining.store(draining, Ordering::Relaxed);
+        info!(upstream = %upstream.label, draining, "upstream drain state changed");
+        Ok(())
+    }
+
+    async fn probe(&self, upstream: &Upstream) {
+        match upstream.client.request(HEALTH_PROBE, false).await {
+            Ok(response) if !is_error_response(&response.payload) => {
+                self.record_success(upstream, response.latency)
+            }
+            Ok(_) => {
+                debug!(upstream = %upstream.label, "upstream reports unhealthy");
+                self.record_failure(upstream);
+            }
+            Err(err) => {
+                debug!(upstream = %upstream.label, error = %err, "upstream health probe failed");
+                self.record_failure(upstream);
+            }
+        }
+    }
+
+    /// The upstream labelled `label`, if it is in rotation, healthy and its
+    /// circuit is not open.
+    fn pinned(&self, upstreams: &[Arc<Upstream>], label: &str) -> Option<usize> {
+        let now = Instant::now();
+        upstreams.iter().position(|upstream| {
+            upstream.label == label
+                && upstream.accepting()
+                && lock(&upstream.health).available(now)
+                && upstream
+                    .breaker
+                    .as_ref()
+                    .is_none_or(|breaker| breaker.available(now))
+        })
+    }
+
+    /// The upstream for the next request, skipping drained ones and those in
+    /// `exclude`; `None` when every other upstream's circuit is open.
+    fn pick(&self, upstreams: &[Arc<Upstream>], exclude: &[usize]) -> Option<usize> {
+        let now = Instant::now();
+        let allowed: Vec<usize> = (0..upstreams.len())
+            .filter(|i| !exclude.contains(i))
+            .filter(|&i| !upstreams[i].draining.load(Ordering::Relaxed))
+            .filter(|&i| {
+                let breaker = upstreams[i].breaker.as_ref();
+                breaker.is_none_or(|breaker| breaker.available(now))
+            })
+            .collect();
+        if allowed.is_empty() {
+            return None;
+        }
+        let mut candidates: Vec<usize> = allowed
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let upstream = &upstreams[i];
+                upstream.weight > 0 && lock(&upstream.health).available(now)
+            })
+            .collect();
+        if candidates.is_empty() {
+            candidates = allowed;
+        }
+        match self.policy {
+            UpstreamPolicy::Weighted => {
+                let _turn = lock(&self.round_robin);
+                let weighted: Vec<(usize, u32)> = candidates
+                    .iter()
+                    .enumerate()
+                    .map(|(k, &i)| (k, upstreams[i].weight))
+                    .collect();
+                let mut current: Vec<i64> = candidates
+                    .iter()
+                    .map(|&i| lock(&upstreams[i].health).current)
+                    .collect();
+                let best = smooth_weighted(&weighted, &mut current);
+                for (&i, value) in candidates.iter().zip(current) {
+                    lock(&upstreams[i].health).current = value;
+                }
+                Some(candidates[best])
+            }
+            UpstreamPolicy::LeastLatency => candidates.into_iter().min_by(|&a, &b| {
+                let latency = |i: usize| lock(&upstreams[i].health).latency.unwrap_or(0.0);
+                latency(a).total_cmp(&latency(b))
+            }),
+        }
+    }
+
+    /// Feed a request's outcome to the upstream's circuit breaker.
+    fn record_outcome(&self, upstream: &Upstream, failed: bool, latency: Duration) {
+        let Some(breaker) = &upstream.breaker else {
+            return;
+        };
+        let Some(state) = breaker.record(failed, latency) else {
+            return;
+        };
+        match state {
+            CircuitState::Open => {
+                warn!(upstream = %upstream.label, "circuit opened");
+                self.metrics.record_circuit_open(&upstream.label);
+            }
+            _ => info!(upstream = %upstream.label, "circuit closed"),
+        }
+        self.metrics.set_circuit_state(&upstream.label, state);
+    }
+
+    fn record_success(&self, upstream: &Upstream, latency: Duration) {
+        let mut health = lock(&upstream.health);
+        health.failures = 0;
+        let sample = latency.as_secs_f64();
+        health.latency = Some(match health.latency {
+            Some(avg) => avg + LATENCY_ALPHA * (sample - avg),
+            None => sample,
+        });
+        if health.ejected_until.take().is_some() {
+            info!(upstream = %upstream.label, "upstream readmitted");
+            self.metrics.set_upstream_healthy(&upstream.label, true);
+        }
+    }
+
+    fn record_failure(&self, upstream: &Upstream) {
+        self.metrics.record_upstream_failure(&upstream.label);
+        let mut health = lock(&upstream.health);
+        health.failures += 1;
+        if health.failures < self.eject_after {
+            return;
+        }
+        if health.ejected_until.is_none() {
+            warn!(upstream = %upstream.label, failures = health.failures, "ejecting upstream");
+            self.metrics.record_upstream_ejection(&upstream.label);
+            self.metrics.set_upstream_healthy(&upstream.label, false);
+        }
+        health.ejected_until = Some(Instant::now() + self.eject_for);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn is_error_response(payload: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(payload)
+        .map(|v| v.get("error").is_some())
+        .unwrap_or(true)
+}
+
+/// Smooth weighted round-robin over `(index, weight)` candidates: every pick
+/// raises each candidate by its weight and lowers the winner by the total.
+fn smooth_weighted(candidates: &[(usize, u32)], current: &mut [i64]) -> usize {
+    let total: i64 = candidates.iter().map(|&(_, w)| i64::from(w)).sum();
+    let mut best = candidates[0].0;
+    for &(i, weight) in candidates {
+        current[i] += i64::from(weight);
+        if current[i] > current[best] {
+            best = i;
+        }
+    }
+    current[best] -= total;
+    best
+}
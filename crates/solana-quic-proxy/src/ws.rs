@@ -0,0 +1,192 @@
+// Numan Thabit 2025
+//! WebSocket pass-through so clients can point their WS URL at the proxy too.
+//!
+//! Each client socket accepted on the HTTP listener gets its own connection to
+//! the configured upstream WebSocket (`ws_upstream`), and frames are pumped
+//! both ways until either side closes. Notifications are opaque to the proxy;
+//! pings are answered on each hop. Upgrades pass the same address filter and
+//! rate limiter as HTTP requests, and so does every request the client sends
+//! afterwards: a rejected one is answered with a JSON-RPC error frame instead
+//! of going upstream.
+
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    extract::ws::{CloseFrame, Message, WebSocket},
+    http::StatusCode,
+};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{
+    self,
+    protocol::{frame::coding::CloseCode, CloseFrame as UpstreamCloseFrame},
+};
+use tracing::{debug, warn};
+
+use crate::{
+    filter::RequestFilter,
+    metrics::ProxyMetrics,
+    ratelimit::{RateLimiter, Verdict},
+    rpc::{self, RpcRequest},
+};
+
+// Error replies waiting for the client; a client that keeps sending rejected
+// requests without reading stalls on its own socket
+const REPLY_QUEUE: usize = 32;
+
+/// What each request on an accepted socket is checked against.
+pub struct Gate {
+    pub filter: Arc<RequestFilter>,
+    pub limiter: Arc<RateLimiter>,
+    pub ip: IpAddr,
+    pub api_key: Option<String>,
+}
+
+impl Gate {
+    /// The error frame to answer a client request with, or `None` to forward it.
+    fn reject(&self, body: &[u8]) -> Option<Message> {
+        let request = RpcRequest::parse(body);
+        let id = request.as_ref().and_then(|r| r.id);
+        let (code, message) = match self.filter.check(request.as_ref()) {
+            Err(rejection) => (rejection.code(), rejection.message()),
+            Ok(()) => {
+                let method = request.as_ref().map(|r| r.method.as_ref());
+                match self.limiter.check(self.api_key.as_deref(), self.ip, method) {
+                    Verdict::Allow => return None,
+                    Verdict::Limited => (-32005, "rate limit exceeded"),
+                    Verdict::Unauthorized => (-32001, "unknown API key"),
+                }
+            }
+        };
+        let reply = rpc::error_response(code, message, id);
+        Some(Message::Text(String::from_utf8_lossy(&reply).into_owned()))
+    }
+}
+
+/// Check an upgrade request from `ip` the way an HTTP request is checked,
+/// returning the status and message to refuse it with.
+pub fn admit(
+    filter: &RequestFilter,
+    limiter: &RateLimiter,
+    ip: IpAddr,
+    api_key: Option<&str>,
+) -> Result<(), (StatusCode, &'static str)> {
+    if !filter.allows(ip) {
+        return Err((StatusCode::FORBIDDEN, "client address not allowed"));
+    }
+    match limiter.check(api_key, ip, None) {
+        Verdict::Allow => Ok(()),
+        Verdict::Limited => Err((StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded")),
+        Verdict::Unauthorized => Err((StatusCode::UNAUTHORIZED, "unknown API key")),
+    }
+}
+
+/// Relay `client` to a fresh connection to `upstream` until either side
+/// closes, checking each client request against `gate`.
+pub async fn pass_through(
+    client: WebSocket,
+    upstream: Arc<str>,
+    metrics: Arc<ProxyMetrics>,
+    gate: Gate,
+) {
+    let (upstream_socket, _) = match tokio_tungstenite::connect_async(upstream.as_ref()).await {
+        Ok(conn) => conn,
+        Err(err) => {
+            metrics.record_ws_upstream_failure();
+            warn!(error = %err, %upstream, "upstream websocket connect failed");
+            let mut client = client;
+            let _ = client
+                .send(Message::Close(Some(CloseFrame {
+                    code: 1011,
+                    reason: "upstream unavailable".into(),
+                })))
+                .await;
+            return;
+        }
+    };
+
+    metrics.ws_connected();
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream_socket.split();
+    let (reply_tx, mut reply_rx) = mpsc::channel(REPLY_QUEUE);
+
+    let to_upstream = async {
+        while let Some(Ok(msg)) = client_rx.next().await {
+            let body = match &msg {
+                Message::Text(text) => Some(text.as_bytes()),
+                Message::Binary(data) => Some(&data[..]),
+                _ => None,
+            };
+            if let Some(reply) = body.and_then(|body| gate.reject(body)) {
+                if reply_tx.send(reply).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            let Some(msg) = client_to_upstream(msg) else {
+                continue;
+            };
+            let close = matches!(msg, tungstenite::Message::Close(_));
+            metrics.record_ws_message("client");
+            if upstream_tx.send(msg).await.is_err() || close {
+                break;
+            }
+        }
+        let _ = upstream_tx.close().await;
+    };
+    let to_client = async {
+        loop {
+            let msg = tokio::select! {
+                Some(reply) = reply_rx.recv() => reply,
+                msg = upstream_rx.next() => {
+                    let Some(Ok(msg)) = msg else {
+                        break;
+                    };
+                    let Some(msg) = upstream_to_client(msg) else {
+                        continue;
+                    };
+                    metrics.record_ws_message("upstream");
+                    msg
+                }
+            };
+            let close = matches!(msg, Message::Close(_));
+            if client_tx.send(msg).await.is_err() || close {
+                break;
+            }
+        }
+        let _ = client_tx.close().await;
+    };
+    // Whichever side finishes first ends the session.
+    tokio::select! {
+        _ = to_upstream => {}
+        _ = to_client => {}
+    }
+    metrics.ws_disconnected();
+    debug!(%upstream, "websocket session closed");
+}
+
+fn client_to_upstream(msg: Message) -> Option<tungstenite::Message> {
+    Some(match msg {
+        Message::Text(text) => tungstenite::Message::Text(text),
+        Message::Binary(data) => tungstenite::Message::Binary(data),
+        Message::Close(frame) => tungstenite::Message::Close(frame.map(|f| UpstreamCloseFrame {
+            code: CloseCode::from(f.code),
+            reason: f.reason,
+        })),
+        Message::Ping(_) | Message::Pong(_) => return None,
+    })
+}
+
+fn upstream_to_client(msg: tungstenite::Message) -> Option<Message> {
+    Some(match msg {
+        tungstenite::Message::Text(text) => Message::Text(text),
+        tungstenite::Message::Binary(data) => Message::Binary(data),
+        tungstenite::Message::Close(frame) => Message::Close(frame.map(|f| CloseFrame {
+            code: f.code.into(),
+            reason: f.reason,
+        })),
+        tungstenite::Message::Ping(_)
+        | tungstenite::Message::Pong(_)
+        | tungstenite::Message::Frame(_) => return None,
+    })
+}
@@ -0,0 +1,127 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{collections::HashMap, io::Write, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use clap::Parser;
+use common::install_crypto_provider;
+use rcgen::Certificate;
+use solana_quic_proxy::{
+    admin,
+    cache::ResponseCache,
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    route::Routes,
+    rpc::RpcRequest,
+};
+use tempfile::NamedTempFile;
+use tower::ServiceExt;
+
+async fn call(app: &Router, method: Method, uri: &str, body: &str) -> Result<(StatusCode, String)> {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))?;
+    let response = app.clone().oneshot(request).await?;
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    Ok((status, String::from_utf8(body.to_vec())?))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn manages_upstreams_and_flushes_the_cache() -> Result<()> {
+    install_crypto_provider();
+    let mut ca_file = NamedTempFile::new()?;
+    let ca = Certificate::from_params(rcgen::CertificateParams::new(["localhost".into()]))?;
+    ca_file.write_all(ca.serialize_pem()?.as_bytes())?;
+    ca_file.flush()?;
+    let cli = CliArgs::parse_from([
+        "test",
+        "--upstream",
+        "127.0.0.1:9001",
+        "--ca-cert",
+        ca_file.path().to_str().expect("temp path utf8"),
+        "--admin-listen",
+        "127.0.0.1:0",
+    ]);
+    let config = Arc::new(Config::from_cli(&cli)?);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let routes = Arc::new(Routes::new(config, metrics.clone())?);
+    let cache = Arc::new(ResponseCache::new(
+        HashMap::from([("getVersion".to_string(), Duration::from_secs(60))]),
+        16,
+        metrics,
+    ));
+    let app = admin::router(routes, cache.clone());
+
+    let added = r#"{"addr":"127.0.0.1:9002","weight":3}"#;
+    assert_eq!(
+        call(&app, Method::POST, "/upstreams", added).await?.0,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        call(&app, Method::POST, "/upstreams", added).await?.0,
+        StatusCode::CONFLICT
+    );
+    let (status, _) = call(&app, Method::POST, "/upstreams?route=archive", added).await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let drain = "/upstreams/127.0.0.1:9001/drain";
+    assert_eq!(
+        call(&app, Method::POST, drain, "").await?.0,
+        StatusCode::NO_CONTENT
+    );
+    // Draining or removing the only upstream left in rotation is refused
+    let (status, body) = call(&app, Method::POST, "/upstreams/127.0.0.1:9002/drain", "").await?;
+    assert_eq!(status, StatusCode::CONFLICT, "{body}");
+    let (status, _) = call(&app, Method::DELETE, "/upstreams/127.0.0.1:9002", "").await?;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, body) = call(&app, Method::GET, "/upstreams", "").await?;
+    assert_eq!(status, StatusCode::OK);
+    let listed: serde_json::Value = serde_json::from_str(&body)?;
+    let upstreams = &listed[0]["upstreams"];
+    assert_eq!(listed[0]["route"], "default");
+    assert_eq!(upstreams[0]["addr"], "127.0.0.1:9001");
+    assert_eq!(upstreams[0]["draining"], true);
+    assert_eq!(upstreams[0]["circuit"], "closed");
+    assert_eq!(upstreams[1]["weight"], 3);
+    assert_eq!(upstreams[1]["healthy"], true);
+
+    assert_eq!(
+        call(&app, Method::DELETE, drain, "").await?.0,
+        StatusCode::NO_CONTENT
+    );
+    let (status, _) = call(&app, Method::DELETE, "/upstreams/127.0.0.1:9002", "").await?;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = call(&app, Method::DELETE, "/upstreams/127.0.0.1:9002", "").await?;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let request =
+        RpcRequest::parse(br#"{"jsonrpc":"2.0","id":1,"method":"getVersion"}"#).expect("a request");
+    let key = cache.key(&request).expect("cached method");
+    cache.insert(
+        &key,
+        br#"{"jsonrpc":"2.0","result":{"solana-core":"2.0.0"},"id":1}"#,
+    );
+    let (status, body) = call(&app, Method::POST, "/cache/flush", "").await?;
+    assert_eq!(
+        (status, body.as_str()),
+        (StatusCode::OK, r#"{"flushed":1}"#)
+    );
+    assert!(cache.get(&key, &request).is_none());
+    Ok(())
+}
+
+#[test]
+fn refuses_an_admin_address_off_loopback() {
+    let cli = CliArgs::parse_from(["test", "--admin-listen", "0.0.0.0:8897"]);
+    assert!(Config::from_cli(&cli).is_err());
+}
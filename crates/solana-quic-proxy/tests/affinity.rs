@@ -0,0 +1,87 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use clap::Parser;
+use common::{ca_file, install_crypto_provider, spawn_upstream, test_ca};
+use solana_quic_proxy::{
+    affinity::Affinity,
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    upstream::UpstreamPool,
+};
+use tokio::time::{timeout, Instant};
+
+const RESPONSE_A: &[u8] = br#"{"jsonrpc":"2.0","result":"a","id":1}"#;
+const RESPONSE_B: &[u8] = br#"{"jsonrpc":"2.0","result":"b","id":1}"#;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn pins_a_session_to_the_upstream_that_answered_it() -> Result<()> {
+    install_crypto_provider();
+    let ca = test_ca()?;
+    let a = spawn_upstream(&ca, |_| RESPONSE_A.to_vec())?.addr;
+    let b = spawn_upstream(&ca, |_| RESPONSE_B.to_vec())?.addr;
+    let ca_file = ca_file(&ca)?;
+
+    let cli = CliArgs::parse_from([
+        "test",
+        "--upstream",
+        &a.to_string(),
+        "--upstream",
+        &b.to_string(),
+        "--server-name",
+        "localhost",
+        "--ca-cert",
+        ca_file.path().to_str().expect("temp path utf8"),
+        "--affinity-ttl-ms",
+        "60000",
+    ]);
+    let config = Arc::new(Config::from_cli(&cli)?);
+    let pool = UpstreamPool::new(config, Arc::new(ProxyMetrics::new()?))?;
+
+    let mut answers = Vec::new();
+    for session in [
+        None,
+        None,
+        Some("alice"),
+        None,
+        Some("alice"),
+        Some("alice"),
+    ] {
+        let request = pool.request(b"{}", false, session);
+        let response = timeout(Duration::from_secs(5), request).await??;
+        answers.push(response.payload);
+    }
+    // Requests without a session alternate; "alice" keeps its first upstream
+    assert_ne!(answers[0], answers[1]);
+    assert_ne!(answers[2], answers[3]);
+    assert_eq!(answers[2], answers[4]);
+    assert_eq!(answers[2], answers[5]);
+    Ok(())
+}
+
+#[test]
+fn forgets_a_session_after_its_ttl() {
+    let affinity = Affinity::new(Duration::from_secs(10));
+    let now = Instant::now();
+    affinity.pin("alice", "10.0.0.1:8899", now);
+    assert_eq!(
+        affinity
+            .get("alice", now + Duration::from_secs(9))
+            .as_deref(),
+        Some("10.0.0.1:8899")
+    );
+    assert_eq!(affinity.get("alice", now + Duration::from_secs(10)), None);
+    assert_eq!(affinity.get("bob", now), None);
+
+    // Repinning moves the session and restarts its TTL
+    affinity.pin("alice", "10.0.0.2:8899", now + Duration::from_secs(5));
+    assert_eq!(
+        affinity
+            .get("alice", now + Duration::from_secs(14))
+            .as_deref(),
+        Some("10.0.0.2:8899")
+    );
+}
@@ -0,0 +1,63 @@
+// Numan Thabit 2025
+use anyhow::Result;
+use bytes::Bytes;
+use solana_quic_proxy::batch::Batch;
+
+#[test]
+fn answers_each_request_in_order_with_rejections_in_place() -> Result<()> {
+    let body = br#"[
+        {"jsonrpc":"2.0","id":1,"method":"getSlot"},
+        {"jsonrpc":"2.0","id":"two","method":"getBalance","params":["x"]},
+        {"jsonrpc":"2.0","method":"getHealth"},
+        42,
+        {"jsonrpc":"2.0","id":3,"method":"getVersion"}
+    ]"#;
+    let mut batch = Batch::parse(body).expect("a batch");
+    assert_eq!(batch.len(), 5);
+    assert!(batch.items()[2].is_notification());
+    assert!(batch.items()[3].request.is_none());
+
+    batch.items_mut()[1].reject(-32005, "rate limit exceeded");
+    batch.items_mut()[3].reject(-32600, "invalid request");
+    let forwarded = [0, 2, 4];
+    assert_eq!(
+        &batch.encode(&forwarded)[..],
+        br#"[{"jsonrpc":"2.0","id":1,"method":"getSlot"},{"jsonrpc":"2.0","method":"getHealth"},{"jsonrpc":"2.0","id":3,"method":"getVersion"}]"#
+    );
+
+    // Upstream answers out of order and leaves nothing for the notification
+    batch.fill(
+        &forwarded,
+        br#"[{"jsonrpc":"2.0","result":{"solana-core":"2.0.0"},"id":3},{"jsonrpc":"2.0","result":9,"id":1}]"#,
+    );
+    let response = batch.response().expect("non-notifications answered");
+    let expected = concat!(
+        r#"[{"jsonrpc":"2.0","result":9,"id":1},"#,
+        r#"{"jsonrpc":"2.0","error":{"code":-32005,"message":"rate limit exceeded"},"id":"two"},"#,
+        r#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"invalid request"},"id":null},"#,
+        r#"{"jsonrpc":"2.0","result":{"solana-core":"2.0.0"},"id":3}]"#
+    );
+    assert_eq!(std::str::from_utf8(&response)?, expected);
+    Ok(())
+}
+
+#[test]
+fn errors_requests_the_upstream_left_unanswered() -> Result<()> {
+    let body = br#"[{"jsonrpc":"2.0","id":1,"method":"getSlot"},{"jsonrpc":"2.0","id":2,"method":"getSlot"}]"#;
+    let mut batch = Batch::parse(body).expect("a batch");
+    batch.fill(
+        &[0, 1],
+        br#"{"jsonrpc":"2.0","error":{"code":-32600,"message":"no"},"id":null}"#,
+    );
+    let response = batch.response().expect("answered");
+    let items: Vec<serde_json::Value> = serde_json::from_slice(&response)?;
+    assert_eq!(items.len(), 2);
+    assert!(items.iter().all(|item| item["error"]["code"] == -32603));
+
+    let mut notifications =
+        Batch::parse(br#"[{"jsonrpc":"2.0","method":"getHealth"}]"#).expect("a batch");
+    notifications.items_mut()[0].respond(Bytes::from_static(b"{}"));
+    assert!(notifications.response().is_none());
+    assert!(Batch::parse(br#"{"jsonrpc":"2.0","id":1,"method":"getSlot"}"#).is_none());
+    Ok(())
+}
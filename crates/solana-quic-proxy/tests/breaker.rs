@@ -0,0 +1,72 @@
+// Numan Thabit 2025
+use std::time::Duration;
+
+use solana_quic_proxy::{
+    breaker::{CircuitBreaker, CircuitState},
+    config::BreakerConfig,
+};
+use tokio::time::Instant;
+
+const FAST: Duration = Duration::from_millis(1);
+
+fn breaker(slow_after: Option<Duration>) -> CircuitBreaker {
+    CircuitBreaker::new(BreakerConfig {
+        window: 4,
+        threshold: 0.5,
+        slow_after,
+        open_for: Duration::from_millis(50),
+    })
+}
+
+#[test]
+fn opens_on_error_ratio_once_the_window_is_full() {
+    let breaker = breaker(None);
+    assert_eq!(breaker.record(true, FAST), None);
+    assert_eq!(breaker.record(true, FAST), None);
+    assert_eq!(breaker.record(false, FAST), None);
+    assert_eq!(breaker.state(), CircuitState::Closed);
+    assert_eq!(breaker.record(false, FAST), Some(CircuitState::Open));
+    assert!(!breaker.available(Instant::now()));
+    assert!(!breaker.acquire(Instant::now()));
+}
+
+#[test]
+fn stays_closed_below_the_threshold() {
+    let breaker = breaker(None);
+    for failed in [true, false, false, false, false, true, false, false] {
+        assert_eq!(breaker.record(failed, FAST), None);
+    }
+    assert_eq!(breaker.state(), CircuitState::Closed);
+}
+
+#[test]
+fn half_opens_with_a_single_trial() {
+    let breaker = breaker(None);
+    for _ in 0..4 {
+        breaker.record(true, FAST);
+    }
+    let later = Instant::now() + Duration::from_millis(60);
+    assert!(breaker.available(later));
+    assert!(breaker.acquire(later));
+    assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    assert!(!breaker.available(later));
+    assert!(!breaker.acquire(later));
+
+    // A failed trial opens the circuit again; a successful one closes it
+    assert_eq!(breaker.record(true, FAST), Some(CircuitState::Open));
+    let later = Instant::now() + Duration::from_millis(60);
+    assert!(breaker.acquire(later));
+    assert_eq!(breaker.record(false, FAST), Some(CircuitState::Closed));
+    assert!(breaker.available(Instant::now()));
+    assert_eq!(breaker.record(true, FAST), None);
+}
+
+#[test]
+fn opens_on_slow_responses() {
+    let breaker = breaker(Some(Duration::from_millis(100)));
+    let slow = Duration::from_millis(200);
+    assert_eq!(breaker.record(false, slow), None);
+    assert_eq!(breaker.record(false, FAST), None);
+    assert_eq!(breaker.record(false, FAST), None);
+    assert_eq!(breaker.record(false, slow), Some(CircuitState::Open));
+}
@@ -0,0 +1,67 @@
+// Numan Thabit 2025
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use solana_quic_proxy::{cache::ResponseCache, metrics::ProxyMetrics, rpc::RpcRequest};
+
+#[tokio::test]
+async fn serves_successes_under_the_callers_id_until_they_expire() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let ttls = HashMap::from([
+        ("getVersion".to_string(), Duration::from_secs(60)),
+        ("getLatestBlockhash".to_string(), Duration::from_millis(20)),
+    ]);
+    let cache = ResponseCache::new(ttls, 16, metrics.clone());
+
+    let first = br#"{"jsonrpc":"2.0","id":1,"method":"getVersion"}"#;
+    let request = RpcRequest::parse(first).expect("valid request");
+    let key = cache.key(&request).expect("getVersion is cached");
+    assert!(cache.get(&key, &request).is_none());
+    cache.insert(
+        &key,
+        br#"{"jsonrpc":"2.0","result":{"solana-core":"2.0.0"},"id":1}"#,
+    );
+
+    let second = br#"{"jsonrpc":"2.0","id":"abc","method":"getVersion"}"#;
+    let request = RpcRequest::parse(second).expect("valid request");
+    let key = cache.key(&request).expect("getVersion is cached");
+    let hit = cache.get(&key, &request).expect("cached");
+    assert_eq!(
+        &hit[..],
+        br#"{"jsonrpc":"2.0","result":{"solana-core":"2.0.0"},"id":"abc"}"#
+    );
+
+    // Different params are a different entry; errors are never kept
+    let blockhash = |commitment: &str| {
+        format!(
+            r#"{{"jsonrpc":"2.0","id":2,"method":"getLatestBlockhash","params":[{{"commitment":"{commitment}"}}]}}"#
+        )
+    };
+    let finalized = blockhash("finalized");
+    let request = RpcRequest::parse(finalized.as_bytes()).expect("valid request");
+    let key = cache.key(&request).expect("getLatestBlockhash is cached");
+    cache.insert(
+        &key,
+        br#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"busy"},"id":2}"#,
+    );
+    assert!(cache.get(&key, &request).is_none());
+    cache.insert(&key, br#"{"jsonrpc":"2.0","result":"hash","id":2}"#);
+    assert!(cache.get(&key, &request).is_some());
+    let processed = blockhash("processed");
+    let other = RpcRequest::parse(processed.as_bytes()).expect("valid request");
+    assert!(cache
+        .get(&cache.key(&other).expect("cached"), &other)
+        .is_none());
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert!(cache.get(&key, &request).is_none());
+
+    let uncached = br#"{"jsonrpc":"2.0","id":3,"method":"getBalance","params":["x"]}"#;
+    assert!(cache
+        .key(&RpcRequest::parse(uncached).expect("valid request"))
+        .is_none());
+
+    let rendered = metrics.render()?;
+    assert!(rendered
+        .contains(r#"solana_quic_proxy_cache_lookups_total{method="getVersion",result="hit"} 1"#));
+    Ok(())
+}
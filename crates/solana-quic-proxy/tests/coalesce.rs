@@ -0,0 +1,60 @@
+// Numan Thabit 2025
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use solana_quic_proxy::{
+    client::ClientResponse, coalesce::Coalescer, metrics::ProxyMetrics, rpc::RpcRequest,
+};
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn identical_requests_share_one_upstream_call() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let never = HashSet::from(["sendTransaction".to_string()]);
+    let coalescer = Arc::new(Coalescer::new(never, metrics.clone()));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let waiters = (0..5).map(|id| {
+        let coalescer = coalescer.clone();
+        let calls = calls.clone();
+        tokio::spawn(async move {
+            let body = format!(r#"{{"jsonrpc":"2.0","id":{id},"method":"getSlot"}}"#);
+            let request = RpcRequest::parse(body.as_bytes()).expect("valid request");
+            let key = coalescer.key(&request);
+            coalescer
+                .run(key, request.id, || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    Ok(ClientResponse {
+                        payload: Bytes::from_static(br#"{"jsonrpc":"2.0","result":42,"id":0}"#),
+                        latency: Duration::from_millis(100),
+                        upstream: Arc::from("127.0.0.1:8899"),
+                    })
+                })
+                .await
+                .map(|response| (id, response.payload))
+        })
+    });
+    for waiter in futures::future::join_all(waiters).await {
+        let (id, payload) = waiter?.map_err(|err| anyhow::anyhow!("{err}"))?;
+        let expected = format!(r#"{{"jsonrpc":"2.0","result":42,"id":{id}}}"#);
+        assert_eq!(&payload[..], expected.as_bytes());
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert!(metrics
+        .render()?
+        .contains("solana_quic_proxy_coalesced_requests_total 4"));
+
+    let send = br#"{"jsonrpc":"2.0","id":1,"method":"sendTransaction","params":["tx"]}"#;
+    assert!(coalescer
+        .key(&RpcRequest::parse(send).expect("valid request"))
+        .is_none());
+    Ok(())
+}
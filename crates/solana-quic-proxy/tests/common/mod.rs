@@ -0,0 +1,104 @@
+// Numan Thabit 2025
+//! Setup shared by the integration tests: the rustls provider, a throwaway CA
+//! and mock QUIC upstreams.
+#![allow(dead_code)]
+
+use std::{
+    io::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex, Once},
+};
+
+use anyhow::Result;
+use quinn::crypto::rustls::QuicServerConfig;
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use tempfile::NamedTempFile;
+
+pub fn install_crypto_provider() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        rustls::crypto::ring::default_provider()
+            .install_default()
+            .expect("install ring crypto provider");
+    });
+}
+
+/// A fresh CA to sign upstream certificates with.
+pub fn test_ca() -> Result<Certificate> {
+    let mut ca_params = CertificateParams::default();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Ok(Certificate::from_params(ca_params)?)
+}
+
+/// `ca` as a PEM bundle for `--ca-cert`.
+pub fn ca_file(ca: &Certificate) -> Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(ca.serialize_pem()?.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// A mock QUIC upstream.
+pub struct Upstream {
+    pub addr: SocketAddr,
+    /// Every connection accepted so far, in order.
+    pub accepted: Arc<Mutex<Vec<quinn::Connection>>>,
+}
+
+/// Start a QUIC upstream for `localhost` with a certificate signed by `ca`.
+/// Each request frame is answered with one frame holding `reply(n)`, `n`
+/// being the number (from 1) of the connection the request came in on.
+pub fn spawn_upstream(
+    ca: &Certificate,
+    reply: impl Fn(usize) -> Vec<u8> + Send + Sync + 'static,
+) -> Result<Upstream> {
+    let server_cert = Certificate::from_params(CertificateParams::new(["localhost".into()]))?;
+    let cert_der =
+        quinn::rustls::pki_types::CertificateDer::from(server_cert.serialize_der_with_signer(ca)?);
+    let key_der =
+        quinn::rustls::pki_types::PrivatePkcs8KeyDer::from(server_cert.serialize_private_key_der());
+
+    let mut tls_config = quinn::rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_der], key_der.into())?;
+    tls_config.alpn_protocols = vec![b"jsonrpc-quic".to_vec()];
+    let server_config =
+        quinn::ServerConfig::with_crypto(Arc::new(QuicServerConfig::try_from(tls_config)?));
+    let endpoint = quinn::Endpoint::server(server_config, "127.0.0.1:0".parse()?)?;
+    let addr = endpoint.local_addr()?;
+
+    let accepted = Arc::new(Mutex::new(Vec::new()));
+    let connections = accepted.clone();
+    let reply = Arc::new(reply);
+    tokio::spawn(async move {
+        while let Some(incoming) = endpoint.accept().await {
+            let (connections, reply) = (connections.clone(), reply.clone());
+            tokio::spawn(async move {
+                let Ok(conn) = incoming.await else { return };
+                let number = {
+                    let mut connections = connections.lock().unwrap();
+                    connections.push(conn.clone());
+                    connections.len()
+                };
+                while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+                    let reply = reply.clone();
+                    tokio::spawn(async move {
+                        let mut header = [0u8; 4];
+                        if recv.read_exact(&mut header).await.is_err() {
+                            return;
+                        }
+                        let mut body = vec![0u8; u32::from_be_bytes(header) as usize];
+                        if recv.read_exact(&mut body).await.is_err() {
+                            return;
+                        }
+                        let response = reply(number);
+                        let _ = send.write_all(&(response.len() as u32).to_be_bytes()).await;
+                        let _ = send.write_all(&response).await;
+                        let _ = send.finish();
+                    });
+                }
+            });
+        }
+    });
+    Ok(Upstream { addr, accepted })
+}
@@ -0,0 +1,123 @@
+// Numan Thabit 2025
+use std::{io::Read, sync::Arc};
+
+use anyhow::Result;
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, VARY},
+        HeaderValue, StatusCode,
+    },
+    response::Response,
+};
+use solana_quic_proxy::{
+    compress::{Compression, Encoding},
+    metrics::ProxyMetrics,
+};
+
+fn json(body: Body) -> Response {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .expect("valid response")
+}
+
+fn accounts(count: usize) -> Vec<u8> {
+    let item = r#"{"pubkey":"11111111111111111111111111111111","account":{"lamports":1}}"#;
+    format!(
+        r#"{{"jsonrpc":"2.0","result":[{}],"id":1}}"#,
+        vec![item; count].join(",")
+    )
+    .into_bytes()
+}
+
+async fn body_bytes(response: Response) -> Result<Bytes> {
+    Ok(axum::body::to_bytes(response.into_body(), usize::MAX).await?)
+}
+
+#[test]
+fn negotiates_the_encoding_the_client_prefers() {
+    assert_eq!(
+        Encoding::negotiate("gzip, deflate, br"),
+        Some(Encoding::Brotli)
+    );
+    assert_eq!(Encoding::negotiate("br;q=0.5, gzip"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("*;q=0.1"), Some(Encoding::Brotli));
+    assert_eq!(
+        Encoding::negotiate("gzip;q=0, *;q=0.3"),
+        Some(Encoding::Brotli)
+    );
+    assert_eq!(Encoding::negotiate("deflate, identity"), None);
+    assert_eq!(Encoding::negotiate(""), None);
+}
+
+#[tokio::test]
+async fn compresses_buffered_json_above_the_minimum() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let compression = Compression::new(1024, metrics.clone());
+    let payload = accounts(200);
+    let gzip = HeaderValue::from_static("gzip");
+
+    let response = json(Body::from(payload.clone()));
+    let response = compression.apply(Some(&gzip), response).await;
+    assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+    assert_eq!(response.headers()[VARY], "accept-encoding");
+    let length: usize = response.headers()[CONTENT_LENGTH].to_str()?.parse()?;
+    let compressed = body_bytes(response).await?;
+    assert_eq!(compressed.len(), length);
+    assert!(compressed.len() < payload.len());
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(&compressed[..]).read_to_end(&mut decoded)?;
+    assert_eq!(decoded, payload);
+
+    // Small responses and clients without a supported encoding go as is
+    let small = json(Body::from(accounts(1)));
+    let small = compression.apply(Some(&gzip), small).await;
+    assert!(small.headers().get(CONTENT_ENCODING).is_none());
+    let plain = compression
+        .apply(None, json(Body::from(payload.clone())))
+        .await;
+    assert!(plain.headers().get(CONTENT_ENCODING).is_none());
+    assert_eq!(body_bytes(plain).await?, payload);
+
+    let rendered = metrics.render()?;
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_compressed_responses_total{encoding="gzip"} 1"#),
+        "{rendered}"
+    );
+    let saved = (payload.len() - length).to_string();
+    assert!(
+        rendered.contains(&format!(
+            r#"solana_quic_proxy_compression_saved_bytes_total{{encoding="gzip"}} {saved}"#
+        )),
+        "{rendered}"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn compresses_streamed_json_chunk_by_chunk() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let compression = Compression::new(1024, metrics);
+    let payload = accounts(2000);
+    let chunks: Vec<Result<Bytes, std::io::Error>> = payload
+        .chunks(4096)
+        .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+        .collect();
+    let mut response = json(Body::from_stream(futures::stream::iter(chunks)));
+    response
+        .headers_mut()
+        .insert(CONTENT_LENGTH, payload.len().into());
+
+    let accept = HeaderValue::from_static("gzip;q=0.8, br");
+    let response = compression.apply(Some(&accept), response).await;
+    assert_eq!(response.headers()[CONTENT_ENCODING], "br");
+    assert!(response.headers().get(CONTENT_LENGTH).is_none());
+    let compressed = body_bytes(response).await?;
+    let mut decoded = Vec::new();
+    brotli::Decompressor::new(&compressed[..], 4096).read_to_end(&mut decoded)?;
+    assert_eq!(decoded, payload);
+    Ok(())
+}
@@ -0,0 +1,94 @@
+// Numan Thabit 2025
+use std::{io::Write, net::IpAddr, sync::Arc};
+
+use anyhow::Result;
+use clap::Parser;
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    filter::{Rejection, RequestFilter},
+    metrics::ProxyMetrics,
+    rpc::RpcRequest,
+};
+use tempfile::NamedTempFile;
+
+fn check(filter: &RequestFilter, body: &str) -> Result<(), Rejection> {
+    filter.check(RpcRequest::parse(body.as_bytes()).as_ref())
+}
+
+#[test]
+fn filters_clients_by_network_with_deny_winning() -> Result<()> {
+    let cli = CliArgs::parse_from([
+        "test",
+        "--ip-allow",
+        "10.0.0.0/8,192.168.1.7",
+        "--ip-deny",
+        "10.6.0.0/16",
+    ]);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let filter = RequestFilter::new(&Config::from_cli(&cli)?, metrics.clone());
+    let ip = |s: &str| s.parse::<IpAddr>();
+
+    assert!(filter.allows(ip("10.1.2.3")?));
+    assert!(filter.allows(ip("192.168.1.7")?));
+    assert!(filter.allows(ip("::ffff:10.1.2.3")?));
+    assert!(!filter.allows(ip("192.168.1.8")?));
+    assert!(!filter.allows(ip("10.6.0.1")?));
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_filtered_requests_total{reason="ip_denied"} 2"#));
+    assert!(CliArgs::try_parse_from(["test", "--ip-allow", "10.0.0.0/33"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn rejects_malformed_requests_and_unlisted_methods() -> Result<()> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(
+        br#"
+allowed_methods = ["getSlot", "getAccountInfo"]
+max_params_bytes = 32
+"#,
+    )?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))?;
+    let filter = RequestFilter::new(&config, Arc::new(ProxyMetrics::new()?));
+
+    assert_eq!(
+        check(&filter, r#"{"jsonrpc":"2.0","id":1,"method":"getSlot"}"#),
+        Ok(())
+    );
+    assert_eq!(
+        check(&filter, r#"{"jsonrpc":"1.0","id":1,"method":"getSlot"}"#),
+        Err(Rejection::Invalid)
+    );
+    assert_eq!(
+        check(&filter, r#"{"id":1,"method":"getSlot","params":"x"}"#),
+        Err(Rejection::Invalid)
+    );
+    assert_eq!(check(&filter, "not json"), Err(Rejection::Invalid));
+    assert_eq!(
+        check(
+            &filter,
+            r#"{"jsonrpc":"2.0","id":1,"method":"getProgramAccounts"}"#
+        ),
+        Err(Rejection::MethodNotAllowed)
+    );
+    let long = format!(
+        r#"{{"jsonrpc":"2.0","id":1,"method":"getAccountInfo","params":["{}"]}}"#,
+        "1".repeat(44)
+    );
+    assert_eq!(check(&filter, &long), Err(Rejection::ParamsTooLarge));
+
+    // Without validation malformed requests pass; the method list still applies
+    let config = Config::from_cli(&CliArgs::parse_from([
+        "test",
+        "--config",
+        path,
+        "--no-validate-requests",
+    ]))?;
+    let filter = RequestFilter::new(&config, Arc::new(ProxyMetrics::new()?));
+    assert_eq!(check(&filter, r#"{"id":1,"method":"getSlot"}"#), Ok(()));
+    assert_eq!(check(&filter, "not json"), Err(Rejection::Invalid));
+    Ok(())
+}
@@ -0,0 +1,39 @@
+// Numan Thabit 2025
+use std::time::Duration;
+
+use anyhow::Result;
+use solana_quic_proxy::metrics::ProxyMetrics;
+
+#[test]
+fn labels_requests_by_method_and_caps_distinct_methods() -> Result<()> {
+    let metrics = ProxyMetrics::new()?;
+    let latency = Duration::from_millis(3);
+    metrics.record_success("getSlot", latency, latency, 60, 40);
+    metrics.record_success("getSlot", latency, latency, 60, 40);
+    metrics.record_success("getProgramAccounts", latency, latency, 200, 5_000_000);
+    metrics.record_failure("getBlock");
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_method_requests_total{method="getSlot"} 2"#));
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_method_requests_failed_total{method="getBlock"} 1"#)
+    );
+    assert!(rendered.contains(
+        r#"solana_quic_proxy_method_response_bytes_sum{method="getProgramAccounts"} 5000000"#
+    ));
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_method_latency_seconds_count{method="getSlot"} 2"#)
+    );
+    assert!(rendered.contains("solana_quic_proxy_requests_total 3"));
+
+    // Made-up methods beyond the label budget share one series
+    for i in 0..200 {
+        metrics.record_failure(&format!("bogus{i}"));
+    }
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"method_requests_failed_total{method="bogus0"} 1"#));
+    assert!(!rendered.contains(r#"method="bogus199""#));
+    assert!(rendered.contains(r#"method_requests_failed_total{method="other"}"#));
+    assert!(rendered.contains(r#"method_requests_total{method="getSlot"} 2"#));
+    Ok(())
+}
@@ -1,13 +1,11 @@
 // Numan Thabit 2025
-use std::{
-    io::Write,
-    net::SocketAddr,
-    sync::{Arc, Once},
-    time::Duration,
-};
+mod common;
+
+use std::{io::Write, net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use clap::Parser;
+use common::install_crypto_provider;
 use quinn::crypto::rustls::QuicServerConfig;
 use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
 use solana_quic_proxy::{
@@ -18,15 +16,6 @@ use solana_quic_proxy::{
 use tempfile::NamedTempFile;
 use tokio::time::timeout;
 
-fn install_crypto_provider() {
-    static INIT: Once = Once::new();
-    INIT.call_once(|| {
-        rustls::crypto::ring::default_provider()
-            .install_default()
-            .expect("install ring crypto provider");
-    });
-}
-
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn eager_preconnect_succeeds() -> Result<()> {
     install_crypto_provider();
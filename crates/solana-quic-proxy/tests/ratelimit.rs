@@ -0,0 +1,134 @@
+// Numan Thabit 2025
+use std::{
+    io::Write,
+    net::{IpAddr, Ipv6Addr},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use clap::Parser;
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    ratelimit::{RateLimiter, Verdict, MAX_CLIENTS},
+};
+use tempfile::NamedTempFile;
+
+#[test]
+fn limits_each_client_by_key_or_ip_with_method_weights() -> Result<()> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(
+        br#"
+rate_limit_rps = 0.001
+rate_limit_burst = 10
+
+[[api_keys]]
+key = "secret"
+name = "dashboards"
+rps = 0.001
+burst = 3
+"#,
+    )?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))?;
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let limiter = RateLimiter::new(&config, metrics.clone());
+    let alice: IpAddr = "10.0.0.1".parse()?;
+    let bob: IpAddr = "10.0.0.2".parse()?;
+
+    // getProgramAccounts costs 10: one call drains an anonymous client's bucket
+    assert_eq!(
+        limiter.check(None, alice, Some("getProgramAccounts")),
+        Verdict::Allow
+    );
+    assert_eq!(
+        limiter.check(None, alice, Some("getSlot")),
+        Verdict::Limited
+    );
+    assert_eq!(limiter.check(None, bob, Some("getSlot")), Verdict::Allow);
+
+    // The key has its own bucket, whichever IP it comes from
+    for _ in 0..3 {
+        assert_eq!(
+            limiter.check(Some("secret"), alice, Some("getSlot")),
+            Verdict::Allow
+        );
+    }
+    assert_eq!(
+        limiter.check(Some("secret"), bob, Some("getSlot")),
+        Verdict::Limited
+    );
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_client_requests_total{client="dashboards"} 4"#));
+    assert!(rendered.contains(r#"solana_quic_proxy_client_requests_total{client="anonymous"} 3"#));
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_client_rate_limited_total{client="dashboards"} 1"#)
+    );
+    Ok(())
+}
+
+#[test]
+fn rejects_unknown_keys_when_one_is_required() -> Result<()> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(
+        br#"
+require_api_key = true
+
+[[api_keys]]
+key = "secret"
+name = "dashboards"
+"#,
+    )?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))?;
+    let limiter = RateLimiter::new(&config, Arc::new(ProxyMetrics::new()?));
+    let ip: IpAddr = "10.0.0.1".parse()?;
+
+    assert_eq!(limiter.check(None, ip, None), Verdict::Unauthorized);
+    assert_eq!(
+        limiter.check(Some("guess"), ip, None),
+        Verdict::Unauthorized
+    );
+    assert_eq!(limiter.check(Some("secret"), ip, None), Verdict::Allow);
+    Ok(())
+}
+
+#[test]
+fn new_ips_share_one_bucket_once_the_map_is_full() -> Result<()> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(
+        br#"
+rate_limit_rps = 0.001
+rate_limit_burst = 1
+
+[[api_keys]]
+key = "secret"
+name = "dashboards"
+"#,
+    )?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))?;
+    let limiter = RateLimiter::new(&config, Arc::new(ProxyMetrics::new()?));
+    let ip = |n: usize| IpAddr::V6(Ipv6Addr::from(n as u128));
+
+    // Drained buckets survive the sweep, so the map fills up
+    for n in 0..MAX_CLIENTS {
+        assert_eq!(limiter.check(None, ip(n), None), Verdict::Allow);
+    }
+    assert_eq!(limiter.check(None, ip(MAX_CLIENTS), None), Verdict::Allow);
+    assert_eq!(
+        limiter.check(None, ip(MAX_CLIENTS + 1), None),
+        Verdict::Limited
+    );
+    // Tracked IPs and known keys keep their own buckets
+    assert_eq!(limiter.check(None, ip(0), None), Verdict::Limited);
+    assert_eq!(
+        limiter.check(Some("secret"), ip(MAX_CLIENTS + 2), None),
+        Verdict::Allow
+    );
+    Ok(())
+}
@@ -0,0 +1,114 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use axum::{extract::State, http::StatusCode, routing::post, Router};
+use clap::Parser;
+use common::install_crypto_provider;
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    retry::{Attempts, RetryPolicy},
+    route::{RequestContext, Routes},
+};
+use tempfile::NamedTempFile;
+
+#[test]
+fn never_retries_or_hedges_transaction_submission() -> Result<()> {
+    let config = Config::from_cli(&CliArgs::parse_from([
+        "test",
+        "--max-retries",
+        "3",
+        "--retry-backoff-ms",
+        "10",
+    ]))?;
+    let policy = RetryPolicy::new(&config);
+    let once = Attempts {
+        retries: 0,
+        hedge: false,
+    };
+    assert_eq!(policy.attempts(Some("sendTransaction")), once);
+    assert_eq!(policy.attempts(None), once);
+    assert_eq!(
+        policy.attempts(Some("getSlot")),
+        Attempts {
+            retries: 3,
+            hedge: true
+        }
+    );
+    assert_eq!(policy.backoff(1), Duration::from_millis(10));
+    assert_eq!(policy.backoff(3), Duration::from_millis(40));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn retries_transient_failures_but_sends_transactions_once() -> Result<()> {
+    install_crypto_provider();
+    // Every other request fails with 503, starting with the first
+    let hits = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route(
+            "/",
+            post(|State(hits): State<Arc<AtomicUsize>>| async move {
+                if hits.fetch_add(1, Ordering::SeqCst) % 2 == 0 {
+                    Err(StatusCode::SERVICE_UNAVAILABLE)
+                } else {
+                    Ok(r#"{"jsonrpc":"2.0","result":7,"id":1}"#)
+                }
+            }),
+        )
+        .with_state(hits.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let mut file = NamedTempFile::new()?;
+    write!(
+        file,
+        r#"
+upstream = "127.0.0.1:9"
+lazy_connect = true
+retry_backoff_ms = 1
+
+[[routes]]
+name = "http"
+methods = ["getSlot", "sendTransaction"]
+url = "http://{addr}/"
+"#
+    )?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Arc::new(Config::from_cli(&CliArgs::parse_from([
+        "test", "--config", path,
+    ]))?);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let routes = Routes::new(config, metrics.clone())?;
+
+    let response = routes
+        .request(Some("getSlot"), b"{}", RequestContext::default())
+        .await?;
+    assert_eq!(
+        &response.payload[..],
+        br#"{"jsonrpc":"2.0","result":7,"id":1}"#
+    );
+    assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+    assert!(routes
+        .request(Some("sendTransaction"), b"{}", RequestContext::default())
+        .await
+        .is_err());
+    assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_retries_total{route="http"} 1"#));
+    Ok(())
+}
@@ -0,0 +1,102 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use axum::{http::HeaderMap, routing::post, Router};
+use clap::Parser;
+use common::install_crypto_provider;
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    route::{RequestContext, Routes},
+};
+use tempfile::NamedTempFile;
+
+const RESPONSE: &str = r#"{"jsonrpc":"2.0","result":[],"id":1}"#;
+
+fn config_file(contents: &str) -> Result<NamedTempFile> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(contents.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn routed_methods_reach_their_own_upstream() -> Result<()> {
+    install_crypto_provider();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let archive = listener.local_addr()?;
+    let seen_id = Arc::new(Mutex::new(None));
+    let seen = seen_id.clone();
+    let app = Router::new().route(
+        "/",
+        post(|headers: HeaderMap| async move {
+            *seen.lock().unwrap() = headers.get("x-request-id").cloned();
+            RESPONSE
+        }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let file = config_file(&format!(
+        r#"
+upstream = "127.0.0.1:9"
+lazy_connect = true
+
+[[routes]]
+name = "archive"
+methods = ["getProgramAccounts"]
+url = "http://{archive}/"
+"#
+    ))?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let config = Arc::new(Config::from_cli(&CliArgs::parse_from([
+        "test", "--config", path,
+    ]))?);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let routes = Routes::new(config, metrics.clone())?;
+
+    let body = br#"{"jsonrpc":"2.0","id":1,"method":"getProgramAccounts","params":["x"]}"#;
+    let context = RequestContext {
+        request_id: Some("req-1"),
+        ..RequestContext::default()
+    };
+    let response = routes
+        .request(Some("getProgramAccounts"), body, context)
+        .await?;
+    assert_eq!(&response.payload[..], RESPONSE.as_bytes());
+    assert_eq!(&*response.upstream, format!("http://{archive}/"));
+    let seen = seen_id.lock().unwrap().clone();
+    assert_eq!(seen.as_ref().map(|id| id.as_bytes()), Some(&b"req-1"[..]));
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_route_requests_total{route="archive"} 1"#));
+    assert!(!rendered.contains(r#"route="default""#));
+    Ok(())
+}
+
+#[test]
+fn rejects_a_method_routed_twice() -> Result<()> {
+    let file = config_file(
+        r#"
+[[routes]]
+name = "staked"
+methods = ["sendTransaction"]
+upstreams = [{ addr = "127.0.0.1:8001" }]
+
+[[routes]]
+name = "other"
+methods = ["sendTransaction"]
+url = "http://127.0.0.1:8899"
+"#,
+    )?;
+    let path = file.path().to_str().expect("temp path utf8");
+    let err = Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))
+        .expect_err("duplicate method");
+    assert!(err.to_string().contains("sendTransaction"), "{err}");
+    Ok(())
+}
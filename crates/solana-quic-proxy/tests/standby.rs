@@ -0,0 +1,72 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Result;
+use clap::Parser;
+use common::{ca_file, install_crypto_provider, spawn_upstream, test_ca, Upstream};
+use solana_quic_proxy::{
+    client::QuicRpcClient,
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+};
+use tokio::time::timeout;
+
+async fn wait_for_connections(accepted: &Mutex<Vec<quinn::Connection>>, count: usize) {
+    while accepted.lock().unwrap().len() < count {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn keeps_connections_open_and_rotates_across_them() -> Result<()> {
+    install_crypto_provider();
+    let ca = test_ca()?;
+    // Each request is answered with the number of the connection it came in on
+    let Upstream {
+        addr: upstream,
+        accepted,
+    } = spawn_upstream(&ca, |number| {
+        format!(r#"{{"jsonrpc":"2.0","result":{number},"id":1}}"#).into_bytes()
+    })?;
+    let ca_file = ca_file(&ca)?;
+    let cli = CliArgs::parse_from([
+        "test",
+        "--upstream",
+        &upstream.to_string(),
+        "--server-name",
+        "localhost",
+        "--ca-cert",
+        ca_file.path().to_str().expect("temp path utf8"),
+        "--connections-per-upstream",
+        "2",
+    ]);
+    let config = Arc::new(Config::from_cli(&cli)?);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let client = Arc::new(QuicRpcClient::new(config, metrics.clone())?);
+
+    client.spawn_standby();
+    timeout(Duration::from_secs(5), wait_for_connections(&accepted, 2)).await?;
+    let mut served = HashSet::new();
+    for _ in 0..4 {
+        let response = timeout(Duration::from_secs(5), client.request(b"{}", false)).await??;
+        served.insert(response.payload);
+    }
+    assert_eq!(served.len(), 2, "requests should use both connections");
+
+    // The upstream dropping a connection gets it replaced without a request
+    let first = accepted.lock().unwrap()[0].clone();
+    first.close(0u32.into(), b"idle");
+    timeout(Duration::from_secs(5), wait_for_connections(&accepted, 3)).await?;
+    let rendered = metrics.render()?;
+    assert!(
+        rendered.contains("solana_quic_proxy_upstream_reconnects_total 1"),
+        "{rendered}"
+    );
+    Ok(())
+}
@@ -0,0 +1,108 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use bytes::Bytes;
+use clap::Parser;
+use common::{ca_file, install_crypto_provider, spawn_upstream, test_ca};
+use futures::StreamExt;
+use solana_quic_proxy::{
+    client::ProxyError,
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    stream::ResponseStream,
+    upstream::UpstreamPool,
+};
+use tempfile::NamedTempFile;
+use tokio::time::timeout;
+
+const BODY_LEN: usize = 3 * 1024 * 1024;
+
+fn build_pool(
+    addr: SocketAddr,
+    ca_file: &NamedTempFile,
+    max_stream_bytes: usize,
+) -> Result<UpstreamPool> {
+    let cli = CliArgs::parse_from([
+        "test",
+        "--upstream",
+        &addr.to_string(),
+        "--server-name",
+        "localhost",
+        "--ca-cert",
+        ca_file.path().to_str().expect("temp path utf8"),
+        "--max-stream-bytes",
+        &max_stream_bytes.to_string(),
+    ]);
+    let config = Arc::new(Config::from_cli(&cli)?);
+    UpstreamPool::new(config, Arc::new(ProxyMetrics::new()?))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn relays_a_large_response_and_refuses_one_over_the_cap() -> Result<()> {
+    install_crypto_provider();
+    let ca = test_ca()?;
+    let addr = spawn_upstream(&ca, |_| vec![b'x'; BODY_LEN])?.addr;
+    let ca_file = ca_file(&ca)?;
+    let metrics = Arc::new(ProxyMetrics::new()?);
+
+    let pool = build_pool(addr, &ca_file, 64 * 1024 * 1024)?;
+    let response = timeout(Duration::from_secs(5), pool.request_stream(b"{}", None)).await??;
+    assert_eq!(response.len, Some(BODY_LEN));
+    let body = response.into_body(64 * 1024 * 1024, metrics.clone());
+    let body = timeout(
+        Duration::from_secs(5),
+        axum::body::to_bytes(body, usize::MAX),
+    )
+    .await??;
+    assert_eq!(body.len(), BODY_LEN);
+    assert!(body.iter().all(|&b| b == b'x'));
+
+    // A client that hangs up early stops the transfer
+    let response = timeout(Duration::from_secs(5), pool.request_stream(b"{}", None)).await??;
+    let mut body = response
+        .into_body(64 * 1024 * 1024, metrics.clone())
+        .into_data_stream();
+    let chunk = timeout(Duration::from_secs(5), body.next()).await?;
+    assert!(chunk.is_some_and(|chunk| chunk.is_ok()));
+    drop(body);
+
+    let rendered = metrics.render()?;
+    assert!(rendered.contains(r#"solana_quic_proxy_streamed_responses_total{result="complete"} 1"#));
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_streamed_responses_total{result="client_gone"} 1"#)
+    );
+
+    let capped = build_pool(addr, &ca_file, 1024 * 1024)?;
+    let result = timeout(Duration::from_secs(5), capped.request_stream(b"{}", None)).await?;
+    assert!(matches!(
+        result,
+        Err(ProxyError::ResponseTooLarge { size: BODY_LEN, .. })
+    ));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn cuts_off_a_response_of_unknown_length_at_the_cap() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let chunks = futures::stream::iter(
+        (0..4).map(|_| Ok::<_, ProxyError>(Bytes::from_static(b"0123456789"))),
+    );
+    let response = ResponseStream::new(
+        Arc::from("127.0.0.1:8899"),
+        None,
+        Duration::ZERO,
+        chunks.boxed(),
+    );
+    let result = axum::body::to_bytes(response.into_body(25, metrics.clone()), usize::MAX).await;
+    assert!(result.is_err());
+
+    let rendered = metrics.render()?;
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_streamed_responses_total{result="too_large"} 1"#)
+    );
+    assert!(rendered.contains("solana_quic_proxy_streamed_bytes_total 20"));
+    Ok(())
+}
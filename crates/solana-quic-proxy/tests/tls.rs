@@ -0,0 +1,87 @@
+// Numan Thabit 2025
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::Result;
+use axum::{routing::get, Router};
+use quinn::rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use solana_quic_proxy::{metrics::ProxyMetrics, tls::TlsConfig};
+use tempfile::TempDir;
+use tokio_rustls::TlsConnector;
+
+/// Write a `localhost` certificate signed by a fresh CA into `dir`; the CA PEM.
+fn issue(dir: &Path) -> Result<String> {
+    let mut ca_params = CertificateParams::default();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = Certificate::from_params(ca_params)?;
+    let cert = Certificate::from_params(CertificateParams::new(["localhost".into()]))?;
+    fs::write(dir.join("cert.pem"), cert.serialize_pem_with_signer(&ca)?)?;
+    fs::write(dir.join("key.pem"), cert.serialize_private_key_pem())?;
+    Ok(ca.serialize_pem()?)
+}
+
+/// The ALPN protocol negotiated with a client trusting only `ca_pem`; an
+/// error if the handshake fails.
+async fn handshake(addr: std::net::SocketAddr, ca_pem: &str) -> Result<Option<Vec<u8>>> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut ca_pem.as_bytes())? {
+        roots.add(cert.into())?;
+    }
+    let provider = Arc::new(quinn::rustls::crypto::ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let stream = tokio::net::TcpStream::connect(addr).await?;
+    let tls = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost")?, stream)
+        .await?;
+    Ok(tls.get_ref().1.alpn_protocol().map(<[u8]>::to_vec))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn serves_http2_and_picks_up_a_renewed_certificate() -> Result<()> {
+    let dir = TempDir::new()?;
+    let first_ca = issue(dir.path())?;
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let tls = Arc::new(TlsConfig::load(
+        &dir.path().join("cert.pem"),
+        &dir.path().join("key.pem"),
+        metrics.clone(),
+    )?);
+    assert!(!tls.reload_if_changed()?);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let app = Router::new().route("/", get(|| async { "ok" }));
+    tokio::spawn(solana_quic_proxy::tls::serve(
+        listener,
+        app,
+        tls.clone(),
+        std::future::pending(),
+    ));
+
+    assert_eq!(handshake(addr, &first_ca).await?, Some(b"h2".to_vec()));
+    let client = reqwest::Client::builder()
+        .add_root_certificate(reqwest::Certificate::from_pem(first_ca.as_bytes())?)
+        .build()?;
+    let body = client
+        .get(format!("https://localhost:{}/", addr.port()))
+        .send()
+        .await?
+        .text()
+        .await?;
+    assert_eq!(body, "ok");
+
+    let second_ca = issue(dir.path())?;
+    assert!(tls.reload_if_changed()?);
+    assert!(handshake(addr, &first_ca).await.is_err());
+    assert_eq!(handshake(addr, &second_ca).await?, Some(b"h2".to_vec()));
+
+    // A broken pair is refused and the current certificate stays
+    fs::write(dir.path().join("key.pem"), "not a key")?;
+    assert!(tls.reload_if_changed().is_err());
+    assert!(handshake(addr, &second_ca).await.is_ok());
+    Ok(())
+}
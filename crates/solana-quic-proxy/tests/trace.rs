@@ -0,0 +1,60 @@
+// Numan Thabit 2025
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::http::HeaderValue;
+use solana_quic_proxy::{
+    metrics::ProxyMetrics,
+    trace::{Completed, RequestIds, SlowLog},
+};
+
+#[test]
+fn keeps_usable_client_ids_and_mints_the_rest() -> Result<()> {
+    let ids = RequestIds::default();
+    let client = HeaderValue::from_static("trace-42");
+    assert_eq!(ids.assign(Some(&client)), "trace-42");
+
+    let first = ids.assign(None);
+    let spaced = HeaderValue::from_static("has spaces");
+    let second = ids.assign(Some(&spaced));
+    let long = HeaderValue::from_str(&"x".repeat(129))?;
+    let third = ids.assign(Some(&long));
+    assert_ne!(first, second);
+    assert_ne!(second, third);
+    // Minted IDs share the process prefix
+    let prefix = first.split_once('-').map(|(prefix, _)| prefix);
+    assert_eq!(prefix, second.split_once('-').map(|(prefix, _)| prefix));
+    Ok(())
+}
+
+#[test]
+fn counts_only_requests_over_the_threshold() -> Result<()> {
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let log = SlowLog::new(Some(Duration::from_millis(100)), metrics.clone());
+    let request = |total| Completed {
+        request_id: "req-1",
+        method: "getBlock",
+        upstream: Some("127.0.0.1:8899"),
+        total,
+        upstream_latency: Some(total),
+        request_bytes: 64,
+        response_bytes: 4096,
+    };
+    log.observe(&request(Duration::from_millis(50)));
+    log.observe(&request(Duration::from_millis(100)));
+    log.observe(&request(Duration::from_millis(250)));
+
+    let rendered = metrics.render()?;
+    assert!(
+        rendered.contains(r#"solana_quic_proxy_slow_requests_total{method="getBlock"} 1"#),
+        "{rendered}"
+    );
+
+    // Without a threshold nothing is slow
+    let off = SlowLog::new(None, metrics.clone());
+    off.observe(&request(Duration::from_secs(60)));
+    assert!(metrics
+        .render()?
+        .contains(r#"solana_quic_proxy_slow_requests_total{method="getBlock"} 1"#));
+    Ok(())
+}
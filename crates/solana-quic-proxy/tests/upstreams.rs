@@ -0,0 +1,64 @@
+// Numan Thabit 2025
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use clap::Parser;
+use common::{ca_file, install_crypto_provider, spawn_upstream, test_ca};
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    metrics::ProxyMetrics,
+    upstream::UpstreamPool,
+};
+use tokio::time::timeout;
+
+const RESPONSE: &[u8] = br#"{"jsonrpc":"2.0","result":"ok","id":1}"#;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn fails_over_and_ejects_an_unreachable_upstream() -> Result<()> {
+    install_crypto_provider();
+    let ca = test_ca()?;
+    let live = spawn_upstream(&ca, |_| RESPONSE.to_vec())?.addr;
+    let ca_file = ca_file(&ca)?;
+    // Bound but silent, so the QUIC handshake never completes
+    let silent = std::net::UdpSocket::bind("127.0.0.1:0")?;
+    let dead = silent.local_addr()?;
+
+    let cli = CliArgs::parse_from([
+        "test",
+        "--upstream",
+        &dead.to_string(),
+        "--upstream",
+        &format!("{live}@1"),
+        "--server-name",
+        "localhost",
+        "--ca-cert",
+        ca_file.path().to_str().expect("temp path utf8"),
+        "--max-idle-timeout-ms",
+        "300",
+        "--eject-after-failures",
+        "1",
+        "--hedged-attempts",
+        "1",
+    ]);
+    let config = Arc::new(Config::from_cli(&cli)?);
+    assert_eq!(config.upstreams.len(), 2);
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let pool = UpstreamPool::new(config, metrics.clone())?;
+
+    for _ in 0..4 {
+        let response = timeout(Duration::from_secs(5), pool.request(b"{}", false, None)).await??;
+        assert_eq!(&response.payload[..], RESPONSE);
+    }
+
+    let rendered = metrics.render()?;
+    let ejected = format!("solana_quic_proxy_upstream_ejections_total{{upstream=\"{dead}\"}} 1");
+    assert!(rendered.contains(&ejected), "{rendered}");
+    let healthy = format!("solana_quic_proxy_upstream_healthy{{upstream=\"{dead}\"}} 0");
+    assert!(rendered.contains(&healthy), "{rendered}");
+    let served = format!("solana_quic_proxy_upstream_requests_total{{upstream=\"{live}\"}} 4");
+    assert!(rendered.contains(&served), "{rendered}");
+    drop(silent);
+    Ok(())
+}
@@ -0,0 +1,203 @@
+// Numan Thabit 2025
+use std::{io::Write, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{
+    extract::{ConnectInfo, WebSocketUpgrade},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use solana_quic_proxy::{
+    config::{CliArgs, Config},
+    filter::RequestFilter,
+    metrics::ProxyMetrics,
+    ratelimit::RateLimiter,
+    ws,
+};
+use tempfile::NamedTempFile;
+use tokio::{net::TcpListener, time::timeout};
+use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, Message};
+
+fn load(config: &str) -> Result<Config> {
+    let mut file = NamedTempFile::new()?;
+    file.write_all(config.as_bytes())?;
+    file.flush()?;
+    let path = file.path().to_str().expect("temp path utf8");
+    Config::from_cli(&CliArgs::parse_from(["test", "--config", path]))
+}
+
+/// Serve a relay to an upstream that echoes every text frame back, checking
+/// client requests against a gate built from a config file body.
+async fn serve_relay(config: &str) -> Result<SocketAddr> {
+    let upstream = TcpListener::bind("127.0.0.1:0").await?;
+    let upstream_url: Arc<str> = format!("ws://{}", upstream.local_addr()?).into();
+    tokio::spawn(async move {
+        while let Ok((sock, _)) = upstream.accept().await {
+            tokio::spawn(async move {
+                let mut ws = tokio_tungstenite::accept_async(sock).await.unwrap();
+                while let Some(Ok(msg)) = ws.next().await {
+                    if msg.is_text() && ws.send(msg).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+
+    let config = load(config)?;
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let filter = Arc::new(RequestFilter::new(&config, metrics.clone()));
+    let limiter = Arc::new(RateLimiter::new(&config, metrics.clone()));
+    let relay = move |ConnectInfo(peer): ConnectInfo<SocketAddr>, upgrade: WebSocketUpgrade| {
+        let (upstream, metrics) = (upstream_url.clone(), metrics.clone());
+        let gate = ws::Gate {
+            filter: filter.clone(),
+            limiter: limiter.clone(),
+            ip: peer.ip(),
+            api_key: None,
+        };
+        async move {
+            upgrade.on_upgrade(move |socket| ws::pass_through(socket, upstream, metrics, gate))
+        }
+    };
+    let app = Router::new().route("/", get(relay));
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let proxy = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    Ok(proxy)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn relays_frames_both_ways() -> Result<()> {
+    let proxy = serve_relay("").await?;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{proxy}/")).await?;
+    let subscribe = r#"{"jsonrpc":"2.0","id":1,"method":"slotSubscribe"}"#;
+    client.send(Message::Text(subscribe.into())).await?;
+    let echoed = timeout(Duration::from_secs(5), client.next())
+        .await?
+        .expect("proxy closed the socket")?;
+    assert_eq!(echoed, Message::Text(subscribe.into()));
+    client.close(None).await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn answers_rejected_requests_instead_of_forwarding_them() -> Result<()> {
+    let proxy = serve_relay(
+        r#"
+allowed_methods = ["slotSubscribe"]
+rate_limit_rps = 0.001
+rate_limit_burst = 1
+"#,
+    )
+    .await?;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{proxy}/")).await?;
+    let requests = [
+        r#"{"jsonrpc":"2.0","id":1,"method":"programSubscribe"}"#,
+        r#"{"jsonrpc":"2.0","id":2,"method":"slotSubscribe"}"#,
+        r#"{"jsonrpc":"2.0","id":3,"method":"slotSubscribe"}"#,
+    ];
+    let mut replies = Vec::new();
+    for request in requests {
+        client.send(Message::Text(request.into())).await?;
+        let reply = timeout(Duration::from_secs(5), client.next())
+            .await?
+            .expect("proxy closed the socket")?;
+        replies.push(reply.into_text()?);
+    }
+
+    // Not on the allowlist, then forwarded, then over the limit
+    assert!(replies[0].contains("-32601") && replies[0].contains(r#""id":1"#));
+    assert_eq!(replies[1], requests[1]);
+    assert!(replies[2].contains("-32005") && replies[2].contains(r#""id":3"#));
+    Ok(())
+}
+
+/// Serve an upgrade endpoint gated like the proxy's, from a config file body.
+async fn serve_gated(config: &str) -> Result<SocketAddr> {
+    let config = load(config)?;
+    let metrics = Arc::new(ProxyMetrics::new()?);
+    let filter = Arc::new(RequestFilter::new(&config, metrics.clone()));
+    let limiter = Arc::new(RateLimiter::new(&config, metrics));
+    let key_header: Arc<str> = config.api_key_header.into();
+
+    let gate = move |ConnectInfo(peer): ConnectInfo<SocketAddr>,
+                     headers: HeaderMap,
+                     upgrade: WebSocketUpgrade| {
+        let (filter, limiter, key_header) = (filter.clone(), limiter.clone(), key_header.clone());
+        async move {
+            let api_key = headers
+                .get(key_header.as_ref())
+                .and_then(|value| value.to_str().ok());
+            match ws::admit(&filter, &limiter, peer.ip(), api_key) {
+                Ok(()) => upgrade.on_upgrade(|_socket| async {}),
+                Err((status, message)) => (status, message).into_response(),
+            }
+        }
+    };
+    let app = Router::new().route("/", get(gate));
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    Ok(addr)
+}
+
+/// Status of an upgrade attempt; 101 when the socket was accepted.
+async fn upgrade_status(addr: SocketAddr, api_key: Option<&str>) -> Result<StatusCode> {
+    let mut request = format!("ws://{addr}/").into_client_request()?;
+    if let Some(key) = api_key {
+        request.headers_mut().insert("x-api-key", key.parse()?);
+    }
+    match tokio_tungstenite::connect_async(request).await {
+        Ok((_, response)) => Ok(StatusCode::from_u16(response.status().as_u16())?),
+        Err(tungstenite::Error::Http(response)) => {
+            Ok(StatusCode::from_u16(response.status().as_u16())?)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn refuses_upgrades_the_http_path_would_refuse() -> Result<()> {
+    let denied = serve_gated(r#"ip_deny = ["127.0.0.0/8"]"#).await?;
+    assert_eq!(upgrade_status(denied, None).await?, StatusCode::FORBIDDEN);
+
+    let keyed = serve_gated(
+        r#"
+require_api_key = true
+
+[[api_keys]]
+key = "secret"
+name = "dashboards"
+rps = 0.001
+burst = 1
+"#,
+    )
+    .await?;
+    assert_eq!(upgrade_status(keyed, None).await?, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        upgrade_status(keyed, Some("secret")).await?,
+        StatusCode::SWITCHING_PROTOCOLS
+    );
+    assert_eq!(
+        upgrade_status(keyed, Some("secret")).await?,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    Ok(())
+}
@@ -1,7 +1,7 @@
 // Numan Thabit 2025
 // crates/solana-ultra-rpc/src/bin/ultra_rpc_server.rs
 use anyhow::Result;
-use solana_ultra_rpc::config::UltraRpcConfig;
+use solana_ultra_rpc::config::{FeedTlsConfig, UltraRpcConfig};
 use solana_ultra_rpc::launch_server;
 use std::path::PathBuf;
 use tokio::signal;
@@ -63,6 +63,15 @@ async fn main() -> Result<()> {
         .and_then(|v| v.parse().ok())
         .unwrap_or(16_384);
     let fallback_url = std::env::var("ULTRA_RPC_FALLBACK").ok();
+    let signature_cache_capacity: usize = std::env::var("ULTRA_RPC_SIGNATURE_CACHE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1_000_000);
+    let feed_tls = FeedTlsConfig {
+        ca_file: std::env::var("ULTRA_RPC_FEED_CA").ok().map(PathBuf::from),
+        client_cert: std::env::var("ULTRA_RPC_FEED_CERT").ok().map(PathBuf::from),
+        client_key: std::env::var("ULTRA_RPC_FEED_KEY").ok().map(PathBuf::from),
+    };
 
     let cfg = UltraRpcConfig {
         rpc_bind,
@@ -82,6 +91,8 @@ async fn main() -> Result<()> {
         } else {
             Some(std::time::Duration::from_millis(quic_idle_ms))
         },
+        signature_cache_capacity,
+        feed_tls,
     };
     let handle = launch_server(cfg).await?;
     info!("solana-ultra-rpc started");
@@ -10,9 +10,11 @@ pub struct UltraRpcConfig {
     pub rpc_bind: SocketAddr,
     /// Prometheus metrics endpoint bind address.
     pub metrics_bind: SocketAddr,
-    /// Path to the Unix domain socket exposed by `ultra-aggregator` for live deltas.
+    /// Path to the Unix domain socket exposed by `ultra-aggregator` for live deltas,
+    /// or `tcp://host:port` / `tls://host:port` for a bridge on another host.
     pub aggregator_socket: PathBuf,
-    /// Path to the snapshot stream (usually another UDS) for bootstrap.
+    /// Path to the snapshot stream (usually another UDS) for bootstrap; same forms
+    /// as `aggregator_socket`.
     pub snapshot_socket: PathBuf,
     /// Size of the hot cache shard vector (power of two, e.g. 64).
     pub shard_count: usize,
@@ -32,6 +34,21 @@ pub struct UltraRpcConfig {
     pub quic_conn_recv_window: u64,
     /// QUIC max idle timeout before disconnect (None disables timeout).
     pub quic_max_idle_timeout: Option<Duration>,
+    /// Most recent transaction signatures kept for `getSignatureStatuses`.
+    pub signature_cache_capacity: usize,
+    /// TLS settings for feeds given as `tls://host:port`.
+    pub feed_tls: FeedTlsConfig,
+}
+
+/// Client TLS for snapshot/delta feeds served by a remote `ultra-rpc-bridge`.
+#[derive(Clone, Debug, Default)]
+pub struct FeedTlsConfig {
+    /// CA bundle (PEM) the bridge certificate must chain to.
+    pub ca_file: Option<PathBuf>,
+    /// Client certificate chain (PEM) for bridges that require mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    /// Private key (PEM) for `client_cert`.
+    pub client_key: Option<PathBuf>,
 }
 
 impl Default for UltraRpcConfig {
@@ -50,6 +67,8 @@ impl Default for UltraRpcConfig {
             quic_stream_recv_window: 4 * 1024 * 1024,
             quic_conn_recv_window: 32 * 1024 * 1024,
             quic_max_idle_timeout: Some(Duration::from_secs(30)),
+            signature_cache_capacity: 1_000_000,
+            feed_tls: FeedTlsConfig::default(),
         }
     }
 }
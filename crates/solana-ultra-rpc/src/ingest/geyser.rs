@@ -1,25 +1,124 @@
 // Numan Thabit 2025
 //! Connector utilities for consuming the geyser ultra aggregator streams.
+//!
+//! Both sockets carry faststreams frames holding [`CacheFeed`] messages: the
+//! snapshot socket `SnapshotSegment`s until it closes, the delta socket a
+//! `SnapshotComplete` marker followed by `Deltas`, `Slots` and `Transactions`
+//! batches. The delta socket opens with the bridge's `Hello`, which we check
+//! against [`CACHE_FEED_VERSION`] and answer with a `HelloAck`, so a bridge on
+//! another protocol version is refused at connect time. The ack also picks the
+//! first compression the bridge offers that this build decodes. A socket path of the form `tcp://host:port` or `tls://host:port`
+//! reaches a bridge on another host instead of a local Unix socket.
 
 use std::collections::VecDeque;
 use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context as AnyhowContext, Result};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use bytes::{Buf, BytesMut};
+use faststreams::{
+    decode_cache_feed_body, encode_cache_feed, AccountDelta, AccountState, CacheFeed,
+    FeedCompression, FrameHeader, SlotStatus, TxUpdate, CACHE_FEED_VERSION, FRAME_HEADER_LEN,
+};
 use futures::TryStreamExt;
 use metrics::{gauge, histogram};
-use serde::Deserialize;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
 use solana_sdk::account::AccountSharedData;
 use solana_sdk::pubkey::Pubkey;
-use tokio::net::UnixStream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::error::TrySendError;
 use tokio_stream::Stream;
-use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
+use tokio_util::codec::{Decoder, FramedRead};
+use tracing::{info, warn};
 
 use crate::cache::{AccountUpdate, SnapshotSegment};
+use crate::config::FeedTlsConfig;
+
+trait FeedIo: AsyncRead + AsyncWrite + Send {}
+impl<T: AsyncRead + AsyncWrite + Send> FeedIo for T {}
+
+type FeedStream = Pin<Box<dyn FeedIo>>;
+
+const FEED_KEEPALIVE: Duration = Duration::from_secs(30);
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+const FEED_RECV_BUF_BYTES: usize = 16 * 1024 * 1024;
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = std::io::BufReader::new(
+        std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?,
+    );
+    rustls_pemfile::certs(&mut reader)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("read certificates from {}", path.display()))
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = std::io::BufReader::new(
+        std::fs::File::open(path).with_context(|| format!("open {}", path.display()))?,
+    );
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow!("no private key in {}", path.display()))
+}
+
+fn tls_client_config(tls: &FeedTlsConfig) -> Result<ClientConfig> {
+    let ca = tls
+        .ca_file
+        .as_deref()
+        .ok_or_else(|| anyhow!("a tls:// feed needs a CA file"))?;
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca)? {
+        roots.add(cert)?;
+    }
+    let builder =
+        ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots);
+    Ok(match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            builder.with_client_auth_cert(read_certs(cert)?, read_key(key)?)?
+        }
+        _ => builder.with_no_client_auth(),
+    })
+}
+
+/// Connect to a feed: a Unix socket path, `tcp://host:port` or `tls://host:port`.
+async fn connect_feed(target: &Path, tls: &FeedTlsConfig) -> Result<FeedStream> {
+    let target_str = target.to_string_lossy();
+    let (secure, addr) = match target_str.split_once("://") {
+        Some(("tcp", addr)) => (false, addr),
+        Some(("tls", addr)) => (true, addr),
+        _ => {
+            let stream = UnixStream::connect(target).await?;
+            return Ok(Box::pin(stream));
+        }
+    };
+    let sock = TcpStream::connect(addr).await?;
+    let _ = sock.set_nodelay(true);
+    let sr = socket2::SockRef::from(&sock);
+    let _ = sr.set_recv_buffer_size(FEED_RECV_BUF_BYTES);
+    let _ = sr.set_tcp_keepalive(
+        &socket2::TcpKeepalive::new()
+            .with_time(FEED_KEEPALIVE)
+            .with_interval(FEED_KEEPALIVE),
+    );
+    if !secure {
+        return Ok(Box::pin(sock));
+    }
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+        .with_context(|| format!("invalid TLS server name {host}"))?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(tls_client_config(tls)?))
+        .connect(name, sock)
+        .await
+        .with_context(|| format!("tls handshake with {addr} failed"))?;
+    Ok(Box::pin(stream))
+}
 
 #[derive(Debug)]
 struct Stamped<T> {
@@ -105,17 +204,15 @@ fn flush_backlog(
 /// Establish a connection to the snapshot stream and expose it as an async stream of segments.
 pub async fn connect_snapshot_stream(
     socket_path: &Path,
+    tls: &FeedTlsConfig,
 ) -> Result<impl Stream<Item = Result<SnapshotSegment>>> {
-    let stream = UnixStream::connect(socket_path).await.with_context(|| {
+    let stream = connect_feed(socket_path, tls).await.with_context(|| {
         format!(
             "failed to connect snapshot socket: {}",
             socket_path.display()
         )
     })?;
-    let codec = LengthDelimitedCodec::builder()
-        .max_frame_length(16 * 1024 * 1024)
-        .new_codec();
-    let mut framed = FramedRead::new(stream, codec);
+    let mut framed = FramedRead::new(stream, FeedCodec::new(16 * 1024 * 1024));
 
     let (tx, rx) = mpsc::channel(64);
     tokio::spawn(async move {
@@ -125,7 +222,7 @@ pub async fn connect_snapshot_stream(
                 Ok(bytes) => {
                     let res = {
                         let t0 = Instant::now();
-                        let res = decode_snapshot_segment(bytes.as_ref());
+                        let res = decode_snapshot_segment(bytes);
                         let dt = t0.elapsed().as_micros() as f64;
                         histogram!("ultra_ingest_snapshot_decode_us", dt);
                         res
@@ -147,7 +244,7 @@ pub async fn connect_snapshot_stream(
                     }
                 },
                 Err(err) => {
-                    let _ = tx.send(Stamped { at: Instant::now(), value: Err(err.into()) }).await;
+                    let _ = tx.send(Stamped { at: Instant::now(), value: Err(err) }).await;
                     break;
                 }
             }
@@ -161,14 +258,15 @@ pub async fn connect_snapshot_stream(
 /// Establish a stream of live account deltas.
 pub async fn connect_delta_stream(
     socket_path: &Path,
+    tls: &FeedTlsConfig,
 ) -> Result<impl Stream<Item = Result<DeltaStreamItem>>> {
-    let stream = UnixStream::connect(socket_path)
+    let stream = connect_feed(socket_path, tls)
         .await
         .with_context(|| format!("failed to connect delta socket: {}", socket_path.display()))?;
-    let codec = LengthDelimitedCodec::builder()
-        .max_frame_length(4 * 1024 * 1024)
-        .new_codec();
-    let mut framed = FramedRead::new(stream, codec);
+    let mut framed = FramedRead::new(stream, FeedCodec::new(4 * 1024 * 1024));
+    accept_hello(&mut framed)
+        .await
+        .with_context(|| format!("delta handshake failed: {}", socket_path.display()))?;
 
     let (tx, rx) = mpsc::channel(1024);
     tokio::spawn(async move {
@@ -191,7 +289,7 @@ pub async fn connect_delta_stream(
                 Ok(bytes) => {
                     let res = {
                         let t0 = Instant::now();
-                        let res = decode_delta_message(bytes.as_ref());
+                        let res = decode_delta_message(bytes);
                         let dt = t0.elapsed().as_micros() as f64;
                         histogram!("ultra_ingest_delta_decode_us", dt);
                         res
@@ -247,7 +345,7 @@ pub async fn connect_delta_stream(
                     }
                 },
                 Err(err) => {
-                    let stamped = Stamped { at: Instant::now(), value: Err(err.into()) };
+                    let stamped = Stamped { at: Instant::now(), value: Err(err) };
                     if !flush_backlog(&mut backlog, &tx, soft_cap, stale_dur) { break; }
                     if let Err(e) = tx.try_send(stamped) {
                         match e {
@@ -278,108 +376,164 @@ pub enum DeltaStreamItem {
     },
     /// Batch of incremental account updates originating after the baseline.
     Updates(Vec<AccountUpdate>),
+    /// Slot status changes, after the account updates they settle.
+    Slots(Vec<SlotStatus>),
+    /// Transaction statuses.
+    Transactions(Vec<TxUpdate>),
 }
 
-fn decode_snapshot_segment(bytes: &[u8]) -> Result<SnapshotSegment> {
-    let payload: SnapshotWireSegment = bincode::deserialize(bytes)?;
-    let mut accounts = Vec::with_capacity(payload.accounts.len());
-    for account in payload.accounts {
-        accounts.push(account.try_into()?);
-    }
-    Ok(SnapshotSegment {
-        base_slot: payload.base_slot,
-        accounts,
-    })
+/// Splits faststreams frames off a socket and decodes them as [`CacheFeed`].
+struct FeedCodec {
+    max_frame: usize,
+    scratch: Vec<u8>,
 }
 
-fn decode_delta_message(bytes: &[u8]) -> Result<DeltaStreamItem> {
-    let payload: DeltaStreamMessage = bincode::deserialize(bytes)?;
-    match payload {
-        DeltaStreamMessage::SnapshotComplete { slot } => {
-            Ok(DeltaStreamItem::SnapshotComplete { slot })
-        }
-        DeltaStreamMessage::Updates(batch) => {
-            let updates: Vec<AccountUpdate> = batch
-                .updates
-                .into_iter()
-                .map(AccountUpdate::try_from)
-                .collect::<std::result::Result<_, _>>()?;
-            Ok(DeltaStreamItem::Updates(updates))
+impl FeedCodec {
+    fn new(max_frame: usize) -> Self {
+        Self {
+            max_frame,
+            scratch: Vec::new(),
         }
     }
 }
 
-#[derive(Deserialize)]
-struct SnapshotWireSegment {
-    base_slot: u64,
-    accounts: Vec<AccountWire>,
-}
-
-#[derive(Deserialize)]
-struct DeltaWireBatch {
-    updates: Vec<DeltaWire>,
-}
+impl Decoder for FeedCodec {
+    type Item = CacheFeed;
+    type Error = anyhow::Error;
 
-#[derive(Deserialize)]
-enum DeltaStreamMessage {
-    SnapshotComplete { slot: u64 },
-    Updates(DeltaWireBatch),
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<CacheFeed>> {
+        let Some(hdr) = FrameHeader::parse(src)? else {
+            return Ok(None);
+        };
+        if hdr.payload_len as usize > self.max_frame {
+            return Err(anyhow!(
+                "{} byte frame over the {} byte limit",
+                hdr.payload_len,
+                self.max_frame
+            ));
+        }
+        let total = hdr.frame_len();
+        if src.len() < total {
+            src.reserve(total - src.len());
+            return Ok(None);
+        }
+        let msg = decode_cache_feed_body(&hdr, &src[FRAME_HEADER_LEN..total], &mut self.scratch);
+        src.advance(total);
+        Ok(Some(msg?))
+    }
 }
 
-#[derive(Clone, Deserialize)]
-struct AccountWire {
-    pubkey: [u8; 32],
-    lamports: u64,
-    owner: [u8; 32],
-    executable: bool,
-    rent_epoch: u64,
-    data: Vec<u8>,
+fn decode_snapshot_segment(msg: CacheFeed) -> Result<SnapshotSegment> {
+    let CacheFeed::SnapshotSegment {
+        base_slot,
+        accounts: wire,
+    } = msg
+    else {
+        return Err(anyhow!("unexpected message on the snapshot stream"));
+    };
+    let mut accounts = Vec::with_capacity(wire.len());
+    for account in wire {
+        accounts.push(account_from_state(account)?);
+    }
+    Ok(SnapshotSegment {
+        base_slot,
+        accounts,
+    })
 }
 
-#[derive(Clone, Deserialize)]
-struct DeltaWire {
-    pubkey: [u8; 32],
-    slot: u64,
-    #[serde(default)]
-    account: Option<AccountWire>,
+/// Check the bridge's hello and acknowledge it with our protocol version.
+async fn accept_hello(framed: &mut FramedRead<FeedStream, FeedCodec>) -> Result<()> {
+    let hello = tokio::time::timeout(HELLO_TIMEOUT, framed.try_next())
+        .await
+        .map_err(|_| anyhow!("no hello from the bridge within {HELLO_TIMEOUT:?}"))??
+        .ok_or_else(|| anyhow!("bridge closed the delta stream before its hello"))?;
+    let CacheFeed::Hello {
+        version,
+        slots,
+        transactions,
+        base_slot,
+        compression: offered,
+    } = hello
+    else {
+        return Err(anyhow!(
+            "bridge did not open the delta stream with a hello; it predates cache feed v{CACHE_FEED_VERSION}"
+        ));
+    };
+    // Acknowledge even a mismatch so the bridge can log both versions.
+    let compression = FeedCompression::choose(&offered);
+    let ack = encode_cache_feed(&CacheFeed::HelloAck {
+        version: CACHE_FEED_VERSION,
+        compression,
+    })?;
+    framed.get_mut().write_all(&ack).await?;
+    framed.get_mut().flush().await?;
+    if version != CACHE_FEED_VERSION {
+        return Err(anyhow!(
+            "bridge speaks cache feed v{version}, this server v{CACHE_FEED_VERSION}"
+        ));
+    }
+    info!(
+        version,
+        slots,
+        transactions,
+        ?base_slot,
+        ?compression,
+        "delta stream handshake complete"
+    );
+    if !slots {
+        warn!("bridge does not forward slot statuses; commitment levels will lag");
+    }
+    if !transactions {
+        warn!(
+            "bridge does not forward transaction statuses; getSignatureStatuses will find nothing"
+        );
+    }
+    Ok(())
 }
 
-impl TryFrom<AccountWire> for (Pubkey, AccountSharedData) {
-    type Error = anyhow::Error;
-
-    fn try_from(value: AccountWire) -> Result<Self, Self::Error> {
-        let owner = Pubkey::try_from(value.owner.as_slice())?;
-        let pubkey = Pubkey::try_from(value.pubkey.as_slice())?;
-        let account = solana_sdk::account::Account {
-            lamports: value.lamports,
-            data: value.data,
-            owner,
-            executable: value.executable,
-            rent_epoch: value.rent_epoch,
-        };
-        Ok((pubkey, AccountSharedData::from(account)))
+fn decode_delta_message(msg: CacheFeed) -> Result<DeltaStreamItem> {
+    match msg {
+        CacheFeed::SnapshotComplete { slot } => Ok(DeltaStreamItem::SnapshotComplete { slot }),
+        CacheFeed::Deltas(batch) => {
+            let updates: Vec<AccountUpdate> = batch
+                .into_iter()
+                .map(update_from_delta)
+                .collect::<Result<_>>()?;
+            Ok(DeltaStreamItem::Updates(updates))
+        }
+        CacheFeed::Slots(slots) => Ok(DeltaStreamItem::Slots(slots)),
+        CacheFeed::Transactions(txs) => Ok(DeltaStreamItem::Transactions(txs)),
+        CacheFeed::SnapshotSegment { .. } => {
+            Err(anyhow!("unexpected snapshot segment on the delta stream"))
+        }
+        CacheFeed::Hello { .. } | CacheFeed::HelloAck { .. } => {
+            Err(anyhow!("unexpected handshake message mid delta stream"))
+        }
     }
 }
 
-impl TryFrom<DeltaWire> for AccountUpdate {
-    type Error = anyhow::Error;
+fn account_from_state(value: AccountState) -> Result<(Pubkey, AccountSharedData)> {
+    let owner = Pubkey::try_from(value.owner.as_slice())?;
+    let pubkey = Pubkey::try_from(value.pubkey.as_slice())?;
+    let account = solana_sdk::account::Account {
+        lamports: value.lamports,
+        data: value.data,
+        owner,
+        executable: value.executable,
+        rent_epoch: value.rent_epoch,
+    };
+    Ok((pubkey, AccountSharedData::from(account)))
+}
 
-    fn try_from(value: DeltaWire) -> Result<Self, Self::Error> {
-        let pubkey = Pubkey::try_from(value.pubkey.as_slice())?;
-        let data = match value.account {
-            Some(account) => Some(AccountSharedData::from(solana_sdk::account::Account {
-                lamports: account.lamports,
-                data: account.data,
-                owner: Pubkey::try_from(account.owner.as_slice())?,
-                executable: account.executable,
-                rent_epoch: account.rent_epoch,
-            })),
-            None => None,
-        };
-        Ok(AccountUpdate {
-            pubkey,
-            data,
-            slot: value.slot,
-        })
-    }
+fn update_from_delta(value: AccountDelta) -> Result<AccountUpdate> {
+    let pubkey = Pubkey::try_from(value.pubkey.as_slice())?;
+    let data = match value.account {
+        Some(account) => Some(account_from_state(account)?.1),
+        None => None,
+    };
+    Ok(AccountUpdate {
+        pubkey,
+        data,
+        slot: value.slot,
+    })
 }
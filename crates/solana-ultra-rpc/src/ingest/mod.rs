@@ -4,13 +4,13 @@
 use std::sync::Arc;
 
 use tokio_stream::{Stream, StreamExt};
-use metrics::{counter, histogram};
+use metrics::{counter, gauge, histogram};
 use std::time::Instant;
 use once_cell::sync::Lazy;
 
 use crate::cache::{AccountCache, AccountCacheBuilder, AccountUpdate, SnapshotSegment};
 use crate::ingest::geyser::DeltaStreamItem;
-use crate::rpc::SlotTracker;
+use crate::rpc::{SignatureStatuses, SlotTracker, TxStatus};
 
 pub mod geyser;
 
@@ -34,10 +34,12 @@ where
     Ok(())
 }
 
-/// Apply a stream of update batches, publishing snapshots atomically.
+/// Apply a stream of update batches, publishing snapshots atomically. Slot and
+/// transaction statuses do not depend on the snapshot and apply as they arrive.
 pub async fn apply_deltas<S>(
     cache: Arc<AccountCache>,
     slot_tracker: Arc<SlotTracker>,
+    signatures: Arc<SignatureStatuses>,
     mut stream: S,
 ) -> anyhow::Result<()>
 where
@@ -65,6 +67,22 @@ where
                 }
                 publish_updates(&cache, &slot_tracker, batch);
             }
+            DeltaStreamItem::Slots(slots) => {
+                for s in slots {
+                    slot_tracker.update_status(s.slot, s.status);
+                }
+            }
+            DeltaStreamItem::Transactions(txs) => {
+                counter!("ultra_ingest_tx_statuses", txs.len() as u64);
+                signatures.insert_batch(txs.into_iter().map(|tx| {
+                    let status = TxStatus {
+                        slot: tx.slot,
+                        err: tx.err,
+                    };
+                    (tx.signature, status)
+                }));
+                gauge!("ultra_signature_cache_entries", signatures.len() as f64);
+            }
         }
     }
     Ok(())
@@ -1,6 +1,7 @@
 // Numan Thabit 2025
 //! JSON-RPC routing atop the lock-free cache.
 
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -9,20 +10,32 @@ use std::time::Instant;
 
 use base64::engine::general_purpose::STANDARD as BASE64_ENGINE;
 use base64::Engine as _;
-use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use parking_lot::RwLock;
+use serde::de::{self, Deserializer, IgnoredAny, SeqAccess, Visitor};
 use serde::ser::{SerializeMap, SerializeStruct, SerializeTuple, Serializer};
 use serde::{Deserialize, Serialize};
 use serde_json::value::RawValue;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
 
 use crate::cache::{AccountCache, AccountRecord};
 use crate::telemetry::RpcMetrics;
 
-/// Tracks most recent root slot applied by the ingest pipeline.
+// faststreams slot status codes, as the geyser plugin maps SlotStatus
+const SLOT_STATUS_CONFIRMED: u8 = 1;
+const SLOT_STATUS_ROOTED: u8 = 2;
+
+/// Most getSignatureStatuses lookups accepted per request, as on Solana RPC.
+const MAX_SIGNATURE_STATUSES: usize = 256;
+
+/// Tracks the most recent slot applied by the ingest pipeline, and the most
+/// recent confirmed and rooted slots reported on the delta stream.
 #[derive(Default)]
 #[repr(align(64))]
 pub struct SlotTracker {
     current: AtomicU64,
+    confirmed: AtomicU64,
+    finalized: AtomicU64,
 }
 
 impl SlotTracker {
@@ -40,6 +53,97 @@ impl SlotTracker {
     pub fn load(&self) -> u64 {
         self.current.load(Ordering::Relaxed)
     }
+
+    /// Apply a slot status (faststreams code) from the delta stream.
+    pub fn update_status(&self, slot: u64, status: u8) {
+        if status == SLOT_STATUS_ROOTED {
+            self.finalized.fetch_max(slot, Ordering::Relaxed);
+        }
+        if matches!(status, SLOT_STATUS_CONFIRMED | SLOT_STATUS_ROOTED) {
+            self.confirmed.fetch_max(slot, Ordering::Relaxed);
+        }
+        self.update(slot);
+    }
+
+    /// Latest slot at the given commitment; processed when `None`.
+    pub fn load_commitment(&self, commitment: Option<&str>) -> u64 {
+        match commitment {
+            Some("finalized") => self.finalized.load(Ordering::Relaxed),
+            Some("confirmed") => self.confirmed.load(Ordering::Relaxed),
+            _ => self.load(),
+        }
+    }
+
+    /// Slot to answer `getSlot` with: the commitment's slot, or the processed
+    /// slot until the first status at that commitment has arrived.
+    pub fn slot_for(&self, commitment: Option<&str>) -> u64 {
+        match self.load_commitment(commitment) {
+            0 => self.load(),
+            slot => slot,
+        }
+    }
+}
+
+/// Status of a transaction seen on the delta stream.
+#[derive(Clone, Debug)]
+pub struct TxStatus {
+    /// Slot the transaction landed in.
+    pub slot: u64,
+    /// Error reported by the validator, if the transaction failed.
+    pub err: Option<String>,
+}
+
+/// Statuses of the most recent transactions, bounded by signature count, for
+/// `getSignatureStatuses`.
+pub struct SignatureStatuses {
+    capacity: usize,
+    inner: RwLock<SignatureStatusesInner>,
+}
+
+#[derive(Default)]
+struct SignatureStatusesInner {
+    by_signature: HashMap<[u8; 64], TxStatus>,
+    order: VecDeque<[u8; 64]>,
+}
+
+impl SignatureStatuses {
+    /// Create a cache keeping at most `capacity` signatures.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            inner: RwLock::new(SignatureStatusesInner::default()),
+        }
+    }
+
+    /// Record transaction statuses, evicting the oldest signatures over capacity.
+    pub fn insert_batch(&self, statuses: impl IntoIterator<Item = ([u8; 64], TxStatus)>) {
+        let mut inner = self.inner.write();
+        for (signature, status) in statuses {
+            if inner.by_signature.insert(signature, status).is_none() {
+                inner.order.push_back(signature);
+            }
+        }
+        while inner.order.len() > self.capacity {
+            if let Some(old) = inner.order.pop_front() {
+                inner.by_signature.remove(&old);
+            }
+        }
+    }
+
+    /// Look up a signature's status.
+    pub fn get(&self, signature: &[u8; 64]) -> Option<TxStatus> {
+        self.inner.read().by_signature.get(signature).cloned()
+    }
+
+    /// Number of signatures held.
+    pub fn len(&self) -> usize {
+        self.inner.read().order.len()
+    }
+
+    /// Returns true when no signature is held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 /// Minimal JSON-RPC router with async handlers.
@@ -47,15 +151,22 @@ pub struct RpcRouter {
     cache: Arc<AccountCache>,
     metrics: RpcMetrics,
     slots: Arc<SlotTracker>,
+    signatures: Arc<SignatureStatuses>,
 }
 
 impl RpcRouter {
     /// Create a router bound to shared cache and metrics.
-    pub fn new(cache: Arc<AccountCache>, metrics: RpcMetrics, slots: Arc<SlotTracker>) -> Self {
+    pub fn new(
+        cache: Arc<AccountCache>,
+        metrics: RpcMetrics,
+        slots: Arc<SlotTracker>,
+        signatures: Arc<SignatureStatuses>,
+    ) -> Self {
         Self {
             cache,
             metrics,
             slots,
+            signatures,
         }
     }
 
@@ -70,11 +181,12 @@ impl RpcRouter {
             "getMultipleAccounts" => self.get_multiple_accounts(params).await,
             "getSlot" => {
                 let start = Instant::now();
-                let slot = self.slots.load();
+                let slot = self.slots.slot_for(slot_commitment(params));
                 self.metrics
                     .record_request("getSlot", start.elapsed().as_secs_f64(), 0);
                 Ok(RpcResult::Slot(slot))
             }
+            "getSignatureStatuses" => self.get_signature_statuses(params),
             other => {
                 let start = Instant::now();
                 self.metrics
@@ -269,6 +381,48 @@ impl RpcRouter {
         let response = RpcResponse::new(self.slots.load(), results);
         Ok(RpcResult::MultipleAccounts(response))
     }
+
+    fn get_signature_statuses(&self, params: Option<&RawValue>) -> Result<RpcResult, RpcCallError> {
+        let start = Instant::now();
+        let signatures = match parse_signature_params(params) {
+            Ok(v) => v,
+            Err(err) => {
+                self.metrics.record_request(
+                    "getSignatureStatuses",
+                    start.elapsed().as_secs_f64(),
+                    0,
+                );
+                return Err(err);
+            }
+        };
+        let processed = self.slots.load();
+        let confirmed = self.slots.load_commitment(Some("confirmed"));
+        let finalized = self.slots.load_commitment(Some("finalized"));
+        let values = signatures
+            .iter()
+            .map(|signature| {
+                self.signatures.get(signature).map(|status| {
+                    let (confirmations, level) = if status.slot <= finalized {
+                        (None, "finalized")
+                    } else if status.slot <= confirmed {
+                        (Some(processed.saturating_sub(status.slot)), "confirmed")
+                    } else {
+                        (Some(processed.saturating_sub(status.slot)), "processed")
+                    };
+                    SignatureStatusValue {
+                        slot: status.slot,
+                        confirmations,
+                        err: status.err,
+                        confirmation_status: level,
+                    }
+                })
+            })
+            .collect();
+        self.metrics
+            .record_request("getSignatureStatuses", start.elapsed().as_secs_f64(), 0);
+        let response = RpcResponse::new(processed, values);
+        Ok(RpcResult::SignatureStatuses(response))
+    }
 }
 
 /// Pre-serialized RPC payload variants.
@@ -279,6 +433,8 @@ pub enum RpcResult {
     MultipleAccounts(RpcResponse<Vec<Option<AccountInfoValue>>>),
     /// Response payload for `getSlot` requests (plain number per spec).
     Slot(u64),
+    /// Response payload for `getSignatureStatuses` requests.
+    SignatureStatuses(RpcResponse<Vec<Option<SignatureStatusValue>>>),
 }
 
 impl Serialize for RpcResult {
@@ -290,6 +446,7 @@ impl Serialize for RpcResult {
             Self::AccountInfo(response) => response.serialize(serializer),
             Self::MultipleAccounts(response) => response.serialize(serializer),
             Self::Slot(value) => value.serialize(serializer),
+            Self::SignatureStatuses(response) => response.serialize(serializer),
         }
     }
 }
@@ -318,6 +475,25 @@ fn parse_multiple_account_params<'a>(
     Ok((pubkeys, parsed.config))
 }
 
+fn parse_signature_params(params: Option<&RawValue>) -> Result<Vec<[u8; 64]>, RpcCallError> {
+    let raw = params.map(|value| value.get()).unwrap_or("[]");
+    let parsed: SignatureParams<'_> = serde_json::from_str(raw)?;
+    if parsed.signatures.len() > MAX_SIGNATURE_STATUSES {
+        return Err(RpcCallError::invalid_params(format!(
+            "too many signatures; at most {MAX_SIGNATURE_STATUSES} are allowed"
+        )));
+    }
+    parsed
+        .signatures
+        .into_iter()
+        .map(|sig| {
+            Signature::from_str(sig)
+                .map(<[u8; 64]>::from)
+                .map_err(|_| RpcCallError::invalid_params("invalid signature"))
+        })
+        .collect()
+}
+
 fn data_size(info: &AccountInfoValue) -> usize {
     info.space()
 }
@@ -420,6 +596,57 @@ impl<'de> Deserialize<'de> for AccountParams<'de> {
     }
 }
 
+#[derive(Deserialize, Default)]
+struct SlotConfig<'a> {
+    #[serde(default)]
+    #[serde(borrow)]
+    commitment: Option<&'a str>,
+}
+
+/// Commitment requested by `getSlot` params; params it cannot read are
+/// ignored, as `getSlot` has always answered without looking at them.
+fn slot_commitment(params: Option<&RawValue>) -> Option<&str> {
+    let items: Vec<&RawValue> = serde_json::from_str(params?.get()).ok()?;
+    let first: &RawValue = *items.first()?;
+    let cfg: SlotConfig<'_> = serde_json::from_str(first.get()).ok()?;
+    cfg.commitment
+}
+
+struct SignatureParams<'a> {
+    signatures: Vec<&'a str>,
+}
+
+impl<'de> Deserialize<'de> for SignatureParams<'de> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct SignatureParamsVisitor;
+
+        impl<'de> Visitor<'de> for SignatureParamsVisitor {
+            type Value = SignatureParams<'de>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("array [signatures, config?]")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let signatures: Vec<&'de str> = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                // searchTransactionHistory: only the recent cache is searched
+                let _config: Option<IgnoredAny> = seq.next_element()?;
+                Ok(SignatureParams { signatures })
+            }
+        }
+
+        deserializer.deserialize_seq(SignatureParamsVisitor)
+    }
+}
+
 struct MultipleAccountParams<'a> {
     pubkeys: Vec<&'a str>,
     config: MultipleAccountConfig<'a>,
@@ -651,6 +878,45 @@ impl Serialize for OwnerString {
     }
 }
 
+/// JSON-RPC transaction status for `getSignatureStatuses`. The validator's
+/// error is carried as the string the geyser plugin reported.
+pub struct SignatureStatusValue {
+    slot: u64,
+    confirmations: Option<u64>,
+    err: Option<String>,
+    confirmation_status: &'static str,
+}
+
+impl Serialize for SignatureStatusValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        struct Status<'a>(Option<&'a str>);
+        impl Serialize for Status<'_> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let mut map = serializer.serialize_map(Some(1))?;
+                match self.0 {
+                    Some(err) => map.serialize_entry("Err", err)?,
+                    None => map.serialize_entry("Ok", &())?,
+                }
+                map.end()
+            }
+        }
+
+        let mut state = serializer.serialize_struct("SignatureStatusValue", 5)?;
+        state.serialize_field("slot", &self.slot)?;
+        state.serialize_field("confirmations", &self.confirmations)?;
+        state.serialize_field("err", &self.err)?;
+        state.serialize_field("status", &Status(self.err.as_deref()))?;
+        state.serialize_field("confirmationStatus", self.confirmation_status)?;
+        state.end()
+    }
+}
+
 #[derive(Clone, Copy, Debug, Serialize)]
 /// Minimal RPC metadata describing the slot context of a response.
 pub struct RpcContext {
@@ -801,3 +1067,49 @@ impl From<serde_json::Error> for RpcCallError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(raw: &str) -> Box<RawValue> {
+        RawValue::from_string(raw.to_string()).unwrap()
+    }
+
+    #[test]
+    fn get_slot_falls_back_to_processed_until_statuses_arrive() {
+        let slots = SlotTracker::new();
+        slots.update(50);
+        assert_eq!(slots.slot_for(Some("finalized")), 50);
+        assert_eq!(slots.slot_for(Some("confirmed")), 50);
+
+        slots.update_status(45, SLOT_STATUS_CONFIRMED);
+        slots.update_status(40, SLOT_STATUS_ROOTED);
+        assert_eq!(slots.slot_for(None), 50);
+        assert_eq!(slots.slot_for(Some("confirmed")), 45);
+        assert_eq!(slots.slot_for(Some("finalized")), 40);
+    }
+
+    #[test]
+    fn get_slot_params_are_lenient() {
+        let commitment = |raw: &str| slot_commitment(Some(&params(raw))).map(str::to_owned);
+        assert_eq!(slot_commitment(None), None);
+        assert_eq!(
+            commitment(r#"[{"commitment":"finalized"}]"#).as_deref(),
+            Some("finalized")
+        );
+        assert_eq!(
+            commitment(r#"[{"commitment":"confirmed","minContextSlot":5},"extra"]"#).as_deref(),
+            Some("confirmed")
+        );
+        for raw in [
+            "[]",
+            "[null]",
+            r#"[{"minContextSlot":5}]"#,
+            r#"{"commitment":1}"#,
+            "[7]",
+        ] {
+            assert_eq!(commitment(raw), None, "{raw}");
+        }
+    }
+}
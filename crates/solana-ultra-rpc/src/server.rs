@@ -14,7 +14,7 @@ use crate::cache::AccountCache;
 use crate::config::UltraRpcConfig;
 use crate::ingest;
 use crate::ingest::geyser;
-use crate::rpc::{RpcRouter, SlotTracker};
+use crate::rpc::{RpcRouter, SignatureStatuses, SlotTracker};
 use crate::telemetry::Telemetry;
 use crate::transport::QuicRpcServer;
 
@@ -51,20 +51,24 @@ pub async fn launch_server(config: UltraRpcConfig) -> Result<UltraRpcServerHandl
     let telemetry = Arc::new(Telemetry::init("solana-ultra-rpc")?);
     let metrics = telemetry.rpc_metrics();
     let slot_tracker = Arc::new(SlotTracker::new());
+    let signatures = Arc::new(SignatureStatuses::new(config.signature_cache_capacity));
 
     info!(addr = %config.snapshot_socket.display(), "hydrating cache from snapshot");
-    let snapshot_stream = geyser::connect_snapshot_stream(&config.snapshot_socket).await?;
+    let snapshot_stream =
+        geyser::connect_snapshot_stream(&config.snapshot_socket, &config.feed_tls).await?;
     ingest::prewarm_from_snapshot(&cache, &slot_tracker, snapshot_stream)
         .await
         .context("failed to hydrate cache from snapshot")?;
 
     info!(addr = %config.aggregator_socket.display(), "connecting delta stream");
-    let delta_stream = geyser::connect_delta_stream(&config.aggregator_socket).await?;
+    let delta_stream =
+        geyser::connect_delta_stream(&config.aggregator_socket, &config.feed_tls).await?;
 
     let router = Arc::new(RpcRouter::new(
         cache.clone(),
         metrics.clone(),
         slot_tracker.clone(),
+        signatures.clone(),
     ));
     let quic = QuicRpcServer::bind(&config, router.clone()).await?;
 
@@ -77,7 +81,7 @@ pub async fn launch_server(config: UltraRpcConfig) -> Result<UltraRpcServerHandl
         tokio::select! {
             biased;
             _ = delta_cancel.cancelled() => Ok(()),
-            res = ingest::apply_deltas(cache, slot_tracker, delta_stream) => res,
+            res = ingest::apply_deltas(cache, slot_tracker, signatures, delta_stream) => res,
         }
     }));
 
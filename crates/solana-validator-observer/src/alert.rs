@@ -1,31 +1,143 @@
 // Numan Thabit 2025
-use std::{sync::Arc, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use reqwest::Client;
-use serde::Serialize;
+use parking_lot::Mutex;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use tokio::time::Instant;
 
-use crate::{config::AlertingConfig, state::ValidatorSnapshot};
+use crate::{
+    config::{AlertRuleConfig, AlertingConfig, ReceiverConfig, ReceiverKind, Severity},
+    maintenance::{self, MaintenanceStatus, MaintenanceWindow},
+    metrics::ObserverMetrics,
+    remediation::Remediator,
+    state::ValidatorSnapshot,
+};
+
+pub const ALERT_SLOT_LAG: &str = "slot_lag";
+
+/// Silenced alerts kept for `/silences/suppressed`.
+const SUPPRESSED_CAPACITY: usize = 1024;
+
+/// An alert condition that holds right now.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    /// The rule it falls under: `slot_lag`, a pipeline alert such as
+    /// `drop_ratio`, or the name of a log watch rule.
+    pub alert: Cow<'static, str>,
+    /// The validator or pipeline component it is about.
+    pub subject: String,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// Mutes alerts matching `alert` and `subject` (either left out matches all)
+/// from `starts_at` until `ends_at`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Silence {
+    pub id: u64,
+    pub alert: Option<String>,
+    pub subject: Option<String>,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+    pub comment: Option<String>,
+}
+
+impl Silence {
+    fn matches(&self, alert: &Alert, now: DateTime<Utc>) -> bool {
+        self.starts_at <= now
+            && now < self.ends_at
+            && self
+                .alert
+                .as_deref()
+                .is_none_or(|name| name == alert.alert.as_ref())
+            && self
+                .subject
+                .as_deref()
+                .is_none_or(|subject| subject == alert.subject)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SilenceRequest {
+    #[serde(default)]
+    pub alert: Option<String>,
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Schedules the silence ahead, e.g. for a planned upgrade; now when
+    /// left out.
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    pub duration_secs: u64,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// An alert a silence or maintenance window kept from the receivers.
+#[derive(Debug, Clone, Serialize)]
+pub struct SuppressedAlert {
+    #[serde(flatten)]
+    pub alert: Alert,
+    /// `silence <id>` or `maintenance <window>`.
+    pub reason: String,
+    pub at: DateTime<Utc>,
+}
 
 #[derive(Clone)]
 pub struct AlertingService {
     client: Client,
     config: AlertingConfig,
+    receivers: Arc<[ReceiverConfig]>,
     last_sent: Arc<DashMap<String, Instant>>,
+    silences: Arc<DashMap<u64, Silence>>,
+    next_silence: Arc<AtomicU64>,
+    maintenance: Arc<[MaintenanceWindow]>,
+    suppressed: Arc<Mutex<VecDeque<SuppressedAlert>>>,
+    remediator: Remediator,
+    metrics: ObserverMetrics,
 }
 
 impl AlertingService {
-    pub fn new(config: AlertingConfig) -> Result<Self> {
+    pub fn new(config: AlertingConfig, metrics: ObserverMetrics) -> Result<Self> {
+        let receivers = receivers(&config)?;
+        let remediator = Remediator::new(config.actions.clone(), metrics.clone())?;
+        for rule in &config.rules {
+            if let Some(name) = rule.actions.iter().find(|name| !remediator.contains(name)) {
+                bail!("alert rule {} runs unknown action {name}", rule.alert);
+            }
+        }
+        let maintenance = config
+            .maintenance
+            .iter()
+            .cloned()
+            .map(MaintenanceWindow::new)
+            .collect::<Result<Vec<_>>>()?;
         Ok(Self {
             client: Client::builder()
                 .timeout(Duration::from_secs(5))
                 .build()
                 .context("failed to build webhook client")?,
             config,
+            receivers: receivers.into(),
             last_sent: Arc::new(DashMap::new()),
+            silences: Arc::new(DashMap::new()),
+            next_silence: Arc::new(AtomicU64::new(1)),
+            maintenance: maintenance.into(),
+            suppressed: Arc::new(Mutex::new(VecDeque::new())),
+            remediator,
+            metrics,
         })
     }
 
@@ -37,35 +149,398 @@ impl AlertingService {
             return Ok(());
         }
 
-        if let Some(last) = self.last_sent.get(&snapshot.name) {
-            if last.elapsed() < self.config.cooldown() {
-                return Ok(());
+        self.fire(&Alert {
+            alert: ALERT_SLOT_LAG.into(),
+            subject: snapshot.name.clone(),
+            value: slot_lag,
+            threshold: self.config.slot_lag_threshold as f64,
+        })
+        .await
+    }
+
+    /// Route `alert` to the receivers of its rule, unless it is silenced, in
+    /// a maintenance window, or was already sent within the rule's dedupe
+    /// window. Remediation actions keep their own cooldowns, so they run even
+    /// while the alert is deduped.
+    pub async fn fire(&self, alert: &Alert) -> Result<()> {
+        let rule = self.rule(&alert.alert);
+        let severity = rule.map(|rule| rule.severity).unwrap_or_default();
+        let dedupe_window = rule
+            .and_then(|rule| rule.dedupe_window)
+            .unwrap_or_else(|| self.config.cooldown());
+
+        let now = Utc::now();
+        if let Some((reason, label)) = self.suppression(alert, now) {
+            self.metrics.inc_alert_suppressed(&alert.alert, label);
+            let mut suppressed = self.suppressed.lock();
+            if suppressed.len() == SUPPRESSED_CAPACITY {
+                suppressed.pop_front();
             }
+            suppressed.push_back(SuppressedAlert {
+                alert: alert.clone(),
+                reason,
+                at: now,
+            });
+            return Ok(());
+        }
+        if let Some(rule) = rule {
+            self.remediator.trigger(&rule.actions, alert);
         }
+        let key = format!("{}/{}", alert.alert, alert.subject);
+        if self
+            .last_sent
+            .get(&key)
+            .is_some_and(|last| last.elapsed() < dedupe_window)
+        {
+            self.metrics
+                .inc_alert_suppressed(&alert.alert, "deduplicated");
+            return Ok(());
+        }
+
+        let mut delivered = false;
+        let mut last_error = None;
+        for receiver in self.recipients(rule, severity) {
+            match self.send(receiver, alert, severity, &key, now).await {
+                Ok(()) => {
+                    self.metrics
+                        .inc_alert_sent(&receiver.name, severity.as_str());
+                    delivered = true;
+                }
+                Err(err) => {
+                    self.metrics.inc_alert_send_error(&receiver.name);
+                    tracing::warn!(receiver = %receiver.name, error = %err, "failed to deliver alert");
+                    last_error = Some(err);
+                }
+            }
+        }
+        if delivered {
+            self.last_sent.insert(key, Instant::now());
+        }
+        match last_error {
+            Some(err) if !delivered => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn silences(&self) -> Vec<Silence> {
+        let now = Utc::now();
+        self.silences.retain(|_, silence| silence.ends_at > now);
+        let mut silences: Vec<Silence> = self
+            .silences
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        silences.sort_by_key(|silence| silence.id);
+        silences
+    }
 
-        let payload = AlertPayload {
-            validator: snapshot.name.clone(),
-            slot_lag,
-            threshold: self.config.slot_lag_threshold,
-            timestamp: snapshot.last_updated.unwrap_or_else(Utc::now),
+    pub fn add_silence(&self, request: SilenceRequest) -> Silence {
+        let id = self.next_silence.fetch_add(1, Ordering::Relaxed);
+        let starts_at = request.starts_at.unwrap_or_else(Utc::now);
+        let silence = Silence {
+            id,
+            alert: request.alert,
+            subject: request.subject,
+            starts_at,
+            ends_at: starts_at + Duration::from_secs(request.duration_secs),
+            comment: request.comment,
         };
+        tracing::info!(
+            id,
+            alert = ?silence.alert,
+            subject = ?silence.subject,
+            starts_at = %silence.starts_at,
+            ends_at = %silence.ends_at,
+            "alert silence added"
+        );
+        self.silences.insert(id, silence.clone());
+        silence
+    }
+
+    pub fn remove_silence(&self, id: u64) -> bool {
+        self.silences.remove(&id).is_some()
+    }
+
+    /// Alerts held back, oldest first.
+    pub fn suppressed(&self) -> Vec<SuppressedAlert> {
+        self.suppressed.lock().iter().cloned().collect()
+    }
+
+    pub fn maintenance_windows(&self) -> Vec<MaintenanceStatus> {
+        let now = Utc::now();
+        self.maintenance
+            .iter()
+            .map(|window| window.status(now))
+            .collect()
+    }
+
+    /// Why `alert` is held back at `now`, and the reason label counted in
+    /// `alerts_suppressed_total`.
+    fn suppression(&self, alert: &Alert, now: DateTime<Utc>) -> Option<(String, &'static str)> {
+        if let Some(silence) = self
+            .silences
+            .iter()
+            .find(|silence| silence.matches(alert, now))
+        {
+            return Some((format!("silence {}", silence.id), "silenced"));
+        }
+        maintenance::covering(&self.maintenance, alert, now)
+            .map(|window| (format!("maintenance {}", window.name()), "maintenance"))
+    }
 
-        self.client
-            .post(self.config.webhook_url.clone())
-            .json(&payload)
+    fn rule(&self, alert: &str) -> Option<&AlertRuleConfig> {
+        self.config.rules.iter().find(|rule| rule.alert == alert)
+    }
+
+    fn recipients<'a>(
+        &'a self,
+        rule: Option<&'a AlertRuleConfig>,
+        severity: Severity,
+    ) -> impl Iterator<Item = &'a ReceiverConfig> {
+        let named = rule.map(|rule| rule.receivers.as_slice()).unwrap_or(&[]);
+        self.receivers.iter().filter(move |receiver| {
+            (named.is_empty() || named.contains(&receiver.name))
+                && receiver.min_severity.is_none_or(|min| severity >= min)
+        })
+    }
+
+    async fn send(
+        &self,
+        receiver: &ReceiverConfig,
+        alert: &Alert,
+        severity: Severity,
+        key: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Result<()> {
+        let summary = format!(
+            "{} on {}: {:.3} (threshold {:.3})",
+            alert.alert, alert.subject, alert.value, alert.threshold
+        );
+        let body = match receiver.kind {
+            ReceiverKind::Webhook => json!({
+                "alert": alert.alert,
+                "subject": alert.subject,
+                "severity": severity,
+                "value": alert.value,
+                "threshold": alert.threshold,
+                "summary": summary,
+                "timestamp": timestamp,
+            }),
+            ReceiverKind::Slack => json!({
+                "text": format!("[{}] {summary}", severity.as_str().to_uppercase()),
+            }),
+            ReceiverKind::Pagerduty => json!({
+                "routing_key": receiver.routing_key,
+                "event_action": "trigger",
+                "dedup_key": key,
+                "payload": {
+                    "summary": summary,
+                    "source": alert.subject,
+                    "severity": severity.as_str(),
+                    "timestamp": timestamp,
+                    "custom_details": {
+                        "alert": alert.alert,
+                        "value": alert.value,
+                        "threshold": alert.threshold,
+                    },
+                },
+            }),
+        };
+
+        let response = self
+            .client
+            .post(receiver.url.clone())
+            .json(&body)
             .send()
             .await
-            .context("failed to send alert webhook")?;
-
-        self.last_sent.insert(snapshot.name.clone(), Instant::now());
+            .with_context(|| format!("failed to send alert to {}", receiver.name))?;
+        if !response.status().is_success() {
+            bail!(
+                "receiver {} returned status {}",
+                receiver.name,
+                response.status()
+            );
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize)]
-struct AlertPayload {
-    validator: String,
-    slot_lag: f64,
-    threshold: u64,
-    timestamp: DateTime<Utc>,
+/// The configured receivers, with the legacy `webhook_url` as a generic
+/// webhook named `default`.
+fn receivers(config: &AlertingConfig) -> Result<Vec<ReceiverConfig>> {
+    let mut receivers = config.receivers.clone();
+    if let Some(url) = &config.webhook_url {
+        receivers.push(ReceiverConfig {
+            name: "default".into(),
+            kind: ReceiverKind::Webhook,
+            url: Url::clone(url),
+            routing_key: None,
+            min_severity: None,
+        });
+    }
+    if receivers.is_empty() && config.actions.is_empty() {
+        bail!("alerting needs a webhook_url, a receiver or a remediation action");
+    }
+    for (idx, receiver) in receivers.iter().enumerate() {
+        if receivers[..idx].iter().any(|r| r.name == receiver.name) {
+            bail!("duplicate alert receiver {}", receiver.name);
+        }
+        if receiver.kind == ReceiverKind::Pagerduty && receiver.routing_key.is_none() {
+            bail!("pagerduty receiver {} needs a routing_key", receiver.name);
+        }
+    }
+    for rule in &config.rules {
+        if let Some(name) = rule
+            .receivers
+            .iter()
+            .find(|name| !receivers.iter().any(|r| &r.name == *name))
+        {
+            bail!(
+                "alert rule {} routes to unknown receiver {name}",
+                rule.alert
+            );
+        }
+    }
+    Ok(receivers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receiver(name: &str, min_severity: Option<Severity>) -> ReceiverConfig {
+        ReceiverConfig {
+            name: name.into(),
+            kind: ReceiverKind::Webhook,
+            url: Url::parse("https://example.com/hook").unwrap(),
+            routing_key: None,
+            min_severity,
+        }
+    }
+
+    fn service(rules: Vec<AlertRuleConfig>) -> AlertingService {
+        let config = AlertingConfig {
+            webhook_url: None,
+            slot_lag_threshold: 50,
+            cooldown: None,
+            receivers: vec![
+                receiver("chat", None),
+                receiver("pager", Some(Severity::Critical)),
+            ],
+            rules,
+            actions: Vec::new(),
+            maintenance: Vec::new(),
+        };
+        AlertingService::new(config, ObserverMetrics::new()).expect("valid alerting config")
+    }
+
+    fn alert(subject: &str) -> Alert {
+        Alert {
+            alert: ALERT_SLOT_LAG.into(),
+            subject: subject.into(),
+            value: 80.0,
+            threshold: 50.0,
+        }
+    }
+
+    fn names<'a>(receivers: impl Iterator<Item = &'a ReceiverConfig>) -> Vec<&'a str> {
+        receivers.map(|r| r.name.as_str()).collect()
+    }
+
+    #[test]
+    fn routes_by_severity_and_rule() {
+        let service = service(vec![AlertRuleConfig {
+            alert: "drop_ratio".into(),
+            severity: Severity::Critical,
+            receivers: vec!["pager".into()],
+            dedupe_window: None,
+            actions: Vec::new(),
+        }]);
+        assert_eq!(
+            names(service.recipients(None, Severity::Warning)),
+            vec!["chat"]
+        );
+        assert_eq!(
+            names(service.recipients(None, Severity::Critical)),
+            vec!["chat", "pager"]
+        );
+        let rule = service.rule("drop_ratio");
+        assert_eq!(
+            names(service.recipients(rule, Severity::Critical)),
+            vec!["pager"]
+        );
+    }
+
+    #[test]
+    fn rejects_rules_for_unknown_receivers() {
+        let config = AlertingConfig {
+            webhook_url: Some(Url::parse("https://example.com/hook").unwrap()),
+            slot_lag_threshold: 50,
+            cooldown: None,
+            receivers: Vec::new(),
+            rules: vec![AlertRuleConfig {
+                alert: ALERT_SLOT_LAG.into(),
+                severity: Severity::Warning,
+                receivers: vec!["pager".into()],
+                dedupe_window: None,
+                actions: Vec::new(),
+            }],
+            actions: Vec::new(),
+            maintenance: Vec::new(),
+        };
+        assert!(AlertingService::new(config, ObserverMetrics::new()).is_err());
+    }
+
+    #[test]
+    fn silences_match_alert_and_subject() {
+        let service = service(Vec::new());
+        let silence = service.add_silence(SilenceRequest {
+            alert: Some(ALERT_SLOT_LAG.into()),
+            subject: Some("alpha".into()),
+            starts_at: None,
+            duration_secs: 60,
+            comment: None,
+        });
+        let now = Utc::now();
+        assert!(service.suppression(&alert("alpha"), now).is_some());
+        assert!(service.suppression(&alert("beta"), now).is_none());
+        assert_eq!(service.silences().len(), 1);
+
+        assert!(service.remove_silence(silence.id));
+        assert!(service.suppression(&alert("alpha"), now).is_none());
+        assert!(!service.remove_silence(silence.id));
+    }
+
+    #[tokio::test]
+    async fn records_alerts_held_back_by_scheduled_silences() {
+        let service = service(Vec::new());
+        let now = Utc::now();
+        let silence = service.add_silence(SilenceRequest {
+            alert: None,
+            subject: Some("alpha".into()),
+            starts_at: Some(now + Duration::from_secs(3_600)),
+            duration_secs: 1_800,
+            comment: Some("upgrade".into()),
+        });
+        assert!(service.suppression(&alert("alpha"), now).is_none());
+        let during = now + Duration::from_secs(4_000);
+        assert_eq!(
+            service.suppression(&alert("alpha"), during),
+            Some((format!("silence {}", silence.id), "silenced"))
+        );
+
+        // Suppressed without reaching a receiver, and kept for review
+        let active = service.add_silence(SilenceRequest {
+            alert: None,
+            subject: Some("beta".into()),
+            starts_at: None,
+            duration_secs: 60,
+            comment: None,
+        });
+        service.fire(&alert("beta")).await.expect("silenced alert");
+        let suppressed = service.suppressed();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed[0].alert.subject, "beta");
+        assert_eq!(suppressed[0].reason, format!("silence {}", active.id));
+    }
 }
@@ -0,0 +1,239 @@
+// Numan Thabit 2025
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{AnomalyConfig, Direction},
+    metrics::ObserverMetrics,
+    state::{AnomalyStatus, ObserverState},
+};
+
+pub const ALERT_ANOMALOUS_DEVIATION: &str = "anomalous_deviation";
+
+/// Exponentially weighted mean and variance of one series.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Baseline {
+    mean: f64,
+    variance: f64,
+    samples: u64,
+}
+
+impl Baseline {
+    /// Score `value` against the baseline learned so far, then fold it in
+    /// with weight `alpha`. `None` until `warmup` samples were learned.
+    pub fn observe(
+        &mut self,
+        value: f64,
+        alpha: f64,
+        warmup: u64,
+        min_deviation: f64,
+    ) -> Option<f64> {
+        let score = (self.samples >= warmup && self.samples > 0).then(|| {
+            let deviation = self.deviation(min_deviation);
+            if deviation > 0.0 {
+                (value - self.mean) / deviation
+            } else if value == self.mean {
+                0.0
+            } else {
+                // A series that never moved before moved now
+                f64::INFINITY.copysign(value - self.mean)
+            }
+        });
+        if self.samples == 0 {
+            self.mean = value;
+        } else {
+            let diff = value - self.mean;
+            let step = alpha * diff;
+            self.mean += step;
+            self.variance = (1.0 - alpha) * (self.variance + diff * step);
+        }
+        self.samples += 1;
+        score
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn deviation(&self, min_deviation: f64) -> f64 {
+        self.variance.sqrt().max(min_deviation)
+    }
+}
+
+/// Weight of the newest sample for samples `interval` apart to halve in
+/// weight every `half_life`.
+pub fn alpha(interval: Duration, half_life: Duration) -> f64 {
+    if half_life.is_zero() {
+        return 1.0;
+    }
+    1.0 - 0.5f64.powf(interval.as_secs_f64() / half_life.as_secs_f64())
+}
+
+/// Whether `score` lies past `sensitivity` on the watched side.
+pub fn is_anomalous(score: f64, sensitivity: f64, direction: Direction) -> bool {
+    match direction {
+        Direction::Above => score > sensitivity,
+        Direction::Below => score < -sensitivity,
+        Direction::Both => score.abs() > sensitivity,
+    }
+}
+
+pub fn spawn_detector(
+    config: AnomalyConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, state, metrics, alerting).await {
+            tracing::error!(%err, "anomaly detector loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: AnomalyConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    for series in &config.series {
+        if series.sensitivity <= 0.0 {
+            bail!(
+                "anomaly series {} needs a positive sensitivity, got {}",
+                series.metric,
+                series.sensitivity
+            );
+        }
+    }
+    let interval = config.evaluation_interval();
+    let mut ticker = interval_at(Instant::now(), interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut baselines: HashMap<(String, String), Baseline> = HashMap::new();
+    let mut firing: HashSet<String> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+        let mut statuses = Vec::new();
+        let mut alerts = Vec::new();
+        let mut now_firing = HashSet::new();
+
+        for series in &config.series {
+            let alpha = alpha(interval, series.half_life());
+            for (subject, value) in metrics.gauge_values(&series.metric) {
+                if !value.is_finite() {
+                    continue;
+                }
+                let baseline = baselines
+                    .entry((series.metric.clone(), subject.clone()))
+                    .or_default();
+                let expected = baseline.mean();
+                let deviation = baseline.deviation(series.min_deviation);
+                let score = baseline.observe(value, alpha, series.warmup, series.min_deviation);
+                let is_firing = score
+                    .is_some_and(|score| is_anomalous(score, series.sensitivity, series.direction));
+                metrics.set_anomaly(
+                    &series.metric,
+                    &subject,
+                    score.unwrap_or_default(),
+                    is_firing,
+                );
+
+                let alert_subject = format!("{}/{subject}", series.metric);
+                if is_firing {
+                    now_firing.insert(alert_subject.clone());
+                    alerts.push(Alert {
+                        alert: ALERT_ANOMALOUS_DEVIATION.into(),
+                        subject: alert_subject,
+                        value: score.unwrap_or_default(),
+                        threshold: series.sensitivity,
+                    });
+                }
+                statuses.push(AnomalyStatus {
+                    metric: series.metric.clone(),
+                    subject,
+                    value,
+                    baseline: expected,
+                    deviation,
+                    score,
+                    firing: is_firing,
+                });
+            }
+        }
+        state.update_anomalies(statuses);
+
+        for started in now_firing.difference(&firing) {
+            tracing::warn!(series = %started, "anomalous deviation");
+        }
+        for resolved in firing.difference(&now_firing) {
+            tracing::info!(series = %resolved, "anomalous deviation resolved");
+        }
+        firing = now_firing;
+
+        if let Some(alerting) = &alerting {
+            for alert in &alerts {
+                if let Err(err) = alerting.fire(alert).await {
+                    tracing::warn!(subject = %alert.subject, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scores_deviation_after_warmup() {
+        let mut baseline = Baseline::default();
+        let alpha = alpha(Duration::from_secs(10), Duration::from_secs(60));
+        // A series wobbling around 0.3
+        for i in 0..40 {
+            let value = if i % 2 == 0 { 0.29 } else { 0.31 };
+            let score = baseline.observe(value, alpha, 30, 0.0);
+            assert_eq!(score.is_some(), i >= 30);
+            if let Some(score) = score {
+                assert!(!is_anomalous(score, 4.0, Direction::Both));
+            }
+        }
+        assert!((baseline.mean() - 0.3).abs() < 0.01);
+
+        let spike = baseline.observe(0.9, alpha, 30, 0.0).unwrap();
+        assert!(is_anomalous(spike, 4.0, Direction::Above));
+        assert!(!is_anomalous(spike, 4.0, Direction::Below));
+    }
+
+    #[test]
+    fn flat_series_need_a_floor_to_score_finitely() {
+        let mut flat = Baseline::default();
+        for _ in 0..5 {
+            assert_eq!(flat.observe(0.0, 0.1, 5, 0.0), None);
+        }
+        assert_eq!(flat.observe(0.0, 0.1, 5, 0.0), Some(0.0));
+        assert_eq!(flat.observe(0.001, 0.1, 5, 0.0), Some(f64::INFINITY));
+
+        let mut floored = Baseline::default();
+        for _ in 0..5 {
+            floored.observe(0.0, 0.1, 5, 0.01);
+        }
+        let score = floored.observe(0.001, 0.1, 5, 0.01).unwrap();
+        assert!((score - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn half_life_sets_the_weight() {
+        let half = alpha(Duration::from_secs(60), Duration::from_secs(60));
+        assert!((half - 0.5).abs() < 1e-12);
+        assert_eq!(alpha(Duration::from_secs(10), Duration::ZERO), 1.0);
+    }
+}
@@ -0,0 +1,191 @@
+// Numan Thabit 2025
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::config::{HttpConfig, TokenConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// No token, or one that is not configured.
+    Unauthenticated,
+    /// A known token not allowed on this endpoint.
+    Forbidden,
+}
+
+/// Bearer tokens and the endpoints each may reach.
+#[derive(Debug, Clone)]
+pub struct AccessControl {
+    tokens: Arc<[TokenConfig]>,
+    public: Arc<[String]>,
+}
+
+impl AccessControl {
+    pub fn new(config: &HttpConfig) -> Result<Self> {
+        for (idx, token) in config.tokens.iter().enumerate() {
+            if config.tokens[..idx].iter().any(|t| t.name == token.name) {
+                bail!("duplicate http token {}", token.name);
+            }
+            if token.token.trim().is_empty() {
+                bail!("http token {} is empty", token.name);
+            }
+        }
+        if let Some(prefix) = config
+            .tokens
+            .iter()
+            .flat_map(|token| &token.endpoints)
+            .chain(&config.public)
+            .find(|prefix| !prefix.starts_with('/'))
+        {
+            bail!("endpoint prefix {prefix} does not start with /");
+        }
+        Ok(Self {
+            tokens: config.tokens.clone().into(),
+            public: config.public.clone().into(),
+        })
+    }
+
+    /// Check a request for `path` with the given `Authorization` header;
+    /// the name of the token that let it through, if one was needed.
+    pub fn check(&self, path: &str, authorization: Option<&str>) -> Result<Option<&str>, Denial> {
+        if self.tokens.is_empty() || self.public.iter().any(|prefix| covers(prefix, path)) {
+            return Ok(None);
+        }
+        let presented = authorization
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .ok_or(Denial::Unauthenticated)?;
+        let token = self
+            .tokens
+            .iter()
+            .find(|token| constant_time_eq(presented.as_bytes(), token.token.as_bytes()))
+            .ok_or(Denial::Unauthenticated)?;
+        if token.endpoints.is_empty() || token.endpoints.iter().any(|prefix| covers(prefix, path)) {
+            Ok(Some(&token.name))
+        } else {
+            Err(Denial::Forbidden)
+        }
+    }
+}
+
+/// Middleware turning away requests `access` does not let through.
+pub async fn require_token(
+    State(access): State<AccessControl>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    match access.check(request.uri().path(), authorization) {
+        Ok(_) => next.run(request).await,
+        Err(Denial::Unauthenticated) => (
+            StatusCode::UNAUTHORIZED,
+            [(WWW_AUTHENTICATE, "Bearer")],
+            "missing or unknown bearer token",
+        )
+            .into_response(),
+        Err(Denial::Forbidden) => {
+            tracing::debug!(path = %request.uri().path(), "token not allowed on endpoint");
+            (StatusCode::FORBIDDEN, "token not allowed on this endpoint").into_response()
+        }
+    }
+}
+
+/// Whether `prefix` covers `path` on a segment boundary, so `/history`
+/// covers `/history/query` but not `/historyx`.
+fn covers(prefix: &str, path: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(name: &str, endpoints: &[&str]) -> TokenConfig {
+        TokenConfig {
+            name: name.into(),
+            token: format!("{name}-secret"),
+            endpoints: endpoints.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    fn access(tokens: Vec<TokenConfig>) -> AccessControl {
+        AccessControl::new(&HttpConfig {
+            tokens,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn open_without_tokens() {
+        assert_eq!(access(Vec::new()).check("/metrics", None), Ok(None));
+    }
+
+    #[test]
+    fn scopes_tokens_to_endpoints() {
+        let access = access(vec![
+            token("prometheus", &["/metrics"]),
+            token("oncall", &["/debug/flamegraph", "/silences"]),
+            token("admin", &[]),
+        ]);
+        assert_eq!(access.check("/healthz", None), Ok(None));
+        assert_eq!(access.check("/metrics", None), Err(Denial::Unauthenticated));
+        assert_eq!(
+            access.check("/metrics", Some("Bearer wrong")),
+            Err(Denial::Unauthenticated)
+        );
+        assert_eq!(
+            access.check("/metrics", Some("Bearer prometheus-secret")),
+            Ok(Some("prometheus"))
+        );
+        assert_eq!(
+            access.check("/validators", Some("Bearer prometheus-secret")),
+            Err(Denial::Forbidden)
+        );
+        assert_eq!(
+            access.check("/debug/flamegraph/diff", Some("Bearer oncall-secret")),
+            Ok(Some("oncall"))
+        );
+        assert_eq!(
+            access.check("/silences/4", Some("Bearer oncall-secret")),
+            Ok(Some("oncall"))
+        );
+        assert_eq!(
+            access.check("/history/query", Some("Bearer admin-secret")),
+            Ok(Some("admin"))
+        );
+        assert!(!covers("/history", "/historyx"));
+    }
+
+    #[test]
+    fn rejects_bad_token_config() {
+        let duplicate = HttpConfig {
+            tokens: vec![token("a", &[]), token("a", &[])],
+            ..Default::default()
+        };
+        assert!(AccessControl::new(&duplicate).is_err());
+        let relative = HttpConfig {
+            tokens: vec![token("a", &["metrics"])],
+            ..Default::default()
+        };
+        assert!(AccessControl::new(&relative).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+// Numan Thabit 2025
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{CatchupConfig, ValidatorConfig},
+    metrics::ObserverMetrics,
+    state::{CatchupState, CatchupStatus, ObserverState},
+};
+
+pub const ALERT_BEHIND: &str = "catchup_behind";
+pub const ALERT_CLUSTER_SLOW: &str = "cluster_slow";
+const CLUSTER_SUBJECT: &str = "cluster";
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcGetSlot {
+    result: u64,
+}
+
+/// How fast a slot advances over a rolling window.
+#[derive(Debug)]
+pub struct SlotRate {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SlotRate {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record `slot` at `now`; returns slots per second once the samples
+    /// span at least half the window.
+    pub fn observe(&mut self, now: Instant, slot: u64) -> Option<f64> {
+        self.samples.push_back((now, slot));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            self.samples.pop_front();
+        }
+        let (oldest_at, oldest_slot) = *self.samples.front()?;
+        let span = now.duration_since(oldest_at);
+        (!span.is_zero() && span >= self.window / 2)
+            .then(|| slot.saturating_sub(oldest_slot) as f64 / span.as_secs_f64())
+    }
+}
+
+/// When a condition started holding without a break.
+#[derive(Debug, Default)]
+pub struct Sustained {
+    since: Option<Instant>,
+}
+
+impl Sustained {
+    /// Record whether the condition holds at `now`; returns for how long.
+    pub fn update(&mut self, holds: bool, now: Instant) -> Option<Duration> {
+        if !holds {
+            self.since = None;
+            return None;
+        }
+        Some(now.duration_since(*self.since.get_or_insert(now)))
+    }
+}
+
+pub fn classify(slot_gap: u64, max_slot_gap: u64, cluster_slow: bool) -> CatchupState {
+    if slot_gap > max_slot_gap {
+        CatchupState::Behind
+    } else if cluster_slow {
+        CatchupState::ClusterSlow
+    } else {
+        CatchupState::InSync
+    }
+}
+
+pub fn spawn_monitor(
+    config: CatchupConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, validators, state, metrics, alerting).await {
+            tracing::error!(%err, "catchup monitor loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: CatchupConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    if config.references.is_empty() {
+        tracing::warn!("catchup detection configured without reference nodes; skipping");
+        return Ok(());
+    }
+    let monitored: Vec<String> = validators
+        .into_iter()
+        .filter(|v| v.rpc_url.is_some())
+        .map(|v| v.name)
+        .collect();
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .context("failed to construct reference rpc client")?;
+    let mut ticker = interval_at(Instant::now(), config.poll_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let sustain_for = config.sustain_for();
+    let mut rate = SlotRate::new(sustain_for);
+    let mut cluster_slow = Sustained::default();
+    let mut behind: HashMap<String, Sustained> = HashMap::new();
+    let mut firing: HashSet<String> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+
+        let polls = join_all(
+            config
+                .references
+                .iter()
+                .map(|reference| fetch_slot(&client, &reference.rpc_url)),
+        )
+        .await;
+        let mut reference_slot = None;
+        for (reference, polled) in config.references.iter().zip(polls) {
+            match polled {
+                Ok(slot) => {
+                    metrics.set_reference_slot(&reference.name, slot);
+                    reference_slot = reference_slot.max(Some(slot));
+                }
+                Err(err) => {
+                    tracing::debug!(reference = %reference.name, error = %err, "reference slot poll failed");
+                }
+            }
+        }
+        let Some(reference_slot) = reference_slot else {
+            tracing::debug!("no reference node answered getSlot");
+            continue;
+        };
+        let now = Instant::now();
+
+        let slot_rate = rate.observe(now, reference_slot);
+        if let Some(slot_rate) = slot_rate {
+            metrics.set_cluster_slot_rate(slot_rate);
+        }
+        let is_slow = slot_rate.is_some_and(|rate| rate < config.min_cluster_slot_rate);
+        let mut alerts = Vec::new();
+        let slow_for = cluster_slow.update(is_slow, now);
+        if slow_for.is_some_and(|held| held >= sustain_for) {
+            alerts.push(Alert {
+                alert: ALERT_CLUSTER_SLOW.into(),
+                subject: CLUSTER_SUBJECT.to_string(),
+                value: slot_rate.unwrap_or_default(),
+                threshold: config.min_cluster_slot_rate,
+            });
+        }
+
+        for name in &monitored {
+            let Some(slot) = state.get(name).and_then(|snapshot| snapshot.last_slot) else {
+                continue;
+            };
+            let slot_gap = reference_slot.saturating_sub(slot);
+            let catchup_state = classify(slot_gap, config.max_slot_gap, is_slow);
+            let behind_for = behind
+                .entry(name.clone())
+                .or_default()
+                .update(catchup_state == CatchupState::Behind, now);
+
+            metrics.set_catchup(name, slot_gap, catchup_state);
+            state.update_catchup(
+                name,
+                CatchupStatus {
+                    reference_slot,
+                    slot_gap,
+                    cluster_slot_rate: slot_rate,
+                    state: catchup_state,
+                    behind_for_secs: behind_for.map(|held| held.as_secs_f64()),
+                },
+            );
+            if behind_for.is_some_and(|held| held >= sustain_for) {
+                alerts.push(Alert {
+                    alert: ALERT_BEHIND.into(),
+                    subject: name.clone(),
+                    value: slot_gap as f64,
+                    threshold: config.max_slot_gap as f64,
+                });
+            }
+        }
+
+        let now_firing: HashSet<String> = alerts
+            .iter()
+            .map(|alert| format!("{}/{}", alert.alert, alert.subject))
+            .collect();
+        for alert in &alerts {
+            if !firing.contains(&format!("{}/{}", alert.alert, alert.subject)) {
+                tracing::warn!(
+                    alert = %alert.alert,
+                    subject = %alert.subject,
+                    value = alert.value,
+                    threshold = alert.threshold,
+                    reference_slot,
+                    "catchup alert firing"
+                );
+            }
+        }
+        for resolved in firing.difference(&now_firing) {
+            tracing::info!(alert = %resolved, "catchup alert resolved");
+        }
+        firing = now_firing;
+
+        if let Some(alerting) = &alerting {
+            for alert in &alerts {
+                if let Err(err) = alerting.fire(alert).await {
+                    tracing::warn!(subject = %alert.subject, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_slot(client: &Client, url: &Url) -> Result<u64> {
+    let response = client
+        .post(url.clone())
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": "getSlot", "params": []}))
+        .send()
+        .await
+        .context("rpc request failed")?;
+    if !response.status().is_success() {
+        anyhow::bail!("rpc endpoint returned status {}", response.status());
+    }
+    Ok(response
+        .json::<JsonRpcGetSlot>()
+        .await
+        .context("failed to decode getSlot body")?
+        .result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slot_rate_needs_half_a_window() {
+        let mut rate = SlotRate::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(rate.observe(at(0), 1_000), None);
+        assert_eq!(rate.observe(at(20), 1_050), None);
+        assert_eq!(rate.observe(at(40), 1_100), Some(2.5));
+        // The first sample ages out; a stalled cluster shows up as a low rate
+        assert_eq!(rate.observe(at(70), 1_110), Some(1.2));
+    }
+
+    #[test]
+    fn behind_is_told_apart_from_a_slow_cluster() {
+        assert_eq!(classify(10, 100, false), CatchupState::InSync);
+        assert_eq!(classify(10, 100, true), CatchupState::ClusterSlow);
+        assert_eq!(classify(250, 100, true), CatchupState::Behind);
+
+        let mut behind = Sustained::default();
+        let start = Instant::now();
+        assert_eq!(behind.update(true, start), Some(Duration::ZERO));
+        assert_eq!(
+            behind.update(true, start + Duration::from_secs(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(behind.update(false, start + Duration::from_secs(31)), None);
+        assert_eq!(
+            behind.update(true, start + Duration::from_secs(40)),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -3,7 +3,7 @@ use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use anyhow::{Context, Result};
 use reqwest::Url;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, DisplayFromStr, DurationSeconds};
 use tokio::fs;
 
@@ -13,6 +13,8 @@ pub struct ObserverConfig {
     #[serde_as(as = "DisplayFromStr")]
     pub metrics_bind: SocketAddr,
     #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
     pub validators: Vec<ValidatorConfig>,
     #[serde(default)]
     pub telemetry: TelemetryConfig,
@@ -23,6 +25,28 @@ pub struct ObserverConfig {
     pub alerting: Option<AlertingConfig>,
     #[serde(default)]
     pub flamegraph: FlamegraphConfig,
+    #[serde(default)]
+    pub pipeline: Vec<PipelineTargetConfig>,
+    #[serde(default)]
+    pub pipeline_alerts: PipelineAlertConfig,
+    #[serde(default)]
+    pub votes: Option<VoteConfig>,
+    #[serde(default)]
+    pub leaders: Option<LeaderConfig>,
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+    #[serde(default)]
+    pub log_watch: Option<LogWatchConfig>,
+    #[serde(default)]
+    pub catchup: Option<CatchupConfig>,
+    #[serde(default)]
+    pub host: Option<HostConfig>,
+    #[serde(default)]
+    pub slo: Option<SloConfig>,
+    #[serde(default)]
+    pub anomaly: Option<AnomalyConfig>,
+    #[serde(default)]
+    pub fleet: Option<FleetConfig>,
 }
 
 impl ObserverConfig {
@@ -42,6 +66,50 @@ impl ObserverConfig {
     }
 }
 
+/// Transport security and access control of the HTTP server. Without
+/// tokens every endpoint is open, as suits a localhost bind.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub tls: Option<HttpTlsConfig>,
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+    /// Path prefixes reachable without a token once tokens are set.
+    #[serde(default = "default_public_paths")]
+    pub public: Vec<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            tls: None,
+            tokens: Vec::new(),
+            public: default_public_paths(),
+        }
+    }
+}
+
+fn default_public_paths() -> Vec<String> {
+    vec!["/healthz".into()]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpTlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+/// A bearer token and the endpoints it opens.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenConfig {
+    pub name: String,
+    pub token: String,
+    /// Path prefixes the token may reach, e.g. `/metrics` or
+    /// `/debug/flamegraph`; every endpoint when empty.
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize)]
 pub struct ValidatorConfig {
@@ -59,12 +127,509 @@ pub struct ValidatorConfig {
     #[serde(default)]
     #[serde_as(as = "Option<DurationSeconds<u64>>")]
     pub max_slot_interval: Option<Duration>,
+    /// Identity pubkey whose vote account the vote poller follows.
+    #[serde(default)]
+    pub identity: Option<String>,
 }
 
 fn default_slot_lookback() -> u64 {
     64
 }
 
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoteConfig {
+    /// Cluster RPC endpoint answering `getVoteAccounts`.
+    #[serde_as(as = "DisplayFromStr")]
+    pub rpc_url: Url,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub poll_interval: Option<Duration>,
+    /// Slots behind the cluster's newest vote before a validator counts as delinquent.
+    #[serde(default = "default_max_vote_distance")]
+    pub max_vote_distance: u64,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub credits_stall_after: Option<Duration>,
+}
+
+impl VoteConfig {
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+            .unwrap_or_else(|| Duration::from_secs(10))
+    }
+
+    pub fn credits_stall_after(&self) -> Duration {
+        self.credits_stall_after
+            .unwrap_or_else(|| Duration::from_secs(60))
+    }
+}
+
+fn default_max_vote_distance() -> u64 {
+    128
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeaderConfig {
+    /// Cluster RPC endpoint answering `getLeaderSchedule` and `getBlockProduction`.
+    #[serde_as(as = "DisplayFromStr")]
+    pub rpc_url: Url,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub poll_interval: Option<Duration>,
+    /// Rolling window the alerting skip rate is measured over.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub window: Option<Duration>,
+    #[serde(default = "default_max_skip_rate")]
+    pub max_skip_rate: f64,
+    /// Leader slots the window needs before its skip rate can alert.
+    #[serde(default = "default_min_leader_slots")]
+    pub min_leader_slots: u64,
+}
+
+impl LeaderConfig {
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+            .unwrap_or_else(|| Duration::from_secs(30))
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window.unwrap_or_else(|| Duration::from_secs(3600))
+    }
+}
+
+/// Reference RPC nodes the validators' slots are compared against.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatchupConfig {
+    pub references: Vec<ReferenceNodeConfig>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub poll_interval: Option<Duration>,
+    /// Slots behind the highest reference before a validator counts as behind.
+    #[serde(default = "default_max_slot_gap")]
+    pub max_slot_gap: u64,
+    /// Reference slots per second below which the whole cluster counts as slow.
+    #[serde(default = "default_min_cluster_slot_rate")]
+    pub min_cluster_slot_rate: f64,
+    /// How long either condition must hold before it alerts.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub sustain_for: Option<Duration>,
+}
+
+impl CatchupConfig {
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval.unwrap_or_else(|| Duration::from_secs(5))
+    }
+
+    pub fn sustain_for(&self) -> Duration {
+        self.sustain_for.unwrap_or_else(|| Duration::from_secs(60))
+    }
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReferenceNodeConfig {
+    pub name: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub rpc_url: Url,
+}
+
+fn default_max_slot_gap() -> u64 {
+    100
+}
+
+fn default_min_cluster_slot_rate() -> f64 {
+    1.5
+}
+
+/// Hardware of the host the validators run on, read from `/proc` and `/sys`
+/// or scraped from a node_exporter.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostConfig {
+    /// Scrape this node_exporter instead of reading the local host.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub node_exporter_url: Option<Url>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub poll_interval: Option<Duration>,
+    /// Block devices to report; every non-virtual device when empty.
+    #[serde(default)]
+    pub disks: Vec<String>,
+    /// Network interfaces to report; every one but loopback when empty.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// NVMe controllers whose SMART wear is read with `nvme smart-log`.
+    #[serde(default)]
+    pub nvme_devices: Vec<String>,
+    #[serde(default)]
+    pub alerts: HostAlertConfig,
+}
+
+impl HostConfig {
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval
+            .unwrap_or_else(|| Duration::from_secs(10))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HostAlertConfig {
+    pub max_disk_utilization: f64,
+    pub max_disk_latency_ms: f64,
+    /// Packets dropped or errored per second, both directions together.
+    pub max_nic_drops_per_sec: f64,
+    pub max_cpu_steal: f64,
+    pub max_temperature_celsius: f64,
+    /// Share of rated NVMe endurance used.
+    pub max_nvme_wear: f64,
+}
+
+impl Default for HostAlertConfig {
+    fn default() -> Self {
+        Self {
+            max_disk_utilization: 0.9,
+            max_disk_latency_ms: 20.0,
+            max_nic_drops_per_sec: 10.0,
+            max_cpu_steal: 0.05,
+            max_temperature_celsius: 85.0,
+            max_nvme_wear: 0.8,
+        }
+    }
+}
+
+/// Service level objectives computed from the observer's own metrics, with
+/// multi-window burn-rate alerts.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct SloConfig {
+    pub objectives: Vec<ObjectiveConfig>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub evaluation_interval: Option<Duration>,
+    #[serde(default = "default_burn_windows")]
+    pub burn_windows: Vec<BurnWindowConfig>,
+}
+
+impl SloConfig {
+    pub fn evaluation_interval(&self) -> Duration {
+        self.evaluation_interval
+            .unwrap_or_else(|| Duration::from_secs(30))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ObjectiveConfig {
+    pub name: String,
+    /// Share of events that must be good, e.g. `0.999`.
+    pub target: f64,
+    /// Label whose values the objective is tracked per.
+    #[serde(default = "default_subject_label")]
+    pub subject_label: String,
+    #[serde(flatten)]
+    pub sli: SliConfig,
+}
+
+fn default_subject_label() -> String {
+    "validator".into()
+}
+
+/// How good and total events are counted. Metric names leave out the
+/// `solana_validator_observer_` prefix.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "sli", rename_all = "snake_case")]
+pub enum SliConfig {
+    /// Events counted by the `total` counter but not the `errors` one are good.
+    Ratio { errors: String, total: String },
+    /// Observations of `histogram` at or under `threshold`, which must be one
+    /// of its bucket bounds, are good.
+    Latency { histogram: String, threshold: f64 },
+}
+
+/// Fires `alert` while the error budget burns more than `factor` times
+/// faster than the target allows over both `long` and `short`.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct BurnWindowConfig {
+    pub alert: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub long: Duration,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub short: Duration,
+    pub factor: f64,
+}
+
+fn default_burn_windows() -> Vec<BurnWindowConfig> {
+    let window = |alert: &str, long: u64, short: u64, factor: f64| BurnWindowConfig {
+        alert: alert.into(),
+        long: Duration::from_secs(long),
+        short: Duration::from_secs(short),
+        factor,
+    };
+    // 2% and 5% of a 30 day budget in an hour and six hours page; 10% in
+    // three days opens a ticket
+    vec![
+        window("slo_page", 3_600, 300, 14.4),
+        window("slo_page", 21_600, 1_800, 6.0),
+        window("slo_ticket", 259_200, 21_600, 1.0),
+    ]
+}
+
+/// Baselines learned over the observer's own gauges, alerting on sharp
+/// deviations instead of fixed thresholds.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnomalyConfig {
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub evaluation_interval: Option<Duration>,
+    #[serde(default = "default_anomaly_series")]
+    pub series: Vec<AnomalySeriesConfig>,
+}
+
+impl AnomalyConfig {
+    pub fn evaluation_interval(&self) -> Duration {
+        self.evaluation_interval
+            .unwrap_or_else(|| Duration::from_secs(10))
+    }
+}
+
+/// A gauge, named without the `solana_validator_observer_` prefix, tracked
+/// per label set against an exponentially weighted mean and variance.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnomalySeriesConfig {
+    pub metric: String,
+    /// Standard deviations from the baseline at which a value is anomalous.
+    #[serde(default = "default_anomaly_sensitivity")]
+    pub sensitivity: f64,
+    /// Floor on the standard deviation, so near-constant series do not alert
+    /// on tiny moves.
+    #[serde(default)]
+    pub min_deviation: f64,
+    #[serde(default)]
+    pub direction: Direction,
+    /// Age at which a sample weighs half as much in the baseline.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub half_life: Option<Duration>,
+    /// Samples learned before the series can alert.
+    #[serde(default = "default_anomaly_warmup")]
+    pub warmup: u64,
+}
+
+impl AnomalySeriesConfig {
+    pub fn half_life(&self) -> Duration {
+        self.half_life.unwrap_or_else(|| Duration::from_secs(1800))
+    }
+}
+
+/// Which side of the baseline counts as anomalous.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Above,
+    Below,
+    Both,
+}
+
+fn default_anomaly_sensitivity() -> f64 {
+    4.0
+}
+
+fn default_anomaly_warmup() -> u64 {
+    30
+}
+
+fn default_anomaly_series() -> Vec<AnomalySeriesConfig> {
+    let series = |metric: &str, min_deviation: f64| AnomalySeriesConfig {
+        metric: metric.into(),
+        sensitivity: default_anomaly_sensitivity(),
+        min_deviation,
+        direction: Direction::Above,
+        half_life: None,
+        warmup: default_anomaly_warmup(),
+    };
+    vec![
+        series("pipeline_queue_saturation", 0.02),
+        series("pipeline_drop_ratio", 0.001),
+        series("pipeline_encode_latency_seconds", 0.000_01),
+    ]
+}
+
+/// Per-validator metrics compared across the whole monitored fleet.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct FleetConfig {
+    /// Also the window histogram quantiles are taken over.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub evaluation_interval: Option<Duration>,
+    #[serde(default = "default_fleet_comparisons")]
+    pub comparisons: Vec<ComparisonConfig>,
+}
+
+impl FleetConfig {
+    pub fn evaluation_interval(&self) -> Duration {
+        self.evaluation_interval
+            .unwrap_or_else(|| Duration::from_secs(60))
+    }
+}
+
+/// One metric per validator, named without the `solana_validator_observer_`
+/// prefix: a gauge, or with `quantile` set a histogram. A validator whose
+/// value is more than `max_ratio` times the median of its peers fires
+/// `fleet_outlier`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComparisonConfig {
+    pub name: String,
+    pub metric: String,
+    #[serde(default)]
+    pub quantile: Option<f64>,
+    #[serde(default = "default_max_peer_ratio")]
+    pub max_ratio: f64,
+    /// Peers with a value needed before a validator can be compared.
+    #[serde(default = "default_min_peers")]
+    pub min_peers: usize,
+}
+
+fn default_max_peer_ratio() -> f64 {
+    3.0
+}
+
+fn default_min_peers() -> usize {
+    2
+}
+
+fn default_fleet_comparisons() -> Vec<ComparisonConfig> {
+    [
+        ("rpc-latency-p99", "rpc_request_latency_seconds"),
+        ("gossip-rtt-p99", "gossip_rtt_seconds"),
+        ("slot-propagation-p99", "slot_propagation_delay_seconds"),
+    ]
+    .into_iter()
+    .map(|(name, metric)| ComparisonConfig {
+        name: name.into(),
+        metric: metric.into(),
+        quantile: Some(0.99),
+        max_ratio: default_max_peer_ratio(),
+        min_peers: default_min_peers(),
+    })
+    .collect()
+}
+
+/// On-disk history of the observer's own metrics.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub sample_interval: Option<Duration>,
+    /// Span of one partition file; retention drops whole partitions.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub partition: Option<Duration>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub retention: Option<Duration>,
+}
+
+impl HistoryConfig {
+    pub fn sample_interval(&self) -> Duration {
+        self.sample_interval
+            .unwrap_or_else(|| Duration::from_secs(10))
+    }
+
+    pub fn partition(&self) -> Duration {
+        self.partition.unwrap_or_else(|| Duration::from_secs(3600))
+    }
+
+    pub fn retention(&self) -> Duration {
+        self.retention
+            .unwrap_or_else(|| Duration::from_secs(24 * 3600))
+    }
+}
+
+/// Log files and journald units to tail for known failure patterns.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogWatchConfig {
+    #[serde(default)]
+    pub files: Vec<LogFileConfig>,
+    /// Units followed with `journalctl -f`.
+    #[serde(default)]
+    pub journald_units: Vec<String>,
+    #[serde(default = "default_log_rules")]
+    pub rules: Vec<LogRuleConfig>,
+    /// How often a file at its end is checked for new lines or rotation.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub poll_interval: Option<Duration>,
+}
+
+impl LogWatchConfig {
+    pub fn poll_interval(&self) -> Duration {
+        self.poll_interval.unwrap_or_else(|| Duration::from_secs(1))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogFileConfig {
+    /// Source label on metrics and alert subject; a validator or plugin name.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// A regex matched against every log line; its name is the alert it fires.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogRuleConfig {
+    pub name: String,
+    pub pattern: String,
+    /// Least time between two alerts for this rule and source; matches in
+    /// between are only counted.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub rate_limit: Option<Duration>,
+}
+
+impl LogRuleConfig {
+    pub fn rate_limit(&self) -> Duration {
+        self.rate_limit.unwrap_or_else(|| Duration::from_secs(60))
+    }
+}
+
+fn default_log_rules() -> Vec<LogRuleConfig> {
+    [
+        ("panic", r"panicked at"),
+        ("writer_not_terminated", r"writer did not terminate"),
+        ("bank_hash_mismatch", r"(?i)bank hash mismatch"),
+    ]
+    .into_iter()
+    .map(|(name, pattern)| LogRuleConfig {
+        name: name.into(),
+        pattern: pattern.into(),
+        rate_limit: None,
+    })
+    .collect()
+}
+
+fn default_max_skip_rate() -> f64 {
+    0.1
+}
+
+fn default_min_leader_slots() -> u64 {
+    8
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct TelemetryConfig {
@@ -103,12 +668,23 @@ impl TelemetryConfig {
 #[serde_as]
 #[derive(Debug, Clone, Deserialize)]
 pub struct AlertingConfig {
-    #[serde_as(as = "DisplayFromStr")]
-    pub webhook_url: Url,
+    /// Shorthand for a generic webhook receiver named `default`.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub webhook_url: Option<Url>,
     pub slot_lag_threshold: u64,
+    /// Dedupe window of alerts whose rule sets none.
     #[serde(default)]
     #[serde_as(as = "Option<DurationSeconds<u64>>")]
     pub cooldown: Option<Duration>,
+    #[serde(default)]
+    pub receivers: Vec<ReceiverConfig>,
+    #[serde(default)]
+    pub rules: Vec<AlertRuleConfig>,
+    #[serde(default)]
+    pub actions: Vec<ActionConfig>,
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceWindowConfig>,
 }
 
 impl AlertingConfig {
@@ -117,6 +693,133 @@ impl AlertingConfig {
     }
 }
 
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReceiverConfig {
+    pub name: String,
+    pub kind: ReceiverKind,
+    #[serde_as(as = "DisplayFromStr")]
+    pub url: Url,
+    /// PagerDuty Events API v2 integration key.
+    #[serde(default)]
+    pub routing_key: Option<String>,
+    /// Alerts below this severity skip the receiver.
+    #[serde(default)]
+    pub min_severity: Option<Severity>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReceiverKind {
+    Webhook,
+    Slack,
+    Pagerduty,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        }
+    }
+}
+
+/// Routing for one alert: `slot_lag`, `delinquent`, `vote_credits_stalled`,
+/// `skip_rate`, `catchup_behind`, `cluster_slow`, a host alert (`disk_utilization`,
+/// `disk_latency`, `nic_drops`, `cpu_steal`, `temperature`, `nvme_wear`), a
+/// pipeline alert (`down`, `drop_ratio`, `queue_saturation`, `stale`), an SLO
+/// burn window alert (`slo_page`, `slo_ticket` by default),
+/// `anomalous_deviation`, `fleet_outlier` or a log watch rule name.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct AlertRuleConfig {
+    pub alert: String,
+    #[serde(default)]
+    pub severity: Severity,
+    /// Receivers to notify; every receiver when empty.
+    #[serde(default)]
+    pub receivers: Vec<String>,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub dedupe_window: Option<Duration>,
+    /// Remediation actions to run when the alert fires.
+    #[serde(default)]
+    pub actions: Vec<String>,
+}
+
+/// A recurring window, such as a weekly upgrade slot, during which matching
+/// alerts are recorded at `/silences/suppressed` instead of being sent.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceWindowConfig {
+    pub name: String,
+    /// Cron expression in UTC for when each window starts, e.g. `0 3 * * 2`.
+    pub schedule: String,
+    #[serde_as(as = "DurationSeconds<u64>")]
+    pub duration: Duration,
+    /// Alerts held back; every alert when empty.
+    #[serde(default)]
+    pub alerts: Vec<String>,
+    /// Validators whose alerts are held back; every subject when empty.
+    #[serde(default)]
+    pub validators: Vec<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// A known recovery run when an alert whose rule names it fires.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ActionConfig {
+    pub name: String,
+    pub kind: ActionKind,
+    /// Program and arguments of a `script` action.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Endpoint of a `webhook` action.
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(default)]
+    pub url: Option<Url>,
+    /// Unit a `systemd_restart` action restarts.
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Only run for alerts about this subject; any when left out.
+    #[serde(default)]
+    pub subject: Option<String>,
+    /// Least time between two runs for the same subject.
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub cooldown: Option<Duration>,
+    /// Log and count what would run without running it.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ActionConfig {
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown.unwrap_or_else(|| Duration::from_secs(600))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Script,
+    Webhook,
+    SystemdRestart,
+}
+
 #[serde_as]
 #[derive(Debug, Clone, Deserialize)]
 pub struct FlamegraphConfig {
@@ -125,6 +828,9 @@ pub struct FlamegraphConfig {
     #[serde(default)]
     #[serde_as(as = "Option<DurationSeconds<u64>>")]
     pub refresh_interval: Option<Duration>,
+    /// Finished profiles, one per refresh interval, kept for diffing.
+    #[serde(default = "default_retained_profiles")]
+    pub retained_profiles: usize,
 }
 
 impl Default for FlamegraphConfig {
@@ -132,6 +838,7 @@ impl Default for FlamegraphConfig {
         Self {
             enabled: true,
             refresh_interval: Some(Duration::from_secs(30)),
+            retained_profiles: default_retained_profiles(),
         }
     }
 }
@@ -147,6 +854,60 @@ fn default_flamegraph_enabled() -> bool {
     true
 }
 
+fn default_retained_profiles() -> usize {
+    20
+}
+
+/// A Numistack component (geyser plugin, ys-consumer, aggregator, rpc bridge)
+/// whose Prometheus endpoint the observer scrapes.
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineTargetConfig {
+    pub name: String,
+    #[serde_as(as = "DisplayFromStr")]
+    pub metrics_url: Url,
+    /// Capacity of the writer queues behind `ultra_queue_len`, which the
+    /// components do not export themselves.
+    #[serde(default)]
+    pub queue_capacity: Option<u64>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Deserialize)]
+pub struct PipelineAlertConfig {
+    #[serde(default = "default_max_drop_ratio")]
+    pub max_drop_ratio: f64,
+    #[serde(default = "default_max_queue_saturation")]
+    pub max_queue_saturation: f64,
+    #[serde(default)]
+    #[serde_as(as = "Option<DurationSeconds<u64>>")]
+    pub stale_after: Option<Duration>,
+}
+
+impl Default for PipelineAlertConfig {
+    fn default() -> Self {
+        Self {
+            max_drop_ratio: default_max_drop_ratio(),
+            max_queue_saturation: default_max_queue_saturation(),
+            stale_after: None,
+        }
+    }
+}
+
+impl PipelineAlertConfig {
+    pub fn stale_after(&self) -> Duration {
+        self.stale_after.unwrap_or_else(|| Duration::from_secs(30))
+    }
+}
+
+fn default_max_drop_ratio() -> f64 {
+    0.01
+}
+
+fn default_max_queue_saturation() -> f64 {
+    0.8
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,6 +948,35 @@ mod tests {
         assert_eq!(cfg.telemetry.ring_buffer_interval().as_secs(), 10);
     }
 
+    #[tokio::test]
+    async fn load_parses_pipeline_targets() {
+        let config_toml = r#"
+            metrics_bind = "127.0.0.1:9090"
+
+            [[pipeline]]
+            name = "geyser"
+            metrics_url = "http://127.0.0.1:9102/metrics"
+            queue_capacity = 65536
+
+            [[pipeline]]
+            name = "bridge"
+            metrics_url = "http://127.0.0.1:9103/metrics"
+
+            [pipeline_alerts]
+            max_drop_ratio = 0.05
+        "#;
+        let file = write_temp_config(config_toml);
+        let cfg = ObserverConfig::load(file.path())
+            .await
+            .expect("load config");
+        assert_eq!(cfg.pipeline.len(), 2);
+        assert_eq!(cfg.pipeline[0].queue_capacity, Some(65536));
+        assert_eq!(cfg.pipeline[1].queue_capacity, None);
+        assert_eq!(cfg.pipeline_alerts.max_drop_ratio, 0.05);
+        assert_eq!(cfg.pipeline_alerts.max_queue_saturation, 0.8);
+        assert_eq!(cfg.pipeline_alerts.stale_after().as_secs(), 30);
+    }
+
     #[tokio::test]
     async fn load_rejects_invalid_toml() {
         let config_toml = "not = [valid";
@@ -210,6 +1000,7 @@ mod tests {
         let cfg = FlamegraphConfig {
             enabled: false,
             refresh_interval: None,
+            retained_profiles: 20,
         };
         assert!(!cfg.enabled);
         assert_eq!(cfg.refresh_interval().as_secs(), 30);
@@ -219,10 +1010,135 @@ mod tests {
     fn alerting_default_cooldown() {
         let url = Url::parse("https://example.com/webhook").unwrap();
         let cfg = AlertingConfig {
-            webhook_url: url,
+            webhook_url: Some(url),
             slot_lag_threshold: 50,
             cooldown: None,
+            receivers: Vec::new(),
+            rules: Vec::new(),
+            actions: Vec::new(),
+            maintenance: Vec::new(),
         };
         assert_eq!(cfg.cooldown().as_secs(), 30);
     }
+
+    #[tokio::test]
+    async fn load_parses_log_watch() {
+        let config_toml = r#"
+            metrics_bind = "127.0.0.1:9090"
+
+            [log_watch]
+            journald_units = ["solana-validator"]
+
+            [[log_watch.files]]
+            name = "geyser"
+            path = "/var/log/geyser.log"
+        "#;
+        let file = write_temp_config(config_toml);
+        let cfg = ObserverConfig::load(file.path())
+            .await
+            .expect("load config");
+        let log_watch = cfg.log_watch.expect("log watch section");
+        assert_eq!(log_watch.files[0].name, "geyser");
+        assert_eq!(log_watch.journald_units, vec!["solana-validator"]);
+        let rules: Vec<&str> = log_watch.rules.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            rules,
+            vec!["panic", "writer_not_terminated", "bank_hash_mismatch"]
+        );
+        assert_eq!(log_watch.rules[0].rate_limit().as_secs(), 60);
+        assert_eq!(log_watch.poll_interval().as_secs(), 1);
+    }
+
+    #[tokio::test]
+    async fn load_parses_slo_objectives() {
+        let config_toml = r#"
+            metrics_bind = "127.0.0.1:9090"
+
+            [[slo.objectives]]
+            name = "scrapes"
+            target = 0.999
+            sli = "ratio"
+            errors = "scrape_errors_total"
+            total = "scrapes_total"
+
+            [[slo.objectives]]
+            name = "rpc-latency"
+            target = 0.99
+            sli = "latency"
+            histogram = "rpc_request_latency_seconds"
+            threshold = 0.05
+        "#;
+        let file = write_temp_config(config_toml);
+        let cfg = ObserverConfig::load(file.path())
+            .await
+            .expect("load config");
+        let slo = cfg.slo.expect("slo section");
+        assert!(matches!(
+            &slo.objectives[0].sli,
+            SliConfig::Ratio { total, .. } if total == "scrapes_total"
+        ));
+        assert!(matches!(
+            slo.objectives[1].sli,
+            SliConfig::Latency { threshold, .. } if threshold == 0.05
+        ));
+        assert_eq!(slo.burn_windows.len(), 3);
+        assert_eq!(slo.evaluation_interval().as_secs(), 30);
+    }
+
+    #[tokio::test]
+    async fn load_parses_anomaly_series() {
+        let config_toml = r#"
+            metrics_bind = "127.0.0.1:9090"
+
+            [anomaly]
+            evaluation_interval = 5
+
+            [[anomaly.series]]
+            metric = "slot_lag"
+            sensitivity = 3.0
+            direction = "both"
+        "#;
+        let file = write_temp_config(config_toml);
+        let cfg = ObserverConfig::load(file.path())
+            .await
+            .expect("load config");
+        let anomaly = cfg.anomaly.expect("anomaly section");
+        assert_eq!(anomaly.evaluation_interval().as_secs(), 5);
+        let series = &anomaly.series[0];
+        assert_eq!(series.sensitivity, 3.0);
+        assert_eq!(series.direction, Direction::Both);
+        assert_eq!(series.warmup, 30);
+        assert_eq!(series.half_life().as_secs(), 1800);
+
+        let defaults: AnomalyConfig = toml::from_str("").expect("empty anomaly section");
+        assert_eq!(defaults.series.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn load_parses_fleet_comparisons() {
+        let config_toml = r#"
+            metrics_bind = "127.0.0.1:9090"
+
+            [[fleet.comparisons]]
+            name = "slot-lag"
+            metric = "slot_lag"
+            max_ratio = 5.0
+        "#;
+        let file = write_temp_config(config_toml);
+        let cfg = ObserverConfig::load(file.path())
+            .await
+            .expect("load config");
+        let fleet = cfg.fleet.expect("fleet section");
+        assert_eq!(fleet.evaluation_interval().as_secs(), 60);
+        let comparison = &fleet.comparisons[0];
+        assert_eq!(comparison.quantile, None);
+        assert_eq!(comparison.max_ratio, 5.0);
+        assert_eq!(comparison.min_peers, 2);
+
+        let defaults: FleetConfig = toml::from_str("").expect("empty fleet section");
+        assert!(defaults
+            .comparisons
+            .iter()
+            .all(|comparison| comparison.quantile == Some(0.99)));
+    }
 }
@@ -2,13 +2,668 @@
 use std::path::Path;
 
 use anyhow::{Context, Result};
+use serde_json::{json, Value};
 use tokio::fs;
 
-pub const DASHBOARD_JSON: &str =
-    include_str!("../../../ops/solana-validator-observer-dashboard.json");
+use crate::{
+    config::{ObserverConfig, PipelineTargetConfig},
+    pipeline,
+    state::{ComponentKind, PipelineSnapshot},
+};
 
-pub async fn write_to(path: &Path) -> Result<()> {
-    fs::write(path, DASHBOARD_JSON)
+const PREFIX: &str = "solana_validator_observer_";
+const GRID_WIDTH: u32 = 24;
+const PANEL_WIDTH: u32 = 8;
+const PANEL_HEIGHT: u32 = 8;
+
+/// What a generated dashboard covers: the configured validators and
+/// sections, and the pipeline components as discovered.
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub validators: Vec<String>,
+    pub components: Vec<Component>,
+    pub votes: bool,
+    pub leaders: bool,
+    pub catchup: bool,
+    pub host: bool,
+    pub slo: bool,
+    pub fleet: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Component {
+    pub name: String,
+    /// `host:port` of the metrics endpoint, the `instance` label Prometheus
+    /// gives the component's own series.
+    pub instance: String,
+    /// `None` until a scrape showed what it is.
+    pub kind: Option<ComponentKind>,
+    pub shards: Vec<String>,
+}
+
+impl Inventory {
+    pub fn from_config(config: &ObserverConfig) -> Self {
+        Self {
+            validators: config.validators.iter().map(|v| v.name.clone()).collect(),
+            components: config.pipeline.iter().map(Component::new).collect(),
+            votes: config.votes.is_some(),
+            leaders: config.leaders.is_some(),
+            catchup: config.catchup.is_some(),
+            host: config.host.is_some(),
+            slo: config.slo.is_some(),
+            fleet: config.fleet.is_some(),
+        }
+    }
+
+    /// Take what the pipeline scrapers found out about each component.
+    pub fn discovered(mut self, snapshots: &[PipelineSnapshot]) -> Self {
+        for component in &mut self.components {
+            if let Some(snapshot) = snapshots.iter().find(|s| s.name == component.name) {
+                component.kind = snapshot.component.or(component.kind);
+                if !snapshot.shards.is_empty() {
+                    component.shards = snapshot.shards.clone();
+                }
+            }
+        }
+        self
+    }
+
+    fn shards(&self) -> Vec<String> {
+        let mut shards: Vec<String> = self
+            .components
+            .iter()
+            .flat_map(|component| component.shards.iter().cloned())
+            .collect();
+        shards.sort_by(|a, b| {
+            a.parse::<u64>()
+                .ok()
+                .cmp(&b.parse::<u64>().ok())
+                .then_with(|| a.cmp(b))
+        });
+        shards.dedup();
+        shards
+    }
+}
+
+impl Component {
+    fn new(target: &PipelineTargetConfig) -> Self {
+        let url = &target.metrics_url;
+        let host = url.host_str().unwrap_or_default();
+        Self {
+            name: target.name.clone(),
+            instance: match url.port_or_known_default() {
+                Some(port) => format!("{host}:{port}"),
+                None => host.to_string(),
+            },
+            kind: None,
+            shards: Vec::new(),
+        }
+    }
+}
+
+/// Scrape each pipeline target once to see what it is; targets that do not
+/// answer keep generic panels.
+pub async fn discover(config: &ObserverConfig) -> Inventory {
+    let mut inventory = Inventory::from_config(config);
+    for (component, target) in inventory.components.iter_mut().zip(&config.pipeline) {
+        match pipeline::discover(target).await {
+            Ok((kind, shards)) => {
+                component.kind = kind;
+                component.shards = shards;
+            }
+            Err(err) => {
+                tracing::warn!(component = %target.name, error = %err, "failed to discover pipeline component");
+            }
+        }
+    }
+    inventory
+}
+
+pub async fn write_to(path: &Path, inventory: &Inventory) -> Result<()> {
+    let body =
+        serde_json::to_vec_pretty(&generate(inventory)).context("failed to encode dashboard")?;
+    fs::write(path, body)
         .await
         .with_context(|| format!("failed to write grafana dashboard to {}", path.display()))
 }
+
+/// Grafana dashboard JSON for `inventory`.
+pub fn generate(inventory: &Inventory) -> Value {
+    let mut layout = Layout::default();
+
+    layout.repeated_row("Validator $validator", "validator");
+    let validator = r#"validator="$validator""#;
+    layout.panel(
+        "Slot Lag",
+        "timeseries",
+        "none",
+        &[(format!("{PREFIX}slot_lag{{{validator}}}"), "lag")],
+    );
+    layout.panel(
+        "Slot Propagation p95",
+        "stat",
+        "s",
+        &[(
+            quantile(0.95, "slot_propagation_delay_seconds", validator),
+            "p95",
+        )],
+    );
+    layout.panel(
+        "RPC Latency p99",
+        "timeseries",
+        "s",
+        &[(
+            quantile(0.99, "rpc_request_latency_seconds", validator),
+            "p99",
+        )],
+    );
+    layout.panel(
+        "Gossip RTT p95",
+        "stat",
+        "s",
+        &[(quantile(0.95, "gossip_rtt_seconds", validator), "p95")],
+    );
+    layout.panel(
+        "Packet Loss Ratio",
+        "gauge",
+        "percentunit",
+        &[(format!("{PREFIX}packet_loss_ratio{{{validator}}}"), "loss")],
+    );
+    layout.panel(
+        "Scrape Errors",
+        "timeseries",
+        "ops",
+        &[(
+            format!("sum by (protocol) (rate({PREFIX}scrape_errors_total{{{validator}}}[5m]))"),
+            "{{protocol}}",
+        )],
+    );
+    if inventory.catchup {
+        layout.panel(
+            "Slots Behind Reference",
+            "timeseries",
+            "none",
+            &[(format!("{PREFIX}catchup_slot_gap{{{validator}}}"), "gap")],
+        );
+    }
+    if inventory.votes {
+        layout.panel(
+            "Vote Distance",
+            "timeseries",
+            "none",
+            &[(
+                format!("{PREFIX}vote_distance_slots{{{validator}}}"),
+                "distance",
+            )],
+        );
+    }
+    if inventory.leaders {
+        layout.panel(
+            "Skip Rate",
+            "timeseries",
+            "percentunit",
+            &[(format!("{PREFIX}skip_rate{{{validator}}}"), "{{window}}")],
+        );
+    }
+
+    for component in &inventory.components {
+        component_row(&mut layout, component);
+    }
+
+    if inventory.host {
+        host_row(&mut layout);
+    }
+
+    if inventory.slo {
+        layout.row("Service Level Objectives");
+        layout.panel(
+            "Burn Rate",
+            "timeseries",
+            "none",
+            &[(
+                format!("{PREFIX}slo_burn_rate"),
+                "{{slo}} {{subject}} {{window}}",
+            )],
+        );
+        layout.panel(
+            "Burn-Rate Alerts Firing",
+            "stat",
+            "none",
+            &[(
+                format!("sum by (slo, subject) ({PREFIX}slo_alert_firing)"),
+                "{{slo}} {{subject}}",
+            )],
+        );
+    }
+
+    if inventory.fleet {
+        layout.row("Fleet Comparison");
+        layout.panel(
+            "Value vs Fleet Median",
+            "timeseries",
+            "none",
+            &[
+                (
+                    format!("{PREFIX}fleet_value{{{validator}}}"),
+                    "{{comparison}} {{validator}}",
+                ),
+                (format!("{PREFIX}fleet_median"), "{{comparison}} median"),
+            ],
+        );
+        layout.panel(
+            "Ratio to Peer Median",
+            "timeseries",
+            "none",
+            &[(
+                format!("{PREFIX}fleet_peer_ratio{{{validator}}}"),
+                "{{comparison}} {{validator}}",
+            )],
+        );
+        layout.panel(
+            "Outliers",
+            "stat",
+            "none",
+            &[(
+                format!("sum by (validator) ({PREFIX}fleet_outlier_firing)"),
+                "{{validator}}",
+            )],
+        );
+    }
+
+    json!({
+        "annotations": {
+            "list": [{
+                "builtIn": 1,
+                "datasource": "-- Grafana --",
+                "enable": true,
+                "hide": true,
+                "iconColor": "rgba(0, 211, 255, 1)",
+                "name": "Annotations & Alerts",
+                "type": "dashboard",
+            }],
+        },
+        "editable": true,
+        "fiscalYearStartMonth": 0,
+        "graphTooltip": 0,
+        "links": [],
+        "liveNow": false,
+        "panels": layout.panels,
+        "refresh": "30s",
+        "schemaVersion": 38,
+        "style": "dark",
+        "tags": ["solana", "validator", "observer"],
+        "templating": {
+            "list": [
+                variable("validator", "Validator", &inventory.validators),
+                variable("shard", "Shard", &inventory.shards()),
+            ],
+        },
+        "time": {"from": "now-6h", "to": "now"},
+        "timepicker": {},
+        "timezone": "",
+        "title": "Solana Validator Observer",
+        "version": 1,
+    })
+}
+
+fn component_row(layout: &mut Layout, component: &Component) {
+    let kind = component.kind.map_or("component", ComponentKind::as_str);
+    layout.row(&format!("{} ({kind})", component.name));
+    let target = format!(r#"target="{}""#, component.name);
+    layout.panel(
+        "Up",
+        "stat",
+        "none",
+        &[(format!("{PREFIX}pipeline_up{{{target}}}"), "up")],
+    );
+    layout.panel(
+        "Drop Ratio",
+        "timeseries",
+        "percentunit",
+        &[(format!("{PREFIX}pipeline_drop_ratio{{{target}}}"), "drops")],
+    );
+    layout.panel(
+        "Seconds Since Last Record",
+        "timeseries",
+        "s",
+        &[(
+            format!("{PREFIX}pipeline_seconds_since_last_record{{{target}}}"),
+            "age",
+        )],
+    );
+
+    let instance = format!(r#"instance="{}""#, component.instance);
+    let sharded = format!(r#"{instance},shard=~"$shard""#);
+    match component.kind {
+        Some(ComponentKind::Plugin) => {
+            layout.panel(
+                "Enqueued / Dropped",
+                "timeseries",
+                "ops",
+                &[
+                    (
+                        format!("sum(rate(ultra_enqueued_total{{{instance}}}[1m]))"),
+                        "enqueued",
+                    ),
+                    (
+                        format!("sum(rate(ultra_dropped_total{{{instance}}}[1m]))"),
+                        "dropped",
+                    ),
+                ],
+            );
+            layout.panel(
+                "Writer Queue Length",
+                "timeseries",
+                "none",
+                &[(format!("ultra_queue_len{{{sharded}}}"), "shard {{shard}}")],
+            );
+        }
+        Some(ComponentKind::Consumer) => {
+            layout.panel(
+                "Write Batches",
+                "timeseries",
+                "ops",
+                &[(
+                    format!(
+                        "sum by (shard) (rate(ys_consumer_write_batches_total{{{sharded}}}[1m]))"
+                    ),
+                    "shard {{shard}}",
+                )],
+            );
+            layout.panel(
+                "Queue Length",
+                "timeseries",
+                "none",
+                &[(format!("ultra_queue_len{{{sharded}}}"), "shard {{shard}}")],
+            );
+            layout.panel(
+                "Dropped",
+                "timeseries",
+                "ops",
+                &[(
+                    format!("sum(rate(ys_consumer_dropped_total{{{instance}}}[1m]))"),
+                    "dropped",
+                )],
+            );
+        }
+        Some(ComponentKind::Aggregator) => {
+            layout.panel(
+                "Frames Received",
+                "timeseries",
+                "ops",
+                &[(
+                    format!("sum(rate(ultra_conn_frames_total{{{instance}}}[1m]))"),
+                    "frames",
+                )],
+            );
+            layout.panel(
+                "Duplicates by Source",
+                "timeseries",
+                "ops",
+                &[(
+                    format!(
+                        "sum by (source) (rate(ultra_dedup_duplicates_total{{{instance}}}[1m]))"
+                    ),
+                    "{{source}}",
+                )],
+            );
+            layout.panel(
+                "Decode Pool Drops",
+                "timeseries",
+                "ops",
+                &[(
+                    format!("sum(rate(ultra_decode_pool_dropped_total{{{instance}}}[1m]))"),
+                    "dropped",
+                )],
+            );
+        }
+        Some(ComponentKind::Rpc) => {
+            layout.panel(
+                "Ingest Publish p99",
+                "timeseries",
+                "ms",
+                &[(
+                    format!(
+                        "histogram_quantile(0.99, sum(rate(ultra_ingest_publish_ms_bucket{{{instance}}}[5m])) by (le))"
+                    ),
+                    "p99",
+                )],
+            );
+            layout.panel(
+                "Seconds Since Last Delta",
+                "timeseries",
+                "s",
+                &[(
+                    format!("rpc_bridge_seconds_since_last_delta{{{instance}}}"),
+                    "age",
+                )],
+            );
+        }
+        None => {}
+    }
+}
+
+fn host_row(layout: &mut Layout) {
+    layout.row("Host");
+    let panels: [(&str, &str, &str, String, &str); 6] = [
+        (
+            "Host Disk Utilization",
+            "timeseries",
+            "percentunit",
+            format!("{PREFIX}host_disk_utilization"),
+            "{{device}}",
+        ),
+        (
+            "Host Disk Latency",
+            "timeseries",
+            "s",
+            format!("{PREFIX}host_disk_latency_seconds"),
+            "{{device}}",
+        ),
+        (
+            "Host NIC Drops + Errors",
+            "timeseries",
+            "pps",
+            format!("{PREFIX}host_nic_drops_per_second + {PREFIX}host_nic_errors_per_second"),
+            "{{interface}}",
+        ),
+        (
+            "Host CPU Steal",
+            "timeseries",
+            "percentunit",
+            format!("{PREFIX}host_cpu_steal_ratio"),
+            "steal",
+        ),
+        (
+            "Host Temperatures",
+            "timeseries",
+            "celsius",
+            format!("{PREFIX}host_temperature_celsius"),
+            "{{sensor}}",
+        ),
+        (
+            "NVMe Wear",
+            "gauge",
+            "percentunit",
+            format!("{PREFIX}host_nvme_wear_ratio"),
+            "{{device}}",
+        ),
+    ];
+    for (title, kind, unit, expr, legend) in panels {
+        layout.panel(title, kind, unit, &[(expr, legend)]);
+    }
+}
+
+fn quantile(q: f64, histogram: &str, selector: &str) -> String {
+    format!(
+        "histogram_quantile({q}, sum(rate({PREFIX}{histogram}_bucket{{{selector}}}[1m])) by (le))"
+    )
+}
+
+fn variable(name: &str, label: &str, values: &[String]) -> Value {
+    json!({
+        "type": "custom",
+        "name": name,
+        "label": label,
+        "query": values.join(","),
+        "options": values
+            .iter()
+            .map(|value| json!({"text": value, "value": value, "selected": false}))
+            .collect::<Vec<_>>(),
+        "current": {"text": "All", "value": "$__all"},
+        "includeAll": true,
+        "multi": true,
+        "hide": if values.is_empty() { 2 } else { 0 },
+        "skipUrlSync": false,
+    })
+}
+
+/// Places panels left to right in rows of three under full-width row headers.
+#[derive(Default)]
+struct Layout {
+    panels: Vec<Value>,
+    next_id: u32,
+    x: u32,
+    y: u32,
+}
+
+impl Layout {
+    fn id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn row(&mut self, title: &str) {
+        self.push_row(json!({"title": title}));
+    }
+
+    /// A row Grafana repeats, with its panels, for every selected value of
+    /// `variable`.
+    fn repeated_row(&mut self, title: &str, variable: &str) {
+        self.push_row(json!({"title": title, "repeat": variable}));
+    }
+
+    fn push_row(&mut self, mut row: Value) {
+        if self.x > 0 {
+            self.x = 0;
+            self.y += PANEL_HEIGHT;
+        }
+        let id = self.id();
+        let fields = row.as_object_mut().expect("row is an object");
+        fields.insert("type".into(), json!("row"));
+        fields.insert("id".into(), json!(id));
+        fields.insert("collapsed".into(), json!(false));
+        fields.insert("panels".into(), json!([]));
+        fields.insert(
+            "gridPos".into(),
+            json!({"h": 1, "w": GRID_WIDTH, "x": 0, "y": self.y}),
+        );
+        self.panels.push(row);
+        self.y += 1;
+    }
+
+    fn panel(&mut self, title: &str, kind: &str, unit: &str, targets: &[(String, &str)]) {
+        if self.x + PANEL_WIDTH > GRID_WIDTH {
+            self.x = 0;
+            self.y += PANEL_HEIGHT;
+        }
+        let id = self.id();
+        let ref_ids = ["A", "B", "C", "D"];
+        self.panels.push(json!({
+            "datasource": {"type": "prometheus", "uid": "prometheus"},
+            "fieldConfig": {"defaults": {"unit": unit}, "overrides": []},
+            "gridPos": {"h": PANEL_HEIGHT, "w": PANEL_WIDTH, "x": self.x, "y": self.y},
+            "id": id,
+            "targets": targets
+                .iter()
+                .zip(ref_ids)
+                .map(|((expr, legend), ref_id)| {
+                    json!({"expr": expr, "legendFormat": legend, "refId": ref_id})
+                })
+                .collect::<Vec<_>>(),
+            "title": title,
+            "type": kind,
+        }));
+        self.x += PANEL_WIDTH;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory() -> Inventory {
+        Inventory {
+            validators: vec!["alpha".into(), "beta".into()],
+            components: vec![
+                Component {
+                    name: "consumer".into(),
+                    instance: "10.0.0.5:9102".into(),
+                    kind: None,
+                    shards: Vec::new(),
+                },
+                Component {
+                    name: "bridge".into(),
+                    instance: "10.0.0.6:9103".into(),
+                    kind: Some(ComponentKind::Rpc),
+                    shards: Vec::new(),
+                },
+            ],
+            host: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn templates_validators_and_discovered_shards() {
+        let snapshots = vec![PipelineSnapshot {
+            name: "consumer".into(),
+            up: true,
+            series: Default::default(),
+            component: Some(ComponentKind::Consumer),
+            shards: vec!["0".into(), "1".into()],
+            drop_ratio: None,
+            queue_saturation: None,
+            encode_latency_seconds: None,
+            seconds_since_last_record: None,
+            firing: Vec::new(),
+            last_updated: None,
+        }];
+        let dashboard = generate(&inventory().discovered(&snapshots));
+
+        let variables = dashboard["templating"]["list"].as_array().unwrap();
+        assert_eq!(variables[0]["query"], "alpha,beta");
+        assert_eq!(variables[1]["query"], "0,1");
+        assert_eq!(variables[1]["hide"], 0);
+
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels[0]["repeat"], "validator");
+        let titles: Vec<&str> = panels.iter().filter_map(|p| p["title"].as_str()).collect();
+        assert!(titles.contains(&"consumer (consumer)"));
+        assert!(titles.contains(&"bridge (rpc)"));
+        assert!(titles.contains(&"NVMe Wear"));
+        assert!(!titles.contains(&"Vote Distance"));
+        let exprs = dashboard.to_string();
+        assert!(exprs.contains(
+            r#"ys_consumer_write_batches_total{instance=\"10.0.0.5:9102\",shard=~\"$shard\"}"#
+        ));
+
+        let mut ids: Vec<u64> = panels.iter().map(|p| p["id"].as_u64().unwrap()).collect();
+        ids.dedup();
+        assert_eq!(ids.len(), panels.len());
+    }
+
+    #[test]
+    fn hides_the_shard_variable_without_shards() {
+        let dashboard = generate(&inventory());
+        assert_eq!(dashboard["templating"]["list"][1]["hide"], 2);
+        let panels = dashboard["panels"].as_array().unwrap();
+        let row = panels
+            .iter()
+            .find(|p| p["title"] == "consumer (component)")
+            .unwrap();
+        // Rows start on a fresh line under a full line of panels
+        assert_eq!(row["gridPos"]["x"], 0);
+        assert_eq!(row["gridPos"]["w"], GRID_WIDTH);
+    }
+}
@@ -1,16 +1,85 @@
 // Numan Thabit 2025
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::Write as _,
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use inferno::differential;
 use parking_lot::Mutex;
-use pprof::ProfilerGuard;
+use pprof::{flamegraph, ProfilerGuard};
+use serde::Serialize;
 use tokio::{task::JoinHandle, time::interval};
 
 use crate::config::FlamegraphConfig;
 
+const SAMPLE_FREQUENCY: i32 = 100;
+
+/// The profile being collected right now. pprof runs one profiler per
+/// process, so the guard is dropped before its replacement starts.
+struct Active {
+    guard: Option<ProfilerGuard<'static>>,
+    started_at: DateTime<Utc>,
+}
+
+/// A finished refresh window as folded stacks (`a;b;c` to sample count).
+#[derive(Debug, Clone)]
+struct Profile {
+    id: u64,
+    started_at: DateTime<Utc>,
+    ended_at: DateTime<Utc>,
+    stacks: BTreeMap<String, u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileSummary {
+    pub id: u64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub samples: u64,
+}
+
+#[derive(Debug)]
+struct ProfileHistory {
+    retained: usize,
+    next_id: u64,
+    profiles: VecDeque<Profile>,
+}
+
+impl ProfileHistory {
+    fn push(
+        &mut self,
+        started_at: DateTime<Utc>,
+        ended_at: DateTime<Utc>,
+        stacks: BTreeMap<String, u64>,
+    ) {
+        if self.retained == 0 {
+            return;
+        }
+        self.next_id += 1;
+        self.profiles.push_back(Profile {
+            id: self.next_id,
+            started_at,
+            ended_at,
+            stacks,
+        });
+        while self.profiles.len() > self.retained {
+            self.profiles.pop_front();
+        }
+    }
+
+    fn get(&self, id: u64) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.id == id)
+    }
+}
+
 #[derive(Clone)]
 pub struct FlamegraphService {
-    guard: Arc<Mutex<ProfilerGuard<'static>>>,
+    active: Arc<Mutex<Active>>,
+    history: Arc<Mutex<ProfileHistory>>,
     refresh_interval: Duration,
 }
 
@@ -19,44 +88,234 @@ impl FlamegraphService {
         if !config.enabled {
             return Ok(None);
         }
-        let guard = ProfilerGuard::new(100).context("failed to initialize profiler guard")?;
+        let guard =
+            ProfilerGuard::new(SAMPLE_FREQUENCY).context("failed to initialize profiler guard")?;
         Ok(Some(Self {
-            guard: Arc::new(Mutex::new(guard)),
+            active: Arc::new(Mutex::new(Active {
+                guard: Some(guard),
+                started_at: Utc::now(),
+            })),
+            history: Arc::new(Mutex::new(ProfileHistory {
+                retained: config.retained_profiles,
+                next_id: 0,
+                profiles: VecDeque::new(),
+            })),
             refresh_interval: config.refresh_interval(),
         }))
     }
 
     pub fn spawn_refresh_task(&self) -> JoinHandle<()> {
-        let guard = self.guard.clone();
-        let refresh_interval = self.refresh_interval;
+        let service = self.clone();
         tokio::spawn(async move {
-            let mut ticker = interval(refresh_interval);
+            let mut ticker = interval(service.refresh_interval);
+            // The first tick completes immediately
+            ticker.tick().await;
             loop {
                 ticker.tick().await;
-                match ProfilerGuard::new(100) {
-                    Ok(new_guard) => {
-                        *guard.lock() = new_guard;
-                    }
-                    Err(err) => {
-                        tracing::warn!(error = %err, "failed to refresh profiler guard");
-                    }
-                }
+                service.rotate();
             }
         })
     }
 
+    /// Close the current window into the history and start a new one.
+    fn rotate(&self) {
+        let mut active = self.active.lock();
+        let ended_at = Utc::now();
+        let finished = active.guard.take().map(|guard| folded_stacks(&guard));
+        active.guard = match ProfilerGuard::new(SAMPLE_FREQUENCY) {
+            Ok(guard) => Some(guard),
+            Err(err) => {
+                tracing::warn!(error = %err, "failed to refresh profiler guard");
+                None
+            }
+        };
+        let started_at = std::mem::replace(&mut active.started_at, ended_at);
+        drop(active);
+
+        match finished {
+            Some(Ok(stacks)) => self.history.lock().push(started_at, ended_at, stacks),
+            Some(Err(err)) => tracing::warn!(error = %err, "failed to fold finished profile"),
+            None => {}
+        }
+    }
+
     pub fn snapshot_svg(&self) -> Result<Vec<u8>> {
-        let mut out = Vec::new();
-        {
-            let guard = self.guard.lock();
-            let report = guard
-                .report()
-                .build()
-                .context("failed to build flamegraph profile")?;
-            report
-                .flamegraph(&mut out)
-                .context("failed to write flamegraph")?;
+        let stacks = {
+            let active = self.active.lock();
+            let guard = active.guard.as_ref().context("profiler is not running")?;
+            folded_stacks(guard)?
+        };
+        render(&stacks, &mut flamegraph::Options::default())
+    }
+
+    pub fn profiles(&self) -> Vec<ProfileSummary> {
+        self.history
+            .lock()
+            .profiles
+            .iter()
+            .map(|profile| ProfileSummary {
+                id: profile.id,
+                started_at: profile.started_at,
+                ended_at: profile.ended_at,
+                samples: profile.stacks.values().sum(),
+            })
+            .collect()
+    }
+
+    /// Flamegraph of a retained profile; `None` once it aged out.
+    pub fn profile_svg(&self, id: u64) -> Option<Result<Vec<u8>>> {
+        let stacks = self.history.lock().get(id)?.stacks.clone();
+        let mut options = flamegraph::Options::default();
+        options.title = format!("Profile {id}");
+        Some(render(&stacks, &mut options))
+    }
+
+    /// Differential flamegraph of `after` against `before`: frames that grew
+    /// are red, frames that shrank blue. `None` when either aged out.
+    pub fn diff_svg(&self, before: u64, after: u64) -> Option<Result<Vec<u8>>> {
+        let (before, after) = {
+            let history = self.history.lock();
+            (history.get(before)?.clone(), history.get(after)?.clone())
+        };
+        Some(render_diff(&before, &after))
+    }
+}
+
+fn folded_stacks(guard: &ProfilerGuard<'_>) -> Result<BTreeMap<String, u64>> {
+    let report = guard
+        .report()
+        .build()
+        .context("failed to build flamegraph profile")?;
+    let mut stacks = BTreeMap::new();
+    for (frames, count) in &report.data {
+        let mut line = frames.thread_name_or_id();
+        for frame in frames.frames.iter().rev() {
+            for symbol in frame.iter().rev() {
+                write!(line, ";{symbol}").expect("write to string");
+            }
         }
-        Ok(out)
+        *stacks.entry(line).or_default() += (*count).max(0) as u64;
+    }
+    Ok(stacks)
+}
+
+fn folded_lines(stacks: &BTreeMap<String, u64>) -> String {
+    stacks
+        .iter()
+        .map(|(stack, count)| format!("{stack} {count}\n"))
+        .collect()
+}
+
+fn render(
+    stacks: &BTreeMap<String, u64>,
+    options: &mut flamegraph::Options<'_>,
+) -> Result<Vec<u8>> {
+    // An empty body, as pprof renders a profile without samples
+    if stacks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let folded = folded_lines(stacks);
+    let mut out = Vec::new();
+    flamegraph::from_lines(options, folded.lines(), &mut out)
+        .context("failed to write flamegraph")?;
+    Ok(out)
+}
+
+fn render_diff(before: &Profile, after: &Profile) -> Result<Vec<u8>> {
+    if before.stacks.is_empty() || after.stacks.is_empty() {
+        bail!("profile has no samples");
+    }
+    let mut folded = Vec::new();
+    // Windows under different load still compare by share of samples
+    differential::from_readers(
+        differential::Options {
+            normalize: true,
+            ..Default::default()
+        },
+        folded_lines(&before.stacks).as_bytes(),
+        folded_lines(&after.stacks).as_bytes(),
+        &mut folded,
+    )
+    .context("failed to diff profiles")?;
+    let folded = String::from_utf8(folded).context("diff output is not utf-8")?;
+
+    let mut options = flamegraph::Options::default();
+    options.title = format!("Profile {} vs {}", after.id, before.id);
+    options.subtitle = Some(format!(
+        "{} to {} against {} to {}",
+        after.started_at.format("%H:%M:%S"),
+        after.ended_at.format("%H:%M:%S"),
+        before.started_at.format("%H:%M:%S"),
+        before.ended_at.format("%H:%M:%S"),
+    ));
+    let mut out = Vec::new();
+    flamegraph::from_lines(&mut options, folded.lines(), &mut out)
+        .context("failed to write differential flamegraph")?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stacks(entries: &[(&str, u64)]) -> BTreeMap<String, u64> {
+        entries
+            .iter()
+            .map(|(stack, count)| (stack.to_string(), *count))
+            .collect()
+    }
+
+    fn history(retained: usize) -> ProfileHistory {
+        ProfileHistory {
+            retained,
+            next_id: 0,
+            profiles: VecDeque::new(),
+        }
+    }
+
+    #[test]
+    fn keeps_only_the_newest_profiles() {
+        let mut history = history(2);
+        let now = Utc::now();
+        for count in 1..=3 {
+            history.push(now, now, stacks(&[("main;work", count)]));
+        }
+        let ids: Vec<u64> = history.profiles.iter().map(|p| p.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+        assert!(history.get(1).is_none());
+
+        let mut disabled = self::history(0);
+        disabled.push(now, now, stacks(&[("main", 1)]));
+        assert!(disabled.profiles.is_empty());
+    }
+
+    #[test]
+    fn renders_differential_flamegraph() {
+        let mut history = history(4);
+        let now = Utc::now();
+        history.push(
+            now,
+            now,
+            stacks(&[("main;replay;verify", 80), ("main;gossip", 20)]),
+        );
+        history.push(
+            now,
+            now,
+            stacks(&[("main;replay;verify", 50), ("main;geyser;notify", 50)]),
+        );
+        let svg = render_diff(history.get(1).unwrap(), history.get(2).unwrap()).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains("Profile 2 vs 1"));
+        assert!(svg.contains("notify"));
+        // Drawn in the shape of the later profile; vanished stacks have no width
+        assert!(!svg.contains("gossip"));
+
+        let empty = Profile {
+            id: 9,
+            started_at: now,
+            ended_at: now,
+            stacks: BTreeMap::new(),
+        };
+        assert!(render_diff(&empty, history.get(2).unwrap()).is_err());
     }
 }
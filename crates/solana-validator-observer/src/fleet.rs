@@ -0,0 +1,268 @@
+// Numan Thabit 2025
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{ComparisonConfig, FleetConfig},
+    metrics::ObserverMetrics,
+    state::{FleetComparison, FleetMember, ObserverState},
+};
+
+pub const ALERT_FLEET_OUTLIER: &str = "fleet_outlier";
+
+/// Label the compared metrics carry the validator name in.
+const VALIDATOR_LABEL: &str = "validator";
+
+/// Value at quantile `q` of cumulative `(upper bound, count)` buckets ending
+/// in `+Inf`, interpolated within the bucket it falls in. `None` without
+/// observations.
+pub fn quantile(buckets: &[(f64, f64)], q: f64) -> Option<f64> {
+    let total = buckets.last()?.1;
+    if total <= 0.0 {
+        return None;
+    }
+    let rank = q * total;
+    let (mut lower_bound, mut lower_count) = (0.0, 0.0);
+    for &(bound, count) in buckets {
+        if count >= rank {
+            if bound.is_infinite() {
+                // Past the highest finite bound all that is known is the bound
+                return Some(lower_bound);
+            }
+            let in_bucket = count - lower_count;
+            let fraction = if in_bucket > 0.0 {
+                (rank - lower_count) / in_bucket
+            } else {
+                1.0
+            };
+            return Some(lower_bound + (bound - lower_bound) * fraction);
+        }
+        (lower_bound, lower_count) = (bound, count);
+    }
+    None
+}
+
+pub fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    })
+}
+
+/// Each validator against the median of the others.
+pub fn compare(
+    comparison: &ComparisonConfig,
+    values: &BTreeMap<String, f64>,
+) -> (Option<f64>, Vec<FleetMember>) {
+    let median_of_all = median(&mut values.values().copied().collect::<Vec<_>>());
+    let members = values
+        .iter()
+        .map(|(validator, &value)| {
+            let mut peers: Vec<f64> = values
+                .iter()
+                .filter(|(peer, _)| *peer != validator)
+                .map(|(_, &value)| value)
+                .collect();
+            let peer_median = (peers.len() >= comparison.min_peers)
+                .then(|| median(&mut peers))
+                .flatten();
+            let ratio = peer_median
+                .filter(|median| *median > 0.0)
+                .map(|median| value / median);
+            FleetMember {
+                validator: validator.clone(),
+                value,
+                peer_median,
+                ratio,
+                firing: ratio.is_some_and(|ratio| ratio > comparison.max_ratio),
+            }
+        })
+        .collect();
+    (median_of_all, members)
+}
+
+pub fn spawn_comparator(
+    config: FleetConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, state, metrics, alerting).await {
+            tracing::error!(%err, "fleet comparison loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: FleetConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    for comparison in &config.comparisons {
+        if let Some(q) = comparison.quantile.filter(|q| !(0.0..=1.0).contains(q)) {
+            bail!(
+                "fleet comparison {} quantile must lie between 0 and 1, got {q}",
+                comparison.name
+            );
+        }
+    }
+
+    let mut ticker = interval_at(Instant::now(), config.evaluation_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // Histogram buckets at the previous evaluation, for quantiles over the
+    // interval rather than since start
+    let mut previous: HashMap<(String, String), Vec<(f64, f64)>> = HashMap::new();
+    let mut firing: HashSet<String> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+        let mut comparisons = Vec::new();
+        let mut alerts = Vec::new();
+        let mut now_firing = HashSet::new();
+
+        for comparison in &config.comparisons {
+            let values = match comparison.quantile {
+                Some(q) => metrics
+                    .histogram_buckets(&comparison.metric, VALIDATOR_LABEL)
+                    .into_iter()
+                    .filter_map(|(validator, buckets)| {
+                        let key = (comparison.metric.clone(), validator.clone());
+                        let window: Vec<(f64, f64)> = match previous.get(&key) {
+                            Some(before) if before.len() == buckets.len() => buckets
+                                .iter()
+                                .zip(before)
+                                .map(|(now, then)| (now.0, now.1 - then.1))
+                                .collect(),
+                            _ => buckets.clone(),
+                        };
+                        previous.insert(key, buckets);
+                        Some((validator, quantile(&window, q)?))
+                    })
+                    .collect(),
+                None => metrics.gauge_values(&comparison.metric),
+            };
+
+            let (median, members) = compare(comparison, &values);
+            if let Some(median) = median {
+                metrics.set_fleet_median(&comparison.name, median);
+            }
+            for member in &members {
+                metrics.set_fleet_member(
+                    &comparison.name,
+                    &member.validator,
+                    member.value,
+                    member.ratio,
+                    member.firing,
+                );
+                if !member.firing {
+                    continue;
+                }
+                let subject = format!("{}/{}", comparison.name, member.validator);
+                now_firing.insert(subject.clone());
+                alerts.push(Alert {
+                    alert: ALERT_FLEET_OUTLIER.into(),
+                    subject,
+                    value: member.ratio.unwrap_or_default(),
+                    threshold: comparison.max_ratio,
+                });
+            }
+            comparisons.push(FleetComparison {
+                name: comparison.name.clone(),
+                metric: comparison.metric.clone(),
+                quantile: comparison.quantile,
+                median,
+                members,
+            });
+        }
+        state.update_fleet(comparisons);
+
+        for started in now_firing.difference(&firing) {
+            tracing::warn!(subject = %started, "validator is an outlier among its peers");
+        }
+        for resolved in firing.difference(&now_firing) {
+            tracing::info!(subject = %resolved, "validator back in line with its peers");
+        }
+        firing = now_firing;
+
+        if let Some(alerting) = &alerting {
+            for alert in &alerts {
+                if let Err(err) = alerting.fire(alert).await {
+                    tracing::warn!(subject = %alert.subject, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_quantiles_within_buckets() {
+        let buckets = [
+            (0.01, 50.0),
+            (0.1, 90.0),
+            (1.0, 100.0),
+            (f64::INFINITY, 100.0),
+        ];
+        assert!((quantile(&buckets, 0.5).unwrap() - 0.01).abs() < 1e-12);
+        assert!((quantile(&buckets, 0.7).unwrap() - 0.055).abs() < 1e-12);
+        assert!((quantile(&buckets, 0.99).unwrap() - 0.91).abs() < 1e-12);
+        assert_eq!(quantile(&[(0.01, 0.0), (f64::INFINITY, 0.0)], 0.99), None);
+        assert_eq!(
+            quantile(&[(0.01, 0.0), (f64::INFINITY, 4.0)], 0.5),
+            Some(0.01)
+        );
+    }
+
+    #[test]
+    fn flags_validators_far_from_their_peers() {
+        let comparison = ComparisonConfig {
+            name: "rpc".into(),
+            metric: "rpc_request_latency_seconds".into(),
+            quantile: Some(0.99),
+            max_ratio: 3.0,
+            min_peers: 2,
+        };
+        let values: BTreeMap<String, f64> = [
+            ("alpha", 0.010),
+            ("beta", 0.012),
+            ("gamma", 0.011),
+            ("delta", 0.040),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+
+        let (median, members) = compare(&comparison, &values);
+        assert!((median.unwrap() - 0.0115).abs() < 1e-12);
+        let firing: Vec<&str> = members
+            .iter()
+            .filter(|member| member.firing)
+            .map(|member| member.validator.as_str())
+            .collect();
+        assert_eq!(firing, vec!["delta"]);
+        let delta = members.iter().find(|m| m.validator == "delta").unwrap();
+        assert_eq!(delta.peer_median, Some(0.011));
+
+        // Too few peers to compare against
+        let pair: BTreeMap<String, f64> = values.into_iter().take(2).collect();
+        let (_, members) = compare(&comparison, &pair);
+        assert!(members.iter().all(|m| m.ratio.is_none() && !m.firing));
+    }
+}
@@ -0,0 +1,290 @@
+// Numan Thabit 2025
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinHandle, time::interval};
+
+use crate::{config::HistoryConfig, metrics::ObserverMetrics};
+
+/// One line of a partition file: every series' value at `t` (unix millis).
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    t: u64,
+    s: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    #[default]
+    Avg,
+    Min,
+    Max,
+    Last,
+}
+
+/// Metric history on disk, one JSON-lines file per partition, named after
+/// the partition's start in unix millis.
+#[derive(Debug)]
+pub struct HistoryStore {
+    dir: PathBuf,
+    partition_ms: u64,
+    retention_ms: u64,
+}
+
+impl HistoryStore {
+    pub fn open(dir: impl Into<PathBuf>, partition: Duration, retention: Duration) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create history dir {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            partition_ms: (partition.as_millis() as u64).max(1),
+            retention_ms: retention.as_millis() as u64,
+        })
+    }
+
+    pub fn append(&self, t: u64, samples: BTreeMap<String, f64>) -> Result<()> {
+        let mut line = serde_json::to_vec(&Snapshot { t, s: samples })?;
+        line.push(b'\n');
+        let path = self.partition_path(t - t % self.partition_ms);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        file.write_all(&line)
+            .with_context(|| format!("failed to append to {}", path.display()))
+    }
+
+    /// Delete partitions entirely older than the retention; returns how many.
+    pub fn enforce_retention(&self, now: u64) -> Result<usize> {
+        let cutoff = now.saturating_sub(self.retention_ms);
+        let mut removed = 0;
+        for start in self.partitions()? {
+            if start + self.partition_ms <= cutoff {
+                fs::remove_file(self.partition_path(start))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Series with at least one sample in `[start, end]`.
+    pub fn series(&self, start: u64, end: u64) -> Result<BTreeSet<String>> {
+        let mut names = BTreeSet::new();
+        self.scan(start, end, |snapshot| names.extend(snapshot.s.into_keys()))?;
+        Ok(names)
+    }
+
+    /// Samples of `series` in `[start, end]`, aggregated into `step`-aligned
+    /// buckets when `step` is set.
+    pub fn query(
+        &self,
+        series: &str,
+        start: u64,
+        end: u64,
+        step: Option<u64>,
+        aggregation: Aggregation,
+    ) -> Result<Vec<(u64, f64)>> {
+        let mut points = Vec::new();
+        self.scan(start, end, |snapshot| {
+            if let Some(value) = snapshot.s.get(series) {
+                points.push((snapshot.t, *value));
+            }
+        })?;
+        let Some(step) = step.filter(|step| *step > 0) else {
+            return Ok(points);
+        };
+
+        let mut buckets: Vec<(u64, Vec<f64>)> = Vec::new();
+        for (t, value) in points {
+            let bucket = t - t % step;
+            match buckets.last_mut() {
+                Some((last, values)) if *last == bucket => values.push(value),
+                _ => buckets.push((bucket, vec![value])),
+            }
+        }
+        Ok(buckets
+            .into_iter()
+            .map(|(bucket, values)| {
+                let value = match aggregation {
+                    Aggregation::Avg => values.iter().sum::<f64>() / values.len() as f64,
+                    Aggregation::Min => values.iter().copied().fold(f64::INFINITY, f64::min),
+                    Aggregation::Max => values.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+                    Aggregation::Last => values[values.len() - 1],
+                };
+                (bucket, value)
+            })
+            .collect())
+    }
+
+    fn scan(&self, start: u64, end: u64, mut visit: impl FnMut(Snapshot)) -> Result<()> {
+        for partition in self.partitions()? {
+            if partition + self.partition_ms <= start || partition > end {
+                continue;
+            }
+            let path = self.partition_path(partition);
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                // Removed by retention since it was listed
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| format!("failed to open {}", path.display()))
+                }
+            };
+            for line in BufReader::new(file).lines() {
+                // A crash can leave the last line cut short
+                let Ok(snapshot) = serde_json::from_str::<Snapshot>(&line?) else {
+                    continue;
+                };
+                if snapshot.t >= start && snapshot.t <= end {
+                    visit(snapshot);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Partition start times, oldest first.
+    fn partitions(&self) -> Result<Vec<u64>> {
+        let mut starts: Vec<u64> = fs::read_dir(&self.dir)
+            .with_context(|| format!("failed to list {}", self.dir.display()))?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_suffix(".jsonl")?.parse().ok()
+            })
+            .collect();
+        starts.sort_unstable();
+        Ok(starts)
+    }
+
+    fn partition_path(&self, start: u64) -> PathBuf {
+        self.dir.join(format!("{start:013}.jsonl"))
+    }
+}
+
+pub fn now_millis() -> u64 {
+    Utc::now().timestamp_millis().max(0) as u64
+}
+
+pub fn spawn_recorder(
+    config: &HistoryConfig,
+    store: Arc<HistoryStore>,
+    metrics: ObserverMetrics,
+) -> JoinHandle<()> {
+    let sample_interval = config.sample_interval();
+    tokio::spawn(async move {
+        let mut ticker = interval(sample_interval);
+        let mut partition = None;
+        loop {
+            ticker.tick().await;
+            let now = now_millis();
+            let samples = metrics.samples();
+            let current = now / store.partition_ms;
+            let rolled = partition.replace(current) != Some(current);
+            let store = store.clone();
+            let written = tokio::task::spawn_blocking(move || {
+                store.append(now, samples)?;
+                if rolled {
+                    let removed = store.enforce_retention(now)?;
+                    if removed > 0 {
+                        tracing::info!(removed, "expired history partitions");
+                    }
+                }
+                Ok::<_, anyhow::Error>(())
+            })
+            .await;
+            match written {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => tracing::warn!(error = %err, "failed to record metric history"),
+                Err(err) => tracing::warn!(error = %err, "history writer panicked"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: u64 = 3_600_000;
+
+    fn store(dir: &tempfile::TempDir) -> HistoryStore {
+        HistoryStore::open(
+            dir.path(),
+            Duration::from_millis(HOUR),
+            Duration::from_millis(2 * HOUR),
+        )
+        .unwrap()
+    }
+
+    fn sample(series: &str, value: f64) -> BTreeMap<String, f64> {
+        BTreeMap::from([(series.to_string(), value)])
+    }
+
+    #[test]
+    fn queries_ranges_across_partitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        for (i, t) in [HOUR - 2_000, HOUR - 1_000, HOUR, HOUR + 1_000]
+            .into_iter()
+            .enumerate()
+        {
+            store.append(t, sample("lag", i as f64)).unwrap();
+        }
+        store.append(HOUR + 2_000, sample("rtt", 9.0)).unwrap();
+
+        let all = store
+            .query("lag", 0, 2 * HOUR, None, Aggregation::Avg)
+            .unwrap();
+        assert_eq!(all.len(), 4);
+        let tail = store
+            .query("lag", HOUR - 1_000, HOUR, None, Aggregation::Avg)
+            .unwrap();
+        assert_eq!(tail, vec![(HOUR - 1_000, 1.0), (HOUR, 2.0)]);
+        assert_eq!(
+            store.series(HOUR + 1_500, 2 * HOUR).unwrap(),
+            BTreeSet::from(["rtt".to_string()])
+        );
+
+        // Reopening finds what was written before
+        let reopened = self::store(&dir);
+        assert_eq!(
+            reopened
+                .query("lag", 0, 2 * HOUR, Some(HOUR), Aggregation::Max)
+                .unwrap(),
+            vec![(0, 1.0), (HOUR, 3.0)]
+        );
+        assert_eq!(
+            reopened
+                .query("lag", 0, 2 * HOUR, Some(HOUR), Aggregation::Avg)
+                .unwrap(),
+            vec![(0, 0.5), (HOUR, 2.5)]
+        );
+    }
+
+    #[test]
+    fn drops_partitions_past_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store(&dir);
+        store.append(10, sample("lag", 1.0)).unwrap();
+        store.append(HOUR + 10, sample("lag", 2.0)).unwrap();
+        store.append(3 * HOUR + 10, sample("lag", 3.0)).unwrap();
+
+        assert_eq!(store.enforce_retention(3 * HOUR + 10).unwrap(), 1);
+        let left = store
+            .query("lag", 0, 4 * HOUR, None, Aggregation::Avg)
+            .unwrap();
+        assert_eq!(left, vec![(HOUR + 10, 2.0), (3 * HOUR + 10, 3.0)]);
+    }
+}
@@ -0,0 +1,649 @@
+// Numan Thabit 2025
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::{Client, Url};
+use tokio::{
+    process::Command,
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{HostAlertConfig, HostConfig},
+    metrics::ObserverMetrics,
+    pipeline::{parse_families, Sample},
+    state::{DiskStats, HostSnapshot, NicStats, ObserverState},
+};
+
+pub const ALERT_DISK_UTILIZATION: &str = "disk_utilization";
+pub const ALERT_DISK_LATENCY: &str = "disk_latency";
+pub const ALERT_NIC_DROPS: &str = "nic_drops";
+pub const ALERT_CPU_STEAL: &str = "cpu_steal";
+pub const ALERT_TEMPERATURE: &str = "temperature";
+pub const ALERT_NVME_WEAR: &str = "nvme_wear";
+
+const NODE_FAMILIES: [(&str, &str); 1] = [("node_", "node")];
+/// SMART wear moves over weeks; reading it spawns a process.
+const NVME_REFRESH: Duration = Duration::from_secs(300);
+
+/// Devices left out unless listed: ramdisks, loop devices and partitions,
+/// as node_exporter does.
+static IGNORED_DISKS: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(ram|loop|fd|zram|sr)\d+$|^((h|s|v|xv)d[a-z]+|nvme\d+n\d+p|mmcblk\d+p)\d+$")
+        .expect("valid disk pattern")
+});
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DiskCounters {
+    /// Reads and writes completed.
+    pub ios: f64,
+    /// Milliseconds spent on them.
+    pub io_ms: f64,
+    /// Milliseconds the device had I/O in flight.
+    pub busy_ms: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NicCounters {
+    pub drops: f64,
+    pub errors: f64,
+}
+
+/// Cumulative host counters at one instant, whichever source they came from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostCounters {
+    pub disks: BTreeMap<String, DiskCounters>,
+    pub nics: BTreeMap<String, NicCounters>,
+    /// Stolen and total CPU time, in the same unit.
+    pub cpu: Option<(f64, f64)>,
+    pub temperatures: BTreeMap<String, f64>,
+}
+
+pub fn parse_diskstats(text: &str) -> BTreeMap<String, DiskCounters> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let counter = |index: usize| fields.get(index)?.parse::<f64>().ok();
+            let disk = DiskCounters {
+                ios: counter(3)? + counter(7)?,
+                io_ms: counter(6)? + counter(10)?,
+                busy_ms: counter(12)?,
+            };
+            Some((fields[2].to_string(), disk))
+        })
+        .collect()
+}
+
+pub fn parse_net_dev(text: &str) -> BTreeMap<String, NicCounters> {
+    text.lines()
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let fields: Vec<f64> = counters
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .ok()?;
+            let nic = NicCounters {
+                drops: fields.get(3)? + fields.get(11)?,
+                errors: fields.get(2)? + fields.get(10)?,
+            };
+            Some((name.trim().to_string(), nic))
+        })
+        .collect()
+}
+
+/// Stolen and total jiffies from the aggregate `cpu` line of `/proc/stat`.
+pub fn parse_cpu_steal(text: &str) -> Option<(f64, f64)> {
+    let line = text.lines().find(|line| line.starts_with("cpu "))?;
+    // user nice system idle iowait irq softirq steal; guest time is already in user
+    let fields: Vec<f64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    (fields.len() == 8).then(|| (fields[7], fields.iter().sum()))
+}
+
+/// Every `temp*_input` under `/sys/class/hwmon`, keyed `chip/label`.
+fn read_hwmon(root: &Path) -> BTreeMap<String, f64> {
+    let mut temperatures = BTreeMap::new();
+    let Ok(chips) = fs::read_dir(root) else {
+        return temperatures;
+    };
+    for chip in chips.flatten() {
+        let dir = chip.path();
+        let read = |file: &str| fs::read_to_string(dir.join(file)).ok();
+        let mut name = read("name").map_or_else(
+            || chip.file_name().to_string_lossy().into_owned(),
+            |name| name.trim().to_string(),
+        );
+        // Several drives share a chip name; their device tells them apart
+        if let Ok(device) = fs::read_link(dir.join("device")) {
+            if let Some(device) = device.file_name() {
+                name = format!("{name}_{}", device.to_string_lossy());
+            }
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file = entry.file_name();
+            let Some(sensor) = file
+                .to_str()
+                .and_then(|file| file.strip_suffix("_input"))
+                .filter(|sensor| sensor.starts_with("temp"))
+            else {
+                continue;
+            };
+            let Some(millis) =
+                read(&format!("{sensor}_input")).and_then(|value| value.trim().parse::<f64>().ok())
+            else {
+                continue;
+            };
+            let label = read(&format!("{sensor}_label"))
+                .map_or_else(|| sensor.to_string(), |label| label.trim().to_string());
+            temperatures.insert(format!("{name}/{label}"), millis / 1_000.0);
+        }
+    }
+    temperatures
+}
+
+fn read_local() -> Result<HostCounters> {
+    let read =
+        |path: &str| fs::read_to_string(path).with_context(|| format!("failed to read {path}"));
+    Ok(HostCounters {
+        disks: parse_diskstats(&read("/proc/diskstats")?),
+        nics: parse_net_dev(&read("/proc/net/dev")?),
+        cpu: parse_cpu_steal(&read("/proc/stat")?),
+        temperatures: read_hwmon(Path::new("/sys/class/hwmon")),
+    })
+}
+
+fn label<'a>(sample: &'a Sample, key: &str) -> Option<&'a str> {
+    sample
+        .labels
+        .iter()
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The same counters out of node_exporter's `node_*` series.
+pub fn from_node_exporter(samples: &[Sample]) -> HostCounters {
+    let mut counters = HostCounters::default();
+    let mut cpu = None;
+    for sample in samples {
+        let value = sample.value;
+        match sample.name.as_str() {
+            "node_disk_reads_completed_total"
+            | "node_disk_writes_completed_total"
+            | "node_disk_read_time_seconds_total"
+            | "node_disk_write_time_seconds_total"
+            | "node_disk_io_time_seconds_total" => {
+                let Some(device) = label(sample, "device") else {
+                    continue;
+                };
+                let disk = counters.disks.entry(device.to_string()).or_default();
+                match sample.name.as_str() {
+                    "node_disk_io_time_seconds_total" => disk.busy_ms += value * 1_000.0,
+                    name if name.ends_with("_completed_total") => disk.ios += value,
+                    _ => disk.io_ms += value * 1_000.0,
+                }
+            }
+            "node_network_receive_drop_total"
+            | "node_network_transmit_drop_total"
+            | "node_network_receive_errs_total"
+            | "node_network_transmit_errs_total" => {
+                let Some(device) = label(sample, "device") else {
+                    continue;
+                };
+                let nic = counters.nics.entry(device.to_string()).or_default();
+                if sample.name.ends_with("_drop_total") {
+                    nic.drops += value;
+                } else {
+                    nic.errors += value;
+                }
+            }
+            "node_cpu_seconds_total" => {
+                let (steal, total) = cpu.get_or_insert((0.0, 0.0));
+                *total += value;
+                if label(sample, "mode") == Some("steal") {
+                    *steal += value;
+                }
+            }
+            "node_hwmon_temp_celsius" => {
+                if let (Some(chip), Some(sensor)) = (label(sample, "chip"), label(sample, "sensor"))
+                {
+                    counters
+                        .temperatures
+                        .insert(format!("{chip}/{sensor}"), value);
+                }
+            }
+            _ => {}
+        }
+    }
+    counters.cpu = cpu;
+    counters
+}
+
+fn selected(name: &str, listed: &[String], ignored: impl Fn(&str) -> bool) -> bool {
+    if listed.is_empty() {
+        !ignored(name)
+    } else {
+        listed.iter().any(|listed| listed == name)
+    }
+}
+
+/// Rates between two readings `elapsed` apart, for the disks and interfaces
+/// `config` selects.
+pub fn derive(
+    previous: &HostCounters,
+    current: &HostCounters,
+    elapsed: Duration,
+    config: &HostConfig,
+) -> HostSnapshot {
+    let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+    let increase = |previous: f64, current: f64| (current - previous).max(0.0);
+
+    let disks = current
+        .disks
+        .iter()
+        .filter(|(name, _)| selected(name, &config.disks, |name| IGNORED_DISKS.is_match(name)))
+        .filter_map(|(name, now)| {
+            let before = previous.disks.get(name)?;
+            let ios = increase(before.ios, now.ios);
+            let stats = DiskStats {
+                utilization: (increase(before.busy_ms, now.busy_ms) / (secs * 1_000.0)).min(1.0),
+                latency_ms: (ios > 0.0).then(|| increase(before.io_ms, now.io_ms) / ios),
+            };
+            Some((name.clone(), stats))
+        })
+        .collect();
+    let nics = current
+        .nics
+        .iter()
+        .filter(|(name, _)| selected(name, &config.interfaces, |name| name == "lo"))
+        .filter_map(|(name, now)| {
+            let before = previous.nics.get(name)?;
+            let stats = NicStats {
+                drops_per_sec: increase(before.drops, now.drops) / secs,
+                errors_per_sec: increase(before.errors, now.errors) / secs,
+            };
+            Some((name.clone(), stats))
+        })
+        .collect();
+    let cpu_steal_ratio =
+        previous
+            .cpu
+            .zip(current.cpu)
+            .and_then(|((steal_before, total_before), (steal, total))| {
+                let total = increase(total_before, total);
+                (total > 0.0).then(|| increase(steal_before, steal) / total)
+            });
+
+    HostSnapshot {
+        disks,
+        nics,
+        cpu_steal_ratio,
+        temperatures_celsius: current.temperatures.clone(),
+        ..HostSnapshot::default()
+    }
+}
+
+pub fn evaluate(snapshot: &HostSnapshot, limits: &HostAlertConfig) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    let mut check = |alert: &'static str, subject: &str, value: f64, threshold: f64| {
+        if value > threshold {
+            alerts.push(Alert {
+                alert: alert.into(),
+                subject: subject.to_string(),
+                value,
+                threshold,
+            });
+        }
+    };
+    for (device, disk) in &snapshot.disks {
+        check(
+            ALERT_DISK_UTILIZATION,
+            device,
+            disk.utilization,
+            limits.max_disk_utilization,
+        );
+        if let Some(latency_ms) = disk.latency_ms {
+            check(
+                ALERT_DISK_LATENCY,
+                device,
+                latency_ms,
+                limits.max_disk_latency_ms,
+            );
+        }
+    }
+    for (interface, nic) in &snapshot.nics {
+        check(
+            ALERT_NIC_DROPS,
+            interface,
+            nic.drops_per_sec + nic.errors_per_sec,
+            limits.max_nic_drops_per_sec,
+        );
+    }
+    if let Some(steal) = snapshot.cpu_steal_ratio {
+        check(ALERT_CPU_STEAL, "cpu", steal, limits.max_cpu_steal);
+    }
+    for (sensor, celsius) in &snapshot.temperatures_celsius {
+        check(
+            ALERT_TEMPERATURE,
+            sensor,
+            *celsius,
+            limits.max_temperature_celsius,
+        );
+    }
+    for (device, wear) in &snapshot.nvme_wear {
+        check(ALERT_NVME_WEAR, device, *wear, limits.max_nvme_wear);
+    }
+    alerts
+}
+
+/// Share of rated endurance used, from `nvme smart-log` JSON.
+pub fn parse_nvme_wear(json: &[u8]) -> Result<f64> {
+    let log: serde_json::Value =
+        serde_json::from_slice(json).context("failed to decode smart log")?;
+    // nvme-cli renamed the field in 2.x
+    let used = ["percent_used", "percentage_used"]
+        .iter()
+        .find_map(|key| log.get(key)?.as_f64())
+        .context("smart log has no percent_used")?;
+    Ok(used / 100.0)
+}
+
+async fn read_nvme_wear(device: &str) -> Result<f64> {
+    let output = Command::new("nvme")
+        .args(["smart-log", "--output-format=json", device])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .context("failed to run nvme smart-log")?;
+    if !output.status.success() {
+        bail!(
+            "nvme smart-log {device} exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    parse_nvme_wear(&output.stdout)
+}
+
+async fn scrape_node_exporter(client: &Client, url: &Url) -> Result<HostCounters> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .context("node_exporter request failed")?;
+    if !response.status().is_success() {
+        bail!("node_exporter returned status {}", response.status());
+    }
+    let body = response
+        .text()
+        .await
+        .context("failed to read node_exporter body")?;
+    Ok(from_node_exporter(&parse_families(&body, &NODE_FAMILIES)))
+}
+
+pub fn spawn_collector(
+    config: HostConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, state, metrics, alerting).await {
+            tracing::error!(%err, "host collector loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: HostConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .context("failed to construct node_exporter client")?;
+    let source = config
+        .node_exporter_url
+        .as_ref()
+        .map_or_else(|| "builtin".to_string(), Url::to_string);
+    let mut ticker = interval_at(Instant::now(), config.poll_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut previous: Option<(Instant, HostCounters)> = None;
+    let mut nvme_wear = BTreeMap::new();
+    let mut nvme_read_at: Option<Instant> = None;
+    let mut firing: HashSet<(String, String)> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+
+        let counters = match &config.node_exporter_url {
+            Some(url) => scrape_node_exporter(&client, url).await,
+            None => tokio::task::spawn_blocking(read_local)
+                .await
+                .context("host reader panicked")
+                .and_then(|read| read),
+        };
+        let counters = match counters {
+            Ok(counters) => counters,
+            Err(err) => {
+                tracing::debug!(%source, error = %err, "host collection failed");
+                metrics.inc_scrape_error("host", "host");
+                continue;
+            }
+        };
+        let now = Instant::now();
+
+        if !config.nvme_devices.is_empty()
+            && nvme_read_at.is_none_or(|at| now.duration_since(at) >= NVME_REFRESH)
+        {
+            nvme_read_at = Some(now);
+            for device in &config.nvme_devices {
+                match read_nvme_wear(device).await {
+                    Ok(wear) => {
+                        nvme_wear.insert(device.clone(), wear);
+                    }
+                    Err(err) => {
+                        tracing::warn!(%device, error = %err, "failed to read nvme wear");
+                    }
+                }
+            }
+        }
+
+        let Some((previous_at, previous_counters)) = previous.replace((now, counters.clone()))
+        else {
+            continue;
+        };
+        let mut snapshot = derive(
+            &previous_counters,
+            &counters,
+            now.duration_since(previous_at),
+            &config,
+        );
+        snapshot.source = source.clone();
+        snapshot.nvme_wear = nvme_wear.clone();
+        snapshot.last_updated = Some(Utc::now());
+
+        let alerts = evaluate(&snapshot, &config.alerts);
+        let now_firing: HashSet<(String, String)> = alerts
+            .iter()
+            .map(|alert| (alert.alert.to_string(), alert.subject.clone()))
+            .collect();
+        for alert in &alerts {
+            metrics.set_host_alert(&alert.alert, &alert.subject, true);
+            if firing.contains(&(alert.alert.to_string(), alert.subject.clone())) {
+                continue;
+            }
+            tracing::warn!(
+                alert = %alert.alert,
+                subject = %alert.subject,
+                value = alert.value,
+                threshold = alert.threshold,
+                "host alert firing"
+            );
+            if let Some(alerting) = &alerting {
+                if let Err(err) = alerting.fire(alert).await {
+                    tracing::warn!(subject = %alert.subject, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+        for (alert, subject) in firing.difference(&now_firing) {
+            metrics.set_host_alert(alert, subject, false);
+            tracing::info!(%alert, %subject, "host alert resolved");
+        }
+        let mut listed: Vec<String> = now_firing
+            .iter()
+            .map(|(alert, subject)| format!("{alert}/{subject}"))
+            .collect();
+        listed.sort_unstable();
+        snapshot.firing = listed;
+        firing = now_firing;
+
+        metrics.set_host(&snapshot);
+        state.update_host(snapshot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISKSTATS: &str = "\
+ 259       0 nvme0n1 1000 0 8000 500 3000 0 24000 1500 0 2000 2000 0 0 0 0
+ 259       1 nvme0n1p1 900 0 7000 450 2900 0 23000 1400 0 1900 1900 0 0 0 0
+   7       0 loop0 10 0 20 1 0 0 0 0 0 1 1 0 0 0 0
+";
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 100 1 0 0 0 0 0 0 100 1 0 0 0 0 0 0
+  eth0: 5000 50 2 7 0 0 0 0 4000 40 1 3 0 0 0 0
+";
+
+    const STAT: &str = "\
+cpu  100 0 50 800 10 0 5 35 0 0
+cpu0 50 0 25 400 5 0 2 18 0 0
+";
+
+    fn config() -> HostConfig {
+        toml::from_str("").unwrap()
+    }
+
+    #[test]
+    fn parses_proc_files() {
+        let disks = parse_diskstats(DISKSTATS);
+        assert_eq!(
+            disks["nvme0n1"],
+            DiskCounters {
+                ios: 4000.0,
+                io_ms: 2000.0,
+                busy_ms: 2000.0
+            }
+        );
+        let nics = parse_net_dev(NET_DEV);
+        assert_eq!(nics.len(), 2);
+        assert_eq!(
+            nics["eth0"],
+            NicCounters {
+                drops: 10.0,
+                errors: 3.0
+            }
+        );
+        assert_eq!(parse_cpu_steal(STAT), Some((35.0, 1000.0)));
+    }
+
+    #[test]
+    fn node_exporter_yields_the_same_counters() {
+        let exposition = r#"
+node_disk_reads_completed_total{device="nvme0n1"} 1000
+node_disk_writes_completed_total{device="nvme0n1"} 3000
+node_disk_read_time_seconds_total{device="nvme0n1"} 0.5
+node_disk_write_time_seconds_total{device="nvme0n1"} 1.5
+node_disk_io_time_seconds_total{device="nvme0n1"} 2
+node_network_receive_drop_total{device="eth0"} 7
+node_network_transmit_drop_total{device="eth0"} 3
+node_network_receive_errs_total{device="eth0"} 2
+node_network_transmit_errs_total{device="eth0"} 1
+node_cpu_seconds_total{cpu="0",mode="user"} 600
+node_cpu_seconds_total{cpu="0",mode="steal"} 35
+node_cpu_seconds_total{cpu="1",mode="idle"} 365
+node_hwmon_temp_celsius{chip="nvme_nvme0",sensor="temp1"} 41.85
+"#;
+        let counters = from_node_exporter(&parse_families(exposition, &NODE_FAMILIES));
+        assert_eq!(
+            counters.disks["nvme0n1"],
+            parse_diskstats(DISKSTATS)["nvme0n1"]
+        );
+        assert_eq!(counters.nics["eth0"], parse_net_dev(NET_DEV)["eth0"]);
+        assert_eq!(counters.cpu, Some((35.0, 1000.0)));
+        assert_eq!(counters.temperatures["nvme_nvme0/temp1"], 41.85);
+    }
+
+    #[test]
+    fn derives_rates_and_alerts() {
+        let before = HostCounters {
+            disks: parse_diskstats(DISKSTATS),
+            nics: parse_net_dev(NET_DEV),
+            cpu: Some((35.0, 1000.0)),
+            temperatures: BTreeMap::new(),
+        };
+        let mut after = before.clone();
+        let disk = after.disks.get_mut("nvme0n1").unwrap();
+        disk.ios += 100.0;
+        disk.io_ms += 5_000.0;
+        disk.busy_ms += 9_500.0;
+        after.nics.get_mut("eth0").unwrap().drops += 200.0;
+        after.cpu = Some((45.0, 1100.0));
+        after.temperatures.insert("nvme_nvme0/temp1".into(), 90.0);
+
+        let mut snapshot = derive(&before, &after, Duration::from_secs(10), &config());
+        // Partitions, loop devices and loopback are left out by default
+        assert_eq!(snapshot.disks.keys().collect::<Vec<_>>(), vec!["nvme0n1"]);
+        assert_eq!(snapshot.nics.keys().collect::<Vec<_>>(), vec!["eth0"]);
+        let disk = &snapshot.disks["nvme0n1"];
+        assert_eq!(disk.utilization, 0.95);
+        assert_eq!(disk.latency_ms, Some(50.0));
+        assert_eq!(snapshot.nics["eth0"].drops_per_sec, 20.0);
+        assert_eq!(snapshot.cpu_steal_ratio, Some(0.1));
+
+        snapshot.nvme_wear.insert(
+            "/dev/nvme0".into(),
+            parse_nvme_wear(br#"{"percent_used": 12}"#).unwrap(),
+        );
+        let alerts: Vec<(String, String)> = evaluate(&snapshot, &HostAlertConfig::default())
+            .into_iter()
+            .map(|alert| (alert.alert.into_owned(), alert.subject))
+            .collect();
+        assert_eq!(
+            alerts,
+            vec![
+                (ALERT_DISK_UTILIZATION.into(), "nvme0n1".into()),
+                (ALERT_DISK_LATENCY.into(), "nvme0n1".into()),
+                (ALERT_NIC_DROPS.into(), "eth0".into()),
+                (ALERT_CPU_STEAL.into(), "cpu".into()),
+                (ALERT_TEMPERATURE.into(), "nvme_nvme0/temp1".into()),
+            ]
+        );
+    }
+}
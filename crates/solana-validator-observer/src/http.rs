@@ -1,23 +1,33 @@
 // Numan Thabit 2025
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use anyhow::Result;
 use axum::{
     body::Body,
-    extract::State,
+    extract::{Path, Query, State},
     http::{header::CONTENT_TYPE, StatusCode},
+    middleware,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{delete, get},
     Json, Router,
 };
+use serde::{Deserialize, Serialize};
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
 use tracing::info;
 
 use crate::{
+    alert::{AlertingService, SilenceRequest},
+    auth::{self, AccessControl},
+    config::HttpConfig,
+    dashboard::{self, Inventory},
     flamegraph::FlamegraphService,
+    history::{self, Aggregation, HistoryStore},
     metrics::ObserverMetrics,
-    state::{ObserverState, ValidatorSnapshot},
+    state::{
+        AnomalyStatus, FleetComparison, ObserverState, PipelineSnapshot, SloStatus,
+        ValidatorSnapshot,
+    },
 };
 
 #[derive(Clone)]
@@ -25,42 +35,88 @@ struct AppState {
     metrics: ObserverMetrics,
     observers: ObserverState,
     flamegraph: Option<FlamegraphService>,
+    alerting: Option<AlertingService>,
+    history: Option<Arc<HistoryStore>>,
+    inventory: Arc<Inventory>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn serve(
     bind: SocketAddr,
+    http: HttpConfig,
     metrics: ObserverMetrics,
     observers: ObserverState,
     flamegraph: Option<FlamegraphService>,
+    alerting: Option<AlertingService>,
+    history: Option<Arc<HistoryStore>>,
+    inventory: Inventory,
 ) -> Result<()> {
+    let access = AccessControl::new(&http)?;
+    let tls = http.tls.as_ref().map(crate::tls::acceptor).transpose()?;
     let state = AppState {
         metrics,
         observers,
         flamegraph,
+        alerting,
+        history,
+        inventory: Arc::new(inventory),
     };
 
     let router = Router::new()
         .route("/metrics", get(metrics_handler))
         .route("/validators", get(validators_handler))
+        .route("/pipeline", get(pipeline_handler))
+        .route("/host", get(host_handler))
+        .route("/slo", get(slo_handler))
+        .route("/anomalies", get(anomalies_handler))
+        .route("/fleet", get(fleet_handler))
+        .route("/fleet/:validator", get(fleet_validator_handler))
         .route("/healthz", get(health_handler))
+        .route("/dashboard", get(dashboard_handler))
         .route("/debug/flamegraph", get(flamegraph_handler))
+        .route(
+            "/debug/flamegraph/profiles",
+            get(flamegraph_profiles_handler),
+        )
+        .route(
+            "/debug/flamegraph/profiles/:id",
+            get(flamegraph_profile_handler),
+        )
+        .route("/debug/flamegraph/diff", get(flamegraph_diff_handler))
+        .route(
+            "/silences",
+            get(list_silences_handler).post(add_silence_handler),
+        )
+        .route("/silences/suppressed", get(suppressed_alerts_handler))
+        .route("/silences/:id", delete(remove_silence_handler))
+        .route("/maintenance", get(maintenance_handler))
+        .route("/history/series", get(history_series_handler))
+        .route("/history/query", get(history_query_handler))
         .with_state(state)
+        .layer(middleware::from_fn_with_state(access, auth::require_token))
         .layer(TraceLayer::new_for_http());
 
-    info!(bind = %bind, "HTTP server listening");
+    info!(bind = %bind, tls = tls.is_some(), tokens = http.tokens.len(), "HTTP server listening");
     let listener = TcpListener::bind(bind).await?;
-    axum::serve(listener, router)
-        .with_graceful_shutdown(async {
-            if let Err(err) = tokio::signal::ctrl_c().await {
-                tracing::error!(error = %err, "failed to listen for shutdown signal");
-            }
-            tracing::info!("shutdown signal received; terminating http server");
-        })
-        .await?;
+    match tls {
+        Some(acceptor) => crate::tls::serve(listener, router, acceptor, shutdown_signal()).await?,
+        None => {
+            axum::serve(listener, router)
+                .with_graceful_shutdown(shutdown_signal())
+                .await?
+        }
+    }
 
     Ok(())
 }
 
+async fn shutdown_signal() {
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::error!(error = %err, "failed to listen for shutdown signal");
+    }
+    tracing::info!("shutdown signal received; terminating http server");
+}
+
 async fn metrics_handler(State(state): State<AppState>) -> impl IntoResponse {
     match state.metrics.gather() {
         Ok(body) => (StatusCode::OK, body).into_response(),
@@ -76,10 +132,195 @@ async fn validators_handler(State(state): State<AppState>) -> impl IntoResponse
     Json(snapshots)
 }
 
+async fn pipeline_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let snapshots: Vec<PipelineSnapshot> = state.observers.pipeline_snapshots();
+    Json(snapshots)
+}
+
+async fn host_handler(State(state): State<AppState>) -> Response {
+    match state.observers.host_snapshot() {
+        Some(snapshot) => Json(snapshot).into_response(),
+        None => (StatusCode::NOT_FOUND, "no host metrics collected").into_response(),
+    }
+}
+
+async fn slo_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let statuses: Vec<SloStatus> = state.observers.slo_statuses();
+    Json(statuses)
+}
+
+async fn anomalies_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let statuses: Vec<AnomalyStatus> = state.observers.anomaly_statuses();
+    Json(statuses)
+}
+
+async fn fleet_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let comparisons: Vec<FleetComparison> = state.observers.fleet_comparisons();
+    Json(comparisons)
+}
+
+/// Every comparison narrowed down to one validator against its peers.
+async fn fleet_validator_handler(
+    State(state): State<AppState>,
+    Path(validator): Path<String>,
+) -> Response {
+    let comparisons: Vec<FleetComparison> = state
+        .observers
+        .fleet_comparisons()
+        .into_iter()
+        .filter_map(|mut comparison| {
+            comparison
+                .members
+                .retain(|member| member.validator == validator);
+            (!comparison.members.is_empty()).then_some(comparison)
+        })
+        .collect();
+    if comparisons.is_empty() {
+        return (StatusCode::NOT_FOUND, "validator not compared").into_response();
+    }
+    Json(comparisons).into_response()
+}
+
+/// The Grafana dashboard for the components as discovered so far.
+async fn dashboard_handler(State(state): State<AppState>) -> impl IntoResponse {
+    let inventory =
+        Inventory::clone(&state.inventory).discovered(&state.observers.pipeline_snapshots());
+    Json(dashboard::generate(&inventory))
+}
+
 async fn health_handler() -> impl IntoResponse {
     (StatusCode::OK, "ok")
 }
 
+async fn list_silences_handler(State(state): State<AppState>) -> Response {
+    match state.alerting {
+        Some(ref alerting) => Json(alerting.silences()).into_response(),
+        None => (StatusCode::NOT_FOUND, "alerting disabled").into_response(),
+    }
+}
+
+async fn add_silence_handler(
+    State(state): State<AppState>,
+    Json(request): Json<SilenceRequest>,
+) -> Response {
+    match state.alerting {
+        Some(ref alerting) => {
+            (StatusCode::CREATED, Json(alerting.add_silence(request))).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "alerting disabled").into_response(),
+    }
+}
+
+async fn remove_silence_handler(State(state): State<AppState>, Path(id): Path<u64>) -> Response {
+    match state.alerting {
+        Some(ref alerting) if alerting.remove_silence(id) => StatusCode::NO_CONTENT.into_response(),
+        Some(_) => (StatusCode::NOT_FOUND, "no such silence").into_response(),
+        None => (StatusCode::NOT_FOUND, "alerting disabled").into_response(),
+    }
+}
+
+async fn suppressed_alerts_handler(State(state): State<AppState>) -> Response {
+    match state.alerting {
+        Some(ref alerting) => Json(alerting.suppressed()).into_response(),
+        None => (StatusCode::NOT_FOUND, "alerting disabled").into_response(),
+    }
+}
+
+async fn maintenance_handler(State(state): State<AppState>) -> Response {
+    match state.alerting {
+        Some(ref alerting) => Json(alerting.maintenance_windows()).into_response(),
+        None => (StatusCode::NOT_FOUND, "alerting disabled").into_response(),
+    }
+}
+
+/// Range in unix seconds; the last hour when left out.
+#[derive(Debug, Deserialize)]
+struct HistoryRange {
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+impl HistoryRange {
+    fn millis(&self) -> (u64, u64) {
+        let end = self
+            .end
+            .map_or_else(history::now_millis, |end| end.saturating_mul(1_000));
+        let start = self.start.map_or(end.saturating_sub(3_600_000), |start| {
+            start.saturating_mul(1_000)
+        });
+        (start, end)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    series: String,
+    start: Option<u64>,
+    end: Option<u64>,
+    /// Downsampling bucket in seconds.
+    step: Option<u64>,
+    #[serde(default)]
+    agg: Aggregation,
+}
+
+#[derive(Debug, Serialize)]
+struct HistoryResponse {
+    series: String,
+    /// `[unix seconds, value]` pairs.
+    points: Vec<(f64, f64)>,
+}
+
+async fn history_series_handler(
+    State(state): State<AppState>,
+    Query(range): Query<HistoryRange>,
+) -> Response {
+    let Some(store) = state.history else {
+        return (StatusCode::NOT_FOUND, "history disabled").into_response();
+    };
+    let (start, end) = range.millis();
+    match tokio::task::spawn_blocking(move || store.series(start, end)).await {
+        Ok(Ok(series)) => Json(series).into_response(),
+        Ok(Err(err)) => history_error(err),
+        Err(err) => history_error(err.into()),
+    }
+}
+
+async fn history_query_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Response {
+    let Some(store) = state.history else {
+        return (StatusCode::NOT_FOUND, "history disabled").into_response();
+    };
+    let (start, end) = HistoryRange {
+        start: query.start,
+        end: query.end,
+    }
+    .millis();
+    let step = query.step.map(|step| step.saturating_mul(1_000));
+    let series = query.series.clone();
+    let points =
+        tokio::task::spawn_blocking(move || store.query(&series, start, end, step, query.agg))
+            .await;
+    match points {
+        Ok(Ok(points)) => Json(HistoryResponse {
+            series: query.series,
+            points: points
+                .into_iter()
+                .map(|(t, value)| (t as f64 / 1_000.0, value))
+                .collect(),
+        })
+        .into_response(),
+        Ok(Err(err)) => history_error(err),
+        Err(err) => history_error(err.into()),
+    }
+}
+
+fn history_error(err: anyhow::Error) -> Response {
+    tracing::error!(error = %err, "history query failed");
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+}
+
 async fn flamegraph_handler(State(state): State<AppState>) -> impl IntoResponse {
     match state.flamegraph {
         Some(ref service) => match service.snapshot_svg() {
@@ -102,3 +343,49 @@ async fn flamegraph_handler(State(state): State<AppState>) -> impl IntoResponse
             .unwrap(),
     }
 }
+
+#[derive(Debug, Deserialize)]
+struct FlamegraphDiffQuery {
+    before: u64,
+    after: u64,
+}
+
+async fn flamegraph_profiles_handler(State(state): State<AppState>) -> Response {
+    match state.flamegraph {
+        Some(ref service) => Json(service.profiles()).into_response(),
+        None => (StatusCode::NOT_FOUND, "flamegraph disabled").into_response(),
+    }
+}
+
+async fn flamegraph_profile_handler(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Response {
+    let Some(service) = state.flamegraph else {
+        return (StatusCode::NOT_FOUND, "flamegraph disabled").into_response();
+    };
+    svg_response(tokio::task::spawn_blocking(move || service.profile_svg(id)).await)
+}
+
+async fn flamegraph_diff_handler(
+    State(state): State<AppState>,
+    Query(query): Query<FlamegraphDiffQuery>,
+) -> Response {
+    let Some(service) = state.flamegraph else {
+        return (StatusCode::NOT_FOUND, "flamegraph disabled").into_response();
+    };
+    svg_response(
+        tokio::task::spawn_blocking(move || service.diff_svg(query.before, query.after)).await,
+    )
+}
+
+fn svg_response(rendered: Result<Option<Result<Vec<u8>>>, tokio::task::JoinError>) -> Response {
+    let err = match rendered {
+        Ok(Some(Ok(svg))) => return ([(CONTENT_TYPE, "image/svg+xml")], svg).into_response(),
+        Ok(None) => return (StatusCode::NOT_FOUND, "no such profile").into_response(),
+        Ok(Some(Err(err))) => err,
+        Err(err) => err.into(),
+    };
+    tracing::error!(error = %err, "failed to render flamegraph");
+    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response()
+}
@@ -0,0 +1,310 @@
+// Numan Thabit 2025
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use reqwest::{Client, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{LeaderConfig, ValidatorConfig},
+    metrics::ObserverMetrics,
+    state::{LeaderStats, ObserverState},
+};
+
+pub const ALERT_SKIP_RATE: &str = "skip_rate";
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcResponse<T> {
+    result: T,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EpochInfo {
+    epoch: u64,
+    absolute_slot: u64,
+    slot_index: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockProduction {
+    value: BlockProductionValue,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockProductionValue {
+    /// Identity to `[leader slots, blocks produced]` so far this epoch.
+    by_identity: HashMap<String, (u64, u64)>,
+}
+
+/// Skip rate over a rolling window, carried across epoch boundaries.
+#[derive(Debug)]
+pub struct SkipWindow {
+    window: Duration,
+    /// Epoch and its cumulative leader slots and blocks at the last poll.
+    last: Option<(u64, u64, u64)>,
+    /// Leader slots and blocks since the observer started.
+    totals: (u64, u64),
+    samples: VecDeque<(Instant, u64, u64)>,
+}
+
+impl SkipWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last: None,
+            totals: (0, 0),
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Record this epoch's production so far; returns the leader slots and
+    /// skip rate within the window, once any leader slot fell in it.
+    pub fn observe(
+        &mut self,
+        now: Instant,
+        epoch: u64,
+        leader_slots: u64,
+        produced: u64,
+    ) -> Option<(u64, f64)> {
+        let (slots_delta, produced_delta) = match self.last {
+            Some((last_epoch, last_slots, last_produced)) if last_epoch == epoch => (
+                leader_slots.saturating_sub(last_slots),
+                produced.saturating_sub(last_produced),
+            ),
+            // The first poll only sets the baseline; a new epoch starts from zero
+            None => (0, 0),
+            Some(_) => (leader_slots, produced),
+        };
+        self.last = Some((epoch, leader_slots, produced));
+        self.totals.0 += slots_delta;
+        self.totals.1 += produced_delta;
+        self.samples.push_back((now, self.totals.0, self.totals.1));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _, _)| now.duration_since(*at) > self.window)
+        {
+            self.samples.pop_front();
+        }
+
+        let (_, oldest_slots, oldest_produced) = *self.samples.front()?;
+        let slots = self.totals.0 - oldest_slots;
+        let produced = self.totals.1 - oldest_produced;
+        (slots > 0).then(|| (slots, 1.0 - produced as f64 / slots as f64))
+    }
+}
+
+pub fn spawn_poller(
+    config: LeaderConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, validators, state, metrics, alerting).await {
+            tracing::error!(%err, "leader poll loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: LeaderConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    let monitored: Vec<(String, String)> = validators
+        .into_iter()
+        .filter_map(|v| v.identity.map(|identity| (v.name, identity)))
+        .collect();
+    if monitored.is_empty() {
+        tracing::warn!("leader tracking configured but no validator has an identity; skipping");
+        return Ok(());
+    }
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .context("failed to construct leader rpc client")?;
+    let mut ticker = interval_at(Instant::now(), config.poll_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut windows: HashMap<String, SkipWindow> = monitored
+        .iter()
+        .map(|(name, _)| (name.clone(), SkipWindow::new(config.window())))
+        .collect();
+    let mut schedule_epoch = None;
+    let mut scheduled: HashMap<String, Vec<u64>> = HashMap::new();
+
+    loop {
+        ticker.tick().await;
+
+        let polled = async {
+            let epoch: EpochInfo =
+                call(&client, &config.rpc_url, "getEpochInfo", json!([])).await?;
+            if schedule_epoch != Some(epoch.epoch) {
+                scheduled.clear();
+                for (name, identity) in &monitored {
+                    let schedule: Option<HashMap<String, Vec<u64>>> = call(
+                        &client,
+                        &config.rpc_url,
+                        "getLeaderSchedule",
+                        json!([null, {"identity": identity}]),
+                    )
+                    .await?;
+                    let slots = schedule
+                        .and_then(|mut schedule| schedule.remove(identity))
+                        .unwrap_or_default();
+                    scheduled.insert(name.clone(), slots);
+                }
+                schedule_epoch = Some(epoch.epoch);
+            }
+            let production: BlockProduction =
+                call(&client, &config.rpc_url, "getBlockProduction", json!([])).await?;
+            Ok::<_, anyhow::Error>((epoch, production))
+        };
+        let (epoch, production) = match polled.await {
+            Ok(polled) => polled,
+            Err(err) => {
+                tracing::debug!(error = %err, "leader schedule poll failed");
+                for (name, _) in &monitored {
+                    metrics.inc_scrape_error(name, "leader");
+                }
+                continue;
+            }
+        };
+        let now = Instant::now();
+
+        for (name, identity) in &monitored {
+            let schedule = scheduled.get(name).map(Vec::as_slice).unwrap_or_default();
+            let (leader_slots, produced) = production
+                .value
+                .by_identity
+                .get(identity)
+                .copied()
+                .unwrap_or_default();
+            let skipped = leader_slots.saturating_sub(produced);
+            let epoch_skip_rate = (leader_slots > 0).then(|| skipped as f64 / leader_slots as f64);
+            let window = windows
+                .get_mut(name)
+                .and_then(|window| window.observe(now, epoch.epoch, leader_slots, produced));
+
+            metrics.set_leader_slots(name, schedule.len() as u64, leader_slots, produced);
+            if let Some(rate) = epoch_skip_rate {
+                metrics.set_skip_rate(name, "epoch", rate);
+            }
+            if let Some((_, rate)) = window {
+                metrics.set_skip_rate(name, "rolling", rate);
+            }
+            state.update_leader(
+                name,
+                LeaderStats {
+                    epoch: epoch.epoch,
+                    scheduled_slots: schedule.len() as u64,
+                    leader_slots,
+                    produced,
+                    skipped,
+                    next_leader_slot: schedule
+                        .iter()
+                        .find(|index| **index > epoch.slot_index)
+                        .map(|index| epoch.absolute_slot - epoch.slot_index + index),
+                    epoch_skip_rate,
+                    window_skip_rate: window.map(|(_, rate)| rate),
+                },
+            );
+
+            let Some((window_slots, rate)) = window else {
+                continue;
+            };
+            if window_slots < config.min_leader_slots || rate <= config.max_skip_rate {
+                continue;
+            }
+            tracing::warn!(
+                validator = %name,
+                skip_rate = rate,
+                leader_slots = window_slots,
+                threshold = config.max_skip_rate,
+                "skip rate over threshold"
+            );
+            if let Some(alerting) = &alerting {
+                let alert = Alert {
+                    alert: ALERT_SKIP_RATE.into(),
+                    subject: name.clone(),
+                    value: rate,
+                    threshold: config.max_skip_rate,
+                };
+                if let Err(err) = alerting.fire(&alert).await {
+                    tracing::warn!(validator = %name, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+    }
+}
+
+async fn call<T: DeserializeOwned>(
+    client: &Client,
+    url: &Url,
+    method: &str,
+    params: Value,
+) -> Result<T> {
+    let response = client
+        .post(url.clone())
+        .json(&json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}))
+        .send()
+        .await
+        .with_context(|| format!("{method} request failed"))?;
+    if !response.status().is_success() {
+        anyhow::bail!("rpc endpoint returned status {}", response.status());
+    }
+    Ok(response
+        .json::<JsonRpcResponse<T>>()
+        .await
+        .with_context(|| format!("failed to decode {method} body"))?
+        .result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rolling_skip_rate_spans_epochs_and_expires() {
+        let mut window = SkipWindow::new(Duration::from_secs(100));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(window.observe(at(0), 10, 40, 38), None);
+        assert_eq!(window.observe(at(30), 10, 48, 44), Some((8, 0.25)));
+        // New epoch: production restarts from zero
+        let (slots, rate) = window.observe(at(60), 11, 4, 4).unwrap();
+        assert_eq!(slots, 12);
+        assert!((rate - 2.0 / 12.0).abs() < 1e-9);
+        // The first two samples aged out of the window
+        assert_eq!(window.observe(at(150), 11, 8, 8), Some((4, 0.0)));
+        assert_eq!(window.observe(at(400), 11, 8, 8), None);
+    }
+
+    #[test]
+    fn decodes_block_production() {
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":{"context":{"slot":9887},
+            "value":{"byIdentity":{"85iYT5RuzRTDgjyRa3cP8SYhM2j21fj7NhfJ3peu1DPr":[9888,9886]},
+            "range":{"firstSlot":0,"lastSlot":9887}}}}"#;
+        let production: JsonRpcResponse<BlockProduction> = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            production.result.value.by_identity["85iYT5RuzRTDgjyRa3cP8SYhM2j21fj7NhfJ3peu1DPr"],
+            (9888, 9886)
+        );
+    }
+}
@@ -0,0 +1,443 @@
+// Numan Thabit 2025
+use std::{
+    io::{ErrorKind, SeekFrom},
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+    process::Command,
+    task::JoinHandle,
+    time::{sleep, Instant},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{LogRuleConfig, LogWatchConfig},
+    metrics::ObserverMetrics,
+};
+
+/// Longest part of a matching line that is logged.
+const EXCERPT_LEN: usize = 256;
+const JOURNALCTL_RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct LogRule {
+    name: String,
+    regex: Regex,
+    rate_limit: Duration,
+}
+
+pub fn compile_rules(rules: &[LogRuleConfig]) -> Result<Arc<[LogRule]>> {
+    rules
+        .iter()
+        .map(|rule| {
+            Ok(LogRule {
+                name: rule.name.clone(),
+                regex: Regex::new(&rule.pattern)
+                    .with_context(|| format!("invalid pattern for log rule {}", rule.name))?,
+                rate_limit: rule.rate_limit(),
+            })
+        })
+        .collect()
+}
+
+/// A rule that matched a line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hit<'a> {
+    pub rule: &'a str,
+    /// Matches since the rule last alerted for this source, this one
+    /// included; `None` while the rule is rate limited.
+    pub alert: Option<u64>,
+}
+
+/// Matches one source's lines against every rule, rate limiting alerts per rule.
+#[derive(Debug)]
+pub struct LogMatcher {
+    rules: Arc<[LogRule]>,
+    /// Per rule: when it last alerted and the matches held back since.
+    limits: Vec<(Option<Instant>, u64)>,
+}
+
+impl LogMatcher {
+    pub fn new(rules: Arc<[LogRule]>) -> Self {
+        let limits = vec![(None, 0); rules.len()];
+        Self { rules, limits }
+    }
+
+    pub fn observe(&mut self, line: &str, now: Instant) -> Vec<Hit<'_>> {
+        let mut hits = Vec::new();
+        for (rule, (last_alert, held)) in self.rules.iter().zip(&mut self.limits) {
+            if !rule.regex.is_match(line) {
+                continue;
+            }
+            *held += 1;
+            let alert = match last_alert {
+                Some(at) if now.duration_since(*at) < rule.rate_limit => None,
+                _ => {
+                    *last_alert = Some(now);
+                    Some(std::mem::take(held))
+                }
+            };
+            hits.push(Hit {
+                rule: &rule.name,
+                alert,
+            });
+        }
+        hits
+    }
+}
+
+/// Follows a file like `tail -F`: starts at its end, and reopens it from the
+/// start once it is truncated or replaced by rotation.
+pub struct FileTail {
+    path: PathBuf,
+    poll_interval: Duration,
+    reader: Option<BufReader<File>>,
+    position: u64,
+    /// Whether the next open skips what the file already holds.
+    from_end: bool,
+    partial: Vec<u8>,
+}
+
+impl FileTail {
+    pub fn new(path: impl Into<PathBuf>, poll_interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            poll_interval,
+            reader: None,
+            position: 0,
+            from_end: true,
+            partial: Vec::new(),
+        }
+    }
+
+    pub async fn next_line(&mut self) -> Result<String> {
+        loop {
+            let Some(reader) = self.reader.as_mut() else {
+                match File::open(&self.path).await {
+                    Ok(mut file) => {
+                        self.position = if self.from_end {
+                            file.seek(SeekFrom::End(0)).await?
+                        } else {
+                            0
+                        };
+                        self.reader = Some(BufReader::new(file));
+                    }
+                    // Not created yet, or between rotation and recreation;
+                    // whatever it holds once it shows up is new
+                    Err(err) if err.kind() == ErrorKind::NotFound => {
+                        self.from_end = false;
+                        sleep(self.poll_interval).await;
+                    }
+                    Err(err) => {
+                        return Err(err)
+                            .with_context(|| format!("failed to open {}", self.path.display()))
+                    }
+                }
+                continue;
+            };
+
+            let read = reader.read_until(b'\n', &mut self.partial).await?;
+            self.position += read as u64;
+            if self.partial.ends_with(b"\n") {
+                return Ok(self.take_line());
+            }
+            if read > 0 {
+                continue;
+            }
+
+            // At the end of what was written so far
+            let opened = reader.get_ref().metadata().await?;
+            let replaced = match tokio::fs::metadata(&self.path).await {
+                Ok(current) => file_id(&current) != file_id(&opened),
+                Err(err) if err.kind() == ErrorKind::NotFound => true,
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("failed to stat {}", self.path.display()))
+                }
+            };
+            if replaced {
+                tracing::debug!(path = %self.path.display(), "log file rotated; reopening");
+                self.reader = None;
+                self.from_end = false;
+                if !self.partial.is_empty() {
+                    return Ok(self.take_line());
+                }
+            } else if opened.len() < self.position {
+                tracing::debug!(path = %self.path.display(), "log file truncated; rereading");
+                reader.seek(SeekFrom::Start(0)).await?;
+                self.position = 0;
+                self.partial.clear();
+            } else {
+                sleep(self.poll_interval).await;
+            }
+        }
+    }
+
+    fn take_line(&mut self) -> String {
+        let line = String::from_utf8_lossy(&self.partial)
+            .trim_end_matches(['\r', '\n'])
+            .to_string();
+        self.partial.clear();
+        line
+    }
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+pub fn spawn_watchers(
+    config: &LogWatchConfig,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<Vec<JoinHandle<()>>> {
+    let rules = compile_rules(&config.rules)?;
+    let mut handles = Vec::new();
+
+    for file in &config.files {
+        let source = file.name.clone();
+        let tail = FileTail::new(&file.path, config.poll_interval());
+        let matcher = LogMatcher::new(rules.clone());
+        let metrics = metrics.clone();
+        let alerting = alerting.clone();
+        handles.push(tokio::spawn(async move {
+            if let Err(err) = run_file(&source, tail, matcher, metrics, alerting).await {
+                tracing::error!(%err, %source, "log file watch loop terminated");
+            }
+        }));
+    }
+
+    for unit in &config.journald_units {
+        let unit = unit.clone();
+        let matcher = LogMatcher::new(rules.clone());
+        let metrics = metrics.clone();
+        let alerting = alerting.clone();
+        handles.push(tokio::spawn(async move {
+            if let Err(err) = run_journald(&unit, matcher, metrics, alerting).await {
+                tracing::error!(%err, %unit, "journald watch loop terminated");
+            }
+        }));
+    }
+
+    Ok(handles)
+}
+
+async fn run_file(
+    source: &str,
+    mut tail: FileTail,
+    mut matcher: LogMatcher,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    loop {
+        let line = tail.next_line().await?;
+        dispatch(source, &line, &mut matcher, &metrics, alerting.as_ref()).await;
+    }
+}
+
+async fn run_journald(
+    unit: &str,
+    mut matcher: LogMatcher,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    loop {
+        let mut child = Command::new("journalctl")
+            .args(["--follow", "--lines=0", "--output=cat", "--unit", unit])
+            .stdout(Stdio::piped())
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to spawn journalctl")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("journalctl stdout not captured")?;
+        let mut lines = BufReader::new(stdout).split(b'\n');
+        while let Some(line) = lines.next_segment().await? {
+            let line = String::from_utf8_lossy(&line);
+            dispatch(unit, &line, &mut matcher, &metrics, alerting.as_ref()).await;
+        }
+        let status = child.wait().await?;
+        tracing::warn!(%unit, %status, "journalctl exited; restarting");
+        sleep(JOURNALCTL_RESTART_DELAY).await;
+    }
+}
+
+async fn dispatch(
+    source: &str,
+    line: &str,
+    matcher: &mut LogMatcher,
+    metrics: &ObserverMetrics,
+    alerting: Option<&AlertingService>,
+) {
+    for hit in matcher.observe(line, Instant::now()) {
+        metrics.inc_log_match(source, hit.rule);
+        let Some(matches) = hit.alert else {
+            if alerting.is_some() {
+                metrics.inc_alert_suppressed(hit.rule, "rate_limited");
+            }
+            continue;
+        };
+        tracing::warn!(
+            %source,
+            rule = hit.rule,
+            matches,
+            line = excerpt(line),
+            "log line matched rule"
+        );
+        if let Some(alerting) = alerting {
+            let alert = Alert {
+                alert: hit.rule.to_string().into(),
+                subject: source.to_string(),
+                value: matches as f64,
+                threshold: 0.0,
+            };
+            if let Err(err) = alerting.fire(&alert).await {
+                tracing::warn!(%source, error = %err, "failed to trigger alert");
+            }
+        }
+    }
+}
+
+fn excerpt(line: &str) -> &str {
+    match line.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => &line[..end],
+        None => line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::config::LogWatchConfig;
+
+    fn default_rules() -> Arc<[LogRule]> {
+        let config: LogWatchConfig = toml::from_str("").unwrap();
+        compile_rules(&config.rules).unwrap()
+    }
+
+    #[test]
+    fn default_rules_match_known_failures() {
+        let mut matcher = LogMatcher::new(default_rules());
+        let now = Instant::now();
+        let rule = |matcher: &mut LogMatcher, line| {
+            let hits = matcher.observe(line, now);
+            hits.first().map(|hit| hit.rule.to_string())
+        };
+        assert_eq!(
+            rule(
+                &mut matcher,
+                "thread 'solReplayStage' panicked at core/src/replay_stage.rs:1:2"
+            )
+            .as_deref(),
+            Some("panic")
+        );
+        assert_eq!(
+            rule(
+                &mut matcher,
+                "[2025-01-01T00:00:00Z ERROR geyser] writer did not terminate in time"
+            )
+            .as_deref(),
+            Some("writer_not_terminated")
+        );
+        assert_eq!(
+            rule(&mut matcher, "Bank hash mismatch for slot 12345").as_deref(),
+            Some("bank_hash_mismatch")
+        );
+        assert_eq!(rule(&mut matcher, "new root 12345"), None);
+    }
+
+    #[test]
+    fn rate_limits_alerts_and_reports_held_matches() {
+        let rules = compile_rules(&[LogRuleConfig {
+            name: "panic".into(),
+            pattern: "panicked".into(),
+            rate_limit: Some(Duration::from_secs(60)),
+        }])
+        .unwrap();
+        let mut matcher = LogMatcher::new(rules);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let alerts = |matcher: &mut LogMatcher, secs| {
+            matcher
+                .observe("thread 'main' panicked", at(secs))
+                .into_iter()
+                .map(|hit| hit.alert)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(alerts(&mut matcher, 0), vec![Some(1)]);
+        assert_eq!(alerts(&mut matcher, 10), vec![None]);
+        assert_eq!(alerts(&mut matcher, 20), vec![None]);
+        assert_eq!(alerts(&mut matcher, 61), vec![Some(3)]);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let err = compile_rules(&[LogRuleConfig {
+            name: "broken".into(),
+            pattern: "(unclosed".into(),
+            rate_limit: None,
+        }])
+        .unwrap_err();
+        assert!(err.to_string().contains("broken"));
+    }
+
+    #[tokio::test]
+    async fn tail_follows_truncation_and_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("validator.log");
+        let append = |text: &str| {
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap()
+                .write_all(text.as_bytes())
+                .unwrap()
+        };
+        append("old line\n");
+
+        let mut tail = FileTail::new(&path, Duration::from_millis(10));
+        async fn next(tail: &mut FileTail) -> String {
+            tokio::time::timeout(Duration::from_secs(5), tail.next_line())
+                .await
+                .expect("line before timeout")
+                .unwrap()
+        }
+
+        // Opens at the end, so the first read blocks until something is appended
+        let pending = tokio::time::timeout(Duration::from_millis(50), tail.next_line()).await;
+        assert!(pending.is_err());
+        append("first\nsec");
+        assert_eq!(next(&mut tail).await, "first");
+        append("ond\n");
+        assert_eq!(next(&mut tail).await, "second");
+
+        std::fs::write(&path, "").unwrap();
+        append("after truncate\n");
+        assert_eq!(next(&mut tail).await, "after truncate");
+
+        std::fs::rename(&path, dir.path().join("validator.log.1")).unwrap();
+        append("after rotate\n");
+        assert_eq!(next(&mut tail).await, "after rotate");
+    }
+}
@@ -1,21 +1,37 @@
 // Numan Thabit 2025
 mod alert;
+mod anomaly;
+mod auth;
+mod catchup;
 mod config;
 mod dashboard;
 mod flamegraph;
+mod fleet;
+mod history;
+mod host;
 mod http;
+mod leader;
+mod logwatch;
+mod maintenance;
 mod metrics;
+mod pipeline;
+mod remediation;
 mod scraper;
+mod slo;
 mod state;
 mod telemetry;
+mod tls;
+mod votes;
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::Arc};
 
 use alert::AlertingService;
 use anyhow::Result;
 use clap::Parser;
 use config::ObserverConfig;
+use dashboard::Inventory;
 use flamegraph::FlamegraphService;
+use history::HistoryStore;
 use metrics::ObserverMetrics;
 use state::ObserverState;
 use tracing_subscriber::{fmt, EnvFilter};
@@ -27,7 +43,8 @@ struct Cli {
     #[arg(long, default_value = "ops/solana-validator-observer.example.toml")]
     config: PathBuf,
 
-    /// Optional path to export the Grafana dashboard JSON
+    /// Optional path to export a Grafana dashboard generated from the config
+    /// and the pipeline components it finds
     #[arg(long)]
     grafana_export: Option<PathBuf>,
 }
@@ -51,7 +68,7 @@ async fn main() -> Result<()> {
     let observer_state = ObserverState::new(&validator_names);
 
     let alerting = match config.alerting.clone() {
-        Some(cfg) => Some(AlertingService::new(cfg)?),
+        Some(cfg) => Some(AlertingService::new(cfg, metrics.clone())?),
         None => None,
     };
 
@@ -61,7 +78,8 @@ async fn main() -> Result<()> {
     }
 
     if let Some(path) = cli.grafana_export {
-        dashboard::write_to(&path).await?;
+        let inventory = dashboard::discover(&config).await;
+        dashboard::write_to(&path, &inventory).await?;
         tracing::info!(path = %path.display(), "exported grafana dashboard");
     }
 
@@ -76,18 +94,132 @@ async fn main() -> Result<()> {
         alerting.clone(),
     );
 
+    let pipeline_handles = pipeline::spawn_scrapers(
+        config.pipeline.clone(),
+        config.pipeline_alerts.clone(),
+        observer_state.clone(),
+        metrics.clone(),
+        config.scrape_interval(),
+        alerting.clone(),
+    );
+
+    let vote_handle = config.votes.clone().map(|votes| {
+        votes::spawn_poller(
+            votes,
+            config.validators.clone(),
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let leader_handle = config.leaders.clone().map(|leaders| {
+        leader::spawn_poller(
+            leaders,
+            config.validators.clone(),
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let catchup_handle = config.catchup.clone().map(|catchup| {
+        catchup::spawn_monitor(
+            catchup,
+            config.validators.clone(),
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let host_handle = config.host.clone().map(|host| {
+        host::spawn_collector(
+            host,
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let slo_handle = config.slo.clone().map(|slo| {
+        slo::spawn_evaluator(
+            slo,
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let anomaly_handle = config.anomaly.clone().map(|anomaly| {
+        anomaly::spawn_detector(
+            anomaly,
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let fleet_handle = config.fleet.clone().map(|fleet| {
+        fleet::spawn_comparator(
+            fleet,
+            observer_state.clone(),
+            metrics.clone(),
+            alerting.clone(),
+        )
+    });
+
+    let history = match &config.history {
+        Some(cfg) => Some(Arc::new(HistoryStore::open(
+            &cfg.path,
+            cfg.partition(),
+            cfg.retention(),
+        )?)),
+        None => None,
+    };
+    let history_handle = config
+        .history
+        .as_ref()
+        .zip(history.clone())
+        .map(|(cfg, store)| history::spawn_recorder(cfg, store, metrics.clone()));
+
+    let log_handles = match &config.log_watch {
+        Some(cfg) => logwatch::spawn_watchers(cfg, metrics.clone(), alerting.clone())?,
+        None => Vec::new(),
+    };
+
     http::serve(
         config.metrics_bind,
+        config.http.clone(),
         metrics,
         observer_state.clone(),
         flamegraph.clone(),
+        alerting.clone(),
+        history,
+        Inventory::from_config(&config),
     )
     .await?;
 
     if let Some(handle) = telemetry_handle {
         handle.abort();
     }
-    for handle in scraper_handles {
+    for handle in vote_handle
+        .into_iter()
+        .chain(leader_handle)
+        .chain(catchup_handle)
+        .chain(host_handle)
+        .chain(slo_handle)
+        .chain(anomaly_handle)
+        .chain(fleet_handle)
+        .chain(history_handle)
+    {
+        handle.abort();
+    }
+    for handle in scraper_handles
+        .into_iter()
+        .chain(pipeline_handles)
+        .chain(log_handles)
+    {
         handle.abort();
     }
 
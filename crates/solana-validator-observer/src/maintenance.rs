@@ -0,0 +1,262 @@
+// Numan Thabit 2025
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, DurationRound, TimeDelta, Timelike, Utc};
+use serde::Serialize;
+
+use crate::{alert::Alert, config::MaintenanceWindowConfig};
+
+/// A five-field cron expression (minute, hour, day of month, month, day of
+/// week) evaluated in UTC. Fields take `*`, values, `a-b` ranges, `/n` steps
+/// and comma-separated lists; Sunday is 0 or 7.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month and day of week were both restricted, in which
+    /// case either matching is enough.
+    either_day: bool,
+}
+
+impl Schedule {
+    pub fn matches(&self, at: DateTime<Utc>) -> bool {
+        let day = bit(self.days, at.day());
+        let weekday = bit(self.weekdays, at.weekday().num_days_from_sunday());
+        bit(self.minutes, at.minute())
+            && bit(self.hours, at.hour())
+            && bit(self.months, at.month())
+            && if self.either_day {
+                day || weekday
+            } else {
+                day && weekday
+            }
+    }
+}
+
+fn bit(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!(
+                "schedule {expression:?} needs five fields, got {}",
+                fields.len()
+            );
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        // 7 is another Sunday
+        if bit(weekdays, 7) {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+}
+
+fn field(text: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0;
+    for part in text.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .with_context(|| format!("bad step in {part:?}"))?,
+            ),
+            None => (part, 1),
+        };
+        let value = |text: &str| {
+            text.parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .with_context(|| format!("{text:?} is not a value from {min} to {max}"))
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            // A single value with a step runs to the end of the range
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let value = value(range)?;
+                (value, value)
+            }
+        };
+        if start > end {
+            bail!("range {range:?} runs backwards");
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// A recurring window during which matching alerts are held back.
+#[derive(Debug, Clone)]
+pub struct MaintenanceWindow {
+    config: MaintenanceWindowConfig,
+    schedule: Schedule,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceStatus {
+    pub name: String,
+    pub schedule: String,
+    pub duration_secs: u64,
+    pub alerts: Vec<String>,
+    pub validators: Vec<String>,
+    pub comment: Option<String>,
+    /// End of the window in progress, if one is.
+    pub active_until: Option<DateTime<Utc>>,
+}
+
+impl MaintenanceWindow {
+    pub fn new(config: MaintenanceWindowConfig) -> Result<Self> {
+        let schedule = config
+            .schedule
+            .parse()
+            .with_context(|| format!("invalid schedule for maintenance window {}", config.name))?;
+        if config.duration.is_zero() {
+            bail!("maintenance window {} has no duration", config.name);
+        }
+        Ok(Self { config, schedule })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// End of the latest window started at or before `now` that has not
+    /// ended yet.
+    pub fn active_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let duration = TimeDelta::from_std(self.config.duration).ok()?;
+        let minute = now.duration_trunc(TimeDelta::minutes(1)).ok()?;
+        (0..=duration.num_minutes())
+            .map(|back| minute - TimeDelta::minutes(back))
+            .find(|start| self.schedule.matches(*start) && *start + duration > now)
+            .map(|start| start + duration)
+    }
+
+    /// Whether `alert` is one the window covers. A validator matches an
+    /// alert subject equal to it or holding it as a `/` separated part, as
+    /// in `rpc-latency-p99/validator-a`.
+    pub fn covers(&self, alert: &Alert) -> bool {
+        (self.config.alerts.is_empty() || self.config.alerts.iter().any(|a| *a == alert.alert))
+            && (self.config.validators.is_empty()
+                || self
+                    .config
+                    .validators
+                    .iter()
+                    .any(|validator| alert.subject.split('/').any(|part| part == validator)))
+    }
+
+    pub fn status(&self, now: DateTime<Utc>) -> MaintenanceStatus {
+        MaintenanceStatus {
+            name: self.config.name.clone(),
+            schedule: self.config.schedule.clone(),
+            duration_secs: self.config.duration.as_secs(),
+            alerts: self.config.alerts.clone(),
+            validators: self.config.validators.clone(),
+            comment: self.config.comment.clone(),
+            active_until: self.active_until(now),
+        }
+    }
+}
+
+/// The window `alert` falls in at `now`, if any.
+pub fn covering<'a>(
+    windows: &'a [MaintenanceWindow],
+    alert: &Alert,
+    now: DateTime<Utc>,
+) -> Option<&'a MaintenanceWindow> {
+    windows
+        .iter()
+        .find(|window| window.covers(alert) && window.active_until(now).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        // 2025-06-01 is a Sunday
+        Utc.with_ymd_and_hms(2025, 6, day, hour, minute, 0).unwrap()
+    }
+
+    fn window(schedule: &str, minutes: u64, validators: &[&str]) -> MaintenanceWindow {
+        MaintenanceWindow::new(MaintenanceWindowConfig {
+            name: "upgrade".into(),
+            schedule: schedule.into(),
+            duration: Duration::from_secs(minutes * 60),
+            alerts: Vec::new(),
+            validators: validators.iter().map(|v| v.to_string()).collect(),
+            comment: None,
+        })
+        .expect("valid window")
+    }
+
+    #[test]
+    fn parses_cron_fields() {
+        let schedule: Schedule = "*/15 2-4 * * 0,6".parse().unwrap();
+        assert!(schedule.matches(at(1, 2, 45)));
+        assert!(!schedule.matches(at(1, 2, 50)));
+        assert!(!schedule.matches(at(1, 5, 0)));
+        // Monday
+        assert!(!schedule.matches(at(2, 3, 0)));
+
+        let sunday: Schedule = "0 0 * * 7".parse().unwrap();
+        assert!(sunday.matches(at(1, 0, 0)));
+        // Day of month or day of week once both are restricted
+        let either: Schedule = "0 0 15 * 1".parse().unwrap();
+        assert!(either.matches(at(2, 0, 0)));
+        assert!(either.matches(at(15, 0, 0)));
+        assert!(!either.matches(at(3, 0, 0)));
+
+        for bad in ["* * * *", "60 * * * *", "5-1 * * * *", "*/0 * * * *"] {
+            assert!(bad.parse::<Schedule>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn windows_cover_their_duration_and_subjects() {
+        let upgrade = window("30 3 * * 2", 90, &["validator-a"]);
+        // Tuesday 2025-06-03
+        assert_eq!(upgrade.active_until(at(3, 3, 29)), None);
+        assert_eq!(upgrade.active_until(at(3, 3, 30)), Some(at(3, 5, 0)));
+        assert_eq!(upgrade.active_until(at(3, 4, 59)), Some(at(3, 5, 0)));
+        assert_eq!(upgrade.active_until(at(3, 5, 0)), None);
+
+        let alert = |subject: &str| Alert {
+            alert: "slot_lag".into(),
+            subject: subject.into(),
+            value: 80.0,
+            threshold: 50.0,
+        };
+        assert!(upgrade.covers(&alert("validator-a")));
+        assert!(upgrade.covers(&alert("rpc-latency-p99/validator-a")));
+        assert!(!upgrade.covers(&alert("validator-b")));
+        let windows = [upgrade];
+        assert!(covering(&windows, &alert("validator-a"), at(3, 4, 0)).is_some());
+        assert!(covering(&windows, &alert("validator-a"), at(4, 4, 0)).is_none());
+    }
+}
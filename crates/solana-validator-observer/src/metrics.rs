@@ -1,12 +1,20 @@
 // Numan Thabit 2025
-use anyhow::Result;
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
 use once_cell::sync::Lazy;
 use prometheus::{
-    opts, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Registry, TextEncoder,
+    opts,
+    proto::{Metric, MetricType},
+    Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, Registry, TextEncoder,
 };
 
+use crate::state::{CatchupState, HostSnapshot};
+
 static METRICS_ENCODER: Lazy<TextEncoder> = Lazy::new(TextEncoder::new);
 
+const NAMESPACE: &str = "solana_validator_observer_";
+
 #[derive(Clone)]
 pub struct ObserverMetrics {
     registry: Registry,
@@ -16,7 +24,46 @@ pub struct ObserverMetrics {
     rpc_latency: HistogramVec,
     packet_loss: GaugeVec,
     slot_lag: GaugeVec,
+    scrapes: IntCounterVec,
     scrape_errors: IntCounterVec,
+    pipeline_up: GaugeVec,
+    pipeline_series: GaugeVec,
+    pipeline_drop_ratio: GaugeVec,
+    pipeline_queue_saturation: GaugeVec,
+    pipeline_record_age: GaugeVec,
+    pipeline_encode_latency: GaugeVec,
+    pipeline_alert_firing: GaugeVec,
+    alerts_sent: IntCounterVec,
+    alerts_suppressed: IntCounterVec,
+    alert_send_errors: IntCounterVec,
+    vote_distance: GaugeVec,
+    vote_delinquent: GaugeVec,
+    vote_epoch_credits: GaugeVec,
+    vote_credits_earned: IntCounterVec,
+    leader_slots: GaugeVec,
+    skip_rate: GaugeVec,
+    log_matches: IntCounterVec,
+    remediation_actions: IntCounterVec,
+    catchup_slot_gap: GaugeVec,
+    catchup_state: GaugeVec,
+    reference_slot: GaugeVec,
+    cluster_slot_rate: Gauge,
+    host_disk_utilization: GaugeVec,
+    host_disk_latency: GaugeVec,
+    host_nic_drops: GaugeVec,
+    host_nic_errors: GaugeVec,
+    host_cpu_steal: Gauge,
+    host_temperature: GaugeVec,
+    host_nvme_wear: GaugeVec,
+    host_alert_firing: GaugeVec,
+    slo_burn_rate: GaugeVec,
+    slo_alert_firing: GaugeVec,
+    anomaly_score: GaugeVec,
+    anomaly_firing: GaugeVec,
+    fleet_value: GaugeVec,
+    fleet_median: GaugeVec,
+    fleet_peer_ratio: GaugeVec,
+    fleet_outlier_firing: GaugeVec,
 }
 
 impl ObserverMetrics {
@@ -84,6 +131,15 @@ impl ObserverMetrics {
         )
         .expect("failed to build slot lag gauge");
 
+        let scrapes = IntCounterVec::new(
+            opts!(
+                "scrapes_total",
+                "Count of scrape attempts per validator and protocol"
+            ),
+            &["validator", "protocol"],
+        )
+        .expect("failed to build scrape counter");
+
         let scrape_errors = IntCounterVec::new(
             opts!(
                 "scrape_errors_total",
@@ -93,6 +149,336 @@ impl ObserverMetrics {
         )
         .expect("failed to build scrape error counter");
 
+        let pipeline_up = GaugeVec::new(
+            opts!(
+                "pipeline_up",
+                "Whether the last scrape of a pipeline component succeeded"
+            ),
+            &["target"],
+        )
+        .expect("failed to build pipeline up gauge");
+
+        let pipeline_series = GaugeVec::new(
+            opts!(
+                "pipeline_series",
+                "Series scraped from a pipeline component per metric family"
+            ),
+            &["target", "family"],
+        )
+        .expect("failed to build pipeline series gauge");
+
+        let pipeline_drop_ratio = GaugeVec::new(
+            opts!(
+                "pipeline_drop_ratio",
+                "Share of records dropped by a pipeline component between scrapes"
+            ),
+            &["target"],
+        )
+        .expect("failed to build pipeline drop ratio gauge");
+
+        let pipeline_queue_saturation = GaugeVec::new(
+            opts!(
+                "pipeline_queue_saturation",
+                "Fill ratio of the fullest queue of a pipeline component"
+            ),
+            &["target"],
+        )
+        .expect("failed to build pipeline queue saturation gauge");
+
+        let pipeline_record_age = GaugeVec::new(
+            opts!(
+                "pipeline_seconds_since_last_record",
+                "Seconds since a pipeline component last moved a record"
+            ),
+            &["target"],
+        )
+        .expect("failed to build pipeline record age gauge");
+
+        let pipeline_encode_latency = GaugeVec::new(
+            opts!(
+                "pipeline_encode_latency_seconds",
+                "Mean time a pipeline component spent encoding a record between scrapes"
+            ),
+            &["target"],
+        )
+        .expect("failed to build pipeline encode latency gauge");
+
+        let pipeline_alert_firing = GaugeVec::new(
+            opts!(
+                "pipeline_alert_firing",
+                "Built-in pipeline alerts currently firing per component"
+            ),
+            &["target", "alert"],
+        )
+        .expect("failed to build pipeline alert gauge");
+
+        let alerts_sent = IntCounterVec::new(
+            opts!(
+                "alerts_sent_total",
+                "Alerts delivered per receiver and severity"
+            ),
+            &["receiver", "severity"],
+        )
+        .expect("failed to build alerts sent counter");
+
+        let alerts_suppressed = IntCounterVec::new(
+            opts!(
+                "alerts_suppressed_total",
+                "Alerts held back by a silence or the dedupe window"
+            ),
+            &["alert", "reason"],
+        )
+        .expect("failed to build alerts suppressed counter");
+
+        let alert_send_errors = IntCounterVec::new(
+            opts!(
+                "alert_send_errors_total",
+                "Failed alert deliveries per receiver"
+            ),
+            &["receiver"],
+        )
+        .expect("failed to build alert send error counter");
+
+        let vote_distance = GaugeVec::new(
+            opts!(
+                "vote_distance_slots",
+                "Slots between a validator's last vote and the newest vote in the cluster"
+            ),
+            &["validator"],
+        )
+        .expect("failed to build vote distance gauge");
+
+        let vote_delinquent = GaugeVec::new(
+            opts!(
+                "vote_delinquent",
+                "Whether a validator's vote account is delinquent"
+            ),
+            &["validator"],
+        )
+        .expect("failed to build vote delinquent gauge");
+
+        let vote_epoch_credits = GaugeVec::new(
+            opts!(
+                "vote_epoch_credits",
+                "Vote credits a validator has earned, as reported by getVoteAccounts"
+            ),
+            &["validator"],
+        )
+        .expect("failed to build vote credits gauge");
+
+        let vote_credits_earned = IntCounterVec::new(
+            opts!(
+                "vote_credits_earned_total",
+                "Vote credits earned since the observer started"
+            ),
+            &["validator"],
+        )
+        .expect("failed to build vote credits counter");
+
+        let leader_slots = GaugeVec::new(
+            opts!(
+                "leader_slots",
+                "Leader slots this epoch: scheduled, elapsed, produced and skipped"
+            ),
+            &["validator", "state"],
+        )
+        .expect("failed to build leader slots gauge");
+
+        let skip_rate = GaugeVec::new(
+            opts!(
+                "skip_rate",
+                "Share of elapsed leader slots without a produced block"
+            ),
+            &["validator", "window"],
+        )
+        .expect("failed to build skip rate gauge");
+
+        let log_matches = IntCounterVec::new(
+            opts!(
+                "log_matches_total",
+                "Log lines matching a log watch rule, rate limited or not"
+            ),
+            &["source", "rule"],
+        )
+        .expect("failed to build log matches counter");
+
+        let remediation_actions = IntCounterVec::new(
+            opts!(
+                "remediation_actions_total",
+                "Remediation action triggers by outcome (succeeded, failed, dry_run, cooldown)"
+            ),
+            &["action", "outcome"],
+        )
+        .expect("failed to build remediation actions counter");
+
+        let catchup_slot_gap = GaugeVec::new(
+            opts!(
+                "catchup_slot_gap",
+                "Slots a validator is behind the highest reference RPC node"
+            ),
+            &["validator"],
+        )
+        .expect("failed to build catchup slot gap gauge");
+
+        let catchup_state = GaugeVec::new(
+            opts!(
+                "catchup_state",
+                "Whether a validator is in_sync, behind, or keeping up with a slow cluster"
+            ),
+            &["validator", "state"],
+        )
+        .expect("failed to build catchup state gauge");
+
+        let reference_slot = GaugeVec::new(
+            opts!(
+                "reference_slot",
+                "Latest slot reported by a reference RPC node"
+            ),
+            &["reference"],
+        )
+        .expect("failed to build reference slot gauge");
+
+        let cluster_slot_rate = Gauge::with_opts(opts!(
+            "cluster_slot_rate",
+            "Slots per second the reference RPC nodes advanced over the sustain window"
+        ))
+        .expect("failed to build cluster slot rate gauge");
+
+        let host_disk_utilization = GaugeVec::new(
+            opts!(
+                "host_disk_utilization",
+                "Share of the last interval a block device was busy"
+            ),
+            &["device"],
+        )
+        .expect("failed to build host disk utilization gauge");
+
+        let host_disk_latency = GaugeVec::new(
+            opts!(
+                "host_disk_latency_seconds",
+                "Mean time per I/O a block device completed in the last interval"
+            ),
+            &["device"],
+        )
+        .expect("failed to build host disk latency gauge");
+
+        let host_nic_drops = GaugeVec::new(
+            opts!(
+                "host_nic_drops_per_second",
+                "Packets a network interface dropped per second, both directions"
+            ),
+            &["interface"],
+        )
+        .expect("failed to build host nic drops gauge");
+
+        let host_nic_errors = GaugeVec::new(
+            opts!(
+                "host_nic_errors_per_second",
+                "Packet errors on a network interface per second, both directions"
+            ),
+            &["interface"],
+        )
+        .expect("failed to build host nic errors gauge");
+
+        let host_cpu_steal = Gauge::with_opts(opts!(
+            "host_cpu_steal_ratio",
+            "Share of CPU time stolen by the hypervisor in the last interval"
+        ))
+        .expect("failed to build host cpu steal gauge");
+
+        let host_temperature = GaugeVec::new(
+            opts!("host_temperature_celsius", "Hardware sensor temperature"),
+            &["sensor"],
+        )
+        .expect("failed to build host temperature gauge");
+
+        let host_nvme_wear = GaugeVec::new(
+            opts!(
+                "host_nvme_wear_ratio",
+                "Share of rated endurance an NVMe controller reports used"
+            ),
+            &["device"],
+        )
+        .expect("failed to build host nvme wear gauge");
+
+        let host_alert_firing = GaugeVec::new(
+            opts!("host_alert_firing", "Whether a host alert is firing"),
+            &["alert", "subject"],
+        )
+        .expect("failed to build host alert gauge");
+
+        let slo_burn_rate = GaugeVec::new(
+            opts!(
+                "slo_burn_rate",
+                "Error budget burn rate of an objective over a burn window"
+            ),
+            &["slo", "subject", "window"],
+        )
+        .expect("failed to build slo burn rate gauge");
+
+        let slo_alert_firing = GaugeVec::new(
+            opts!(
+                "slo_alert_firing",
+                "Whether both windows of a burn-rate alert burn too fast"
+            ),
+            &["slo", "subject", "window"],
+        )
+        .expect("failed to build slo alert gauge");
+
+        let anomaly_score = GaugeVec::new(
+            opts!(
+                "anomaly_score",
+                "Standard deviations a series sits from its learned baseline"
+            ),
+            &["metric", "subject"],
+        )
+        .expect("failed to build anomaly score gauge");
+
+        let anomaly_firing = GaugeVec::new(
+            opts!(
+                "anomaly_firing",
+                "Whether a series deviates further from its baseline than its sensitivity allows"
+            ),
+            &["metric", "subject"],
+        )
+        .expect("failed to build anomaly firing gauge");
+
+        let fleet_value = GaugeVec::new(
+            opts!(
+                "fleet_value",
+                "A validator's value of a fleet comparison over the last evaluation"
+            ),
+            &["comparison", "validator"],
+        )
+        .expect("failed to build fleet value gauge");
+
+        let fleet_median = GaugeVec::new(
+            opts!(
+                "fleet_median",
+                "Median of a fleet comparison across the monitored validators"
+            ),
+            &["comparison"],
+        )
+        .expect("failed to build fleet median gauge");
+
+        let fleet_peer_ratio = GaugeVec::new(
+            opts!(
+                "fleet_peer_ratio",
+                "A validator's value over the median of its peers"
+            ),
+            &["comparison", "validator"],
+        )
+        .expect("failed to build fleet peer ratio gauge");
+
+        let fleet_outlier_firing = GaugeVec::new(
+            opts!(
+                "fleet_outlier_firing",
+                "Whether a validator is further from its peers than a comparison allows"
+            ),
+            &["comparison", "validator"],
+        )
+        .expect("failed to build fleet outlier gauge");
+
         registry
             .register(Box::new(slot_propagation.clone()))
             .expect("register slot_propagation");
@@ -111,9 +497,126 @@ impl ObserverMetrics {
         registry
             .register(Box::new(slot_lag.clone()))
             .expect("register slot_lag");
+        registry
+            .register(Box::new(scrapes.clone()))
+            .expect("register scrapes");
         registry
             .register(Box::new(scrape_errors.clone()))
             .expect("register scrape_errors");
+        registry
+            .register(Box::new(pipeline_up.clone()))
+            .expect("register pipeline_up");
+        registry
+            .register(Box::new(pipeline_series.clone()))
+            .expect("register pipeline_series");
+        registry
+            .register(Box::new(pipeline_drop_ratio.clone()))
+            .expect("register pipeline_drop_ratio");
+        registry
+            .register(Box::new(pipeline_queue_saturation.clone()))
+            .expect("register pipeline_queue_saturation");
+        registry
+            .register(Box::new(pipeline_record_age.clone()))
+            .expect("register pipeline_record_age");
+        registry
+            .register(Box::new(pipeline_encode_latency.clone()))
+            .expect("register pipeline_encode_latency");
+        registry
+            .register(Box::new(pipeline_alert_firing.clone()))
+            .expect("register pipeline_alert_firing");
+        registry
+            .register(Box::new(alerts_sent.clone()))
+            .expect("register alerts_sent");
+        registry
+            .register(Box::new(alerts_suppressed.clone()))
+            .expect("register alerts_suppressed");
+        registry
+            .register(Box::new(alert_send_errors.clone()))
+            .expect("register alert_send_errors");
+        registry
+            .register(Box::new(vote_distance.clone()))
+            .expect("register vote_distance");
+        registry
+            .register(Box::new(vote_delinquent.clone()))
+            .expect("register vote_delinquent");
+        registry
+            .register(Box::new(vote_epoch_credits.clone()))
+            .expect("register vote_epoch_credits");
+        registry
+            .register(Box::new(vote_credits_earned.clone()))
+            .expect("register vote_credits_earned");
+        registry
+            .register(Box::new(leader_slots.clone()))
+            .expect("register leader_slots");
+        registry
+            .register(Box::new(skip_rate.clone()))
+            .expect("register skip_rate");
+        registry
+            .register(Box::new(log_matches.clone()))
+            .expect("register log_matches");
+        registry
+            .register(Box::new(remediation_actions.clone()))
+            .expect("register remediation_actions");
+        registry
+            .register(Box::new(catchup_slot_gap.clone()))
+            .expect("register catchup_slot_gap");
+        registry
+            .register(Box::new(catchup_state.clone()))
+            .expect("register catchup_state");
+        registry
+            .register(Box::new(reference_slot.clone()))
+            .expect("register reference_slot");
+        registry
+            .register(Box::new(cluster_slot_rate.clone()))
+            .expect("register cluster_slot_rate");
+        registry
+            .register(Box::new(host_disk_utilization.clone()))
+            .expect("register host_disk_utilization");
+        registry
+            .register(Box::new(host_disk_latency.clone()))
+            .expect("register host_disk_latency");
+        registry
+            .register(Box::new(host_nic_drops.clone()))
+            .expect("register host_nic_drops");
+        registry
+            .register(Box::new(host_nic_errors.clone()))
+            .expect("register host_nic_errors");
+        registry
+            .register(Box::new(host_cpu_steal.clone()))
+            .expect("register host_cpu_steal");
+        registry
+            .register(Box::new(host_temperature.clone()))
+            .expect("register host_temperature");
+        registry
+            .register(Box::new(host_nvme_wear.clone()))
+            .expect("register host_nvme_wear");
+        registry
+            .register(Box::new(host_alert_firing.clone()))
+            .expect("register host_alert_firing");
+        registry
+            .register(Box::new(slo_burn_rate.clone()))
+            .expect("register slo_burn_rate");
+        registry
+            .register(Box::new(slo_alert_firing.clone()))
+            .expect("register slo_alert_firing");
+        registry
+            .register(Box::new(anomaly_score.clone()))
+            .expect("register anomaly_score");
+        registry
+            .register(Box::new(anomaly_firing.clone()))
+            .expect("register anomaly_firing");
+        registry
+            .register(Box::new(fleet_value.clone()))
+            .expect("register fleet_value");
+        registry
+            .register(Box::new(fleet_median.clone()))
+            .expect("register fleet_median");
+        registry
+            .register(Box::new(fleet_peer_ratio.clone()))
+            .expect("register fleet_peer_ratio");
+        registry
+            .register(Box::new(fleet_outlier_firing.clone()))
+            .expect("register fleet_outlier_firing");
 
         Self {
             registry,
@@ -123,7 +626,46 @@ impl ObserverMetrics {
             rpc_latency,
             packet_loss,
             slot_lag,
+            scrapes,
             scrape_errors,
+            pipeline_up,
+            pipeline_series,
+            pipeline_drop_ratio,
+            pipeline_queue_saturation,
+            pipeline_record_age,
+            pipeline_encode_latency,
+            pipeline_alert_firing,
+            alerts_sent,
+            alerts_suppressed,
+            alert_send_errors,
+            vote_distance,
+            vote_delinquent,
+            vote_epoch_credits,
+            vote_credits_earned,
+            leader_slots,
+            skip_rate,
+            log_matches,
+            remediation_actions,
+            catchup_slot_gap,
+            catchup_state,
+            reference_slot,
+            cluster_slot_rate,
+            host_disk_utilization,
+            host_disk_latency,
+            host_nic_drops,
+            host_nic_errors,
+            host_cpu_steal,
+            host_temperature,
+            host_nvme_wear,
+            host_alert_firing,
+            slo_burn_rate,
+            slo_alert_firing,
+            anomaly_score,
+            anomaly_firing,
+            fleet_value,
+            fleet_median,
+            fleet_peer_ratio,
+            fleet_outlier_firing,
         }
     }
 
@@ -161,12 +703,360 @@ impl ObserverMetrics {
             .set(loss_ratio);
     }
 
+    pub fn inc_scrape(&self, validator: &str, protocol: &str) {
+        self.scrapes.with_label_values(&[validator, protocol]).inc();
+    }
+
     pub fn inc_scrape_error(&self, validator: &str, protocol: &str) {
         self.scrape_errors
             .with_label_values(&[validator, protocol])
             .inc();
     }
 
+    pub fn set_pipeline_up(&self, target: &str, up: bool) {
+        self.pipeline_up
+            .with_label_values(&[target])
+            .set(if up { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_pipeline_series(&self, target: &str, family: &str, series: usize) {
+        self.pipeline_series
+            .with_label_values(&[target, family])
+            .set(series as f64);
+    }
+
+    pub fn set_pipeline_drop_ratio(&self, target: &str, ratio: f64) {
+        self.pipeline_drop_ratio
+            .with_label_values(&[target])
+            .set(ratio);
+    }
+
+    pub fn set_pipeline_queue_saturation(&self, target: &str, saturation: f64) {
+        self.pipeline_queue_saturation
+            .with_label_values(&[target])
+            .set(saturation);
+    }
+
+    pub fn set_pipeline_record_age(&self, target: &str, seconds: f64) {
+        self.pipeline_record_age
+            .with_label_values(&[target])
+            .set(seconds);
+    }
+
+    pub fn set_pipeline_encode_latency(&self, target: &str, seconds: f64) {
+        self.pipeline_encode_latency
+            .with_label_values(&[target])
+            .set(seconds);
+    }
+
+    pub fn set_pipeline_alert(&self, target: &str, alert: &str, firing: bool) {
+        self.pipeline_alert_firing
+            .with_label_values(&[target, alert])
+            .set(if firing { 1.0 } else { 0.0 });
+    }
+
+    pub fn inc_alert_sent(&self, receiver: &str, severity: &str) {
+        self.alerts_sent
+            .with_label_values(&[receiver, severity])
+            .inc();
+    }
+
+    pub fn inc_alert_suppressed(&self, alert: &str, reason: &str) {
+        self.alerts_suppressed
+            .with_label_values(&[alert, reason])
+            .inc();
+    }
+
+    pub fn inc_alert_send_error(&self, receiver: &str) {
+        self.alert_send_errors.with_label_values(&[receiver]).inc();
+    }
+
+    pub fn set_vote_status(&self, validator: &str, distance: u64, delinquent: bool, credits: u64) {
+        self.vote_distance
+            .with_label_values(&[validator])
+            .set(distance as f64);
+        self.vote_delinquent
+            .with_label_values(&[validator])
+            .set(if delinquent { 1.0 } else { 0.0 });
+        self.vote_epoch_credits
+            .with_label_values(&[validator])
+            .set(credits as f64);
+    }
+
+    pub fn inc_vote_credits(&self, validator: &str, earned: u64) {
+        self.vote_credits_earned
+            .with_label_values(&[validator])
+            .inc_by(earned);
+    }
+
+    pub fn set_leader_slots(&self, validator: &str, scheduled: u64, elapsed: u64, produced: u64) {
+        for (state, slots) in [
+            ("scheduled", scheduled),
+            ("elapsed", elapsed),
+            ("produced", produced),
+            ("skipped", elapsed.saturating_sub(produced)),
+        ] {
+            self.leader_slots
+                .with_label_values(&[validator, state])
+                .set(slots as f64);
+        }
+    }
+
+    pub fn set_skip_rate(&self, validator: &str, window: &str, rate: f64) {
+        self.skip_rate
+            .with_label_values(&[validator, window])
+            .set(rate);
+    }
+
+    pub fn inc_log_match(&self, source: &str, rule: &str) {
+        self.log_matches.with_label_values(&[source, rule]).inc();
+    }
+
+    pub fn inc_remediation(&self, action: &str, outcome: &str) {
+        self.remediation_actions
+            .with_label_values(&[action, outcome])
+            .inc();
+    }
+
+    pub fn set_catchup(&self, validator: &str, slot_gap: u64, state: CatchupState) {
+        self.catchup_slot_gap
+            .with_label_values(&[validator])
+            .set(slot_gap as f64);
+        for candidate in [
+            CatchupState::InSync,
+            CatchupState::Behind,
+            CatchupState::ClusterSlow,
+        ] {
+            self.catchup_state
+                .with_label_values(&[validator, candidate.as_str()])
+                .set(if candidate == state { 1.0 } else { 0.0 });
+        }
+    }
+
+    pub fn set_reference_slot(&self, reference: &str, slot: u64) {
+        self.reference_slot
+            .with_label_values(&[reference])
+            .set(slot as f64);
+    }
+
+    pub fn set_cluster_slot_rate(&self, rate: f64) {
+        self.cluster_slot_rate.set(rate);
+    }
+
+    pub fn set_host(&self, host: &HostSnapshot) {
+        for (device, disk) in &host.disks {
+            self.host_disk_utilization
+                .with_label_values(&[device])
+                .set(disk.utilization);
+            if let Some(latency_ms) = disk.latency_ms {
+                self.host_disk_latency
+                    .with_label_values(&[device])
+                    .set(latency_ms / 1_000.0);
+            }
+        }
+        for (interface, nic) in &host.nics {
+            self.host_nic_drops
+                .with_label_values(&[interface])
+                .set(nic.drops_per_sec);
+            self.host_nic_errors
+                .with_label_values(&[interface])
+                .set(nic.errors_per_sec);
+        }
+        if let Some(steal) = host.cpu_steal_ratio {
+            self.host_cpu_steal.set(steal);
+        }
+        for (sensor, celsius) in &host.temperatures_celsius {
+            self.host_temperature
+                .with_label_values(&[sensor])
+                .set(*celsius);
+        }
+        for (device, wear) in &host.nvme_wear {
+            self.host_nvme_wear.with_label_values(&[device]).set(*wear);
+        }
+    }
+
+    pub fn set_host_alert(&self, alert: &str, subject: &str, firing: bool) {
+        self.host_alert_firing
+            .with_label_values(&[alert, subject])
+            .set(if firing { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_slo_burn_rate(&self, slo: &str, subject: &str, window: &str, rate: f64) {
+        self.slo_burn_rate
+            .with_label_values(&[slo, subject, window])
+            .set(rate);
+    }
+
+    pub fn set_slo_alert(&self, slo: &str, subject: &str, window: &str, firing: bool) {
+        self.slo_alert_firing
+            .with_label_values(&[slo, subject, window])
+            .set(if firing { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_anomaly(&self, metric: &str, subject: &str, score: f64, firing: bool) {
+        self.anomaly_score
+            .with_label_values(&[metric, subject])
+            .set(score);
+        self.anomaly_firing
+            .with_label_values(&[metric, subject])
+            .set(if firing { 1.0 } else { 0.0 });
+    }
+
+    pub fn set_fleet_median(&self, comparison: &str, median: f64) {
+        self.fleet_median
+            .with_label_values(&[comparison])
+            .set(median);
+    }
+
+    pub fn set_fleet_member(
+        &self,
+        comparison: &str,
+        validator: &str,
+        value: f64,
+        ratio: Option<f64>,
+        firing: bool,
+    ) {
+        self.fleet_value
+            .with_label_values(&[comparison, validator])
+            .set(value);
+        if let Some(ratio) = ratio {
+            self.fleet_peer_ratio
+                .with_label_values(&[comparison, validator])
+                .set(ratio);
+        }
+        self.fleet_outlier_firing
+            .with_label_values(&[comparison, validator])
+            .set(if firing { 1.0 } else { 0.0 });
+    }
+
+    /// Gauge `name` (without the namespace) per series, keyed by its label
+    /// values joined with `/`.
+    pub fn gauge_values(&self, name: &str) -> BTreeMap<String, f64> {
+        self.metrics_of(name, MetricType::GAUGE)
+            .iter()
+            .map(|metric| {
+                let subject = metric
+                    .get_label()
+                    .iter()
+                    .map(|pair| pair.get_value())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                (subject, metric.get_gauge().get_value())
+            })
+            .collect()
+    }
+
+    /// Counter `name` (without the namespace) summed per value of `label`.
+    pub fn counter_totals(&self, name: &str, label: &str) -> BTreeMap<String, f64> {
+        let mut totals = BTreeMap::new();
+        for metric in self.metrics_of(name, MetricType::COUNTER) {
+            *totals.entry(label_value(&metric, label)).or_default() +=
+                metric.get_counter().get_value();
+        }
+        totals
+    }
+
+    /// Observations of histogram `name` at or under the bucket bound `le`,
+    /// and in all, per value of `label`.
+    pub fn histogram_totals(
+        &self,
+        name: &str,
+        label: &str,
+        le: f64,
+    ) -> Result<BTreeMap<String, (f64, f64)>> {
+        let mut totals: BTreeMap<String, (f64, f64)> = BTreeMap::new();
+        for metric in self.metrics_of(name, MetricType::HISTOGRAM) {
+            let histogram = metric.get_histogram();
+            let bucket = histogram
+                .get_bucket()
+                .iter()
+                .find(|bucket| (bucket.get_upper_bound() - le).abs() < 1e-9)
+                .with_context(|| format!("{name} has no bucket at {le}"))?;
+            let entry = totals.entry(label_value(&metric, label)).or_default();
+            entry.0 += bucket.get_cumulative_count() as f64;
+            entry.1 += histogram.get_sample_count() as f64;
+        }
+        Ok(totals)
+    }
+
+    /// Cumulative bucket counts of histogram `name` per value of `label`, as
+    /// `(upper bound, count)` pairs ending in `+Inf` with the total.
+    pub fn histogram_buckets(&self, name: &str, label: &str) -> BTreeMap<String, Vec<(f64, f64)>> {
+        let mut totals: BTreeMap<String, Vec<(f64, f64)>> = BTreeMap::new();
+        for metric in self.metrics_of(name, MetricType::HISTOGRAM) {
+            let histogram = metric.get_histogram();
+            let buckets = histogram
+                .get_bucket()
+                .iter()
+                .map(|bucket| {
+                    (
+                        bucket.get_upper_bound(),
+                        bucket.get_cumulative_count() as f64,
+                    )
+                })
+                .chain([(f64::INFINITY, histogram.get_sample_count() as f64)]);
+            let entry = totals.entry(label_value(&metric, label)).or_default();
+            if entry.is_empty() {
+                entry.extend(buckets);
+            } else {
+                for (total, (_, count)) in entry.iter_mut().zip(buckets) {
+                    total.1 += count;
+                }
+            }
+        }
+        totals
+    }
+
+    fn metrics_of(&self, name: &str, kind: MetricType) -> Vec<Metric> {
+        self.registry
+            .gather()
+            .into_iter()
+            .find(|family| {
+                family.get_field_type() == kind
+                    && family.get_name().strip_prefix(NAMESPACE) == Some(name)
+            })
+            .map(|family| family.get_metric().to_vec())
+            .unwrap_or_default()
+    }
+
+    /// Current value of every series, keyed as in the text exposition;
+    /// histograms contribute their `_sum` and `_count`.
+    pub fn samples(&self) -> BTreeMap<String, f64> {
+        let mut samples = BTreeMap::new();
+        for family in self.registry.gather() {
+            for metric in family.get_metric() {
+                let labels = metric
+                    .get_label()
+                    .iter()
+                    .map(|pair| format!("{}=\"{}\"", pair.get_name(), pair.get_value()))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                let key = |suffix: &str| {
+                    if labels.is_empty() {
+                        format!("{}{suffix}", family.get_name())
+                    } else {
+                        format!("{}{suffix}{{{labels}}}", family.get_name())
+                    }
+                };
+                match family.get_field_type() {
+                    MetricType::GAUGE => {
+                        samples.insert(key(""), metric.get_gauge().get_value());
+                    }
+                    MetricType::COUNTER => {
+                        samples.insert(key(""), metric.get_counter().get_value());
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        samples.insert(key("_sum"), histogram.get_sample_sum());
+                        samples.insert(key("_count"), histogram.get_sample_count() as f64);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        samples
+    }
+
     pub fn gather(&self) -> Result<String> {
         let metric_families = self.registry.gather();
         let mut buffer = Vec::with_capacity(8192);
@@ -176,3 +1066,12 @@ impl ObserverMetrics {
         Ok(String::from_utf8(buffer).expect("prometheus output is utf8"))
     }
 }
+
+fn label_value(metric: &Metric, label: &str) -> String {
+    metric
+        .get_label()
+        .iter()
+        .find(|pair| pair.get_name() == label)
+        .map(|pair| pair.get_value().to_string())
+        .unwrap_or_default()
+}
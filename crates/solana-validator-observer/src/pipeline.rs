@@ -0,0 +1,563 @@
+// Numan Thabit 2025
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use reqwest::Client;
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{PipelineAlertConfig, PipelineTargetConfig},
+    metrics::ObserverMetrics,
+    state::{ComponentKind, ObserverState, PipelineSnapshot},
+};
+
+/// Metric families the Numistack components export, by name prefix.
+const FAMILIES: [(&str, &str); 3] = [
+    ("ultra_", "ultra"),
+    ("ys_consumer_", "ys_consumer"),
+    ("rpc_bridge_", "rpc_bridge"),
+];
+
+/// Counters of records lost on the way through a component.
+const DROP_COUNTERS: &[&str] = &[
+    "ultra_dropped_total",
+    "ultra_decode_pool_dropped_total",
+    "ultra_dlq_dropped_total",
+    "ys_consumer_dropped_total",
+    "ys_consumer_shm_dropped_total",
+    "ys_consumer_shm_backpressure_drops_total",
+    "ys_consumer_drain_dropped_total",
+];
+
+/// Counters of records a component passed on.
+const RECORD_COUNTERS: &[&str] = &[
+    "ultra_enqueued_total",
+    "ultra_records_total",
+    "ys_consumer_shm_written_total",
+    "rpc_bridge_producer_records_total",
+];
+
+/// Gauges in which a component reports its own record age.
+const AGE_GAUGES: &[&str] = &[
+    "ultra_source_last_record_age_seconds",
+    "rpc_bridge_seconds_since_last_delta",
+];
+
+/// Sum and count of the histogram of nanoseconds spent encoding a record.
+const ENCODE_NS_SUM: &str = "ultra_encode_ns_sum";
+const ENCODE_NS_COUNT: &str = "ultra_encode_ns_count";
+
+const ALERT_DOWN: &str = "down";
+const ALERT_DROP_RATIO: &str = "drop_ratio";
+const ALERT_QUEUE_SATURATION: &str = "queue_saturation";
+const ALERT_STALE: &str = "stale";
+const ALERTS: [&str; 4] = [
+    ALERT_DOWN,
+    ALERT_DROP_RATIO,
+    ALERT_QUEUE_SATURATION,
+    ALERT_STALE,
+];
+
+pub fn spawn_scrapers(
+    targets: Vec<PipelineTargetConfig>,
+    thresholds: PipelineAlertConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    scrape_interval: Duration,
+    alerting: Option<AlertingService>,
+) -> Vec<JoinHandle<()>> {
+    targets
+        .into_iter()
+        .map(|target| {
+            let thresholds = thresholds.clone();
+            let state = state.clone();
+            let metrics = metrics.clone();
+            let alerting = alerting.clone();
+            tokio::spawn(async move {
+                if let Err(err) = run_target(
+                    target,
+                    thresholds,
+                    state,
+                    metrics,
+                    scrape_interval,
+                    alerting,
+                )
+                .await
+                {
+                    tracing::error!(%err, "pipeline scrape loop terminated");
+                }
+            })
+        })
+        .collect()
+}
+
+async fn run_target(
+    target: PipelineTargetConfig,
+    thresholds: PipelineAlertConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    scrape_interval: Duration,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(2))
+        .pool_max_idle_per_host(1)
+        .build()
+        .context("failed to construct metrics client")?;
+
+    let mut ticker = interval_at(Instant::now(), scrape_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut tracker = Tracker::default();
+    let mut firing: Vec<&'static str> = Vec::new();
+
+    loop {
+        ticker.tick().await;
+
+        let scraped = scrape(&client, &target).await;
+        let now = Instant::now();
+        let mut snapshot = PipelineSnapshot {
+            name: target.name.clone(),
+            up: scraped.is_ok(),
+            series: BTreeMap::new(),
+            component: None,
+            shards: Vec::new(),
+            drop_ratio: None,
+            queue_saturation: None,
+            encode_latency_seconds: None,
+            seconds_since_last_record: None,
+            firing: Vec::new(),
+            last_updated: Some(Utc::now()),
+        };
+        let mut alerts = Vec::new();
+        metrics.set_pipeline_up(&target.name, snapshot.up);
+
+        match scraped {
+            Ok(samples) => {
+                for (_, family) in FAMILIES {
+                    let series = samples.iter().filter(|s| s.family == family).count();
+                    metrics.set_pipeline_series(&target.name, family, series);
+                    snapshot.series.insert(family, series);
+                }
+                snapshot.component = classify(&samples);
+                snapshot.shards = shards(&samples);
+
+                let derived = tracker.observe(&samples, target.queue_capacity, now);
+                if let Some(ratio) = derived.drop_ratio {
+                    metrics.set_pipeline_drop_ratio(&target.name, ratio);
+                    if ratio > thresholds.max_drop_ratio {
+                        alerts.push((ALERT_DROP_RATIO, ratio, thresholds.max_drop_ratio));
+                    }
+                }
+                if let Some(saturation) = derived.queue_saturation {
+                    metrics.set_pipeline_queue_saturation(&target.name, saturation);
+                    if saturation > thresholds.max_queue_saturation {
+                        alerts.push((
+                            ALERT_QUEUE_SATURATION,
+                            saturation,
+                            thresholds.max_queue_saturation,
+                        ));
+                    }
+                }
+                if let Some(latency) = derived.encode_latency_seconds {
+                    metrics.set_pipeline_encode_latency(&target.name, latency);
+                }
+                let age = derived.seconds_since_last_record;
+                metrics.set_pipeline_record_age(&target.name, age);
+                let stale_after = thresholds.stale_after().as_secs_f64();
+                if age > stale_after {
+                    alerts.push((ALERT_STALE, age, stale_after));
+                }
+                snapshot.drop_ratio = derived.drop_ratio;
+                snapshot.queue_saturation = derived.queue_saturation;
+                snapshot.encode_latency_seconds = derived.encode_latency_seconds;
+                snapshot.seconds_since_last_record = Some(age);
+            }
+            Err(err) => {
+                tracing::debug!(component = %target.name, error = %err, "pipeline scrape failed");
+                alerts.push((ALERT_DOWN, 1.0, 0.0));
+            }
+        }
+
+        for alert in ALERTS {
+            let now_firing = alerts.iter().find(|(name, _, _)| *name == alert);
+            let was_firing = firing.contains(&alert);
+            metrics.set_pipeline_alert(&target.name, alert, now_firing.is_some());
+            match (now_firing, was_firing) {
+                (Some(&(alert, value, threshold)), false) => {
+                    tracing::warn!(
+                        component = %target.name,
+                        alert,
+                        value,
+                        threshold,
+                        "pipeline alert firing"
+                    );
+                    if let Some(alerting) = &alerting {
+                        let alert = Alert {
+                            alert: alert.into(),
+                            subject: target.name.clone(),
+                            value,
+                            threshold,
+                        };
+                        if let Err(err) = alerting.fire(&alert).await {
+                            tracing::warn!(component = %target.name, error = %err, "failed to trigger alert");
+                        }
+                    }
+                }
+                (None, true) => {
+                    tracing::info!(component = %target.name, alert, "pipeline alert resolved");
+                }
+                _ => {}
+            }
+        }
+        firing = alerts.iter().map(|(name, _, _)| *name).collect();
+        snapshot.firing = firing.clone();
+        state.update_pipeline(snapshot);
+    }
+}
+
+/// Tell the components apart by the metrics only each exports; the consumer
+/// and the aggregator export `ultra_` families too.
+pub fn classify(samples: &[Sample]) -> Option<ComponentKind> {
+    let any = |prefixes: &[&str]| {
+        samples
+            .iter()
+            .any(|sample| prefixes.iter().any(|p| sample.name.starts_with(p)))
+    };
+    if any(&["ys_consumer_"]) {
+        Some(ComponentKind::Consumer)
+    } else if any(&["ultra_ingest_", "rpc_bridge_"]) {
+        Some(ComponentKind::Rpc)
+    } else if any(&["ultra_dedup_", "ultra_conn_", "ultra_decode_"]) {
+        Some(ComponentKind::Aggregator)
+    } else if any(&["ultra_"]) {
+        Some(ComponentKind::Plugin)
+    } else {
+        None
+    }
+}
+
+/// Distinct values of the `shard` label, in order.
+pub fn shards(samples: &[Sample]) -> Vec<String> {
+    let mut shards: Vec<String> = samples
+        .iter()
+        .flat_map(|sample| &sample.labels)
+        .filter(|(key, _)| key == "shard")
+        .map(|(_, value)| value.clone())
+        .collect();
+    shards.sort_by(|a, b| {
+        a.parse::<u64>()
+            .ok()
+            .cmp(&b.parse::<u64>().ok())
+            .then_with(|| a.cmp(b))
+    });
+    shards.dedup();
+    shards
+}
+
+/// One scrape of `target`, for what it is and which shards it runs.
+pub async fn discover(
+    target: &PipelineTargetConfig,
+) -> Result<(Option<ComponentKind>, Vec<String>)> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(2))
+        .build()
+        .context("failed to construct metrics client")?;
+    let samples = scrape(&client, target).await?;
+    Ok((classify(&samples), shards(&samples)))
+}
+
+async fn scrape(client: &Client, target: &PipelineTargetConfig) -> Result<Vec<Sample>> {
+    let response = client
+        .get(target.metrics_url.clone())
+        .send()
+        .await
+        .context("metrics request failed")?;
+    if !response.status().is_success() {
+        anyhow::bail!("metrics endpoint returned status {}", response.status());
+    }
+    let body = response
+        .text()
+        .await
+        .context("failed to read metrics body")?;
+    Ok(parse_exposition(&body))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub family: &'static str,
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// Samples of the pipeline families in a Prometheus text exposition; other
+/// families and malformed lines are skipped.
+pub fn parse_exposition(text: &str) -> Vec<Sample> {
+    parse_families(text, &FAMILIES)
+}
+
+/// Samples whose name starts with one of the `(prefix, family)` pairs.
+pub fn parse_families(text: &str, families: &[(&str, &'static str)]) -> Vec<Sample> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| parse_sample(line, families))
+        .collect()
+}
+
+fn parse_sample(line: &str, families: &[(&str, &'static str)]) -> Option<Sample> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .unwrap_or(line.len());
+    let name = &line[..name_end];
+    let family = families
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, family)| *family)?;
+
+    let mut rest = &line[name_end..];
+    let mut labels = Vec::new();
+    if let Some(body) = rest.strip_prefix('{') {
+        let (parsed, after) = parse_labels(body)?;
+        labels = parsed;
+        rest = after;
+    }
+    // The value may be followed by a timestamp
+    let value = match rest.split_whitespace().next()? {
+        "+Inf" => f64::INFINITY,
+        "-Inf" => f64::NEG_INFINITY,
+        value => value.parse().ok()?,
+    };
+    Some(Sample {
+        family,
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+/// Labels up to the closing brace, and what follows it.
+fn parse_labels(mut body: &str) -> Option<(Vec<(String, String)>, &str)> {
+    let mut labels = Vec::new();
+    loop {
+        body = body.trim_start_matches([' ', ',']);
+        if let Some(rest) = body.strip_prefix('}') {
+            return Some((labels, rest));
+        }
+        let (key, after) = body.split_once('=')?;
+        let mut chars = after.strip_prefix('"')?.char_indices();
+        let mut value = String::new();
+        let end = loop {
+            match chars.next()? {
+                (i, '"') => break i,
+                (_, '\\') => match chars.next()?.1 {
+                    'n' => value.push('\n'),
+                    other => value.push(other),
+                },
+                (_, c) => value.push(c),
+            }
+        };
+        labels.push((key.trim().to_string(), value));
+        body = &after[end + 2..];
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derived {
+    pub drop_ratio: Option<f64>,
+    pub queue_saturation: Option<f64>,
+    /// Mean time per record encoded since the previous scrape.
+    pub encode_latency_seconds: Option<f64>,
+    pub seconds_since_last_record: f64,
+}
+
+/// Turns successive scrapes of one component into rates and ages.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    previous: Option<(f64, f64)>,
+    previous_encode: Option<(f64, f64)>,
+    last_record: Option<Instant>,
+}
+
+impl Tracker {
+    pub fn observe(
+        &mut self,
+        samples: &[Sample],
+        queue_capacity: Option<u64>,
+        now: Instant,
+    ) -> Derived {
+        let drops = sum(samples, DROP_COUNTERS);
+        let records = sum(samples, RECORD_COUNTERS);
+
+        let mut drop_ratio = None;
+        match self.previous {
+            Some((prev_drops, prev_records)) => {
+                let dropped = increase(prev_drops, drops);
+                let passed = increase(prev_records, records);
+                if dropped + passed > 0.0 {
+                    drop_ratio = Some(dropped / (dropped + passed));
+                }
+                if passed > 0.0 {
+                    self.last_record = Some(now);
+                }
+            }
+            None => self.last_record = Some(now),
+        }
+        self.previous = Some((drops, records));
+
+        let encode_ns = sum(samples, &[ENCODE_NS_SUM]);
+        let encodes = sum(samples, &[ENCODE_NS_COUNT]);
+        let encode_latency_seconds = self.previous_encode.and_then(|(prev_ns, prev_count)| {
+            let count = increase(prev_count, encodes);
+            (count > 0.0).then(|| increase(prev_ns, encode_ns) / count / 1e9)
+        });
+        self.previous_encode = Some((encode_ns, encodes));
+
+        let observed_age = self
+            .last_record
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+        let reported_age = samples
+            .iter()
+            .filter(|s| AGE_GAUGES.contains(&s.name.as_str()))
+            .map(|s| s.value)
+            .fold(0.0, f64::max);
+
+        Derived {
+            drop_ratio,
+            queue_saturation: queue_saturation(samples, queue_capacity),
+            encode_latency_seconds,
+            seconds_since_last_record: observed_age.max(reported_age),
+        }
+    }
+}
+
+fn sum(samples: &[Sample], names: &[&str]) -> f64 {
+    samples
+        .iter()
+        .filter(|s| names.contains(&s.name.as_str()))
+        .map(|s| s.value)
+        .sum()
+}
+
+// A counter that went down was reset by a restart
+fn increase(previous: f64, current: f64) -> f64 {
+    if current >= previous {
+        current - previous
+    } else {
+        current
+    }
+}
+
+fn queue_saturation(samples: &[Sample], queue_capacity: Option<u64>) -> Option<f64> {
+    let sinks = samples
+        .iter()
+        .filter(|s| s.name == "ultra_sink_queue_depth")
+        .filter_map(|depth| {
+            let capacity = samples
+                .iter()
+                .find(|s| s.name == "ultra_sink_queue_capacity" && s.labels == depth.labels)?;
+            (capacity.value > 0.0).then(|| depth.value / capacity.value)
+        });
+    let writers = queue_capacity
+        .filter(|capacity| *capacity > 0)
+        .into_iter()
+        .flat_map(|capacity| {
+            samples
+                .iter()
+                .filter(|s| s.name == "ultra_queue_len")
+                .map(move |len| len.value / capacity as f64)
+        });
+    sinks.chain(writers).reduce(f64::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"
+# HELP ultra_dropped_total dropped records
+# TYPE ultra_dropped_total counter
+ultra_dropped_total{reason="queue_full"} 5
+ultra_dropped_total{reason="no_buf"} 5
+ultra_enqueued_total 990
+ultra_queue_len{shard="0"} 100
+ultra_queue_len{shard="1"} 300
+ultra_sink_queue_depth{sink="kafka"} 50
+ultra_sink_queue_capacity{sink="kafka"} 100
+ultra_encode_ns_sum{kind="account"} 400000
+ultra_encode_ns_count{kind="account"} 100
+rpc_bridge_producer_records_total{producer="a \"quoted\" one"} 0 1700000000000
+process_cpu_seconds_total 12.5
+"#;
+
+    #[test]
+    fn parses_pipeline_families_only() {
+        let samples = parse_exposition(EXPOSITION);
+        assert_eq!(samples.len(), 10);
+        assert!(samples.iter().all(|s| s.family != "process"));
+        let bridge = samples.last().unwrap();
+        assert_eq!(bridge.family, "rpc_bridge");
+        assert_eq!(
+            bridge.labels,
+            vec![("producer".to_string(), "a \"quoted\" one".to_string())]
+        );
+        assert_eq!(bridge.value, 0.0);
+        assert_eq!(
+            parse_sample("ultra_queue_len{shard=\"0\" 1", &FAMILIES),
+            None
+        );
+    }
+
+    #[test]
+    fn classifies_components_and_shards() {
+        let samples = parse_exposition(EXPOSITION);
+        assert_eq!(classify(&samples), Some(ComponentKind::Rpc));
+        assert_eq!(shards(&samples), vec!["0", "1"]);
+
+        let consumer = parse_exposition(
+            "ultra_queue_len{shard=\"10\"} 1\nultra_queue_len{shard=\"2\"} 1\nys_consumer_dropped_total 0",
+        );
+        assert_eq!(classify(&consumer), Some(ComponentKind::Consumer));
+        assert_eq!(shards(&consumer), vec!["2", "10"]);
+        let aggregator = parse_exposition("ultra_dedup_duplicates_total{source=\"a\"} 3");
+        assert_eq!(classify(&aggregator), Some(ComponentKind::Aggregator));
+        let plugin = parse_exposition("ultra_enqueued_total 3");
+        assert_eq!(classify(&plugin), Some(ComponentKind::Plugin));
+        assert_eq!(classify(&[]), None);
+    }
+
+    #[test]
+    fn derives_drop_ratio_saturation_and_age() {
+        let mut tracker = Tracker::default();
+        let start = Instant::now();
+        let first = parse_exposition(EXPOSITION);
+        let derived = tracker.observe(&first, Some(1000), start);
+        assert_eq!(derived.drop_ratio, None);
+        assert_eq!(derived.queue_saturation, Some(0.5));
+        assert_eq!(derived.encode_latency_seconds, None);
+        assert_eq!(derived.seconds_since_last_record, 0.0);
+
+        let second = parse_exposition(
+            &EXPOSITION
+                .replace("\"queue_full\"} 5", "\"queue_full\"} 15")
+                .replace("ultra_enqueued_total 990", "ultra_enqueued_total 1080")
+                .replace("\"account\"} 100", "\"account\"} 200")
+                .replace("} 400000", "} 1000000"),
+        );
+        let derived = tracker.observe(&second, None, start + Duration::from_secs(2));
+        assert_eq!(derived.drop_ratio, Some(0.1));
+        assert_eq!(derived.queue_saturation, Some(0.5));
+        assert_eq!(derived.encode_latency_seconds, Some(6e-6));
+        assert_eq!(derived.seconds_since_last_record, 0.0);
+
+        // Nothing moves: no ratio, and the age climbs
+        let derived = tracker.observe(&second, None, start + Duration::from_secs(7));
+        assert_eq!(derived.drop_ratio, None);
+        assert_eq!(derived.seconds_since_last_record, 5.0);
+    }
+}
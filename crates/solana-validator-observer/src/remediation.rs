@@ -0,0 +1,300 @@
+// Numan Thabit 2025
+use std::{process::Stdio, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use dashmap::DashMap;
+use reqwest::Client;
+use serde_json::json;
+use tokio::{process::Command, time::Instant};
+
+use crate::{
+    alert::Alert,
+    config::{ActionConfig, ActionKind},
+    metrics::ObserverMetrics,
+};
+
+const RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a trigger does with one action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// The action is scoped to another subject.
+    Skip,
+    /// The action ran for this subject within its cooldown.
+    Cooldown,
+    DryRun,
+    Run,
+}
+
+/// Runs the remediation actions alert rules name, at most once per cooldown
+/// for each action and subject.
+#[derive(Clone)]
+pub struct Remediator {
+    client: Client,
+    actions: Arc<[ActionConfig]>,
+    last_run: Arc<DashMap<String, Instant>>,
+    metrics: ObserverMetrics,
+}
+
+impl Remediator {
+    pub fn new(actions: Vec<ActionConfig>, metrics: ObserverMetrics) -> Result<Self> {
+        for (idx, action) in actions.iter().enumerate() {
+            if actions[..idx].iter().any(|a| a.name == action.name) {
+                bail!("duplicate remediation action {}", action.name);
+            }
+            match action.kind {
+                ActionKind::Script if action.command.is_empty() => {
+                    bail!("script action {} needs a command", action.name)
+                }
+                ActionKind::Webhook if action.url.is_none() => {
+                    bail!("webhook action {} needs a url", action.name)
+                }
+                ActionKind::SystemdRestart if action.unit.is_none() => {
+                    bail!("systemd_restart action {} needs a unit", action.name)
+                }
+                _ => {}
+            }
+        }
+        Ok(Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .context("failed to build remediation client")?,
+            actions: actions.into(),
+            last_run: Arc::new(DashMap::new()),
+            metrics,
+        })
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.actions.iter().any(|action| action.name == name)
+    }
+
+    /// Start the actions named by an alert's rule. Runs happen in the
+    /// background so a slow script never holds up notifications.
+    pub fn trigger(&self, names: &[String], alert: &Alert) {
+        let now = Instant::now();
+        for action in self
+            .actions
+            .iter()
+            .filter(|action| names.contains(&action.name))
+        {
+            match self.admit(action, alert, now) {
+                Admission::Skip => {}
+                Admission::Cooldown => self.metrics.inc_remediation(&action.name, "cooldown"),
+                Admission::DryRun => {
+                    tracing::info!(
+                        action = %action.name,
+                        alert = %alert.alert,
+                        subject = %alert.subject,
+                        "remediation dry run; not running action"
+                    );
+                    self.metrics.inc_remediation(&action.name, "dry_run");
+                }
+                Admission::Run => {
+                    let remediator = self.clone();
+                    let action = action.clone();
+                    let alert = alert.clone();
+                    tokio::spawn(async move {
+                        tracing::warn!(
+                            action = %action.name,
+                            alert = %alert.alert,
+                            subject = %alert.subject,
+                            "running remediation action"
+                        );
+                        match remediator.run(&action, &alert).await {
+                            Ok(()) => remediator
+                                .metrics
+                                .inc_remediation(&action.name, "succeeded"),
+                            Err(err) => {
+                                tracing::error!(action = %action.name, error = %err, "remediation action failed");
+                                remediator.metrics.inc_remediation(&action.name, "failed");
+                            }
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Decide whether `action` runs for `alert`, starting its cooldown if so.
+    /// Failed runs keep their cooldown so a broken fix is not retried in a loop.
+    fn admit(&self, action: &ActionConfig, alert: &Alert, now: Instant) -> Admission {
+        if action
+            .subject
+            .as_deref()
+            .is_some_and(|subject| subject != alert.subject)
+        {
+            return Admission::Skip;
+        }
+        let key = format!("{}/{}", action.name, alert.subject);
+        if self
+            .last_run
+            .get(&key)
+            .is_some_and(|last| now.duration_since(*last) < action.cooldown())
+        {
+            return Admission::Cooldown;
+        }
+        self.last_run.insert(key, now);
+        if action.dry_run {
+            Admission::DryRun
+        } else {
+            Admission::Run
+        }
+    }
+
+    async fn run(&self, action: &ActionConfig, alert: &Alert) -> Result<()> {
+        match action.kind {
+            ActionKind::Script => run_command(&action.command, alert).await,
+            ActionKind::SystemdRestart => {
+                let unit = action.unit.as_deref().context("action has no unit")?;
+                run_command(&["systemctl", "restart", unit], alert).await
+            }
+            ActionKind::Webhook => {
+                let url = action.url.clone().context("action has no url")?;
+                let response = self
+                    .client
+                    .post(url)
+                    .json(&json!({
+                        "action": action.name,
+                        "alert": alert.alert,
+                        "subject": alert.subject,
+                        "value": alert.value,
+                        "threshold": alert.threshold,
+                        "timestamp": Utc::now(),
+                    }))
+                    .send()
+                    .await
+                    .context("remediation webhook request failed")?;
+                if !response.status().is_success() {
+                    bail!("remediation webhook returned status {}", response.status());
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Run `command` with the alert in its environment.
+async fn run_command(command: &[impl AsRef<str>], alert: &Alert) -> Result<()> {
+    let (program, args) = command.split_first().context("empty command")?;
+    let child = Command::new(program.as_ref())
+        .args(args.iter().map(AsRef::as_ref))
+        .env("ALERT", alert.alert.as_ref())
+        .env("ALERT_SUBJECT", &alert.subject)
+        .env("ALERT_VALUE", alert.value.to_string())
+        .env("ALERT_THRESHOLD", alert.threshold.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to spawn {}", program.as_ref()))?;
+    let output = tokio::time::timeout(RUN_TIMEOUT, child.wait_with_output())
+        .await
+        .with_context(|| format!("{} timed out after {RUN_TIMEOUT:?}", program.as_ref()))?
+        .with_context(|| format!("failed to wait for {}", program.as_ref()))?;
+    if !output.status.success() {
+        bail!(
+            "{} exited with {}: {}",
+            program.as_ref(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(name: &str, kind: ActionKind) -> ActionConfig {
+        ActionConfig {
+            name: name.into(),
+            kind,
+            command: vec!["true".into()],
+            url: None,
+            unit: None,
+            subject: None,
+            cooldown: Some(Duration::from_secs(60)),
+            dry_run: false,
+        }
+    }
+
+    fn alert(subject: &str) -> Alert {
+        Alert {
+            alert: "stale".into(),
+            subject: subject.into(),
+            value: 30.0,
+            threshold: 10.0,
+        }
+    }
+
+    #[test]
+    fn rejects_incomplete_actions() {
+        let metrics = ObserverMetrics::new();
+        let webhook = action("notify", ActionKind::Webhook);
+        assert!(Remediator::new(vec![webhook], metrics.clone()).is_err());
+        let restart = action("restart", ActionKind::SystemdRestart);
+        assert!(Remediator::new(vec![restart], metrics.clone()).is_err());
+        let script = action("fix", ActionKind::Script);
+        assert!(Remediator::new(vec![script.clone(), script], metrics).is_err());
+    }
+
+    #[test]
+    fn admits_once_per_cooldown_and_subject() {
+        let mut scoped = action("restart-consumer", ActionKind::Script);
+        scoped.subject = Some("ys-consumer".into());
+        let mut dry = action("dry", ActionKind::Script);
+        dry.dry_run = true;
+        let remediator = Remediator::new(vec![scoped, dry], ObserverMetrics::new()).unwrap();
+        let [scoped, dry] = &remediator.actions[..] else {
+            unreachable!()
+        };
+        let now = Instant::now();
+
+        assert_eq!(
+            remediator.admit(scoped, &alert("geyser"), now),
+            Admission::Skip
+        );
+        assert_eq!(
+            remediator.admit(scoped, &alert("ys-consumer"), now),
+            Admission::Run
+        );
+        assert_eq!(
+            remediator.admit(scoped, &alert("ys-consumer"), now + Duration::from_secs(30)),
+            Admission::Cooldown
+        );
+        assert_eq!(
+            remediator.admit(scoped, &alert("ys-consumer"), now + Duration::from_secs(61)),
+            Admission::Run
+        );
+
+        assert_eq!(
+            remediator.admit(dry, &alert("geyser"), now),
+            Admission::DryRun
+        );
+        assert_eq!(
+            remediator.admit(dry, &alert("validator"), now),
+            Admission::DryRun
+        );
+        assert_eq!(
+            remediator.admit(dry, &alert("geyser"), now),
+            Admission::Cooldown
+        );
+    }
+
+    #[tokio::test]
+    async fn runs_scripts_with_the_alert_in_the_environment() {
+        let check = [
+            "sh",
+            "-c",
+            r#"test "$ALERT/$ALERT_SUBJECT/$ALERT_VALUE" = stale/geyser/30"#,
+        ];
+        run_command(&check, &alert("geyser")).await.unwrap();
+        let err = run_command(&["false"], &alert("geyser")).await.unwrap_err();
+        assert!(err.to_string().contains("false exited with"));
+    }
+}
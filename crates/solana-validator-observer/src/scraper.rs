@@ -55,6 +55,7 @@ async fn run_validator(
         rpc_url,
         expected_slot_lookback,
         max_slot_interval,
+        identity: _,
     } = validator;
 
     let rpc_client = if rpc_url.is_some() {
@@ -104,6 +105,7 @@ async fn run_validator(
         let mut propagation = None;
 
         if let Some(result) = rpc_result {
+            metrics.inc_scrape(&name, "rpc");
             match result {
                 Ok(sample) => {
                     let bounded = max_slot_interval
@@ -122,6 +124,7 @@ async fn run_validator(
             }
         }
 
+        metrics.inc_scrape(&name, "gossip");
         match gossip_result {
             Ok(Some(latency)) => {
                 metrics.record_gossip_latency(&name, latency.as_secs_f64());
@@ -138,6 +141,7 @@ async fn run_validator(
         }
 
         if let Some(result) = quic_result {
+            metrics.inc_scrape(&name, "quic");
             match result {
                 Ok(Some(latency)) => {
                     metrics.record_quic_latency(&name, latency.as_secs_f64());
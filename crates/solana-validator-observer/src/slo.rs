@@ -0,0 +1,326 @@
+// Numan Thabit 2025
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{ObjectiveConfig, SliConfig, SloConfig},
+    metrics::ObserverMetrics,
+    state::{ObserverState, SloStatus},
+};
+
+/// Cumulative good and total event counts of one objective and subject.
+#[derive(Debug, Default)]
+pub struct EventHistory {
+    samples: VecDeque<(Instant, f64, f64)>,
+}
+
+impl EventHistory {
+    /// Record the counts at `now`, keeping enough samples to cover `retain`.
+    pub fn observe(&mut self, now: Instant, good: f64, total: f64, retain: Duration) {
+        self.samples.push_back((now, good, total));
+        // One sample at or past the edge anchors the oldest window
+        while self
+            .samples
+            .get(1)
+            .is_some_and(|(at, ..)| now.duration_since(*at) >= retain)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Share of bad events over the trailing `window`, or over all samples
+    /// while they span less. `None` without events.
+    pub fn error_ratio(&self, now: Instant, window: Duration) -> Option<f64> {
+        let (_, good, total) = *self.samples.back()?;
+        let (_, base_good, base_total) = *self
+            .samples
+            .iter()
+            .rev()
+            .find(|(at, ..)| now.duration_since(*at) >= window)
+            .or(self.samples.front())?;
+        let events = total - base_total;
+        (events > 0.0).then(|| ((events - (good - base_good)) / events).clamp(0.0, 1.0))
+    }
+}
+
+/// How many times faster than `target` allows the error budget is spent.
+pub fn burn_rate(error_ratio: f64, target: f64) -> f64 {
+    error_ratio / (1.0 - target)
+}
+
+/// Short form of a window length: `5m`, `6h`, `3d`.
+pub fn window_label(window: Duration) -> String {
+    let secs = window.as_secs();
+    match secs {
+        0 => "0s".into(),
+        _ if secs.is_multiple_of(86_400) => format!("{}d", secs / 86_400),
+        _ if secs.is_multiple_of(3_600) => format!("{}h", secs / 3_600),
+        _ if secs.is_multiple_of(60) => format!("{}m", secs / 60),
+        _ => format!("{secs}s"),
+    }
+}
+
+/// Cumulative good and total events per subject.
+fn sli_counts(
+    metrics: &ObserverMetrics,
+    objective: &ObjectiveConfig,
+) -> Result<BTreeMap<String, (f64, f64)>> {
+    let label = &objective.subject_label;
+    match &objective.sli {
+        SliConfig::Ratio { errors, total } => {
+            let errors = metrics.counter_totals(errors, label);
+            Ok(metrics
+                .counter_totals(total, label)
+                .into_iter()
+                .map(|(subject, total)| {
+                    let bad = errors.get(&subject).copied().unwrap_or_default();
+                    (subject, ((total - bad).max(0.0), total))
+                })
+                .collect())
+        }
+        SliConfig::Latency {
+            histogram,
+            threshold,
+        } => metrics.histogram_totals(histogram, label, *threshold),
+    }
+}
+
+pub fn spawn_evaluator(
+    config: SloConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, state, metrics, alerting).await {
+            tracing::error!(%err, "slo evaluator loop terminated");
+        }
+    })
+}
+
+async fn run(
+    config: SloConfig,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    for objective in &config.objectives {
+        if objective.target <= 0.0 || objective.target >= 1.0 {
+            bail!(
+                "slo {} target must lie between 0 and 1, got {}",
+                objective.name,
+                objective.target
+            );
+        }
+    }
+    for window in &config.burn_windows {
+        if window.short > window.long {
+            bail!(
+                "burn window {} has a short window past its long one",
+                window.alert
+            );
+        }
+    }
+    let retain = config
+        .burn_windows
+        .iter()
+        .map(|window| window.long)
+        .max()
+        .unwrap_or_default();
+    let mut lengths: Vec<Duration> = config
+        .burn_windows
+        .iter()
+        .flat_map(|window| [window.short, window.long])
+        .collect();
+    lengths.sort();
+    lengths.dedup();
+
+    let mut ticker = interval_at(Instant::now(), config.evaluation_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut histories: HashMap<(String, String), EventHistory> = HashMap::new();
+    let mut unreadable: HashSet<String> = HashSet::new();
+    let mut firing: HashSet<String> = HashSet::new();
+
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+        let mut statuses = Vec::new();
+        let mut alerts = Vec::new();
+        let mut now_firing = HashSet::new();
+
+        for objective in &config.objectives {
+            let counts = match sli_counts(&metrics, objective) {
+                Ok(counts) => counts,
+                Err(err) => {
+                    if unreadable.insert(objective.name.clone()) {
+                        tracing::warn!(slo = %objective.name, error = %err, "failed to read slo metrics");
+                    }
+                    continue;
+                }
+            };
+            for (subject, (good, total)) in counts {
+                let history = histories
+                    .entry((objective.name.clone(), subject.clone()))
+                    .or_default();
+                history.observe(now, good, total, retain);
+
+                let mut burn_rates = BTreeMap::new();
+                for &length in &lengths {
+                    if let Some(ratio) = history.error_ratio(now, length) {
+                        let rate = burn_rate(ratio, objective.target);
+                        metrics.set_slo_burn_rate(
+                            &objective.name,
+                            &subject,
+                            &window_label(length),
+                            rate,
+                        );
+                        burn_rates.insert(length, rate);
+                    }
+                }
+
+                let mut status_firing = Vec::new();
+                for window in &config.burn_windows {
+                    let label = window_label(window.long);
+                    let rate = burn_rates
+                        .get(&window.long)
+                        .zip(burn_rates.get(&window.short))
+                        .map(|(long, short)| long.min(*short));
+                    let is_firing = rate.is_some_and(|rate| rate > window.factor);
+                    metrics.set_slo_alert(&objective.name, &subject, &label, is_firing);
+                    if !is_firing {
+                        continue;
+                    }
+                    let alert_subject = format!("{}/{subject}", objective.name);
+                    now_firing.insert(format!("{}/{alert_subject}/{label}", window.alert));
+                    status_firing.push(format!("{}/{label}", window.alert));
+                    alerts.push(Alert {
+                        alert: window.alert.clone().into(),
+                        subject: alert_subject,
+                        value: rate.unwrap_or_default(),
+                        threshold: window.factor,
+                    });
+                }
+
+                statuses.push(SloStatus {
+                    objective: objective.name.clone(),
+                    subject,
+                    target: objective.target,
+                    sli: lengths
+                        .last()
+                        .and_then(|&longest| history.error_ratio(now, longest))
+                        .map(|ratio| 1.0 - ratio),
+                    burn_rates: burn_rates
+                        .into_iter()
+                        .map(|(length, rate)| (window_label(length), rate))
+                        .collect(),
+                    firing: status_firing,
+                });
+            }
+        }
+        state.update_slo(statuses);
+
+        for started in now_firing.difference(&firing) {
+            tracing::warn!(alert = %started, "slo burn-rate alert firing");
+        }
+        for resolved in firing.difference(&now_firing) {
+            tracing::info!(alert = %resolved, "slo burn-rate alert resolved");
+        }
+        firing = now_firing;
+
+        if let Some(alerting) = &alerting {
+            for alert in &alerts {
+                if let Err(err) = alerting.fire(alert).await {
+                    tracing::warn!(subject = %alert.subject, error = %err, "failed to trigger alert");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_ratio_covers_the_trailing_window() {
+        let mut history = EventHistory::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let retain = Duration::from_secs(3_600);
+
+        assert_eq!(history.error_ratio(start, retain), None);
+        // 100 events a minute; the last five minutes lose 10 of each 100
+        let mut good = 0.0;
+        for minute in 0..=60u64 {
+            history.observe(at(minute * 60), good, minute as f64 * 100.0, retain);
+            good += if minute >= 55 { 90.0 } else { 100.0 };
+        }
+        let now = at(3_600);
+        let five_minutes = history.error_ratio(now, Duration::from_secs(300)).unwrap();
+        assert!((five_minutes - 0.1).abs() < 1e-9);
+        let hour = history.error_ratio(now, retain).unwrap();
+        assert!((hour - 50.0 / 6_000.0).abs() < 1e-9);
+        assert!((burn_rate(five_minutes, 0.99) - 10.0).abs() < 1e-9);
+
+        // A window longer than what is kept falls back to all of it
+        assert_eq!(
+            history.error_ratio(now, Duration::from_secs(86_400)),
+            Some(hour)
+        );
+        history.observe(at(3_660), good, 6_100.0, retain);
+        assert_eq!(history.samples.len(), 61);
+    }
+
+    #[test]
+    fn labels_windows() {
+        assert_eq!(window_label(Duration::from_secs(300)), "5m");
+        assert_eq!(window_label(Duration::from_secs(21_600)), "6h");
+        assert_eq!(window_label(Duration::from_secs(259_200)), "3d");
+        assert_eq!(window_label(Duration::from_secs(90)), "90s");
+    }
+
+    #[test]
+    fn counts_good_events_from_metrics() {
+        let metrics = ObserverMetrics::new();
+        for latency in [0.004, 0.03, 0.2] {
+            metrics.record_rpc_latency("alpha", latency);
+        }
+        for _ in 0..4 {
+            metrics.inc_scrape("alpha", "rpc");
+            metrics.inc_scrape("alpha", "gossip");
+        }
+        metrics.inc_scrape_error("alpha", "gossip");
+
+        let objective = |sli| ObjectiveConfig {
+            name: "test".into(),
+            target: 0.99,
+            subject_label: "validator".into(),
+            sli,
+        };
+        let latency = objective(SliConfig::Latency {
+            histogram: "rpc_request_latency_seconds".into(),
+            threshold: 0.05,
+        });
+        assert_eq!(sli_counts(&metrics, &latency).unwrap()["alpha"], (2.0, 3.0));
+        let scrapes = objective(SliConfig::Ratio {
+            errors: "scrape_errors_total".into(),
+            total: "scrapes_total".into(),
+        });
+        assert_eq!(sli_counts(&metrics, &scrapes).unwrap()["alpha"], (7.0, 8.0));
+
+        let off_bucket = objective(SliConfig::Latency {
+            histogram: "rpc_request_latency_seconds".into(),
+            threshold: 0.07,
+        });
+        assert!(sli_counts(&metrics, &off_bucket).is_err());
+    }
+}
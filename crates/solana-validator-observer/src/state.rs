@@ -1,5 +1,6 @@
 // Numan Thabit 2025
 use std::{
+    collections::BTreeMap,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -9,6 +10,7 @@ use std::{
 
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
+use parking_lot::RwLock;
 use serde::Serialize;
 
 #[derive(Debug, Clone, Serialize)]
@@ -22,9 +24,184 @@ pub struct ValidatorSnapshot {
     pub quic_latency_ms: Option<f64>,
     pub rpc_latency_ms: Option<f64>,
     pub packet_loss_ratio: Option<f64>,
+    pub vote: Option<VoteStatus>,
+    pub leader: Option<LeaderStats>,
+    pub catchup: Option<CatchupStatus>,
     pub last_updated: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VoteStatus {
+    pub vote_pubkey: String,
+    pub last_vote: u64,
+    /// Slots between the validator's last vote and the newest vote in the cluster.
+    pub vote_distance: u64,
+    pub delinquent: bool,
+    pub epoch_credits: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaderStats {
+    pub epoch: u64,
+    /// Leader slots assigned for the whole epoch.
+    pub scheduled_slots: u64,
+    /// Leader slots that have passed so far this epoch.
+    pub leader_slots: u64,
+    pub produced: u64,
+    pub skipped: u64,
+    pub next_leader_slot: Option<u64>,
+    pub epoch_skip_rate: Option<f64>,
+    pub window_skip_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchupState {
+    InSync,
+    /// Further behind the reference nodes than allowed.
+    Behind,
+    /// Keeping up, but the reference nodes themselves advance slowly.
+    ClusterSlow,
+}
+
+impl CatchupState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CatchupState::InSync => "in_sync",
+            CatchupState::Behind => "behind",
+            CatchupState::ClusterSlow => "cluster_slow",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CatchupStatus {
+    /// Highest slot reported by any reference node.
+    pub reference_slot: u64,
+    pub slot_gap: u64,
+    /// Slots per second the reference nodes advanced over the sustain window.
+    pub cluster_slot_rate: Option<f64>,
+    pub state: CatchupState,
+    pub behind_for_secs: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PipelineSnapshot {
+    pub name: String,
+    pub up: bool,
+    /// Series scraped per metric family (`ultra`, `ys_consumer`, `rpc_bridge`).
+    pub series: BTreeMap<&'static str, usize>,
+    /// What the exported metrics show the component to be.
+    pub component: Option<ComponentKind>,
+    /// Values of the `shard` label the component exports.
+    pub shards: Vec<String>,
+    pub drop_ratio: Option<f64>,
+    pub queue_saturation: Option<f64>,
+    /// Mean time per record encoded between the last two scrapes.
+    pub encode_latency_seconds: Option<f64>,
+    pub seconds_since_last_record: Option<f64>,
+    pub firing: Vec<&'static str>,
+    pub last_updated: Option<DateTime<Utc>>,
+}
+
+/// The Numistack component behind a pipeline target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    Plugin,
+    Consumer,
+    Aggregator,
+    Rpc,
+}
+
+impl ComponentKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ComponentKind::Plugin => "plugin",
+            ComponentKind::Consumer => "consumer",
+            ComponentKind::Aggregator => "aggregator",
+            ComponentKind::Rpc => "rpc",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiskStats {
+    /// Share of the interval the device was busy.
+    pub utilization: f64,
+    /// Mean time per completed I/O; `None` when none completed.
+    pub latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NicStats {
+    pub drops_per_sec: f64,
+    pub errors_per_sec: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HostSnapshot {
+    /// `builtin`, or the node_exporter scraped.
+    pub source: String,
+    pub disks: BTreeMap<String, DiskStats>,
+    pub nics: BTreeMap<String, NicStats>,
+    pub cpu_steal_ratio: Option<f64>,
+    pub temperatures_celsius: BTreeMap<String, f64>,
+    /// Share of rated endurance used per NVMe controller.
+    pub nvme_wear: BTreeMap<String, f64>,
+    /// `alert/subject` of every host alert firing.
+    pub firing: Vec<String>,
+    pub last_updated: Option<DateTime<Utc>>,
+}
+
+/// Where one objective stands for one subject.
+#[derive(Debug, Clone, Serialize)]
+pub struct SloStatus {
+    pub objective: String,
+    pub subject: String,
+    pub target: f64,
+    /// Share of good events over the longest burn window.
+    pub sli: Option<f64>,
+    /// Burn rate per window length, e.g. `1h` or `5m`.
+    pub burn_rates: BTreeMap<String, f64>,
+    /// `alert/long window` of every burn-rate alert firing.
+    pub firing: Vec<String>,
+}
+
+/// How far one series sits from its learned baseline.
+#[derive(Debug, Clone, Serialize)]
+pub struct AnomalyStatus {
+    pub metric: String,
+    pub subject: String,
+    pub value: f64,
+    pub baseline: f64,
+    pub deviation: f64,
+    /// `None` while the baseline is still warming up.
+    pub score: Option<f64>,
+    pub firing: bool,
+}
+
+/// One metric across the monitored validators.
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetComparison {
+    pub name: String,
+    pub metric: String,
+    pub quantile: Option<f64>,
+    pub median: Option<f64>,
+    pub members: Vec<FleetMember>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FleetMember {
+    pub validator: String,
+    pub value: f64,
+    /// Median of every other validator with a value.
+    pub peer_median: Option<f64>,
+    /// `value` over `peer_median`.
+    pub ratio: Option<f64>,
+    pub firing: bool,
+}
+
 #[derive(Debug)]
 struct MutableValidatorSnapshot {
     name: String,
@@ -35,6 +212,9 @@ struct MutableValidatorSnapshot {
     quic_latency_ms: Option<f64>,
     rpc_latency_ms: Option<f64>,
     packet_loss_ratio: Option<f64>,
+    vote: Option<VoteStatus>,
+    leader: Option<LeaderStats>,
+    catchup: Option<CatchupStatus>,
     last_updated: Option<DateTime<Utc>>,
 }
 
@@ -49,6 +229,9 @@ impl MutableValidatorSnapshot {
             quic_latency_ms: None,
             rpc_latency_ms: None,
             packet_loss_ratio: None,
+            vote: None,
+            leader: None,
+            catchup: None,
             last_updated: None,
         }
     }
@@ -57,6 +240,11 @@ impl MutableValidatorSnapshot {
 #[derive(Clone, Debug)]
 pub struct ObserverState {
     inner: Arc<DashMap<String, MutableValidatorSnapshot>>,
+    pipeline: Arc<DashMap<String, PipelineSnapshot>>,
+    host: Arc<RwLock<Option<HostSnapshot>>>,
+    slo: Arc<RwLock<Vec<SloStatus>>>,
+    anomalies: Arc<RwLock<Vec<AnomalyStatus>>>,
+    fleet: Arc<RwLock<Vec<FleetComparison>>>,
     global_highest_slot: Arc<AtomicU64>,
 }
 
@@ -73,6 +261,11 @@ impl ObserverState {
         }
         Self {
             inner: Arc::new(inner),
+            pipeline: Arc::new(DashMap::new()),
+            host: Arc::new(RwLock::new(None)),
+            slo: Arc::new(RwLock::new(Vec::new())),
+            anomalies: Arc::new(RwLock::new(Vec::new())),
+            fleet: Arc::new(RwLock::new(Vec::new())),
             global_highest_slot: Arc::new(AtomicU64::new(0)),
         }
     }
@@ -130,6 +323,9 @@ impl ObserverState {
                 quic_latency_ms: entry.quic_latency_ms,
                 rpc_latency_ms: entry.rpc_latency_ms,
                 packet_loss_ratio: entry.packet_loss_ratio,
+                vote: entry.vote.clone(),
+                leader: entry.leader.clone(),
+                catchup: entry.catchup.clone(),
                 last_updated: entry.last_updated,
             })
             .collect()
@@ -147,6 +343,9 @@ impl ObserverState {
             quic_latency_ms: entry.quic_latency_ms,
             rpc_latency_ms: entry.rpc_latency_ms,
             packet_loss_ratio: entry.packet_loss_ratio,
+            vote: entry.vote.clone(),
+            leader: entry.leader.clone(),
+            catchup: entry.catchup.clone(),
             last_updated: entry.last_updated,
         })
     }
@@ -159,6 +358,73 @@ impl ObserverState {
         });
     }
 
+    pub fn update_vote(&self, validator: &str, vote: Option<VoteStatus>) {
+        let now = Utc::now();
+        self.with_validator_mut(validator, |entry| {
+            entry.vote = vote.clone();
+            entry.last_updated = Some(now);
+        });
+    }
+
+    pub fn update_leader(&self, validator: &str, leader: LeaderStats) {
+        let now = Utc::now();
+        self.with_validator_mut(validator, |entry| {
+            entry.leader = Some(leader.clone());
+            entry.last_updated = Some(now);
+        });
+    }
+
+    pub fn update_catchup(&self, validator: &str, catchup: CatchupStatus) {
+        let now = Utc::now();
+        self.with_validator_mut(validator, |entry| {
+            entry.catchup = Some(catchup.clone());
+            entry.last_updated = Some(now);
+        });
+    }
+
+    pub fn update_pipeline(&self, snapshot: PipelineSnapshot) {
+        self.pipeline.insert(snapshot.name.clone(), snapshot);
+    }
+
+    pub fn pipeline_snapshots(&self) -> Vec<PipelineSnapshot> {
+        self.pipeline
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    pub fn update_host(&self, snapshot: HostSnapshot) {
+        *self.host.write() = Some(snapshot);
+    }
+
+    pub fn host_snapshot(&self) -> Option<HostSnapshot> {
+        self.host.read().clone()
+    }
+
+    pub fn update_slo(&self, statuses: Vec<SloStatus>) {
+        *self.slo.write() = statuses;
+    }
+
+    pub fn slo_statuses(&self) -> Vec<SloStatus> {
+        self.slo.read().clone()
+    }
+
+    pub fn update_anomalies(&self, statuses: Vec<AnomalyStatus>) {
+        *self.anomalies.write() = statuses;
+    }
+
+    pub fn anomaly_statuses(&self) -> Vec<AnomalyStatus> {
+        self.anomalies.read().clone()
+    }
+
+    pub fn update_fleet(&self, comparisons: Vec<FleetComparison>) {
+        *self.fleet.write() = comparisons;
+    }
+
+    pub fn fleet_comparisons(&self) -> Vec<FleetComparison> {
+        self.fleet.read().clone()
+    }
+
     pub fn highest_slot(&self) -> Option<u64> {
         self.cluster_highest_slot()
     }
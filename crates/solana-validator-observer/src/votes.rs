@@ -0,0 +1,274 @@
+// Numan Thabit 2025
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tokio::{
+    task::JoinHandle,
+    time::{interval_at, Instant, MissedTickBehavior},
+};
+
+use crate::{
+    alert::{Alert, AlertingService},
+    config::{ValidatorConfig, VoteConfig},
+    metrics::ObserverMetrics,
+    state::{ObserverState, VoteStatus},
+};
+
+pub const ALERT_DELINQUENT: &str = "delinquent";
+pub const ALERT_CREDITS_STALLED: &str = "vote_credits_stalled";
+
+static GET_VOTE_ACCOUNTS_PAYLOAD: Lazy<serde_json::Value> = Lazy::new(|| {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "getVoteAccounts",
+        "params": [{"commitment": "confirmed", "keepUnstakedDelinquents": true}],
+    })
+});
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcGetVoteAccounts {
+    result: VoteAccounts,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VoteAccounts {
+    current: Vec<VoteAccount>,
+    delinquent: Vec<VoteAccount>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VoteAccount {
+    vote_pubkey: String,
+    node_pubkey: String,
+    last_vote: u64,
+    /// `[epoch, credits, previous credits]`, oldest first.
+    #[serde(default)]
+    epoch_credits: Vec<(u64, u64, u64)>,
+}
+
+impl VoteAccounts {
+    /// Status of the vote account of `identity`; `None` when it has none.
+    pub fn status(&self, identity: &str, max_vote_distance: u64) -> Option<VoteStatus> {
+        let newest_vote = self
+            .current
+            .iter()
+            .chain(&self.delinquent)
+            .map(|account| account.last_vote)
+            .max()
+            .unwrap_or(0);
+        let (account, listed_delinquent) = self
+            .current
+            .iter()
+            .map(|account| (account, false))
+            .chain(self.delinquent.iter().map(|account| (account, true)))
+            .find(|(account, _)| account.node_pubkey == identity)?;
+        let vote_distance = newest_vote.saturating_sub(account.last_vote);
+        Some(VoteStatus {
+            vote_pubkey: account.vote_pubkey.clone(),
+            last_vote: account.last_vote,
+            vote_distance,
+            delinquent: listed_delinquent || vote_distance > max_vote_distance,
+            epoch_credits: account
+                .epoch_credits
+                .last()
+                .map_or(0, |(_, credits, _)| *credits),
+        })
+    }
+}
+
+pub fn spawn_poller(
+    config: VoteConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        if let Err(err) = run(config, validators, state, metrics, alerting).await {
+            tracing::error!(%err, "vote poll loop terminated");
+        }
+    })
+}
+
+/// Credits last seen for a validator, and when they last grew.
+struct Credits {
+    credits: u64,
+    grew_at: Instant,
+}
+
+async fn run(
+    config: VoteConfig,
+    validators: Vec<ValidatorConfig>,
+    state: ObserverState,
+    metrics: ObserverMetrics,
+    alerting: Option<AlertingService>,
+) -> Result<()> {
+    let monitored: Vec<(String, String)> = validators
+        .into_iter()
+        .filter_map(|v| v.identity.map(|identity| (v.name, identity)))
+        .collect();
+    if monitored.is_empty() {
+        tracing::warn!("vote polling configured but no validator has an identity; skipping");
+        return Ok(());
+    }
+
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .build()
+        .context("failed to construct vote rpc client")?;
+    let mut ticker = interval_at(Instant::now(), config.poll_interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut credits: HashMap<String, Credits> = HashMap::new();
+    let mut delinquent: HashMap<String, bool> = HashMap::new();
+
+    loop {
+        ticker.tick().await;
+
+        let accounts = match fetch_vote_accounts(&client, &config.rpc_url).await {
+            Ok(accounts) => accounts,
+            Err(err) => {
+                tracing::debug!(error = %err, "getVoteAccounts failed");
+                for (name, _) in &monitored {
+                    metrics.inc_scrape_error(name, "vote");
+                }
+                continue;
+            }
+        };
+        let now = Instant::now();
+
+        for (name, identity) in &monitored {
+            let status = accounts.status(identity, config.max_vote_distance);
+            let mut alerts = Vec::new();
+            match &status {
+                Some(status) => {
+                    metrics.set_vote_status(
+                        name,
+                        status.vote_distance,
+                        status.delinquent,
+                        status.epoch_credits,
+                    );
+                    if status.delinquent {
+                        alerts.push(Alert {
+                            alert: ALERT_DELINQUENT.into(),
+                            subject: name.clone(),
+                            value: status.vote_distance as f64,
+                            threshold: config.max_vote_distance as f64,
+                        });
+                    }
+
+                    let seen = credits.entry(name.clone()).or_insert(Credits {
+                        credits: status.epoch_credits,
+                        grew_at: now,
+                    });
+                    if status.epoch_credits > seen.credits {
+                        metrics.inc_vote_credits(name, status.epoch_credits - seen.credits);
+                        seen.grew_at = now;
+                    }
+                    seen.credits = status.epoch_credits;
+                    let stalled_for = now.duration_since(seen.grew_at);
+                    if stalled_for >= config.credits_stall_after() {
+                        alerts.push(Alert {
+                            alert: ALERT_CREDITS_STALLED.into(),
+                            subject: name.clone(),
+                            value: stalled_for.as_secs_f64(),
+                            threshold: config.credits_stall_after().as_secs_f64(),
+                        });
+                    }
+                }
+                None => {
+                    tracing::debug!(validator = %name, %identity, "no vote account for identity");
+                    metrics.inc_scrape_error(name, "vote");
+                }
+            }
+
+            let is_delinquent = status.as_ref().is_some_and(|status| status.delinquent);
+            let was_delinquent = delinquent.insert(name.clone(), is_delinquent);
+            if let Some(status) = &status {
+                if is_delinquent && was_delinquent != Some(true) {
+                    tracing::warn!(
+                        validator = %name,
+                        last_vote = status.last_vote,
+                        distance = status.vote_distance,
+                        "validator is delinquent"
+                    );
+                } else if !is_delinquent && was_delinquent == Some(true) {
+                    tracing::info!(validator = %name, "validator is voting again");
+                }
+            }
+            state.update_vote(name, status);
+
+            if let Some(alerting) = &alerting {
+                for alert in &alerts {
+                    if let Err(err) = alerting.fire(alert).await {
+                        tracing::warn!(validator = %name, error = %err, "failed to trigger alert");
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn fetch_vote_accounts(client: &Client, url: &Url) -> Result<VoteAccounts> {
+    let response = client
+        .post(url.clone())
+        .json(&*GET_VOTE_ACCOUNTS_PAYLOAD)
+        .send()
+        .await
+        .context("rpc request failed")?;
+    if !response.status().is_success() {
+        anyhow::bail!("rpc endpoint returned status {}", response.status());
+    }
+    Ok(response
+        .json::<JsonRpcGetVoteAccounts>()
+        .await
+        .context("failed to decode getVoteAccounts body")?
+        .result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOTE_ACCOUNTS: &str = r#"{
+        "current": [
+            {"votePubkey": "VoteA", "nodePubkey": "NodeA", "activatedStake": 42,
+             "commission": 5, "epochVoteAccount": true, "lastVote": 1000, "rootSlot": 968,
+             "epochCredits": [[9, 4000, 3000], [10, 4500, 4000]]},
+            {"votePubkey": "VoteB", "nodePubkey": "NodeB", "activatedStake": 7,
+             "commission": 5, "epochVoteAccount": true, "lastVote": 820, "rootSlot": 790,
+             "epochCredits": [[10, 900, 800]]}
+        ],
+        "delinquent": [
+            {"votePubkey": "VoteC", "nodePubkey": "NodeC", "activatedStake": 1,
+             "commission": 100, "epochVoteAccount": false, "lastVote": 990, "rootSlot": 0,
+             "epochCredits": []}
+        ]
+    }"#;
+
+    #[test]
+    fn derives_vote_status_per_identity() {
+        let accounts: VoteAccounts = serde_json::from_str(VOTE_ACCOUNTS).unwrap();
+        let a = accounts.status("NodeA", 128).unwrap();
+        assert_eq!(a.vote_pubkey, "VoteA");
+        assert_eq!(a.vote_distance, 0);
+        assert!(!a.delinquent);
+        assert_eq!(a.epoch_credits, 4500);
+
+        // Too far behind counts even before the RPC node lists it
+        let b = accounts.status("NodeB", 128).unwrap();
+        assert_eq!(b.vote_distance, 180);
+        assert!(b.delinquent);
+
+        let c = accounts.status("NodeC", 128).unwrap();
+        assert_eq!(c.vote_distance, 10);
+        assert!(c.delinquent);
+        assert_eq!(c.epoch_credits, 0);
+
+        assert_eq!(accounts.status("NodeD", 128), None);
+    }
+}
@@ -0,0 +1,124 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/account_data.rs
+//
+// Optional account data in the JSON events (stdout and WebSocket). Without a
+// `json_account_data` block only `data_len` is emitted.
+//
+//   "json_account_data": {"encoding": "base64", "max_bytes": 1024, "prefix_hex_bytes": 8}
+//
+// Data longer than `max_bytes` is cut and flagged with `"data_truncated": true`;
+// `prefix_hex_bytes` adds `data_prefix_hex` (e.g. an Anchor discriminator) so
+// consumers can filter without decoding the payload.
+use base64::Engine;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DataEncoding {
+    #[default]
+    Base64,
+    Base58,
+}
+
+impl DataEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DataEncoding::Base64 => "base64",
+            DataEncoding::Base58 => "base58",
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountDataCfg {
+    #[serde(default)]
+    pub encoding: DataEncoding,
+    /// Bytes of data to encode; 0 leaves out `data` (useful with `prefix_hex_bytes` alone)
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default)]
+    pub prefix_hex_bytes: Option<usize>,
+}
+
+fn default_max_bytes() -> usize {
+    1024
+}
+
+/// Pre-encoded account data fields for one JSON event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDataJson {
+    /// `(encoding, encoded, truncated)`
+    pub data: Option<(DataEncoding, String, bool)>,
+    pub prefix_hex: Option<String>,
+}
+
+impl AccountDataJson {
+    pub fn new(cfg: &AccountDataCfg, data: &[u8]) -> Self {
+        let encoded = (cfg.max_bytes > 0).then(|| {
+            let take = &data[..data.len().min(cfg.max_bytes)];
+            let s = match cfg.encoding {
+                DataEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(take),
+                DataEncoding::Base58 => bs58::encode(take).into_string(),
+            };
+            (cfg.encoding, s, take.len() < data.len())
+        });
+        let prefix_hex = cfg.prefix_hex_bytes.map(|n| {
+            let take = &data[..data.len().min(n)];
+            let mut s = String::with_capacity(take.len() * 2);
+            for b in take {
+                let _ = write!(s, "{b:02x}");
+            }
+            s
+        });
+        Self {
+            data: encoded,
+            prefix_hex,
+        }
+    }
+
+    /// Number of JSON entries this adds to the account object.
+    pub fn entries(&self) -> usize {
+        self.data.as_ref().map_or(0, |_| 3) + usize::from(self.prefix_hex.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_and_adds_hex_prefix() {
+        let cfg: AccountDataCfg = serde_json::from_str(
+            r#"{"encoding": "base58", "max_bytes": 3, "prefix_hex_bytes": 2}"#,
+        )
+        .unwrap();
+        let out = AccountDataJson::new(&cfg, &[0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(
+            out.data,
+            Some((
+                DataEncoding::Base58,
+                bs58::encode([0xde, 0xad, 0xbe]).into_string(),
+                true
+            ))
+        );
+        assert_eq!(out.prefix_hex.as_deref(), Some("dead"));
+        assert_eq!(out.entries(), 4);
+
+        let cfg = AccountDataCfg {
+            encoding: DataEncoding::Base64,
+            max_bytes: 0,
+            prefix_hex_bytes: Some(16),
+        };
+        let out = AccountDataJson::new(&cfg, &[1, 2]);
+        assert_eq!(out.data, None);
+        assert_eq!(out.prefix_hex.as_deref(), Some("0102"));
+
+        let cfg = AccountDataCfg {
+            max_bytes: 1024,
+            ..cfg
+        };
+        let (_, b64, truncated) = AccountDataJson::new(&cfg, &[1, 2]).data.unwrap();
+        assert_eq!((b64.as_str(), truncated), ("AQI=", false));
+    }
+}
@@ -0,0 +1,274 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/admin.rs
+//
+// Admin HTTP API for runtime sink control, off unless configured:
+//
+//   "admin": {"bind": "127.0.0.1:9981", "token": "<secret>"}
+//
+//   GET  /sinks               every sink queue: depth, totals and per-second rates
+//   POST /sinks/:name/pause   drop whatever is sent to the sink until resumed
+//   POST /sinks/:name/resume
+//   POST /reload              re-read the config file and apply what can change live
+//
+// Pausing isolates a misbehaving downstream (say a Kafka cluster) without a
+// restart: records already queued still drain, new ones are counted as dropped
+// with reason `paused`. With `token` set, requests need `Authorization: Bearer`.
+use crate::producer_auth::constant_time_eq;
+use crate::sink_queue::{self, SinkStatus};
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{info, warn};
+
+const RATE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminCfg {
+    pub bind: String,
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Handed to whoever applies config reloads; answered once the reload is done.
+pub type ReloadReply = oneshot::Sender<Result<()>>;
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+struct Rates {
+    enqueued_per_sec: f64,
+    dropped_per_sec: f64,
+    errors_per_sec: f64,
+}
+
+#[derive(serde::Serialize)]
+struct SinkView {
+    #[serde(flatten)]
+    status: SinkStatus,
+    #[serde(flatten)]
+    rates: Rates,
+}
+
+struct AdminState {
+    token: Option<String>,
+    reload: mpsc::Sender<ReloadReply>,
+    rates: Mutex<HashMap<&'static str, Rates>>,
+}
+
+/// Serve the admin API on `cfg.bind`; returns the bound address.
+pub async fn spawn(cfg: &AdminCfg, reload: mpsc::Sender<ReloadReply>) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind(&cfg.bind)
+        .await
+        .with_context(|| format!("bind admin {}", cfg.bind))?;
+    let addr = listener.local_addr()?;
+    if cfg.token.is_none() && !addr.ip().is_loopback() {
+        warn!("admin API on {addr} has no token; anyone who can reach it can pause sinks");
+    }
+    let state = Arc::new(AdminState {
+        token: cfg.token.clone(),
+        reload,
+        rates: Mutex::new(HashMap::new()),
+    });
+    tokio::spawn(sample_rates(state.clone()));
+    let app = Router::new()
+        .route("/sinks", get(list_sinks))
+        .route("/sinks/:name/pause", post(pause_sink))
+        .route("/sinks/:name/resume", post(resume_sink))
+        .route("/reload", post(reload_config))
+        .with_state(state);
+    info!("admin API on http://{addr}");
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("admin server stopped: {e}");
+        }
+    });
+    Ok(addr)
+}
+
+// Rates are deltas of the queue totals over the last sampling interval.
+async fn sample_rates(state: Arc<AdminState>) {
+    let mut prev: HashMap<&'static str, (u64, u64, u64)> = HashMap::new();
+    let mut last = Instant::now();
+    let mut tick = tokio::time::interval(RATE_INTERVAL);
+    loop {
+        tick.tick().await;
+        let secs = last.elapsed().as_secs_f64().max(1e-3);
+        last = Instant::now();
+        let mut totals: HashMap<&'static str, (u64, u64, u64)> = HashMap::new();
+        for s in sink_queue::statuses() {
+            let t = totals.entry(s.sink).or_default();
+            t.0 += s.enqueued_total;
+            t.1 += s.dropped_total;
+            t.2 += s.errors_total;
+        }
+        let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / secs;
+        let rates = totals
+            .iter()
+            .map(|(sink, &(e, d, x))| {
+                let (pe, pd, px) = prev.get(sink).copied().unwrap_or((e, d, x));
+                let r = Rates {
+                    enqueued_per_sec: rate(e, pe),
+                    dropped_per_sec: rate(d, pd),
+                    errors_per_sec: rate(x, px),
+                };
+                (*sink, r)
+            })
+            .collect();
+        *state.rates.lock().unwrap() = rates;
+        prev = totals;
+    }
+}
+
+fn authorized(state: &AdminState, headers: &HeaderMap) -> bool {
+    let Some(token) = &state.token else {
+        return true;
+    };
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| constant_time_eq(v.as_bytes(), token.as_bytes()))
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": "missing or wrong bearer token"})),
+    )
+        .into_response()
+}
+
+async fn list_sinks(State(state): State<Arc<AdminState>>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return unauthorized();
+    }
+    let rates = state.rates.lock().unwrap().clone();
+    let sinks: Vec<SinkView> = sink_queue::statuses()
+        .into_iter()
+        .map(|status| SinkView {
+            rates: rates.get(status.sink).copied().unwrap_or_default(),
+            status,
+        })
+        .collect();
+    Json(json!({ "sinks": sinks })).into_response()
+}
+
+fn set_paused(state: &AdminState, headers: &HeaderMap, name: &str, paused: bool) -> Response {
+    if !authorized(state, headers) {
+        return unauthorized();
+    }
+    match sink_queue::set_paused(name, paused) {
+        Some(status) => {
+            info!(sink = name, paused, "sink state changed via admin API");
+            Json(status).into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": format!("no sink named {name}") })),
+        )
+            .into_response(),
+    }
+}
+
+async fn pause_sink(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    set_paused(&state, &headers, &name, true)
+}
+
+async fn resume_sink(
+    State(state): State<Arc<AdminState>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Response {
+    set_paused(&state, &headers, &name, false)
+}
+
+async fn reload_config(State(state): State<Arc<AdminState>>, headers: HeaderMap) -> Response {
+    if !authorized(&state, &headers) {
+        return unauthorized();
+    }
+    let (reply, done) = oneshot::channel();
+    let outcome = match state.reload.send(reply).await {
+        Ok(()) => done
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("reload dropped"))),
+        Err(_) => Err(anyhow::anyhow!("reload is not available")),
+    };
+    match outcome {
+        Ok(()) => Json(json!({"reloaded": true})).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"reloaded": false, "error": format!("{e:#}")})),
+        )
+            .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink_queue::QueueCfg;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn request(addr: SocketAddr, method: &str, path: &str, token: Option<&str>) -> String {
+        let mut sock = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let auth = token
+            .map(|t| format!("Authorization: Bearer {t}\r\n"))
+            .unwrap_or_default();
+        let req = format!(
+            "{method} {path} HTTP/1.1\r\nHost: admin\r\n{auth}Content-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        sock.write_all(req.as_bytes()).await.unwrap();
+        let mut resp = String::new();
+        sock.read_to_string(&mut resp).await.unwrap();
+        resp
+    }
+
+    #[tokio::test]
+    async fn pauses_sinks_and_forwards_reloads() {
+        let (tx, _rx) = sink_queue::queue::<u32>("admin_test", &QueueCfg::default());
+        let (reload_tx, mut reload_rx) = mpsc::channel::<ReloadReply>(1);
+        tokio::spawn(async move {
+            while let Some(reply) = reload_rx.recv().await {
+                let _ = reply.send(Ok(()));
+            }
+        });
+        let cfg = AdminCfg {
+            bind: "127.0.0.1:0".into(),
+            token: Some("s3cret".into()),
+        };
+        let addr = spawn(&cfg, reload_tx).await.unwrap();
+
+        let resp = request(addr, "GET", "/sinks", None).await;
+        assert!(resp.starts_with("HTTP/1.1 401"), "{resp}");
+
+        let resp = request(addr, "POST", "/sinks/admin_test/pause", Some("s3cret")).await;
+        assert!(resp.starts_with("HTTP/1.1 200"), "{resp}");
+        assert!(resp.contains(r#""paused":true"#), "{resp}");
+        assert!(!tx.send(1, "slot").await);
+
+        let resp = request(addr, "GET", "/sinks", Some("s3cret")).await;
+        assert!(resp.contains(r#""sink":"admin_test""#), "{resp}");
+        assert!(resp.contains(r#""dropped_total":1"#), "{resp}");
+
+        request(addr, "POST", "/sinks/admin_test/resume", Some("s3cret")).await;
+        assert!(tx.send(2, "slot").await);
+
+        let resp = request(addr, "POST", "/sinks/nope/pause", Some("s3cret")).await;
+        assert!(resp.starts_with("HTTP/1.1 404"), "{resp}");
+
+        let resp = request(addr, "POST", "/reload", Some("s3cret")).await;
+        assert!(resp.contains(r#""reloaded":true"#), "{resp}");
+    }
+}
@@ -0,0 +1,192 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/decode_pool.rs
+//
+// Optional decode worker pool, `"decode_workers": N`. Without it each UDS/TCP
+// connection task splits and decodes its own frames; with several producers and
+// rkyv off, bincode decode becomes the bottleneck. With it, the connection task
+// only validates headers and splits frames, and hands each read's worth of
+// whole frames to one of N decode threads. A connection is pinned to one
+// worker for its lifetime, so its records reach the output stage in the order
+// they were sent. SHM rings keep decoding on their own reader threads.
+//
+// Each worker's queue is bounded (`QUEUE_BATCHES`); a full queue stops the
+// connection reading, so backpressure reaches the producer's socket instead of
+// growing memory. Bodies that fail to decode on a worker are reported back to
+// the connection for its per-connection stats and quarantine.
+use crate::ingest::{decode_frame, forward, Ingested};
+use bytes::Bytes;
+use faststreams::FrameHeader;
+use metrics::{counter, gauge};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+const QUEUE_BATCHES: usize = 64;
+
+static POOL: OnceLock<DecodePool> = OnceLock::new();
+
+/// Whole, header-checked frames from one connection, decoded in order.
+struct DecodeJob {
+    frames: Bytes,
+    out: mpsc::Sender<Ingested>,
+    body_errors: Arc<AtomicU64>,
+}
+
+pub struct DecodePool {
+    workers: Vec<mpsc::Sender<DecodeJob>>,
+    next: AtomicUsize,
+}
+
+/// One connection's pinned worker.
+pub struct Lane {
+    tx: mpsc::Sender<DecodeJob>,
+    out: mpsc::Sender<Ingested>,
+    body_errors: Arc<AtomicU64>,
+}
+
+/// Start the process-wide pool; later calls are ignored.
+pub fn start(workers: usize) -> std::io::Result<()> {
+    if POOL.get().is_none() {
+        let pool = DecodePool::new(workers)?;
+        gauge!("ultra_decode_workers").set(pool.workers.len() as f64);
+        let _ = POOL.set(pool);
+    }
+    Ok(())
+}
+
+/// The process-wide pool, if `decode_workers` is set.
+pub fn get() -> Option<&'static DecodePool> {
+    POOL.get()
+}
+
+impl DecodePool {
+    pub fn new(workers: usize) -> std::io::Result<Self> {
+        let workers = (0..workers.max(1))
+            .map(|i| {
+                let (tx, rx) = mpsc::channel(QUEUE_BATCHES);
+                std::thread::Builder::new()
+                    .name(format!("ultra-decode-{i}"))
+                    .spawn(move || run_worker(rx))
+                    .map(|_| tx)
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(Self {
+            workers,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Pin a new connection, feeding `out`, to the next worker round-robin.
+    pub fn lane(&self, out: mpsc::Sender<Ingested>) -> Lane {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+        Lane {
+            tx: self.workers[i].clone(),
+            out,
+            body_errors: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Lane {
+    /// Queue a batch of whole frames, waiting while the worker is full.
+    pub async fn submit(&self, frames: Bytes) {
+        if frames.is_empty() {
+            return;
+        }
+        let job = DecodeJob {
+            frames,
+            out: self.out.clone(),
+            body_errors: self.body_errors.clone(),
+        };
+        if self.tx.send(job).await.is_err() {
+            counter!("ultra_decode_pool_dropped_total").increment(1);
+        }
+    }
+
+    /// Bodies that failed to decode since the last call.
+    pub fn take_body_errors(&self) -> u64 {
+        self.body_errors.swap(0, Ordering::Relaxed)
+    }
+}
+
+fn run_worker(mut rx: mpsc::Receiver<DecodeJob>) {
+    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+    while let Some(mut job) = rx.blocking_recv() {
+        let mut errors = 0;
+        while !job.frames.is_empty() {
+            // The connection already checked these headers (legacy included).
+            let hdr = match FrameHeader::parse_lenient(&job.frames) {
+                Ok(Some(hdr)) if hdr.frame_len() <= job.frames.len() => hdr,
+                _ => {
+                    warn!("decode worker got a truncated batch");
+                    break;
+                }
+            };
+            let frame = job.frames.split_to(hdr.frame_len());
+            match decode_frame(&hdr, &frame, &mut scratch) {
+                Some(rec) => forward(&job.out, rec),
+                None => errors += 1,
+            }
+        }
+        if errors > 0 {
+            job.body_errors.fetch_add(errors, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{encode_record, Record};
+
+    #[tokio::test]
+    async fn keeps_each_connection_in_order() {
+        let pool = DecodePool::new(2).unwrap();
+        let (out_a, mut rx_a) = mpsc::channel(1024);
+        let (out_b, mut rx_b) = mpsc::channel(1024);
+        let (a, b) = (pool.lane(out_a), pool.lane(out_b));
+
+        let batch = |from: u64| {
+            let mut frames = Vec::new();
+            for slot in from..from + 100 {
+                let rec = Record::Slot {
+                    slot,
+                    parent: None,
+                    status: 0,
+                };
+                frames.extend_from_slice(&encode_record(&rec).unwrap());
+            }
+            Bytes::from(frames)
+        };
+        for i in 0..5 {
+            a.submit(batch(i * 100)).await;
+            b.submit(batch(10_000 + i * 100)).await;
+        }
+        // A frame whose header is fine but whose body is not
+        let mut bad = batch(0)[..].to_vec();
+        let hdr = FrameHeader::parse(&bad).unwrap().unwrap();
+        bad.truncate(hdr.frame_len());
+        bad[faststreams::FRAME_HEADER_LEN..].fill(0xFF);
+        b.submit(Bytes::from(bad)).await;
+        let b_errors = b.body_errors.clone();
+        drop((a, b, pool));
+
+        let slots = |rx: &mut mpsc::Receiver<Ingested>| {
+            let mut v = Vec::new();
+            while let Some(i) = rx.blocking_recv() {
+                if let Record::Slot { slot, .. } = i.rec {
+                    v.push(slot);
+                }
+            }
+            v
+        };
+        let (a_slots, b_slots) =
+            tokio::task::spawn_blocking(move || (slots(&mut rx_a), slots(&mut rx_b)))
+                .await
+                .unwrap();
+        assert_eq!(a_slots, (0..500).collect::<Vec<_>>());
+        assert_eq!(b_slots, (10_000..10_500).collect::<Vec<_>>());
+        assert_eq!(b_errors.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,203 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/dedup.rs
+//
+// Drops records already seen on another input within `window_ms`, for setups
+// where several listeners carry redundant feeds (e.g. two validators). Shared by
+// every output stage. Keys are a hash of the kind plus:
+//
+//   account         pubkey, slot, lamports, owner and data (faststreams frames
+//                   carry no write_version, so the update contents stand in)
+//   tx              signature
+//   block           slot and blockhash
+//   slot            slot and status
+//   end_of_startup  nothing
+//
+// Per-input counters show which feed delivers first and how many duplicates
+// each one contributes.
+use crate::routing::record_kind;
+use faststreams::Record;
+use metrics::{counter, gauge, Counter};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const SHARDS: usize = 16;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DedupCfg {
+    #[serde(default = "default_window_ms")]
+    pub window_ms: u64,
+    /// Keys remembered across all inputs; the oldest are forgotten first
+    #[serde(default = "default_max_keys")]
+    pub max_keys: usize,
+}
+
+fn default_window_ms() -> u64 {
+    2_000
+}
+
+fn default_max_keys() -> usize {
+    1_000_000
+}
+
+fn record_key(rec: &Record) -> u64 {
+    let mut h = DefaultHasher::new();
+    record_kind(rec).hash(&mut h);
+    match rec {
+        Record::Account(a) => {
+            a.pubkey.hash(&mut h);
+            a.slot.hash(&mut h);
+            a.lamports.hash(&mut h);
+            a.owner.hash(&mut h);
+            a.data.hash(&mut h);
+        }
+        Record::Tx(t) => t.signature.hash(&mut h),
+        Record::Block(b) => {
+            b.slot.hash(&mut h);
+            b.blockhash.hash(&mut h);
+        }
+        Record::Slot { slot, status, .. } => {
+            slot.hash(&mut h);
+            status.hash(&mut h);
+        }
+        Record::EndOfStartup => {}
+    }
+    h.finish()
+}
+
+#[derive(Default)]
+struct Shard {
+    seen: HashMap<u64, Instant>,
+    order: VecDeque<(Instant, u64)>,
+}
+
+impl Shard {
+    fn expire(&mut self, cutoff: Option<Instant>, max: usize) {
+        while let Some(&(at, key)) = self.order.front() {
+            let expired = cutoff.is_some_and(|c| at <= c);
+            if !expired && self.order.len() < max {
+                break;
+            }
+            self.order.pop_front();
+            // A key re-inserted after expiring has a newer entry further back.
+            if self.seen.get(&key) == Some(&at) {
+                self.seen.remove(&key);
+            }
+        }
+    }
+}
+
+pub struct Deduper {
+    window: Duration,
+    max_per_shard: usize,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Deduper {
+    pub fn new(cfg: &DedupCfg) -> Self {
+        Self {
+            window: Duration::from_millis(cfg.window_ms),
+            max_per_shard: (cfg.max_keys / SHARDS).max(1),
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+        }
+    }
+
+    /// True the first time `rec` is seen within the window.
+    pub fn first_seen(&self, rec: &Record, now: Instant) -> bool {
+        let key = record_key(rec);
+        let mut shard = self.shards[key as usize % SHARDS].lock().unwrap();
+        let cutoff = now.checked_sub(self.window);
+        shard.expire(cutoff, self.max_per_shard);
+        if let Some(&at) = shard.seen.get(&key) {
+            if cutoff.is_none_or(|c| at > c) {
+                return false;
+            }
+        }
+        shard.seen.insert(key, now);
+        shard.order.push_back((now, key));
+        true
+    }
+}
+
+/// One input's view of the shared `Deduper`, with its own counters.
+pub struct SourceDedup {
+    dedup: Arc<Deduper>,
+    first: Counter,
+    duplicate: Counter,
+}
+
+impl SourceDedup {
+    pub fn new(dedup: Arc<Deduper>, source: &str) -> Self {
+        gauge!("ultra_dedup_window_ms").set(dedup.window.as_millis() as f64);
+        Self {
+            dedup,
+            first: counter!("ultra_dedup_first_seen_total", "source" => source.to_string()),
+            duplicate: counter!("ultra_dedup_duplicates_total", "source" => source.to_string()),
+        }
+    }
+
+    pub fn admit(&self, rec: &Record) -> bool {
+        let first = self.dedup.first_seen(rec, Instant::now());
+        if first {
+            self.first.increment(1);
+        } else {
+            self.duplicate.increment(1);
+        }
+        first
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::TxUpdate;
+
+    fn tx(sig: u8) -> Record {
+        Record::Tx(TxUpdate {
+            slot: 1,
+            signature: [sig; 64],
+            err: None,
+            vote: false,
+        })
+    }
+
+    #[test]
+    fn duplicates_within_window_are_dropped() {
+        let d = Deduper::new(&DedupCfg {
+            window_ms: 100,
+            max_keys: 1_000,
+        });
+        let t0 = Instant::now();
+        assert!(d.first_seen(&tx(1), t0));
+        assert!(!d.first_seen(&tx(1), t0 + Duration::from_millis(50)));
+        assert!(d.first_seen(&tx(2), t0 + Duration::from_millis(50)));
+        let slot = |status| Record::Slot {
+            slot: 9,
+            parent: None,
+            status,
+        };
+        assert!(d.first_seen(&slot(0), t0));
+        assert!(d.first_seen(&slot(1), t0), "status is part of the key");
+        // Past the window the same signature counts as new again.
+        assert!(d.first_seen(&tx(1), t0 + Duration::from_millis(150)));
+    }
+
+    #[test]
+    fn max_keys_forgets_oldest() {
+        let d = Deduper::new(&DedupCfg {
+            window_ms: 60_000,
+            max_keys: SHARDS,
+        });
+        let t0 = Instant::now();
+        // One key per shard at most: a second key in a shard evicts the first.
+        let keys: Vec<u8> = (0..=255u8)
+            .filter(|s| (record_key(&tx(*s)) as usize).is_multiple_of(SHARDS))
+            .take(2)
+            .collect();
+        assert!(d.first_seen(&tx(keys[0]), t0));
+        assert!(d.first_seen(&tx(keys[1]), t0));
+        assert!(d.first_seen(&tx(keys[0]), t0));
+    }
+}
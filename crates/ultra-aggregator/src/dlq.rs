@@ -0,0 +1,122 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/dlq.rs
+//
+// Local dead-letter directory for records a sink could not deliver. Files use
+// the ys-consumer DLQ layout (`dlq-<secs>-<nanos>-<seq>.fstr` holding one
+// faststreams frame, plus a `.meta` text sidecar), so
+// `ys-consumer replay-dlq --dir <dir>` can feed them back into the aggregator.
+use crate::routing::record_kind;
+use faststreams::{encode_record, Record};
+use metrics::counter;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+struct DlqEntry {
+    rec: Record,
+    reason: &'static str,
+    topic: String,
+    timestamp: SystemTime,
+}
+
+#[derive(Clone)]
+pub struct DlqWriter {
+    tx: tokio::sync::mpsc::Sender<DlqEntry>,
+}
+
+impl DlqWriter {
+    pub fn new(dir: PathBuf, capacity: usize) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<DlqEntry>(capacity.max(1));
+        std::thread::Builder::new()
+            .name("ultra-dlq".into())
+            .spawn(move || {
+                let mut seq: u64 = 0;
+                while let Some(entry) = rx.blocking_recv() {
+                    match write_entry(&dir, &entry, seq) {
+                        Ok(()) => counter!("ultra_dlq_written_total").increment(1),
+                        Err(e) => {
+                            counter!("ultra_dlq_write_errors_total").increment(1);
+                            warn!(
+                                "dlq write failed: {e} (reason={}, topic={})",
+                                entry.reason, entry.topic
+                            );
+                        }
+                    }
+                    seq = seq.wrapping_add(1);
+                }
+            })?;
+        Ok(Self { tx })
+    }
+
+    /// Queue `rec` for the DLQ directory; false (and counted) when the queue is full.
+    pub fn park(&self, rec: Record, reason: &'static str, topic: &str) -> bool {
+        let entry = DlqEntry {
+            rec,
+            reason,
+            topic: topic.to_string(),
+            timestamp: SystemTime::now(),
+        };
+        let ok = self.tx.try_send(entry).is_ok();
+        if !ok {
+            counter!("ultra_dlq_dropped_total").increment(1);
+        }
+        ok
+    }
+}
+
+fn write_entry(dir: &Path, entry: &DlqEntry, seq: u64) -> std::io::Result<()> {
+    let frame = encode_record(&entry.rec).map_err(std::io::Error::other)?;
+    let elapsed = entry
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    let base = format!(
+        "dlq-{}-{:09}-{:06}",
+        elapsed.as_secs(),
+        elapsed.subsec_nanos(),
+        seq
+    );
+    std::fs::write(dir.join(format!("{base}.fstr")), &frame)?;
+    let meta = format!(
+        "ts_unix={}\nreason={}\nkind={}\nframe_len={}\ntopic={}\n",
+        elapsed.as_secs_f64(),
+        entry.reason,
+        record_kind(&entry.rec),
+        frame.len(),
+        entry.topic
+    );
+    std::fs::write(dir.join(format!("{base}.meta")), meta)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::decode_record_from_slice;
+
+    #[test]
+    fn entries_use_the_ys_consumer_layout() {
+        let dir = std::env::temp_dir().join(format!("ultra-dlq-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let entry = DlqEntry {
+            rec: Record::Slot {
+                slot: 9,
+                parent: Some(8),
+                status: 1,
+            },
+            reason: "permanent",
+            topic: "slots".into(),
+            timestamp: UNIX_EPOCH + Duration::new(1_700_000_000, 5),
+        };
+        write_entry(&dir, &entry, 3).unwrap();
+        let base = dir.join("dlq-1700000000-000000005-000003");
+        let frame = std::fs::read(base.with_extension("fstr")).unwrap();
+        let mut scratch = Vec::new();
+        let (rec, used) = decode_record_from_slice(&frame, &mut scratch).unwrap();
+        assert_eq!(used, frame.len());
+        assert!(matches!(rec, Record::Slot { slot: 9, .. }));
+        let meta = std::fs::read_to_string(base.with_extension("meta")).unwrap();
+        assert!(meta.contains("reason=permanent\nkind=slot\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,621 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/es_sink.rs
+//
+// Elasticsearch/OpenSearch sink over the `_bulk` API. Documents are the stdout
+// JSON events, written to an index named by a template with `{kind}` and
+// `{date}` (UTC `YYYY.MM.DD` when the batch starts); `kind_indices` overrides
+// it per kind. Document ids come from the record, so a retried batch
+// overwrites instead of duplicating:
+//
+//   account  <pubkey>-<slot>, or <pubkey> with "account_id": "pubkey" (latest state)
+//   tx       <signature>
+//   block    <slot>
+//   slot     <slot>-<status>
+//
+//   "elasticsearch": {"url": "http://127.0.0.1:9200", "index": "ultra-{kind}-{date}",
+//                     "api_key": "...", "batch_max": 1000, "flush_ms": 1000}
+//
+// A batch goes out at `batch_max` documents, `batch_bytes`, or `flush_ms` after
+// its first record. 429 and 5xx answers, for the whole request or single items,
+// are retried with exponential backoff from `retry_backoff_ms` (or the server's
+// `Retry-After`) up to `max_retries` times; other item errors are dropped and
+// counted in `ultra_es_docs_failed_total{kind,reason}`.
+use crate::account_data::AccountDataCfg;
+use crate::routing::record_kind;
+use crate::{json_event_owned_from_record, sink_queue, write_json_event, Base58Cache};
+use anyhow::{bail, Context, Result};
+use faststreams::Record;
+use metrics::{counter, histogram};
+use reqwest::header::{CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+const KINDS: [&str; 4] = ["account", "tx", "block", "slot"];
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountId {
+    /// One document per account update
+    #[default]
+    PubkeySlot,
+    /// One document per account, overwritten by each update
+    Pubkey,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EsCfg {
+    /// Cluster base URL, e.g. `http://127.0.0.1:9200`
+    pub url: String,
+    /// Index name template with `{kind}` and `{date}`
+    #[serde(default = "default_index")]
+    pub index: String,
+    #[serde(default)]
+    pub kind_indices: HashMap<String, String>,
+    #[serde(default)]
+    pub account_id: AccountId,
+    /// Basic auth
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Sent as `Authorization: ApiKey <api_key>`
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub batch_max: Option<usize>,
+    #[serde(default)]
+    pub batch_bytes: Option<usize>,
+    #[serde(default)]
+    pub flush_ms: Option<u64>,
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+    #[serde(default)]
+    pub retry_backoff_ms: Option<u64>,
+}
+
+fn default_index() -> String {
+    "ultra-{kind}-{date}".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Lit(String),
+    Kind,
+    Date,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IndexName(Vec<Part>);
+
+impl IndexName {
+    fn parse(spec: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = spec;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Lit(rest[..open].to_string()));
+            }
+            let Some(close) = rest[open..].find('}') else {
+                bail!("unclosed placeholder in {spec}");
+            };
+            parts.push(match &rest[open + 1..open + close] {
+                "kind" => Part::Kind,
+                "date" => Part::Date,
+                other => bail!("unknown placeholder {{{other}}} in {spec}"),
+            });
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Lit(rest.to_string()));
+        }
+        Ok(Self(parts))
+    }
+
+    fn render(&self, kind: &str, date: &str, out: &mut String) {
+        out.clear();
+        for part in &self.0 {
+            match part {
+                Part::Lit(s) => out.push_str(s),
+                Part::Kind => out.push_str(kind),
+                Part::Date => out.push_str(date),
+            }
+        }
+    }
+}
+
+/// The default index template plus per-kind overrides.
+#[derive(Debug)]
+struct Indices {
+    default: IndexName,
+    per_kind: HashMap<&'static str, IndexName>,
+}
+
+impl Indices {
+    fn new(default: &str, per_kind: &HashMap<String, String>) -> Result<Self> {
+        let mut indices = Indices {
+            default: IndexName::parse(default)?,
+            per_kind: HashMap::new(),
+        };
+        for (kind, spec) in per_kind {
+            let Some(kind) = KINDS.iter().find(|k| *k == kind) else {
+                bail!("unknown record kind {kind} in elasticsearch kind_indices");
+            };
+            indices.per_kind.insert(kind, IndexName::parse(spec)?);
+        }
+        Ok(indices)
+    }
+
+    fn get(&self, kind: &str) -> &IndexName {
+        self.per_kind.get(kind).unwrap_or(&self.default)
+    }
+}
+
+/// UTC date as `YYYY.MM.DD`, the usual suffix of daily indices.
+fn utc_date() -> String {
+    let d = time::OffsetDateTime::now_utc().date();
+    format!("{:04}.{:02}.{:02}", d.year(), u8::from(d.month()), d.day())
+}
+
+/// One bulk item: the action line and the document line, each newline-terminated.
+#[derive(Debug, Clone)]
+struct Doc {
+    kind: &'static str,
+    lines: Vec<u8>,
+}
+
+struct Encoder {
+    indices: Indices,
+    account_id: AccountId,
+    account_data: Option<Arc<AccountDataCfg>>,
+    cache32: Base58Cache<32>,
+    cache64: Base58Cache<64>,
+    index: String,
+    id: String,
+}
+
+impl Encoder {
+    /// Document id for `rec`; `false` for records that are not indexed.
+    fn doc_id(&mut self, rec: &Record) -> bool {
+        self.id.clear();
+        match rec {
+            Record::Account(a) => {
+                self.id.push_str(&self.cache32.encode(&a.pubkey));
+                if self.account_id == AccountId::PubkeySlot {
+                    let _ = write!(self.id, "-{}", a.slot);
+                }
+            }
+            Record::Tx(t) => self.id.push_str(&self.cache64.encode(&t.signature)),
+            Record::Block(b) => {
+                let _ = write!(self.id, "{}", b.slot);
+            }
+            Record::Slot { slot, status, .. } => {
+                let _ = write!(self.id, "{slot}-{status}");
+            }
+            Record::EndOfStartup => return false,
+        }
+        true
+    }
+
+    /// The bulk item for `rec`, indexed into `index` if the route names one.
+    fn doc(&mut self, rec: &Record, index: Option<&str>, date: &str) -> Option<Doc> {
+        let kind = record_kind(rec);
+        if !self.doc_id(rec) {
+            return None;
+        }
+        match index {
+            Some(name) => {
+                self.index.clear();
+                self.index.push_str(name);
+            }
+            None => self.indices.get(kind).render(kind, date, &mut self.index),
+        }
+        let action = serde_json::json!({"index": {"_index": &self.index, "_id": &self.id}});
+        let mut lines = serde_json::to_vec(&action).ok()?;
+        lines.push(b'\n');
+        let evt = json_event_owned_from_record(rec, self.account_data.as_deref());
+        if write_json_event(&evt, &mut lines, &mut self.cache32, &mut self.cache64).is_err() {
+            counter!("ultra_es_encode_errors_total", "kind" => kind).increment(1);
+            return None;
+        }
+        lines.push(b'\n');
+        Some(Doc { kind, lines })
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BulkResponse {
+    errors: bool,
+    #[serde(default)]
+    items: Vec<HashMap<String, BulkItem>>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BulkItem {
+    status: u16,
+    #[serde(default)]
+    error: Option<BulkError>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct BulkError {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+fn retryable(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// Split `docs` by item outcome: docs to retry, and failed docs with the
+/// error type. Items line up with the request's documents.
+fn settle(resp: &BulkResponse, docs: Vec<Doc>) -> (Vec<Doc>, Vec<(&'static str, String)>) {
+    let mut retry = Vec::new();
+    let mut failed = Vec::new();
+    if !resp.errors {
+        return (retry, failed);
+    }
+    for (doc, item) in docs.into_iter().zip(&resp.items) {
+        let Some(item) = item.values().next() else {
+            continue;
+        };
+        if item.status < 300 {
+            continue;
+        }
+        if retryable(item.status) {
+            retry.push(doc);
+        } else {
+            let reason = item
+                .error
+                .as_ref()
+                .map_or_else(|| item.status.to_string(), |e| e.kind.clone());
+            failed.push((doc.kind, reason));
+        }
+    }
+    (retry, failed)
+}
+
+enum Auth {
+    Basic(String, Option<String>),
+    ApiKey(String),
+}
+
+enum Attempt {
+    Settled(BulkResponse),
+    Retry(&'static str, Option<Duration>),
+    Fail(String),
+}
+
+struct Bulk {
+    http: reqwest::Client,
+    url: String,
+    endpoint: String,
+    auth: Option<Auth>,
+    max_retries: u32,
+    backoff: Duration,
+}
+
+impl Bulk {
+    fn request(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.auth {
+            Some(Auth::Basic(user, pass)) => req.basic_auth(user, pass.as_ref()),
+            Some(Auth::ApiKey(key)) => req.header("Authorization", format!("ApiKey {key}")),
+            None => req,
+        }
+    }
+
+    /// Fail fast on a wrong URL or credentials; returns the server version.
+    async fn ping(&self) -> Result<String> {
+        let info: serde_json::Value = self
+            .request(self.http.get(&self.url))
+            .send()
+            .await
+            .with_context(|| format!("elasticsearch connect {}", self.url))?
+            .error_for_status()
+            .with_context(|| format!("elasticsearch {}", self.url))?
+            .json()
+            .await?;
+        Ok(info["version"]["number"]
+            .as_str()
+            .unwrap_or("unknown")
+            .to_string())
+    }
+
+    async fn post(&self, body: Vec<u8>) -> Attempt {
+        let req = self
+            .http
+            .post(&self.endpoint)
+            .header(CONTENT_TYPE, "application/x-ndjson")
+            .body(body);
+        let resp = match self.request(req).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                warn!("elasticsearch bulk request failed: {e}");
+                return Attempt::Retry("transport", None);
+            }
+        };
+        let status = resp.status();
+        if retryable(status.as_u16()) {
+            let after = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .map(Duration::from_secs);
+            let reason = if status == StatusCode::TOO_MANY_REQUESTS {
+                "429"
+            } else {
+                "5xx"
+            };
+            return Attempt::Retry(reason, after);
+        }
+        if !status.is_success() {
+            let mut text = resp.text().await.unwrap_or_default();
+            text.truncate(512);
+            return Attempt::Fail(format!("{status}: {text}"));
+        }
+        match resp.json().await {
+            Ok(r) => Attempt::Settled(r),
+            Err(e) => Attempt::Fail(format!("unreadable bulk response: {e}")),
+        }
+    }
+
+    /// Send `docs`, retrying what the cluster pushed back on.
+    async fn send(&self, mut docs: Vec<Doc>, errors: &sink_queue::SinkErrors) {
+        let t0 = Instant::now();
+        histogram!("ultra_es_bulk_docs").record(docs.len() as f64);
+        let mut attempt = 0u32;
+        loop {
+            let body = docs.iter().flat_map(|d| &d.lines).copied().collect();
+            let (reason, after) = match self.post(body).await {
+                Attempt::Settled(resp) => {
+                    let total = docs.len();
+                    let (retry, failed) = settle(&resp, docs);
+                    for (kind, reason) in &failed {
+                        counter!("ultra_es_docs_failed_total", "kind" => *kind, "reason" => reason.clone())
+                            .increment(1);
+                    }
+                    if !failed.is_empty() {
+                        errors.add(failed.len() as u64);
+                        warn!(failed = failed.len(), first = %failed[0].1, "elasticsearch rejected documents");
+                    }
+                    counter!("ultra_es_docs_indexed_total")
+                        .increment((total - retry.len() - failed.len()) as u64);
+                    if retry.is_empty() {
+                        histogram!("ultra_es_bulk_seconds").record(t0.elapsed().as_secs_f64());
+                        return;
+                    }
+                    docs = retry;
+                    ("item", None)
+                }
+                Attempt::Retry(reason, after) => (reason, after),
+                Attempt::Fail(e) => {
+                    error!(
+                        docs = docs.len(),
+                        "elasticsearch bulk request rejected: {e}"
+                    );
+                    self.give_up(&docs, "rejected", errors);
+                    return;
+                }
+            };
+            if attempt >= self.max_retries {
+                error!(
+                    docs = docs.len(),
+                    "elasticsearch bulk failed after {attempt} retries"
+                );
+                self.give_up(&docs, "retries_exhausted", errors);
+                return;
+            }
+            counter!("ultra_es_retries_total", "reason" => reason).increment(1);
+            let delay = after.unwrap_or_else(|| self.backoff.saturating_mul(1 << attempt.min(16)));
+            attempt += 1;
+            tokio::time::sleep(delay.min(MAX_BACKOFF)).await;
+        }
+    }
+
+    fn give_up(&self, docs: &[Doc], reason: &'static str, errors: &sink_queue::SinkErrors) {
+        for doc in docs {
+            counter!("ultra_es_docs_failed_total", "kind" => doc.kind, "reason" => reason)
+                .increment(1);
+        }
+        errors.add(docs.len() as u64);
+    }
+}
+
+#[derive(Clone)]
+pub struct EsSink {
+    tx: sink_queue::QueueTx<(Record, Option<Arc<str>>)>,
+}
+
+impl EsSink {
+    pub async fn new(
+        cfg: EsCfg,
+        queue: &sink_queue::QueueCfg,
+        account_data: Option<Arc<AccountDataCfg>>,
+    ) -> Result<Self> {
+        let indices = Indices::new(&cfg.index, &cfg.kind_indices)?;
+        let auth = match (cfg.api_key, cfg.username) {
+            (Some(_), Some(_)) => bail!("elasticsearch takes api_key or username, not both"),
+            (Some(key), None) => Some(Auth::ApiKey(key)),
+            (None, Some(user)) => Some(Auth::Basic(user, cfg.password)),
+            (None, None) => None,
+        };
+        let url = cfg.url.trim_end_matches('/').to_string();
+        let bulk = Bulk {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(60))
+                .build()?,
+            endpoint: format!("{url}/_bulk?filter_path=errors,items.*.status,items.*.error.type"),
+            url,
+            auth,
+            max_retries: cfg.max_retries.unwrap_or(8),
+            backoff: Duration::from_millis(cfg.retry_backoff_ms.unwrap_or(200)),
+        };
+        let version = bulk.ping().await?;
+        let batch_max = cfg.batch_max.unwrap_or(1_000).max(1);
+        let batch_bytes = cfg.batch_bytes.unwrap_or(5 * 1024 * 1024);
+        let flush = Duration::from_millis(cfg.flush_ms.unwrap_or(1_000));
+        info!(url = %bulk.url, %version, batch_max, ?flush, "elasticsearch sink ready");
+
+        let mut enc = Encoder {
+            indices,
+            account_id: cfg.account_id,
+            account_data,
+            cache32: Base58Cache::new(16_384),
+            cache64: Base58Cache::new(8_192),
+            index: String::new(),
+            id: String::new(),
+        };
+        let (tx, rx) = sink_queue::queue::<(Record, Option<Arc<str>>)>("elasticsearch", queue);
+        let errors = rx.errors();
+        tokio::spawn(async move {
+            let mut open = true;
+            while open {
+                let Some(first) = rx.recv().await else { break };
+                let date = utc_date();
+                let deadline = tokio::time::Instant::now() + flush;
+                let (mut batch, mut bytes) = (Vec::with_capacity(batch_max), 0usize);
+                let mut next = Some(first);
+                while let Some((rec, index)) = next.take() {
+                    if let Some(doc) = enc.doc(&rec, index.as_deref(), &date) {
+                        bytes += doc.lines.len();
+                        batch.push(doc);
+                    }
+                    if batch.len() >= batch_max || bytes >= batch_bytes {
+                        break;
+                    }
+                    next = tokio::select! {
+                        item = rx.recv() => {
+                            open = item.is_some();
+                            item
+                        }
+                        _ = tokio::time::sleep_until(deadline) => None,
+                    };
+                }
+                if !batch.is_empty() {
+                    bulk.send(batch, &errors).await;
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    /// `index` replaces the index template for this record.
+    pub async fn send(&self, rec: Record, index: Option<Arc<str>>) -> bool {
+        let kind = record_kind(&rec);
+        self.tx.send((rec, index), kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{AccountUpdate, TxUpdate};
+
+    fn encoder(account_id: AccountId) -> Encoder {
+        let mut per_kind = HashMap::new();
+        per_kind.insert("tx".to_string(), "txs-{date}".to_string());
+        Encoder {
+            indices: Indices::new(&default_index(), &per_kind).unwrap(),
+            account_id,
+            account_data: None,
+            cache32: Base58Cache::new(16),
+            cache64: Base58Cache::new(16),
+            index: String::new(),
+            id: String::new(),
+        }
+    }
+
+    fn action(doc: &Doc) -> serde_json::Value {
+        let line = doc.lines.split(|b| *b == b'\n').next().unwrap();
+        serde_json::from_slice(line).unwrap()
+    }
+
+    #[test]
+    fn derives_index_and_id_per_kind() {
+        let acct = Record::Account(AccountUpdate {
+            slot: 42,
+            is_startup: false,
+            pubkey: [1; 32],
+            lamports: 1,
+            owner: [2; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![],
+        });
+        let pubkey = bs58::encode([1u8; 32]).into_string();
+        let mut enc = encoder(AccountId::PubkeySlot);
+        let doc = enc.doc(&acct, None, "2024.02.29").unwrap();
+        assert_eq!(
+            action(&doc)["index"],
+            serde_json::json!({"_index": "ultra-account-2024.02.29", "_id": format!("{pubkey}-42")})
+        );
+        assert_eq!(doc.lines.iter().filter(|b| **b == b'\n').count(), 2);
+
+        let doc = encoder(AccountId::Pubkey)
+            .doc(&acct, Some("accounts"), "2024.02.29")
+            .unwrap();
+        assert_eq!(
+            action(&doc)["index"],
+            serde_json::json!({"_index": "accounts", "_id": pubkey})
+        );
+
+        let tx = Record::Tx(TxUpdate {
+            slot: 7,
+            signature: [3; 64],
+            err: None,
+            vote: false,
+        });
+        let doc = enc.doc(&tx, None, "2024.03.01").unwrap();
+        assert_eq!(
+            action(&doc)["index"],
+            serde_json::json!({"_index": "txs-2024.03.01", "_id": bs58::encode([3u8; 64]).into_string()})
+        );
+        let slot = Record::Slot {
+            slot: 9,
+            parent: None,
+            status: 2,
+        };
+        assert_eq!(
+            action(&enc.doc(&slot, None, "d").unwrap())["index"]["_id"],
+            "9-2"
+        );
+        assert!(enc.doc(&Record::EndOfStartup, None, "d").is_none());
+
+        assert!(IndexName::parse("x-{slot}").is_err());
+        let mut bad = HashMap::new();
+        bad.insert("accounts".to_string(), "a".to_string());
+        assert!(Indices::new("a", &bad).is_err());
+    }
+
+    #[test]
+    fn settles_items_into_retries_and_failures() {
+        let docs: Vec<Doc> = ["account", "tx", "block"]
+            .into_iter()
+            .map(|kind| Doc {
+                kind,
+                lines: Vec::new(),
+            })
+            .collect();
+        let resp: BulkResponse = serde_json::from_str(
+            r#"{"errors": true, "items": [
+                {"index": {"status": 201}},
+                {"index": {"status": 429, "error": {"type": "es_rejected_execution_exception"}}},
+                {"index": {"status": 400, "error": {"type": "mapper_parsing_exception"}}}
+            ]}"#,
+        )
+        .unwrap();
+        let (retry, failed) = settle(&resp, docs.clone());
+        assert_eq!(retry.iter().map(|d| d.kind).collect::<Vec<_>>(), ["tx"]);
+        assert_eq!(failed, [("block", "mapper_parsing_exception".to_string())]);
+
+        let ok: BulkResponse = serde_json::from_str(r#"{"errors": false}"#).unwrap();
+        let (retry, failed) = settle(&ok, docs);
+        assert!(retry.is_empty() && failed.is_empty());
+    }
+}
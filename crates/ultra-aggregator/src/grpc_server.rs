@@ -0,0 +1,1029 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/grpc_server.rs
+//
+// Yellowstone-compatible gRPC server: re-exposes the decoded stream with the
+// yellowstone-grpc `Geyser` service, so existing Yellowstone clients (and other
+// aggregators, via ys-consumer) can subscribe here instead of at the validator.
+//
+//   "grpc": {"bind": "0.0.0.0:10000", "x_token": "<secret>", "client_queue": 16384}
+//
+// `Subscribe` honours the filters the aggregator has data for:
+//
+//   accounts             account, owner, datasize, memcmp, lamports and
+//                        token_account_state filters
+//   slots                filter_by_commitment and interslot_updates
+//   transactions(_status) vote, failed and signature
+//   blocks_meta          every block record
+//   accounts_data_slice  applied to account data
+//
+// Records carry no transaction message/meta, account keys or full blocks, so
+// requests for `blocks`, `entry`, account-key transaction filters or
+// `from_slot` are rejected with INVALID_ARGUMENT.
+// Transaction updates carry the signature and vote flag; a failed transaction
+// has an empty `err`. Accounts get `write_version` from the arrival order here.
+// A client more than `client_queue` records behind is disconnected.
+use crate::sink_queue;
+use anyhow::{Context, Result};
+use faststreams::Record;
+use metrics::{counter, gauge};
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tracing::{info, warn};
+use yellowstone_grpc_proto::geyser::geyser_server::{Geyser, GeyserServer};
+use yellowstone_grpc_proto::geyser::subscribe_request_filter_accounts_filter::Filter as AccountsFilter;
+use yellowstone_grpc_proto::geyser::subscribe_request_filter_accounts_filter_lamports::Cmp;
+use yellowstone_grpc_proto::geyser::subscribe_request_filter_accounts_filter_memcmp::Data;
+use yellowstone_grpc_proto::geyser::subscribe_update::UpdateOneof;
+use yellowstone_grpc_proto::prelude::*;
+use yellowstone_grpc_proto::tonic::codec::CompressionEncoding;
+use yellowstone_grpc_proto::tonic::{self, Request, Response, Status, Streaming};
+
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// Blocks a blockhash stays valid for, as on the validator
+const BLOCKHASH_VALID_BLOCKS: u64 = 150;
+const RECENT_BLOCKHASHES: usize = 300;
+/// SPL token account layout, shared by Token-2022 before its extensions
+const TOKEN_ACCOUNT_LEN: usize = 165;
+const TOKEN_MULTISIG_LEN: usize = 355;
+/// Offset of the account `state` byte; 0 is uninitialized
+const TOKEN_STATE_OFFSET: usize = 108;
+/// Token-2022 `AccountType::Account`, stored right after the base layout
+const TOKEN_ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcCfg {
+    pub bind: String,
+    /// Required `x-token` metadata, as on Yellowstone endpoints
+    #[serde(default)]
+    pub x_token: Option<String>,
+    /// Records a client may fall behind before it is disconnected
+    #[serde(default)]
+    pub client_queue: Option<usize>,
+    #[serde(default)]
+    pub max_clients: Option<usize>,
+}
+
+// --- filters ---------------------------------------------------------------
+
+fn decode_key<const N: usize>(s: &str, what: &str) -> Result<[u8; N], Status> {
+    bs58::decode(s)
+        .into_vec()
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| Status::invalid_argument(format!("invalid {what} {s}")))
+}
+
+/// SPL Token and Token-2022, the owners of token accounts.
+fn token_programs() -> &'static [[u8; 32]; 2] {
+    static PROGRAMS: OnceLock<[[u8; 32]; 2]> = OnceLock::new();
+    PROGRAMS.get_or_init(|| {
+        [
+            "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+            "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+        ]
+        .map(|id| decode_key(id, "token program").expect("valid program id"))
+    })
+}
+
+/// An initialized token account, by the rules the token programs apply to
+/// their own data (`Account::valid_account_data`).
+fn is_token_account(a: &faststreams::AccountUpdate) -> bool {
+    if !token_programs().contains(&a.owner) {
+        return false;
+    }
+    let initialized = a.data.get(TOKEN_STATE_OFFSET).is_some_and(|s| *s != 0);
+    match a.data.len() {
+        TOKEN_ACCOUNT_LEN => initialized,
+        TOKEN_MULTISIG_LEN => false,
+        n if n > TOKEN_ACCOUNT_LEN => {
+            initialized && a.data[TOKEN_ACCOUNT_LEN] == TOKEN_ACCOUNT_TYPE_ACCOUNT
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug)]
+struct AccountRule {
+    name: String,
+    pubkeys: HashSet<[u8; 32]>,
+    owners: HashSet<[u8; 32]>,
+    datasize: Option<u64>,
+    memcmp: Vec<(usize, Vec<u8>)>,
+    lamports: Vec<Cmp>,
+    token_account: Option<bool>,
+}
+
+impl AccountRule {
+    fn new(name: &str, f: &SubscribeRequestFilterAccounts) -> Result<Self, Status> {
+        let mut rule = AccountRule {
+            name: name.to_string(),
+            pubkeys: f
+                .account
+                .iter()
+                .map(|s| decode_key(s, "account"))
+                .collect::<Result<_, _>>()?,
+            owners: f
+                .owner
+                .iter()
+                .map(|s| decode_key(s, "owner"))
+                .collect::<Result<_, _>>()?,
+            datasize: None,
+            memcmp: Vec::new(),
+            lamports: Vec::new(),
+            token_account: None,
+        };
+        for filter in f.filters.iter().filter_map(|f| f.filter.as_ref()) {
+            match filter {
+                AccountsFilter::Datasize(n) => rule.datasize = Some(*n),
+                AccountsFilter::Memcmp(m) => {
+                    let bytes = match &m.data {
+                        Some(Data::Bytes(b)) => b.clone(),
+                        Some(Data::Base58(s)) => bs58::decode(s)
+                            .into_vec()
+                            .map_err(|_| Status::invalid_argument("invalid base58 memcmp"))?,
+                        Some(Data::Base64(s)) => {
+                            use base64::Engine;
+                            base64::engine::general_purpose::STANDARD
+                                .decode(s)
+                                .map_err(|_| Status::invalid_argument("invalid base64 memcmp"))?
+                        }
+                        None => return Err(Status::invalid_argument("memcmp without data")),
+                    };
+                    rule.memcmp.push((m.offset as usize, bytes));
+                }
+                AccountsFilter::Lamports(l) => {
+                    if let Some(cmp) = l.cmp {
+                        rule.lamports.push(cmp);
+                    }
+                }
+                AccountsFilter::TokenAccountState(want) => rule.token_account = Some(*want),
+            }
+        }
+        Ok(rule)
+    }
+
+    fn matches(&self, a: &faststreams::AccountUpdate) -> bool {
+        (self.pubkeys.is_empty() || self.pubkeys.contains(&a.pubkey))
+            && (self.owners.is_empty() || self.owners.contains(&a.owner))
+            && self.datasize.is_none_or(|n| a.data.len() as u64 == n)
+            && self.memcmp.iter().all(|(off, bytes)| {
+                off.checked_add(bytes.len())
+                    .and_then(|end| a.data.get(*off..end))
+                    == Some(bytes.as_slice())
+            })
+            && self.lamports.iter().all(|cmp| match *cmp {
+                Cmp::Eq(v) => a.lamports == v,
+                Cmp::Ne(v) => a.lamports != v,
+                Cmp::Lt(v) => a.lamports < v,
+                Cmp::Gt(v) => a.lamports > v,
+            })
+            && self
+                .token_account
+                .is_none_or(|want| is_token_account(a) == want)
+    }
+}
+
+#[derive(Debug)]
+struct TxRule {
+    name: String,
+    vote: Option<bool>,
+    failed: Option<bool>,
+    signature: Option<[u8; 64]>,
+}
+
+impl TxRule {
+    fn new(name: &str, f: &SubscribeRequestFilterTransactions) -> Result<Self, Status> {
+        if !f.account_include.is_empty()
+            || !f.account_exclude.is_empty()
+            || !f.account_required.is_empty()
+        {
+            return Err(Status::invalid_argument(
+                "transaction account filters are not supported: records carry no account keys",
+            ));
+        }
+        Ok(TxRule {
+            name: name.to_string(),
+            vote: f.vote,
+            failed: f.failed,
+            signature: f
+                .signature
+                .as_deref()
+                .map(|s| decode_key(s, "signature"))
+                .transpose()?,
+        })
+    }
+
+    fn matches(&self, t: &faststreams::TxUpdate) -> bool {
+        self.vote.is_none_or(|v| v == t.vote)
+            && self.failed.is_none_or(|f| f == t.err.is_some())
+            && self.signature.is_none_or(|s| s == t.signature)
+    }
+}
+
+#[derive(Debug)]
+struct SlotRule {
+    name: String,
+    /// Only this status, when `filter_by_commitment` is set
+    status: Option<i32>,
+    interslot: bool,
+}
+
+#[derive(Debug, Default)]
+struct ClientFilter {
+    accounts: Vec<AccountRule>,
+    slots: Vec<SlotRule>,
+    transactions: Vec<TxRule>,
+    transactions_status: Vec<TxRule>,
+    blocks_meta: Vec<String>,
+    data_slice: Vec<(usize, usize)>,
+}
+
+fn names<'a, T: 'a>(rules: impl Iterator<Item = (&'a String, &'a T)>) -> Vec<String> {
+    rules.map(|(name, _)| name.clone()).collect()
+}
+
+impl ClientFilter {
+    fn new(req: &SubscribeRequest) -> Result<Self, Status> {
+        if !req.blocks.is_empty() {
+            return Err(Status::invalid_argument(
+                "full blocks are not available; subscribe to blocks_meta",
+            ));
+        }
+        if !req.entry.is_empty() {
+            return Err(Status::invalid_argument("entries are not available"));
+        }
+        if req.from_slot.is_some() {
+            return Err(Status::invalid_argument(
+                "from_slot replay is not supported",
+            ));
+        }
+        let commitment = req.commitment.unwrap_or(CommitmentLevel::Processed as i32);
+        Ok(ClientFilter {
+            accounts: req
+                .accounts
+                .iter()
+                .map(|(n, f)| AccountRule::new(n, f))
+                .collect::<Result<_, _>>()?,
+            slots: req
+                .slots
+                .iter()
+                .map(|(n, f)| SlotRule {
+                    name: n.clone(),
+                    status: f
+                        .filter_by_commitment
+                        .unwrap_or(false)
+                        .then_some(commitment),
+                    interslot: f.interslot_updates.unwrap_or(false),
+                })
+                .collect(),
+            transactions: req
+                .transactions
+                .iter()
+                .map(|(n, f)| TxRule::new(n, f))
+                .collect::<Result<_, _>>()?,
+            transactions_status: req
+                .transactions_status
+                .iter()
+                .map(|(n, f)| TxRule::new(n, f))
+                .collect::<Result<_, _>>()?,
+            blocks_meta: names(req.blocks_meta.iter()),
+            data_slice: req
+                .accounts_data_slice
+                .iter()
+                .map(|s| {
+                    s.offset
+                        .checked_add(s.length)
+                        .and_then(|end| usize::try_from(end).ok())
+                        .map(|_| (s.offset as usize, s.length as usize))
+                        .ok_or_else(|| {
+                            Status::invalid_argument(format!(
+                                "accounts_data_slice {}+{} is out of range",
+                                s.offset, s.length
+                            ))
+                        })
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn slice(&self, data: &[u8]) -> Vec<u8> {
+        if self.data_slice.is_empty() {
+            return data.to_vec();
+        }
+        let mut out = Vec::new();
+        for &(off, len) in &self.data_slice {
+            let start = off.min(data.len());
+            out.extend_from_slice(&data[start..off.saturating_add(len).min(data.len())]);
+        }
+        out
+    }
+
+    /// The updates `rec` produces for this client (none if nothing matches).
+    fn updates(&self, rec: &Record, write_version: u64, out: &mut Vec<SubscribeUpdate>) {
+        let created_at = Some(SystemTime::now().into());
+        let mut push = |filters: Vec<String>, update: UpdateOneof| {
+            if !filters.is_empty() {
+                out.push(SubscribeUpdate {
+                    filters,
+                    update_oneof: Some(update),
+                    created_at,
+                });
+            }
+        };
+        match rec {
+            Record::Account(a) => {
+                let filters: Vec<String> = self
+                    .accounts
+                    .iter()
+                    .filter(|r| r.matches(a))
+                    .map(|r| r.name.clone())
+                    .collect();
+                if filters.is_empty() {
+                    return;
+                }
+                push(
+                    filters,
+                    UpdateOneof::Account(SubscribeUpdateAccount {
+                        account: Some(SubscribeUpdateAccountInfo {
+                            pubkey: a.pubkey.to_vec(),
+                            lamports: a.lamports,
+                            owner: a.owner.to_vec(),
+                            executable: a.executable,
+                            rent_epoch: a.rent_epoch,
+                            data: self.slice(&a.data),
+                            write_version,
+                            txn_signature: None,
+                        }),
+                        slot: a.slot,
+                        is_startup: a.is_startup,
+                    }),
+                );
+            }
+            Record::Tx(t) => {
+                let matching = |rules: &[TxRule]| -> Vec<String> {
+                    rules
+                        .iter()
+                        .filter(|r| r.matches(t))
+                        .map(|r| r.name.clone())
+                        .collect()
+                };
+                push(
+                    matching(&self.transactions),
+                    UpdateOneof::Transaction(SubscribeUpdateTransaction {
+                        transaction: Some(SubscribeUpdateTransactionInfo {
+                            signature: t.signature.to_vec(),
+                            is_vote: t.vote,
+                            transaction: None,
+                            meta: None,
+                            index: 0,
+                        }),
+                        slot: t.slot,
+                    }),
+                );
+                push(
+                    matching(&self.transactions_status),
+                    UpdateOneof::TransactionStatus(SubscribeUpdateTransactionStatus {
+                        slot: t.slot,
+                        signature: t.signature.to_vec(),
+                        is_vote: t.vote,
+                        index: 0,
+                        err: t.err.as_ref().map(|_| TransactionError::default()),
+                    }),
+                );
+            }
+            Record::Block(b) => push(
+                self.blocks_meta.clone(),
+                UpdateOneof::BlockMeta(SubscribeUpdateBlockMeta {
+                    slot: b.slot,
+                    blockhash: b
+                        .blockhash
+                        .map(|h| bs58::encode(h).into_string())
+                        .unwrap_or_default(),
+                    rewards: None,
+                    block_time: b
+                        .block_time_unix
+                        .map(|timestamp| UnixTimestamp { timestamp }),
+                    block_height: b
+                        .block_height
+                        .map(|block_height| BlockHeight { block_height }),
+                    parent_slot: b.parent_slot.unwrap_or_default(),
+                    parent_blockhash: String::new(),
+                    executed_transaction_count: 0,
+                    entries_count: 0,
+                }),
+            ),
+            Record::Slot {
+                slot,
+                parent,
+                status,
+            } => {
+                let status = i32::from(*status);
+                let filters = self
+                    .slots
+                    .iter()
+                    .filter(|r| r.status.is_none_or(|s| s == status))
+                    .filter(|r| r.interslot || status <= SlotStatus::SlotFinalized as i32)
+                    .map(|r| r.name.clone())
+                    .collect();
+                push(
+                    filters,
+                    UpdateOneof::Slot(SubscribeUpdateSlot {
+                        slot: *slot,
+                        parent: *parent,
+                        status,
+                        dead_error: None,
+                    }),
+                );
+            }
+            Record::EndOfStartup => {}
+        }
+    }
+}
+
+// --- chain state for the unary calls ---------------------------------------
+
+#[derive(Debug, Default)]
+struct Chain {
+    /// Highest slot seen per commitment (processed, confirmed, finalized)
+    slots: [u64; 3],
+    block_height: u64,
+    latest: Option<(u64, String)>,
+    /// (blockhash, block_height), oldest first
+    recent: VecDeque<(String, u64)>,
+}
+
+impl Chain {
+    fn observe(&mut self, rec: &Record) {
+        match rec {
+            Record::Slot { slot, status, .. } => {
+                if let Some(s) = self.slots.get_mut(usize::from(*status)) {
+                    *s = (*s).max(*slot);
+                }
+            }
+            Record::Block(b) => {
+                let (Some(hash), Some(height)) = (b.blockhash, b.block_height) else {
+                    return;
+                };
+                if height < self.block_height {
+                    return;
+                }
+                let hash = bs58::encode(hash).into_string();
+                self.block_height = height;
+                self.latest = Some((b.slot, hash.clone()));
+                self.recent.push_back((hash, height));
+                if self.recent.len() > RECENT_BLOCKHASHES {
+                    self.recent.pop_front();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn slot(&self, commitment: Option<i32>) -> u64 {
+        let idx = commitment.unwrap_or(0).clamp(0, 2) as usize;
+        self.slots[idx]
+    }
+}
+
+// --- service ---------------------------------------------------------------
+
+struct Shared {
+    events: broadcast::Sender<Arc<(Record, u64)>>,
+    chain: Mutex<Chain>,
+    clients: AtomicUsize,
+    max_clients: usize,
+}
+
+struct Service(Arc<Shared>);
+
+/// One of `max_clients`, released when the client's task ends, panics or is dropped.
+struct ClientSlot(Arc<Shared>);
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        self.0.clients.fetch_sub(1, Ordering::Relaxed);
+        gauge!("ultra_grpc_clients").decrement(1.0);
+    }
+}
+
+#[derive(Clone)]
+pub struct GrpcSink {
+    tx: sink_queue::QueueTx<Record>,
+}
+
+impl GrpcSink {
+    pub async fn new(cfg: GrpcCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
+        let (events, _) = broadcast::channel(cfg.client_queue.unwrap_or(16_384).max(1));
+        let shared = Arc::new(Shared {
+            events,
+            chain: Mutex::new(Chain::default()),
+            clients: AtomicUsize::new(0),
+            max_clients: cfg.max_clients.unwrap_or(256),
+        });
+
+        let (tx, rx) = sink_queue::queue::<Record>("grpc", queue);
+        let feed = shared.clone();
+        tokio::spawn(async move {
+            let write_version = AtomicU64::new(0);
+            while let Some(rec) = rx.recv().await {
+                feed.chain.lock().unwrap().observe(&rec);
+                if feed.events.receiver_count() == 0 {
+                    continue;
+                }
+                let wv = write_version.fetch_add(1, Ordering::Relaxed);
+                let _ = feed.events.send(Arc::new((rec, wv)));
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind(&cfg.bind)
+            .await
+            .with_context(|| format!("bind grpc {}", cfg.bind))?;
+        info!("yellowstone gRPC on {}", listener.local_addr()?);
+        let token = cfg.x_token.clone();
+        let svc = GeyserServer::new(Service(shared))
+            .accept_compressed(CompressionEncoding::Gzip)
+            .accept_compressed(CompressionEncoding::Zstd)
+            .send_compressed(CompressionEncoding::Gzip)
+            .send_compressed(CompressionEncoding::Zstd);
+        let svc =
+            tonic::service::interceptor::InterceptedService::new(svc, move |req: Request<()>| {
+                match &token {
+                    Some(t)
+                        if req
+                            .metadata()
+                            .get("x-token")
+                            .is_none_or(|v| v != t.as_str()) =>
+                    {
+                        Err(Status::unauthenticated("invalid x-token"))
+                    }
+                    _ => Ok(req),
+                }
+            });
+        tokio::spawn(async move {
+            if let Err(e) = tonic::transport::Server::builder()
+                .add_service(svc)
+                .serve_with_incoming(TcpListenerStream::new(listener))
+                .await
+            {
+                warn!("grpc server stopped: {e}");
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    pub async fn send(&self, rec: Record, kind: &'static str) -> bool {
+        self.tx.send(rec, kind).await
+    }
+}
+
+type Updates = ReceiverStream<Result<SubscribeUpdate, Status>>;
+
+#[tonic::async_trait]
+impl Geyser for Service {
+    type SubscribeStream = Updates;
+
+    async fn subscribe(
+        &self,
+        request: Request<Streaming<SubscribeRequest>>,
+    ) -> Result<Response<Updates>, Status> {
+        let shared = self.0.clone();
+        if shared.clients.fetch_add(1, Ordering::Relaxed) >= shared.max_clients {
+            shared.clients.fetch_sub(1, Ordering::Relaxed);
+            counter!("ultra_grpc_rejected_total").increment(1);
+            return Err(Status::resource_exhausted("too many clients"));
+        }
+        gauge!("ultra_grpc_clients").increment(1.0);
+        let events = shared.events.subscribe();
+        let slot = ClientSlot(shared);
+        let (tx, rx) = mpsc::channel(1024);
+        tokio::spawn(serve_client(request.into_inner(), events, tx, slot));
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn subscribe_replay_info(
+        &self,
+        _request: Request<SubscribeReplayInfoRequest>,
+    ) -> Result<Response<SubscribeReplayInfoResponse>, Status> {
+        Ok(Response::new(SubscribeReplayInfoResponse {
+            first_available: None,
+        }))
+    }
+
+    async fn ping(&self, request: Request<PingRequest>) -> Result<Response<PongResponse>, Status> {
+        Ok(Response::new(PongResponse {
+            count: request.into_inner().count,
+        }))
+    }
+
+    async fn get_latest_blockhash(
+        &self,
+        _request: Request<GetLatestBlockhashRequest>,
+    ) -> Result<Response<GetLatestBlockhashResponse>, Status> {
+        let chain = self.0.chain.lock().unwrap();
+        let Some((slot, blockhash)) = chain.latest.clone() else {
+            return Err(Status::unavailable("no block seen yet"));
+        };
+        Ok(Response::new(GetLatestBlockhashResponse {
+            slot,
+            blockhash,
+            last_valid_block_height: chain.block_height + BLOCKHASH_VALID_BLOCKS,
+        }))
+    }
+
+    async fn get_block_height(
+        &self,
+        _request: Request<GetBlockHeightRequest>,
+    ) -> Result<Response<GetBlockHeightResponse>, Status> {
+        Ok(Response::new(GetBlockHeightResponse {
+            block_height: self.0.chain.lock().unwrap().block_height,
+        }))
+    }
+
+    async fn get_slot(
+        &self,
+        request: Request<GetSlotRequest>,
+    ) -> Result<Response<GetSlotResponse>, Status> {
+        let commitment = request.into_inner().commitment;
+        Ok(Response::new(GetSlotResponse {
+            slot: self.0.chain.lock().unwrap().slot(commitment),
+        }))
+    }
+
+    async fn is_blockhash_valid(
+        &self,
+        request: Request<IsBlockhashValidRequest>,
+    ) -> Result<Response<IsBlockhashValidResponse>, Status> {
+        let req = request.into_inner();
+        let chain = self.0.chain.lock().unwrap();
+        let valid = chain.recent.iter().any(|(h, height)| {
+            *h == req.blockhash && height + BLOCKHASH_VALID_BLOCKS >= chain.block_height
+        });
+        Ok(Response::new(IsBlockhashValidResponse {
+            slot: chain.slot(req.commitment),
+            valid,
+        }))
+    }
+
+    async fn get_version(
+        &self,
+        _request: Request<GetVersionRequest>,
+    ) -> Result<Response<GetVersionResponse>, Status> {
+        Ok(Response::new(GetVersionResponse {
+            version: serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "source": "ultra-aggregator",
+            })
+            .to_string(),
+        }))
+    }
+}
+
+async fn serve_client(
+    mut requests: Streaming<SubscribeRequest>,
+    mut events: broadcast::Receiver<Arc<(Record, u64)>>,
+    tx: mpsc::Sender<Result<SubscribeUpdate, Status>>,
+    _slot: ClientSlot,
+) {
+    let mut filter = ClientFilter::default();
+    let mut requests_open = true;
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut out = Vec::new();
+    'client: loop {
+        tokio::select! {
+            req = requests.message(), if requests_open => match req {
+                Ok(Some(req)) => {
+                    if let Some(p) = req.ping {
+                        let pong = SubscribeUpdate {
+                            filters: Vec::new(),
+                            update_oneof: Some(UpdateOneof::Pong(SubscribeUpdatePong { id: p.id })),
+                            created_at: Some(SystemTime::now().into()),
+                        };
+                        if tx.send(Ok(pong)).await.is_err() {
+                            break;
+                        }
+                        // A keep-alive ping alone leaves the filters in place.
+                        if req.accounts.is_empty()
+                            && req.slots.is_empty()
+                            && req.transactions.is_empty()
+                            && req.transactions_status.is_empty()
+                            && req.blocks_meta.is_empty()
+                        {
+                            continue;
+                        }
+                    }
+                    match ClientFilter::new(&req) {
+                        Ok(f) => filter = f,
+                        Err(status) => {
+                            let _ = tx.send(Err(status)).await;
+                            break;
+                        }
+                    }
+                }
+                Ok(None) => requests_open = false,
+                Err(_) => break,
+            },
+            ev = events.recv() => match ev {
+                Ok(ev) => {
+                    filter.updates(&ev.0, ev.1, &mut out);
+                    for update in out.drain(..) {
+                        if tx.send(Ok(update)).await.is_err() {
+                            break 'client;
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    counter!("ultra_grpc_slow_disconnects_total").increment(1);
+                    let _ = tx
+                        .send(Err(Status::data_loss(format!(
+                            "client fell {missed} records behind"
+                        ))))
+                        .await;
+                    break;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = ping.tick() => {
+                let ping = SubscribeUpdate {
+                    filters: Vec::new(),
+                    update_oneof: Some(UpdateOneof::Ping(SubscribeUpdatePing {})),
+                    created_at: Some(SystemTime::now().into()),
+                };
+                if tx.send(Ok(ping)).await.is_err() {
+                    break;
+                }
+            }
+            _ = tx.closed() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{AccountUpdate, TxUpdate};
+    use yellowstone_grpc_proto::geyser::geyser_client::GeyserClient;
+
+    fn account(owner: u8, lamports: u64) -> Record {
+        Record::Account(AccountUpdate {
+            slot: 9,
+            is_startup: false,
+            pubkey: [7; 32],
+            lamports,
+            owner: [owner; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![1, 2, 3, 4],
+        })
+    }
+
+    #[test]
+    fn filters_match_like_yellowstone() {
+        let mut req = SubscribeRequest::default();
+        req.accounts.insert(
+            "acc".into(),
+            SubscribeRequestFilterAccounts {
+                owner: vec![bs58::encode([1u8; 32]).into_string()],
+                filters: vec![SubscribeRequestFilterAccountsFilter {
+                    filter: Some(AccountsFilter::Memcmp(
+                        SubscribeRequestFilterAccountsFilterMemcmp {
+                            offset: 1,
+                            data: Some(Data::Bytes(vec![2, 3])),
+                        },
+                    )),
+                }],
+                ..Default::default()
+            },
+        );
+        req.transactions_status.insert(
+            "failed".into(),
+            SubscribeRequestFilterTransactions {
+                failed: Some(true),
+                ..Default::default()
+            },
+        );
+        req.accounts_data_slice
+            .push(SubscribeRequestAccountsDataSlice {
+                offset: 2,
+                length: 10,
+            });
+        let f = ClientFilter::new(&req).unwrap();
+        let mut out = Vec::new();
+        f.updates(&account(1, 5), 3, &mut out);
+        f.updates(&account(2, 5), 4, &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].filters, ["acc"]);
+        let Some(UpdateOneof::Account(a)) = &out[0].update_oneof else {
+            panic!("expected an account update");
+        };
+        let info = a.account.as_ref().unwrap();
+        assert_eq!(
+            (info.data.as_slice(), info.write_version),
+            (&[3u8, 4][..], 3)
+        );
+
+        out.clear();
+        let tx = |err: Option<&str>| {
+            Record::Tx(TxUpdate {
+                slot: 1,
+                signature: [1; 64],
+                err: err.map(str::to_string),
+                vote: false,
+            })
+        };
+        f.updates(&tx(None), 0, &mut out);
+        f.updates(&tx(Some("boom")), 0, &mut out);
+        assert_eq!(out.len(), 1);
+        assert!(matches!(
+            &out[0].update_oneof,
+            Some(UpdateOneof::TransactionStatus(s)) if s.err.is_some()
+        ));
+
+        req.blocks.insert("b".into(), Default::default());
+        assert!(ClientFilter::new(&req).is_err());
+    }
+
+    #[test]
+    fn token_account_state_matches_the_ys_consumer_mint_filter() {
+        let token =
+            decode_key::<32>("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA", "owner").unwrap();
+        let mint = [5u8; 32];
+        // What ys-consumer's `YS_MINTS` sends for one mint.
+        let mut req = SubscribeRequest::default();
+        req.accounts.insert(
+            "mint".into(),
+            SubscribeRequestFilterAccounts {
+                owner: vec![
+                    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".into(),
+                    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb".into(),
+                ],
+                filters: vec![
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(AccountsFilter::Memcmp(
+                            SubscribeRequestFilterAccountsFilterMemcmp {
+                                offset: 0,
+                                data: Some(Data::Bytes(mint.to_vec())),
+                            },
+                        )),
+                    },
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(AccountsFilter::TokenAccountState(true)),
+                    },
+                ],
+                ..Default::default()
+            },
+        );
+        let f = ClientFilter::new(&req).unwrap();
+        let account = |len: usize, state: u8| {
+            let mut data = vec![0u8; len];
+            data[..32].copy_from_slice(&mint);
+            data[TOKEN_STATE_OFFSET] = state;
+            Record::Account(AccountUpdate {
+                slot: 1,
+                is_startup: false,
+                pubkey: [7; 32],
+                lamports: 1,
+                owner: token,
+                executable: false,
+                rent_epoch: 0,
+                data,
+            })
+        };
+        let matched = |rec: Record| {
+            let mut out = Vec::new();
+            f.updates(&rec, 0, &mut out);
+            !out.is_empty()
+        };
+        assert!(matched(account(TOKEN_ACCOUNT_LEN, 1)));
+        assert!(!matched(account(TOKEN_ACCOUNT_LEN, 0)), "uninitialized");
+        assert!(!matched(account(TOKEN_MULTISIG_LEN, 1)), "multisig");
+        let mut with_extensions = account(TOKEN_ACCOUNT_LEN + 8, 1);
+        assert!(!matched(with_extensions.clone()), "no account type");
+        if let Record::Account(a) = &mut with_extensions {
+            a.data[TOKEN_ACCOUNT_LEN] = TOKEN_ACCOUNT_TYPE_ACCOUNT;
+        }
+        assert!(matched(with_extensions));
+    }
+
+    #[tokio::test]
+    async fn serves_yellowstone_clients() {
+        // The server logs its address but does not return it; pick a free port.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let cfg = GrpcCfg {
+            bind: format!("127.0.0.1:{port}"),
+            x_token: None,
+            client_queue: None,
+            max_clients: None,
+        };
+        let sink = GrpcSink::new(cfg, &sink_queue::QueueCfg::default())
+            .await
+            .unwrap();
+        let mut client = GeyserClient::connect(format!("http://127.0.0.1:{port}"))
+            .await
+            .unwrap();
+
+        let mut req = SubscribeRequest::default();
+        req.slots.insert("s".into(), Default::default());
+        let (req_tx, req_rx) = mpsc::channel(4);
+        req_tx.send(req).await.unwrap();
+        let mut stream = client
+            .subscribe(ReceiverStream::new(req_rx))
+            .await
+            .unwrap()
+            .into_inner();
+        // The first update is the server's ping; wait until the filter is in place.
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        let slot = loop {
+            assert!(tokio::time::Instant::now() < deadline);
+            sink.send(
+                Record::Slot {
+                    slot: 42,
+                    parent: Some(41),
+                    status: 0,
+                },
+                "slot",
+            )
+            .await;
+            let msg = tokio::time::timeout(Duration::from_millis(200), stream.message()).await;
+            if let Ok(Ok(Some(SubscribeUpdate {
+                update_oneof: Some(UpdateOneof::Slot(s)),
+                filters,
+                ..
+            }))) = msg
+            {
+                assert_eq!(filters, ["s"]);
+                break s;
+            }
+        };
+        assert_eq!((slot.slot, slot.parent), (42, Some(41)));
+        let got = client.get_slot(GetSlotRequest::default()).await.unwrap();
+        assert_eq!(got.into_inner().slot, 42);
+    }
+
+    #[tokio::test]
+    async fn rejected_data_slice_frees_the_client_slot() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let cfg = GrpcCfg {
+            bind: format!("127.0.0.1:{port}"),
+            x_token: None,
+            client_queue: None,
+            max_clients: Some(1),
+        };
+        let _sink = GrpcSink::new(cfg, &sink_queue::QueueCfg::default())
+            .await
+            .unwrap();
+        let mut client = GeyserClient::connect(format!("http://127.0.0.1:{port}"))
+            .await
+            .unwrap();
+
+        let mut req = SubscribeRequest::default();
+        req.accounts_data_slice
+            .push(SubscribeRequestAccountsDataSlice {
+                offset: u64::MAX,
+                length: 8,
+            });
+        let mut stream = client
+            .subscribe(tokio_stream::iter([req]))
+            .await
+            .unwrap()
+            .into_inner();
+        let status = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match stream.message().await {
+                    Ok(Some(_)) => continue,
+                    Ok(None) => panic!("stream ended without an error"),
+                    Err(status) => break status,
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        // The only slot comes back once the rejected client's task is gone.
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let req = SubscribeRequest::default();
+            match client.subscribe(tokio_stream::iter([req])).await {
+                Ok(_) => break,
+                Err(status) => {
+                    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+                    assert!(tokio::time::Instant::now() < deadline);
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,511 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/ingest.rs
+//
+// Producer connections: peel faststreams frames off a byte stream and hand the
+// decoded records to the shard's output stage. Headers are parsed with
+// `faststreams::FrameHeader`; with `accept_legacy_frames` the reader also takes
+// frames from producers that predate header checksums, so producers can be
+// upgraded one at a time. The header has no magic marker, so on a bad header
+// the reader drops one byte and rescans; a contiguous run of skipped bytes is
+// one resync event (`ultra_resync_events_total`), and the bytes themselves are
+// counted in `ultra_resync_skipped_bytes_total`. A producer's origin timestamp, when the
+// frame carries one, travels with the record for the lag metrics. Auth frames
+// past the handshake (or on listeners without a token) carry no record and are
+// skipped, so they never count as decode errors.
+//
+// Decode outcomes are also counted per connection (`ultra_conn_frames_total{conn}`,
+// `ultra_conn_decode_errors_total{conn,reason}` with reason `bad_header`,
+// `oversize` or `body`, one per garbage run) and skipped bytes
+// (`ultra_conn_skipped_bytes_total{conn}`). `conn` names the producer's source (UDS path, TCP
+// peer IP or shm ring) rather than the connection, so reconnecting producers
+// keep their series instead of adding new ones. With `"quarantine": {"max_error_ratio": ...}` a
+// connection whose errors exceed that share of everything it sent (after
+// `min_events`; every header's worth of skipped bytes weighs one error) is disconnected, so one broken producer cannot flood the shared
+// output stage with resyncs and garbage.
+use bytes::{Buf, BytesMut};
+#[cfg(feature = "rkyv")]
+use faststreams::{decode_record_archived_trusted_from_slice, FLAG_LZ4, FLAG_RKYV};
+use faststreams::{decode_record_body, FrameHeader, Record, FRAME_HEADER_LEN};
+use metrics::{counter, histogram, Counter};
+#[cfg(feature = "rkyv")]
+use rkyv::de::deserializers::SharedDeserializeMap;
+#[cfg(feature = "rkyv")]
+use rkyv::Deserialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::warn;
+
+static INGEST_SEQ: AtomicU64 = AtomicU64::new(0);
+const INGEST_SAMPLE_MASK: u64 = 0xFF; // sample ~1/256
+const INGEST_SAMPLE_WEIGHT: u64 = 256;
+
+pub(crate) static RESYNC_EVENTS_THIS_MINUTE: AtomicU64 = AtomicU64::new(0);
+
+/// A decoded record and the producer's origin timestamp (unix nanos), if stamped.
+#[derive(Debug)]
+pub struct Ingested {
+    pub rec: Record,
+    pub origin_ns: Option<u64>,
+}
+
+impl std::borrow::Borrow<Record> for Ingested {
+    fn borrow(&self) -> &Record {
+        &self.rec
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameLimits {
+    pub max_frame_bytes: usize,
+    pub accept_legacy: bool,
+    pub quarantine: Option<QuarantineCfg>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuarantineCfg {
+    /// Disconnect when errors / (frames + errors) exceeds this
+    pub max_error_ratio: f64,
+    /// Frames plus errors seen before the ratio is enforced
+    #[serde(default = "default_min_events")]
+    pub min_events: u64,
+}
+
+fn default_min_events() -> u64 {
+    1_000
+}
+
+/// Decode outcomes on one producer connection.
+pub struct ConnStats {
+    frames: u64,
+    bad_headers: u64,
+    oversize: u64,
+    body_errors: u64,
+    skipped_bytes: u64,
+    /// Whether the last byte looked at was skipped, so the run continues
+    in_garbage: bool,
+    published: [u64; 5],
+    counters: [Counter; 5],
+}
+
+impl ConnStats {
+    pub fn new(conn: &str) -> Self {
+        let errors = |reason: &'static str| counter!("ultra_conn_decode_errors_total", "conn" => conn.to_string(), "reason" => reason);
+        Self {
+            frames: 0,
+            bad_headers: 0,
+            oversize: 0,
+            body_errors: 0,
+            skipped_bytes: 0,
+            in_garbage: false,
+            published: [0; 5],
+            counters: [
+                counter!("ultra_conn_frames_total", "conn" => conn.to_string()),
+                errors("bad_header"),
+                errors("oversize"),
+                errors("body"),
+                counter!("ultra_conn_skipped_bytes_total", "conn" => conn.to_string()),
+            ],
+        }
+    }
+
+    fn errors(&self) -> u64 {
+        self.bad_headers + self.oversize + self.body_errors
+    }
+
+    /// Push what changed since the last call to the metrics; called once per
+    /// read rather than per frame.
+    pub fn publish(&mut self) {
+        let now = [
+            self.frames,
+            self.bad_headers,
+            self.oversize,
+            self.body_errors,
+            self.skipped_bytes,
+        ];
+        for ((c, n), seen) in self.counters.iter().zip(now).zip(&mut self.published) {
+            if n > *seen {
+                c.increment(n - *seen);
+                *seen = n;
+            }
+        }
+    }
+
+    /// Move `n` frames the decode pool could not decode from frames to body errors.
+    pub fn fold_remote_body_errors(&mut self, n: u64) {
+        let n = n.min(self.frames);
+        self.frames -= n;
+        self.body_errors += n;
+    }
+
+    /// The error ratio, if it is over the quarantine threshold.
+    pub fn over_threshold(&self, q: &QuarantineCfg) -> Option<f64> {
+        // A producer sending nothing but garbage is one long run, so the
+        // skipped bytes weigh in too.
+        let errors = self.errors() + self.skipped_bytes / FRAME_HEADER_LEN as u64;
+        let total = self.frames + errors;
+        if total < q.min_events.max(1) {
+            return None;
+        }
+        let ratio = errors as f64 / total as f64;
+        (ratio > q.max_error_ratio).then_some(ratio)
+    }
+}
+
+/// Skip one byte; only the first byte of a garbage run counts as a resync.
+fn resync(buf: &mut BytesMut, stats: &mut ConnStats) {
+    if !std::mem::replace(&mut stats.in_garbage, true) {
+        counter!("ultra_resync_events_total").increment(1);
+        RESYNC_EVENTS_THIS_MINUTE.fetch_add(1, Ordering::Relaxed);
+    }
+    counter!("ultra_resync_skipped_bytes_total").increment(1);
+    stats.skipped_bytes += 1;
+    buf.advance(1);
+}
+
+#[cfg(feature = "rkyv")]
+fn decode_archived(frame: &[u8]) -> Option<Record> {
+    let (arec, _) = decode_record_archived_trusted_from_slice(frame).ok()?;
+    let mut map = SharedDeserializeMap::new();
+    match arec.deserialize(&mut map) {
+        Ok(rec) => Some(rec),
+        Err(_) => {
+            counter!("ultra_rkyv_deser_errors_total").increment(1);
+            None
+        }
+    }
+}
+
+/// Peel every complete frame off `buf`, resyncing past bad headers and
+/// oversize frames, skipping auth frames and leaving a partial frame (if any)
+/// buffered. `frame` gets
+/// each header and whole frame and returns whether its body decoded.
+pub fn split_frames(
+    buf: &mut BytesMut,
+    limits: FrameLimits,
+    stats: &mut ConnStats,
+    mut frame: impl FnMut(&FrameHeader, &[u8]) -> bool,
+) {
+    loop {
+        let parsed = if limits.accept_legacy {
+            FrameHeader::parse_lenient(buf)
+        } else {
+            FrameHeader::parse(buf)
+        };
+        let hdr = match parsed {
+            Ok(Some(hdr)) => hdr,
+            Ok(None) => return,
+            Err(_) => {
+                if !stats.in_garbage {
+                    counter!("ultra_decode_bad_header_total").increment(1);
+                    stats.bad_headers += 1;
+                }
+                resync(buf, stats);
+                continue;
+            }
+        };
+        if hdr.payload_len as usize > limits.max_frame_bytes {
+            if !stats.in_garbage {
+                counter!("ultra_frame_too_large_total").increment(1);
+                histogram!("ultra_frame_oversize_bytes").record(hdr.payload_len as f64);
+                stats.oversize += 1;
+            }
+            resync(buf, stats);
+            continue;
+        }
+        stats.in_garbage = false;
+        let total = hdr.frame_len();
+        if buf.len() < total {
+            counter!("ultra_decode_need_more_total").increment(1);
+            return;
+        }
+        if !hdr.is_checksummed() {
+            counter!("ultra_legacy_frames_total").increment(1);
+        }
+        if hdr.is_auth() {
+            counter!("ultra_auth_frames_skipped_total").increment(1);
+            buf.advance(total);
+            continue;
+        }
+        if frame(&hdr, &buf[..total]) {
+            stats.frames += 1;
+        } else {
+            stats.body_errors += 1;
+        }
+        buf.advance(total);
+    }
+}
+
+/// Decode one whole frame whose header was already checked.
+pub fn decode_frame(hdr: &FrameHeader, frame: &[u8], scratch: &mut Vec<u8>) -> Option<Ingested> {
+    let (origin_ns, _) = hdr.split_origin(&frame[FRAME_HEADER_LEN..]);
+    #[cfg(feature = "rkyv")]
+    if (hdr.flags & FLAG_RKYV) != 0 && (hdr.flags & FLAG_LZ4) == 0 {
+        if let Some(rec) = decode_archived(frame) {
+            return Some(Ingested { rec, origin_ns });
+        }
+        // fall through to the bincode path
+    }
+    // The header checked out, so a body that fails to decode is skipped whole.
+    match decode_record_body(hdr, &frame[FRAME_HEADER_LEN..], scratch) {
+        Ok(rec) => Some(Ingested { rec, origin_ns }),
+        Err(_) => {
+            counter!("ultra_decode_body_errors_total").increment(1);
+            None
+        }
+    }
+}
+
+/// Decode every complete frame in `buf`, leaving a partial frame (if any) buffered.
+pub fn drain_frames(
+    buf: &mut BytesMut,
+    limits: FrameLimits,
+    scratch: &mut Vec<u8>,
+    stats: &mut ConnStats,
+    mut emit: impl FnMut(Ingested),
+) {
+    split_frames(buf, limits, stats, |hdr, frame| {
+        decode_frame(hdr, frame, scratch).map(&mut emit).is_some()
+    });
+}
+
+/// Hand a decoded record to the output stage, dropping it if the stage is full.
+pub fn forward(out: &tokio::sync::mpsc::Sender<Ingested>, rec: Ingested) {
+    let v = INGEST_SEQ.fetch_add(1, Ordering::Relaxed);
+    if (v & INGEST_SAMPLE_MASK) == 0 {
+        counter!("ultra_records_ingested_total").increment(INGEST_SAMPLE_WEIGHT);
+    }
+    if out.try_send(rec).is_err() {
+        counter!("ultra_output_queue_dropped_total").increment(1);
+    }
+}
+
+/// Decode frames from one producer connection, counted under its source
+/// `conn` in the metrics, until it closes, is quarantined or shutdown begins.
+pub async fn handle_client<R: AsyncRead + Unpin>(
+    mut sock: R,
+    limits: FrameLimits,
+    token: Option<&str>,
+    conn: &str,
+    out: tokio::sync::mpsc::Sender<Ingested>,
+) -> anyhow::Result<()> {
+    let mut buf = BytesMut::with_capacity(1 << 20);
+    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+    let mut stats = ConnStats::new(conn);
+    if let Some(token) = token {
+        crate::producer_auth::authenticate(&mut sock, &mut buf, token).await?;
+    }
+    // With `decode_workers`, this task only splits frames; one worker decodes
+    // them all, in order.
+    let lane = crate::decode_pool::get().map(|pool| pool.lane(out.clone()));
+    loop {
+        // Frames that arrived with the auth frame are already buffered.
+        match &lane {
+            Some(lane) => {
+                let mut batch = BytesMut::new();
+                split_frames(&mut buf, limits, &mut stats, |_, frame| {
+                    batch.extend_from_slice(frame);
+                    true
+                });
+                lane.submit(batch.freeze()).await;
+                stats.fold_remote_body_errors(lane.take_body_errors());
+            }
+            None => drain_frames(&mut buf, limits, &mut scratch, &mut stats, |rec| {
+                forward(&out, rec)
+            }),
+        }
+        stats.publish();
+        if let Some(ratio) = limits.quarantine.and_then(|q| stats.over_threshold(&q)) {
+            warn!(
+                conn,
+                ratio,
+                frames = stats.frames,
+                bad_headers = stats.bad_headers,
+                oversize = stats.oversize,
+                body_errors = stats.body_errors,
+                "producer over the decode error threshold; disconnecting"
+            );
+            counter!("ultra_conn_quarantined_total").increment(1);
+            break;
+        }
+        // read available bytes directly into the growable buffer; on shutdown,
+        // stop after the frames already read
+        let n = tokio::select! {
+            n = sock.read_buf(&mut buf) => n?,
+            _ = crate::shutdown::stopped() => break,
+        };
+        if n == 0 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{encode_auth_frame, encode_record, stamp_origin, FLAG_HAS_CHECKSUM};
+    use tokio::io::AsyncWriteExt;
+
+    fn slot(n: u64) -> Record {
+        Record::Slot {
+            slot: n,
+            parent: None,
+            status: 0,
+        }
+    }
+
+    fn slots_in(buf: &mut BytesMut, limits: FrameLimits) -> Vec<u64> {
+        let mut got = Vec::new();
+        drain_frames(
+            buf,
+            limits,
+            &mut Vec::new(),
+            &mut ConnStats::new("test"),
+            |ing| {
+                if let Record::Slot { slot, .. } = ing.rec {
+                    got.push(slot);
+                }
+            },
+        );
+        got
+    }
+
+    #[test]
+    fn resyncs_past_garbage_and_keeps_partial_frames() {
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let a = encode_record(&slot(1)).unwrap();
+        let mut b = encode_record(&slot(2)).unwrap();
+        stamp_origin(&mut b, 77).unwrap();
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&[0xde, 0xad, 0xbe]);
+        buf.extend_from_slice(&a);
+        buf.extend_from_slice(&b[..b.len() - 1]);
+        assert_eq!(slots_in(&mut buf, limits), [1]);
+        assert_eq!(buf.len(), b.len() - 1);
+        buf.extend_from_slice(&b[b.len() - 1..]);
+        let mut got = Vec::new();
+        drain_frames(
+            &mut buf,
+            limits,
+            &mut Vec::new(),
+            &mut ConnStats::new("test"),
+            |ing| got.push(ing),
+        );
+        assert!(matches!(
+            got[..],
+            [Ingested {
+                rec: Record::Slot { slot: 2, .. },
+                origin_ns: Some(77)
+            }]
+        ));
+        assert!(buf.is_empty());
+    }
+
+    #[tokio::test]
+    async fn quarantines_a_producer_sending_garbage() {
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: Some(QuarantineCfg {
+                max_error_ratio: 0.5,
+                min_events: 16,
+            }),
+        };
+        let (mut producer, sock) = tokio::io::duplex(4096);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let client =
+            tokio::spawn(async move { handle_client(sock, limits, None, "test", tx).await });
+        producer
+            .write_all(&encode_record(&slot(1)).unwrap())
+            .await
+            .unwrap();
+        producer.write_all(&[0xab; 256]).await.unwrap();
+        // The connection is still open, yet the reader gives up on it.
+        tokio::time::timeout(std::time::Duration::from_secs(5), client)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            rx.recv().await.map(|i| i.rec),
+            Some(Record::Slot { slot: 1, .. })
+        ));
+
+        let mut stats = ConnStats::new("test");
+        stats.frames = 20;
+        stats.body_errors = 19;
+        assert_eq!(stats.over_threshold(&limits.quarantine.unwrap()), None);
+    }
+
+    #[test]
+    fn counts_one_resync_per_garbage_run() {
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let mut stats = ConnStats::new("test");
+        let mut buf = BytesMut::from(&[0xab; 40][..]);
+        split_frames(&mut buf, limits, &mut stats, |_, _| true);
+        // The run carries on into the next read.
+        buf.extend_from_slice(&[0xcd; 40]);
+        buf.extend_from_slice(&encode_record(&slot(1)).unwrap());
+        buf.extend_from_slice(&[0xef; 3]);
+        buf.extend_from_slice(&encode_record(&slot(2)).unwrap());
+        split_frames(&mut buf, limits, &mut stats, |_, _| true);
+        assert_eq!(stats.frames, 2);
+        assert_eq!(stats.bad_headers, 2);
+        assert_eq!(stats.skipped_bytes, 83);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn skips_auth_frames_without_counting_errors() {
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let mut buf = BytesMut::new();
+        buf.extend_from_slice(&encode_auth_frame(b"token"));
+        buf.extend_from_slice(&encode_record(&slot(1)).unwrap());
+        buf.extend_from_slice(&encode_auth_frame(b"again"));
+        buf.extend_from_slice(&encode_record(&slot(2)).unwrap());
+        let mut stats = ConnStats::new("test");
+        let mut got = Vec::new();
+        drain_frames(&mut buf, limits, &mut Vec::new(), &mut stats, |ing| {
+            got.push(ing.rec)
+        });
+        assert!(matches!(
+            got[..],
+            [Record::Slot { slot: 1, .. }, Record::Slot { slot: 2, .. }]
+        ));
+        assert_eq!((stats.frames, stats.errors()), (2, 0));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn legacy_frames_need_opt_in() {
+        let mut legacy = encode_record(&slot(5)).unwrap();
+        legacy[1] &= !FLAG_HAS_CHECKSUM;
+        legacy[8..12].fill(0);
+        let strict = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let mut buf = BytesMut::from(&legacy[..]);
+        assert!(slots_in(&mut buf, strict).is_empty());
+        let mut buf = BytesMut::from(&legacy[..]);
+        let lenient = FrameLimits {
+            accept_legacy: true,
+            ..strict
+        };
+        assert_eq!(slots_in(&mut buf, lenient), [5]);
+    }
+}
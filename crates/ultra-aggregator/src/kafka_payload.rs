@@ -0,0 +1,450 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/kafka_payload.rs
+//
+// Kafka payload encodings. `bincode` is the workspace-internal format; `json`
+// matches the stdout events; `avro` and `protobuf` encode every record kind
+// through one schema (an Avro union / a protobuf `oneof`) so a topic fed by
+// routing rules with mixed kinds still has a single value schema. With a schema
+// registry configured, Avro and protobuf payloads are registered under the
+// `<topic>-value` subject and carry the Confluent wire header.
+use crate::{json_event_owned_from_record, write_json_event, Base58Cache};
+use anyhow::{bail, Context, Result};
+use faststreams::Record;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadFormat {
+    #[default]
+    Bincode,
+    Json,
+    Avro,
+    Protobuf,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct SchemaRegistryCfg {
+    pub url: String,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+pub const AVRO_SCHEMA: &str = r#"[
+  {"type": "record", "name": "Account", "namespace": "numistack.ultra", "fields": [
+    {"name": "slot", "type": "long"},
+    {"name": "is_startup", "type": "boolean"},
+    {"name": "pubkey", "type": {"type": "fixed", "name": "Pubkey", "size": 32}},
+    {"name": "lamports", "type": "long"},
+    {"name": "owner", "type": "Pubkey"},
+    {"name": "executable", "type": "boolean"},
+    {"name": "rent_epoch", "type": "long"},
+    {"name": "data", "type": "bytes"}]},
+  {"type": "record", "name": "Tx", "namespace": "numistack.ultra", "fields": [
+    {"name": "slot", "type": "long"},
+    {"name": "signature", "type": {"type": "fixed", "name": "Signature", "size": 64}},
+    {"name": "err", "type": ["null", "string"]},
+    {"name": "vote", "type": "boolean"}]},
+  {"type": "record", "name": "Block", "namespace": "numistack.ultra", "fields": [
+    {"name": "slot", "type": "long"},
+    {"name": "blockhash", "type": ["null", "Pubkey"]},
+    {"name": "parent_slot", "type": ["null", "long"]},
+    {"name": "rewards_len", "type": "int"},
+    {"name": "block_time_unix", "type": ["null", "long"]},
+    {"name": "leader", "type": ["null", "Pubkey"]},
+    {"name": "block_height", "type": ["null", "long"]}]},
+  {"type": "record", "name": "Slot", "namespace": "numistack.ultra", "fields": [
+    {"name": "slot", "type": "long"},
+    {"name": "parent", "type": ["null", "long"]},
+    {"name": "status", "type": "int"}]},
+  {"type": "record", "name": "EndOfStartup", "namespace": "numistack.ultra", "fields": []}
+]"#;
+
+pub const PROTO_SCHEMA: &str = r#"syntax = "proto3";
+package numistack.ultra;
+
+message Record {
+  oneof kind {
+    Account account = 1;
+    Tx tx = 2;
+    Block block = 3;
+    Slot slot = 4;
+    EndOfStartup end_of_startup = 5;
+  }
+}
+message Account {
+  uint64 slot = 1;
+  bool is_startup = 2;
+  bytes pubkey = 3;
+  uint64 lamports = 4;
+  bytes owner = 5;
+  bool executable = 6;
+  uint64 rent_epoch = 7;
+  bytes data = 8;
+}
+message Tx {
+  uint64 slot = 1;
+  bytes signature = 2;
+  optional string err = 3;
+  bool vote = 4;
+}
+message Block {
+  uint64 slot = 1;
+  optional bytes blockhash = 2;
+  optional uint64 parent_slot = 3;
+  uint32 rewards_len = 4;
+  optional int64 block_time_unix = 5;
+  optional bytes leader = 6;
+  optional uint64 block_height = 7;
+}
+message Slot {
+  uint64 slot = 1;
+  optional uint64 parent = 2;
+  uint32 status = 3;
+}
+message EndOfStartup {}
+"#;
+
+// ---- Avro binary encoding ----
+// u64 fields are written bit-for-bit as Avro longs.
+
+fn avro_long(out: &mut Vec<u8>, v: i64) {
+    let mut z = ((v << 1) ^ (v >> 63)) as u64;
+    while z >= 0x80 {
+        out.push((z as u8) | 0x80);
+        z >>= 7;
+    }
+    out.push(z as u8);
+}
+
+fn avro_bytes(out: &mut Vec<u8>, b: &[u8]) {
+    avro_long(out, b.len() as i64);
+    out.extend_from_slice(b);
+}
+
+// ["null", T] unions: branch 0 is null, branch 1 the value.
+fn avro_opt<T>(out: &mut Vec<u8>, v: Option<T>, write: impl FnOnce(&mut Vec<u8>, T)) {
+    match v {
+        None => avro_long(out, 0),
+        Some(v) => {
+            avro_long(out, 1);
+            write(out, v);
+        }
+    }
+}
+
+pub fn encode_avro(rec: &Record, out: &mut Vec<u8>) {
+    match rec {
+        Record::Account(a) => {
+            avro_long(out, 0);
+            avro_long(out, a.slot as i64);
+            out.push(a.is_startup as u8);
+            out.extend_from_slice(&a.pubkey);
+            avro_long(out, a.lamports as i64);
+            out.extend_from_slice(&a.owner);
+            out.push(a.executable as u8);
+            avro_long(out, a.rent_epoch as i64);
+            avro_bytes(out, &a.data);
+        }
+        Record::Tx(t) => {
+            avro_long(out, 1);
+            avro_long(out, t.slot as i64);
+            out.extend_from_slice(&t.signature);
+            avro_opt(out, t.err.as_deref(), |o, e| avro_bytes(o, e.as_bytes()));
+            out.push(t.vote as u8);
+        }
+        Record::Block(b) => {
+            avro_long(out, 2);
+            avro_long(out, b.slot as i64);
+            avro_opt(out, b.blockhash, |o, h| o.extend_from_slice(&h));
+            avro_opt(out, b.parent_slot, |o, s| avro_long(o, s as i64));
+            avro_long(out, b.rewards_len as i64);
+            avro_opt(out, b.block_time_unix, avro_long);
+            avro_opt(out, b.leader, |o, l| o.extend_from_slice(&l));
+            avro_opt(out, b.block_height, |o, h| avro_long(o, h as i64));
+        }
+        Record::Slot {
+            slot,
+            parent,
+            status,
+        } => {
+            avro_long(out, 3);
+            avro_long(out, *slot as i64);
+            avro_opt(out, *parent, |o, p| avro_long(o, p as i64));
+            avro_long(out, *status as i64);
+        }
+        Record::EndOfStartup => avro_long(out, 4),
+    }
+}
+
+// ---- Protobuf encoding ----
+
+fn pb_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn pb_uint(out: &mut Vec<u8>, field: u32, v: u64) {
+    pb_varint(out, (field as u64) << 3);
+    pb_varint(out, v);
+}
+
+fn pb_bytes(out: &mut Vec<u8>, field: u32, b: &[u8]) {
+    pb_varint(out, ((field as u64) << 3) | 2);
+    pb_varint(out, b.len() as u64);
+    out.extend_from_slice(b);
+}
+
+// proto3 leaves zero-valued implicit fields off the wire.
+fn pb_uint_nonzero(out: &mut Vec<u8>, field: u32, v: u64) {
+    if v != 0 {
+        pb_uint(out, field, v);
+    }
+}
+
+fn pb_bytes_nonempty(out: &mut Vec<u8>, field: u32, b: &[u8]) {
+    if !b.is_empty() {
+        pb_bytes(out, field, b);
+    }
+}
+
+/// Encodes `rec` as a `numistack.ultra.Record`; `scratch` holds the inner message.
+pub fn encode_protobuf(rec: &Record, scratch: &mut Vec<u8>, out: &mut Vec<u8>) {
+    scratch.clear();
+    let field = match rec {
+        Record::Account(a) => {
+            pb_uint_nonzero(scratch, 1, a.slot);
+            pb_uint_nonzero(scratch, 2, a.is_startup as u64);
+            pb_bytes(scratch, 3, &a.pubkey);
+            pb_uint_nonzero(scratch, 4, a.lamports);
+            pb_bytes(scratch, 5, &a.owner);
+            pb_uint_nonzero(scratch, 6, a.executable as u64);
+            pb_uint_nonzero(scratch, 7, a.rent_epoch);
+            pb_bytes_nonempty(scratch, 8, &a.data);
+            1
+        }
+        Record::Tx(t) => {
+            pb_uint_nonzero(scratch, 1, t.slot);
+            pb_bytes(scratch, 2, &t.signature);
+            if let Some(err) = &t.err {
+                pb_bytes(scratch, 3, err.as_bytes());
+            }
+            pb_uint_nonzero(scratch, 4, t.vote as u64);
+            2
+        }
+        Record::Block(b) => {
+            pb_uint_nonzero(scratch, 1, b.slot);
+            if let Some(h) = &b.blockhash {
+                pb_bytes(scratch, 2, h);
+            }
+            if let Some(p) = b.parent_slot {
+                pb_uint(scratch, 3, p);
+            }
+            pb_uint_nonzero(scratch, 4, b.rewards_len as u64);
+            if let Some(t) = b.block_time_unix {
+                // int64 varints are two's complement
+                pb_uint(scratch, 5, t as u64);
+            }
+            if let Some(l) = &b.leader {
+                pb_bytes(scratch, 6, l);
+            }
+            if let Some(h) = b.block_height {
+                pb_uint(scratch, 7, h);
+            }
+            3
+        }
+        Record::Slot {
+            slot,
+            parent,
+            status,
+        } => {
+            pb_uint_nonzero(scratch, 1, *slot);
+            if let Some(p) = parent {
+                pb_uint(scratch, 2, *p);
+            }
+            pb_uint_nonzero(scratch, 3, *status as u64);
+            4
+        }
+        Record::EndOfStartup => 5,
+    };
+    // oneof members are always written, even when empty
+    pb_bytes(out, field, scratch);
+}
+
+/// Confluent wire header: magic 0 + big-endian schema id (+ message index for protobuf).
+fn wire_header(out: &mut Vec<u8>, schema_id: u32, format: PayloadFormat) {
+    out.push(0);
+    out.extend_from_slice(&schema_id.to_be_bytes());
+    if format == PayloadFormat::Protobuf {
+        // Index path [0] (the first message, `Record`) is written as a single 0.
+        out.push(0);
+    }
+}
+
+/// Per-worker encoder state.
+pub struct PayloadEncoder {
+    cache32: Base58Cache<32>,
+    cache64: Base58Cache<64>,
+    scratch: Vec<u8>,
+}
+
+impl PayloadEncoder {
+    pub fn new() -> Self {
+        Self {
+            cache32: Base58Cache::<32>::new(16_384),
+            cache64: Base58Cache::<64>::new(8_192),
+            scratch: Vec::with_capacity(256),
+        }
+    }
+
+    /// `schema_id` adds the registry header (Avro/protobuf only).
+    pub fn encode(
+        &mut self,
+        rec: &Record,
+        format: PayloadFormat,
+        schema_id: Option<u32>,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        out.clear();
+        match format {
+            PayloadFormat::Bincode => bincode::serialize_into(&mut *out, rec)?,
+            PayloadFormat::Json => write_json_event(
+                &json_event_owned_from_record(rec, None),
+                out,
+                &mut self.cache32,
+                &mut self.cache64,
+            )?,
+            PayloadFormat::Avro | PayloadFormat::Protobuf => {
+                if let Some(id) = schema_id {
+                    wire_header(out, id, format);
+                }
+                if format == PayloadFormat::Avro {
+                    encode_avro(rec, out);
+                } else {
+                    encode_protobuf(rec, &mut self.scratch, out);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Registers value schemas (`<topic>-value`, topic-name strategy) and caches their ids.
+pub struct SchemaRegistry {
+    http: reqwest::Client,
+    cfg: SchemaRegistryCfg,
+    ids: Mutex<HashMap<String, u32>>,
+}
+
+#[derive(serde::Deserialize)]
+struct RegisterResponse {
+    id: u32,
+}
+
+impl SchemaRegistry {
+    pub fn new(cfg: SchemaRegistryCfg) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            cfg,
+            ids: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Schema id for `topic`; only the first call per topic reaches the registry.
+    pub async fn schema_id(&self, topic: &str, format: PayloadFormat) -> Result<u32> {
+        if let Some(id) = self.ids.lock().unwrap().get(topic) {
+            return Ok(*id);
+        }
+        let (schema_type, schema) = match format {
+            PayloadFormat::Avro => ("AVRO", AVRO_SCHEMA),
+            PayloadFormat::Protobuf => ("PROTOBUF", PROTO_SCHEMA),
+            _ => bail!("{format:?} payloads have no registry schema"),
+        };
+        let url = format!(
+            "{}/subjects/{topic}-value/versions",
+            self.cfg.url.trim_end_matches('/')
+        );
+        let mut req = self
+            .http
+            .post(&url)
+            .header("content-type", "application/vnd.schemaregistry.v1+json")
+            .json(&serde_json::json!({ "schemaType": schema_type, "schema": schema }));
+        if let Some(user) = &self.cfg.username {
+            req = req.basic_auth(user, self.cfg.password.as_ref());
+        }
+        let resp = req
+            .send()
+            .await
+            .with_context(|| format!("schema registry {url}"))?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            bail!("schema registry {url}: {status}: {body}");
+        }
+        let id = resp.json::<RegisterResponse>().await?.id;
+        self.ids.lock().unwrap().insert(topic.to_string(), id);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::TxUpdate;
+
+    #[test]
+    fn avro_and_protobuf_encode_union_branches() {
+        let slot = Record::Slot {
+            slot: 300,
+            parent: None,
+            status: 1,
+        };
+        let mut out = Vec::new();
+        encode_avro(&slot, &mut out);
+        // branch 3, zigzag(300) = 600 = [0xd8, 0x04], null parent, status 1
+        assert_eq!(out, [6, 0xd8, 0x04, 0, 2]);
+
+        let mut scratch = Vec::new();
+        out.clear();
+        encode_protobuf(&slot, &mut scratch, &mut out);
+        // field 4 (len-delimited): {slot = 300, status = 1}
+        assert_eq!(out, [0x22, 5, 0x08, 0xac, 0x02, 0x18, 1]);
+        out.clear();
+        encode_protobuf(&Record::EndOfStartup, &mut scratch, &mut out);
+        assert_eq!(out, [0x2a, 0]);
+        assert!(AVRO_SCHEMA.parse::<serde_json::Value>().is_ok());
+    }
+
+    #[test]
+    fn registry_framing_and_json_payloads() {
+        let tx = Record::Tx(TxUpdate {
+            slot: 1,
+            signature: [0u8; 64],
+            err: None,
+            vote: false,
+        });
+        let mut enc = PayloadEncoder::new();
+        let mut out = Vec::new();
+        enc.encode(&tx, PayloadFormat::Protobuf, Some(7), &mut out)
+            .unwrap();
+        assert_eq!(out[..6], [0, 0, 0, 0, 7, 0]);
+        enc.encode(&tx, PayloadFormat::Avro, Some(7), &mut out)
+            .unwrap();
+        assert_eq!(out[..6], [0, 0, 0, 0, 7, 2]);
+        enc.encode(&tx, PayloadFormat::Json, None, &mut out)
+            .unwrap();
+        let v: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(v["type"], "tx");
+        enc.encode(&tx, PayloadFormat::Bincode, None, &mut out)
+            .unwrap();
+        assert!(matches!(
+            bincode::deserialize::<Record>(&out).unwrap(),
+            Record::Tx(_)
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/lag.rs
+//
+// Per-source throughput and pipeline lag. Producers that stamp frames with an
+// origin timestamp (`FLAG_ORIGIN_TS`, e.g. ys-consumer with `YS_STAMP_ORIGIN=1`)
+// get lag measured along the whole path:
+//
+//   ultra_records_total{source,kind}                records entering an output stage
+//   ultra_ingest_lag_seconds{source,kind}           origin -> output stage (producer -> aggregator)
+//   ultra_sink_queue_lag_seconds{sink}              output stage -> sink worker (aggregator -> sink)
+//   ultra_pipeline_lag_seconds{sink,kind}           origin -> sink worker (end to end)
+//   ultra_source_last_record_age_seconds{source}    time since the source last delivered a record
+//
+// Lag histograms are sampled; the age gauge ticks every second, so a stalled
+// producer shows up as a climbing age even when nothing else moves.
+use crate::routing::{record_kind, KINDS};
+use faststreams::Record;
+use metrics::{counter, gauge, histogram, Counter, Histogram};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LAG_SAMPLE_MASK: u64 = 0x3F; // sample ~1/64
+const AGE_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Seconds from `origin_ns` to now; clock skew between hosts clamps to zero.
+pub fn since_origin(origin_ns: u64) -> f64 {
+    unix_nanos().saturating_sub(origin_ns) as f64 / 1e9
+}
+
+fn kind_index(kind: &str) -> usize {
+    KINDS.iter().position(|k| *k == kind).unwrap_or(0)
+}
+
+/// Metrics for one output stage's input.
+pub struct SourceMeter {
+    records: Vec<Counter>,
+    ingest_lag: Vec<Histogram>,
+    seq: u64,
+    last_seen_ns: Arc<AtomicU64>,
+}
+
+impl SourceMeter {
+    /// Must be created inside the runtime: it spawns the age ticker, which
+    /// stops once the meter is dropped.
+    pub fn new(source: &str) -> Self {
+        let source = source.to_string();
+        let last_seen_ns = Arc::new(AtomicU64::new(unix_nanos()));
+        let last = Arc::downgrade(&last_seen_ns);
+        let age = gauge!("ultra_source_last_record_age_seconds", "source" => source.clone());
+        tokio::spawn(async move {
+            let mut tick = tokio::time::interval(AGE_INTERVAL);
+            loop {
+                tick.tick().await;
+                let Some(last) = last.upgrade() else { break };
+                age.set(since_origin(last.load(Ordering::Relaxed)));
+            }
+        });
+        Self {
+            records: KINDS
+                .iter()
+                .map(|k| counter!("ultra_records_total", "source" => source.clone(), "kind" => *k))
+                .collect(),
+            ingest_lag: KINDS
+                .iter()
+                .map(|k| {
+                    histogram!("ultra_ingest_lag_seconds", "source" => source.clone(), "kind" => *k)
+                })
+                .collect(),
+            seq: 0,
+            last_seen_ns,
+        }
+    }
+
+    pub fn observe(&mut self, rec: &Record, origin_ns: Option<u64>) {
+        let i = kind_index(record_kind(rec));
+        self.records[i].increment(1);
+        self.last_seen_ns.store(unix_nanos(), Ordering::Relaxed);
+        self.seq += 1;
+        if let Some(origin) = origin_ns.filter(|_| self.seq & LAG_SAMPLE_MASK == 0) {
+            self.ingest_lag[i].record(since_origin(origin));
+        }
+    }
+}
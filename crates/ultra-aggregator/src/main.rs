@@ -1,29 +1,58 @@
 // Numan Thabit 2025
 // crates/ultra-aggregator/src/main.rs
-#![forbid(unsafe_code)]
+#![deny(unsafe_code)]
+mod account_data;
+mod admin;
+mod decode_pool;
+mod dedup;
+#[cfg(feature = "kafka")]
+mod dlq;
+#[cfg(feature = "elasticsearch")]
+mod es_sink;
+#[cfg(feature = "grpc")]
+mod grpc_server;
+mod ingest;
+#[cfg(feature = "kafka")]
+mod kafka_payload;
+mod lag;
+#[cfg(feature = "nats")]
+mod nats_sink;
+#[cfg(feature = "parquet")]
+mod parquet_sink;
+#[cfg(feature = "postgres")]
+mod pg_sink;
+mod producer_auth;
+#[cfg(feature = "redis")]
+mod redis_sink;
+mod reload;
+mod reorder;
+mod routing;
+mod shm_input;
+mod shutdown;
+mod sink_queue;
+mod tcp_input;
+mod uds_input;
+mod ws;
 use anyhow::Result;
-use bytes::{Buf, BytesMut};
+use arc_swap::ArcSwap;
 #[cfg(feature = "rkyv")]
-use faststreams::{decode_record_archived_trusted_from_slice, ArchivedRecord, FLAG_LZ4, FLAG_RKYV};
-use faststreams::{decode_record_from_slice, Record};
-use metrics::{counter, gauge, histogram};
+use faststreams::ArchivedRecord;
+use faststreams::Record;
+#[cfg(feature = "kafka")]
+use metrics::counter;
+use metrics::gauge;
 use metrics_exporter_prometheus::PrometheusBuilder;
-#[cfg(feature = "rkyv")]
-use rkyv::de::deserializers::SharedDeserializeMap;
-#[cfg(feature = "rkyv")]
-use rkyv::Deserialize;
+use routing::SinkTarget;
 use serde::ser::{SerializeMap, Serializer};
-use socket2::SockRef;
 use std::collections::VecDeque;
 use std::io::Write;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use tokio::io::AsyncReadExt;
-use tokio::net::{UnixListener, UnixStream};
 use tokio::signal;
 use tokio::time::{self, Duration};
-use tracing::{error, info};
+#[cfg(feature = "kafka")]
+use tracing::error;
+use tracing::warn;
 use tracing_subscriber::EnvFilter;
 
 #[cfg(feature = "kafka")]
@@ -37,6 +66,122 @@ struct KafkaCfg {
     /// Optional number of Kafka worker tasks; defaults to number of CPUs
     #[serde(default)]
     workers: Option<usize>,
+    /// Payload encoding: bincode (default), json, avro or protobuf
+    #[serde(default)]
+    format: kafka_payload::PayloadFormat,
+    /// Per-topic overrides of `format`
+    #[serde(default)]
+    topic_formats: std::collections::HashMap<String, kafka_payload::PayloadFormat>,
+    /// Registers avro/protobuf schemas and prefixes payloads with the schema id
+    #[serde(default)]
+    schema_registry: Option<kafka_payload::SchemaRegistryCfg>,
+    /// Producer acks: "all" (default), "1" or "0"
+    #[serde(default)]
+    acks: Option<String>,
+    /// Re-sends of a record after a transient delivery error (default 5)
+    #[serde(default)]
+    max_retries: Option<u32>,
+    /// First retry delay; doubles per attempt up to 5s (default 100ms)
+    #[serde(default)]
+    retry_backoff_ms: Option<u64>,
+    /// Records awaiting delivery or retry before workers stop taking new ones
+    #[serde(default)]
+    max_in_flight: Option<usize>,
+    /// Directory for records that could not be delivered (ys-consumer DLQ layout)
+    #[serde(default)]
+    dlq_dir: Option<String>,
+}
+
+#[cfg(feature = "kafka")]
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_error_is_transient(e: &rdkafka::error::KafkaError) -> bool {
+    use rdkafka::error::RDKafkaErrorCode as C;
+    matches!(
+        e.rdkafka_error_code(),
+        Some(
+            C::QueueFull
+                | C::MessageTimedOut
+                | C::RequestTimedOut
+                | C::BrokerTransportFailure
+                | C::AllBrokersDown
+                | C::LeaderNotAvailable
+                | C::NotLeaderForPartition
+                | C::NetworkException
+                | C::NotEnoughReplicas
+                | C::NotEnoughReplicasAfterAppend
+        )
+    )
+}
+
+// Awaits the broker ack for one record, re-sending transient failures with
+// exponential backoff; anything still undelivered goes to the DLQ. The permit
+// bounds how many records are buffered this way.
+#[cfg(feature = "kafka")]
+#[allow(clippy::too_many_arguments)]
+async fn deliver_to_kafka(
+    prod: rdkafka::producer::FutureProducer<
+        rdkafka::client::DefaultClientContext,
+        rdkafka::util::TokioRuntime,
+    >,
+    topic: String,
+    key: String,
+    payload: Vec<u8>,
+    rec: Record,
+    policy: RetryPolicy,
+    dlq: Option<dlq::DlqWriter>,
+    errors: sink_queue::SinkErrors,
+    work: sink_queue::InFlightGuard,
+    permit: tokio::sync::OwnedSemaphorePermit,
+) {
+    use rdkafka::producer::FutureRecord;
+    let t0 = std::time::Instant::now();
+    let mut attempt = 0u32;
+    let reason = loop {
+        let res = prod
+            .send(
+                FutureRecord::to(&topic).key(&key).payload(&payload),
+                Duration::from_secs(1),
+            )
+            .await;
+        match res {
+            Ok(_) => {
+                counter!("ultra_kafka_delivered_total").increment(1);
+                metrics::histogram!("ultra_kafka_delivery_seconds")
+                    .record(t0.elapsed().as_secs_f64());
+                drop(permit);
+                return;
+            }
+            Err((e, _)) if kafka_error_is_transient(&e) => {
+                if attempt >= policy.max_retries {
+                    error!("kafka delivery to {topic} failed after {attempt} retries: {e}");
+                    break "retries_exhausted";
+                }
+                counter!("ultra_kafka_retries_total").increment(1);
+                let delay = policy
+                    .backoff
+                    .saturating_mul(1 << attempt.min(16))
+                    .min(Duration::from_secs(5));
+                attempt += 1;
+                time::sleep(delay).await;
+            }
+            Err((e, _)) => {
+                error!("kafka delivery to {topic} failed: {e}");
+                break "permanent";
+            }
+        }
+    };
+    counter!("ultra_kafka_delivery_failed_total", "reason" => reason).increment(1);
+    errors.add(1);
+    if let Some(dlq) = &dlq {
+        dlq.park(rec, reason, &topic);
+    }
+    drop(permit);
 }
 
 // json_view removed: replaced with JsonEvent pipeline
@@ -61,23 +206,91 @@ struct Cfg {
     max_frame_bytes: Option<usize>,
     // New: multi-listener with per-socket overrides
     listeners: Option<Vec<SocketCfg>>,
+    // TCP (optionally TLS) listeners for producers on other hosts
+    #[serde(default)]
+    tcp_listeners: Option<Vec<tcp_input::TcpInputCfg>>,
+    // Shared-memory rings written by ys-consumer (`YS_OUTPUT=shm:<path>`)
+    #[serde(default)]
+    shm_inputs: Option<Vec<shm_input::ShmInputCfg>>,
+    // Also accept frames from producers that predate header checksums (rolling upgrades)
+    #[serde(default)]
+    accept_legacy_frames: bool,
+    // Account data (capped, base64/base58) in the JSON events; only `data_len` without it
+    #[serde(default)]
+    json_account_data: Option<account_data::AccountDataCfg>,
+    // Optional WebSocket fan-out of the JSON events
+    #[serde(default)]
+    ws: Option<ws::WsCfg>,
     #[cfg(feature = "kafka")]
     kafka: Option<KafkaCfg>,
+    #[cfg(feature = "postgres")]
+    postgres: Option<pg_sink::PgCfg>,
+    #[cfg(feature = "nats")]
+    nats: Option<nats_sink::NatsCfg>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<parquet_sink::ParquetCfg>,
+    #[cfg(feature = "redis")]
+    redis: Option<redis_sink::RedisCfg>,
+    #[cfg(feature = "elasticsearch")]
+    elasticsearch: Option<es_sink::EsCfg>,
+    // Yellowstone-compatible gRPC server over the decoded stream
+    #[cfg(feature = "grpc")]
+    grpc: Option<grpc_server::GrpcCfg>,
+    // Drop records already delivered by another input within a window
+    #[serde(default)]
+    dedup: Option<dedup::DedupCfg>,
+    // Hold records up to a window and release each kind in slot order
+    #[serde(default)]
+    reorder: Option<reorder::ReorderCfg>,
+    // Per-sink queue capacity and full-queue policy
+    #[serde(default)]
+    sink_queues: sink_queue::SinkQueuesCfg,
+    // Optional per-record sink selection; without it every sink gets every record
+    #[serde(default)]
+    routing: Option<routing::RoutingCfg>,
+    // HTTP API to inspect, pause and resume sinks and to reload the config
+    #[serde(default)]
+    admin: Option<admin::AdminCfg>,
+    // Reload when the config file changes, not just on SIGHUP or `POST /reload`
+    #[serde(default)]
+    watch_config: bool,
+    // Disconnect producers whose frames mostly fail to decode
+    #[serde(default)]
+    quarantine: Option<ingest::QuarantineCfg>,
+    // Shared-token handshake and UDS peer UID allowlist for producers
+    #[serde(default)]
+    producer_auth: Option<producer_auth::ProducerAuthCfg>,
+    // Decode UDS/TCP frames on this many worker threads instead of per connection
+    #[serde(default)]
+    decode_workers: Option<usize>,
+    // Budget for draining output stages and sink queues on ctrl-c/SIGTERM
+    #[serde(default)]
+    shutdown_timeout_ms: Option<u64>,
 }
 
 #[cfg(feature = "kafka")]
 #[derive(Clone)]
 struct KafkaSink {
-    tx: tokio::sync::mpsc::Sender<Record>,
+    tx: sink_queue::QueueTx<(Record, Option<Arc<str>>)>,
 }
 #[cfg(feature = "kafka")]
 impl KafkaSink {
-    fn new(cfg: KafkaCfg) -> Result<Self> {
+    fn new(cfg: KafkaCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
         use rdkafka::client::DefaultClientContext;
-        use rdkafka::producer::{FutureProducer, FutureRecord};
+        use rdkafka::producer::FutureProducer;
         use rdkafka::util::TokioRuntime;
         use rdkafka::ClientConfig;
-        let (tx, rx) = tokio::sync::mpsc::channel::<Record>(65_536);
+        let (tx, rx) = sink_queue::queue::<(Record, Option<Arc<str>>)>("kafka", queue);
+        let dlq = match &cfg.dlq_dir {
+            Some(dir) => Some(dlq::DlqWriter::new(dir.into(), 65_536)?),
+            None => None,
+        };
+        let policy = RetryPolicy {
+            max_retries: cfg.max_retries.unwrap_or(5),
+            backoff: Duration::from_millis(cfg.retry_backoff_ms.unwrap_or(100)),
+        };
+        let max_in_flight = cfg.max_in_flight.unwrap_or(100_000).max(1);
+        let in_flight = Arc::new(tokio::sync::Semaphore::new(max_in_flight));
         let workers = cfg.workers.unwrap_or_else(|| {
             std::thread::available_parallelism()
                 .map(|n| n.get())
@@ -89,6 +302,7 @@ impl KafkaSink {
             .set("queue.buffering.max.messages", "2000000")
             .set("queue.buffering.max.kbytes", "1048576")
             .set("message.timeout.ms", "5000")
+            .set("acks", cfg.acks.as_deref().unwrap_or("all"))
             .create::<FutureProducer<DefaultClientContext, TokioRuntime>>()
         {
             Ok(p) => p,
@@ -98,20 +312,26 @@ impl KafkaSink {
             }
         };
 
-        let rx = std::sync::Arc::new(tokio::sync::Mutex::new(rx));
+        let registry = cfg
+            .schema_registry
+            .clone()
+            .map(|r| Arc::new(kafka_payload::SchemaRegistry::new(r)));
         for _ in 0..workers {
             let rx_cl = rx.clone();
             let prod_cl = prod.clone();
             let cfg_cl = cfg.clone();
+            let registry = registry.clone();
+            let dlq = dlq.clone();
+            let in_flight = in_flight.clone();
+            let errors = rx.errors();
+            let work = rx.in_flight();
             tokio::spawn(async move {
-                use metrics::gauge;
+                let mut encoder = kafka_payload::PayloadEncoder::new();
+                let mut payload = Vec::with_capacity(512);
                 loop {
-                    let mut guard = rx_cl.lock().await;
-                    // Update depth gauge when we have the lock
-                    gauge!("ultra_kafka_queue_depth").set(guard.len() as f64);
-                    let opt = guard.recv().await;
-                    drop(guard);
-                    let Some(rec) = opt else { break };
+                    let Some((rec, topic_override)) = rx_cl.recv().await else {
+                        break;
+                    };
                     let (topic, key) = match &rec {
                         Record::Account(a) => (
                             &cfg_cl.topic_accounts,
@@ -130,33 +350,84 @@ impl KafkaSink {
                         Record::Slot { slot, .. } => (&cfg_cl.topic_slots, slot.to_string()),
                         Record::EndOfStartup => (&cfg_cl.topic_slots, "eos".to_string()),
                     };
-                    if let Ok(payload) = bincode::serialize(&rec) {
-                        let _ = prod_cl
-                            .send(
-                                FutureRecord::to(topic).key(&key).payload(&payload),
-                                std::time::Duration::from_secs(1),
-                            )
-                            .await;
+                    let topic: &str = topic_override.as_deref().unwrap_or(topic);
+                    let format = cfg_cl
+                        .topic_formats
+                        .get(topic)
+                        .copied()
+                        .unwrap_or(cfg_cl.format);
+                    let schema_id = match (&registry, format) {
+                        (
+                            Some(r),
+                            kafka_payload::PayloadFormat::Avro
+                            | kafka_payload::PayloadFormat::Protobuf,
+                        ) => match r.schema_id(topic, format).await {
+                            Ok(id) => Some(id),
+                            Err(e) => {
+                                counter!("ultra_kafka_schema_errors_total").increment(1);
+                                errors.add(1);
+                                error!("kafka schema registration failed: {e:#}");
+                                continue;
+                            }
+                        },
+                        _ => None,
+                    };
+                    if encoder
+                        .encode(&rec, format, schema_id, &mut payload)
+                        .is_err()
+                    {
+                        counter!("ultra_kafka_encode_errors_total").increment(1);
+                        errors.add(1);
+                        continue;
                     }
+                    let Ok(permit) = in_flight.clone().acquire_owned().await else {
+                        break;
+                    };
+                    gauge!("ultra_kafka_in_flight")
+                        .set((max_in_flight - in_flight.available_permits()) as f64);
+                    tokio::spawn(deliver_to_kafka(
+                        prod_cl.clone(),
+                        topic.to_string(),
+                        key,
+                        std::mem::replace(&mut payload, Vec::with_capacity(512)),
+                        rec,
+                        policy,
+                        dlq.clone(),
+                        errors.clone(),
+                        work.begin(),
+                        permit,
+                    ));
                 }
+                // The queue is closed and drained: once every delivery has
+                // settled, flush whatever the producer still buffers.
+                let _work = work.begin();
+                let _settled = in_flight.acquire_many(max_in_flight as u32).await;
+                let _ = tokio::task::spawn_blocking(move || {
+                    use rdkafka::producer::Producer;
+                    prod_cl.flush(Duration::from_secs(5))
+                })
+                .await;
             });
         }
         Ok(Self { tx })
     }
 
-    fn try_send(&self, rec: Record) -> bool {
-        self.tx.try_send(rec).is_ok()
+    /// `topic` overrides the per-kind topic for this record.
+    async fn send(&self, rec: Record, topic: Option<Arc<str>>) -> bool {
+        let kind = routing::record_kind(&rec);
+        self.tx.send((rec, topic), kind).await
     }
 }
 
 #[derive(Clone)]
 struct JsonSink {
-    tx: tokio::sync::mpsc::Sender<JsonEvent>,
+    tx: sink_queue::QueueTx<JsonEvent>,
 }
 
 impl JsonSink {
-    fn new() -> Self {
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<JsonEvent>(65_536);
+    fn new(queue: &sink_queue::QueueCfg) -> Self {
+        let (tx, rx) = sink_queue::queue::<JsonEvent>("json", queue);
+        let errors = rx.errors();
         std::thread::spawn(move || {
             let stdout = std::io::stdout();
             let mut w = std::io::LineWriter::new(stdout.lock());
@@ -167,24 +438,21 @@ impl JsonSink {
             let mut cache32 = Base58Cache::<32>::new(cache_cap);
             let mut cache64 = Base58Cache::<64>::new(cache_cap / 2);
             while let Some(evt) = rx.blocking_recv() {
-                gauge!("ultra_json_queue_depth").set(rx.len() as f64);
                 if write_json_event(&evt, &mut w, &mut cache32, &mut cache64).is_ok() {
                     let _ = w.write_all(b"\n");
+                } else {
+                    errors.add(1);
                 }
             }
         });
         Self { tx }
     }
 
-    fn try_send(&self, evt: JsonEvent) -> bool {
-        self.tx.try_send(evt).is_ok()
+    async fn send(&self, evt: JsonEvent, kind: &'static str) -> bool {
+        self.tx.send(evt, kind).await
     }
 }
 
-static INGEST_SEQ: AtomicU64 = AtomicU64::new(0);
-const INGEST_SAMPLE_MASK: u64 = 0xFF; // sample ~1/256
-const INGEST_SAMPLE_WEIGHT: u64 = 256;
-
 #[derive(Clone, Debug)]
 enum JsonEvent {
     Account {
@@ -196,6 +464,7 @@ enum JsonEvent {
         executable: bool,
         rent_epoch: u64,
         data_len: usize,
+        data: Option<Box<account_data::AccountDataJson>>,
     },
     Tx {
         slot: u64,
@@ -210,6 +479,7 @@ enum JsonEvent {
         rewards_len: u32,
         block_time_unix: Option<i64>,
         leader: Option<[u8; 32]>,
+        block_height: Option<u64>,
     },
     Slot {
         slot: u64,
@@ -219,7 +489,10 @@ enum JsonEvent {
     EndOfStartup,
 }
 
-fn json_event_owned_from_record(rec: &Record) -> JsonEvent {
+fn json_event_owned_from_record(
+    rec: &Record,
+    data_cfg: Option<&account_data::AccountDataCfg>,
+) -> JsonEvent {
     match rec {
         Record::Account(a) => JsonEvent::Account {
             slot: a.slot,
@@ -230,6 +503,7 @@ fn json_event_owned_from_record(rec: &Record) -> JsonEvent {
             executable: a.executable,
             rent_epoch: a.rent_epoch,
             data_len: a.data.len(),
+            data: data_cfg.map(|c| Box::new(account_data::AccountDataJson::new(c, &a.data))),
         },
         Record::Tx(t) => JsonEvent::Tx {
             slot: t.slot,
@@ -244,6 +518,7 @@ fn json_event_owned_from_record(rec: &Record) -> JsonEvent {
             rewards_len: b.rewards_len,
             block_time_unix: b.block_time_unix,
             leader: b.leader,
+            block_height: b.block_height,
         },
         Record::Slot {
             slot,
@@ -260,7 +535,10 @@ fn json_event_owned_from_record(rec: &Record) -> JsonEvent {
 
 #[cfg(feature = "rkyv")]
 #[cfg_attr(feature = "rkyv", allow(dead_code))]
-fn json_event_from_archived_record(rec: &ArchivedRecord) -> JsonEvent {
+fn json_event_from_archived_record(
+    rec: &ArchivedRecord,
+    data_cfg: Option<&account_data::AccountDataCfg>,
+) -> JsonEvent {
     match rec {
         ArchivedRecord::Account(a) => JsonEvent::Account {
             slot: a.slot,
@@ -271,6 +549,7 @@ fn json_event_from_archived_record(rec: &ArchivedRecord) -> JsonEvent {
             executable: a.executable,
             rent_epoch: a.rent_epoch,
             data_len: a.data.len(),
+            data: data_cfg.map(|c| Box::new(account_data::AccountDataJson::new(c, &a.data))),
         },
         ArchivedRecord::Tx(t) => {
             let err = match &t.err {
@@ -301,6 +580,10 @@ fn json_event_from_archived_record(rec: &ArchivedRecord) -> JsonEvent {
                 rkyv::option::ArchivedOption::Some(p) => Some(*p),
                 rkyv::option::ArchivedOption::None => None,
             };
+            let block_height = match &b.block_height {
+                rkyv::option::ArchivedOption::Some(x) => Some(*x),
+                rkyv::option::ArchivedOption::None => None,
+            };
             JsonEvent::Block {
                 slot: b.slot,
                 blockhash,
@@ -308,6 +591,7 @@ fn json_event_from_archived_record(rec: &ArchivedRecord) -> JsonEvent {
                 rewards_len: b.rewards_len,
                 block_time_unix,
                 leader,
+                block_height,
             }
         }
         ArchivedRecord::Slot {
@@ -383,10 +667,12 @@ fn write_json_event<W: Write>(
             executable,
             rent_epoch,
             data_len,
+            data,
         } => {
             let pubkey_b58 = cache32.encode(pubkey);
             let owner_b58 = cache32.encode(owner);
-            let mut m = ser.serialize_map(Some(9))?;
+            let extra = data.as_ref().map_or(0, |d| d.entries());
+            let mut m = ser.serialize_map(Some(9 + extra))?;
             m.serialize_entry("type", "account")?;
             m.serialize_entry("slot", slot)?;
             m.serialize_entry("is_startup", is_startup)?;
@@ -396,6 +682,16 @@ fn write_json_event<W: Write>(
             m.serialize_entry("executable", executable)?;
             m.serialize_entry("rent_epoch", rent_epoch)?;
             m.serialize_entry("data_len", data_len)?;
+            if let Some(d) = data {
+                if let Some((encoding, encoded, truncated)) = &d.data {
+                    m.serialize_entry("data", encoded)?;
+                    m.serialize_entry("data_encoding", encoding.as_str())?;
+                    m.serialize_entry("data_truncated", truncated)?;
+                }
+                if let Some(hex) = &d.prefix_hex {
+                    m.serialize_entry("data_prefix_hex", hex)?;
+                }
+            }
             m.end()
         }
         JsonEvent::Tx {
@@ -420,10 +716,11 @@ fn write_json_event<W: Write>(
             rewards_len,
             block_time_unix,
             leader,
+            block_height,
         } => {
             let blockhash_b58 = blockhash.as_ref().map(|h| cache32.encode(h));
             let leader_b58 = leader.as_ref().map(|l| cache32.encode(l));
-            let mut m = ser.serialize_map(Some(7))?;
+            let mut m = ser.serialize_map(Some(8))?;
             m.serialize_entry("type", "block")?;
             m.serialize_entry("slot", slot)?;
             m.serialize_entry("blockhash", &blockhash_b58.as_ref().map(|s| s.as_ref()))?;
@@ -431,6 +728,7 @@ fn write_json_event<W: Write>(
             m.serialize_entry("rewards_len", rewards_len)?;
             m.serialize_entry("block_time_unix", block_time_unix)?;
             m.serialize_entry("leader", &leader_b58.as_ref().map(|s| s.as_ref()))?;
+            m.serialize_entry("block_height", block_height)?;
             m.end()
         }
         JsonEvent::Slot {
@@ -453,302 +751,506 @@ fn write_json_event<W: Write>(
     }
 }
 
-static RESYNC_EVENTS_THIS_MINUTE: AtomicU64 = AtomicU64::new(0);
+/// Every configured sink plus the router. Output stages share one set through
+/// an `ArcSwap` that a config reload replaces as a whole.
+#[derive(Clone)]
+struct Sinks {
+    router: Arc<routing::RecordRouter>,
+    dedup: Option<Arc<dedup::Deduper>>,
+    reorder: Option<Arc<reorder::ReorderCfg>>,
+    account_data: Option<Arc<account_data::AccountDataCfg>>,
+    json: Option<JsonSink>,
+    ws: Option<ws::WsSink>,
+    #[cfg(feature = "kafka")]
+    kafka: Option<KafkaSink>,
+    #[cfg(feature = "postgres")]
+    pg: Option<pg_sink::PgSink>,
+    #[cfg(feature = "nats")]
+    nats: Option<nats_sink::NatsSink>,
+    #[cfg(feature = "parquet")]
+    parquet: Option<parquet_sink::ParquetSink>,
+    #[cfg(feature = "redis")]
+    redis: Option<redis_sink::RedisSink>,
+    #[cfg(feature = "elasticsearch")]
+    es: Option<es_sink::EsSink>,
+    #[cfg(feature = "grpc")]
+    grpc: Option<grpc_server::GrpcSink>,
+}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse()?))
-        .init();
+impl Sinks {
+    /// Build the sinks for `cfg` (parsed from `raw`). On reload `prev` is the
+    /// running set with the raw config it came from; sinks whose config blocks
+    /// are unchanged are carried over instead of reconnecting.
+    async fn build(
+        cfg: &Cfg,
+        raw: &serde_json::Value,
+        prev: Option<(&Sinks, &serde_json::Value)>,
+    ) -> Result<Self> {
+        let kept = |blocks: &[&str]| {
+            prev.filter(|(_, old)| reload::unchanged(old, raw, blocks))
+                .map(|(p, _)| p)
+        };
 
-    let cfg_path = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "configs/aggregator.json".to_string());
-    let cfg: Cfg = {
-        let raw = std::fs::read_to_string(&cfg_path)?;
-        serde_json::from_str(&raw)?
-    };
+        #[cfg(feature = "kafka")]
+        let kafka_sink = match (kept(&["/kafka", "/sink_queues/kafka"]), cfg.kafka.clone()) {
+            (Some(p), _) => p.kafka.clone(),
+            (None, Some(k)) => Some(KafkaSink::new(k, &cfg.sink_queues.kafka)?),
+            (None, None) => None,
+        };
 
-    if let Some(addr) = &cfg.metrics_addr {
-        let _ = PrometheusBuilder::new()
-            .with_http_listener(addr.parse::<std::net::SocketAddr>().unwrap())
-            .install();
-    }
+        #[cfg(feature = "postgres")]
+        let pg_sink = match (
+            kept(&["/postgres", "/sink_queues/postgres"]),
+            cfg.postgres.clone(),
+        ) {
+            (Some(p), _) => p.pg.clone(),
+            (None, Some(p)) => Some(pg_sink::PgSink::new(p, &cfg.sink_queues.postgres).await?),
+            (None, None) => None,
+        };
 
-    // Export a per-minute gauge for resync events
-    tokio::spawn(async move {
-        let mut tick = time::interval(Duration::from_secs(60));
-        loop {
-            tick.tick().await;
-            let n = RESYNC_EVENTS_THIS_MINUTE.swap(0, Ordering::Relaxed);
-            gauge!("ultra_resync_events_per_minute").set(n as f64);
-        }
-    });
+        #[cfg(feature = "nats")]
+        let nats_sink = match (kept(&["/nats", "/sink_queues/nats"]), cfg.nats.clone()) {
+            (Some(p), _) => p.nats.clone(),
+            (None, Some(n)) => Some(nats_sink::NatsSink::new(n, &cfg.sink_queues.nats).await?),
+            (None, None) => None,
+        };
 
-    // Construct listeners list (multi-listener support with per-socket overrides)
-    let listeners_cfg: Vec<SocketCfg> = if let Some(list) = cfg.listeners.clone() {
-        list
-    } else {
-        vec![SocketCfg {
-            uds_path: cfg.uds_path.clone(),
-            uds_recv_buf_bytes: cfg.uds_recv_buf_bytes,
-            max_frame_bytes: cfg.max_frame_bytes,
-        }]
-    };
+        #[cfg(feature = "parquet")]
+        let parquet_sink = match (
+            kept(&["/parquet", "/sink_queues/parquet"]),
+            cfg.parquet.clone(),
+        ) {
+            (Some(p), _) => p.parquet.clone(),
+            (None, Some(p)) => Some(parquet_sink::ParquetSink::new(p, &cfg.sink_queues.parquet)?),
+            (None, None) => None,
+        };
 
-    #[cfg(feature = "kafka")]
-    let kafka_sink = if let Some(k) = cfg.kafka.clone() {
-        Some(KafkaSink::new(k)?)
-    } else {
-        None
-    };
+        let account_data = cfg.json_account_data.clone().map(Arc::new);
 
-    let json_sink = if cfg.stdout_json {
-        Some(JsonSink::new())
-    } else {
-        None
-    };
+        #[cfg(feature = "redis")]
+        let redis_sink = match (
+            kept(&["/redis", "/sink_queues/redis", "/json_account_data"]),
+            cfg.redis.clone(),
+        ) {
+            (Some(p), _) => p.redis.clone(),
+            (None, Some(r)) => Some(
+                redis_sink::RedisSink::new(r, &cfg.sink_queues.redis, account_data.clone()).await?,
+            ),
+            (None, None) => None,
+        };
 
-    let shutdown = signal::ctrl_c();
-    tokio::pin!(shutdown);
+        #[cfg(feature = "elasticsearch")]
+        let es_sink = match (
+            kept(&[
+                "/elasticsearch",
+                "/sink_queues/elasticsearch",
+                "/json_account_data",
+            ]),
+            cfg.elasticsearch.clone(),
+        ) {
+            (Some(p), _) => p.es.clone(),
+            (None, Some(e)) => Some(
+                es_sink::EsSink::new(e, &cfg.sink_queues.elasticsearch, account_data.clone())
+                    .await?,
+            ),
+            (None, None) => None,
+        };
 
-    // Spawn one accept loop + output stage per listener (shard)
-    for s in listeners_cfg {
-        let json_clone = json_sink.clone();
-        let default_recv = cfg.uds_recv_buf_bytes;
-        let default_mfb = cfg.max_frame_bytes;
-        #[cfg(feature = "kafka")]
-        let ks = kafka_sink.clone();
-        tokio::spawn(async move {
-            let uds_path = s.uds_path.clone();
-            if Path::new(&uds_path).exists() {
-                let _ = std::fs::remove_file(&uds_path);
+        let json_sink = match kept(&["/stdout_json", "/sink_queues/json"]) {
+            Some(p) => p.json.clone(),
+            None if cfg.stdout_json => Some(JsonSink::new(&cfg.sink_queues.json)),
+            None => None,
+        };
+
+        // The WebSocket server keeps its port for the life of the process.
+        let ws_sink = match (kept(&["/ws", "/sink_queues/ws"]), prev, cfg.ws.clone()) {
+            (Some(p), _, _) => p.ws.clone(),
+            (None, Some((p, _)), _) if p.ws.is_some() => {
+                warn!("`ws` settings changed; restart to apply");
+                p.ws.clone()
             }
-            let listener = match UnixListener::bind(&uds_path) {
-                Ok(l) => l,
-                Err(e) => {
-                    error!("failed to bind {}: {e}", uds_path);
-                    return;
-                }
-            };
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                if let Ok(_meta) = std::fs::metadata(&uds_path) {
-                    let _ =
-                        std::fs::set_permissions(&uds_path, std::fs::Permissions::from_mode(0o660));
-                }
+            (None, _, Some(w)) => Some(ws::WsSink::new(w, &cfg.sink_queues.ws).await?),
+            (None, _, None) => None,
+        };
+
+        // Like `ws`, the gRPC server keeps its port for the life of the process.
+        #[cfg(feature = "grpc")]
+        let grpc_sink = match (
+            kept(&["/grpc", "/sink_queues/grpc"]),
+            prev,
+            cfg.grpc.clone(),
+        ) {
+            (Some(p), _, _) => p.grpc.clone(),
+            (None, Some((p, _)), _) if p.grpc.is_some() => {
+                warn!("`grpc` settings changed; restart to apply");
+                p.grpc.clone()
             }
-            info!("listening UDS {}", uds_path);
-
-            let recv_req = s
-                .uds_recv_buf_bytes
-                .or(default_recv)
-                .unwrap_or(32 * 1024 * 1024);
-            let max_frame_bytes = s
-                .max_frame_bytes
-                .or(default_mfb)
-                .unwrap_or(16 * 1024 * 1024);
-            gauge!("ultra_max_frame_bytes").set(max_frame_bytes as f64);
-
-            // Create bounded MPSC for this shard; output stage consumes, producers never await
-            let (out_tx, mut out_rx) = tokio::sync::mpsc::channel::<Record>(65_536);
-
-            // Output stage: single-thread consumer per shard
-            let json_for_out = json_clone.clone();
+            (None, _, Some(g)) => Some(grpc_server::GrpcSink::new(g, &cfg.sink_queues.grpc).await?),
+            (None, _, None) => None,
+        };
+
+        let mut enabled_sinks = Vec::new();
+        if json_sink.is_some() {
+            enabled_sinks.push("json");
+        }
+        if ws_sink.is_some() {
+            enabled_sinks.push("ws");
+        }
+        #[cfg(feature = "postgres")]
+        if pg_sink.is_some() {
+            enabled_sinks.push("postgres");
+        }
+        #[cfg(feature = "nats")]
+        if nats_sink.is_some() {
+            enabled_sinks.push("nats");
+        }
+        #[cfg(feature = "kafka")]
+        if kafka_sink.is_some() {
+            enabled_sinks.push("kafka");
+        }
+        #[cfg(feature = "parquet")]
+        if parquet_sink.is_some() {
+            enabled_sinks.push("parquet");
+        }
+        #[cfg(feature = "redis")]
+        if redis_sink.is_some() {
+            enabled_sinks.push("redis");
+        }
+        #[cfg(feature = "elasticsearch")]
+        if es_sink.is_some() {
+            enabled_sinks.push("elasticsearch");
+        }
+        #[cfg(feature = "grpc")]
+        if grpc_sink.is_some() {
+            enabled_sinks.push("grpc");
+        }
+        Ok(Sinks {
+            router: Arc::new(routing::RecordRouter::new(
+                cfg.routing.as_ref(),
+                &enabled_sinks,
+            )?),
+            // Carried over so a reload does not forget the keys already seen.
+            dedup: match kept(&["/dedup"]) {
+                Some(p) => p.dedup.clone(),
+                None => cfg.dedup.as_ref().map(|d| Arc::new(dedup::Deduper::new(d))),
+            },
+            reorder: match kept(&["/reorder"]) {
+                Some(p) => p.reorder.clone(),
+                None => cfg.reorder.clone().map(Arc::new),
+            },
+            account_data,
+            json: json_sink,
+            ws: ws_sink,
             #[cfg(feature = "kafka")]
-            let ks_for_out = ks.clone();
-            tokio::spawn(async move {
-                loop {
-                    use metrics::gauge;
-                    // update queue depth
-                    gauge!("ultra_output_queue_depth").set(out_rx.len() as f64);
-                    match out_rx.recv().await {
-                        Some(rec) => {
-                            // Tee to JSON (debug) and Kafka (off fast path)
-                            if let Some(js) = &json_for_out {
-                                let evt = json_event_owned_from_record(&rec);
-                                if !js.try_send(evt) {
-                                    counter!("ultra_json_dropped_total").increment(1);
-                                }
-                            }
-                            #[cfg(feature = "kafka")]
-                            if let Some(k) = &ks_for_out {
-                                if !k.try_send(rec) {
-                                    counter!("ultra_kafka_enqueue_dropped_total").increment(1);
-                                }
-                            }
-                            #[cfg(not(feature = "kafka"))]
-                            {
-                                let _ = rec; // no-op when no sinks enabled
-                            }
+            kafka: kafka_sink,
+            #[cfg(feature = "postgres")]
+            pg: pg_sink,
+            #[cfg(feature = "nats")]
+            nats: nats_sink,
+            #[cfg(feature = "parquet")]
+            parquet: parquet_sink,
+            #[cfg(feature = "redis")]
+            redis: redis_sink,
+            #[cfg(feature = "elasticsearch")]
+            es: es_sink,
+            #[cfg(feature = "grpc")]
+            grpc: grpc_sink,
+        })
+    }
+
+    /// Dispatch with the producer's origin timestamp in scope for the sink queues.
+    async fn dispatch_ingested(&self, ing: &ingest::Ingested) {
+        sink_queue::with_origin(ing.origin_ns, self.dispatch(&ing.rec)).await
+    }
+
+    async fn dispatch(&self, rec: &Record) {
+        let kind = routing::record_kind(rec);
+        for target in self.router.route(rec) {
+            match target {
+                SinkTarget::Json => {
+                    if let Some(js) = &self.json {
+                        js.send(
+                            json_event_owned_from_record(rec, self.account_data.as_deref()),
+                            kind,
+                        )
+                        .await;
+                    }
+                }
+                SinkTarget::Ws => {
+                    if let Some(ws) = &self.ws {
+                        ws.send(
+                            json_event_owned_from_record(rec, self.account_data.as_deref()),
+                            kind,
+                        )
+                        .await;
+                    }
+                }
+                SinkTarget::Postgres => {
+                    // Only accounts and transactions have tables
+                    #[cfg(feature = "postgres")]
+                    if let Some(p) = &self.pg {
+                        if matches!(rec, Record::Account(_) | Record::Tx(_)) {
+                            p.send(rec.clone(), kind).await;
                         }
-                        None => break,
                     }
                 }
-            });
-
-            loop {
-                tokio::select! {
-                    Ok((sock, _)) = listener.accept() => {
-                        #[cfg(unix)] {
-                            let sr = SockRef::from(&sock);
-                            let _ = sr.set_recv_buffer_size(recv_req);
-                            if let Ok(actual) = sr.recv_buffer_size() {
-                                info!("UDS recv buffer set: requested={} actual={}", recv_req, actual);
-                                gauge!("ultra_uds_recv_buf_bytes").set(actual as f64);
-                            }
+                SinkTarget::Nats(_subject) =>
+                {
+                    #[cfg(feature = "nats")]
+                    if let Some(n) = &self.nats {
+                        n.send(rec.clone(), _subject.clone()).await;
+                    }
+                }
+                SinkTarget::Kafka(_topic) =>
+                {
+                    #[cfg(feature = "kafka")]
+                    if let Some(k) = &self.kafka {
+                        k.send(rec.clone(), _topic.clone()).await;
+                    }
+                }
+                SinkTarget::Parquet =>
+                {
+                    #[cfg(feature = "parquet")]
+                    if let Some(p) = &self.parquet {
+                        if !matches!(rec, Record::EndOfStartup) {
+                            p.send(rec.clone(), kind).await;
                         }
-                        let out_clone = out_tx.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = handle_client(sock, max_frame_bytes, out_clone).await {
-                                error!("client error: {e:?}");
-                            }
-                        });
                     }
                 }
+                SinkTarget::Redis(_name) =>
+                {
+                    #[cfg(feature = "redis")]
+                    if let Some(r) = &self.redis {
+                        r.send(rec.clone(), _name.clone()).await;
+                    }
+                }
+                SinkTarget::Elasticsearch(_index) =>
+                {
+                    #[cfg(feature = "elasticsearch")]
+                    if let Some(e) = &self.es {
+                        if !matches!(rec, Record::EndOfStartup) {
+                            e.send(rec.clone(), _index.clone()).await;
+                        }
+                    }
+                }
+                SinkTarget::Grpc =>
+                {
+                    #[cfg(feature = "grpc")]
+                    if let Some(g) = &self.grpc {
+                        g.send(rec.clone(), kind).await;
+                    }
+                }
+                SinkTarget::Drop => {}
             }
-        });
+        }
     }
-
-    // Wait for shutdown signal
-    let _ = shutdown.as_mut().await;
-    info!("shutting down");
-    Ok(())
 }
 
-async fn handle_client(
-    mut sock: UnixStream,
-    max_frame_bytes: usize,
-    out: tokio::sync::mpsc::Sender<Record>,
-) -> Result<()> {
-    let mut buf = BytesMut::with_capacity(1 << 20);
-    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
-    loop {
-        // read available bytes directly into the growable buffer
-        let n = sock.read_buf(&mut buf).await?;
-        if n == 0 {
-            break;
-        }
+fn same_arc<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
 
-        // Try to peel records out
+/// Spawn one shard's output stage for the input named `source`. Producers
+/// push into the returned bounded queue with `try_send` and never await.
+/// Sinks swapped in by a reload are picked up before the next record.
+fn spawn_output_stage(
+    live: &Arc<ArcSwap<Sinks>>,
+    source: &str,
+) -> tokio::sync::mpsc::Sender<ingest::Ingested> {
+    let (out_tx, mut out_rx) = tokio::sync::mpsc::channel::<ingest::Ingested>(65_536);
+    let source = source.to_string();
+    let live = live.clone();
+    tokio::spawn(async move {
+        let _stage = shutdown::StageGuard::new();
+        let mut meter = lag::SourceMeter::new(&source);
+        let mut sinks = live.load_full();
+        let mut dedup = sinks
+            .dedup
+            .clone()
+            .map(|d| dedup::SourceDedup::new(d, &source));
+        let mut reorder = sinks.reorder.as_deref().map(reorder::Reorderer::new);
+        let mut ready: Vec<ingest::Ingested> = Vec::new();
         loop {
-            // Safety pre-check: if header present and declared frame size is excessive, resync
-            if buf.len() >= 12 {
-                let ver = buf[0];
-                if ver != faststreams::FRAME_VERSION {
-                    counter!("ultra_decode_bad_header_total").increment(1);
-                    counter!("ultra_resync_events_total").increment(1);
-                    RESYNC_EVENTS_THIS_MINUTE.fetch_add(1, Ordering::Relaxed);
-                    // Drop one byte to attempt resync (no magic field in header)
-                    buf.advance(1);
-                    break;
+            gauge!("ultra_output_queue_depth").set(out_rx.len() as f64);
+            let latest = live.load_full();
+            if !Arc::ptr_eq(&latest, &sinks) {
+                if !same_arc(&latest.dedup, &sinks.dedup) {
+                    dedup = latest
+                        .dedup
+                        .clone()
+                        .map(|d| dedup::SourceDedup::new(d, &source));
                 }
-                // Validate header CRC16 over bytes [0..8)
-                let hdr_crc = u16::from_be_bytes([buf[8], buf[9]]);
-                let calc = {
-                    fn crc16_ccitt(mut data: &[u8]) -> u16 {
-                        let mut crc: u16 = 0xFFFF;
-                        while !data.is_empty() {
-                            crc ^= (data[0] as u16) << 8;
-                            for _ in 0..8 {
-                                if (crc & 0x8000) != 0 {
-                                    crc = (crc << 1) ^ 0x1021;
-                                } else {
-                                    crc <<= 1;
-                                }
-                            }
-                            data = &data[1..];
-                        }
-                        crc
+                if !same_arc(&latest.reorder, &sinks.reorder) {
+                    // Whatever the old window still holds goes out first.
+                    if let Some(r) = reorder.as_mut() {
+                        r.drain(|rec| ready.push(rec));
                     }
-                    crc16_ccitt(&buf[..8])
-                };
-                if hdr_crc != calc {
-                    counter!("ultra_decode_bad_header_total").increment(1);
-                    counter!("ultra_resync_events_total").increment(1);
-                    RESYNC_EVENTS_THIS_MINUTE.fetch_add(1, Ordering::Relaxed);
-                    buf.advance(1);
-                    break;
+                    reorder = latest.reorder.as_deref().map(reorder::Reorderer::new);
                 }
-                let len = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
-                if len > max_frame_bytes {
-                    counter!("ultra_frame_too_large_total").increment(1);
-                    histogram!("ultra_frame_oversize_bytes").record(len as f64);
-                    counter!("ultra_resync_events_total").increment(1);
-                    RESYNC_EVENTS_THIS_MINUTE.fetch_add(1, Ordering::Relaxed);
-                    // Drop one byte to attempt resync
-                    buf.advance(1);
-                    break;
+                sinks = latest;
+                for ing in ready.drain(..) {
+                    sinks.dispatch_ingested(&ing).await;
                 }
             }
-            #[cfg(feature = "rkyv")]
-            {
-                if buf.len() >= 12 {
-                    let flags = buf[1];
-                    if (flags & FLAG_RKYV) != 0 && (flags & FLAG_LZ4) == 0 {
-                        match decode_record_archived_trusted_from_slice(&buf[..]) {
-                            Ok((arec, consumed)) => {
-                                // Convert to owned Record for output stage
-                                let mut map = SharedDeserializeMap::new();
-                                match arec.deserialize(&mut map) {
-                                    Ok(rec) => {
-                                        if out.try_send(rec).is_err() {
-                                            counter!("ultra_output_queue_dropped_total")
-                                                .increment(1);
-                                        }
-                                        let v = INGEST_SEQ.fetch_add(1, Ordering::Relaxed);
-                                        if (v & INGEST_SAMPLE_MASK) == 0 {
-                                            counter!("ultra_records_ingested_total")
-                                                .increment(INGEST_SAMPLE_WEIGHT);
-                                        }
-                                    }
-                                    Err(_) => {
-                                        counter!("ultra_rkyv_deser_errors_total").increment(1);
-                                    }
-                                }
-                                buf.advance(consumed);
-                                continue;
-                            }
-                            Err(_) => { /* fall through to bincode path */ }
+            let Some(r) = reorder.as_mut() else {
+                match out_rx.recv().await {
+                    Some(ing) => {
+                        meter.observe(&ing.rec, ing.origin_ns);
+                        if dedup.as_ref().is_none_or(|d| d.admit(&ing.rec)) {
+                            sinks.dispatch_ingested(&ing).await;
                         }
                     }
+                    None => break,
                 }
-            }
-            match decode_record_from_slice(&buf[..], &mut scratch) {
-                Ok(rec_and_len) => {
-                    let (rec, consumed) = rec_and_len;
-                    let v = INGEST_SEQ.fetch_add(1, Ordering::Relaxed);
-                    if (v & INGEST_SAMPLE_MASK) == 0 {
-                        counter!("ultra_records_ingested_total").increment(INGEST_SAMPLE_WEIGHT);
-                    }
-                    if out.try_send(rec).is_err() {
-                        counter!("ultra_output_queue_dropped_total").increment(1);
+                continue;
+            };
+            let next = match r.next_deadline() {
+                Some(deadline) => tokio::select! {
+                    rec = out_rx.recv() => Some(rec),
+                    _ = time::sleep_until(time::Instant::from_std(deadline)) => None,
+                },
+                None => Some(out_rx.recv().await),
+            };
+            let now = std::time::Instant::now();
+            let closed = match next {
+                Some(Some(ing)) => {
+                    meter.observe(&ing.rec, ing.origin_ns);
+                    if dedup.as_ref().is_none_or(|d| d.admit(&ing.rec)) {
+                        r.push(ing, now, |ing| ready.push(ing));
                     }
-                    buf.advance(consumed);
-                }
-                Err(faststreams::StreamError::BadHeader) => {
-                    counter!("ultra_decode_bad_header_total").increment(1);
-                    counter!("ultra_resync_events_total").increment(1);
-                    RESYNC_EVENTS_THIS_MINUTE.fetch_add(1, Ordering::Relaxed);
-                    // Without a magic marker, drop one byte to attempt resync
-                    buf.advance(1);
-                    break;
+                    false
                 }
-                Err(faststreams::StreamError::De(_)) => {
-                    counter!("ultra_decode_need_more_total").increment(1);
-                    break;
-                } // need more bytes
-                Err(faststreams::StreamError::Io(_)) => {
-                    counter!("ultra_decode_io_total").increment(1);
-                    break;
-                }
-                Err(faststreams::StreamError::Ser(_)) => {
-                    counter!("ultra_decode_ser_total").increment(1);
-                    break;
+                Some(None) => {
+                    r.drain(|rec| ready.push(rec));
+                    true
                 }
+                None => false,
+            };
+            r.flush_expired(now, |rec| ready.push(rec));
+            for ing in ready.drain(..) {
+                sinks.dispatch_ingested(&ing).await;
             }
+            if closed {
+                break;
+            }
+        }
+    });
+    out_tx
+}
+
+impl Cfg {
+    fn frame_limits(&self, max_frame_bytes: Option<usize>) -> ingest::FrameLimits {
+        ingest::FrameLimits {
+            max_frame_bytes: max_frame_bytes
+                .or(self.max_frame_bytes)
+                .unwrap_or(16 * 1024 * 1024),
+            accept_legacy: self.accept_legacy_frames,
+            quarantine: self.quarantine,
         }
     }
+
+    /// UDS listeners by path (multi-listener support with per-socket overrides).
+    fn uds_listeners(&self) -> Vec<(String, uds_input::UdsSettings)> {
+        let listeners = match &self.listeners {
+            Some(list) => list.clone(),
+            None => vec![SocketCfg {
+                uds_path: self.uds_path.clone(),
+                uds_recv_buf_bytes: self.uds_recv_buf_bytes,
+                max_frame_bytes: self.max_frame_bytes,
+            }],
+        };
+        listeners
+            .into_iter()
+            .map(|s| {
+                let settings = uds_input::UdsSettings {
+                    recv_buf_bytes: s
+                        .uds_recv_buf_bytes
+                        .or(self.uds_recv_buf_bytes)
+                        .unwrap_or(32 * 1024 * 1024),
+                    limits: self.frame_limits(s.max_frame_bytes),
+                    auth: self.producer_auth.clone().map(Arc::new),
+                };
+                (s.uds_path, settings)
+            })
+            .collect()
+    }
+}
+
+/// The config at `path`, with its raw JSON for diffing on reload.
+fn read_cfg(path: &str) -> Result<(Cfg, serde_json::Value)> {
+    let raw: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let cfg = serde_json::from_value(raw.clone())?;
+    Ok((cfg, raw))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse()?))
+        .init();
+
+    let cfg_path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "configs/aggregator.json".to_string());
+    let (cfg, raw) = read_cfg(&cfg_path)?;
+
+    if let Some(addr) = &cfg.metrics_addr {
+        let _ = PrometheusBuilder::new()
+            .with_http_listener(addr.parse::<std::net::SocketAddr>().unwrap())
+            .install();
+    }
+
+    // Export a per-minute gauge for resync events
+    tokio::spawn(async move {
+        let mut tick = time::interval(Duration::from_secs(60));
+        loop {
+            tick.tick().await;
+            let n = ingest::RESYNC_EVENTS_THIS_MINUTE.swap(0, Ordering::Relaxed);
+            gauge!("ultra_resync_events_per_minute").set(n as f64);
+        }
+    });
+
+    if let Some(n) = cfg.decode_workers {
+        decode_pool::start(n)?;
+    }
+
+    // Sinks and UDS listeners (one output stage each) are owned by the reloader
+    let live = reload::Live::start(cfg_path, &cfg, raw).await?;
+
+    let (reload_tx, reload_rx) = tokio::sync::mpsc::channel::<admin::ReloadReply>(4);
+    if let Some(a) = &cfg.admin {
+        admin::spawn(a, reload_tx).await?;
+    }
+
+    // TCP listeners are shards too, each with its own output stage
+    for t in cfg.tcp_listeners.clone().unwrap_or_default() {
+        let limits = cfg.frame_limits(t.max_frame_bytes);
+        let out = spawn_output_stage(&live.sinks, &t.bind);
+        tcp_input::bind(&t, limits, cfg.producer_auth.clone().map(Arc::new), out).await?;
+    }
+
+    // Each SHM ring gets a reader thread and its own output stage
+    for r in cfg.shm_inputs.clone().unwrap_or_default() {
+        let limits = cfg.frame_limits(r.max_frame_bytes);
+        let out = spawn_output_stage(&live.sinks, &r.path);
+        shm_input::spawn(r, limits, out)?;
+    }
+
+    tokio::spawn(live.run(reload_rx, cfg.watch_config));
+
+    // Wait for ctrl-c or SIGTERM, then drain what is queued
+    let mut term = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = term.recv() => {}
+    }
+    shutdown::run(Duration::from_millis(
+        cfg.shutdown_timeout_ms.unwrap_or(10_000),
+    ))
+    .await;
     Ok(())
 }
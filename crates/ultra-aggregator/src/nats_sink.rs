@@ -0,0 +1,159 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/nats_sink.rs
+//
+// NATS JetStream sink: bincode records (same payload as the Kafka sink) on one
+// subject per record kind. Publish acks are awaited off the send loop, with at
+// most `max_pending` outstanding; the loop blocks once that window is full.
+use crate::sink_queue;
+use anyhow::{Context, Result};
+use async_nats::jetstream;
+use faststreams::Record;
+use metrics::{counter, gauge, histogram};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct NatsCfg {
+    pub url: String,
+    /// Subjects are `<prefix>.accounts`, `.txs`, `.blocks` and `.slots`.
+    #[serde(default)]
+    pub subject_prefix: Option<String>,
+    /// JetStream domain, for leaf-node deployments.
+    #[serde(default)]
+    pub domain: Option<String>,
+    /// Publishes awaiting an ack before the sink stops sending.
+    #[serde(default)]
+    pub max_pending: Option<usize>,
+    #[serde(default)]
+    pub ack_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Subjects {
+    accounts: String,
+    txs: String,
+    blocks: String,
+    slots: String,
+}
+
+impl Subjects {
+    fn new(prefix: &str) -> Self {
+        Self {
+            accounts: format!("{prefix}.accounts"),
+            txs: format!("{prefix}.txs"),
+            blocks: format!("{prefix}.blocks"),
+            slots: format!("{prefix}.slots"),
+        }
+    }
+
+    /// Subject and metric `kind` label for a record.
+    fn route(&self, rec: &Record) -> (&str, &'static str) {
+        match rec {
+            Record::Account(_) => (&self.accounts, "account"),
+            Record::Tx(_) => (&self.txs, "tx"),
+            Record::Block(_) => (&self.blocks, "block"),
+            Record::Slot { .. } => (&self.slots, "slot"),
+            Record::EndOfStartup => (&self.slots, "end_of_startup"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct NatsSink {
+    tx: sink_queue::QueueTx<(Record, Option<Arc<str>>)>,
+}
+
+impl NatsSink {
+    pub async fn new(cfg: NatsCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
+        let client = async_nats::connect(cfg.url.as_str())
+            .await
+            .with_context(|| format!("nats connect {}", cfg.url))?;
+        let mut js = match &cfg.domain {
+            Some(domain) => jetstream::with_domain(client, domain),
+            None => jetstream::new(client),
+        };
+        js.set_timeout(Duration::from_millis(cfg.ack_timeout_ms.unwrap_or(5_000)));
+        let subjects = Subjects::new(cfg.subject_prefix.as_deref().unwrap_or("ultra"));
+        let max_pending = cfg.max_pending.unwrap_or(4_096).max(1);
+        info!(url = %cfg.url, prefix = ?cfg.subject_prefix, max_pending, "nats sink ready");
+
+        let window = Arc::new(Semaphore::new(max_pending));
+        let (tx, rx) = sink_queue::queue::<(Record, Option<Arc<str>>)>("nats", queue);
+        let errors = rx.errors();
+        tokio::spawn(async move {
+            while let Some((rec, subject_override)) = rx.recv().await {
+                let (subject, kind) = subjects.route(&rec);
+                let subject = subject_override.as_deref().unwrap_or(subject);
+                let payload = match bincode::serialize(&rec) {
+                    Ok(p) => p,
+                    Err(_) => {
+                        counter!("ultra_nats_encode_errors_total", "kind" => kind).increment(1);
+                        errors.add(1);
+                        continue;
+                    }
+                };
+                let Ok(permit) = window.clone().acquire_owned().await else {
+                    break;
+                };
+                gauge!("ultra_nats_pending_acks")
+                    .set((max_pending - window.available_permits()) as f64);
+                let t0 = Instant::now();
+                match js.publish(subject.to_string(), payload.into()).await {
+                    Ok(ack) => {
+                        let errors = errors.clone();
+                        tokio::spawn(async move {
+                            match ack.await {
+                                Ok(_) => {
+                                    counter!("ultra_nats_acked_total", "kind" => kind).increment(1);
+                                    histogram!("ultra_nats_ack_seconds")
+                                        .record(t0.elapsed().as_secs_f64());
+                                }
+                                Err(e) => {
+                                    counter!("ultra_nats_publish_errors_total", "kind" => kind, "stage" => "ack")
+                                        .increment(1);
+                                    errors.add(1);
+                                    error!("nats ack failed: {e}");
+                                }
+                            }
+                            drop(permit);
+                        });
+                    }
+                    Err(e) => {
+                        counter!("ultra_nats_publish_errors_total", "kind" => kind, "stage" => "publish")
+                            .increment(1);
+                        errors.add(1);
+                        error!("nats publish failed: {e}");
+                    }
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    /// `subject` overrides the per-kind subject for this record.
+    pub async fn send(&self, rec: Record, subject: Option<Arc<str>>) -> bool {
+        let kind = crate::routing::record_kind(&rec);
+        self.tx.send((rec, subject), kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_route_to_per_kind_subjects() {
+        let s = Subjects::new("sol");
+        let slot = Record::Slot {
+            slot: 1,
+            parent: None,
+            status: 0,
+        };
+        assert_eq!(s.route(&slot), ("sol.slots", "slot"));
+        assert_eq!(s.route(&Record::EndOfStartup).0, "sol.slots");
+        let cfg: NatsCfg = serde_json::from_str(r#"{"url":"nats://127.0.0.1:4222"}"#).unwrap();
+        assert!(cfg.subject_prefix.is_none() && cfg.max_pending.is_none());
+    }
+}
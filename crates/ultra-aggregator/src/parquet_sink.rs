@@ -0,0 +1,680 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/parquet_sink.rs
+//
+// Parquet archival sink: records are collected into Arrow batches per kind and
+// written as Snappy-compressed Parquet files under
+// `<prefix>/kind=<kind>/date=<YYYY-MM-DD>/part-<unix_ms>-<seq>.parquet` (UTC
+// date at file open), so Athena/DuckDB/Spark can prune by kind and day. A file
+// is uploaded once it reaches `max_rows`, `max_bytes` or `max_age_secs`. Targets
+// an S3-compatible bucket (`endpoint` for MinIO/R2/etc., credentials from the
+// config or the usual AWS_* environment) or, with `local_dir`, a directory.
+use crate::sink_queue;
+use anyhow::{bail, Context, Result};
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, Int64Builder, StringBuilder, UInt32Builder, UInt64Builder,
+    UInt8Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use faststreams::Record;
+use metrics::{counter, histogram};
+use object_store::aws::AmazonS3Builder;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParquetCfg {
+    #[serde(default)]
+    pub bucket: Option<String>,
+    /// Write under this directory instead of a bucket
+    #[serde(default)]
+    pub local_dir: Option<String>,
+    #[serde(default)]
+    pub prefix: Option<String>,
+    #[serde(default)]
+    pub region: Option<String>,
+    /// S3-compatible endpoint URL (MinIO, R2, Ceph, ...)
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+    #[serde(default)]
+    pub secret_access_key: Option<String>,
+    #[serde(default)]
+    pub allow_http: bool,
+    /// Rows buffered before they are encoded as one row group chunk
+    #[serde(default)]
+    pub batch_rows: Option<usize>,
+    #[serde(default)]
+    pub max_rows: Option<usize>,
+    /// Encoded size at which a file is closed and uploaded
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+}
+
+fn object_store(cfg: &ParquetCfg) -> Result<Arc<dyn ObjectStore>> {
+    match (&cfg.bucket, &cfg.local_dir) {
+        (Some(bucket), None) => {
+            let mut b = AmazonS3Builder::from_env()
+                .with_bucket_name(bucket)
+                .with_allow_http(cfg.allow_http);
+            if let Some(region) = &cfg.region {
+                b = b.with_region(region);
+            }
+            if let Some(endpoint) = &cfg.endpoint {
+                b = b.with_endpoint(endpoint);
+            }
+            if let Some(key) = &cfg.access_key_id {
+                b = b.with_access_key_id(key);
+            }
+            if let Some(secret) = &cfg.secret_access_key {
+                b = b.with_secret_access_key(secret);
+            }
+            Ok(Arc::new(b.build().context("parquet s3 store")?))
+        }
+        (None, Some(dir)) => {
+            std::fs::create_dir_all(dir).with_context(|| format!("create {dir}"))?;
+            Ok(Arc::new(LocalFileSystem::new_with_prefix(dir)?))
+        }
+        _ => bail!("parquet sink needs exactly one of bucket or local_dir"),
+    }
+}
+
+/// Column buffers for one record kind.
+enum Rows {
+    Account {
+        slot: UInt64Builder,
+        is_startup: BooleanBuilder,
+        pubkey: StringBuilder,
+        lamports: UInt64Builder,
+        owner: StringBuilder,
+        executable: BooleanBuilder,
+        rent_epoch: UInt64Builder,
+        data: BinaryBuilder,
+    },
+    Tx {
+        slot: UInt64Builder,
+        signature: StringBuilder,
+        err: StringBuilder,
+        vote: BooleanBuilder,
+    },
+    Block {
+        slot: UInt64Builder,
+        blockhash: StringBuilder,
+        parent_slot: UInt64Builder,
+        rewards_len: UInt32Builder,
+        block_time_unix: Int64Builder,
+        leader: StringBuilder,
+        block_height: UInt64Builder,
+    },
+    Slot {
+        slot: UInt64Builder,
+        parent: UInt64Builder,
+        status: UInt8Builder,
+    },
+}
+
+fn field(name: &str, dt: DataType, nullable: bool) -> Field {
+    Field::new(name, dt, nullable)
+}
+
+impl Rows {
+    fn for_kind(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "account" => Rows::Account {
+                slot: UInt64Builder::new(),
+                is_startup: BooleanBuilder::new(),
+                pubkey: StringBuilder::new(),
+                lamports: UInt64Builder::new(),
+                owner: StringBuilder::new(),
+                executable: BooleanBuilder::new(),
+                rent_epoch: UInt64Builder::new(),
+                data: BinaryBuilder::new(),
+            },
+            "tx" => Rows::Tx {
+                slot: UInt64Builder::new(),
+                signature: StringBuilder::new(),
+                err: StringBuilder::new(),
+                vote: BooleanBuilder::new(),
+            },
+            "block" => Rows::Block {
+                slot: UInt64Builder::new(),
+                blockhash: StringBuilder::new(),
+                parent_slot: UInt64Builder::new(),
+                rewards_len: UInt32Builder::new(),
+                block_time_unix: Int64Builder::new(),
+                leader: StringBuilder::new(),
+                block_height: UInt64Builder::new(),
+            },
+            "slot" => Rows::Slot {
+                slot: UInt64Builder::new(),
+                parent: UInt64Builder::new(),
+                status: UInt8Builder::new(),
+            },
+            _ => return None,
+        })
+    }
+
+    fn schema(&self) -> SchemaRef {
+        use DataType::*;
+        let fields = match self {
+            Rows::Account { .. } => vec![
+                field("slot", UInt64, false),
+                field("is_startup", Boolean, false),
+                field("pubkey", Utf8, false),
+                field("lamports", UInt64, false),
+                field("owner", Utf8, false),
+                field("executable", Boolean, false),
+                field("rent_epoch", UInt64, false),
+                field("data", Binary, false),
+            ],
+            Rows::Tx { .. } => vec![
+                field("slot", UInt64, false),
+                field("signature", Utf8, false),
+                field("err", Utf8, true),
+                field("vote", Boolean, false),
+            ],
+            Rows::Block { .. } => vec![
+                field("slot", UInt64, false),
+                field("blockhash", Utf8, true),
+                field("parent_slot", UInt64, true),
+                field("rewards_len", UInt32, false),
+                field("block_time_unix", Int64, true),
+                field("leader", Utf8, true),
+                field("block_height", UInt64, true),
+            ],
+            Rows::Slot { .. } => vec![
+                field("slot", UInt64, false),
+                field("parent", UInt64, true),
+                field("status", UInt8, false),
+            ],
+        };
+        Arc::new(Schema::new(fields))
+    }
+
+    fn push(&mut self, rec: &Record) {
+        let b58 = |b: &[u8]| bs58::encode(b).into_string();
+        match (self, rec) {
+            (
+                Rows::Account {
+                    slot,
+                    is_startup,
+                    pubkey,
+                    lamports,
+                    owner,
+                    executable,
+                    rent_epoch,
+                    data,
+                },
+                Record::Account(a),
+            ) => {
+                slot.append_value(a.slot);
+                is_startup.append_value(a.is_startup);
+                pubkey.append_value(b58(&a.pubkey));
+                lamports.append_value(a.lamports);
+                owner.append_value(b58(&a.owner));
+                executable.append_value(a.executable);
+                rent_epoch.append_value(a.rent_epoch);
+                data.append_value(&a.data);
+            }
+            (
+                Rows::Tx {
+                    slot,
+                    signature,
+                    err,
+                    vote,
+                },
+                Record::Tx(t),
+            ) => {
+                slot.append_value(t.slot);
+                signature.append_value(b58(&t.signature));
+                err.append_option(t.err.as_deref());
+                vote.append_value(t.vote);
+            }
+            (
+                Rows::Block {
+                    slot,
+                    blockhash,
+                    parent_slot,
+                    rewards_len,
+                    block_time_unix,
+                    leader,
+                    block_height,
+                },
+                Record::Block(b),
+            ) => {
+                slot.append_value(b.slot);
+                blockhash.append_option(b.blockhash.map(|h| b58(&h)));
+                parent_slot.append_option(b.parent_slot);
+                rewards_len.append_value(b.rewards_len);
+                block_time_unix.append_option(b.block_time_unix);
+                leader.append_option(b.leader.map(|l| b58(&l)));
+                block_height.append_option(b.block_height);
+            }
+            (
+                Rows::Slot {
+                    slot,
+                    parent,
+                    status,
+                },
+                Record::Slot {
+                    slot: s,
+                    parent: p,
+                    status: st,
+                },
+            ) => {
+                slot.append_value(*s);
+                parent.append_option(*p);
+                status.append_value(*st);
+            }
+            _ => {}
+        }
+    }
+
+    fn len(&self) -> usize {
+        use arrow_array::builder::ArrayBuilder;
+        match self {
+            Rows::Account { slot, .. }
+            | Rows::Tx { slot, .. }
+            | Rows::Block { slot, .. }
+            | Rows::Slot { slot, .. } => slot.len(),
+        }
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        fn a<T: arrow_array::Array + 'static>(x: T) -> ArrayRef {
+            Arc::new(x)
+        }
+        match self {
+            Rows::Account {
+                slot,
+                is_startup,
+                pubkey,
+                lamports,
+                owner,
+                executable,
+                rent_epoch,
+                data,
+            } => vec![
+                a(slot.finish()),
+                a(is_startup.finish()),
+                a(pubkey.finish()),
+                a(lamports.finish()),
+                a(owner.finish()),
+                a(executable.finish()),
+                a(rent_epoch.finish()),
+                a(data.finish()),
+            ],
+            Rows::Tx {
+                slot,
+                signature,
+                err,
+                vote,
+            } => vec![
+                a(slot.finish()),
+                a(signature.finish()),
+                a(err.finish()),
+                a(vote.finish()),
+            ],
+            Rows::Block {
+                slot,
+                blockhash,
+                parent_slot,
+                rewards_len,
+                block_time_unix,
+                leader,
+                block_height,
+            } => vec![
+                a(slot.finish()),
+                a(blockhash.finish()),
+                a(parent_slot.finish()),
+                a(rewards_len.finish()),
+                a(block_time_unix.finish()),
+                a(leader.finish()),
+                a(block_height.finish()),
+            ],
+            Rows::Slot {
+                slot,
+                parent,
+                status,
+            } => vec![a(slot.finish()), a(parent.finish()), a(status.finish())],
+        }
+    }
+}
+
+/// (year, month, day) for a count of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's days_from_civil inverse.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
+
+fn object_path(prefix: &str, kind: &str, opened: SystemTime, seq: u64) -> ObjectPath {
+    let since = opened.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let (y, m, d) = civil_from_days((since.as_secs() / 86_400) as i64);
+    let name = format!(
+        "kind={kind}/date={y:04}-{m:02}-{d:02}/part-{}-{seq:06}.parquet",
+        since.as_millis()
+    );
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        ObjectPath::from(name)
+    } else {
+        ObjectPath::from(format!("{prefix}/{name}"))
+    }
+}
+
+struct Limits {
+    batch_rows: usize,
+    max_rows: usize,
+    max_bytes: usize,
+    max_age: Duration,
+}
+
+struct OpenFile {
+    writer: ArrowWriter<Vec<u8>>,
+    opened: SystemTime,
+    opened_at: Instant,
+    rows: usize,
+}
+
+struct KindWriter {
+    kind: &'static str,
+    rows: Rows,
+    schema: SchemaRef,
+    file: Option<OpenFile>,
+}
+
+struct Archiver {
+    store: Arc<dyn ObjectStore>,
+    prefix: String,
+    limits: Limits,
+    kinds: Vec<KindWriter>,
+    seq: u64,
+    errors: sink_queue::SinkErrors,
+    in_flight: sink_queue::InFlight,
+}
+
+impl Archiver {
+    fn push(&mut self, rec: &Record) -> Result<()> {
+        let kind = crate::routing::record_kind(rec);
+        let idx = match self.kinds.iter().position(|k| k.kind == kind) {
+            Some(i) => i,
+            None => {
+                let Some(rows) = Rows::for_kind(kind) else {
+                    return Ok(());
+                };
+                self.kinds.push(KindWriter {
+                    kind,
+                    schema: rows.schema(),
+                    rows,
+                    file: None,
+                });
+                self.kinds.len() - 1
+            }
+        };
+        self.kinds[idx].rows.push(rec);
+        if self.kinds[idx].rows.len() >= self.limits.batch_rows {
+            self.encode(idx)?;
+        }
+        Ok(())
+    }
+
+    /// Move buffered rows of kind `idx` into its open file, rotating if due.
+    fn encode(&mut self, idx: usize) -> Result<()> {
+        let kw = &mut self.kinds[idx];
+        let n = kw.rows.len();
+        if n > 0 {
+            let batch = RecordBatch::try_new(kw.schema.clone(), kw.rows.finish())?;
+            if kw.file.is_none() {
+                let props = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .build();
+                kw.file = Some(OpenFile {
+                    writer: ArrowWriter::try_new(Vec::new(), kw.schema.clone(), Some(props))?,
+                    opened: SystemTime::now(),
+                    opened_at: Instant::now(),
+                    rows: 0,
+                });
+            }
+            let file = kw.file.as_mut().expect("opened above");
+            file.writer.write(&batch)?;
+            file.rows += n;
+            counter!("ultra_parquet_rows_total", "kind" => kw.kind).increment(n as u64);
+        }
+        if let Some(file) = &kw.file {
+            let size = file.writer.bytes_written() + file.writer.in_progress_size();
+            if file.rows >= self.limits.max_rows
+                || size >= self.limits.max_bytes
+                || file.opened_at.elapsed() >= self.limits.max_age
+            {
+                self.rotate(idx)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Close kind `idx`'s file and upload it in the background.
+    fn rotate(&mut self, idx: usize) -> Result<()> {
+        let kw = &mut self.kinds[idx];
+        let Some(file) = kw.file.take() else {
+            return Ok(());
+        };
+        let bytes = file.writer.into_inner()?;
+        let path = object_path(&self.prefix, kw.kind, file.opened, self.seq);
+        self.seq += 1;
+        let (store, kind, rows) = (self.store.clone(), kw.kind, file.rows);
+        let errors = self.errors.clone();
+        let work = self.in_flight.begin();
+        histogram!("ultra_parquet_file_bytes").record(bytes.len() as f64);
+        tokio::spawn(async move {
+            let _work = work;
+            let t0 = Instant::now();
+            let len = bytes.len();
+            match store.put(&path, bytes.into()).await {
+                Ok(_) => {
+                    counter!("ultra_parquet_files_total", "kind" => kind).increment(1);
+                    histogram!("ultra_parquet_upload_seconds").record(t0.elapsed().as_secs_f64());
+                    info!(%path, rows, bytes = len, "parquet file written");
+                }
+                Err(e) => {
+                    counter!("ultra_parquet_upload_errors_total", "kind" => kind).increment(1);
+                    errors.add(1);
+                    error!(%path, rows, "parquet upload failed: {e}");
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Encode pending rows everywhere, rotating files that are due (all with `force`).
+    fn flush(&mut self, force: bool) {
+        for idx in 0..self.kinds.len() {
+            let res = self
+                .encode(idx)
+                .and_then(|()| if force { self.rotate(idx) } else { Ok(()) });
+            if let Err(e) = res {
+                counter!("ultra_parquet_encode_errors_total").increment(1);
+                self.errors.add(1);
+                error!("parquet {} flush failed: {e:#}", self.kinds[idx].kind);
+                self.kinds[idx].file = None;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ParquetSink {
+    tx: sink_queue::QueueTx<Record>,
+}
+
+impl ParquetSink {
+    pub fn new(cfg: ParquetCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
+        let store = object_store(&cfg)?;
+        let limits = Limits {
+            batch_rows: cfg.batch_rows.unwrap_or(8_192).max(1),
+            max_rows: cfg.max_rows.unwrap_or(5_000_000).max(1),
+            max_bytes: cfg.max_bytes.unwrap_or(128 << 20).max(1),
+            max_age: Duration::from_secs(cfg.max_age_secs.unwrap_or(300).max(1)),
+        };
+        info!(
+            bucket = ?cfg.bucket,
+            local_dir = ?cfg.local_dir,
+            prefix = ?cfg.prefix,
+            max_rows = limits.max_rows,
+            max_bytes = limits.max_bytes,
+            "parquet sink ready"
+        );
+        let (tx, rx) = sink_queue::queue::<Record>("parquet", queue);
+        let mut archiver = Archiver {
+            store,
+            prefix: cfg.prefix.clone().unwrap_or_default(),
+            limits,
+            kinds: Vec::new(),
+            seq: 0,
+            errors: rx.errors(),
+            in_flight: rx.in_flight(),
+        };
+        tokio::spawn(async move {
+            // Age-based rotation is checked at a fraction of the window.
+            let mut tick = tokio::time::interval(
+                (archiver.limits.max_age / 4).max(Duration::from_millis(100)),
+            );
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    rec = rx.recv() => match rec {
+                        Some(rec) => {
+                            if let Err(e) = archiver.push(&rec) {
+                                counter!("ultra_parquet_encode_errors_total").increment(1);
+                                archiver.errors.add(1);
+                                error!("parquet encode failed: {e:#}");
+                            }
+                        }
+                        None => {
+                            archiver.flush(true);
+                            break;
+                        }
+                    },
+                    _ = tick.tick() => archiver.flush(false),
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    pub async fn send(&self, rec: Record, kind: &'static str) -> bool {
+        self.tx.send(rec, kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::UInt64Type;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        let p = object_path(
+            "/archive/",
+            "tx",
+            UNIX_EPOCH + Duration::from_secs(86_400),
+            7,
+        );
+        assert_eq!(
+            p.as_ref(),
+            "archive/kind=tx/date=1970-01-02/part-86400000-000007.parquet"
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_partitioned_files_to_local_dir() {
+        let dir = std::env::temp_dir().join(format!("ultra-parquet-{}", std::process::id()));
+        let cfg = ParquetCfg {
+            bucket: None,
+            local_dir: Some(dir.display().to_string()),
+            prefix: Some("solana".into()),
+            region: None,
+            endpoint: None,
+            access_key_id: None,
+            secret_access_key: None,
+            allow_http: false,
+            batch_rows: Some(2),
+            max_rows: None,
+            max_bytes: None,
+            max_age_secs: None,
+        };
+        let sink = ParquetSink::new(cfg, &sink_queue::QueueCfg::default()).unwrap();
+        for slot in [3u64, 4, 5] {
+            let rec = Record::Slot {
+                slot,
+                parent: Some(slot - 1),
+                status: 1,
+            };
+            assert!(sink.send(rec, "slot").await);
+        }
+        // Dropping the last sender closes the queue, which flushes and uploads.
+        drop(sink);
+        let kind_dir = dir.join("solana/kind=slot");
+        let file = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let found = walk(&kind_dir);
+                if let Some(f) = found {
+                    return f;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&file).unwrap())
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut slots = Vec::new();
+        for batch in reader {
+            let batch = batch.unwrap();
+            let col = batch.column_by_name("slot").unwrap();
+            slots.extend(col.as_primitive::<UInt64Type>().values().iter().copied());
+        }
+        assert_eq!(slots, [3, 4, 5]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn walk(dir: &std::path::Path) -> Option<std::path::PathBuf> {
+        for entry in std::fs::read_dir(dir).ok()? {
+            let path = entry.ok()?.path();
+            if path.is_dir() {
+                if let Some(f) = walk(&path) {
+                    return Some(f);
+                }
+            } else if path.extension().is_some_and(|e| e == "parquet") {
+                return Some(path);
+            }
+        }
+        None
+    }
+}
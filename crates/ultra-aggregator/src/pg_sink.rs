@@ -0,0 +1,402 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/pg_sink.rs
+//
+// PostgreSQL sink: account and transaction batches are loaded with binary COPY
+// over a small connection pool. In `upsert` mode accounts land in a latest-state
+// table keyed by pubkey (COPY into a temp staging table, then one
+// INSERT .. ON CONFLICT per batch) so smaller deployments can skip Kafka.
+use crate::sink_queue;
+use anyhow::{Context, Result};
+use deadpool_postgres::{Pool, PoolConfig, Runtime};
+use faststreams::{AccountUpdate, Record, TxUpdate};
+use metrics::{counter, histogram};
+use std::time::{Duration, Instant};
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::NoTls;
+use tracing::{error, info};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountMode {
+    /// Every update becomes a row.
+    #[default]
+    Append,
+    /// One row per pubkey holding the highest-slot update seen.
+    Upsert,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct PgCfg {
+    /// libpq-style connection string or `postgres://` URL.
+    pub url: String,
+    #[serde(default)]
+    pub pool_size: Option<usize>,
+    /// Rows per COPY before a flush is forced.
+    #[serde(default)]
+    pub batch_max: Option<usize>,
+    #[serde(default)]
+    pub flush_ms: Option<u64>,
+    #[serde(default)]
+    pub accounts_table: Option<String>,
+    #[serde(default)]
+    pub txs_table: Option<String>,
+    #[serde(default)]
+    pub account_mode: AccountMode,
+    /// Store account data bytes; off keeps rows small for balance/owner tracking.
+    #[serde(default = "default_true")]
+    pub account_data: bool,
+    /// Run `CREATE TABLE IF NOT EXISTS` at startup.
+    #[serde(default = "default_true")]
+    pub create_tables: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// Postgres has no unsigned integers; u64 values are stored bit-for-bit as BIGINT
+// (e.g. the u64::MAX rent epoch of rent-exempt accounts reads back as -1).
+const ACCOUNT_COLUMNS: &str =
+    "slot, pubkey, lamports, owner, executable, rent_epoch, is_startup, data";
+const ACCOUNT_TYPES: [Type; 8] = [
+    Type::INT8,
+    Type::BYTEA,
+    Type::INT8,
+    Type::BYTEA,
+    Type::BOOL,
+    Type::INT8,
+    Type::BOOL,
+    Type::BYTEA,
+];
+/// The upsert staging table adds each row's position in its batch, so two
+/// updates to a pubkey in the same slot resolve to the later one.
+const STAGE_TYPES: [Type; 9] = [
+    Type::INT8,
+    Type::BYTEA,
+    Type::INT8,
+    Type::BYTEA,
+    Type::BOOL,
+    Type::INT8,
+    Type::BOOL,
+    Type::BYTEA,
+    Type::INT8,
+];
+const TX_COLUMNS: &str = "slot, signature, err, vote";
+const TX_TYPES: [Type; 4] = [Type::INT8, Type::BYTEA, Type::TEXT, Type::BOOL];
+
+fn accounts_ddl(table: &str, mode: AccountMode) -> String {
+    let key = match mode {
+        AccountMode::Append => "",
+        AccountMode::Upsert => " PRIMARY KEY",
+    };
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (\
+         slot BIGINT NOT NULL, pubkey BYTEA NOT NULL{key}, lamports BIGINT NOT NULL, \
+         owner BYTEA NOT NULL, executable BOOLEAN NOT NULL, rent_epoch BIGINT NOT NULL, \
+         is_startup BOOLEAN NOT NULL, data BYTEA)"
+    )
+}
+
+fn txs_ddl(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (\
+         slot BIGINT NOT NULL, signature BYTEA NOT NULL, err TEXT, vote BOOLEAN NOT NULL)"
+    )
+}
+
+fn staging_table(table: &str) -> String {
+    format!("{}_stage", table.replace('.', "_"))
+}
+
+// Keeps the newest update per pubkey within the batch (the last to arrive when
+// slots tie), and never lets an older slot overwrite a newer row from an
+// earlier (or concurrent) batch.
+fn upsert_sql(table: &str) -> String {
+    let stage = staging_table(table);
+    format!(
+        "INSERT INTO {table} AS t ({ACCOUNT_COLUMNS}) \
+         SELECT DISTINCT ON (pubkey) {ACCOUNT_COLUMNS} FROM {stage} \
+         ORDER BY pubkey, slot DESC, seq DESC \
+         ON CONFLICT (pubkey) DO UPDATE SET slot = EXCLUDED.slot, lamports = EXCLUDED.lamports, \
+         owner = EXCLUDED.owner, executable = EXCLUDED.executable, \
+         rent_epoch = EXCLUDED.rent_epoch, is_startup = EXCLUDED.is_startup, data = EXCLUDED.data \
+         WHERE t.slot <= EXCLUDED.slot"
+    )
+}
+
+#[derive(Clone)]
+pub struct PgSink {
+    tx: sink_queue::QueueTx<Record>,
+}
+
+struct Tables {
+    accounts: String,
+    txs: String,
+    mode: AccountMode,
+    account_data: bool,
+}
+
+impl PgSink {
+    pub async fn new(cfg: PgCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
+        let pool_size = cfg.pool_size.unwrap_or(4).max(1);
+        let mut pg = deadpool_postgres::Config::new();
+        pg.url = Some(cfg.url.clone());
+        pg.pool = Some(PoolConfig::new(pool_size));
+        let pool = pg
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .context("postgres pool")?;
+        let tables = std::sync::Arc::new(Tables {
+            accounts: cfg
+                .accounts_table
+                .clone()
+                .unwrap_or_else(|| "ultra_accounts".to_string()),
+            txs: cfg
+                .txs_table
+                .clone()
+                .unwrap_or_else(|| "ultra_txs".to_string()),
+            mode: cfg.account_mode,
+            account_data: cfg.account_data,
+        });
+        if cfg.create_tables {
+            let client = pool.get().await.context("postgres connect")?;
+            client
+                .batch_execute(&format!(
+                    "{}; {}",
+                    accounts_ddl(&tables.accounts, tables.mode),
+                    txs_ddl(&tables.txs)
+                ))
+                .await
+                .context("postgres create tables")?;
+        }
+        info!(
+            accounts = %tables.accounts,
+            txs = %tables.txs,
+            mode = ?tables.mode,
+            pool_size,
+            "postgres sink ready"
+        );
+
+        let batch_max = cfg.batch_max.unwrap_or(10_000).max(1);
+        let flush_every = Duration::from_millis(cfg.flush_ms.unwrap_or(200).max(1));
+        let (tx, rx) = sink_queue::queue::<Record>("postgres", queue);
+        let flush = Flusher {
+            pool,
+            tables,
+            errors: rx.errors(),
+            in_flight: rx.in_flight(),
+            permits: std::sync::Arc::new(tokio::sync::Semaphore::new(pool_size)),
+        };
+        tokio::spawn(async move {
+            let mut accounts: Vec<AccountUpdate> = Vec::with_capacity(batch_max);
+            let mut txs: Vec<TxUpdate> = Vec::with_capacity(batch_max);
+            let mut tick = tokio::time::interval(flush_every);
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                let closed = tokio::select! {
+                    rec = rx.recv() => match rec {
+                        Some(Record::Account(a)) => { accounts.push(a); false }
+                        Some(Record::Tx(t)) => { txs.push(t); false }
+                        Some(_) => false,
+                        None => true,
+                    },
+                    _ = tick.tick() => {
+                        flush.accounts(&mut accounts).await;
+                        flush.txs(&mut txs).await;
+                        false
+                    }
+                };
+                if accounts.len() >= batch_max {
+                    flush.accounts(&mut accounts).await;
+                }
+                if txs.len() >= batch_max {
+                    flush.txs(&mut txs).await;
+                }
+                if closed {
+                    flush.accounts(&mut accounts).await;
+                    flush.txs(&mut txs).await;
+                    break;
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    pub async fn send(&self, rec: Record, kind: &'static str) -> bool {
+        self.tx.send(rec, kind).await
+    }
+}
+
+struct Flusher {
+    pool: Pool,
+    tables: std::sync::Arc<Tables>,
+    errors: sink_queue::SinkErrors,
+    in_flight: sink_queue::InFlight,
+    /// One per pooled connection; the receive loop waits for a free one, so a
+    /// slow database backs up into the sink queue instead of piling up COPYs.
+    permits: std::sync::Arc<tokio::sync::Semaphore>,
+}
+
+// Each flush runs on its own pooled connection, at most `pool_size` at a time.
+impl Flusher {
+    async fn accounts(&self, batch: &mut Vec<AccountUpdate>) {
+        if batch.is_empty() {
+            return;
+        }
+        let Ok(permit) = self.permits.clone().acquire_owned().await else {
+            return;
+        };
+        let rows = std::mem::take(batch);
+        let (pool, tables, errors) = (self.pool.clone(), self.tables.clone(), self.errors.clone());
+        let work = self.in_flight.begin();
+        tokio::spawn(async move {
+            let _work = (work, permit);
+            let t0 = Instant::now();
+            let n = rows.len();
+            match copy_accounts(&pool, &tables, rows).await {
+                Ok(()) => {
+                    counter!("ultra_pg_rows_total", "table" => "accounts").increment(n as u64);
+                    histogram!("ultra_pg_flush_seconds", "table" => "accounts")
+                        .record(t0.elapsed().as_secs_f64());
+                }
+                Err(e) => {
+                    counter!("ultra_pg_errors_total", "table" => "accounts").increment(1);
+                    errors.add(1);
+                    counter!("ultra_pg_rows_dropped_total", "table" => "accounts")
+                        .increment(n as u64);
+                    error!("postgres account flush failed: {e:#}");
+                }
+            }
+        });
+    }
+
+    async fn txs(&self, batch: &mut Vec<TxUpdate>) {
+        if batch.is_empty() {
+            return;
+        }
+        let Ok(permit) = self.permits.clone().acquire_owned().await else {
+            return;
+        };
+        let rows = std::mem::take(batch);
+        let (pool, tables, errors) = (self.pool.clone(), self.tables.clone(), self.errors.clone());
+        let work = self.in_flight.begin();
+        tokio::spawn(async move {
+            let _work = (work, permit);
+            let t0 = Instant::now();
+            let n = rows.len();
+            match copy_txs(&pool, &tables.txs, rows).await {
+                Ok(()) => {
+                    counter!("ultra_pg_rows_total", "table" => "txs").increment(n as u64);
+                    histogram!("ultra_pg_flush_seconds", "table" => "txs")
+                        .record(t0.elapsed().as_secs_f64());
+                }
+                Err(e) => {
+                    counter!("ultra_pg_errors_total", "table" => "txs").increment(1);
+                    errors.add(1);
+                    counter!("ultra_pg_rows_dropped_total", "table" => "txs").increment(n as u64);
+                    error!("postgres tx flush failed: {e:#}");
+                }
+            }
+        });
+    }
+}
+
+async fn copy_accounts(pool: &Pool, tables: &Tables, rows: Vec<AccountUpdate>) -> Result<()> {
+    let mut client = pool.get().await?;
+    let txn = client.transaction().await?;
+    let staged = tables.mode == AccountMode::Upsert;
+    let (target, columns, types) = if staged {
+        let stage = staging_table(&tables.accounts);
+        txn.batch_execute(&format!(
+            "CREATE TEMP TABLE IF NOT EXISTS {stage} \
+             (LIKE {} INCLUDING DEFAULTS, seq BIGINT NOT NULL) ON COMMIT DELETE ROWS",
+            tables.accounts
+        ))
+        .await?;
+        (stage, format!("{ACCOUNT_COLUMNS}, seq"), &STAGE_TYPES[..])
+    } else {
+        (
+            tables.accounts.clone(),
+            ACCOUNT_COLUMNS.to_string(),
+            &ACCOUNT_TYPES[..],
+        )
+    };
+    let sink = txn
+        .copy_in(&format!("COPY {target} ({columns}) FROM STDIN BINARY"))
+        .await?;
+    let mut writer = std::pin::pin!(BinaryCopyInWriter::new(sink, types));
+    for (seq, a) in rows.iter().enumerate() {
+        let data: Option<&[u8]> = tables.account_data.then_some(a.data.as_slice());
+        let seq = seq as i64;
+        let row: [&(dyn ToSql + Sync); 9] = [
+            &(a.slot as i64),
+            &&a.pubkey[..],
+            &(a.lamports as i64),
+            &&a.owner[..],
+            &a.executable,
+            &(a.rent_epoch as i64),
+            &a.is_startup,
+            &data,
+            &seq,
+        ];
+        writer.as_mut().write(&row[..types.len()]).await?;
+    }
+    writer.as_mut().finish().await?;
+    if staged {
+        txn.batch_execute(&upsert_sql(&tables.accounts)).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+async fn copy_txs(pool: &Pool, table: &str, rows: Vec<TxUpdate>) -> Result<()> {
+    let client = pool.get().await?;
+    let sink = client
+        .copy_in(&format!("COPY {table} ({TX_COLUMNS}) FROM STDIN BINARY"))
+        .await?;
+    let mut writer = std::pin::pin!(BinaryCopyInWriter::new(sink, &TX_TYPES));
+    for t in &rows {
+        writer
+            .as_mut()
+            .write(&[
+                &(t.slot as i64) as &(dyn ToSql + Sync),
+                &&t.signature[..],
+                &t.err,
+                &t.vote,
+            ])
+            .await?;
+    }
+    writer.as_mut().finish().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upsert_keeps_newest_slot_per_pubkey() {
+        let sql = upsert_sql("public.accounts");
+        assert!(sql.contains("FROM public_accounts_stage"));
+        assert!(sql.contains("DISTINCT ON (pubkey)"));
+        assert!(sql.contains("ORDER BY pubkey, slot DESC, seq DESC"));
+        assert!(sql.contains("ON CONFLICT (pubkey)"));
+        assert!(sql.ends_with("WHERE t.slot <= EXCLUDED.slot"));
+        assert!(
+            accounts_ddl("a", AccountMode::Upsert).contains("pubkey BYTEA NOT NULL PRIMARY KEY")
+        );
+        assert!(!accounts_ddl("a", AccountMode::Append).contains("PRIMARY KEY"));
+    }
+
+    #[test]
+    fn config_defaults_to_append_with_data() {
+        let cfg: PgCfg = serde_json::from_str(r#"{"url":"host=localhost"}"#).unwrap();
+        assert_eq!(cfg.account_mode, AccountMode::Append);
+        assert!(cfg.account_data && cfg.create_tables);
+        let cfg: PgCfg =
+            serde_json::from_str(r#"{"url":"x","account_mode":"upsert","account_data":false}"#)
+                .unwrap();
+        assert_eq!(cfg.account_mode, AccountMode::Upsert);
+        assert!(!cfg.account_data);
+    }
+}
@@ -0,0 +1,153 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/producer_auth.rs
+//
+// Optional producer authentication, `"producer_auth": {"token": ...,
+// "uds_allowed_uids": [...]}`. With a token, the first frame on every UDS or
+// TCP connection must be a faststreams auth frame (`encode_auth_frame`, sent by
+// ys-consumer with `YS_AUTH_TOKEN` and by geyser-plugin-ultra with
+// `auth_token`) carrying it, within `AUTH_TIMEOUT`;
+// anything else closes the connection before a record is decoded. With
+// `uds_allowed_uids`, Unix socket peers (SO_PEERCRED) outside the list are
+// closed on accept. Rejections are counted in
+// `ultra_producer_auth_rejected_total{reason}`.
+use anyhow::{bail, Result};
+use bytes::{Buf, BytesMut};
+use faststreams::{FrameHeader, FRAME_HEADER_LEN};
+use metrics::counter;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UnixStream;
+use tracing::warn;
+
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_TOKEN_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProducerAuthCfg {
+    /// Shared token producers present in their first frame
+    #[serde(default)]
+    pub token: Option<String>,
+    /// UIDs allowed to connect to the Unix sockets
+    #[serde(default)]
+    pub uds_allowed_uids: Option<Vec<u32>>,
+}
+
+fn reject(reason: &'static str) {
+    counter!("ultra_producer_auth_rejected_total", "reason" => reason).increment(1);
+}
+
+/// Whether the peer of a Unix socket connection is on `uds_allowed_uids`.
+pub fn uid_allowed(cfg: &ProducerAuthCfg, sock: &UnixStream) -> bool {
+    let Some(uids) = &cfg.uds_allowed_uids else {
+        return true;
+    };
+    match sock.peer_cred() {
+        Ok(cred) if uids.contains(&cred.uid()) => true,
+        Ok(cred) => {
+            warn!(uid = cred.uid(), pid = ?cred.pid(), "UDS producer not in uds_allowed_uids; closing");
+            reject("uid");
+            false
+        }
+        Err(e) => {
+            warn!("UDS peer credentials unavailable: {e}; closing");
+            reject("uid");
+            false
+        }
+    }
+}
+
+/// Read the connection's first frame into `buf` and check that it is an auth
+/// frame carrying `token`. Whatever follows it stays in `buf`.
+pub async fn authenticate<R: AsyncRead + Unpin>(
+    sock: &mut R,
+    buf: &mut BytesMut,
+    token: &str,
+) -> Result<()> {
+    let presented = match tokio::time::timeout(AUTH_TIMEOUT, read_auth_frame(sock, buf)).await {
+        Ok(res) => res?,
+        Err(_) => {
+            reject("timeout");
+            bail!("no auth frame within {AUTH_TIMEOUT:?}");
+        }
+    };
+    if !constant_time_eq(&presented, token.as_bytes()) {
+        reject("bad_token");
+        bail!("wrong producer token");
+    }
+    counter!("ultra_producer_auth_accepted_total").increment(1);
+    Ok(())
+}
+
+async fn read_auth_frame<R: AsyncRead + Unpin>(
+    sock: &mut R,
+    buf: &mut BytesMut,
+) -> Result<Vec<u8>> {
+    loop {
+        match FrameHeader::parse(buf) {
+            Ok(Some(hdr)) if !hdr.is_auth() => {
+                reject("missing");
+                bail!("first frame is not an auth frame");
+            }
+            Ok(Some(hdr)) if hdr.payload_len as usize > MAX_TOKEN_BYTES => {
+                reject("bad_token");
+                bail!("auth frame of {} bytes", hdr.payload_len);
+            }
+            Ok(Some(hdr)) if buf.len() >= hdr.frame_len() => {
+                let token = buf[FRAME_HEADER_LEN..hdr.frame_len()].to_vec();
+                buf.advance(hdr.frame_len());
+                return Ok(token);
+            }
+            Ok(_) => {}
+            Err(_) => {
+                reject("missing");
+                bail!("first frame has a bad header");
+            }
+        }
+        if sock.read_buf(buf).await? == 0 {
+            reject("closed");
+            bail!("connection closed before authenticating");
+        }
+    }
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{encode_auth_frame, encode_record, Record};
+
+    #[tokio::test]
+    async fn first_frame_must_carry_the_token() {
+        let slot = encode_record(&Record::Slot {
+            slot: 3,
+            parent: None,
+            status: 0,
+        })
+        .unwrap();
+        let mut stream = encode_auth_frame(b"s3cret");
+        stream.extend_from_slice(&slot);
+
+        let mut buf = BytesMut::new();
+        authenticate(&mut stream.as_slice(), &mut buf, "s3cret")
+            .await
+            .unwrap();
+        assert_eq!(&buf[..], &slot[..]);
+
+        let mut buf = BytesMut::new();
+        assert!(authenticate(&mut stream.as_slice(), &mut buf, "other")
+            .await
+            .is_err());
+        let mut buf = BytesMut::new();
+        assert!(authenticate(&mut slot.as_slice(), &mut buf, "s3cret")
+            .await
+            .is_err());
+        let mut buf = BytesMut::new();
+        assert!(authenticate(&mut &stream[..10], &mut buf, "s3cret")
+            .await
+            .is_err());
+    }
+}
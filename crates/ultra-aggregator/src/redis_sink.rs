@@ -0,0 +1,343 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/redis_sink.rs
+//
+// Redis sink: PUBLISH for realtime fan-out and/or XADD for replayable streams.
+// Channel and stream names are templates with `{kind}`, `{slot}`, and for
+// accounts `{owner}` and `{pubkey}` (base58); a per-kind template overrides
+// the default, and a kind with no template is not sent for that command.
+//
+//   "redis": {"url": "redis://127.0.0.1/", "channel": "ultra.{kind}",
+//             "stream": "ultra:{kind}", "kind_streams": {"account": "ultra:acct:{owner}"},
+//             "stream_maxlen": 1000000}
+//
+// Records already queued when the writer wakes go out in one pipeline (up to
+// `batch_max`). Payloads are the stdout JSON events, or bincode records with
+// `"format": "bincode"`; stream entries carry `kind`, `slot` and `data` fields.
+use crate::account_data::AccountDataCfg;
+use crate::routing::record_kind;
+use crate::{json_event_owned_from_record, sink_queue, write_json_event, Base58Cache};
+use anyhow::{bail, Context, Result};
+use faststreams::Record;
+use metrics::{counter, histogram};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info};
+
+const KINDS: [&str; 5] = ["account", "tx", "block", "slot", "end_of_startup"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisFormat {
+    #[default]
+    Json,
+    Bincode,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisCfg {
+    pub url: String,
+    /// PUBLISH channel template
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub kind_channels: HashMap<String, String>,
+    /// XADD stream template
+    #[serde(default)]
+    pub stream: Option<String>,
+    #[serde(default)]
+    pub kind_streams: HashMap<String, String>,
+    /// Approximate per-stream cap (`MAXLEN ~`); unbounded when omitted
+    #[serde(default)]
+    pub stream_maxlen: Option<u64>,
+    #[serde(default)]
+    pub format: RedisFormat,
+    /// Records per pipeline
+    #[serde(default)]
+    pub batch_max: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Lit(String),
+    Kind,
+    Slot,
+    Owner,
+    Pubkey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template(Vec<Part>);
+
+impl Template {
+    fn parse(spec: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = spec;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(Part::Lit(rest[..open].to_string()));
+            }
+            let Some(close) = rest[open..].find('}') else {
+                bail!("unclosed placeholder in {spec}");
+            };
+            parts.push(match &rest[open + 1..open + close] {
+                "kind" => Part::Kind,
+                "slot" => Part::Slot,
+                "owner" => Part::Owner,
+                "pubkey" => Part::Pubkey,
+                other => bail!("unknown placeholder {{{other}}} in {spec}"),
+            });
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Lit(rest.to_string()));
+        }
+        Ok(Self(parts))
+    }
+
+    /// Render into `out`, or use the route's `name` override as is.
+    fn render_or(&self, name: Option<&str>, rec: &Record, out: &mut String) {
+        match name {
+            Some(n) => {
+                out.clear();
+                out.push_str(n);
+            }
+            None => self.render(rec, out),
+        }
+    }
+
+    fn render(&self, rec: &Record, out: &mut String) {
+        use std::fmt::Write;
+        out.clear();
+        for part in &self.0 {
+            match part {
+                Part::Lit(s) => out.push_str(s),
+                Part::Kind => out.push_str(record_kind(rec)),
+                Part::Slot => {
+                    if let Some(slot) = record_slot(rec) {
+                        let _ = write!(out, "{slot}");
+                    }
+                }
+                Part::Owner => {
+                    if let Record::Account(a) = rec {
+                        out.push_str(&bs58::encode(a.owner).into_string());
+                    }
+                }
+                Part::Pubkey => {
+                    if let Record::Account(a) = rec {
+                        out.push_str(&bs58::encode(a.pubkey).into_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn record_slot(rec: &Record) -> Option<u64> {
+    match rec {
+        Record::Account(a) => Some(a.slot),
+        Record::Tx(t) => Some(t.slot),
+        Record::Block(b) => Some(b.slot),
+        Record::Slot { slot, .. } => Some(*slot),
+        Record::EndOfStartup => None,
+    }
+}
+
+/// Templates for one command: the default plus per-kind overrides.
+#[derive(Debug, Default)]
+struct Names {
+    default: Option<Template>,
+    per_kind: HashMap<&'static str, Template>,
+}
+
+impl Names {
+    fn new(default: Option<&str>, per_kind: &HashMap<String, String>, what: &str) -> Result<Self> {
+        let mut names = Names {
+            default: default.map(Template::parse).transpose()?,
+            per_kind: HashMap::new(),
+        };
+        for (kind, spec) in per_kind {
+            let Some(kind) = KINDS.iter().find(|k| *k == kind) else {
+                bail!("unknown record kind {kind} in redis kind_{what}s");
+            };
+            names.per_kind.insert(kind, Template::parse(spec)?);
+        }
+        Ok(names)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.default.is_none() && self.per_kind.is_empty()
+    }
+
+    fn get(&self, kind: &str) -> Option<&Template> {
+        self.per_kind.get(kind).or(self.default.as_ref())
+    }
+}
+
+struct Encoder {
+    format: RedisFormat,
+    account_data: Option<Arc<AccountDataCfg>>,
+    cache32: Base58Cache<32>,
+    cache64: Base58Cache<64>,
+}
+
+impl Encoder {
+    fn encode(&mut self, rec: &Record, out: &mut Vec<u8>) -> Result<()> {
+        out.clear();
+        match self.format {
+            RedisFormat::Json => {
+                let evt = json_event_owned_from_record(rec, self.account_data.as_deref());
+                write_json_event(&evt, out, &mut self.cache32, &mut self.cache64)?;
+            }
+            RedisFormat::Bincode => bincode::serialize_into(out, rec)?,
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct RedisSink {
+    tx: sink_queue::QueueTx<(Record, Option<Arc<str>>)>,
+}
+
+impl RedisSink {
+    pub async fn new(
+        cfg: RedisCfg,
+        queue: &sink_queue::QueueCfg,
+        account_data: Option<Arc<AccountDataCfg>>,
+    ) -> Result<Self> {
+        let channels = Names::new(cfg.channel.as_deref(), &cfg.kind_channels, "channel")?;
+        let streams = Names::new(cfg.stream.as_deref(), &cfg.kind_streams, "stream")?;
+        if channels.is_empty() && streams.is_empty() {
+            bail!("redis sink needs a channel or stream template");
+        }
+        let client = redis::Client::open(cfg.url.as_str())
+            .with_context(|| format!("redis url {}", cfg.url))?;
+        let mut conn = redis::aio::ConnectionManager::new(client)
+            .await
+            .with_context(|| format!("redis connect {}", cfg.url))?;
+        let batch_max = cfg.batch_max.unwrap_or(512).max(1);
+        info!(url = %cfg.url, ?cfg.format, batch_max, "redis sink ready");
+
+        let mut enc = Encoder {
+            format: cfg.format,
+            account_data,
+            cache32: Base58Cache::new(16_384),
+            cache64: Base58Cache::new(8_192),
+        };
+        let maxlen = cfg.stream_maxlen;
+        let (tx, rx) = sink_queue::queue::<(Record, Option<Arc<str>>)>("redis", queue);
+        let errors = rx.errors();
+        tokio::spawn(async move {
+            let mut payload = Vec::with_capacity(512);
+            let mut name = String::new();
+            while let Some(first) = rx.recv().await {
+                let mut pipe = redis::pipe();
+                let mut records = 0usize;
+                let mut next = Some(first);
+                while let Some((rec, name_override)) = next.take() {
+                    let kind = record_kind(&rec);
+                    if enc.encode(&rec, &mut payload).is_err() {
+                        counter!("ultra_redis_encode_errors_total", "kind" => kind).increment(1);
+                        errors.add(1);
+                    } else {
+                        records += 1;
+                        let over = name_override.as_deref();
+                        if let Some(t) = channels.get(kind) {
+                            t.render_or(over, &rec, &mut name);
+                            pipe.cmd("PUBLISH").arg(&name).arg(&payload).ignore();
+                            counter!("ultra_redis_commands_total", "kind" => kind, "op" => "publish")
+                                .increment(1);
+                        }
+                        if let Some(t) = streams.get(kind) {
+                            t.render_or(over, &rec, &mut name);
+                            let cmd = pipe.cmd("XADD").arg(&name);
+                            if let Some(n) = maxlen {
+                                cmd.arg("MAXLEN").arg("~").arg(n);
+                            }
+                            cmd.arg("*").arg("kind").arg(kind);
+                            if let Some(slot) = record_slot(&rec) {
+                                cmd.arg("slot").arg(slot);
+                            }
+                            cmd.arg("data").arg(&payload).ignore();
+                            counter!("ultra_redis_commands_total", "kind" => kind, "op" => "xadd")
+                                .increment(1);
+                        }
+                    }
+                    if records < batch_max {
+                        next = rx.try_recv();
+                    }
+                }
+                if records == 0 {
+                    continue;
+                }
+                let t0 = Instant::now();
+                histogram!("ultra_redis_pipeline_records").record(records as f64);
+                match pipe.query_async::<()>(&mut conn).await {
+                    Ok(()) => {
+                        histogram!("ultra_redis_pipeline_seconds")
+                            .record(t0.elapsed().as_secs_f64());
+                    }
+                    Err(e) => {
+                        counter!("ultra_redis_errors_total").increment(1);
+                        counter!("ultra_redis_records_dropped_total").increment(records as u64);
+                        errors.add(1);
+                        error!(records, "redis pipeline failed: {e}");
+                    }
+                }
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    /// `name` replaces the channel and stream templates for this record.
+    pub async fn send(&self, rec: Record, name: Option<Arc<str>>) -> bool {
+        let kind = record_kind(&rec);
+        self.tx.send((rec, name), kind).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::AccountUpdate;
+
+    #[test]
+    fn renders_templates_per_kind() {
+        let mut per_kind = HashMap::new();
+        per_kind.insert("account".to_string(), "acct:{owner}:{slot}".to_string());
+        let names = Names::new(Some("ultra.{kind}"), &per_kind, "channel").unwrap();
+        let acct = Record::Account(AccountUpdate {
+            slot: 42,
+            is_startup: false,
+            pubkey: [1; 32],
+            lamports: 1,
+            owner: [2; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![],
+        });
+        let mut out = String::new();
+        names.get("account").unwrap().render(&acct, &mut out);
+        assert_eq!(
+            out,
+            format!("acct:{}:42", bs58::encode([2u8; 32]).into_string())
+        );
+        names
+            .get("slot")
+            .unwrap()
+            .render(&Record::EndOfStartup, &mut out);
+        assert_eq!(out, "ultra.end_of_startup");
+
+        assert!(Template::parse("x.{nope}").is_err());
+        assert!(Template::parse("x.{kind").is_err());
+        let mut bad = HashMap::new();
+        bad.insert("accounts".to_string(), "a".to_string());
+        assert!(Names::new(None, &bad, "stream").is_err());
+        assert!(Names::new(None, &HashMap::new(), "stream")
+            .unwrap()
+            .get("tx")
+            .is_none());
+    }
+}
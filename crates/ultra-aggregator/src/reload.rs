@@ -0,0 +1,176 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/reload.rs
+//
+// Config hot-reload, triggered by SIGHUP, `POST /reload` on the admin API, or
+// (with `"watch_config": true`) a change to the config file's mtime. The new
+// file is parsed and every sink it needs is built before anything is swapped,
+// so a bad config leaves the running one in place. What changes live:
+//
+//   UDS listeners    bound or closed only where `uds_path` changed; the rest
+//                    take the new recv buffer, frame limits and `producer_auth`
+//                    for new connections
+//   sinks            json, kafka, postgres, nats, parquet, redis and elasticsearch are
+//                    rebuilt when their block or `sink_queues` entry changed; the old
+//                    one drains and exits
+//   routing, dedup, reorder and json_account_data
+//                    swapped into every output stage before its next record
+//
+// `metrics_addr`, `admin`, `tcp_listeners`, `shm_inputs`, `watch_config`,
+// `shutdown_timeout_ms`, `decode_workers` and existing `ws` and `grpc` blocks
+// are only read at startup; changes are logged.
+use crate::admin::ReloadReply;
+use crate::uds_input::UdsListener;
+use crate::{read_cfg, spawn_output_stage, Cfg, Sinks};
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+const STARTUP_ONLY: &[&str] = &[
+    "/metrics_addr",
+    "/admin",
+    "/tcp_listeners",
+    "/shm_inputs",
+    "/watch_config",
+    "/shutdown_timeout_ms",
+    "/decode_workers",
+];
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// True if every JSON pointer in `blocks` resolves to the same value in both configs.
+pub fn unchanged(old: &Value, new: &Value, blocks: &[&str]) -> bool {
+    blocks.iter().all(|p| old.pointer(p) == new.pointer(p))
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// The running sinks and UDS listeners, and the raw config they came from.
+pub struct Live {
+    cfg_path: String,
+    raw: Value,
+    pub sinks: Arc<ArcSwap<Sinks>>,
+    uds: HashMap<String, UdsListener>,
+}
+
+impl Live {
+    pub async fn start(cfg_path: String, cfg: &Cfg, raw: Value) -> Result<Self> {
+        let sinks = Arc::new(ArcSwap::from_pointee(Sinks::build(cfg, &raw, None).await?));
+        let mut uds = HashMap::new();
+        for (path, settings) in cfg.uds_listeners() {
+            let out = spawn_output_stage(&sinks, &path);
+            match UdsListener::bind(&path, settings, out) {
+                Ok(l) => {
+                    uds.insert(path, l);
+                }
+                Err(e) => error!("failed to bind {}: {e}", path),
+            }
+        }
+        Ok(Self {
+            cfg_path,
+            raw,
+            sinks,
+            uds,
+        })
+    }
+
+    async fn reload(&mut self) -> Result<()> {
+        let (cfg, raw) = read_cfg(&self.cfg_path)?;
+        let current = self.sinks.load_full();
+        let sinks = Sinks::build(&cfg, &raw, Some((&current, &self.raw))).await?;
+        let wanted = cfg.uds_listeners();
+        let mut added = HashMap::new();
+        for (path, settings) in &wanted {
+            if !self.uds.contains_key(path) {
+                let out = spawn_output_stage(&self.sinks, path);
+                let l = UdsListener::bind(path, settings.clone(), out)
+                    .with_context(|| format!("bind {path}"))?;
+                added.insert(path.clone(), l);
+            }
+        }
+
+        // Everything is built; switch over.
+        self.sinks.store(Arc::new(sinks));
+        self.uds
+            .retain(|path, _| wanted.iter().any(|(p, _)| p == path));
+        for (path, settings) in &wanted {
+            if let Some(l) = self.uds.get(path) {
+                l.update(settings.clone());
+            }
+        }
+        self.uds.extend(added);
+        for key in STARTUP_ONLY {
+            if !unchanged(&self.raw, &raw, &[key]) {
+                warn!("`{}` changed; restart to apply", &key[1..]);
+            }
+        }
+        self.raw = raw;
+        info!("config reloaded from {}", self.cfg_path);
+        Ok(())
+    }
+
+    /// Apply reloads until shutdown, then drop the UDS listeners. Requests
+    /// from the admin API arrive on `requests` and get the outcome back.
+    pub async fn run(mut self, mut requests: mpsc::Receiver<ReloadReply>, watch: bool) {
+        let mut hup = match signal(SignalKind::hangup()) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("SIGHUP handler unavailable: {e}");
+                return;
+            }
+        };
+        let mut mtime = modified(&self.cfg_path);
+        let mut tick = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            let reply = tokio::select! {
+                Some(reply) = requests.recv() => Some(reply),
+                Some(()) = hup.recv() => {
+                    info!("SIGHUP: reloading config");
+                    None
+                }
+                _ = tick.tick(), if watch => {
+                    if modified(&self.cfg_path) == mtime {
+                        continue;
+                    }
+                    info!("config file changed: reloading");
+                    None
+                }
+                _ = crate::shutdown::stopped() => break,
+                else => break,
+            };
+            mtime = modified(&self.cfg_path);
+            let res = self.reload().await;
+            if let Err(e) = &res {
+                error!("config reload failed, keeping the running config: {e:#}");
+            }
+            if let Some(reply) = reply {
+                let _ = reply.send(res);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_only_the_listed_blocks() {
+        let old = serde_json::json!({"kafka": {"brokers": "a"}, "sink_queues": {"kafka": {}}});
+        let new = serde_json::json!({"kafka": {"brokers": "b"}, "sink_queues": {"kafka": {}}});
+        assert!(unchanged(&old, &new, &["/sink_queues/kafka", "/nats"]));
+        assert!(!unchanged(&old, &new, &["/kafka", "/sink_queues/kafka"]));
+        assert!(!unchanged(
+            &old,
+            &serde_json::json!({}),
+            &["/sink_queues/kafka"]
+        ));
+    }
+}
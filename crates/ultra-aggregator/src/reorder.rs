@@ -0,0 +1,260 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/reorder.rs
+//
+// Optional slot-ordering stage in front of the sinks. Records are held for up
+// to `window_ms`; when the oldest held record of a kind expires, every record of
+// that kind with a slot at or below its slot is released in slot order, so each
+// kind leaves in non-decreasing slot order. A record that arrives after a higher
+// slot of its kind was already released is late: it is passed through (or
+// dropped with `drop_late`) and counted. `end_of_startup` is never held. Held
+// items are anything that borrows as a record, so ingest metadata rides along.
+//
+//   "reorder": {"window_ms": 400, "max_records": 200000}
+use crate::routing::record_kind;
+use faststreams::Record;
+use metrics::{counter, gauge};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReorderCfg {
+    pub window_ms: u64,
+    /// Records held across all kinds before the oldest are released early
+    #[serde(default = "default_max_records")]
+    pub max_records: usize,
+    #[serde(default)]
+    pub drop_late: bool,
+}
+
+fn default_max_records() -> usize {
+    200_000
+}
+
+fn record_slot(rec: &Record) -> Option<u64> {
+    match rec {
+        Record::Account(a) => Some(a.slot),
+        Record::Tx(t) => Some(t.slot),
+        Record::Block(b) => Some(b.slot),
+        Record::Slot { slot, .. } => Some(*slot),
+        Record::EndOfStartup => None,
+    }
+}
+
+struct KindBuf<T> {
+    /// Keyed by (slot, arrival seq) so equal slots keep arrival order.
+    held: BTreeMap<(u64, u64), T>,
+    arrivals: VecDeque<(Instant, (u64, u64))>,
+    /// Highest slot released so far
+    released: Option<u64>,
+}
+
+impl<T> Default for KindBuf<T> {
+    fn default() -> Self {
+        Self {
+            held: BTreeMap::new(),
+            arrivals: VecDeque::new(),
+            released: None,
+        }
+    }
+}
+
+impl<T> KindBuf<T> {
+    /// Release everything up to and including `key`.
+    fn release_through(&mut self, key: (u64, u64), emit: &mut impl FnMut(T)) -> usize {
+        let rest = self.held.split_off(&(key.0, key.1 + 1));
+        let out = std::mem::replace(&mut self.held, rest);
+        let n = out.len();
+        for ((slot, _), rec) in out {
+            self.released = Some(self.released.map_or(slot, |r| r.max(slot)));
+            emit(rec);
+        }
+        n
+    }
+
+    /// Oldest arrival still held, skipping entries already released.
+    fn oldest(&mut self) -> Option<(Instant, (u64, u64))> {
+        while let Some(&(at, key)) = self.arrivals.front() {
+            if self.held.contains_key(&key) {
+                return Some((at, key));
+            }
+            self.arrivals.pop_front();
+        }
+        None
+    }
+}
+
+pub struct Reorderer<T = Record> {
+    window: Duration,
+    max_records: usize,
+    drop_late: bool,
+    kinds: Vec<(&'static str, KindBuf<T>)>,
+    held: usize,
+    seq: u64,
+}
+
+impl<T: Borrow<Record>> Reorderer<T> {
+    pub fn new(cfg: &ReorderCfg) -> Self {
+        Self {
+            window: Duration::from_millis(cfg.window_ms),
+            max_records: cfg.max_records.max(1),
+            drop_late: cfg.drop_late,
+            kinds: Vec::new(),
+            held: 0,
+            seq: 0,
+        }
+    }
+
+    fn kind(&mut self, kind: &'static str) -> &mut KindBuf<T> {
+        let idx = match self.kinds.iter().position(|(k, _)| *k == kind) {
+            Some(i) => i,
+            None => {
+                self.kinds.push((kind, KindBuf::default()));
+                self.kinds.len() - 1
+            }
+        };
+        &mut self.kinds[idx].1
+    }
+
+    pub fn push(&mut self, rec: T, now: Instant, mut emit: impl FnMut(T)) {
+        let Some(slot) = record_slot(rec.borrow()) else {
+            emit(rec);
+            return;
+        };
+        let drop_late = self.drop_late;
+        let seq = self.seq;
+        self.seq += 1;
+        let kind = record_kind(rec.borrow());
+        let buf = self.kind(kind);
+        if buf.released.is_some_and(|r| slot < r) {
+            counter!("ultra_reorder_late_total", "kind" => kind).increment(1);
+            if !drop_late {
+                emit(rec);
+            }
+            return;
+        }
+        buf.held.insert((slot, seq), rec);
+        buf.arrivals.push_back((now, (slot, seq)));
+        self.held += 1;
+        while self.held > self.max_records {
+            counter!("ultra_reorder_forced_releases_total").increment(1);
+            if !self.release_oldest(None, &mut emit) {
+                break;
+            }
+        }
+        gauge!("ultra_reorder_held_records").set(self.held as f64);
+    }
+
+    /// Release the kind whose oldest record arrived first, if it arrived before
+    /// `cutoff` (any age when `None`). Returns false when nothing qualified.
+    fn release_oldest(&mut self, cutoff: Option<Instant>, emit: &mut impl FnMut(T)) -> bool {
+        let oldest = self
+            .kinds
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, (_, b))| b.oldest().map(|o| (i, o)))
+            .min_by_key(|(_, (at, _))| *at);
+        let Some((i, (at, key))) = oldest else {
+            return false;
+        };
+        if cutoff.is_some_and(|c| at > c) {
+            return false;
+        }
+        self.held -= self.kinds[i].1.release_through(key, emit);
+        true
+    }
+
+    /// Release every record that has been held for the full window.
+    pub fn flush_expired(&mut self, now: Instant, mut emit: impl FnMut(T)) {
+        let Some(cutoff) = now.checked_sub(self.window) else {
+            return;
+        };
+        while self.release_oldest(Some(cutoff), &mut emit) {}
+        gauge!("ultra_reorder_held_records").set(self.held as f64);
+    }
+
+    /// Release everything still held, e.g. when the input closes.
+    pub fn drain(&mut self, mut emit: impl FnMut(T)) {
+        while self.release_oldest(None, &mut emit) {}
+        gauge!("ultra_reorder_held_records").set(self.held as f64);
+    }
+
+    /// When the next held record expires.
+    pub fn next_deadline(&mut self) -> Option<Instant> {
+        self.kinds
+            .iter_mut()
+            .filter_map(|(_, b)| b.oldest().map(|(at, _)| at))
+            .min()
+            .map(|at| at + self.window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot(n: u64) -> Record {
+        Record::Slot {
+            slot: n,
+            parent: None,
+            status: 0,
+        }
+    }
+
+    fn slots(out: &[Record]) -> Vec<u64> {
+        out.iter().filter_map(record_slot).collect()
+    }
+
+    #[test]
+    fn releases_in_slot_order_after_window() {
+        let cfg = ReorderCfg {
+            window_ms: 100,
+            max_records: 1000,
+            drop_late: false,
+        };
+        let mut r = Reorderer::new(&cfg);
+        let t0 = Instant::now();
+        let mut out = Vec::new();
+        for (i, s) in [5u64, 3, 4].into_iter().enumerate() {
+            r.push(slot(s), t0 + Duration::from_millis(i as u64), |rec| {
+                out.push(rec)
+            });
+        }
+        r.push(Record::EndOfStartup, t0, |rec| out.push(rec));
+        assert_eq!(out.len(), 1, "end_of_startup is not held");
+        out.clear();
+        r.flush_expired(t0 + Duration::from_millis(50), |rec| out.push(rec));
+        assert!(out.is_empty());
+        assert_eq!(r.next_deadline(), Some(t0 + Duration::from_millis(100)));
+        // Slot 5 expires first and takes the lower slots with it.
+        r.flush_expired(t0 + Duration::from_millis(100), |rec| out.push(rec));
+        assert_eq!(slots(&out), [3, 4, 5]);
+        assert_eq!(r.next_deadline(), None);
+
+        out.clear();
+        r.push(slot(2), t0 + Duration::from_millis(120), |rec| {
+            out.push(rec)
+        });
+        assert_eq!(slots(&out), [2], "late records pass through");
+    }
+
+    #[test]
+    fn cap_forces_release_and_late_records_can_be_dropped() {
+        let cfg = ReorderCfg {
+            window_ms: 10_000,
+            max_records: 2,
+            drop_late: true,
+        };
+        let mut r = Reorderer::new(&cfg);
+        let t0 = Instant::now();
+        let mut out = Vec::new();
+        for s in [9u64, 7, 8] {
+            r.push(slot(s), t0, |rec| out.push(rec));
+        }
+        assert_eq!(slots(&out), [7, 8, 9]);
+        out.clear();
+        r.push(slot(1), t0, |rec| out.push(rec));
+        assert!(out.is_empty());
+    }
+}
@@ -0,0 +1,317 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/routing.rs
+//
+// Config-driven record routing. Rules are tried in order and the first match
+// decides which sinks (and, for Kafka/NATS, which topic/subject) get the record;
+// unmatched records go to `default`, which itself defaults to every enabled sink.
+//
+//   "routing": {
+//     "rules": [
+//       {"name": "spl", "kinds": ["account"], "owners": ["Tokenkeg..."], "sinks": ["kafka:spl"]},
+//       {"name": "failed_txs", "kinds": ["tx"], "tx_success": false, "sinks": ["drop"]}
+//     ],
+//     "default": ["kafka"]
+//   }
+use anyhow::{anyhow, bail, Result};
+use faststreams::Record;
+use metrics::{counter, Counter};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingCfg {
+    #[serde(default)]
+    pub rules: Vec<RuleCfg>,
+    #[serde(default)]
+    pub default: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleCfg {
+    pub name: String,
+    /// account, tx, block, slot or end_of_startup; empty matches any kind.
+    #[serde(default)]
+    pub kinds: Vec<String>,
+    /// Account owner programs (base58); only account records can match.
+    #[serde(default)]
+    pub owners: Vec<String>,
+    /// Prefix of the base58 account pubkey; only account records can match.
+    #[serde(default)]
+    pub pubkey_prefix: Option<String>,
+    /// Transaction outcome; only tx records can match.
+    #[serde(default)]
+    pub tx_success: Option<bool>,
+    /// `json`, `ws`, `postgres`, `parquet`, `kafka[:topic]`, `nats[:subject]`,
+    /// `redis[:channel/stream]`, `elasticsearch[:index]`, `grpc` or `drop`.
+    pub sinks: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkTarget {
+    Json,
+    Ws,
+    Postgres,
+    Parquet,
+    /// Topic override for this route; `None` keeps the per-kind topic.
+    Kafka(Option<Arc<str>>),
+    /// Subject override for this route; `None` keeps the per-kind subject.
+    Nats(Option<Arc<str>>),
+    /// Channel/stream name for this route; `None` keeps the templates.
+    Redis(Option<Arc<str>>),
+    /// Index for this route; `None` keeps the index templates.
+    Elasticsearch(Option<Arc<str>>),
+    Grpc,
+    Drop,
+}
+
+impl SinkTarget {
+    fn parse(spec: &str) -> Result<Self> {
+        let (name, arg) = match spec.split_once(':') {
+            Some((name, arg)) if !arg.is_empty() => (name, Some(Arc::<str>::from(arg))),
+            Some((name, _)) => (name, None),
+            None => (spec, None),
+        };
+        Ok(match (name, arg) {
+            ("json", None) => SinkTarget::Json,
+            ("ws", None) => SinkTarget::Ws,
+            ("postgres", None) => SinkTarget::Postgres,
+            ("parquet", None) => SinkTarget::Parquet,
+            ("grpc", None) => SinkTarget::Grpc,
+            ("drop", None) => SinkTarget::Drop,
+            ("kafka", topic) => SinkTarget::Kafka(topic),
+            ("nats", subject) => SinkTarget::Nats(subject),
+            ("redis", name) => SinkTarget::Redis(name),
+            ("elasticsearch", index) => SinkTarget::Elasticsearch(index),
+            _ => bail!("unknown sink {spec}"),
+        })
+    }
+
+    fn sink_name(&self) -> &'static str {
+        match self {
+            SinkTarget::Json => "json",
+            SinkTarget::Ws => "ws",
+            SinkTarget::Postgres => "postgres",
+            SinkTarget::Parquet => "parquet",
+            SinkTarget::Kafka(_) => "kafka",
+            SinkTarget::Nats(_) => "nats",
+            SinkTarget::Redis(_) => "redis",
+            SinkTarget::Elasticsearch(_) => "elasticsearch",
+            SinkTarget::Grpc => "grpc",
+            SinkTarget::Drop => "drop",
+        }
+    }
+}
+
+pub fn record_kind(rec: &Record) -> &'static str {
+    match rec {
+        Record::Account(_) => "account",
+        Record::Tx(_) => "tx",
+        Record::Block(_) => "block",
+        Record::Slot { .. } => "slot",
+        Record::EndOfStartup => "end_of_startup",
+    }
+}
+
+pub const KINDS: [&str; 5] = ["account", "tx", "block", "slot", "end_of_startup"];
+
+struct Rule {
+    kinds: Vec<&'static str>,
+    owners: HashSet<[u8; 32]>,
+    pubkey_prefix: Option<String>,
+    tx_success: Option<bool>,
+    sinks: Vec<SinkTarget>,
+    matched: Counter,
+}
+
+impl Rule {
+    fn matches(&self, rec: &Record) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&record_kind(rec)) {
+            return false;
+        }
+        if !self.owners.is_empty() || self.pubkey_prefix.is_some() {
+            let Record::Account(a) = rec else {
+                return false;
+            };
+            if !self.owners.is_empty() && !self.owners.contains(&a.owner) {
+                return false;
+            }
+            if let Some(prefix) = &self.pubkey_prefix {
+                if !bs58::encode(a.pubkey)
+                    .into_string()
+                    .starts_with(prefix.as_str())
+                {
+                    return false;
+                }
+            }
+        }
+        if let Some(want) = self.tx_success {
+            let Record::Tx(t) = rec else {
+                return false;
+            };
+            if t.err.is_none() != want {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub struct RecordRouter {
+    rules: Vec<Rule>,
+    default: Vec<SinkTarget>,
+    unmatched: Counter,
+}
+
+fn parse_targets(specs: &[String], enabled: &[&str], ctx: &str) -> Result<Vec<SinkTarget>> {
+    specs
+        .iter()
+        .map(|spec| {
+            let target = SinkTarget::parse(spec).map_err(|e| anyhow!("{ctx}: {e}"))?;
+            let name = target.sink_name();
+            if name != "drop" && !enabled.contains(&name) {
+                bail!("{ctx}: sink {name} is not enabled");
+            }
+            Ok(target)
+        })
+        .collect()
+}
+
+impl RecordRouter {
+    /// `enabled` names the sinks configured (and compiled in) for this run.
+    pub fn new(cfg: Option<&RoutingCfg>, enabled: &[&str]) -> Result<Self> {
+        let cfg = cfg.cloned().unwrap_or_default();
+        let default = match &cfg.default {
+            Some(specs) => parse_targets(specs, enabled, "routing.default")?,
+            None => enabled
+                .iter()
+                .map(|name| SinkTarget::parse(name))
+                .collect::<Result<_>>()?,
+        };
+        let mut rules = Vec::with_capacity(cfg.rules.len());
+        for r in &cfg.rules {
+            let ctx = format!("routing rule {}", r.name);
+            let kinds = r
+                .kinds
+                .iter()
+                .map(|k| {
+                    KINDS
+                        .iter()
+                        .copied()
+                        .find(|known| known == k)
+                        .ok_or_else(|| anyhow!("{ctx}: unknown kind {k}"))
+                })
+                .collect::<Result<_>>()?;
+            let owners = r
+                .owners
+                .iter()
+                .map(|o| {
+                    bs58::decode(o)
+                        .into_vec()
+                        .ok()
+                        .and_then(|v| v.try_into().ok())
+                        .ok_or_else(|| anyhow!("{ctx}: invalid owner {o}"))
+                })
+                .collect::<Result<_>>()?;
+            rules.push(Rule {
+                kinds,
+                owners,
+                pubkey_prefix: r.pubkey_prefix.clone().filter(|p| !p.is_empty()),
+                tx_success: r.tx_success,
+                sinks: parse_targets(&r.sinks, enabled, &ctx)?,
+                matched: counter!("ultra_route_matched_total", "rule" => r.name.clone()),
+            });
+        }
+        Ok(Self {
+            rules,
+            default,
+            unmatched: counter!("ultra_route_unmatched_total"),
+        })
+    }
+
+    /// Sinks for `rec`: the first matching rule's, else the defaults.
+    pub fn route(&self, rec: &Record) -> &[SinkTarget] {
+        match self.rules.iter().find(|r| r.matches(rec)) {
+            Some(rule) => {
+                rule.matched.increment(1);
+                &rule.sinks
+            }
+            None => {
+                if !self.rules.is_empty() {
+                    self.unmatched.increment(1);
+                }
+                &self.default
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{AccountUpdate, TxUpdate};
+
+    fn account(owner: [u8; 32]) -> Record {
+        Record::Account(AccountUpdate {
+            slot: 1,
+            is_startup: false,
+            pubkey: [7u8; 32],
+            lamports: 1,
+            owner,
+            executable: false,
+            rent_epoch: 0,
+            data: vec![],
+        })
+    }
+
+    fn tx(err: Option<&str>) -> Record {
+        Record::Tx(TxUpdate {
+            slot: 1,
+            signature: [1u8; 64],
+            err: err.map(str::to_string),
+            vote: false,
+        })
+    }
+
+    #[test]
+    fn first_matching_rule_wins_and_default_catches_rest() {
+        let token = [6u8; 32];
+        let cfg: RoutingCfg = serde_json::from_value(serde_json::json!({
+            "rules": [
+                {"name": "spl", "kinds": ["account"], "owners": [bs58::encode(token).into_string()], "sinks": ["kafka:spl"]},
+                {"name": "failed", "tx_success": false, "sinks": ["drop"]},
+                {"name": "prefix", "pubkey_prefix": bs58::encode([7u8; 32]).into_string()[..3].to_string(), "sinks": ["json"]}
+            ],
+            "default": ["kafka"]
+        }))
+        .unwrap();
+        let router = RecordRouter::new(Some(&cfg), &["kafka", "json"]).unwrap();
+        assert_eq!(
+            router.route(&account(token)),
+            [SinkTarget::Kafka(Some("spl".into()))]
+        );
+        assert_eq!(router.route(&account([0u8; 32])), [SinkTarget::Json]);
+        assert_eq!(router.route(&tx(Some("boom"))), [SinkTarget::Drop]);
+        assert_eq!(router.route(&tx(None)), [SinkTarget::Kafka(None)]);
+    }
+
+    #[test]
+    fn rejects_unknown_or_disabled_sinks() {
+        let rule = |sink: &str| RoutingCfg {
+            rules: vec![RuleCfg {
+                name: "r".into(),
+                kinds: vec![],
+                owners: vec![],
+                pubkey_prefix: None,
+                tx_success: None,
+                sinks: vec![sink.into()],
+            }],
+            default: None,
+        };
+        assert!(RecordRouter::new(Some(&rule("nats:x")), &["json"]).is_err());
+        assert!(RecordRouter::new(Some(&rule("carrier-pigeon")), &["json"]).is_err());
+        let router = RecordRouter::new(None, &["json", "ws"]).unwrap();
+        assert_eq!(router.route(&tx(None)), [SinkTarget::Json, SinkTarget::Ws]);
+    }
+}
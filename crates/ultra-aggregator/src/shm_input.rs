@@ -0,0 +1,367 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/shm_input.rs
+//
+// Reader side of the ys-consumer shared-memory ring (`YS_OUTPUT=shm:<path>`).
+// The ring is single-producer/single-consumer: ys-consumer owns `head`, this
+// reader owns `tail`. Each slot holds one faststreams frame, which goes through
+// the same frame decoder as the socket inputs. A dedicated thread polls the ring
+// using the configured wait strategy and, after freeing space, wakes a writer
+// that is blocked on the `tail` futex.
+use crate::ingest::{self, FrameLimits, Ingested};
+use bytes::BytesMut;
+use memmap2::{MmapMut, MmapOptions};
+use metrics::{counter, gauge};
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Must match crates/ys-consumer/src/shm_ring.rs
+const HDR_LEN: usize = 64;
+const MAGIC: u32 = 0x59534D52; // 'YSMR'
+const VERSION: u32 = 1;
+const CAP_OFF: usize = 8;
+const HEAD_OFF: usize = 16;
+const TAIL_OFF: usize = 24;
+
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitStrategy {
+    /// Busy-poll the head index; lowest latency, burns a core.
+    Spin,
+    /// Sleep `poll_us` between empty polls.
+    Sleep,
+    /// Spin for `spin_iters` empty polls, then yield, then sleep.
+    #[default]
+    Adaptive,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct ShmInputCfg {
+    pub path: String,
+    #[serde(default)]
+    pub wait: WaitStrategy,
+    #[serde(default = "default_poll_us")]
+    pub poll_us: u64,
+    #[serde(default = "default_spin_iters")]
+    pub spin_iters: u32,
+    /// Futex-wake a writer waiting for space after each drained burst
+    #[serde(default = "default_wake_writer")]
+    pub wake_writer: bool,
+    #[serde(default)]
+    pub max_frame_bytes: Option<usize>,
+}
+
+fn default_poll_us() -> u64 {
+    50
+}
+
+fn default_spin_iters() -> u32 {
+    1_000
+}
+
+fn default_wake_writer() -> bool {
+    true
+}
+
+struct RingReader {
+    mmap: MmapMut,
+    cap: usize,
+}
+
+#[allow(unsafe_code)]
+fn map_existing(file: &std::fs::File, len: usize) -> io::Result<MmapMut> {
+    if (file.metadata()?.len() as usize) < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "ring file shorter than its header",
+        ));
+    }
+    // SAFETY: offset 0 and `len` <= file length (checked above); the FD is read+write.
+    unsafe { MmapOptions::new().len(len).map_mut(file) }
+}
+
+fn read_u32_le(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
+}
+
+impl RingReader {
+    fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let hdr = map_existing(&file, HDR_LEN)?;
+        if read_u32_le(&hdr, 0) != MAGIC || read_u32_le(&hdr, 4) != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a YSMR v1 ring",
+            ));
+        }
+        let cap = u64::from_le_bytes(hdr[CAP_OFF..CAP_OFF + 8].try_into().unwrap()) as usize;
+        drop(hdr);
+        let mmap = map_existing(&file, HDR_LEN + cap)?;
+        Ok(Self { mmap, cap })
+    }
+
+    #[allow(unsafe_code)]
+    fn word(&self, off: usize) -> &AtomicU64 {
+        debug_assert!(off.is_multiple_of(8) && off + 8 <= HDR_LEN);
+        // SAFETY: the mapping is page-aligned, `off` is a multiple of 8 inside the
+        // header, and the mapping outlives the returned borrow. The other process
+        // only touches these words with plain aligned 8-byte stores.
+        unsafe { &*(self.mmap.as_ptr().add(off) as *const AtomicU64) }
+    }
+
+    fn head(&self) -> usize {
+        self.word(HEAD_OFF).load(Ordering::Acquire) as usize
+    }
+
+    fn tail(&self) -> usize {
+        self.word(TAIL_OFF).load(Ordering::Relaxed) as usize
+    }
+
+    fn set_tail(&self, tail: usize) {
+        self.word(TAIL_OFF).store(tail as u64, Ordering::Release);
+    }
+
+    /// The writer re-initialises the header when it restarts with another capacity.
+    fn is_stale(&self) -> bool {
+        read_u32_le(&self.mmap, 0) != MAGIC
+            || self.word(CAP_OFF).load(Ordering::Relaxed) as usize != self.cap
+    }
+
+    /// Copy the oldest slot into `out` and release it; false when the ring is empty.
+    fn next_slot(&mut self, out: &mut BytesMut) -> bool {
+        let head = self.head();
+        let mut tail = self.tail();
+        if tail == head {
+            return false;
+        }
+        // The writer wraps when the slot does not fit, leaving a zero length
+        // marker (or nothing at all if fewer than 4 bytes remain).
+        if self.cap - tail < 4 || read_u32_le(&self.mmap, HDR_LEN + tail) == 0 {
+            tail = 0;
+            if tail == head {
+                self.set_tail(tail);
+                return false;
+            }
+        }
+        let off = HDR_LEN + tail;
+        let len = read_u32_le(&self.mmap, off) as usize;
+        let end = tail + 4 + len;
+        let in_bounds = if tail < head {
+            end <= head
+        } else {
+            end <= self.cap
+        };
+        if len == 0 || !in_bounds {
+            // Torn or foreign data: skip everything published so far.
+            counter!("ultra_shm_corrupt_slots_total").increment(1);
+            self.set_tail(head);
+            return false;
+        }
+        out.extend_from_slice(&self.mmap[off + 4..off + 4 + len]);
+        self.set_tail(end);
+        true
+    }
+
+    #[cfg(target_os = "linux")]
+    #[allow(unsafe_code)]
+    fn wake_writer(&self) {
+        let word = self.word(TAIL_OFF) as *const AtomicU64 as *const u32;
+        // SAFETY: `word` points at the (little-endian) low half of the aligned tail
+        // word, which is what the writer waits on. FUTEX_WAKE does not dereference
+        // it beyond hashing the address.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word,
+                libc::FUTEX_WAKE,
+                i32::MAX,
+                0usize,
+                0usize,
+                0u32,
+            );
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wake_writer(&self) {}
+}
+
+fn idle(cfg: &ShmInputCfg, empty_polls: u32) {
+    match cfg.wait {
+        WaitStrategy::Spin => std::hint::spin_loop(),
+        WaitStrategy::Sleep => std::thread::sleep(Duration::from_micros(cfg.poll_us)),
+        WaitStrategy::Adaptive => {
+            if empty_polls < cfg.spin_iters {
+                std::hint::spin_loop();
+            } else if empty_polls < cfg.spin_iters.saturating_add(64) {
+                std::thread::yield_now();
+            } else {
+                std::thread::sleep(Duration::from_micros(cfg.poll_us));
+            }
+        }
+    }
+}
+
+/// Decode slots until the ring goes stale, the output stage shuts down or
+/// the process does.
+/// Returns true when the caller should reopen the ring.
+fn pump(
+    ring: &mut RingReader,
+    cfg: &ShmInputCfg,
+    limits: FrameLimits,
+    out: &tokio::sync::mpsc::Sender<Ingested>,
+) -> bool {
+    let mut buf = BytesMut::with_capacity(1 << 20);
+    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+    let mut stats = ingest::ConnStats::new(&format!("shm:{}", cfg.path));
+    let mut empty_polls: u32 = 0;
+    loop {
+        if crate::shutdown::is_stopping() {
+            return false;
+        }
+        let mut slots = 0u64;
+        while ring.next_slot(&mut buf) {
+            slots += 1;
+            ingest::drain_frames(&mut buf, limits, &mut scratch, &mut stats, |rec| {
+                ingest::forward(out, rec)
+            });
+            if !buf.is_empty() {
+                // Slots carry whole frames; never splice one slot onto the next.
+                counter!("ultra_shm_partial_slots_total").increment(1);
+                buf.clear();
+            }
+        }
+        if slots > 0 {
+            counter!("ultra_shm_slots_read_total").increment(slots);
+            stats.publish();
+            if cfg.wake_writer {
+                ring.wake_writer();
+            }
+            empty_polls = 0;
+            continue;
+        }
+        if out.is_closed() {
+            return false;
+        }
+        if ring.is_stale() {
+            return true;
+        }
+        empty_polls = empty_polls.saturating_add(1);
+        idle(cfg, empty_polls);
+    }
+}
+
+/// Attach to the ring at `cfg.path` on a dedicated thread, waiting for the
+/// producer to create it if needed.
+pub fn spawn(
+    cfg: ShmInputCfg,
+    limits: FrameLimits,
+    out: tokio::sync::mpsc::Sender<Ingested>,
+) -> io::Result<()> {
+    std::thread::Builder::new()
+        .name("ultra-shm-in".into())
+        .spawn(move || {
+            let mut logged_wait = false;
+            loop {
+                if crate::shutdown::is_stopping() {
+                    return;
+                }
+                let mut ring = match RingReader::open(&cfg.path) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        if !logged_wait {
+                            warn!("shm ring {} not ready: {e}; retrying", cfg.path);
+                            logged_wait = true;
+                        }
+                        std::thread::sleep(REOPEN_INTERVAL);
+                        continue;
+                    }
+                };
+                logged_wait = false;
+                info!(path = %cfg.path, capacity = ring.cap, wait = ?cfg.wait, "reading SHM ring");
+                gauge!("ultra_shm_ring_capacity_bytes").set(ring.cap as f64);
+                if !pump(&mut ring, &cfg, limits, &out) {
+                    return;
+                }
+                counter!("ultra_shm_reattach_total").increment(1);
+                warn!("shm ring {} was re-initialised; reopening", cfg.path);
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::encode_record;
+    use faststreams::Record;
+
+    // Minimal stand-in for ys-consumer's ShmRingWriter::push_timeout.
+    fn push(map: &mut [u8], cap: usize, frame: &[u8]) {
+        let mut head = u64::from_le_bytes(map[HEAD_OFF..HEAD_OFF + 8].try_into().unwrap()) as usize;
+        let need = 4 + frame.len();
+        if cap - head < need {
+            if cap - head >= 4 {
+                map[HDR_LEN + head..HDR_LEN + head + 4].fill(0);
+            }
+            head = 0;
+        }
+        let off = HDR_LEN + head;
+        map[off..off + 4].copy_from_slice(&(frame.len() as u32).to_le_bytes());
+        map[off + 4..off + need].copy_from_slice(frame);
+        map[HEAD_OFF..HEAD_OFF + 8].copy_from_slice(&((head + need) as u64).to_le_bytes());
+    }
+
+    #[test]
+    fn reads_slots_across_wraparound() {
+        let path = std::env::temp_dir().join(format!("ultra-shm-in-{}", std::process::id()));
+        let frame = |n| {
+            encode_record(&Record::Slot {
+                slot: n,
+                parent: None,
+                status: 0,
+            })
+            .unwrap()
+        };
+        let slot_len = 4 + frame(0).len();
+        // Room for two slots and a few bytes, so the third wraps.
+        let cap = 2 * slot_len + 6;
+        let mut init = vec![0u8; HDR_LEN + cap];
+        init[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        init[4..8].copy_from_slice(&VERSION.to_le_bytes());
+        init[CAP_OFF..CAP_OFF + 8].copy_from_slice(&(cap as u64).to_le_bytes());
+        std::fs::write(&path, &init).unwrap();
+
+        let mut ring = RingReader::open(path.to_str().unwrap()).unwrap();
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let mut got = Vec::new();
+        let mut read_all = |ring: &mut RingReader| {
+            let mut buf = BytesMut::new();
+            while ring.next_slot(&mut buf) {
+                let mut stats = ingest::ConnStats::new("test");
+                ingest::drain_frames(&mut buf, limits, &mut Vec::new(), &mut stats, |ing| {
+                    if let Record::Slot { slot, .. } = ing.rec {
+                        got.push(slot);
+                    }
+                });
+            }
+        };
+        push(&mut ring.mmap, cap, &frame(1));
+        push(&mut ring.mmap, cap, &frame(2));
+        read_all(&mut ring);
+        push(&mut ring.mmap, cap, &frame(3));
+        read_all(&mut ring);
+        assert_eq!(got, [1, 2, 3]);
+        assert_eq!(ring.tail(), slot_len);
+        assert!(!ring.is_stale());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
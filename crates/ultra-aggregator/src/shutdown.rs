@@ -0,0 +1,97 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/shutdown.rs
+//
+// Orderly shutdown on ctrl-c or SIGTERM, in three steps:
+//
+//   1. inputs stop: listeners close (UDS socket files are removed), and
+//      connections and SHM readers stop after the frames they already read
+//   2. output stages dispatch what they still hold, reorder windows included
+//   3. sink queues close; each sink drains its queue, finishes its in-flight
+//      work (Kafka deliveries and producer flush, Postgres COPYs, Parquet
+//      uploads) and exits
+//
+// `shutdown_timeout_ms` (default 10000) bounds steps 2 and 3 together. Per-sink
+// drained, abandoned and dropped counts are logged before the process exits.
+use crate::sink_queue;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+const POLL: Duration = Duration::from_millis(20);
+
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static STOP: Notify = Notify::const_new();
+static STAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Acquire)
+}
+
+/// Resolves once shutdown has begun; inputs select on it.
+pub async fn stopped() {
+    loop {
+        let notified = STOP.notified();
+        if is_stopping() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Held by each output stage while it runs.
+pub struct StageGuard(());
+
+impl StageGuard {
+    pub fn new() -> Self {
+        STAGES.fetch_add(1, Ordering::AcqRel);
+        Self(())
+    }
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        STAGES.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Run the shutdown steps, giving stages and sinks `timeout` in total.
+pub async fn run(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    info!(?timeout, "shutting down: inputs stopped, draining");
+    STOPPING.store(true, Ordering::Release);
+    STOP.notify_waiters();
+
+    while STAGES.load(Ordering::Acquire) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(POLL).await;
+    }
+    let stages = STAGES.load(Ordering::Acquire);
+    if stages > 0 {
+        warn!(stages, "output stages still busy at the shutdown deadline");
+    }
+
+    let report = sink_queue::drain_all(deadline, POLL).await;
+    let (mut drained, mut abandoned) = (0, 0);
+    for r in &report {
+        drained += r.drained;
+        abandoned += r.abandoned;
+        if r.abandoned > 0 || r.unfinished > 0 {
+            warn!(
+                sink = r.sink,
+                drained = r.drained,
+                abandoned = r.abandoned,
+                unfinished = r.unfinished,
+                dropped = r.dropped,
+                "sink did not drain before the deadline"
+            );
+        } else {
+            info!(
+                sink = r.sink,
+                drained = r.drained,
+                dropped = r.dropped,
+                "sink drained"
+            );
+        }
+    }
+    info!(drained, abandoned, "shutdown complete");
+}
@@ -0,0 +1,669 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/sink_queue.rs
+//
+// Bounded queue in front of every sink. What happens when a sink falls behind
+// is set per sink with `"sink_queues": {"kafka": {"capacity": 65536, "policy": "block"}}`:
+//
+//   drop_newest  reject the incoming record (default; what every sink used to do)
+//   drop_oldest  evict the oldest queued record to make room
+//   block        hold the output stage until there is room, pushing back on ingest
+//
+// Drops are counted per sink and record kind; depth and time-in-queue are
+// exported per sink. Receivers can wait from async tasks or plain threads.
+//
+// Every queue is also listed in a process-wide registry so the admin API can
+// report it and pause the sink behind it; a paused sink drops what it is sent
+// (reason `paused`) while whatever is already queued keeps draining.
+//
+// Items sent while an origin timestamp is in scope (`with_origin`, set by the
+// output stage around dispatch) also feed `ultra_pipeline_lag_seconds` when a
+// sink worker takes them, without every sink having to carry the timestamp.
+//
+// On shutdown `drain_all` closes every queue: sends are dropped (reason
+// `shutdown`), workers drain what is queued, wait for their `in_flight` work
+// and exit, and the per-sink outcome is reported.
+use crate::lag;
+use metrics::{counter, gauge, histogram, Gauge, Histogram};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+const DEFAULT_CAPACITY: usize = 65_536;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropPolicy {
+    #[default]
+    DropNewest,
+    DropOldest,
+    Block,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueueCfg {
+    #[serde(default)]
+    pub capacity: Option<usize>,
+    #[serde(default)]
+    pub policy: DropPolicy,
+}
+
+/// Queue settings for each sink, keyed by the sink names used in `routing`.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkQueuesCfg {
+    #[serde(default)]
+    pub json: QueueCfg,
+    #[serde(default)]
+    pub ws: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "postgres"), allow(dead_code))]
+    pub postgres: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+    pub kafka: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "nats"), allow(dead_code))]
+    pub nats: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "parquet"), allow(dead_code))]
+    pub parquet: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub redis: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub grpc: QueueCfg,
+    #[serde(default)]
+    #[cfg_attr(not(feature = "elasticsearch"), allow(dead_code))]
+    pub elasticsearch: QueueCfg,
+}
+
+struct Entry<T> {
+    item: T,
+    kind: &'static str,
+    queued_at: Instant,
+    origin_ns: Option<u64>,
+}
+
+tokio::task_local! {
+    static ORIGIN: Option<u64>;
+}
+
+/// Run `f` (a dispatch to the sinks) with the record's producer origin timestamp in scope.
+pub async fn with_origin<F: Future>(origin_ns: Option<u64>, f: F) -> F::Output {
+    ORIGIN.scope(origin_ns, f).await
+}
+
+struct Shared<T> {
+    sink: &'static str,
+    cap: usize,
+    policy: DropPolicy,
+    items: Mutex<VecDeque<Entry<T>>>,
+    // Async receivers wait on `not_empty`, thread receivers on `not_empty_cv`.
+    not_empty: Notify,
+    not_empty_cv: Condvar,
+    not_full: Notify,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    closed: AtomicBool,
+    seq: AtomicU64,
+    depth: Gauge,
+    lag: Histogram,
+    paused: AtomicBool,
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    errors: SinkErrors,
+    in_flight: InFlight,
+}
+
+/// Delivery failures reported by a sink's workers, for the admin API.
+#[derive(Clone, Default)]
+pub struct SinkErrors(Arc<AtomicU64>);
+
+impl SinkErrors {
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+}
+
+/// Work a sink has taken off its queue but not finished (a COPY, an upload, a
+/// delivery); shutdown waits for it.
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    #[cfg_attr(
+        not(any(feature = "kafka", feature = "postgres", feature = "parquet")),
+        allow(dead_code)
+    )]
+    pub fn begin(&self) -> InFlightGuard {
+        self.0.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard(self.0.clone())
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "kafka", feature = "postgres", feature = "parquet")),
+    allow(dead_code)
+)]
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// What became of one sink's queue during shutdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DrainReport {
+    pub sink: &'static str,
+    /// Queued at shutdown and taken by the sink
+    pub drained: u64,
+    /// Still queued at the deadline
+    pub abandoned: u64,
+    /// In-flight work units the sink had not finished at the deadline
+    pub unfinished: u64,
+    /// Sent after the queue closed
+    pub dropped: u64,
+}
+
+/// One sink's queue as reported by the admin API.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct SinkStatus {
+    pub sink: &'static str,
+    pub paused: bool,
+    pub depth: usize,
+    pub capacity: usize,
+    pub policy: DropPolicy,
+    pub enqueued_total: u64,
+    pub dropped_total: u64,
+    pub errors_total: u64,
+}
+
+trait Control: Send + Sync {
+    fn status(&self) -> SinkStatus;
+    fn set_paused(&self, paused: bool);
+    fn shut(&self);
+    /// Workers have exited and their in-flight work is done.
+    fn finished(&self) -> bool;
+    fn unfinished(&self) -> usize;
+}
+
+impl<T: Send> Control for Shared<T> {
+    fn status(&self) -> SinkStatus {
+        SinkStatus {
+            sink: self.sink,
+            paused: self.paused.load(Ordering::Relaxed),
+            depth: self.items.lock().unwrap().len(),
+            capacity: self.cap,
+            policy: self.policy,
+            enqueued_total: self.enqueued.load(Ordering::Relaxed),
+            dropped_total: self.dropped.load(Ordering::Relaxed),
+            errors_total: self.errors.0.load(Ordering::Relaxed),
+        }
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+        gauge!("ultra_sink_paused", "sink" => self.sink).set(if paused { 1.0 } else { 0.0 });
+        // Blocked senders must not keep waiting on a sink that now drops.
+        self.not_full.notify_waiters();
+    }
+
+    fn shut(&self) {
+        self.close();
+    }
+
+    fn finished(&self) -> bool {
+        self.receivers.load(Ordering::Acquire) == 0 && self.unfinished() == 0
+    }
+
+    fn unfinished(&self) -> usize {
+        self.in_flight.0.load(Ordering::Acquire)
+    }
+}
+
+static REGISTRY: Mutex<Vec<Weak<dyn Control>>> = Mutex::new(Vec::new());
+
+fn live_queues() -> Vec<Arc<dyn Control>> {
+    let mut reg = REGISTRY.lock().unwrap();
+    reg.retain(|w| w.strong_count() > 0);
+    reg.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Every live sink queue, in creation order.
+pub fn statuses() -> Vec<SinkStatus> {
+    live_queues().iter().map(|q| q.status()).collect()
+}
+
+/// Close every queue and wait until each sink has drained it and finished its
+/// in-flight work, or until `deadline`.
+pub async fn drain_all(deadline: Instant, poll: std::time::Duration) -> Vec<DrainReport> {
+    drain(live_queues(), deadline, poll).await
+}
+
+async fn drain(
+    queues: Vec<Arc<dyn Control>>,
+    deadline: Instant,
+    poll: std::time::Duration,
+) -> Vec<DrainReport> {
+    let before: Vec<SinkStatus> = queues.iter().map(|q| q.status()).collect();
+    for q in &queues {
+        q.shut();
+    }
+    while !queues.iter().all(|q| q.finished()) && Instant::now() < deadline {
+        tokio::time::sleep(poll).await;
+    }
+    queues
+        .iter()
+        .zip(before)
+        .map(|(q, at_close)| {
+            let now = q.status();
+            let abandoned = now.depth as u64;
+            DrainReport {
+                sink: now.sink,
+                drained: (at_close.depth as u64).saturating_sub(abandoned),
+                abandoned,
+                unfinished: q.unfinished() as u64,
+                dropped: now.dropped_total - at_close.dropped_total,
+            }
+        })
+        .collect()
+}
+
+/// Pause or resume the queues of `sink`; `None` if there is no such sink.
+pub fn set_paused(sink: &str, paused: bool) -> Option<SinkStatus> {
+    let mut found = None;
+    for q in live_queues() {
+        if q.status().sink == sink {
+            q.set_paused(paused);
+            found = Some(q.status());
+        }
+    }
+    found
+}
+
+impl<T> Shared<T> {
+    fn dropped(&self, kind: &'static str, reason: &'static str) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        counter!("ultra_sink_dropped_total", "sink" => self.sink, "kind" => kind, "reason" => reason)
+            .increment(1);
+    }
+
+    #[cfg_attr(
+        not(any(
+            feature = "kafka",
+            feature = "nats",
+            feature = "postgres",
+            feature = "parquet",
+            feature = "redis",
+            feature = "grpc",
+            feature = "elasticsearch"
+        )),
+        allow(dead_code)
+    )]
+    fn pop(&self) -> Option<T> {
+        let mut q = self.items.lock().unwrap();
+        let entry = q.pop_front()?;
+        self.depth.set(q.len() as f64);
+        drop(q);
+        Some(self.taken(entry))
+    }
+
+    fn taken(&self, entry: Entry<T>) -> T {
+        if self.policy == DropPolicy::Block {
+            self.not_full.notify_one();
+        }
+        if self.seq.fetch_add(1, Ordering::Relaxed) & lag::LAG_SAMPLE_MASK == 0 {
+            self.lag.record(entry.queued_at.elapsed().as_secs_f64());
+            if let Some(origin) = entry.origin_ns {
+                histogram!("ultra_pipeline_lag_seconds", "sink" => self.sink, "kind" => entry.kind)
+                    .record(lag::since_origin(origin));
+            }
+        }
+        entry.item
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        let _guard = self.items.lock().unwrap();
+        self.not_empty.notify_waiters();
+        self.not_empty_cv.notify_all();
+        self.not_full.notify_waiters();
+    }
+}
+
+pub struct QueueTx<T> {
+    shared: Arc<Shared<T>>,
+}
+
+pub struct QueueRx<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// A queue for `sink` configured by `cfg`.
+pub fn queue<T: Send + 'static>(sink: &'static str, cfg: &QueueCfg) -> (QueueTx<T>, QueueRx<T>) {
+    let cap = cfg.capacity.unwrap_or(DEFAULT_CAPACITY).max(1);
+    gauge!("ultra_sink_queue_capacity", "sink" => sink).set(cap as f64);
+    // A sink rebuilt by a config reload stays paused if it was.
+    let paused = live_queues().iter().any(|q| {
+        let s = q.status();
+        s.sink == sink && s.paused
+    });
+    let shared = Arc::new(Shared {
+        sink,
+        cap,
+        policy: cfg.policy,
+        items: Mutex::new(VecDeque::with_capacity(cap.min(4096))),
+        not_empty: Notify::new(),
+        not_empty_cv: Condvar::new(),
+        not_full: Notify::new(),
+        senders: AtomicUsize::new(1),
+        receivers: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        seq: AtomicU64::new(0),
+        depth: gauge!("ultra_sink_queue_depth", "sink" => sink),
+        lag: histogram!("ultra_sink_queue_lag_seconds", "sink" => sink),
+        paused: AtomicBool::new(paused),
+        enqueued: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        errors: SinkErrors::default(),
+        in_flight: InFlight::default(),
+    });
+    gauge!("ultra_sink_paused", "sink" => sink).set(if paused { 1.0 } else { 0.0 });
+    let control: Arc<dyn Control> = shared.clone();
+    REGISTRY.lock().unwrap().push(Arc::downgrade(&control));
+    (
+        QueueTx {
+            shared: shared.clone(),
+        },
+        QueueRx { shared },
+    )
+}
+
+impl<T> Shared<T> {
+    /// Push under the drop policy: `Some(queued)`, or `None` if `block` must wait.
+    fn try_push(&self, item: &mut Option<T>, kind: &'static str) -> Option<bool> {
+        let mut q = self.items.lock().unwrap();
+        if q.len() >= self.cap {
+            match self.policy {
+                DropPolicy::DropNewest => {
+                    drop(q);
+                    self.dropped(kind, "queue_full");
+                    return Some(false);
+                }
+                DropPolicy::DropOldest => {
+                    if let Some(old) = q.pop_front() {
+                        self.dropped(old.kind, "evicted");
+                    }
+                }
+                DropPolicy::Block => return None,
+            }
+        }
+        q.push_back(Entry {
+            item: item.take()?,
+            kind,
+            queued_at: Instant::now(),
+            origin_ns: ORIGIN.try_with(|o| *o).ok().flatten(),
+        });
+        self.depth.set(q.len() as f64);
+        drop(q);
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        self.not_empty.notify_one();
+        self.not_empty_cv.notify_one();
+        Some(true)
+    }
+}
+
+impl<T> QueueTx<T> {
+    /// Enqueue `item` (a record of `kind`) under the sink's drop policy. Only
+    /// `block` ever waits; returns false if the item was dropped.
+    pub async fn send(&self, item: T, kind: &'static str) -> bool {
+        let s = &*self.shared;
+        let mut item = Some(item);
+        loop {
+            if s.receivers.load(Ordering::Acquire) == 0 {
+                s.dropped(kind, "closed");
+                return false;
+            }
+            if s.closed.load(Ordering::Acquire) {
+                s.dropped(kind, "shutdown");
+                return false;
+            }
+            if s.paused.load(Ordering::Relaxed) {
+                s.dropped(kind, "paused");
+                return false;
+            }
+            let wait = s.not_full.notified();
+            if let Some(queued) = s.try_push(&mut item, kind) {
+                return queued;
+            }
+            counter!("ultra_sink_blocked_total", "sink" => s.sink).increment(1);
+            wait.await;
+        }
+    }
+}
+
+impl<T> QueueRx<T> {
+    /// Next item, or `None` once every sender is gone and the queue is drained.
+    #[cfg_attr(
+        not(any(
+            feature = "kafka",
+            feature = "nats",
+            feature = "postgres",
+            feature = "parquet",
+            feature = "redis",
+            feature = "grpc",
+            feature = "elasticsearch"
+        )),
+        allow(dead_code)
+    )]
+    pub async fn recv(&self) -> Option<T> {
+        let s = &*self.shared;
+        loop {
+            let wait = s.not_empty.notified();
+            if let Some(item) = s.pop() {
+                return Some(item);
+            }
+            if s.closed.load(Ordering::Acquire) {
+                return s.pop();
+            }
+            wait.await;
+        }
+    }
+
+    /// The next item if one is queued, without waiting.
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn try_recv(&self) -> Option<T> {
+        self.shared.pop()
+    }
+
+    /// Counter for this sink's delivery failures.
+    pub fn errors(&self) -> SinkErrors {
+        self.shared.errors.clone()
+    }
+
+    /// Tracker for work this sink finishes after taking it off the queue.
+    #[cfg_attr(
+        not(any(feature = "kafka", feature = "postgres", feature = "parquet")),
+        allow(dead_code)
+    )]
+    pub fn in_flight(&self) -> InFlight {
+        self.shared.in_flight.clone()
+    }
+
+    /// Like `recv`, for receivers on their own thread.
+    pub fn blocking_recv(&self) -> Option<T> {
+        let s = &*self.shared;
+        let mut q = s.items.lock().unwrap();
+        loop {
+            if let Some(entry) = q.pop_front() {
+                s.depth.set(q.len() as f64);
+                drop(q);
+                return Some(s.taken(entry));
+            }
+            if s.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            q = s.not_empty_cv.wait(q).unwrap();
+        }
+    }
+}
+
+impl<T> Clone for QueueTx<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueTx<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.close();
+        }
+    }
+}
+
+impl<T> Clone for QueueRx<T> {
+    fn clone(&self) -> Self {
+        self.shared.receivers.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for QueueRx<T> {
+    fn drop(&mut self) {
+        if self.shared.receivers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Wake blocked senders so they see there is nobody left to drain.
+            self.shared.not_full.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cfg(capacity: usize, policy: DropPolicy) -> QueueCfg {
+        QueueCfg {
+            capacity: Some(capacity),
+            policy,
+        }
+    }
+
+    #[tokio::test]
+    async fn drop_policies_pick_which_item_is_lost() {
+        let (tx, rx) = queue::<u32>("test", &cfg(2, DropPolicy::DropNewest));
+        for i in 0..3 {
+            tx.send(i, "slot").await;
+        }
+        assert_eq!((rx.recv().await, rx.recv().await), (Some(0), Some(1)));
+
+        let (tx, rx) = queue::<u32>("test", &cfg(2, DropPolicy::DropOldest));
+        for i in 0..3 {
+            assert!(tx.send(i, "slot").await);
+        }
+        drop(tx);
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.blocking_recv(), Some(2));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_the_receiver() {
+        let (tx, rx) = queue::<u32>("test", &cfg(1, DropPolicy::Block));
+        assert!(tx.send(1, "slot").await);
+        let sender = tokio::spawn(async move { tx.send(2, "slot").await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv().await, Some(1));
+        assert!(sender.await.unwrap());
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn paused_sink_drops_and_reports_status() {
+        let (tx, rx) = queue::<u32>("pause_test", &cfg(4, DropPolicy::Block));
+        assert!(tx.send(1, "slot").await);
+        assert!(set_paused("pause_test", true).is_some_and(|s| s.paused));
+        assert!(!tx.send(2, "slot").await);
+        rx.errors().add(3);
+        let status = statuses()
+            .into_iter()
+            .find(|s| s.sink == "pause_test")
+            .unwrap();
+        assert_eq!(
+            (
+                status.depth,
+                status.enqueued_total,
+                status.dropped_total,
+                status.errors_total
+            ),
+            (1, 1, 1, 3)
+        );
+        set_paused("pause_test", false);
+        assert!(tx.send(3, "slot").await);
+        assert_eq!((rx.recv().await, rx.recv().await), (Some(1), Some(3)));
+        assert!(set_paused("no_such_sink", true).is_none());
+        drop((tx, rx));
+        assert!(!statuses().iter().any(|s| s.sink == "pause_test"));
+    }
+    #[tokio::test]
+    async fn drain_waits_for_workers_and_reports_leftovers() {
+        let (tx, rx) = queue::<u32>("drain_test", &cfg(8, DropPolicy::DropNewest));
+        let (stuck_tx, _stuck_rx) =
+            queue::<u32>("drain_stuck_test", &cfg(8, DropPolicy::DropNewest));
+        for i in 0..3 {
+            tx.send(i, "slot").await;
+            stuck_tx.send(i, "slot").await;
+        }
+        let in_flight = rx.in_flight();
+        let worker = tokio::spawn(async move {
+            let mut got = Vec::new();
+            while let Some(i) = rx.recv().await {
+                let _work = in_flight.begin();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                got.push(i);
+            }
+            got
+        });
+        let queues: Vec<Arc<dyn Control>> = vec![tx.shared.clone(), stuck_tx.shared.clone()];
+        let deadline = Instant::now() + Duration::from_millis(300);
+        let report = drain(queues, deadline, Duration::from_millis(5)).await;
+        assert_eq!(worker.await.unwrap(), [0, 1, 2]);
+        assert!(!tx.send(3, "slot").await);
+        let outcome = |r: &DrainReport| (r.sink, r.drained, r.abandoned, r.unfinished);
+        assert_eq!(
+            report.iter().map(outcome).collect::<Vec<_>>(),
+            [("drain_test", 3, 0, 0), ("drain_stuck_test", 0, 3, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn origin_in_scope_rides_with_the_item() {
+        let (tx, rx) = queue::<u32>("origin_test", &cfg(4, DropPolicy::DropNewest));
+        tx.send(1, "tx").await;
+        with_origin(Some(42), tx.send(2, "tx")).await;
+        let origins: Vec<_> = rx
+            .shared
+            .items
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.origin_ns)
+            .collect();
+        assert_eq!(origins, [None, Some(42)]);
+    }
+}
@@ -0,0 +1,218 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/tcp_input.rs
+//
+// TCP listeners (optionally TLS, optionally mutual TLS) for producers on other
+// hosts, e.g. `ys-consumer` with `YS_OUTPUT=tls`. Connections carry the same
+// faststreams frames as the Unix sockets and feed the listener's own output stage.
+// With a `producer_auth` token, each connection must authenticate first.
+use crate::ingest::{self, FrameLimits, Ingested};
+use crate::producer_auth::ProducerAuthCfg;
+use anyhow::{anyhow, Context, Result};
+use metrics::{counter, gauge};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use socket2::SockRef;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TcpInputCfg {
+    pub bind: String,
+    /// Requested per-connection socket recv buffer size
+    #[serde(default)]
+    pub recv_buf_bytes: Option<usize>,
+    #[serde(default)]
+    pub max_frame_bytes: Option<usize>,
+    #[serde(default)]
+    pub tls: Option<TlsInputCfg>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TlsInputCfg {
+    pub cert_file: String,
+    pub key_file: String,
+    /// Require client certificates issued by this CA bundle (mutual TLS)
+    #[serde(default)]
+    pub client_ca_file: Option<String>,
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader =
+        std::io::BufReader::new(std::fs::File::open(path).with_context(|| format!("open {path}"))?);
+    rustls_pemfile::certs(&mut reader)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("read certificates from {path}"))
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader =
+        std::io::BufReader::new(std::fs::File::open(path).with_context(|| format!("open {path}"))?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| anyhow!("no private key in {path}"))
+}
+
+pub fn tls_acceptor(cfg: &TlsInputCfg) -> Result<TlsAcceptor> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match &cfg.client_ca_file {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(read_certs(&cfg.cert_file)?, read_key(&cfg.key_file)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+async fn serve_conn(
+    sock: TcpStream,
+    tls: Option<TlsAcceptor>,
+    limits: FrameLimits,
+    token: Option<&str>,
+    conn: &str,
+    out: tokio::sync::mpsc::Sender<Ingested>,
+) -> Result<()> {
+    match tls {
+        Some(acceptor) => {
+            let stream = match acceptor.accept(sock).await {
+                Ok(s) => s,
+                Err(e) => {
+                    counter!("ultra_tcp_tls_handshake_errors_total").increment(1);
+                    return Err(e.into());
+                }
+            };
+            ingest::handle_client(stream, limits, token, conn, out).await
+        }
+        None => ingest::handle_client(sock, limits, token, conn, out).await,
+    }
+}
+
+/// Bind `cfg.bind` and accept producers until shutdown.
+pub async fn bind(
+    cfg: &TcpInputCfg,
+    limits: FrameLimits,
+    auth: Option<Arc<ProducerAuthCfg>>,
+    out: tokio::sync::mpsc::Sender<Ingested>,
+) -> Result<()> {
+    let tls = cfg.tls.as_ref().map(tls_acceptor).transpose()?;
+    let listener = TcpListener::bind(&cfg.bind)
+        .await
+        .with_context(|| format!("bind tcp {}", cfg.bind))?;
+    info!(bind = %cfg.bind, tls = tls.is_some(), "listening TCP");
+    let recv_req = cfg.recv_buf_bytes.unwrap_or(8 * 1024 * 1024);
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                a = listener.accept() => a,
+                _ = crate::shutdown::stopped() => return,
+            };
+            let (sock, peer) = match accepted {
+                Ok(conn) => conn,
+                Err(e) => {
+                    warn!("tcp accept failed: {e}");
+                    continue;
+                }
+            };
+            let _ = sock.set_nodelay(true);
+            let sr = SockRef::from(&sock);
+            let _ = sr.set_recv_buffer_size(recv_req);
+            if let Ok(actual) = sr.recv_buffer_size() {
+                gauge!("ultra_tcp_recv_buf_bytes").set(actual as f64);
+            }
+            counter!("ultra_tcp_connections_total").increment(1);
+            gauge!("ultra_tcp_active_connections").increment(1.0);
+            let (tls, auth, out) = (tls.clone(), auth.clone(), out.clone());
+            tokio::spawn(async move {
+                let token = auth.as_ref().and_then(|a| a.token.as_deref());
+                let conn = peer.ip().to_string();
+                if let Err(e) = serve_conn(sock, tls, limits, token, &conn, out).await {
+                    error!("tcp client {peer} error: {e:#}");
+                }
+                gauge!("ultra_tcp_active_connections").decrement(1.0);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::Record;
+    use rustls::pki_types::ServerName;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn tls_listener_decodes_frames() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ultra-tcp-in-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+
+        // Grab a free port, then hand it to the listener.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let cfg = TcpInputCfg {
+            bind: format!("127.0.0.1:{port}"),
+            recv_buf_bytes: None,
+            max_frame_bytes: None,
+            tls: Some(TlsInputCfg {
+                cert_file: cert_path.display().to_string(),
+                key_file: key_path.display().to_string(),
+                client_ca_file: None,
+            }),
+        };
+        let limits = FrameLimits {
+            max_frame_bytes: 1 << 20,
+            accept_legacy: false,
+            quarantine: None,
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        bind(&cfg, limits, None, tx).await.unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(read_certs(&cfg.tls.as_ref().unwrap().cert_file).unwrap()[0].clone())
+            .unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let tcp = TcpStream::connect(&cfg.bind).await.unwrap();
+        let mut stream = tokio_rustls::TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        let frame = faststreams::encode_record(&Record::Slot {
+            slot: 42,
+            parent: None,
+            status: 0,
+        })
+        .unwrap();
+        stream.write_all(&frame).await.unwrap();
+        stream.flush().await.unwrap();
+        let rec = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(rec.rec, Record::Slot { slot: 42, .. }));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,176 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/uds_input.rs
+//
+// Unix socket listeners (`uds_path`, or one per entry in `listeners`). Each is
+// a shard with its own output stage. The receive buffer and frame limits come
+// through a watch channel, so a config reload changes them in place; they
+// apply to connections accepted afterwards. Dropping a listener closes it and
+// removes the socket file; connections already accepted run until they end
+// or the process shuts down. `producer_auth` rides along with the settings:
+// peers outside `uds_allowed_uids` are closed on accept, and with a token each
+// connection must authenticate before its records are read.
+use crate::ingest::{self, FrameLimits, Ingested};
+use crate::producer_auth::{self, ProducerAuthCfg};
+use metrics::gauge;
+use socket2::SockRef;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UdsSettings {
+    /// Requested socket recv buffer size
+    pub recv_buf_bytes: usize,
+    pub limits: FrameLimits,
+    pub auth: Option<Arc<ProducerAuthCfg>>,
+}
+
+pub struct UdsListener {
+    path: String,
+    settings: watch::Sender<UdsSettings>,
+    task: JoinHandle<()>,
+}
+
+impl UdsListener {
+    /// Bind `path` (replacing a stale socket file) and start accepting.
+    pub fn bind(
+        path: &str,
+        settings: UdsSettings,
+        out: mpsc::Sender<Ingested>,
+    ) -> io::Result<Self> {
+        if Path::new(path).exists() {
+            let _ = std::fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660));
+        }
+        info!("listening UDS {}", path);
+        gauge!("ultra_max_frame_bytes").set(settings.limits.max_frame_bytes as f64);
+        let (tx, rx) = watch::channel(settings);
+        Ok(Self {
+            path: path.to_string(),
+            settings: tx,
+            task: tokio::spawn(accept_loop(path.to_string(), listener, rx, out)),
+        })
+    }
+
+    pub fn update(&self, settings: UdsSettings) {
+        if self.settings.send_replace(settings.clone()) != settings {
+            info!(?settings, "UDS {} settings updated", self.path);
+            gauge!("ultra_max_frame_bytes").set(settings.limits.max_frame_bytes as f64);
+        }
+    }
+}
+
+impl Drop for UdsListener {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = std::fs::remove_file(&self.path);
+        info!("closed UDS {}", self.path);
+    }
+}
+
+async fn accept_loop(
+    path: String,
+    listener: UnixListener,
+    settings: watch::Receiver<UdsSettings>,
+    out: mpsc::Sender<Ingested>,
+) {
+    loop {
+        let accepted = tokio::select! {
+            a = listener.accept() => a,
+            _ = crate::shutdown::stopped() => return,
+        };
+        let sock = match accepted {
+            Ok((sock, _)) => sock,
+            Err(e) => {
+                warn!("UDS accept failed: {e}");
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                continue;
+            }
+        };
+        let s = settings.borrow().clone();
+        if let Some(auth) = &s.auth {
+            if !producer_auth::uid_allowed(auth, &sock) {
+                continue;
+            }
+        }
+        #[cfg(unix)]
+        {
+            let sr = SockRef::from(&sock);
+            let _ = sr.set_recv_buffer_size(s.recv_buf_bytes);
+            if let Ok(actual) = sr.recv_buffer_size() {
+                info!(
+                    "UDS recv buffer set: requested={} actual={}",
+                    s.recv_buf_bytes, actual
+                );
+                gauge!("ultra_uds_recv_buf_bytes").set(actual as f64);
+            }
+        }
+        let (conn, out) = (path.clone(), out.clone());
+        tokio::spawn(async move {
+            let token = s.auth.as_ref().and_then(|a| a.token.as_deref());
+            if let Err(e) = ingest::handle_client(sock, s.limits, token, &conn, out).await {
+                error!("client error: {e:?}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::Record;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn settings_apply_to_new_connections() {
+        let path = std::env::temp_dir().join(format!("ultra-uds-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let settings = |max_frame_bytes| UdsSettings {
+            recv_buf_bytes: 1 << 16,
+            limits: FrameLimits {
+                max_frame_bytes,
+                accept_legacy: false,
+                quarantine: None,
+            },
+            auth: None,
+        };
+        let (out, mut rx) = mpsc::channel(16);
+        let l = UdsListener::bind(&path, settings(1), out).unwrap();
+        let rec = Record::Slot {
+            slot: 7,
+            parent: None,
+            status: 1,
+        };
+        let frame = faststreams::encode_record(&rec).unwrap();
+
+        // Too large for the first limit: the frame is skipped.
+        let mut c = tokio::net::UnixStream::connect(&path).await.unwrap();
+        c.write_all(&frame).await.unwrap();
+        drop(c);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(rx.try_recv().is_err());
+        l.update(settings(1 << 20));
+        let mut c = tokio::net::UnixStream::connect(&path).await.unwrap();
+        c.write_all(&frame).await.unwrap();
+        drop(c);
+        let got = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap();
+        assert!(matches!(
+            got.map(|i| i.rec),
+            Some(Record::Slot { slot: 7, .. })
+        ));
+
+        drop(l);
+        assert!(!Path::new(&path).exists());
+    }
+}
@@ -0,0 +1,299 @@
+// Numan Thabit 2025
+// crates/ultra-aggregator/src/ws.rs
+//
+// WebSocket fan-out of the JSON events written by `stdout_json`. Each event is
+// rendered once and broadcast; clients narrow what they receive by sending a
+// subscription message at any time:
+//   {"kinds": ["account", "tx"], "owners": ["<b58>"], "pubkeys": ["<b58>"]}
+// Empty lists mean "everything". Owners/pubkeys only constrain account events.
+// A client that falls more than `client_queue` events behind is disconnected
+// (or, with `slow_client: "skip"`, loses the events it missed).
+use crate::{sink_queue, write_json_event, Base58Cache, JsonEvent};
+use anyhow::{Context, Result};
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use metrics::{counter, gauge};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlowClientPolicy {
+    #[default]
+    Disconnect,
+    Skip,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct WsCfg {
+    pub bind: String,
+    /// Events a client may fall behind before the slow-client policy applies.
+    #[serde(default)]
+    pub client_queue: Option<usize>,
+    #[serde(default)]
+    pub slow_client: SlowClientPolicy,
+    #[serde(default)]
+    pub max_clients: Option<usize>,
+}
+
+// One rendered event plus the fields subscriptions filter on.
+#[derive(Debug)]
+struct WsEvent {
+    kind: &'static str,
+    pubkey: Option<[u8; 32]>,
+    owner: Option<[u8; 32]>,
+    json: String,
+}
+
+fn event_kind(evt: &JsonEvent) -> &'static str {
+    match evt {
+        JsonEvent::Account { .. } => "account",
+        JsonEvent::Tx { .. } => "tx",
+        JsonEvent::Block { .. } => "block",
+        JsonEvent::Slot { .. } => "slot",
+        JsonEvent::EndOfStartup => "end_of_startup",
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscribeMsg {
+    #[serde(default)]
+    kinds: Vec<String>,
+    #[serde(default)]
+    owners: Vec<String>,
+    #[serde(default)]
+    pubkeys: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ClientFilter {
+    kinds: HashSet<String>,
+    owners: HashSet<[u8; 32]>,
+    pubkeys: HashSet<[u8; 32]>,
+}
+
+fn decode_key(s: &str) -> Result<[u8; 32], String> {
+    bs58::decode(s)
+        .into_vec()
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| format!("invalid pubkey {s}"))
+}
+
+impl ClientFilter {
+    fn parse(text: &str) -> Result<Self, String> {
+        let msg: SubscribeMsg = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        Ok(Self {
+            kinds: msg.kinds.into_iter().collect(),
+            owners: msg
+                .owners
+                .iter()
+                .map(|s| decode_key(s))
+                .collect::<Result<_, _>>()?,
+            pubkeys: msg
+                .pubkeys
+                .iter()
+                .map(|s| decode_key(s))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn matches(&self, evt: &WsEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(evt.kind) {
+            return false;
+        }
+        if evt.kind != "account" || (self.owners.is_empty() && self.pubkeys.is_empty()) {
+            return true;
+        }
+        evt.pubkey.is_some_and(|p| self.pubkeys.contains(&p))
+            || evt.owner.is_some_and(|o| self.owners.contains(&o))
+    }
+}
+
+struct Shared {
+    events: broadcast::Sender<Arc<WsEvent>>,
+    policy: SlowClientPolicy,
+    clients: AtomicUsize,
+    max_clients: usize,
+}
+
+#[derive(Clone)]
+pub struct WsSink {
+    tx: sink_queue::QueueTx<JsonEvent>,
+}
+
+impl WsSink {
+    pub async fn new(cfg: WsCfg, queue: &sink_queue::QueueCfg) -> Result<Self> {
+        let (events, _) = broadcast::channel(cfg.client_queue.unwrap_or(1024).max(1));
+        let shared = Arc::new(Shared {
+            events: events.clone(),
+            policy: cfg.slow_client,
+            clients: AtomicUsize::new(0),
+            max_clients: cfg.max_clients.unwrap_or(256),
+        });
+
+        // Render on a dedicated thread, like the stdout sink; skipped while nobody listens.
+        let (tx, rx) = sink_queue::queue::<JsonEvent>("ws", queue);
+        let errors = rx.errors();
+        std::thread::spawn(move || {
+            let mut cache32 = Base58Cache::<32>::new(16_384);
+            let mut cache64 = Base58Cache::<64>::new(8_192);
+            while let Some(evt) = rx.blocking_recv() {
+                if events.receiver_count() == 0 {
+                    continue;
+                }
+                let mut json = Vec::with_capacity(256);
+                if write_json_event(&evt, &mut json, &mut cache32, &mut cache64).is_err() {
+                    errors.add(1);
+                    continue;
+                }
+                let (pubkey, owner) = match &evt {
+                    JsonEvent::Account { pubkey, owner, .. } => (Some(*pubkey), Some(*owner)),
+                    _ => (None, None),
+                };
+                let _ = events.send(Arc::new(WsEvent {
+                    kind: event_kind(&evt),
+                    pubkey,
+                    owner,
+                    json: String::from_utf8(json).unwrap_or_default(),
+                }));
+            }
+        });
+
+        let listener = tokio::net::TcpListener::bind(&cfg.bind)
+            .await
+            .with_context(|| format!("bind ws {}", cfg.bind))?;
+        info!("websocket output on ws://{}", cfg.bind);
+        let app = Router::new().route("/", get(upgrade)).with_state(shared);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                warn!("ws server stopped: {e}");
+            }
+        });
+        Ok(Self { tx })
+    }
+
+    pub async fn send(&self, evt: JsonEvent, kind: &'static str) -> bool {
+        self.tx.send(evt, kind).await
+    }
+}
+
+async fn upgrade(ws: WebSocketUpgrade, State(shared): State<Arc<Shared>>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| serve_client(socket, shared))
+}
+
+async fn close(mut socket: WebSocket, code: u16, reason: &'static str) {
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        })))
+        .await;
+}
+
+async fn serve_client(mut socket: WebSocket, shared: Arc<Shared>) {
+    if shared.clients.fetch_add(1, Ordering::Relaxed) >= shared.max_clients {
+        shared.clients.fetch_sub(1, Ordering::Relaxed);
+        counter!("ultra_ws_rejected_total").increment(1);
+        // 1013: try again later
+        close(socket, 1013, "too many clients").await;
+        return;
+    }
+    gauge!("ultra_ws_clients").increment(1.0);
+    let mut events = shared.events.subscribe();
+    let mut filter = ClientFilter::default();
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => match ClientFilter::parse(&text) {
+                    Ok(f) => filter = f,
+                    Err(e) => {
+                        let err = serde_json::json!({ "error": e }).to_string();
+                        if socket.send(Message::Text(err)).await.is_err() {
+                            break;
+                        }
+                    }
+                },
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => {}
+            },
+            evt = events.recv() => match evt {
+                Ok(evt) => {
+                    if !filter.matches(&evt) {
+                        continue;
+                    }
+                    if socket.send(Message::Text(evt.json.clone())).await.is_err() {
+                        break;
+                    }
+                    counter!("ultra_ws_sent_total").increment(1);
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    counter!("ultra_ws_lagged_events_total").increment(missed);
+                    if shared.policy == SlowClientPolicy::Disconnect {
+                        counter!("ultra_ws_slow_disconnects_total").increment(1);
+                        // 1008: policy violation
+                        close(socket, 1008, "slow client").await;
+                        client_gone(&shared);
+                        return;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+        }
+    }
+    client_gone(&shared);
+}
+
+fn client_gone(shared: &Shared) {
+    shared.clients.fetch_sub(1, Ordering::Relaxed);
+    gauge!("ultra_ws_clients").decrement(1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(pubkey: u8, owner: u8) -> WsEvent {
+        WsEvent {
+            kind: "account",
+            pubkey: Some([pubkey; 32]),
+            owner: Some([owner; 32]),
+            json: String::new(),
+        }
+    }
+
+    #[test]
+    fn filter_matches_kinds_owners_and_pubkeys() {
+        let b58 = |b: u8| bs58::encode([b; 32]).into_string();
+        let slot = WsEvent {
+            kind: "slot",
+            pubkey: None,
+            owner: None,
+            json: String::new(),
+        };
+        assert!(ClientFilter::default().matches(&slot));
+
+        let f = ClientFilter::parse(&format!(
+            r#"{{"kinds":["account","slot"],"owners":["{}"],"pubkeys":["{}"]}}"#,
+            b58(1),
+            b58(2)
+        ))
+        .unwrap();
+        assert!(f.matches(&account(9, 1)), "owner match");
+        assert!(f.matches(&account(2, 9)), "pubkey match");
+        assert!(!f.matches(&account(9, 9)));
+        assert!(f.matches(&slot), "owners/pubkeys only narrow accounts");
+        let tx = WsEvent { kind: "tx", ..slot };
+        assert!(!f.matches(&tx));
+
+        assert!(ClientFilter::parse(r#"{"owners":["nope"]}"#).is_err());
+        assert!(ClientFilter::parse(r#"{"kind":["tx"]}"#).is_err());
+    }
+}
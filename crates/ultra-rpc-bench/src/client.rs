@@ -0,0 +1,247 @@
+// Numan Thabit 2025
+use std::{
+    fs,
+    io::Cursor,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::ValueEnum;
+use quinn::{crypto::rustls::QuicClientConfig, ClientConfig, Connection, Endpoint};
+use rustls::{pki_types::CertificateDer, RootCertStore};
+use serde::{Deserialize, Serialize};
+
+/// Length prefix of a QUIC frame (u32 big endian), as the server expects.
+const FRAME_HEADER: usize = 4;
+/// Largest frame the server accepts or sends.
+const MAX_FRAME_LEN: usize = 1 << 20;
+/// ALPN the solana-ultra-rpc QUIC listener negotiates.
+const QUIC_ALPN: &[u8] = b"jsonrpc-quic";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportKind {
+    /// JSON-RPC over HTTP POST, e.g. through solana-quic-proxy.
+    Http,
+    /// Length-prefixed JSON-RPC frames on QUIC bidirectional streams.
+    Quic,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub transport: TransportKind,
+    pub endpoint: String,
+    pub connections: usize,
+    pub ca_cert: Option<PathBuf>,
+    pub server_name: Option<String>,
+}
+
+/// A JSON-RPC client shared by every in-flight request of a run.
+#[derive(Clone)]
+pub enum RpcClient {
+    Http {
+        client: reqwest::Client,
+        url: Arc<str>,
+    },
+    Quic(Arc<QuicPool>),
+}
+
+pub struct QuicPool {
+    endpoint: Endpoint,
+    connections: Vec<Connection>,
+    next: AtomicUsize,
+}
+
+impl RpcClient {
+    pub async fn connect(options: &ClientOptions) -> Result<Self> {
+        let connections = options.connections.max(1);
+        match options.transport {
+            TransportKind::Http => {
+                let client = reqwest::Client::builder()
+                    .tcp_nodelay(true)
+                    .tcp_keepalive(Some(Duration::from_secs(15)))
+                    .pool_max_idle_per_host(connections)
+                    .build()
+                    .context("failed to build http client")?;
+                Ok(Self::Http {
+                    client,
+                    url: http_url(&options.endpoint).into(),
+                })
+            }
+            TransportKind::Quic => {
+                let (addr, host) = quic_addr(&options.endpoint)?;
+                let server_name = options.server_name.clone().unwrap_or(host);
+                let ca_cert = options.ca_cert.as_ref().ok_or_else(|| {
+                    anyhow!("the quic transport needs --quic-ca-cert to trust the server")
+                })?;
+                let bind: SocketAddr = if addr.is_ipv4() {
+                    "0.0.0.0:0".parse()?
+                } else {
+                    "[::]:0".parse()?
+                };
+                let mut endpoint =
+                    Endpoint::client(bind).context("failed to create QUIC endpoint")?;
+                endpoint.set_default_client_config(quic_client_config(ca_cert)?);
+
+                let mut pool = Vec::with_capacity(connections);
+                for _ in 0..connections {
+                    let connection = endpoint
+                        .connect(addr, &server_name)
+                        .context("failed to start QUIC connection")?
+                        .await
+                        .with_context(|| format!("failed to connect to {addr}"))?;
+                    pool.push(connection);
+                }
+                Ok(Self::Quic(Arc::new(QuicPool {
+                    endpoint,
+                    connections: pool,
+                    next: AtomicUsize::new(0),
+                })))
+            }
+        }
+    }
+
+    /// Sends one encoded request and returns the raw response body.
+    pub async fn call(&self, body: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            Self::Http { client, url } => {
+                let response = client
+                    .post(url.as_ref())
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+                Ok(response.bytes().await?.to_vec())
+            }
+            Self::Quic(pool) => pool.call(body).await,
+        }
+    }
+
+    pub async fn close(&self) {
+        if let Self::Quic(pool) = self {
+            pool.endpoint.close(0u32.into(), b"done");
+            pool.endpoint.wait_idle().await;
+        }
+    }
+}
+
+impl QuicPool {
+    async fn call(&self, body: Vec<u8>) -> Result<Vec<u8>> {
+        ensure!(
+            body.len() <= MAX_FRAME_LEN,
+            "request of {} bytes exceeds the frame limit",
+            body.len()
+        );
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        let (mut send, mut recv) = self.connections[index].open_bi().await?;
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER + body.len());
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&body);
+        send.write_all(&frame).await?;
+        send.finish()?;
+
+        let mut header = [0u8; FRAME_HEADER];
+        recv.read_exact(&mut header).await?;
+        let len = u32::from_be_bytes(header) as usize;
+        ensure!(
+            len <= MAX_FRAME_LEN,
+            "response frame length {len} exceeds the limit"
+        );
+        let mut payload = vec![0u8; len];
+        recv.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+}
+
+/// Whether a response body is a JSON-RPC success rather than an error
+/// object.
+pub fn is_success(body: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct Envelope {
+        #[serde(default)]
+        error: Option<serde::de::IgnoredAny>,
+    }
+    serde_json::from_slice::<Envelope>(body).is_ok_and(|envelope| envelope.error.is_none())
+}
+
+pub fn http_url(endpoint: &str) -> String {
+    if endpoint.contains("://") {
+        endpoint.to_string()
+    } else {
+        format!("http://{endpoint}")
+    }
+}
+
+/// Resolves a `host:port` endpoint, returning the address and the host to
+/// verify the certificate against.
+fn quic_addr(endpoint: &str) -> Result<(SocketAddr, String)> {
+    let authority = endpoint.strip_prefix("quic://").unwrap_or(endpoint);
+    let addr = authority
+        .to_socket_addrs()
+        .with_context(|| format!("failed to resolve {authority}"))?
+        .next()
+        .ok_or_else(|| anyhow!("{authority} resolved to no addresses"))?;
+    let host = authority
+        .rsplit_once(':')
+        .map_or(authority, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    Ok((addr, host.to_string()))
+}
+
+fn quic_client_config(ca_cert: &Path) -> Result<ClientConfig> {
+    let pem = fs::read(ca_cert)
+        .with_context(|| format!("failed to read CA bundle {}", ca_cert.display()))?;
+    let certs: Vec<CertificateDer<'static>> = rustls_pemfile::certs(&mut Cursor::new(pem))
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("failed to parse CA bundle {}", ca_cert.display()))?;
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(certs);
+    if added == 0 {
+        bail!("no usable certificates in {}", ca_cert.display());
+    }
+
+    let mut crypto = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(crypto)
+        .context("failed to convert TLS client config for QUIC")?;
+    Ok(ClientConfig::new(Arc::new(crypto)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_responses() {
+        assert!(is_success(br#"{"jsonrpc":"2.0","id":1,"result":42}"#));
+        assert!(!is_success(
+            br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"nope"}}"#
+        ));
+        assert!(!is_success(b"<html>bad gateway</html>"));
+    }
+
+    #[test]
+    fn normalizes_endpoints() {
+        assert_eq!(http_url("127.0.0.1:8898"), "http://127.0.0.1:8898");
+        assert_eq!(
+            http_url("https://rpc.example/rpc"),
+            "https://rpc.example/rpc"
+        );
+        let (addr, host) = quic_addr("quic://127.0.0.1:8899").unwrap();
+        assert_eq!(addr, "127.0.0.1:8899".parse::<SocketAddr>().unwrap());
+        assert_eq!(host, "127.0.0.1");
+        let (_, host) = quic_addr("[::1]:8899").unwrap();
+        assert_eq!(host, "::1");
+    }
+}
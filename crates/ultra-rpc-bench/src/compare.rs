@@ -0,0 +1,278 @@
+// Numan Thabit 2025
+use std::collections::BTreeSet;
+
+use anyhow::{bail, Context, Result};
+use hdrhistogram::Histogram;
+use serde::Serialize;
+
+use crate::{
+    latency,
+    report::{IterationReport, MethodReport},
+};
+
+/// Scope of the deltas computed over every method together.
+const TOTAL_SCOPE: &str = "total";
+
+/// Limits beyond which a change against the baseline is a regression.
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    /// Largest tolerated throughput drop, in percent.
+    pub max_throughput_drop: f64,
+    /// Largest tolerated rise of a gated latency percentile, in percent.
+    pub max_latency_increase: f64,
+    /// Largest tolerated rise of the error rate, in percentage points.
+    pub max_error_rate_increase: f64,
+    /// Latency percentiles that are gated.
+    pub percentiles: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delta {
+    /// `total` or a method label.
+    pub scope: String,
+    pub metric: String,
+    pub baseline: f64,
+    pub current: f64,
+    /// Change in percent of the baseline, or in percentage points for error
+    /// rates.
+    pub change: f64,
+    pub limit: f64,
+    pub regressed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Comparison {
+    pub deltas: Vec<Delta>,
+}
+
+impl Comparison {
+    pub fn regressions(&self) -> impl Iterator<Item = &Delta> {
+        self.deltas.iter().filter(|delta| delta.regressed)
+    }
+}
+
+/// One scope's requests across every iteration of a report.
+struct Aggregate {
+    attempts: u64,
+    errors: u64,
+    latency: Histogram<u64>,
+}
+
+impl Aggregate {
+    fn new<'a>(reports: impl Iterator<Item = &'a MethodReport>) -> Result<Self> {
+        let mut aggregate = Self {
+            attempts: 0,
+            errors: 0,
+            latency: latency::new_histogram(),
+        };
+        for report in reports {
+            aggregate.attempts += report.requests + report.dropped;
+            aggregate.errors +=
+                report.rpc_errors + report.failures + report.timeouts + report.dropped;
+            if let Some(summary) = &report.latency {
+                aggregate
+                    .latency
+                    .add(latency::decode(&summary.histogram)?)
+                    .context("failed to merge latency histograms")?;
+            }
+        }
+        Ok(aggregate)
+    }
+
+    /// Share of attempted requests that failed, in percent.
+    fn error_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.0
+        } else {
+            self.errors as f64 * 100.0 / self.attempts as f64
+        }
+    }
+}
+
+/// The total with no `method`, otherwise that method's entry.
+fn scope_report<'a>(
+    report: &'a IterationReport,
+    method: Option<&String>,
+) -> Option<&'a MethodReport> {
+    match method {
+        None => Some(&report.total),
+        Some(method) => report.methods.get(method),
+    }
+}
+
+/// Median of `values`, or `None` when there are none.
+pub fn median(values: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let mut values: Vec<f64> = values.into_iter().collect();
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    })
+}
+
+fn median_rate(reports: &[IterationReport]) -> f64 {
+    median(reports.iter().map(|report| report.achieved_rate)).unwrap_or(0.0)
+}
+
+pub fn percent_change(baseline: f64, current: f64) -> f64 {
+    if baseline == 0.0 {
+        if current == 0.0 {
+            0.0
+        } else {
+            f64::INFINITY.copysign(current)
+        }
+    } else {
+        (current - baseline) * 100.0 / baseline
+    }
+}
+
+/// Compares `current` with `baseline`: median throughput over iterations,
+/// then error rate and gated latency percentiles of the merged histograms,
+/// for the total and for every method present in both.
+pub fn compare(
+    baseline: &[IterationReport],
+    current: &[IterationReport],
+    thresholds: &Thresholds,
+) -> Result<Comparison> {
+    if baseline.is_empty() || current.is_empty() {
+        bail!("both reports need at least one iteration to compare");
+    }
+
+    let mut deltas = Vec::new();
+    let (before, after) = (median_rate(baseline), median_rate(current));
+    let change = percent_change(before, after);
+    deltas.push(Delta {
+        scope: TOTAL_SCOPE.to_string(),
+        metric: "throughput".to_string(),
+        baseline: before,
+        current: after,
+        change,
+        limit: thresholds.max_throughput_drop,
+        regressed: change < -thresholds.max_throughput_drop,
+    });
+
+    let methods: BTreeSet<&String> = baseline
+        .iter()
+        .flat_map(|report| report.methods.keys())
+        .filter(|method| {
+            current
+                .iter()
+                .any(|report| report.methods.contains_key(*method))
+        })
+        .collect();
+    let scopes = std::iter::once(None).chain(methods.into_iter().map(Some));
+
+    for scope in scopes {
+        let before = Aggregate::new(baseline.iter().filter_map(|r| scope_report(r, scope)))?;
+        let after = Aggregate::new(current.iter().filter_map(|r| scope_report(r, scope)))?;
+        let scope = scope.map_or(TOTAL_SCOPE, String::as_str);
+
+        let change = after.error_rate() - before.error_rate();
+        deltas.push(Delta {
+            scope: scope.to_string(),
+            metric: "error_rate_pct".to_string(),
+            baseline: before.error_rate(),
+            current: after.error_rate(),
+            change,
+            limit: thresholds.max_error_rate_increase,
+            regressed: change > thresholds.max_error_rate_increase,
+        });
+
+        if before.latency.is_empty() || after.latency.is_empty() {
+            continue;
+        }
+        for &percentile in &thresholds.percentiles {
+            let old = before.latency.value_at_percentile(percentile) as f64;
+            let new = after.latency.value_at_percentile(percentile) as f64;
+            let change = percent_change(old, new);
+            deltas.push(Delta {
+                scope: scope.to_string(),
+                metric: format!("p{percentile}_latency_ns"),
+                baseline: old,
+                current: new,
+                change,
+                limit: thresholds.max_latency_increase,
+                regressed: change > thresholds.max_latency_increase,
+            });
+        }
+    }
+
+    Ok(Comparison { deltas })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::*;
+    use crate::{
+        client::TransportKind,
+        load::{Arrival, LoadPlan, MethodStats},
+    };
+
+    fn report(rate: f64, latency_ns: u64, errors: u64) -> IterationReport {
+        let mut stats = MethodStats {
+            requests: 1_000,
+            ok: 1_000 - errors,
+            failures: errors,
+            ..MethodStats::default()
+        };
+        stats.latency.record_n(latency_ns, 1_000 - errors).unwrap();
+        let plan = LoadPlan {
+            rate,
+            duration: Duration::from_secs(10),
+            arrival: Arrival::Constant,
+            max_in_flight: 64,
+            request_timeout: Duration::from_secs(1),
+        };
+        let methods = BTreeMap::from([("getSlot".to_string(), stats.clone())]);
+        let elapsed = Duration::from_secs_f64((1_000 - errors) as f64 / rate);
+        IterationReport::new(1, TransportKind::Quic, &plan, &stats, &methods, elapsed).unwrap()
+    }
+
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            max_throughput_drop: 5.0,
+            max_latency_increase: 10.0,
+            max_error_rate_increase: 0.5,
+            percentiles: vec![99.0],
+        }
+    }
+
+    #[test]
+    fn passes_within_thresholds() {
+        let baseline = [report(1_000.0, 1_000_000, 0)];
+        let current = [report(980.0, 1_050_000, 2)];
+        let comparison = compare(&baseline, &current, &thresholds()).unwrap();
+        assert_eq!(comparison.regressions().count(), 0);
+        // throughput, then error rate and p99 for the total and for getSlot
+        assert_eq!(comparison.deltas.len(), 5);
+        assert!(comparison
+            .deltas
+            .iter()
+            .any(|delta| delta.scope == "getSlot" && delta.metric == "p99_latency_ns"));
+    }
+
+    #[test]
+    fn flags_each_regressed_metric() {
+        let baseline = [report(1_000.0, 1_000_000, 0), report(1_010.0, 1_000_000, 0)];
+        let current = [report(900.0, 1_500_000, 20)];
+        let comparison = compare(&baseline, &current, &thresholds()).unwrap();
+        let regressed: BTreeSet<(&str, &str)> = comparison
+            .regressions()
+            .map(|delta| (delta.scope.as_str(), delta.metric.as_str()))
+            .collect();
+        assert!(regressed.contains(&("total", "throughput")));
+        assert!(regressed.contains(&("total", "p99_latency_ns")));
+        assert!(regressed.contains(&("getSlot", "error_rate_pct")));
+
+        let throughput = &comparison.deltas[0];
+        assert!((throughput.baseline - 1_005.0).abs() < 0.01);
+        assert!(compare(&[], &current, &thresholds()).is_err());
+    }
+}
@@ -0,0 +1,435 @@
+// Numan Thabit 2025
+//! Coordinator/worker mode for loads beyond one client machine.
+//!
+//! Workers listen on TCP; the coordinator connects to each and exchanges
+//! newline-delimited JSON [`Message`]s. Every iteration it sends each
+//! worker a `prepare` with its share of the plan and waits for `ready`, then
+//! sends `start` to all and collects one `result` per worker, whose HDR
+//! histograms are merged into a single report. Workers hit the endpoint and
+//! run the workload given on their own command line.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    time::timeout,
+};
+use tracing::{info, warn};
+
+use crate::{
+    client::{ClientOptions, RpcClient},
+    latency,
+    load::{self, Arrival, LoadPlan, MethodStats},
+    report::group_by_method,
+    workload::Workload,
+};
+
+/// Bumped whenever [`Message`] changes incompatibly.
+const PROTOCOL_VERSION: u32 = 1;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a worker may take to connect to the endpoint and answer `prepare`.
+const PREPARE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Slack past the plan's duration and request timeout before a worker's
+/// result is given up on.
+const RESULT_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Prepare { version: u32, job: Job },
+    Ready,
+    Start,
+    Result(WorkerResult),
+    Error { message: String },
+}
+
+/// One worker's share of an iteration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Job {
+    iteration: u32,
+    rate: f64,
+    duration_ms: u64,
+    arrival: Arrival,
+    max_in_flight: usize,
+    request_timeout_ms: u64,
+}
+
+impl Job {
+    fn share(iteration: u32, plan: &LoadPlan, workers: usize) -> Self {
+        Self {
+            iteration,
+            rate: plan.rate / workers as f64,
+            duration_ms: millis(plan.duration),
+            arrival: plan.arrival,
+            max_in_flight: plan.max_in_flight.div_ceil(workers),
+            request_timeout_ms: millis(plan.request_timeout),
+        }
+    }
+
+    fn plan(&self) -> LoadPlan {
+        LoadPlan {
+            rate: self.rate,
+            duration: Duration::from_millis(self.duration_ms),
+            arrival: self.arrival,
+            max_in_flight: self.max_in_flight,
+            request_timeout: Duration::from_millis(self.request_timeout_ms),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WorkerResult {
+    elapsed_ms: u64,
+    methods: BTreeMap<String, WireStats>,
+}
+
+/// [`MethodStats`] on the wire, the histogram as [`latency::encode`]s it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct WireStats {
+    requests: u64,
+    ok: u64,
+    rpc_errors: u64,
+    failures: u64,
+    timeouts: u64,
+    dropped: u64,
+    latency: String,
+}
+
+impl WireStats {
+    fn new(stats: &MethodStats) -> Result<Self> {
+        Ok(Self {
+            requests: stats.requests,
+            ok: stats.ok,
+            rpc_errors: stats.rpc_errors,
+            failures: stats.failures,
+            timeouts: stats.timeouts,
+            dropped: stats.dropped,
+            latency: latency::encode(&stats.latency)?,
+        })
+    }
+
+    fn into_stats(self) -> Result<MethodStats> {
+        Ok(MethodStats {
+            requests: self.requests,
+            ok: self.ok,
+            rpc_errors: self.rpc_errors,
+            failures: self.failures,
+            timeouts: self.timeouts,
+            dropped: self.dropped,
+            latency: latency::decode(&self.latency)?,
+        })
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+/// One side of a control connection.
+struct Channel {
+    peer: String,
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Channel {
+    fn new(stream: TcpStream, peer: String) -> Self {
+        let _ = stream.set_nodelay(true);
+        let (reader, writer) = stream.into_split();
+        Self {
+            peer,
+            lines: BufReader::new(reader).lines(),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, message: &Message) -> Result<()> {
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.writer
+            .write_all(&line)
+            .await
+            .with_context(|| format!("failed to send to {}", self.peer))
+    }
+
+    async fn recv(&mut self) -> Result<Option<Message>> {
+        let Some(line) = self
+            .lines
+            .next_line()
+            .await
+            .with_context(|| format!("failed to read from {}", self.peer))?
+        else {
+            return Ok(None);
+        };
+        serde_json::from_str(&line)
+            .with_context(|| format!("malformed control message from {}", self.peer))
+            .map(Some)
+    }
+
+    /// Waits for the worker's answer, turning a reported error or a hang up
+    /// into an error.
+    async fn expect(&mut self, wait: Duration) -> Result<Message> {
+        let message = timeout(wait, self.recv())
+            .await
+            .map_err(|_| anyhow!("worker {} did not answer within {wait:?}", self.peer))??;
+        match message {
+            Some(Message::Error { message }) => bail!("worker {} failed: {message}", self.peer),
+            Some(message) => Ok(message),
+            None => bail!("worker {} closed the control connection", self.peer),
+        }
+    }
+}
+
+/// One worker's part of a distributed iteration.
+pub struct WorkerRun {
+    pub peer: String,
+    pub elapsed: Duration,
+    /// All of the worker's methods merged.
+    pub total: MethodStats,
+}
+
+/// Merged outcome of one distributed iteration.
+pub struct DistributedRun {
+    /// Longest any worker's run took.
+    pub elapsed: Duration,
+    pub methods: BTreeMap<String, MethodStats>,
+    pub workers: Vec<WorkerRun>,
+}
+
+/// Drives a fixed set of workers, splitting every iteration's rate and
+/// in-flight cap evenly between them.
+pub struct Coordinator {
+    workers: Vec<Channel>,
+}
+
+impl Coordinator {
+    pub async fn connect(addrs: &[String]) -> Result<Self> {
+        let mut workers = Vec::with_capacity(addrs.len());
+        for addr in addrs {
+            let stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+                .await
+                .map_err(|_| anyhow!("timed out connecting to worker {addr}"))?
+                .with_context(|| format!("failed to connect to worker {addr}"))?;
+            workers.push(Channel::new(stream, addr.clone()));
+        }
+        info!(workers = workers.len(), "connected to load workers");
+        Ok(Self { workers })
+    }
+
+    pub async fn run(&mut self, iteration: u32, plan: &LoadPlan) -> Result<DistributedRun> {
+        let job = Job::share(iteration, plan, self.workers.len());
+        for worker in &mut self.workers {
+            worker
+                .send(&Message::Prepare {
+                    version: PROTOCOL_VERSION,
+                    job: job.clone(),
+                })
+                .await?;
+        }
+        for worker in &mut self.workers {
+            match worker.expect(PREPARE_TIMEOUT).await? {
+                Message::Ready => {}
+                other => bail!("worker {} answered prepare with {other:?}", worker.peer),
+            }
+        }
+
+        // Start everyone once all are connected, so the shares overlap
+        for worker in &mut self.workers {
+            worker.send(&Message::Start).await?;
+        }
+        let wait = plan.duration + plan.request_timeout + RESULT_GRACE;
+        let mut elapsed = Duration::ZERO;
+        let mut methods: BTreeMap<String, MethodStats> = BTreeMap::new();
+        let mut runs = Vec::with_capacity(self.workers.len());
+        for worker in &mut self.workers {
+            let Message::Result(result) = worker.expect(wait).await? else {
+                bail!("worker {} answered start without a result", worker.peer);
+            };
+            let worker_elapsed = Duration::from_millis(result.elapsed_ms);
+            elapsed = elapsed.max(worker_elapsed);
+            let mut total = MethodStats::default();
+            for (label, stats) in result.methods {
+                let stats = stats
+                    .into_stats()
+                    .with_context(|| format!("bad stats from worker {}", worker.peer))?;
+                total.merge(&stats);
+                methods.entry(label).or_default().merge(&stats);
+            }
+            runs.push(WorkerRun {
+                peer: worker.peer.clone(),
+                elapsed: worker_elapsed,
+                total,
+            });
+        }
+        Ok(DistributedRun {
+            elapsed,
+            methods,
+            workers: runs,
+        })
+    }
+}
+
+/// Serves coordinators one at a time, forever.
+pub async fn serve(
+    listener: TcpListener,
+    options: &ClientOptions,
+    workload: &Workload,
+) -> Result<()> {
+    info!(addr = %listener.local_addr()?, "load worker listening");
+    loop {
+        let (stream, peer) = listener
+            .accept()
+            .await
+            .context("failed to accept coordinator")?;
+        info!(%peer, "coordinator connected");
+        match session(Channel::new(stream, peer.to_string()), options, workload).await {
+            Ok(()) => info!(%peer, "coordinator disconnected"),
+            Err(err) => warn!(%peer, error = %format!("{err:#}"), "coordinator session failed"),
+        }
+    }
+}
+
+async fn session(mut channel: Channel, options: &ClientOptions, workload: &Workload) -> Result<()> {
+    let labels = workload.labels();
+    let mut client: Option<RpcClient> = None;
+    let mut prepared: Option<Job> = None;
+
+    while let Some(message) = channel.recv().await? {
+        let reply = match message {
+            Message::Prepare { version, .. } if version != PROTOCOL_VERSION => Message::Error {
+                message: format!(
+                    "coordinator speaks protocol v{version}, this worker v{PROTOCOL_VERSION}"
+                ),
+            },
+            Message::Prepare { job, .. } => {
+                let connected = match client.take() {
+                    Some(client) => Ok(client),
+                    None => RpcClient::connect(options).await,
+                };
+                match connected.and_then(|connected| {
+                    job.plan().validate()?;
+                    Ok(connected)
+                }) {
+                    Ok(connected) => {
+                        client = Some(connected);
+                        prepared = Some(job);
+                        Message::Ready
+                    }
+                    Err(err) => Message::Error {
+                        message: format!("{err:#}"),
+                    },
+                }
+            }
+            Message::Start => match (prepared.take(), client.as_ref()) {
+                (Some(job), Some(client)) => {
+                    info!(
+                        iteration = job.iteration,
+                        rate = job.rate,
+                        duration_ms = job.duration_ms,
+                        "running share of load"
+                    );
+                    match run_job(client, workload, &labels, &job).await {
+                        Ok(result) => Message::Result(result),
+                        Err(err) => Message::Error {
+                            message: format!("{err:#}"),
+                        },
+                    }
+                }
+                _ => Message::Error {
+                    message: "start before prepare".to_string(),
+                },
+            },
+            other => Message::Error {
+                message: format!("unexpected {other:?}"),
+            },
+        };
+        channel.send(&reply).await?;
+    }
+
+    if let Some(client) = client {
+        client.close().await;
+    }
+    Ok(())
+}
+
+async fn run_job(
+    client: &RpcClient,
+    workload: &Workload,
+    labels: &[String],
+    job: &Job,
+) -> Result<WorkerResult> {
+    let stats = load::run(client, workload, &job.plan()).await?;
+    Ok(WorkerResult {
+        elapsed_ms: millis(stats.elapsed),
+        methods: group_by_method(labels, &stats)
+            .iter()
+            .map(|(label, stats)| Ok((label.clone(), WireStats::new(stats)?)))
+            .collect::<Result<_>>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http_options, http_stub};
+
+    #[tokio::test]
+    async fn merges_worker_results() {
+        let addr = http_stub(|_| r#"{"jsonrpc":"2.0","id":1,"result":7}"#).await;
+        let options = http_options(addr);
+        let mut workers = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            workers.push(listener.local_addr().unwrap().to_string());
+            let options = options.clone();
+            tokio::spawn(async move {
+                let workload = Workload::new(vec!["getSlot".parse().unwrap()]).unwrap();
+                serve(listener, &options, &workload).await
+            });
+        }
+
+        let mut coordinator = Coordinator::connect(&workers).await.unwrap();
+        let plan = LoadPlan {
+            rate: 400.0,
+            duration: Duration::from_millis(100),
+            arrival: Arrival::Constant,
+            max_in_flight: 32,
+            request_timeout: Duration::from_secs(1),
+        };
+        for iteration in 1..=2 {
+            let run = coordinator.run(iteration, &plan).await.unwrap();
+            let stats = &run.methods["getSlot"];
+            // 200/s for 100ms on each of two workers
+            assert_eq!(stats.requests + stats.dropped, 40, "{stats:?}");
+            assert_eq!(stats.latency.len(), stats.ok);
+            assert_eq!(run.workers.len(), 2);
+            let per_worker: u64 = run.workers.iter().map(|w| w.total.latency.len()).sum();
+            assert_eq!(stats.latency.len(), per_worker);
+            let share = plan.rate / run.workers.len() as f64;
+            for worker in &run.workers {
+                // Constant arrivals: the last answered send is scheduled no
+                // earlier than (n - 1) / rate after the first
+                let spacing = worker.total.requests.saturating_sub(1) as f64 / share;
+                assert!(
+                    worker.elapsed >= Duration::from_secs_f64(spacing),
+                    "{} took {:?} for {} requests",
+                    worker.peer,
+                    worker.elapsed,
+                    worker.total.requests
+                );
+                assert!(run.elapsed >= worker.elapsed);
+            }
+        }
+
+        let mut bad = plan.clone();
+        bad.rate = 0.0;
+        let err = coordinator.run(3, &bad).await.err().unwrap();
+        assert!(err.to_string().contains("failed"), "{err:#}");
+    }
+}
@@ -0,0 +1,422 @@
+// Numan Thabit 2025
+//! CSV and standalone HTML renderings of iteration reports, so results can
+//! be shared without post-processing scripts.
+
+use std::{fmt::Write as _, fs, path::Path};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+
+use crate::{
+    latency,
+    report::{create_parent_dir, IterationReport, MethodReport},
+};
+
+const NS_PER_MS: f64 = 1_000_000.0;
+/// Latency percentiles given a column in the CSV and the HTML tables.
+const TABLE_PERCENTILES: [f64; 5] = [50.0, 90.0, 99.0, 99.9, 99.99];
+/// Deepest tail the charts plot, in nines (5 is p99.999).
+const MAX_NINES: f64 = 5.0;
+const CHART_WIDTH: f64 = 760.0;
+const CHART_HEIGHT: f64 = 320.0;
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 15.0;
+const MARGIN_BOTTOM: f64 = 40.0;
+const Y_TICKS: usize = 5;
+const PALETTE: [&str; 8] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+
+const STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:right}\
+th:first-child,td:first-child{text-align:left}\
+th{background:#f4f4f4}\
+.legend span{display:inline-block;margin-right:1.5em}\
+.swatch{display:inline-block;width:12px;height:12px;margin-right:4px;vertical-align:middle}\
+svg text{font-size:11px;fill:#444}";
+
+/// The total and each method of a report, in that order.
+fn scopes(report: &IterationReport) -> impl Iterator<Item = (&str, &MethodReport)> {
+    std::iter::once(("total", &report.total)).chain(
+        report
+            .methods
+            .iter()
+            .map(|(label, method)| (label.as_str(), method)),
+    )
+}
+
+fn percentile_column(percentile: f64) -> String {
+    format!("p{}", percentile.to_string().replace('.', ""))
+}
+
+fn errors(method: &MethodReport) -> u64 {
+    method.rpc_errors + method.failures + method.timeouts
+}
+
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    create_parent_dir(path)?;
+    fs::write(path, contents).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// One row per iteration and scope (`total` or a method name), latencies
+/// in nanoseconds.
+pub fn render_csv(reports: &[IterationReport]) -> String {
+    let mut out = String::from(
+        "iteration,scope,transport,arrival,duration_ms,target_rate,achieved_rate,\
+         requests,ok,rpc_errors,failures,timeouts,dropped,min_ns,mean_ns",
+    );
+    for percentile in TABLE_PERCENTILES {
+        let _ = write!(out, ",{}_ns", percentile_column(percentile));
+    }
+    out.push_str(",max_ns\n");
+
+    for report in reports {
+        let transport = value_name(report.transport);
+        let arrival = value_name(report.arrival);
+        for (scope, method) in scopes(report) {
+            let _ = write!(
+                out,
+                "{},{},{transport},{arrival},{},{},{:.3},{},{},{},{},{},{}",
+                report.iteration,
+                csv_field(scope),
+                report.duration_ms,
+                report.target_rate,
+                report.achieved_rate,
+                method.requests,
+                method.ok,
+                method.rpc_errors,
+                method.failures,
+                method.timeouts,
+                method.dropped,
+            );
+            let latency = method.latency.as_ref();
+            let columns = std::iter::once(latency.map(|l| l.min_ns))
+                .chain(std::iter::once(latency.map(|l| l.mean_ns)))
+                .chain(
+                    TABLE_PERCENTILES
+                        .iter()
+                        .map(|&p| latency.and_then(|l| l.percentile(p))),
+                )
+                .chain(std::iter::once(latency.map(|l| l.max_ns)));
+            for value in columns {
+                out.push(',');
+                if let Some(value) = value {
+                    let _ = write!(out, "{value}");
+                }
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn write_csv(path: &Path, reports: &[IterationReport]) -> Result<()> {
+    write_file(path, &render_csv(reports))
+}
+
+/// A self-contained page: a summary table, then per iteration a latency
+/// by percentile chart (inline SVG) and a per-method table.
+pub fn render_html(reports: &[IterationReport]) -> Result<String> {
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>ultra-rpc-bench report</title>\n<style>{STYLE}</style>\n</head>\n<body>\n\
+         <h1>ultra-rpc-bench report</h1>\n"
+    );
+    if let Some(first) = reports.first() {
+        let _ = writeln!(
+            out,
+            "<p>{} iteration(s) over {}, {} arrivals at {} requests/s.</p>",
+            reports.len(),
+            value_name(first.transport),
+            value_name(first.arrival),
+            first.target_rate
+        );
+    }
+
+    out.push_str("<h2>Iterations</h2>\n<table>\n<tr><th>Iteration</th><th>Duration</th>");
+    out.push_str("<th>Achieved rate</th><th>Requests</th><th>Errors</th><th>Dropped</th>");
+    push_percentile_headers(&mut out);
+    out.push_str("</tr>\n");
+    for report in reports {
+        let _ = write!(
+            out,
+            "<tr><td>{}</td><td>{:.1} s</td><td>{:.1}/s</td><td>{}</td><td>{}</td><td>{}</td>",
+            report.iteration,
+            report.duration_ms as f64 / 1000.0,
+            report.achieved_rate,
+            report.total.requests,
+            errors(&report.total),
+            report.total.dropped
+        );
+        push_latency_cells(&mut out, &report.total);
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n");
+
+    for report in reports {
+        let _ = writeln!(out, "<h2>Iteration {}</h2>", report.iteration);
+        latency_chart(&mut out, report)?;
+        out.push_str("<table>\n<tr><th>Scope</th><th>Requests</th><th>OK</th>");
+        out.push_str("<th>RPC errors</th><th>Failures</th><th>Timeouts</th><th>Dropped</th>");
+        push_percentile_headers(&mut out);
+        out.push_str("</tr>\n");
+        for (scope, method) in scopes(report) {
+            let _ = write!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>",
+                escape_html(scope),
+                method.requests,
+                method.ok,
+                method.rpc_errors,
+                method.failures,
+                method.timeouts,
+                method.dropped
+            );
+            push_latency_cells(&mut out, method);
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    Ok(out)
+}
+
+pub fn write_html(path: &Path, reports: &[IterationReport]) -> Result<()> {
+    write_file(path, &render_html(reports)?)
+}
+
+fn push_percentile_headers(out: &mut String) {
+    for percentile in TABLE_PERCENTILES {
+        let _ = write!(out, "<th>{}</th>", percentile_column(percentile));
+    }
+    out.push_str("<th>max</th>");
+}
+
+fn push_latency_cells(out: &mut String, method: &MethodReport) {
+    let latency = method.latency.as_ref();
+    let cells = TABLE_PERCENTILES
+        .iter()
+        .map(|&p| latency.and_then(|l| l.percentile(p)))
+        .chain(std::iter::once(latency.map(|l| l.max_ns)));
+    for value in cells {
+        match value {
+            Some(ns) => {
+                let _ = write!(out, "<td>{:.3} ms</td>", ns as f64 / NS_PER_MS);
+            }
+            None => out.push_str("<td>-</td>"),
+        }
+    }
+}
+
+/// Latency (ms) against percentile on the usual HdrHistogram axis, where
+/// each step right adds a nine.
+fn latency_chart(out: &mut String, report: &IterationReport) -> Result<()> {
+    let mut series = Vec::new();
+    for (scope, method) in scopes(report) {
+        let Some(summary) = &method.latency else {
+            continue;
+        };
+        let histogram = latency::decode(&summary.histogram)?;
+        let points: Vec<(f64, f64)> = histogram
+            .iter_quantiles(5)
+            .map(|step| {
+                let quantile = step.quantile_iterated_to();
+                let nines = if quantile < 1.0 {
+                    (1.0 / (1.0 - quantile)).log10().min(MAX_NINES)
+                } else {
+                    MAX_NINES
+                };
+                (nines, step.value_iterated_to() as f64 / NS_PER_MS)
+            })
+            .collect();
+        series.push((scope, points));
+    }
+    if series.is_empty() {
+        out.push_str("<p>No answered requests.</p>\n");
+        return Ok(());
+    }
+
+    let points = series.iter().flat_map(|(_, points)| points);
+    let x_max = points.clone().map(|&(x, _)| x).fold(1.0, f64::max).ceil();
+    let y_max = points.map(|&(_, y)| y).fold(0.0, f64::max) * 1.05;
+    let y_max = if y_max > 0.0 { y_max } else { 1.0 };
+    let plot_width = CHART_WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = CHART_HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+    let to_x = |x: f64| MARGIN_LEFT + x / x_max * plot_width;
+    let to_y = |y: f64| MARGIN_TOP + plot_height - y / y_max * plot_height;
+
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{CHART_WIDTH}\" height=\"{CHART_HEIGHT}\" \
+         viewBox=\"0 0 {CHART_WIDTH} {CHART_HEIGHT}\" role=\"img\" \
+         aria-label=\"Latency by percentile, iteration {}\">",
+        report.iteration
+    );
+    for tick in 0..=Y_TICKS {
+        let value = y_max * tick as f64 / Y_TICKS as f64;
+        let y = to_y(value);
+        let _ = writeln!(
+            out,
+            "<line x1=\"{MARGIN_LEFT}\" y1=\"{y:.1}\" x2=\"{:.1}\" y2=\"{y:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{value:.2} ms</text>",
+            MARGIN_LEFT + plot_width,
+            MARGIN_LEFT - 6.0,
+            y + 4.0
+        );
+    }
+    for nines in 0..=x_max as i32 {
+        let x = to_x(f64::from(nines));
+        let percentile = 100.0 - 100.0 / 10f64.powi(nines);
+        let _ = writeln!(
+            out,
+            "<line x1=\"{x:.1}\" y1=\"{MARGIN_TOP}\" x2=\"{x:.1}\" y2=\"{:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{x:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{:.*}%</text>",
+            MARGIN_TOP + plot_height,
+            MARGIN_TOP + plot_height + 16.0,
+            (nines as usize).saturating_sub(2),
+            percentile
+        );
+    }
+    let _ = writeln!(
+        out,
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">percentile</text>",
+        MARGIN_LEFT + plot_width / 2.0,
+        CHART_HEIGHT - 4.0
+    );
+    for (index, (_, points)) in series.iter().enumerate() {
+        let path = points
+            .iter()
+            .map(|&(x, y)| format!("{:.1},{:.1}", to_x(x), to_y(y)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(
+            out,
+            "<polyline fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\" points=\"{path}\"/>",
+            PALETTE[index % PALETTE.len()]
+        );
+    }
+    out.push_str("</svg>\n<div class=\"legend\">");
+    for (index, (scope, _)) in series.iter().enumerate() {
+        let _ = write!(
+            out,
+            "<span><i class=\"swatch\" style=\"background:{}\"></i>{}</span>",
+            PALETTE[index % PALETTE.len()],
+            escape_html(scope)
+        );
+    }
+    out.push_str("</div>\n");
+    Ok(())
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use super::*;
+    use crate::{
+        client::TransportKind,
+        load::{Arrival, LoadPlan, MethodStats},
+    };
+
+    fn report() -> IterationReport {
+        let mut slot = MethodStats {
+            requests: 100,
+            ok: 100,
+            ..MethodStats::default()
+        };
+        for latency_us in 1..=100u64 {
+            slot.latency.record(latency_us * 1_000).unwrap();
+        }
+        let failing = MethodStats {
+            requests: 2,
+            failures: 2,
+            ..MethodStats::default()
+        };
+        let mut total = slot.clone();
+        total.merge(&failing);
+        let methods = BTreeMap::from([
+            ("getSlot".to_string(), slot),
+            ("<odd>,\"name\"".to_string(), failing),
+        ]);
+        let plan = LoadPlan {
+            rate: 100.0,
+            duration: Duration::from_secs(1),
+            arrival: Arrival::Poisson,
+            max_in_flight: 16,
+            request_timeout: Duration::from_secs(1),
+        };
+        IterationReport::new(
+            1,
+            TransportKind::Quic,
+            &plan,
+            &total,
+            &methods,
+            Duration::from_secs(1),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn renders_csv_and_html() {
+        let reports = vec![report()];
+
+        let csv = render_csv(&reports);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4, "{csv}");
+        assert!(lines[0].ends_with("p50_ns,p90_ns,p99_ns,p999_ns,p9999_ns,max_ns"));
+        let columns = lines[0].split(',').count();
+        let total: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(total.len(), columns);
+        assert_eq!(&total[..4], ["1", "total", "quic", "poisson"]);
+        assert_eq!(total[7], "102");
+        // Quoted, so the commas inside do not add columns
+        assert!(
+            lines[2].starts_with("1,\"<odd>,\"\"name\"\"\",quic,"),
+            "{csv}"
+        );
+        assert!(lines[2].ends_with(",,,,,,,,"), "{csv}");
+
+        let html = render_html(&reports).unwrap();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("&lt;odd&gt;,&quot;name&quot;"));
+        assert!(!html.contains("<odd>"));
+        // Only scopes with answered requests are charted
+        assert_eq!(html.matches("<polyline").count(), 2);
+        assert!(html.contains("99.9%"));
+    }
+}
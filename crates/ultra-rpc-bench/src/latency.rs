@@ -0,0 +1,208 @@
+// Numan Thabit 2025
+use std::{fmt::Write as _, fs, io::Cursor, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hdrhistogram::{
+    serialization::{Deserializer, Serializer, V2DeflateSerializer},
+    Histogram,
+};
+use serde::{Deserialize, Serialize};
+
+/// Highest latency a histogram tracks, one hour in nanoseconds. Slower
+/// requests are clamped to it.
+const HIGHEST_TRACKABLE_NS: u64 = 3_600_000_000_000;
+/// Significant decimal digits kept for every recorded latency.
+const SIGNIFICANT_DIGITS: u8 = 3;
+/// Percentiles every latency summary reports.
+pub const REPORTED_PERCENTILES: [f64; 8] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 99.99, 100.0];
+
+/// An empty histogram of request latencies in nanoseconds. All of them
+/// share bounds, so they always merge.
+pub fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, HIGHEST_TRACKABLE_NS, SIGNIFICANT_DIGITS)
+        .expect("static histogram bounds are valid")
+}
+
+pub fn record(histogram: &mut Histogram<u64>, latency_ns: u64) {
+    histogram.saturating_record(latency_ns.max(1));
+}
+
+/// Base64 of the V2 deflate encoding, the format HdrHistogram tooling in
+/// other languages reads.
+pub fn encode(histogram: &Histogram<u64>) -> Result<String> {
+    let mut bytes = Vec::new();
+    V2DeflateSerializer::new()
+        .serialize(histogram, &mut bytes)
+        .map_err(|err| anyhow!("failed to encode histogram: {err:?}"))?;
+    Ok(STANDARD.encode(bytes))
+}
+
+/// Reads back a histogram written by [`encode`].
+pub fn decode(encoded: &str) -> Result<Histogram<u64>> {
+    let bytes = STANDARD
+        .decode(encoded)
+        .context("histogram is not valid base64")?;
+    let decoded: Histogram<u64> = Deserializer::new()
+        .deserialize(&mut Cursor::new(bytes))
+        .map_err(|err| anyhow!("failed to decode histogram: {err:?}"))?;
+    // Re-home into the shared bounds so decoded histograms merge
+    let mut histogram = new_histogram();
+    histogram
+        .add(&decoded)
+        .context("histogram exceeds the tracked latency range")?;
+    Ok(histogram)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub latency_ns: u64,
+}
+
+/// Latency distribution of answered requests, in nanoseconds.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub min_ns: u64,
+    pub mean_ns: u64,
+    pub stdev_ns: u64,
+    pub max_ns: u64,
+    pub percentiles: Vec<Percentile>,
+    /// The full histogram, see [`encode`].
+    pub histogram: String,
+}
+
+impl LatencySummary {
+    pub fn from_histogram(histogram: &Histogram<u64>) -> Result<Option<Self>> {
+        if histogram.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            count: histogram.len(),
+            min_ns: histogram.min(),
+            mean_ns: histogram.mean().round() as u64,
+            stdev_ns: histogram.stdev().round() as u64,
+            max_ns: histogram.max(),
+            percentiles: REPORTED_PERCENTILES
+                .iter()
+                .map(|&percentile| Percentile {
+                    percentile,
+                    latency_ns: histogram.value_at_percentile(percentile),
+                })
+                .collect(),
+            histogram: encode(histogram)?,
+        }))
+    }
+
+    pub fn percentile(&self, target: f64) -> Option<u64> {
+        self.percentiles
+            .iter()
+            .find(|entry| (entry.percentile - target).abs() < 0.0001)
+            .map(|entry| entry.latency_ns)
+    }
+}
+
+/// Writes the percentile distribution in the `.hgrm` text layout the
+/// HdrHistogram plotter reads, with values in milliseconds.
+pub fn write_distribution(path: &Path, histogram: &Histogram<u64>) -> Result<()> {
+    const NS_PER_MS: f64 = 1_000_000.0;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{:>12} {:>14} {:>10} {:>14}\n",
+        "Value", "Percentile", "TotalCount", "1/(1-Percentile)"
+    );
+    let mut total = 0u64;
+    for step in histogram.iter_quantiles(5) {
+        total += step.count_since_last_iteration();
+        let quantile = step.quantile_iterated_to();
+        let inverse = if quantile < 1.0 {
+            format!("{:14.2}", 1.0 / (1.0 - quantile))
+        } else {
+            format!("{:>14}", "")
+        };
+        let _ = writeln!(
+            out,
+            "{:12.3} {:2.12} {:10} {}",
+            step.value_iterated_to() as f64 / NS_PER_MS,
+            quantile,
+            total,
+            inverse.trim_end()
+        );
+    }
+    let _ = writeln!(
+        out,
+        "#[Mean    = {:12.3}, StdDeviation   = {:12.3}]",
+        histogram.mean() / NS_PER_MS,
+        histogram.stdev() / NS_PER_MS
+    );
+    let _ = writeln!(
+        out,
+        "#[Max     = {:12.3}, Total count    = {:12}]",
+        histogram.max() as f64 / NS_PER_MS,
+        histogram.len()
+    );
+    let _ = writeln!(
+        out,
+        "#[Buckets = {:12}, SubBuckets     = {:12}]",
+        histogram.buckets(),
+        histogram.distinct_values()
+    );
+
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir).with_context(|| {
+                format!("failed to create histogram directory {}", dir.display())
+            })?;
+        }
+    }
+    fs::write(path, out).with_context(|| format!("failed to write histogram to {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarizes_and_round_trips_histograms() {
+        let mut histogram = new_histogram();
+        for latency_us in 1..=1_000u64 {
+            record(&mut histogram, latency_us * 1_000);
+        }
+        let summary = LatencySummary::from_histogram(&histogram)
+            .unwrap()
+            .expect("samples recorded");
+        assert_eq!(summary.count, 1_000);
+        assert_eq!(summary.percentiles.len(), REPORTED_PERCENTILES.len());
+        let p99 = summary.percentile(99.0).unwrap();
+        assert!((989_000..=991_000).contains(&p99), "p99 {p99}");
+        assert!(summary.percentile(99.99).unwrap() >= p99);
+        assert_eq!(summary.percentile(42.0), None);
+
+        assert_eq!(decode(&summary.histogram).unwrap(), histogram);
+        let path =
+            std::env::temp_dir().join(format!("ultra-rpc-bench-{}.hgrm", std::process::id()));
+        write_distribution(&path, &histogram).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(text
+            .lines()
+            .any(|line| line.trim_start().starts_with("0.500 ")));
+        assert!(text.contains("Total count    =         1000"));
+
+        assert!(LatencySummary::from_histogram(&new_histogram())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn clamps_out_of_range_latencies() {
+        let mut histogram = new_histogram();
+        record(&mut histogram, 0);
+        record(&mut histogram, u64::MAX);
+        assert_eq!(histogram.len(), 2);
+        assert_eq!(histogram.min(), 1);
+        assert!(histogram.max() >= HIGHEST_TRACKABLE_NS);
+    }
+}
@@ -0,0 +1,375 @@
+// Numan Thabit 2025
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use hdrhistogram::Histogram;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    time::{interval_at, sleep_until, timeout, Instant},
+};
+use tracing::debug;
+
+use crate::{
+    client::{is_success, RpcClient},
+    latency,
+    workload::Workload,
+};
+
+/// How request send times are spaced at the target rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Arrival {
+    /// Evenly spaced requests.
+    Constant,
+    /// Exponentially distributed gaps, as from many independent clients.
+    Poisson,
+}
+
+/// An open-loop run: requests are sent on schedule whether or not earlier
+/// ones have been answered.
+#[derive(Debug, Clone)]
+pub struct LoadPlan {
+    pub rate: f64,
+    pub duration: Duration,
+    pub arrival: Arrival,
+    pub max_in_flight: usize,
+    pub request_timeout: Duration,
+}
+
+impl LoadPlan {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.rate.is_finite() && self.rate > 0.0) {
+            bail!("request rate must be positive, got {}", self.rate);
+        }
+        if self.max_in_flight == 0 {
+            bail!("max in-flight requests must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// Gap before the next scheduled request.
+    fn gap<R: Rng>(&self, rng: &mut R) -> Duration {
+        let mean = 1.0 / self.rate;
+        match self.arrival {
+            Arrival::Constant => Duration::from_secs_f64(mean),
+            Arrival::Poisson => {
+                let uniform: f64 = rng.gen();
+                Duration::from_secs_f64(-(1.0 - uniform).ln() * mean)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// The server answered with a JSON-RPC error.
+    RpcError,
+    /// The transport failed before a response arrived.
+    Failed,
+    TimedOut,
+}
+
+struct Sample {
+    index: usize,
+    outcome: Outcome,
+    latency: Duration,
+}
+
+enum Event {
+    Completed(Sample),
+    /// An arrival of this entry skipped at the in-flight limit.
+    Dropped(usize),
+}
+
+/// Request counts and latencies for one workload entry.
+#[derive(Debug, Clone)]
+pub struct MethodStats {
+    pub requests: u64,
+    pub ok: u64,
+    pub rpc_errors: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    /// Arrivals skipped because `max_in_flight` requests were outstanding.
+    pub dropped: u64,
+    /// Latency of every answered request, in nanoseconds.
+    pub latency: Histogram<u64>,
+}
+
+impl Default for MethodStats {
+    fn default() -> Self {
+        Self {
+            requests: 0,
+            ok: 0,
+            rpc_errors: 0,
+            failures: 0,
+            timeouts: 0,
+            dropped: 0,
+            latency: latency::new_histogram(),
+        }
+    }
+}
+
+impl MethodStats {
+    fn record(&mut self, outcome: Outcome, latency: Duration) {
+        self.requests += 1;
+        match outcome {
+            Outcome::Ok => self.ok += 1,
+            Outcome::RpcError => self.rpc_errors += 1,
+            Outcome::Failed => self.failures += 1,
+            Outcome::TimedOut => self.timeouts += 1,
+        }
+        if matches!(outcome, Outcome::Ok | Outcome::RpcError) {
+            latency::record(
+                &mut self.latency,
+                latency.as_nanos().min(u128::from(u64::MAX)) as u64,
+            );
+        }
+    }
+
+    pub fn merge(&mut self, other: &MethodStats) {
+        self.requests += other.requests;
+        self.ok += other.ok;
+        self.rpc_errors += other.rpc_errors;
+        self.failures += other.failures;
+        self.timeouts += other.timeouts;
+        self.dropped += other.dropped;
+        self.latency
+            .add(&other.latency)
+            .expect("latency histograms share bounds");
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunStats {
+    /// Time from the first scheduled send until the last response.
+    pub elapsed: Duration,
+    pub methods: Vec<MethodStats>,
+}
+
+impl RunStats {
+    pub fn total(&self) -> MethodStats {
+        merge_all(&self.methods)
+    }
+}
+
+/// Folds several stats into one, e.g. every method of a run.
+pub fn merge_all<'a>(stats: impl IntoIterator<Item = &'a MethodStats>) -> MethodStats {
+    let mut total = MethodStats::default();
+    for method in stats {
+        total.merge(method);
+    }
+    total
+}
+
+/// Drives `workload` against `client` for the plan's duration. Latency is
+/// measured from each request's scheduled send time, so a server that
+/// falls behind is charged for the queueing it causes.
+pub async fn run(client: &RpcClient, workload: &Workload, plan: &LoadPlan) -> Result<RunStats> {
+    run_inner(client, workload, plan, None).await
+}
+
+/// Like [`run`], also sending the stats of every completed `every` window
+/// to `checkpoints` while the run goes on. Requests are counted in the
+/// window they complete in; a trailing partial window is not sent.
+pub async fn run_with_checkpoints(
+    client: &RpcClient,
+    workload: &Workload,
+    plan: &LoadPlan,
+    every: Duration,
+    checkpoints: mpsc::UnboundedSender<RunStats>,
+) -> Result<RunStats> {
+    if every.is_zero() {
+        bail!("checkpoint interval must be positive");
+    }
+    run_inner(client, workload, plan, Some((every, checkpoints))).await
+}
+
+async fn run_inner(
+    client: &RpcClient,
+    workload: &Workload,
+    plan: &LoadPlan,
+    checkpoints: Option<(Duration, mpsc::UnboundedSender<RunStats>)>,
+) -> Result<RunStats> {
+    plan.validate()?;
+    let mut rng = workload
+        .seed()
+        .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64);
+    let in_flight = Arc::new(Semaphore::new(plan.max_in_flight));
+    let methods = workload.labels().len();
+    let (tx, rx) = mpsc::unbounded_channel::<Event>();
+
+    let start = Instant::now();
+    let collector = tokio::spawn(collect(rx, methods, start, checkpoints));
+    let end = start + plan.duration;
+    let mut scheduled = start;
+    let mut id = 0u64;
+
+    while scheduled < end {
+        sleep_until(scheduled).await;
+        let (index, body) = workload.next_request(&mut rng, id);
+        id += 1;
+
+        match in_flight.clone().try_acquire_owned() {
+            Ok(permit) => {
+                let client = client.clone();
+                let tx = tx.clone();
+                let request_timeout = plan.request_timeout;
+                let sent_at = scheduled;
+                tokio::spawn(async move {
+                    let outcome = match timeout(request_timeout, client.call(body)).await {
+                        Ok(Ok(response)) if is_success(&response) => Outcome::Ok,
+                        Ok(Ok(_)) => Outcome::RpcError,
+                        Ok(Err(err)) => {
+                            debug!(%err, "request failed");
+                            Outcome::Failed
+                        }
+                        Err(_) => Outcome::TimedOut,
+                    };
+                    let latency = sent_at.elapsed();
+                    drop(permit);
+                    let _ = tx.send(Event::Completed(Sample {
+                        index,
+                        outcome,
+                        latency,
+                    }));
+                });
+            }
+            Err(_) => {
+                let _ = tx.send(Event::Dropped(index));
+            }
+        }
+
+        scheduled += plan.gap(&mut rng);
+    }
+    drop(tx);
+
+    let methods = collector.await.context("load result collector panicked")?;
+    Ok(RunStats {
+        elapsed: start.elapsed(),
+        methods,
+    })
+}
+
+/// Tallies events until every sender is gone, cutting a checkpoint window
+/// on each tick when asked to.
+async fn collect(
+    mut rx: mpsc::UnboundedReceiver<Event>,
+    methods: usize,
+    start: Instant,
+    checkpoints: Option<(Duration, mpsc::UnboundedSender<RunStats>)>,
+) -> Vec<MethodStats> {
+    let mut stats = vec![MethodStats::default(); methods];
+    let Some((every, tx)) = checkpoints else {
+        while let Some(event) = rx.recv().await {
+            tally(&mut stats, &event);
+        }
+        return stats;
+    };
+
+    let mut ticks = interval_at(start + every, every);
+    let mut window = vec![MethodStats::default(); methods];
+    let mut window_start = start;
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                tally(&mut stats, &event);
+                tally(&mut window, &event);
+            }
+            _ = ticks.tick() => {
+                let now = Instant::now();
+                let _ = tx.send(RunStats {
+                    elapsed: now.duration_since(window_start),
+                    methods: std::mem::replace(&mut window, vec![MethodStats::default(); methods]),
+                });
+                window_start = now;
+            }
+        }
+    }
+    stats
+}
+
+fn tally(stats: &mut [MethodStats], event: &Event) {
+    match event {
+        Event::Completed(sample) => stats[sample.index].record(sample.outcome, sample.latency),
+        Event::Dropped(index) => stats[*index].dropped += 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{http_options, http_stub};
+
+    fn plan(arrival: Arrival) -> LoadPlan {
+        LoadPlan {
+            rate: 1_000.0,
+            duration: Duration::from_secs(1),
+            arrival,
+            max_in_flight: 16,
+            request_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn arrival_gaps_average_to_the_rate() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let constant = plan(Arrival::Constant);
+        assert_eq!(constant.gap(&mut rng), Duration::from_millis(1));
+
+        let poisson = plan(Arrival::Poisson);
+        let total: Duration = (0..20_000).map(|_| poisson.gap(&mut rng)).sum();
+        let mean_ms = total.as_secs_f64() * 1_000.0 / 20_000.0;
+        assert!((mean_ms - 1.0).abs() < 0.05, "mean gap {mean_ms}ms");
+
+        let mut bad = constant.clone();
+        bad.rate = 0.0;
+        assert!(bad.validate().is_err());
+    }
+
+    #[test]
+    fn records_outcomes() {
+        let mut stats = MethodStats::default();
+        stats.record(Outcome::Ok, Duration::from_micros(5));
+        stats.record(Outcome::RpcError, Duration::from_micros(7));
+        stats.record(Outcome::TimedOut, Duration::from_secs(1));
+        assert_eq!((stats.requests, stats.ok, stats.rpc_errors), (3, 1, 1));
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.latency.len(), 2);
+        assert!(stats.latency.equivalent(stats.latency.max(), 7_000));
+    }
+
+    #[tokio::test]
+    async fn drives_an_http_endpoint() {
+        let addr = http_stub(|_| r#"{"jsonrpc":"2.0","id":1,"result":7}"#).await;
+        let client = RpcClient::connect(&http_options(addr)).await.unwrap();
+        let workload = Workload::new(vec!["getSlot".parse().unwrap()]).unwrap();
+        let mut plan = plan(Arrival::Constant);
+        plan.rate = 200.0;
+        plan.duration = Duration::from_millis(100);
+
+        let stats = run(&client, &workload, &plan).await.unwrap();
+        let total = stats.total();
+        assert_eq!(total.requests, 20);
+        assert_eq!(total.ok + total.dropped, 20, "{total:?}");
+        assert_eq!(total.latency.len(), total.ok);
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let stats = run_with_checkpoints(&client, &workload, &plan, Duration::from_millis(30), tx)
+            .await
+            .unwrap();
+        let mut windows = Vec::new();
+        while let Some(window) = rx.recv().await {
+            windows.push(window.total());
+        }
+        assert!(windows.len() >= 3, "{} windows", windows.len());
+        let windowed: u64 = windows.iter().map(|w| w.requests + w.dropped).sum();
+        assert!(windowed <= stats.total().requests + stats.total().dropped);
+    }
+}
@@ -1,23 +1,53 @@
 // Numan Thabit 2017
+mod client;
+mod compare;
+mod distributed;
+mod export;
+mod latency;
+mod load;
+mod profile;
+mod ready;
+mod replay;
+mod report;
+mod server_metrics;
+mod soak;
+#[cfg(test)]
+mod test_support;
+mod workload;
+
 use std::{
     fs::{self, OpenOptions},
-    io::BufWriter,
     path::{Path, PathBuf},
     process::Stdio,
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use anyhow::{anyhow, Context, Result};
 use clap::Parser;
 use humantime::format_duration;
-use serde::Serialize;
 use tokio::{
-    io::{AsyncReadExt, BufReader},
+    net::TcpListener,
     process::Command,
     time::{sleep, timeout},
 };
 use tracing::{info, warn};
 
+use crate::{
+    client::{ClientOptions, RpcClient, TransportKind},
+    compare::Thresholds,
+    distributed::Coordinator,
+    load::{merge_all, Arrival, LoadPlan},
+    profile::{PerfOptions, ProfileOptions, Profiler},
+    ready::{wait_until_ready, ReadinessProbe},
+    replay::{ReplayOptions, Replayer},
+    report::{
+        group_by_method, read_reports, write_histograms, write_json, write_reports, IterationReport,
+    },
+    server_metrics::{ScrapeOptions, Scraper},
+    soak::SoakOptions,
+    workload::{RpcCall, Workload, WorkloadProfile},
+};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Benchmark harness for solana-ultra-rpc")]
 struct BenchArgs {
@@ -41,53 +71,239 @@ struct BenchArgs {
     #[arg(long)]
     server_log: Option<PathBuf>,
 
-    /// How long to wait for the server to warm up before issuing load.
-    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
-    warmup: Duration,
+    /// Longest to wait for the server to pass its readiness probe before
+    /// giving up.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "60s")]
+    ready_timeout: Duration,
+
+    /// Consecutive successful probes required before load starts.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    ready_successes: u32,
+
+    /// Interval between readiness probes.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "250ms")]
+    ready_interval: Duration,
+
+    /// JSON-RPC call used as the readiness probe, as `METHOD` or
+    /// `METHOD=PARAMS`.
+    #[arg(long, default_value = "getSlot")]
+    ready_method: RpcCall,
 
     /// Maximum time to wait for graceful shutdown before forcing a kill.
     #[arg(long, value_parser = humantime::parse_duration, default_value = "3s")]
     shutdown_grace: Duration,
 
-    /// Optional path to the wrk (or wrk-quic) binary used to generate load.
+    /// Transport used to reach the RPC endpoint.
+    #[arg(long, value_enum, default_value_t = TransportKind::Quic)]
+    transport: TransportKind,
+
+    /// RPC endpoint to benchmark: host:port for QUIC, a URL or host:port for HTTP.
+    #[arg(long, default_value = "127.0.0.1:8899")]
+    rpc_endpoint: String,
+
+    /// PEM bundle trusted for the server's QUIC certificate. When the harness
+    /// launches the server itself, its self-signed certificate is trusted
+    /// without this.
     #[arg(long)]
-    wrk_bin: Option<PathBuf>,
+    quic_ca_cert: Option<PathBuf>,
 
-    /// Optional wrk Lua script to drive request patterns.
+    /// TLS server name for QUIC connections; defaults to the endpoint host.
     #[arg(long)]
-    wrk_script: Option<PathBuf>,
+    quic_server_name: Option<String>,
+
+    /// Number of connections requests are spread across.
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..))]
+    connections: u64,
+
+    /// JSON-RPC call to issue, as METHOD or METHOD=PARAMS_JSON. Repeat to mix
+    /// several calls uniformly. Ignored when a workload profile is given.
+    #[arg(
+        long = "rpc-method",
+        value_name = "METHOD[=PARAMS]",
+        default_value = "getSlot",
+        action = clap::ArgAction::Append
+    )]
+    rpc_methods: Vec<RpcCall>,
+
+    /// TOML workload profile describing a weighted request mix and the key
+    /// sets its requests draw from.
+    #[arg(long)]
+    workload: Option<PathBuf>,
 
-    /// Number of wrk threads to launch.
-    #[arg(long, default_value_t = 12)]
-    wrk_threads: u32,
+    /// Target request rate in requests per second.
+    #[arg(long, default_value_t = 10_000.0)]
+    rate: f64,
 
-    /// Number of wrk connections to maintain.
-    #[arg(long, default_value_t = 600)]
-    wrk_connections: u32,
+    /// How request send times are spaced at the target rate.
+    #[arg(long, value_enum, default_value_t = Arrival::Constant)]
+    arrival: Arrival,
 
-    /// Duration for the wrk run.
+    /// Duration of each load iteration.
     #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
-    wrk_duration: Duration,
+    duration: Duration,
 
-    /// Number of wrk iterations to execute sequentially.
+    /// Number of load iterations to execute sequentially.
     #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
     iterations: u32,
 
-    /// Cooldown duration between wrk iterations.
+    /// Run a single soak of this length instead of `--duration` iterations,
+    /// logging rolling checkpoints and flagging drift over time.
+    #[arg(long, value_parser = humantime::parse_duration, conflicts_with = "iterations")]
+    soak: Option<Duration>,
+
+    /// Window of each soak checkpoint.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1m")]
+    checkpoint_interval: Duration,
+
+    /// Leading soak checkpoints that form the baseline drift is measured
+    /// against.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u64).range(1..))]
+    soak_baseline_checkpoints: u64,
+
+    /// Largest tolerated soak p99 latency rise over the baseline, in percent.
+    #[arg(long, default_value_t = 20.0)]
+    soak_max_latency_creep: f64,
+
+    /// Largest tolerated server RSS growth over the soak baseline, in percent.
+    #[arg(long, default_value_t = 20.0)]
+    soak_max_rss_growth: f64,
+
+    /// Largest tolerated soak throughput drop below the baseline, in percent.
+    #[arg(long, default_value_t = 5.0)]
+    soak_max_throughput_drop: f64,
+
+    /// Cooldown duration between iterations.
     #[arg(long, value_parser = humantime::parse_duration, default_value = "0s")]
-    wrk_cooldown: Duration,
+    cooldown: Duration,
+
+    /// Maximum requests outstanding at once. Arrivals beyond it are dropped
+    /// and counted rather than delayed, keeping the load open-loop.
+    #[arg(long, default_value_t = 4096)]
+    max_in_flight: usize,
 
-    /// Maximum time allowed for a single wrk execution before it is terminated.
-    #[arg(long, value_parser = humantime::parse_duration, default_value = "90s")]
-    wrk_timeout: Duration,
+    /// Time after which an outstanding request counts as timed out.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "5s")]
+    request_timeout: Duration,
 
-    /// Optional path to persist structured wrk output as JSON.
+    /// Optional path to persist per-iteration results as JSON.
     #[arg(long)]
-    wrk_output_json: Option<PathBuf>,
+    output_json: Option<PathBuf>,
 
-    /// RPC endpoint to benchmark (passed as the wrk target URI).
-    #[arg(long, default_value = "127.0.0.1:8899")]
-    rpc_endpoint: String,
+    /// Optional path for per-iteration, per-method metrics as CSV.
+    #[arg(long)]
+    output_csv: Option<PathBuf>,
+
+    /// Optional path for a standalone HTML report with latency
+    /// distribution charts.
+    #[arg(long)]
+    output_html: Option<PathBuf>,
+
+    /// Optional directory for per-iteration, per-method `.hgrm` latency
+    /// distributions.
+    #[arg(long)]
+    histogram_dir: Option<PathBuf>,
+
+    /// Server Prometheus endpoint (`host:port` or URL) scraped during each
+    /// iteration; cache hit ratios, queue depths and CPU/memory samples are
+    /// embedded in the report.
+    #[arg(long)]
+    server_metrics: Option<String>,
+
+    /// Interval between server metrics scrapes.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    metrics_interval: Duration,
+
+    /// Extra metric name recorded verbatim in every server sample. Repeat to
+    /// record several.
+    #[arg(long = "metrics-series", value_name = "NAME", action = clap::ArgAction::Append)]
+    metrics_series: Vec<String>,
+
+    /// Interval between `/proc` samples (CPU, RSS, fds, context switches) of
+    /// a server the harness launched.
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "1s")]
+    profile_interval: Duration,
+
+    /// Attach `perf record` to the launched server for each iteration and
+    /// keep its data files in this directory.
+    #[arg(long, conflicts_with = "skip_server")]
+    perf_dir: Option<PathBuf>,
+
+    /// Sampling frequency of the perf recording, in Hz.
+    #[arg(long, default_value_t = 99, value_parser = clap::value_parser!(u32).range(1..))]
+    perf_frequency: u32,
+
+    /// Render a flamegraph SVG from each perf recording; needs `perf script`
+    /// and inferno (`cargo install inferno`).
+    #[arg(long, action = clap::ArgAction::SetTrue, requires = "perf_dir")]
+    flamegraph: bool,
+
+    /// faststreams capture replayed into the ingest socket during each
+    /// iteration, so reads are measured under concurrent write pressure.
+    #[arg(long)]
+    replay_capture: Option<PathBuf>,
+
+    /// Ingest Unix socket the capture is written to, e.g. the bridge input.
+    #[arg(long, default_value = "/tmp/ultra-geyser.sock")]
+    replay_socket: PathBuf,
+
+    /// Multiplier over the recorded pace; 0 replays as fast as the socket
+    /// accepts.
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f64,
+
+    /// Baseline JSON report to compare the results with. The harness exits
+    /// non-zero when a gated metric regresses past its threshold.
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+
+    /// Compare this existing JSON report with the baseline instead of
+    /// running load.
+    #[arg(long, requires = "baseline")]
+    candidate: Option<PathBuf>,
+
+    /// Largest tolerated throughput drop against the baseline, in percent.
+    #[arg(long, default_value_t = 5.0)]
+    max_throughput_drop: f64,
+
+    /// Largest tolerated rise of a gated latency percentile against the
+    /// baseline, in percent.
+    #[arg(long, default_value_t = 10.0)]
+    max_latency_increase: f64,
+
+    /// Largest tolerated rise of the error rate against the baseline, in
+    /// percentage points.
+    #[arg(long, default_value_t = 0.1)]
+    max_error_rate_increase: f64,
+
+    /// Latency percentile gated against the baseline. Repeat to gate several.
+    #[arg(
+        long = "gate-percentile",
+        value_name = "PERCENTILE",
+        default_values_t = [99.0, 99.9],
+        action = clap::ArgAction::Append
+    )]
+    gate_percentiles: Vec<f64>,
+
+    /// Optional path to persist the baseline comparison as JSON.
+    #[arg(long)]
+    comparison_json: Option<PathBuf>,
+
+    /// Run as a load worker: listen on this address for a coordinator and
+    /// run its share of each iteration against `--rpc-endpoint` with the
+    /// local workload. No server is launched and no reports are written.
+    #[arg(long, value_name = "ADDR")]
+    worker_listen: Option<String>,
+
+    /// Remote worker (`host:port`) to split the load with. Repeat to add
+    /// several; this process then only coordinates and merges their
+    /// histograms into one report.
+    #[arg(
+        long = "worker",
+        value_name = "ADDR",
+        action = clap::ArgAction::Append,
+        conflicts_with_all = ["soak", "worker_listen"]
+    )]
+    workers: Vec<String>,
 
     /// Skip launching the server; assumes an endpoint is already available.
     #[arg(long, action = clap::ArgAction::SetTrue)]
@@ -98,63 +314,94 @@ struct BenchArgs {
     dry_run: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct WrkReport {
-    iteration: u32,
-    duration_ms: u64,
-    stdout: String,
-    #[serde(skip_serializing_if = "String::is_empty")]
-    stderr: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    metrics: Option<WrkMetrics>,
-}
+impl BenchArgs {
+    fn workload(&self) -> Result<Workload> {
+        match &self.workload {
+            Some(path) => {
+                let profile = WorkloadProfile::load(path)?;
+                let base = path.parent().unwrap_or(Path::new("."));
+                Workload::from_profile(&profile, base)
+            }
+            None => Workload::new(self.rpc_methods.clone()),
+        }
+    }
 
-#[derive(Debug, Clone, Serialize, Default)]
-struct WrkMetrics {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    requests_per_sec: Option<f64>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    transfer_per_sec: Option<TransferRate>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    latency: Option<WrkLatencyStats>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
-    latency_distribution: Vec<WrkPercentile>,
-}
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            max_throughput_drop: self.max_throughput_drop,
+            max_latency_increase: self.max_latency_increase,
+            max_error_rate_increase: self.max_error_rate_increase,
+            percentiles: self.gate_percentiles.clone(),
+        }
+    }
 
-#[derive(Debug, Clone, Serialize)]
-struct WrkLatencyStats {
-    avg_ns: u64,
-    stdev_ns: u64,
-    max_ns: u64,
-    stdev_pct: f64,
-}
+    fn scrape_options(&self) -> Option<ScrapeOptions> {
+        self.server_metrics.as_ref().map(|endpoint| ScrapeOptions {
+            endpoint: endpoint.clone(),
+            interval: self.metrics_interval,
+            series: self.metrics_series.clone(),
+        })
+    }
 
-#[derive(Debug, Clone, Serialize)]
-struct WrkPercentile {
-    percentile: f64,
-    latency_ns: u64,
-}
+    fn replay_options(&self) -> Option<ReplayOptions> {
+        self.replay_capture.as_ref().map(|capture| ReplayOptions {
+            capture: capture.clone(),
+            socket: self.replay_socket.clone(),
+            speed: self.replay_speed,
+        })
+    }
 
-#[derive(Debug, Clone, Serialize)]
-struct TransferRate {
-    value: f64,
-    unit: String,
-}
+    fn readiness_probe(&self) -> ReadinessProbe {
+        ReadinessProbe {
+            call: self.ready_method.clone(),
+            successes: self.ready_successes,
+            interval: self.ready_interval,
+            timeout: self.ready_timeout,
+        }
+    }
 
-impl WrkMetrics {
-    fn is_empty(&self) -> bool {
-        self.requests_per_sec.is_none()
-            && self.transfer_per_sec.is_none()
-            && self.latency.is_none()
-            && self.latency_distribution.is_empty()
+    fn client_options(&self, ca_cert: Option<PathBuf>) -> ClientOptions {
+        ClientOptions {
+            transport: self.transport,
+            endpoint: self.rpc_endpoint.clone(),
+            connections: self.connections as usize,
+            ca_cert,
+            server_name: self.quic_server_name.clone(),
+        }
+    }
+
+    fn soak_options(&self, server_pid: Option<u32>) -> Option<SoakOptions> {
+        self.soak.map(|_| SoakOptions {
+            checkpoint: self.checkpoint_interval,
+            baseline_checkpoints: self.soak_baseline_checkpoints as usize,
+            max_latency_creep: self.soak_max_latency_creep,
+            max_rss_growth: self.soak_max_rss_growth,
+            max_throughput_drop: self.soak_max_throughput_drop,
+            server_pid,
+        })
     }
 
-    fn find_percentile(&self, target: f64) -> Option<&WrkPercentile> {
-        self.latency_distribution.iter().find(|entry| {
-            (entry.percentile - target).abs() < f64::EPSILON
-                || (entry.percentile - target).abs() < 0.0001
+    fn profile_options(&self, server_pid: Option<u32>) -> Option<ProfileOptions> {
+        server_pid.map(|pid| ProfileOptions {
+            pid,
+            interval: self.profile_interval,
+            perf: self.perf_dir.as_ref().map(|dir| PerfOptions {
+                dir: dir.clone(),
+                frequency: self.perf_frequency,
+                flamegraph: self.flamegraph,
+            }),
         })
     }
+
+    fn plan(&self) -> LoadPlan {
+        LoadPlan {
+            rate: self.rate,
+            duration: self.soak.unwrap_or(self.duration),
+            arrival: self.arrival,
+            max_in_flight: self.max_in_flight,
+            request_timeout: self.request_timeout,
+        }
+    }
 }
 
 struct ServerHandle {
@@ -163,7 +410,7 @@ struct ServerHandle {
 }
 
 impl ServerHandle {
-    async fn spawn(args: &BenchArgs) -> Result<Self> {
+    async fn spawn(args: &BenchArgs, cert_out: Option<&Path>) -> Result<Self> {
         if !args.server_bin.exists() {
             return Err(anyhow!(
                 "server binary not found at {}",
@@ -183,6 +430,10 @@ impl ServerHandle {
         }
 
         apply_server_env(&mut cmd, &args.server_env)?;
+        if let Some(path) = cert_out {
+            // Have the server export its self-signed certificate for the QUIC client
+            cmd.env("ULTRA_RPC_CERT_OUT", path);
+        }
 
         configure_stdio(&mut cmd, args.server_log.as_deref())?;
 
@@ -196,12 +447,9 @@ impl ServerHandle {
         })
     }
 
-    async fn wait_ready(&mut self, warmup: Duration) {
-        if warmup.is_zero() {
-            return;
-        }
-        info!(duration = %format_duration(warmup), "waiting for server warmup");
-        sleep(warmup).await;
+    async fn wait_ready(&mut self, options: &ClientOptions, probe: &ReadinessProbe) -> Result<()> {
+        wait_until_ready(options, probe, Some(&mut self.child)).await?;
+        Ok(())
     }
 
     async fn shutdown(mut self) -> Result<()> {
@@ -289,399 +537,295 @@ fn parse_env_assignment(assignment: &str) -> Result<(String, String)> {
     Ok((key.to_string(), value.to_string()))
 }
 
-async fn run_wrk_iterations(args: &BenchArgs, wrk_bin: &Path) -> Result<Vec<WrkReport>> {
-    if !wrk_bin.exists() {
-        return Err(anyhow!("wrk binary not found at {}", wrk_bin.display()));
-    }
-
-    if let Some(script) = &args.wrk_script {
-        if !script.exists() {
-            return Err(anyhow!("wrk script not found at {}", script.display()));
-        }
-    }
+/// What drives each iteration's requests.
+enum LoadSource<'a> {
+    Local {
+        client: &'a RpcClient,
+        workload: &'a Workload,
+    },
+    Workers(&'a mut Coordinator),
+}
 
+async fn run_iterations(
+    args: &BenchArgs,
+    mut source: LoadSource<'_>,
+    server_pid: Option<u32>,
+) -> Result<Vec<IterationReport>> {
+    let plan = args.plan();
+    let soak = args.soak_options(server_pid);
+    let scrape = args.scrape_options();
+    let replay = args.replay_options();
+    let profile = args.profile_options(server_pid);
     let mut reports = Vec::with_capacity(args.iterations as usize);
 
     for iteration in 1..=args.iterations {
         info!(
             iteration,
             total = args.iterations,
-            bin = %wrk_bin.display(),
-            threads = args.wrk_threads,
-            connections = args.wrk_connections,
-            duration = %format_duration(args.wrk_duration),
+            transport = ?args.transport,
+            rate = args.rate,
+            arrival = ?args.arrival,
+            duration = %format_duration(plan.duration),
             endpoint = %args.rpc_endpoint,
-            "starting wrk iteration"
+            "starting load iteration"
         );
 
-        let report = run_wrk(args, wrk_bin, iteration).await?;
-        log_iteration_metrics(&report);
+        let profiler = profile
+            .as_ref()
+            .map(|options| Profiler::start(options, iteration))
+            .transpose()?;
+        let scraper = scrape.as_ref().map(Scraper::start).transpose()?;
+        let replayer = replay.as_ref().map(Replayer::start).transpose()?;
+        let (elapsed, methods, soaked) = match &mut source {
+            LoadSource::Local { client, workload } => {
+                let (stats, soaked) = match &soak {
+                    Some(options) => {
+                        let (stats, report) = soak::run(client, workload, &plan, options).await?;
+                        (stats, Some(report))
+                    }
+                    None => (load::run(client, workload, &plan).await?, None),
+                };
+                let methods = group_by_method(&workload.labels(), &stats);
+                (stats.elapsed, methods, soaked)
+            }
+            LoadSource::Workers(coordinator) => {
+                let run = coordinator.run(iteration, &plan).await?;
+                for worker in &run.workers {
+                    info!(
+                        iteration,
+                        worker = %worker.peer,
+                        requests = worker.total.requests,
+                        dropped = worker.total.dropped,
+                        elapsed = %format_duration(worker.elapsed),
+                        "worker share complete"
+                    );
+                }
+                (run.elapsed, run.methods, None)
+            }
+        };
+        let replayed = replayer.map(Replayer::finish).transpose()?;
+        let server = match scraper {
+            Some(scraper) => Some(scraper.finish().await?),
+            None => None,
+        };
+        let resources = match profiler {
+            Some(profiler) => Some(profiler.finish().await?),
+            None => None,
+        };
+        let total = merge_all(methods.values());
+        if let Some(dir) = &args.histogram_dir {
+            write_histograms(dir, iteration, &total, &methods)?;
+        }
+        let mut report =
+            IterationReport::new(iteration, args.transport, &plan, &total, &methods, elapsed)?;
+        report.server = server;
+        report.replay = replayed;
+        report.soak = soaked;
+        report.resources = resources;
+        log_iteration(&report);
         reports.push(report);
 
-        if iteration < args.iterations && !args.wrk_cooldown.is_zero() {
+        if iteration < args.iterations && !args.cooldown.is_zero() {
             info!(
                 iteration,
-                cooldown = %format_duration(args.wrk_cooldown),
+                cooldown = %format_duration(args.cooldown),
                 "cooldown before next iteration"
             );
-            sleep(args.wrk_cooldown).await;
+            sleep(args.cooldown).await;
         }
     }
 
     Ok(reports)
 }
 
-async fn run_wrk(args: &BenchArgs, wrk_bin: &Path, iteration: u32) -> Result<WrkReport> {
-    let start = Instant::now();
-
-    let mut cmd = Command::new(wrk_bin);
-    cmd.kill_on_drop(true);
-    cmd.stdin(Stdio::null());
-    cmd.stdout(Stdio::piped());
-    cmd.stderr(Stdio::piped());
-    cmd.arg("--latency");
-    cmd.arg("-t").arg(args.wrk_threads.to_string());
-    cmd.arg("-c").arg(args.wrk_connections.to_string());
-    cmd.arg("-d")
-        .arg(format!("{}", format_duration(args.wrk_duration)));
-    if let Some(script) = &args.wrk_script {
-        cmd.arg("-s").arg(script);
-    }
-    cmd.arg(format!("quic://{}", args.rpc_endpoint));
-
-    let mut child = cmd
-        .spawn()
-        .with_context(|| format!("failed running {}", wrk_bin.display()))?;
-
-    let stdout = child
-        .stdout
-        .take()
-        .context("failed to capture wrk stdout")?;
-    let stderr = child
-        .stderr
-        .take()
-        .context("failed to capture wrk stderr")?;
-
-    let stdout_task = tokio::spawn(async move {
-        let mut reader = BufReader::new(stdout);
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf).await?;
-        Ok::<String, std::io::Error>(String::from_utf8_lossy(&buf).into_owned())
-    });
-
-    let stderr_task = tokio::spawn(async move {
-        let mut reader = BufReader::new(stderr);
-        let mut buf = Vec::new();
-        reader.read_to_end(&mut buf).await?;
-        Ok::<String, std::io::Error>(String::from_utf8_lossy(&buf).into_owned())
-    });
-
-    let wait_result = timeout(args.wrk_timeout, child.wait()).await;
-
-    let status = match wait_result {
-        Ok(status_res) => {
-            status_res.with_context(|| format!("failed waiting for {}", wrk_bin.display()))?
-        }
-        Err(_) => {
-            let timeout_str = format!("{}", format_duration(args.wrk_timeout));
+fn format_latency(latency_ns: Option<u64>) -> String {
+    latency_ns
+        .map(|ns| format!("{}", format_duration(Duration::from_nanos(ns))))
+        .unwrap_or_else(|| "<n/a>".to_string())
+}
+
+fn log_iteration(report: &IterationReport) {
+    let latency = report.total.latency.as_ref();
+    info!(
+        iteration = report.iteration,
+        duration_ms = report.duration_ms,
+        requests = report.total.requests,
+        errors = report.total.rpc_errors + report.total.failures + report.total.timeouts,
+        dropped = report.total.dropped,
+        achieved_rate = report.achieved_rate,
+        p50_latency = %format_latency(latency.and_then(|l| l.percentile(50.0))),
+        p99_latency = %format_latency(latency.and_then(|l| l.percentile(99.0))),
+        p9999_latency = %format_latency(latency.and_then(|l| l.percentile(99.99))),
+        "load iteration complete"
+    );
+    if report.total.dropped > 0 {
+        warn!(
+            iteration = report.iteration,
+            dropped = report.total.dropped,
+            "arrivals dropped at the in-flight limit; the server did not keep up"
+        );
+    }
+    if let Some(replay) = &report.replay {
+        info!(
+            iteration = report.iteration,
+            frames = replay.frames,
+            bytes = replay.bytes,
+            passes = replay.passes,
+            max_lag_ms = replay.max_lag_ms,
+            "capture replay"
+        );
+        if replay.invalid > 0 {
             warn!(
-                iteration,
-                timeout = %timeout_str,
-                "wrk exceeded timeout; terminating"
+                iteration = report.iteration,
+                invalid = replay.invalid,
+                "skipped capture entries with bad frame headers"
             );
-            if let Err(err) = child.start_kill() {
-                warn!(%err, "failed to signal wrk for shutdown; retrying with kill()");
-                child
-                    .kill()
-                    .await
-                    .context("failed to kill timed out wrk process")?;
-            }
-            child
-                .wait()
-                .await
-                .context("failed to await wrk termination after timeout")?;
-            stdout_task.abort();
-            stderr_task.abort();
-            let _ = stdout_task.await;
-            let _ = stderr_task.await;
-            return Err(anyhow!(
-                "wrk iteration {} exceeded timeout {}",
-                iteration,
-                timeout_str
-            ));
         }
-    };
-
-    let stdout = stdout_task
-        .await
-        .context("failed to join wrk stdout task")??;
-    let stderr = stderr_task
-        .await
-        .context("failed to join wrk stderr task")??;
-
-    if !status.success() {
-        return Err(anyhow!(
-            "wrk iteration {} exited with status {}\nstdout:\n{}\nstderr:\n{}",
-            iteration,
-            status,
-            stdout.trim(),
-            stderr.trim()
-        ));
     }
-
-    let duration_ms = start.elapsed().as_millis().min(u128::from(u64::MAX)) as u64;
-    let metrics = parse_wrk_output(&stdout);
-
-    Ok(WrkReport {
-        iteration,
-        duration_ms,
-        stdout,
-        stderr,
-        metrics,
-    })
-}
-
-fn log_iteration_metrics(report: &WrkReport) {
-    if let Some(metrics) = &report.metrics {
-        let avg_latency = metrics
-            .latency
-            .as_ref()
-            .map(|lat| format!("{}", format_duration(Duration::from_nanos(lat.avg_ns))))
-            .unwrap_or_else(|| "<n/a>".to_string());
-        let p99_latency = metrics
-            .find_percentile(99.0)
-            .map(|p| format!("{}", format_duration(Duration::from_nanos(p.latency_ns))))
-            .unwrap_or_else(|| "<n/a>".to_string());
-
+    if let Some(resources) = &report.resources {
         info!(
             iteration = report.iteration,
-            duration_ms = report.duration_ms,
-            requests_per_sec = metrics.requests_per_sec.unwrap_or_default(),
-            avg_latency = %avg_latency,
-            p99_latency = %p99_latency,
-            "wrk iteration complete"
+            samples = resources.samples.len(),
+            mean_cpu_cores = ?resources.cpu_cores.as_ref().map(|cpu| cpu.mean),
+            max_rss_bytes = ?resources.rss_bytes.as_ref().map(|rss| rss.max),
+            max_open_fds = ?resources.open_fds.as_ref().map(|fds| fds.max),
+            max_threads = ?resources.threads.as_ref().map(|threads| threads.max),
+            voluntary_context_switches = resources.voluntary_context_switches,
+            involuntary_context_switches = resources.involuntary_context_switches,
+            perf_data = ?resources.perf_data,
+            flamegraph = ?resources.flamegraph,
+            "server resources"
         );
-    } else {
-        warn!(
+        if resources.failed_samples > 0 {
+            warn!(
+                iteration = report.iteration,
+                pid = resources.pid,
+                failed = resources.failed_samples,
+                "some /proc samples of the server failed"
+            );
+        }
+    }
+    if let Some(server) = &report.server {
+        let deepest_queue = server
+            .queue_depths
+            .iter()
+            .max_by(|a, b| a.1.max.total_cmp(&b.1.max));
+        info!(
             iteration = report.iteration,
-            "wrk output parsing yielded no metrics"
+            scrapes = server.scrapes,
+            failed_scrapes = server.failed_scrapes,
+            mean_cpu_cores = ?server.cpu_cores.as_ref().map(|cpu| cpu.mean),
+            max_resident_memory_bytes = ?server.resident_memory_bytes.as_ref().map(|mem| mem.max),
+            deepest_queue = ?deepest_queue.map(|(queue, depth)| (queue, depth.max)),
+            cache_hit_ratios = ?server.cache_hit_ratios,
+            "server metrics"
         );
+        if server.scrapes == 0 {
+            warn!(
+                iteration = report.iteration,
+                endpoint = %server.endpoint,
+                "no server metrics scrape succeeded"
+            );
+        }
     }
 }
 
-fn log_aggregate_metrics(reports: &[WrkReport]) {
+fn log_aggregate_metrics(reports: &[IterationReport]) {
     if reports.is_empty() {
         return;
     }
 
-    let mut rps_total = 0.0;
-    let mut rps_count = 0;
-    let mut p99_values = Vec::new();
-
-    for report in reports {
-        if let Some(metrics) = &report.metrics {
-            if let Some(rps) = metrics.requests_per_sec {
-                rps_total += rps;
-                rps_count += 1;
-            }
-            if let Some(p99) = metrics.find_percentile(99.0) {
-                p99_values.push(p99.latency_ns);
-            }
-        }
-    }
-
-    if rps_count > 0 {
-        info!(
-            iterations = rps_count,
-            avg_requests_per_sec = rps_total / rps_count as f64,
-            "wrk average throughput"
-        );
-    }
-
+    let rps_total: f64 = reports.iter().map(|report| report.achieved_rate).sum();
+    info!(
+        iterations = reports.len(),
+        avg_requests_per_sec = rps_total / reports.len() as f64,
+        "average throughput"
+    );
+
+    let mut p99_values: Vec<u64> = reports
+        .iter()
+        .filter_map(|report| report.total.latency.as_ref()?.percentile(99.0))
+        .collect();
     if !p99_values.is_empty() {
         p99_values.sort_unstable();
         let median = p99_values[p99_values.len() / 2];
         info!(
             iterations = p99_values.len(),
             median_p99_latency = %format_duration(Duration::from_nanos(median)),
-            "wrk median p99 latency"
+            "median p99 latency"
         );
     }
 }
 
-fn write_wrk_reports(path: &Path, reports: &[WrkReport]) -> Result<()> {
-    if let Some(dir) = path.parent() {
-        if !dir.as_os_str().is_empty() {
-            fs::create_dir_all(dir).with_context(|| {
-                format!("failed to create wrk output directory {}", dir.display())
-            })?;
+/// Compares `reports` with the baseline, failing on any regression.
+fn gate_on_baseline(
+    args: &BenchArgs,
+    baseline_path: &Path,
+    reports: &[IterationReport],
+) -> Result<()> {
+    let baseline = read_reports(baseline_path)?;
+    if let (Some(before), Some(after)) = (baseline.first(), reports.first()) {
+        if before.target_rate != after.target_rate || before.transport != after.transport {
+            warn!(
+                baseline_rate = before.target_rate,
+                current_rate = after.target_rate,
+                baseline_transport = ?before.transport,
+                current_transport = ?after.transport,
+                "baseline was taken with different load settings"
+            );
         }
     }
 
-    let file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(path)
-        .with_context(|| format!("failed to open wrk output path {}", path.display()))?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer_pretty(writer, reports)
-        .with_context(|| format!("failed to write wrk output to {}", path.display()))?;
-    Ok(())
-}
-
-fn parse_wrk_output(stdout: &str) -> Option<WrkMetrics> {
-    let mut metrics = WrkMetrics::default();
-    let mut in_distribution = false;
-
-    for line in stdout.lines() {
-        let trimmed = line.trim();
-
-        if trimmed.is_empty() {
-            if in_distribution {
-                in_distribution = false;
-            }
-            continue;
-        }
-
-        if let Some(value) = trimmed.strip_prefix("Requests/sec:") {
-            if let Some(token) = value.split_whitespace().next() {
-                if let Ok(parsed) = token.parse::<f64>() {
-                    metrics.requests_per_sec = Some(parsed);
-                }
-            }
-            continue;
-        }
-
-        if let Some(value) = trimmed.strip_prefix("Transfer/sec:") {
-            if let Some(rate) = parse_transfer_rate(value.trim()) {
-                metrics.transfer_per_sec = Some(rate);
-            }
-            continue;
-        }
-
-        if trimmed.starts_with("Latency Distribution") {
-            in_distribution = true;
-            continue;
-        }
-
-        if in_distribution {
-            if let Some(percentile) = parse_distribution_line(trimmed) {
-                metrics.latency_distribution.push(percentile);
-                continue;
-            } else {
-                in_distribution = false;
-            }
-        }
-
-        if trimmed.starts_with("Latency") {
-            if let Some(latency) = parse_latency_line(trimmed) {
-                metrics.latency = Some(latency);
-            }
+    let comparison = compare::compare(&baseline, reports, &args.thresholds())?;
+    for delta in &comparison.deltas {
+        if delta.regressed {
+            warn!(
+                scope = %delta.scope,
+                metric = %delta.metric,
+                baseline = delta.baseline,
+                current = delta.current,
+                change = delta.change,
+                limit = delta.limit,
+                "regression against baseline"
+            );
+        } else {
+            info!(
+                scope = %delta.scope,
+                metric = %delta.metric,
+                baseline = delta.baseline,
+                current = delta.current,
+                change = delta.change,
+                "compared with baseline"
+            );
         }
     }
-
-    if metrics.is_empty() {
-        None
-    } else {
-        Some(metrics)
+    if let Some(path) = &args.comparison_json {
+        write_json(path, &comparison)?;
+        info!(path = %path.display(), "persisted baseline comparison");
     }
-}
-
-fn parse_transfer_rate(value: &str) -> Option<TransferRate> {
-    let token = value.split_whitespace().next()?;
-    let (amount, unit) = split_numeric_unit(token)?;
-    Some(TransferRate {
-        value: amount,
-        unit: unit.to_string(),
-    })
-}
-
-fn parse_distribution_line(line: &str) -> Option<WrkPercentile> {
-    let mut parts = line.split_whitespace();
-    let percentile_token = parts.next()?;
-    if !percentile_token.ends_with('%') {
-        return None;
-    }
-    let percentile = percentile_token.trim_end_matches('%').parse::<f64>().ok()?;
-    let latency_token = parts.next()?;
-    let latency_ns = parse_latency_token(latency_token)?;
-    Some(WrkPercentile {
-        percentile,
-        latency_ns,
-    })
-}
 
-fn parse_latency_line(line: &str) -> Option<WrkLatencyStats> {
-    let mut parts = line.split_whitespace();
-    if parts.next()? != "Latency" {
-        return None;
+    let regressions = comparison.regressions().count();
+    if regressions > 0 {
+        return Err(anyhow!(
+            "{} metric(s) regressed against baseline {}",
+            regressions,
+            baseline_path.display()
+        ));
     }
-
-    let avg_ns = parse_latency_token(parts.next()?)?;
-    let stdev_ns = parse_latency_token(parts.next()?)?;
-    let max_ns = parse_latency_token(parts.next()?)?;
-    let stdev_pct = parts
-        .next()
-        .and_then(|value| value.trim_end_matches('%').parse::<f64>().ok())?;
-
-    Some(WrkLatencyStats {
-        avg_ns,
-        stdev_ns,
-        max_ns,
-        stdev_pct,
-    })
-}
-
-fn parse_latency_token(token: &str) -> Option<u64> {
-    let (value, unit_raw) = split_numeric_unit(token)?;
-    let unit = unit_raw.trim();
-    let unit_lower = unit.to_ascii_lowercase();
-    let nanos = match unit_lower.as_str() {
-        "s" | "sec" | "secs" => value * 1_000_000_000.0,
-        "ms" => value * 1_000_000.0,
-        "us" => value * 1_000.0,
-        "ns" => value,
-        _ => match unit {
-            "µs" | "μs" => value * 1_000.0,
-            _ => return None,
-        },
-    };
-    Some(nanos.round() as u64)
+    info!(baseline = %baseline_path.display(), "no regressions against baseline");
+    Ok(())
 }
 
-fn split_numeric_unit(token: &str) -> Option<(f64, &str)> {
-    let token = token.trim();
-    if token.is_empty() {
-        return None;
-    }
-
-    let mut split_idx = None;
-    for (idx, ch) in token.char_indices() {
-        if !(ch.is_ascii_digit() || ch == '.') {
-            split_idx = Some(idx);
-            break;
-        }
-    }
-
-    let idx = split_idx?;
-    let (value_part, unit_part) = token.split_at(idx);
-    if unit_part.is_empty() {
-        return None;
-    }
-    let value = value_part.parse::<f64>().ok()?;
-    Some((value, unit_part))
+/// Where the launched server should export its certificate, when the QUIC
+/// client has no CA bundle of its own.
+fn certificate_export_path(args: &BenchArgs) -> Option<PathBuf> {
+    (!args.skip_server && args.transport == TransportKind::Quic && args.quic_ca_cert.is_none())
+        .then(|| std::env::temp_dir().join(format!("ultra-rpc-bench-{}.pem", std::process::id())))
 }
 
 fn log_dry_run(args: &BenchArgs) {
     if args.skip_server {
         info!("dry run: server launch skipped (--skip-server)");
-        if !args.warmup.is_zero() {
-            info!(
-                warmup = %format_duration(args.warmup),
-                "dry run: warmup suppressed due to --skip-server"
-            );
-        }
     } else {
         info!(
             bin = %args.server_bin.display(),
@@ -690,7 +834,6 @@ fn log_dry_run(args: &BenchArgs) {
                 .as_ref()
                 .map(|p| p.display().to_string())
                 .unwrap_or_else(|| "<none>".to_string()),
-            warmup = %format_duration(args.warmup),
             shutdown_grace = %format_duration(args.shutdown_grace),
             "dry run: would spawn server"
         );
@@ -708,33 +851,149 @@ fn log_dry_run(args: &BenchArgs) {
         }
     }
 
-    if let Some(wrk) = &args.wrk_bin {
-        let script = args
-            .wrk_script
-            .as_ref()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|| "<none>".to_string());
+    let methods = match args.workload() {
+        Ok(workload) => workload.labels(),
+        Err(err) => {
+            warn!(error = %err, "dry run: workload is invalid");
+            Vec::new()
+        }
+    };
+    info!(
+        transport = ?args.transport,
+        endpoint = %args.rpc_endpoint,
+        connections = args.connections,
+        methods = ?methods,
+        rate = args.rate,
+        arrival = ?args.arrival,
+        duration = %format_duration(args.duration),
+        iterations = args.iterations,
+        cooldown = %format_duration(args.cooldown),
+        max_in_flight = args.max_in_flight,
+        request_timeout = %format_duration(args.request_timeout),
+        "dry run: would generate load"
+    );
+    if let Some(output_path) = &args.output_json {
         info!(
-            bin = %wrk.display(),
-            threads = args.wrk_threads,
-            connections = args.wrk_connections,
-            duration = %format_duration(args.wrk_duration),
-            iterations = args.iterations,
-            cooldown = %format_duration(args.wrk_cooldown),
-            timeout = %format_duration(args.wrk_timeout),
-            endpoint = %args.rpc_endpoint,
-            script = %script,
-            "dry run: would execute wrk"
+            path = %output_path.display(),
+            "dry run: would persist results"
         );
-        if let Some(output_path) = &args.wrk_output_json {
-            info!(
-                path = %output_path.display(),
-                "dry run: would persist wrk output"
-            );
-        }
-    } else {
-        info!("dry run: no wrk binary configured");
     }
+    if let Some(path) = &args.output_csv {
+        info!(path = %path.display(), "dry run: would write CSV report");
+    }
+    if let Some(path) = &args.output_html {
+        info!(path = %path.display(), "dry run: would write HTML report");
+    }
+    if let Some(dir) = &args.histogram_dir {
+        info!(
+            dir = %dir.display(),
+            "dry run: would write latency histograms"
+        );
+    }
+    info!(
+        method = %args.ready_method.method,
+        successes = args.ready_successes,
+        interval = %format_duration(args.ready_interval),
+        timeout = %format_duration(args.ready_timeout),
+        "dry run: would probe readiness before load"
+    );
+    if !args.skip_server {
+        info!(
+            interval = %format_duration(args.profile_interval),
+            perf_dir = ?args.perf_dir,
+            perf_frequency = args.perf_frequency,
+            flamegraph = args.flamegraph,
+            "dry run: would profile the launched server"
+        );
+    }
+    if let Some(replay) = args.replay_options() {
+        info!(
+            capture = %replay.capture.display(),
+            socket = %replay.socket.display(),
+            speed = replay.speed,
+            "dry run: would replay capture during load"
+        );
+    }
+    if let Some(soak) = args.soak {
+        info!(
+            duration = %format_duration(soak),
+            checkpoint = %format_duration(args.checkpoint_interval),
+            baseline_checkpoints = args.soak_baseline_checkpoints,
+            max_latency_creep = args.soak_max_latency_creep,
+            max_rss_growth = args.soak_max_rss_growth,
+            max_throughput_drop = args.soak_max_throughput_drop,
+            "dry run: would soak with rolling checkpoints"
+        );
+    }
+    if let Some(addr) = &args.worker_listen {
+        info!(addr = %addr, "dry run: would serve as a load worker");
+    }
+    if !args.workers.is_empty() {
+        info!(
+            workers = ?args.workers,
+            rate_per_worker = args.rate / args.workers.len() as f64,
+            "dry run: would split load across workers"
+        );
+    }
+    if let Some(scrape) = args.scrape_options() {
+        info!(
+            endpoint = %scrape.endpoint,
+            interval = %format_duration(scrape.interval),
+            series = ?scrape.series,
+            "dry run: would scrape server metrics"
+        );
+    }
+    if let Some(baseline) = &args.baseline {
+        info!(
+            baseline = %baseline.display(),
+            candidate = args
+                .candidate
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "<this run>".to_string()),
+            max_throughput_drop = args.max_throughput_drop,
+            max_latency_increase = args.max_latency_increase,
+            max_error_rate_increase = args.max_error_rate_increase,
+            percentiles = ?args.gate_percentiles,
+            "dry run: would compare against baseline"
+        );
+    }
+}
+
+async fn generate_load(
+    args: &BenchArgs,
+    options: &ClientOptions,
+    server_pid: Option<u32>,
+) -> Result<Vec<IterationReport>> {
+    args.plan().validate()?;
+    if !args.workers.is_empty() {
+        let mut coordinator = Coordinator::connect(&args.workers).await?;
+        return run_iterations(args, LoadSource::Workers(&mut coordinator), server_pid).await;
+    }
+    let workload = args.workload()?;
+    let client = RpcClient::connect(options).await?;
+    let reports = run_iterations(
+        args,
+        LoadSource::Local {
+            client: &client,
+            workload: &workload,
+        },
+        server_pid,
+    )
+    .await;
+    client.close().await;
+    reports
+}
+
+/// Serves coordinators until killed, generating load with the local
+/// endpoint and workload settings.
+async fn run_worker(args: &BenchArgs, addr: &str) -> Result<()> {
+    let workload = args.workload()?;
+    let options = args.client_options(args.quic_ca_cert.clone());
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind worker control address {addr}"))?;
+    distributed::serve(listener, &options, &workload).await
 }
 
 #[tokio::main]
@@ -748,27 +1007,35 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(addr) = &args.worker_listen {
+        return run_worker(&args, addr).await;
+    }
+
+    if let (Some(candidate), Some(baseline)) = (&args.candidate, &args.baseline) {
+        let reports = read_reports(candidate)?;
+        return gate_on_baseline(&args, baseline, &reports);
+    }
+
+    let cert_out = certificate_export_path(&args);
     let mut server = if args.skip_server {
         info!("server launch skipped (--skip-server)");
         None
     } else {
-        Some(ServerHandle::spawn(&args).await?)
+        Some(ServerHandle::spawn(&args, cert_out.as_deref()).await?)
     };
 
-    if let Some(handle) = server.as_mut() {
-        handle.wait_ready(args.warmup).await;
-    } else if !args.warmup.is_zero() {
-        info!(
-            duration = %format_duration(args.warmup),
-            "warmup skipped because server launch was omitted"
-        );
-    }
-
-    let wrk_result = if let Some(wrk_bin) = args.wrk_bin.as_deref() {
-        run_wrk_iterations(&args, wrk_bin).await
-    } else {
-        warn!("wrk binary not provided; skipping load generation");
-        Ok(Vec::new())
+    let options = args.client_options(args.quic_ca_cert.clone().or(cert_out.clone()));
+    let probe = args.readiness_probe();
+    let ready = match server.as_mut() {
+        Some(handle) => handle.wait_ready(&options, &probe).await,
+        None => wait_until_ready(&options, &probe, None).await.map(|_| ()),
+    };
+    let load_result = match ready {
+        Ok(()) => {
+            let server_pid = server.as_ref().and_then(|handle| handle.child.id());
+            generate_load(&args, &options, server_pid).await
+        }
+        Err(err) => Err(err),
     };
 
     let shutdown_result = if let Some(handle) = server {
@@ -776,22 +1043,47 @@ async fn main() -> Result<()> {
     } else {
         Ok(())
     };
+    if let Some(path) = &cert_out {
+        let _ = fs::remove_file(path);
+    }
 
-    let wrk_reports = wrk_result?;
+    let reports = load_result?;
     shutdown_result?;
 
-    if !wrk_reports.is_empty() {
-        log_aggregate_metrics(&wrk_reports);
-        if let Some(path) = &args.wrk_output_json {
-            write_wrk_reports(path, &wrk_reports)?;
-            info!(
-                path = %path.display(),
-                entries = wrk_reports.len(),
-                "persisted wrk results"
-            );
+    log_aggregate_metrics(&reports);
+    if let Some(path) = &args.output_json {
+        write_reports(path, &reports)?;
+        info!(
+            path = %path.display(),
+            entries = reports.len(),
+            "persisted load results"
+        );
+    }
+    if let Some(path) = &args.output_csv {
+        export::write_csv(path, &reports)?;
+        info!(path = %path.display(), "wrote CSV report");
+    }
+    if let Some(path) = &args.output_html {
+        export::write_html(path, &reports)?;
+        info!(path = %path.display(), "wrote HTML report");
+    }
+
+    if let Some(baseline) = &args.baseline {
+        gate_on_baseline(&args, baseline, &reports)?;
+    }
+
+    if let Some(soak) = reports.iter().find_map(|report| report.soak.as_ref()) {
+        if !soak.degraded.is_empty() {
+            for violation in &soak.degraded {
+                warn!(%violation, "soak degraded over time");
+            }
+            return Err(anyhow!("soak degraded: {}", soak.degraded.join("; ")));
         }
-    } else if args.wrk_output_json.is_some() {
-        warn!("wrk output path configured but no runs were executed; nothing written");
+        info!(
+            checkpoints = soak.checkpoints.len(),
+            drift = ?soak.drift,
+            "soak held steady"
+        );
     }
 
     Ok(())
@@ -813,59 +1105,4 @@ mod tests {
         let err = parse_env_assignment("BROKEN").expect_err("missing '=' should fail");
         assert!(err.to_string().contains("missing '='"));
     }
-
-    #[test]
-    fn split_numeric_unit_handles_decimals() {
-        let (value, unit) = split_numeric_unit("123.45ms").expect("valid pair");
-        assert!((value - 123.45).abs() < f64::EPSILON);
-        assert_eq!(unit, "ms");
-    }
-
-    #[test]
-    fn parse_latency_token_supports_micro() {
-        let micros = parse_latency_token("1.5ms").expect("parse token");
-        assert_eq!(micros, 1_500_000);
-        let micro_symbol = parse_latency_token("2µs").expect("micro symbol");
-        assert_eq!(micro_symbol, 2_000);
-    }
-
-    #[test]
-    fn parse_distribution_line_extracts_percentile() {
-        let pct = parse_distribution_line("  99.00%   123.4ms").expect("parse line");
-        assert_eq!(pct.percentile, 99.0);
-        assert_eq!(pct.latency_ns, 123_400_000);
-    }
-
-    #[test]
-    fn parse_wrk_output_collects_metrics() {
-        let output = r#"
-Running 1m test @ http://localhost
-  1 threads and 10 connections
-  Thread Stats   Avg      Stdev     Max   +/- Stdev
-    Latency     2.50ms    1.00ms  10.00ms   75.00%
-    Req/Sec     4.00k   500.00     5.50k    70.00%
-  Latency Distribution
-     50%    2.00ms
-     75%    3.00ms
-     99%    8.00ms
-
-  240000 requests in 60.00s, 18.00MB read
-Requests/sec:   4000.00
-Transfer/sec:     300.00KB
-"#;
-        let metrics = parse_wrk_output(output).expect("metrics parsed");
-        assert_eq!(metrics.requests_per_sec, Some(4000.0));
-        let latency = metrics.latency.as_ref().expect("latency stats");
-        assert_eq!(latency.avg_ns, 2_500_000);
-        let p99 = metrics.find_percentile(99.0).expect("p99 present");
-        assert_eq!(p99.latency_ns, 8_000_000);
-        let transfer = metrics.transfer_per_sec.expect("transfer");
-        assert_eq!(transfer.unit, "KB");
-    }
-
-    #[test]
-    fn wrk_metrics_is_empty_checks() {
-        let metrics = WrkMetrics::default();
-        assert!(metrics.is_empty());
-    }
 }
@@ -0,0 +1,401 @@
+// Numan Thabit 2025
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    process::{Child, Command},
+    sync::oneshot,
+    task::JoinHandle,
+    time::{interval, timeout, Instant, MissedTickBehavior},
+};
+use tracing::{debug, info, warn};
+
+use crate::server_metrics::GaugeSummary;
+
+/// Clock ticks per second of the CPU times in `/proc/<pid>/stat`. Linux
+/// fixes USER_HZ at 100 on every architecture it exposes it to userspace.
+const CLOCK_TICKS_PER_SEC: f64 = 100.0;
+/// Time perf gets to flush its data file after being interrupted.
+const PERF_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct PerfOptions {
+    /// Directory the `perf.data` files (and flamegraphs) are written to.
+    pub dir: PathBuf,
+    /// Sampling frequency handed to `perf record -F`.
+    pub frequency: u32,
+    /// Render a flamegraph SVG with `perf script` and inferno after each
+    /// iteration.
+    pub flamegraph: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileOptions {
+    pub pid: u32,
+    pub interval: Duration,
+    pub perf: Option<PerfOptions>,
+}
+
+/// The server's `/proc` readings at one instant.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ResourceSample {
+    pub elapsed_ms: u64,
+    /// CPU cores the server kept busy since the previous sample.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<f64>,
+    pub rss_bytes: u64,
+    pub open_fds: u64,
+    pub threads: u64,
+    /// Summed over the server's live threads.
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+}
+
+/// Resource usage of the launched server over one iteration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ResourceProfile {
+    pub pid: u32,
+    pub failed_samples: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<GaugeSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<GaugeSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_fds: Option<GaugeSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<GaugeSummary>,
+    /// Context switches between the first and last sample.
+    pub voluntary_context_switches: u64,
+    pub involuntary_context_switches: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perf_data: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flamegraph: Option<PathBuf>,
+    pub samples: Vec<ResourceSample>,
+}
+
+/// Raw counters read from `/proc/<pid>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ProcReading {
+    cpu_seconds: f64,
+    threads: u64,
+    rss_bytes: u64,
+    open_fds: u64,
+    voluntary_context_switches: u64,
+    involuntary_context_switches: u64,
+}
+
+/// Resident set size of `pid`, from `/proc/<pid>/status`.
+pub fn read_rss(pid: u32) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    status_field(&status, "VmRSS:").map(|kib| kib * 1024)
+}
+
+/// A numeric `/proc/<pid>/status` field, without its `kB` unit.
+fn status_field(status: &str, name: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(name))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// CPU seconds (user + system) and thread count from `/proc/<pid>/stat`.
+fn parse_stat(stat: &str) -> Option<(f64, u64)> {
+    // The command name may hold spaces and parentheses, so skip past the
+    // last `)`; the state, field 3, comes next
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let threads = fields.get(17)?.parse().ok()?;
+    Some(((utime + stime) as f64 / CLOCK_TICKS_PER_SEC, threads))
+}
+
+fn read_proc(pid: u32) -> Result<ProcReading> {
+    let root = PathBuf::from(format!("/proc/{pid}"));
+    let stat = fs::read_to_string(root.join("stat"))
+        .with_context(|| format!("failed to read /proc/{pid}/stat"))?;
+    let Some((cpu_seconds, threads)) = parse_stat(&stat) else {
+        bail!("malformed /proc/{pid}/stat");
+    };
+    let rss_bytes = read_rss(pid).with_context(|| format!("no VmRSS for pid {pid}"))?;
+    let open_fds = fs::read_dir(root.join("fd"))
+        .with_context(|| format!("failed to list /proc/{pid}/fd"))?
+        .count() as u64;
+
+    // The process-level status only counts the main thread's switches
+    let (mut voluntary, mut involuntary) = (0, 0);
+    for task in fs::read_dir(root.join("task"))
+        .with_context(|| format!("failed to list /proc/{pid}/task"))?
+        .flatten()
+    {
+        // Threads may exit between listing and reading
+        let Ok(status) = fs::read_to_string(task.path().join("status")) else {
+            continue;
+        };
+        voluntary += status_field(&status, "voluntary_ctxt_switches:").unwrap_or(0);
+        involuntary += status_field(&status, "nonvoluntary_ctxt_switches:").unwrap_or(0);
+    }
+
+    Ok(ProcReading {
+        cpu_seconds,
+        threads,
+        rss_bytes,
+        open_fds,
+        voluntary_context_switches: voluntary,
+        involuntary_context_switches: involuntary,
+    })
+}
+
+/// A `perf record` attached to the server for one iteration.
+struct PerfRecording {
+    child: Child,
+    data: PathBuf,
+    flamegraph: Option<PathBuf>,
+}
+
+impl PerfRecording {
+    fn start(options: &PerfOptions, pid: u32, iteration: u32) -> Result<Self> {
+        fs::create_dir_all(&options.dir).with_context(|| {
+            format!("failed to create perf directory {}", options.dir.display())
+        })?;
+        let data = options.dir.join(format!("iteration-{iteration}.perf.data"));
+        let child = Command::new("perf")
+            .arg("record")
+            .arg("-F")
+            .arg(options.frequency.to_string())
+            .arg("-g")
+            .arg("-p")
+            .arg(pid.to_string())
+            .arg("-o")
+            .arg(&data)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .context("failed to spawn `perf record`; is perf installed?")?;
+        info!(data = %data.display(), pid, "perf recording the server");
+        Ok(Self {
+            child,
+            flamegraph: options.flamegraph.then(|| {
+                options
+                    .dir
+                    .join(format!("iteration-{iteration}.flamegraph.svg"))
+            }),
+            data,
+        })
+    }
+
+    /// Interrupts perf so it writes its data file, then renders the
+    /// flamegraph. A failed flamegraph only warns; the data is kept.
+    async fn finish(mut self) -> Result<(PathBuf, Option<PathBuf>)> {
+        if let Some(id) = self.child.id() {
+            let _ = Command::new("kill")
+                .arg("-INT")
+                .arg(id.to_string())
+                .status()
+                .await;
+        }
+        let status = match timeout(PERF_STOP_TIMEOUT, self.child.wait()).await {
+            Ok(status) => status.context("failed to wait for perf")?,
+            Err(_) => {
+                let _ = self.child.kill().await;
+                bail!("perf did not stop within {PERF_STOP_TIMEOUT:?}");
+            }
+        };
+        if !self.data.exists() {
+            bail!(
+                "perf exited with {status} without writing {}",
+                self.data.display()
+            );
+        }
+
+        let flamegraph = match self.flamegraph {
+            Some(svg) => match render_flamegraph(&self.data, &svg).await {
+                Ok(()) => {
+                    info!(path = %svg.display(), "wrote flamegraph");
+                    Some(svg)
+                }
+                Err(err) => {
+                    warn!(error = %format!("{err:#}"), "flamegraph rendering failed");
+                    None
+                }
+            },
+            None => None,
+        };
+        Ok((self.data, flamegraph))
+    }
+}
+
+/// `perf script | inferno-collapse-perf | inferno-flamegraph > svg`.
+async fn render_flamegraph(data: &Path, svg: &Path) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(r#"perf script -i "$1" | inferno-collapse-perf | inferno-flamegraph > "$2""#)
+        .arg("sh")
+        .arg(data)
+        .arg(svg)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .context("failed to run the flamegraph pipeline")?;
+    let written = fs::metadata(svg).map(|meta| meta.len()).unwrap_or(0);
+    if !status.success() || written == 0 {
+        bail!("flamegraph pipeline exited with {status}; are perf and inferno installed?");
+    }
+    Ok(())
+}
+
+/// Samples the server's `/proc` stats in the background while load runs,
+/// optionally with perf attached.
+pub struct Profiler {
+    pid: u32,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<(Vec<(Duration, ProcReading)>, u64)>,
+    perf: Option<PerfRecording>,
+}
+
+impl Profiler {
+    pub fn start(options: &ProfileOptions, iteration: u32) -> Result<Self> {
+        if options.interval.is_zero() {
+            bail!("profile sample interval must be positive");
+        }
+        let perf = options
+            .perf
+            .as_ref()
+            .map(|perf| PerfRecording::start(perf, options.pid, iteration))
+            .transpose()?;
+        let pid = options.pid;
+        let period = options.interval;
+        let (stop, mut stopped) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            let start = Instant::now();
+            let mut ticks = interval(period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut readings = Vec::new();
+            let mut failed = 0u64;
+            loop {
+                let last = tokio::select! {
+                    _ = ticks.tick() => false,
+                    _ = &mut stopped => true,
+                };
+                match read_proc(pid) {
+                    Ok(reading) => readings.push((start.elapsed(), reading)),
+                    Err(err) => {
+                        debug!(error = %format!("{err:#}"), pid, "resource sample failed");
+                        failed += 1;
+                    }
+                }
+                if last {
+                    break;
+                }
+            }
+            (readings, failed)
+        });
+
+        Ok(Self {
+            pid,
+            stop,
+            task,
+            perf,
+        })
+    }
+
+    pub async fn finish(self) -> Result<ResourceProfile> {
+        let _ = self.stop.send(());
+        let (readings, failed) = self.task.await.context("resource profiler panicked")?;
+        let mut profile = summarize(self.pid, &readings, failed);
+        if let Some(perf) = self.perf {
+            let (data, flamegraph) = perf.finish().await?;
+            profile.perf_data = Some(data);
+            profile.flamegraph = flamegraph;
+        }
+        Ok(profile)
+    }
+}
+
+fn summarize(pid: u32, readings: &[(Duration, ProcReading)], failed: u64) -> ResourceProfile {
+    let mut samples = Vec::with_capacity(readings.len());
+    for (index, (elapsed, reading)) in readings.iter().enumerate() {
+        let cpu_cores = index.checked_sub(1).and_then(|prev| {
+            let (before, previous) = &readings[prev];
+            let seconds = elapsed.checked_sub(*before)?.as_secs_f64();
+            (seconds > 0.0).then(|| (reading.cpu_seconds - previous.cpu_seconds).max(0.0) / seconds)
+        });
+        samples.push(ResourceSample {
+            elapsed_ms: elapsed.as_millis().min(u128::from(u64::MAX)) as u64,
+            cpu_cores,
+            rss_bytes: reading.rss_bytes,
+            open_fds: reading.open_fds,
+            threads: reading.threads,
+            voluntary_context_switches: reading.voluntary_context_switches,
+            involuntary_context_switches: reading.involuntary_context_switches,
+        });
+    }
+
+    let switches = |count: fn(&ResourceSample) -> u64| match (samples.first(), samples.last()) {
+        (Some(first), Some(last)) => count(last).saturating_sub(count(first)),
+        _ => 0,
+    };
+    ResourceProfile {
+        pid,
+        failed_samples: failed,
+        cpu_cores: GaugeSummary::from_values(samples.iter().filter_map(|s| s.cpu_cores)),
+        rss_bytes: GaugeSummary::from_values(samples.iter().map(|s| s.rss_bytes as f64)),
+        open_fds: GaugeSummary::from_values(samples.iter().map(|s| s.open_fds as f64)),
+        threads: GaugeSummary::from_values(samples.iter().map(|s| s.threads as f64)),
+        voluntary_context_switches: switches(|s| s.voluntary_context_switches),
+        involuntary_context_switches: switches(|s| s.involuntary_context_switches),
+        perf_data: None,
+        flamegraph: None,
+        samples,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_with_an_awkward_command_name() {
+        let stat = "4242 (ultra rpc (main)) S 1 4242 4242 0 -1 4194560 1500 0 0 0 \
+                    250 50 0 0 20 0 12 0 100 1000000 2000 18446744073709551615";
+        assert_eq!(parse_stat(stat), Some((3.0, 12)));
+        assert_eq!(parse_stat("4242 (truncated"), None);
+    }
+
+    #[tokio::test]
+    async fn profiles_own_process() {
+        let options = ProfileOptions {
+            pid: std::process::id(),
+            interval: Duration::from_millis(10),
+            perf: None,
+        };
+        let profiler = Profiler::start(&options, 1).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let profile = profiler.finish().await.unwrap();
+
+        assert_eq!(profile.failed_samples, 0);
+        assert!(profile.samples.len() >= 3, "{profile:?}");
+        assert!(profile.rss_bytes.as_ref().is_some_and(|rss| rss.min > 0.0));
+        assert!(profile.open_fds.as_ref().is_some_and(|fds| fds.min >= 1.0));
+        assert!(profile
+            .threads
+            .as_ref()
+            .is_some_and(|threads| threads.min >= 1.0));
+        assert!(profile.cpu_cores.is_some());
+        assert!(read_rss(std::process::id()).is_some_and(|bytes| bytes > 0));
+    }
+}
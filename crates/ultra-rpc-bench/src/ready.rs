@@ -0,0 +1,189 @@
+// Numan Thabit 2025
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use humantime::format_duration;
+use tokio::{
+    process::Child,
+    time::{sleep, timeout, Instant},
+};
+use tracing::{debug, info};
+
+use crate::{
+    client::{is_success, ClientOptions, RpcClient},
+    workload::{encode_request, RpcCall},
+};
+
+/// Longest a single probe may take before it counts as a failure.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Bytes of an error response quoted when readiness times out.
+const QUOTED_RESPONSE_LEN: usize = 200;
+
+/// When the server counts as ready: `successes` probes in a row answered
+/// without a JSON-RPC error, each `interval` apart, within `timeout`.
+#[derive(Debug, Clone)]
+pub struct ReadinessProbe {
+    pub call: RpcCall,
+    pub successes: u32,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+/// Connects and probes until the server is ready, returning how long that
+/// took. Fails early if `server` exits first.
+pub async fn wait_until_ready(
+    options: &ClientOptions,
+    probe: &ReadinessProbe,
+    mut server: Option<&mut Child>,
+) -> Result<Duration> {
+    info!(
+        method = %probe.call.method,
+        successes = probe.successes,
+        timeout = %format_duration(probe.timeout),
+        endpoint = %options.endpoint,
+        "waiting for server readiness"
+    );
+    let body = encode_request(0, &probe.call.method, &probe.call.params);
+    let start = Instant::now();
+    let deadline = start + probe.timeout;
+    let mut client: Option<RpcClient> = None;
+    let mut streak = 0u32;
+    let mut attempts = 0u64;
+    let mut last_failure = "no probe completed".to_string();
+
+    loop {
+        if let Some(child) = server.as_deref_mut() {
+            if let Some(status) = child.try_wait()? {
+                bail!("server exited with {status} before becoming ready");
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            bail!(
+                "server not ready after {} ({attempts} probes): {last_failure}",
+                format_duration(probe.timeout)
+            );
+        }
+
+        attempts += 1;
+        let failure = match timeout(
+            remaining.min(PROBE_TIMEOUT),
+            probe_once(&mut client, options, &body),
+        )
+        .await
+        {
+            Ok(Ok(response)) if is_success(&response) => None,
+            Ok(Ok(response)) => {
+                let quoted = &response[..response.len().min(QUOTED_RESPONSE_LEN)];
+                Some(format!(
+                    "probe answered {}",
+                    String::from_utf8_lossy(quoted)
+                ))
+            }
+            Ok(Err(err)) => {
+                client = None;
+                Some(format!("{err:#}"))
+            }
+            Err(_) => {
+                client = None;
+                Some("probe timed out".to_string())
+            }
+        };
+
+        match failure {
+            None => {
+                streak += 1;
+                if streak >= probe.successes {
+                    if let Some(client) = client {
+                        client.close().await;
+                    }
+                    let elapsed = start.elapsed();
+                    info!(
+                        elapsed = %format_duration(elapsed),
+                        attempts,
+                        "server ready"
+                    );
+                    return Ok(elapsed);
+                }
+            }
+            Some(failure) => {
+                debug!(attempt = attempts, %failure, "readiness probe failed");
+                streak = 0;
+                last_failure = failure;
+            }
+        }
+        sleep(
+            probe
+                .interval
+                .min(deadline.saturating_duration_since(Instant::now())),
+        )
+        .await;
+    }
+}
+
+/// Sends one probe, connecting first when no connection is up.
+async fn probe_once(
+    client: &mut Option<RpcClient>,
+    options: &ClientOptions,
+    body: &[u8],
+) -> Result<Vec<u8>> {
+    let client = match client {
+        Some(client) => client,
+        None => client.insert(RpcClient::connect(options).await?),
+    };
+    client.call(body.to_vec()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::test_support::{http_options, http_stub};
+
+    fn probe(timeout: Duration) -> ReadinessProbe {
+        ReadinessProbe {
+            call: "getSlot".parse().unwrap(),
+            successes: 2,
+            interval: Duration::from_millis(10),
+            timeout,
+        }
+    }
+
+    #[tokio::test]
+    async fn waits_for_consecutive_successes() {
+        // Still hydrating for the first three probes
+        let addr = http_stub(|n| {
+            if n < 3 {
+                r#"{"jsonrpc":"2.0","id":0,"error":{"code":-32002,"message":"warming"}}"#
+            } else {
+                r#"{"jsonrpc":"2.0","id":0,"result":7}"#
+            }
+        })
+        .await;
+
+        let started = Instant::now();
+        wait_until_ready(&http_options(addr), &probe(Duration::from_secs(5)), None)
+            .await
+            .unwrap();
+        // Three failures and two successes, each an interval apart
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn gives_up_at_the_timeout() {
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let err = wait_until_ready(
+            &http_options(addr),
+            &probe(Duration::from_millis(100)),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("not ready after"), "{err:#}");
+    }
+}
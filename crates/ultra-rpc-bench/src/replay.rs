@@ -0,0 +1,231 @@
+// Numan Thabit 2025
+use std::{
+    fs::File,
+    io::{BufReader, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use faststreams::{frame_flags, CaptureReader};
+use serde::{Deserialize, Serialize};
+
+/// Longest single sleep while pacing, so a stop request is seen promptly.
+const MAX_PACING_SLEEP: Duration = Duration::from_millis(50);
+/// Write timeout on the ingest socket; a reader stalled this long fails the
+/// replay rather than hanging the run.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// faststreams capture, as recorded by `ys-consumer` with
+    /// `YS_OUTPUT=capture:<path>`.
+    pub capture: PathBuf,
+    /// Ingest Unix socket the frames are written to.
+    pub socket: PathBuf,
+    /// Multiplier over the recorded pace; 0 writes as fast as the socket
+    /// accepts.
+    pub speed: f64,
+}
+
+/// What the replay feeder wrote while an iteration ran.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ReplayReport {
+    pub capture: PathBuf,
+    pub speed: f64,
+    pub frames: u64,
+    pub bytes: u64,
+    /// Passes started over the capture; it restarts when it runs out before
+    /// the iteration does.
+    pub passes: u64,
+    /// Entries skipped because they do not start with a frame header.
+    pub invalid: u64,
+    /// Furthest the writes fell behind the recorded pace.
+    pub max_lag_ms: u64,
+}
+
+/// Writes a capture into the ingest socket on a dedicated thread while load
+/// runs, so reads are measured under write and publish pressure.
+pub struct Replayer {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<ReplayReport>>,
+}
+
+impl Replayer {
+    pub fn start(options: &ReplayOptions) -> Result<Self> {
+        if !(options.speed.is_finite() && options.speed >= 0.0) {
+            bail!("replay speed must be 0 or positive, got {}", options.speed);
+        }
+        // Fail before load starts when either end is unusable
+        open_capture(&options.capture)?;
+        let stream = UnixStream::connect(&options.socket).with_context(|| {
+            format!(
+                "failed to connect ingest socket {}",
+                options.socket.display()
+            )
+        })?;
+        stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .context("failed to set ingest socket write timeout")?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("capture-replay".to_string())
+            .spawn({
+                let options = options.clone();
+                let stop = stop.clone();
+                move || feed(&options, stream, &stop)
+            })
+            .context("failed to spawn capture replay thread")?;
+        Ok(Self { stop, thread })
+    }
+
+    pub fn finish(self) -> Result<ReplayReport> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("capture replay thread panicked"))?
+    }
+}
+
+fn open_capture(path: &Path) -> Result<CaptureReader<BufReader<File>>> {
+    let file =
+        File::open(path).with_context(|| format!("failed to open capture {}", path.display()))?;
+    CaptureReader::new(BufReader::with_capacity(1 << 20, file))
+        .with_context(|| format!("{} is not a faststreams capture", path.display()))
+}
+
+/// Replays the capture, restarting it, until `stop` is set.
+fn feed(
+    options: &ReplayOptions,
+    mut stream: UnixStream,
+    stop: &AtomicBool,
+) -> Result<ReplayReport> {
+    let mut report = ReplayReport {
+        capture: options.capture.clone(),
+        speed: options.speed,
+        ..ReplayReport::default()
+    };
+    let mut frame = Vec::with_capacity(64 * 1024);
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut reader = open_capture(&options.capture)?;
+        report.passes += 1;
+        let mut origin: Option<(u64, Instant)> = None;
+        let mut entries = 0u64;
+
+        while let Some(captured_at) = reader
+            .next_into(&mut frame)
+            .with_context(|| format!("failed to read capture {}", options.capture.display()))?
+        {
+            entries += 1;
+            if frame_flags(&frame).is_none() {
+                report.invalid += 1;
+                continue;
+            }
+            if options.speed > 0.0 {
+                let (first, start) = *origin.get_or_insert((captured_at, Instant::now()));
+                let due = start
+                    + Duration::from_nanos(captured_at.saturating_sub(first))
+                        .div_f64(options.speed);
+                loop {
+                    if stop.load(Ordering::Relaxed) {
+                        return Ok(report);
+                    }
+                    let now = Instant::now();
+                    if now >= due {
+                        let lag = now.duration_since(due).as_millis();
+                        report.max_lag_ms =
+                            report.max_lag_ms.max(lag.min(u128::from(u64::MAX)) as u64);
+                        break;
+                    }
+                    thread::sleep((due - now).min(MAX_PACING_SLEEP));
+                }
+            } else if stop.load(Ordering::Relaxed) {
+                return Ok(report);
+            }
+
+            stream.write_all(&frame).with_context(|| {
+                format!(
+                    "failed to write to ingest socket {}",
+                    options.socket.display()
+                )
+            })?;
+            report.frames += 1;
+            report.bytes += frame.len() as u64;
+        }
+
+        if entries == 0 {
+            bail!("capture {} holds no frames", options.capture.display());
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, os::unix::net::UnixListener};
+
+    use faststreams::{encode_record, CaptureWriter, Record};
+
+    use super::*;
+
+    #[test]
+    fn replays_a_capture_into_the_socket() {
+        let dir =
+            std::env::temp_dir().join(format!("ultra-rpc-bench-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let capture = dir.join("capture.fscap");
+        let socket = dir.join("ingest.sock");
+        let _ = std::fs::remove_file(&socket);
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for slot in 0..3u64 {
+            let frame = encode_record(&Record::Slot {
+                slot,
+                parent: slot.checked_sub(1),
+                status: 0,
+            })
+            .unwrap();
+            writer.append(1_000_000 * slot, &frame).unwrap();
+        }
+        std::fs::write(&capture, writer.into_inner()).unwrap();
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let reader = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            conn.read_to_end(&mut received).unwrap();
+            received.len() as u64
+        });
+
+        let options = ReplayOptions {
+            capture: capture.clone(),
+            socket: socket.clone(),
+            speed: 1.0,
+        };
+        let replayer = Replayer::start(&options).unwrap();
+        thread::sleep(Duration::from_millis(30));
+        let report = replayer.finish().unwrap();
+        let received = reader.join().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // 3 frames spread over 2ms, so at least one full pass in 30ms
+        assert!(report.passes >= 1, "{report:?}");
+        assert!(report.frames >= 3, "{report:?}");
+        assert_eq!(report.invalid, 0);
+        assert_eq!(received, report.bytes);
+
+        let bad = ReplayOptions {
+            speed: -1.0,
+            ..options
+        };
+        assert!(Replayer::start(&bad).is_err());
+    }
+}
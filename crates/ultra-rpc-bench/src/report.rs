@@ -0,0 +1,260 @@
+// Numan Thabit 2025
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::BufWriter,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::TransportKind,
+    latency::{self, LatencySummary},
+    load::{Arrival, LoadPlan, MethodStats, RunStats},
+    profile::ResourceProfile,
+    replay::ReplayReport,
+    server_metrics::ServerMetrics,
+    soak::SoakReport,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IterationReport {
+    pub iteration: u32,
+    pub duration_ms: u64,
+    pub transport: TransportKind,
+    pub arrival: Arrival,
+    pub target_rate: f64,
+    /// Answered requests per second over the iteration.
+    pub achieved_rate: f64,
+    pub total: MethodReport,
+    pub methods: BTreeMap<String, MethodReport>,
+    /// Server-side metrics scraped while the iteration ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<ServerMetrics>,
+    /// Capture frames written into the ingest socket during the iteration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay: Option<ReplayReport>,
+    /// Rolling checkpoints and drift of a soak run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soak: Option<SoakReport>,
+    /// `/proc` samples (and perf output) of the launched server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<ResourceProfile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MethodReport {
+    pub requests: u64,
+    pub ok: u64,
+    pub rpc_errors: u64,
+    pub failures: u64,
+    pub timeouts: u64,
+    pub dropped: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency: Option<LatencySummary>,
+}
+
+impl MethodReport {
+    pub fn new(stats: &MethodStats) -> Result<Self> {
+        Ok(Self {
+            requests: stats.requests,
+            ok: stats.ok,
+            rpc_errors: stats.rpc_errors,
+            failures: stats.failures,
+            timeouts: stats.timeouts,
+            dropped: stats.dropped,
+            latency: LatencySummary::from_histogram(&stats.latency)?,
+        })
+    }
+}
+
+/// Stats per method name; calls sharing a method are reported together.
+pub fn group_by_method(labels: &[String], stats: &RunStats) -> BTreeMap<String, MethodStats> {
+    let mut methods: BTreeMap<String, MethodStats> = BTreeMap::new();
+    for (label, method) in labels.iter().zip(&stats.methods) {
+        methods.entry(label.clone()).or_default().merge(method);
+    }
+    methods
+}
+
+impl IterationReport {
+    pub fn new(
+        iteration: u32,
+        transport: TransportKind,
+        plan: &LoadPlan,
+        total: &MethodStats,
+        methods: &BTreeMap<String, MethodStats>,
+        elapsed: Duration,
+    ) -> Result<Self> {
+        let seconds = elapsed.as_secs_f64();
+        let answered = total.ok + total.rpc_errors;
+        Ok(Self {
+            iteration,
+            duration_ms: elapsed.as_millis().min(u128::from(u64::MAX)) as u64,
+            transport,
+            arrival: plan.arrival,
+            target_rate: plan.rate,
+            achieved_rate: if seconds > 0.0 {
+                answered as f64 / seconds
+            } else {
+                0.0
+            },
+            total: MethodReport::new(total)?,
+            methods: methods
+                .iter()
+                .map(|(label, stats)| Ok((label.clone(), MethodReport::new(stats)?)))
+                .collect::<Result<_>>()?,
+            server: None,
+            replay: None,
+            soak: None,
+            resources: None,
+        })
+    }
+}
+
+pub fn write_histograms(
+    dir: &Path,
+    iteration: u32,
+    total: &MethodStats,
+    methods: &BTreeMap<String, MethodStats>,
+) -> Result<()> {
+    let histograms = std::iter::once(("total", total))
+        .chain(methods.iter().map(|(label, stats)| (label.as_str(), stats)));
+    for (label, stats) in histograms {
+        if stats.latency.is_empty() {
+            continue;
+        }
+        let path = dir.join(format!("iteration-{iteration}-{label}.hgrm"));
+        latency::write_distribution(&path, &stats.latency)?;
+    }
+    Ok(())
+}
+
+pub fn write_reports(path: &Path, reports: &[IterationReport]) -> Result<()> {
+    write_json(path, reports)
+}
+
+pub fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create output directory {}", dir.display()))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    create_parent_dir(path)?;
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .with_context(|| format!("failed to open output path {}", path.display()))?;
+    let writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(writer, value)
+        .with_context(|| format!("failed to write results to {}", path.display()))?;
+    Ok(())
+}
+
+pub fn read_reports(path: &Path) -> Result<Vec<IterationReport>> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to open report {}", path.display()))?;
+    serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("failed to parse report {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workload::Workload;
+
+    fn plan() -> LoadPlan {
+        LoadPlan {
+            rate: 100.0,
+            duration: Duration::from_secs(2),
+            arrival: Arrival::Constant,
+            max_in_flight: 16,
+            request_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn groups_calls_by_method() {
+        let workload = Workload::new(vec![
+            "getSlot".parse().unwrap(),
+            r#"getBalance=["11111111111111111111111111111111"]"#
+                .parse()
+                .unwrap(),
+            r#"getBalance=["Vote111111111111111111111111111111111111111"]"#
+                .parse()
+                .unwrap(),
+        ])
+        .unwrap();
+        let sample = |ok: u64, latency_ns: u64| {
+            let mut stats = MethodStats {
+                requests: ok,
+                ok,
+                ..MethodStats::default()
+            };
+            stats.latency.record_n(latency_ns, ok).unwrap();
+            stats
+        };
+        let stats = RunStats {
+            elapsed: Duration::from_secs(2),
+            methods: vec![sample(10, 1_000), sample(4, 3_000), sample(6, 5_000)],
+        };
+
+        let methods = group_by_method(&workload.labels(), &stats);
+        let report = IterationReport::new(
+            1,
+            TransportKind::Quic,
+            &plan(),
+            &stats.total(),
+            &methods,
+            stats.elapsed,
+        )
+        .unwrap();
+        assert_eq!(report.total.requests, 20);
+        assert_eq!(report.achieved_rate, 10.0);
+        assert_eq!(report.methods.len(), 2);
+        let balance = &report.methods["getBalance"];
+        assert_eq!(balance.requests, 10);
+        let latency = balance.latency.as_ref().unwrap();
+        assert_eq!(latency.count, 10);
+        assert!((4_990..=5_010).contains(&latency.max_ns));
+    }
+
+    #[test]
+    fn reports_round_trip_through_json() {
+        let mut stats = MethodStats {
+            requests: 3,
+            ok: 3,
+            ..MethodStats::default()
+        };
+        stats.latency.record_n(2_000, 3).unwrap();
+        let methods = BTreeMap::from([("getSlot".to_string(), stats.clone())]);
+        let report = IterationReport::new(
+            1,
+            TransportKind::Http,
+            &plan(),
+            &stats,
+            &methods,
+            Duration::from_secs(1),
+        )
+        .unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("ultra-rpc-bench-{}.json", std::process::id()));
+        write_reports(&path, std::slice::from_ref(&report)).unwrap();
+        let read = read_reports(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].transport, TransportKind::Http);
+        assert_eq!(read[0].total.latency, report.total.latency);
+    }
+}
@@ -0,0 +1,448 @@
+// Numan Thabit 2025
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::oneshot,
+    task::JoinHandle,
+    time::{interval, Instant, MissedTickBehavior},
+};
+use tracing::debug;
+
+use crate::client::http_url;
+
+/// Counter of CPU seconds the server process has consumed.
+const CPU_SECONDS: &str = "process_cpu_seconds_total";
+/// Gauge of the server's resident set size.
+const RESIDENT_MEMORY: &str = "process_resident_memory_bytes";
+/// Gauge name suffixes reported as queue depths.
+const QUEUE_SUFFIXES: [&str; 3] = ["_queue_depth", "_backlog_depth", "_queue_len"];
+/// Counter name suffixes of cache hits, each paired with its miss counter.
+const CACHE_COUNTERS: [(&str, &str); 2] = [("_hits_total", "_misses_total"), ("_hits", "_misses")];
+
+/// A series identity: metric name and its rendered label set.
+type SeriesKey = (String, String);
+
+/// One scrape of the exposition, keyed by series.
+#[derive(Debug, Clone, Default)]
+struct Scrape {
+    elapsed: Duration,
+    values: BTreeMap<SeriesKey, f64>,
+}
+
+impl Scrape {
+    fn get(&self, name: &str, labels: &str) -> Option<f64> {
+        self.values
+            .get(&(name.to_string(), labels.to_string()))
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct GaugeSummary {
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+}
+
+impl GaugeSummary {
+    pub fn from_values(values: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let (mut min, mut max, mut sum, mut count) = (f64::INFINITY, f64::NEG_INFINITY, 0.0, 0);
+        for value in values {
+            min = min.min(value);
+            max = max.max(value);
+            sum += value;
+            count += 1;
+        }
+        (count > 0).then(|| Self {
+            min,
+            mean: sum / count as f64,
+            max,
+        })
+    }
+}
+
+/// Server-side readings at one scrape. Rates and ratios cover the time
+/// since the previous scrape, so the first sample has none.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServerSample {
+    pub elapsed_ms: u64,
+    /// CPU cores the server kept busy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resident_memory_bytes: Option<f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub queue_depths: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cache_hit_ratios: BTreeMap<String, f64>,
+    /// Series requested with `--metrics-series`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub series: BTreeMap<String, f64>,
+}
+
+/// What the server's Prometheus endpoint reported during one iteration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ServerMetrics {
+    pub endpoint: String,
+    pub scrapes: u64,
+    pub failed_scrapes: u64,
+    /// Hit ratio of each cache over the whole iteration.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cache_hit_ratios: BTreeMap<String, f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub queue_depths: BTreeMap<String, GaugeSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_cores: Option<GaugeSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resident_memory_bytes: Option<GaugeSummary>,
+    pub samples: Vec<ServerSample>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScrapeOptions {
+    /// `host:port` or a full URL; `/metrics` is appended when no path is
+    /// given.
+    pub endpoint: String,
+    pub interval: Duration,
+    /// Extra series names recorded verbatim in every sample.
+    pub series: Vec<String>,
+}
+
+impl ScrapeOptions {
+    fn url(&self) -> String {
+        let url = http_url(&self.endpoint);
+        let authority = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+        if authority.contains('/') {
+            url
+        } else {
+            format!("{url}/metrics")
+        }
+    }
+}
+
+/// Scrapes the server's metrics in the background while load runs.
+pub struct Scraper {
+    options: ScrapeOptions,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<(Vec<Scrape>, u64)>,
+}
+
+impl Scraper {
+    pub fn start(options: &ScrapeOptions) -> Result<Self> {
+        if options.interval.is_zero() {
+            bail!("metrics scrape interval must be positive");
+        }
+        let client = reqwest::Client::builder()
+            .timeout(options.interval.max(Duration::from_secs(1)))
+            .build()
+            .context("failed to build metrics http client")?;
+        let url = options.url();
+        let period = options.interval;
+        let (stop, mut stopped) = oneshot::channel();
+
+        let task = tokio::spawn(async move {
+            let start = Instant::now();
+            let mut ticks = interval(period);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut scrapes = Vec::new();
+            let mut failed = 0u64;
+            loop {
+                let last = tokio::select! {
+                    _ = ticks.tick() => false,
+                    _ = &mut stopped => true,
+                };
+                // A final scrape makes the deltas span the whole run
+                match scrape(&client, &url).await {
+                    Ok(values) => scrapes.push(Scrape {
+                        elapsed: start.elapsed(),
+                        values,
+                    }),
+                    Err(err) => {
+                        debug!(%err, url = %url, "metrics scrape failed");
+                        failed += 1;
+                    }
+                }
+                if last {
+                    break;
+                }
+            }
+            (scrapes, failed)
+        });
+
+        Ok(Self {
+            options: options.clone(),
+            stop,
+            task,
+        })
+    }
+
+    pub async fn finish(self) -> Result<ServerMetrics> {
+        let _ = self.stop.send(());
+        let (scrapes, failed) = self.task.await.context("metrics scraper panicked")?;
+        Ok(summarize(&self.options, &scrapes, failed))
+    }
+}
+
+async fn scrape(client: &reqwest::Client, url: &str) -> Result<BTreeMap<SeriesKey, f64>> {
+    let body = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    parse_exposition(&body)
+}
+
+/// Parses the Prometheus text exposition format, ignoring comments and
+/// timestamps.
+fn parse_exposition(text: &str) -> Result<BTreeMap<SeriesKey, f64>> {
+    let mut values = BTreeMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, labels, rest) =
+            parse_series(line).with_context(|| format!("malformed metrics line {line:?}"))?;
+        let value = rest
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| anyhow!("metrics line {line:?} has no value"))?;
+        let value: f64 = value
+            .parse()
+            .with_context(|| format!("metrics line {line:?} has an invalid value"))?;
+        values.insert((name.to_string(), labels), value);
+    }
+    Ok(values)
+}
+
+/// Splits a sample line into its name, labels rendered in sorted order and
+/// the remainder holding the value.
+fn parse_series(line: &str) -> Result<(&str, String, &str)> {
+    let end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(|| anyhow!("missing value"))?;
+    let name = &line[..end];
+    if !line[end..].starts_with('{') {
+        return Ok((name, String::new(), &line[end..]));
+    }
+
+    let mut labels = BTreeMap::new();
+    let mut rest = &line[end + 1..];
+    loop {
+        rest = rest.trim_start_matches([',', ' ']);
+        if let Some(after) = rest.strip_prefix('}') {
+            rest = after;
+            break;
+        }
+        let (key, after) = rest
+            .split_once('=')
+            .ok_or_else(|| anyhow!("label without a value"))?;
+        let after = after
+            .strip_prefix('"')
+            .ok_or_else(|| anyhow!("label value is not quoted"))?;
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let close = loop {
+            match chars.next() {
+                Some((i, '"')) => break i,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => bail!("unterminated label value"),
+                },
+                Some((_, c)) => value.push(c),
+                None => bail!("unterminated label value"),
+            }
+        };
+        labels.insert(key.trim().to_string(), value);
+        rest = &after[close + 1..];
+    }
+
+    let rendered = labels
+        .iter()
+        .map(|(key, value)| format!("{key}={value:?}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok((name, rendered, rest))
+}
+
+fn display_name(name: &str, labels: &str) -> String {
+    if labels.is_empty() {
+        name.to_string()
+    } else {
+        format!("{name}{{{labels}}}")
+    }
+}
+
+/// Hit ratio of every cache whose hit and miss counters moved between two
+/// scrapes.
+fn cache_hit_ratios(before: &Scrape, after: &Scrape) -> BTreeMap<String, f64> {
+    let mut ratios = BTreeMap::new();
+    for ((name, labels), &hits) in &after.values {
+        for (hit_suffix, miss_suffix) in CACHE_COUNTERS {
+            let Some(cache) = name.strip_suffix(hit_suffix) else {
+                continue;
+            };
+            let Some(misses) = after.get(&format!("{cache}{miss_suffix}"), labels) else {
+                continue;
+            };
+            let hits = hits - before.get(name, labels).unwrap_or(0.0);
+            let misses = misses
+                - before
+                    .get(&format!("{cache}{miss_suffix}"), labels)
+                    .unwrap_or(0.0);
+            if hits + misses > 0.0 {
+                ratios.insert(display_name(cache, labels), hits / (hits + misses));
+            }
+            break;
+        }
+    }
+    ratios
+}
+
+fn summarize(options: &ScrapeOptions, scrapes: &[Scrape], failed: u64) -> ServerMetrics {
+    let mut samples = Vec::with_capacity(scrapes.len());
+    for (index, scrape) in scrapes.iter().enumerate() {
+        let previous = index.checked_sub(1).map(|prev| &scrapes[prev]);
+        let cpu_cores = previous.and_then(|previous| {
+            let seconds = scrape.elapsed.checked_sub(previous.elapsed)?.as_secs_f64();
+            let busy = scrape.get(CPU_SECONDS, "")? - previous.get(CPU_SECONDS, "")?;
+            (seconds > 0.0).then(|| busy / seconds)
+        });
+        let queue_depths = scrape
+            .values
+            .iter()
+            .filter(|((name, _), _)| QUEUE_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)))
+            .map(|((name, labels), &value)| (display_name(name, labels), value))
+            .collect();
+        let series = scrape
+            .values
+            .iter()
+            .filter(|((name, _), _)| options.series.contains(name))
+            .map(|((name, labels), &value)| (display_name(name, labels), value))
+            .collect();
+        samples.push(ServerSample {
+            elapsed_ms: scrape.elapsed.as_millis().min(u128::from(u64::MAX)) as u64,
+            cpu_cores,
+            resident_memory_bytes: scrape.get(RESIDENT_MEMORY, ""),
+            queue_depths,
+            cache_hit_ratios: previous
+                .map(|previous| cache_hit_ratios(previous, scrape))
+                .unwrap_or_default(),
+            series,
+        });
+    }
+
+    let mut queues: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for sample in &samples {
+        for (queue, &depth) in &sample.queue_depths {
+            queues.entry(queue.clone()).or_default().push(depth);
+        }
+    }
+
+    ServerMetrics {
+        endpoint: options.url(),
+        scrapes: scrapes.len() as u64,
+        failed_scrapes: failed,
+        cache_hit_ratios: match (scrapes.first(), scrapes.last()) {
+            (Some(first), Some(last)) if scrapes.len() > 1 => cache_hit_ratios(first, last),
+            _ => BTreeMap::new(),
+        },
+        queue_depths: queues
+            .into_iter()
+            .filter_map(|(queue, depths)| Some((queue, GaugeSummary::from_values(depths)?)))
+            .collect(),
+        cpu_cores: GaugeSummary::from_values(samples.iter().filter_map(|s| s.cpu_cores)),
+        resident_memory_bytes: GaugeSummary::from_values(
+            samples.iter().filter_map(|s| s.resident_memory_bytes),
+        ),
+        samples,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSITION: &str = r#"
+# HELP process_cpu_seconds_total Total user and system CPU time spent in seconds.
+# TYPE process_cpu_seconds_total counter
+process_cpu_seconds_total 12.5
+process_resident_memory_bytes 1.048576e+08
+ingest_delta_queue_depth 3
+account_cache_hits_total{shard="0"} 90
+account_cache_misses_total{shard="0"} 10
+rpc_requests_total{method="getSlot",note="a \"quoted\", value"} 7 1700000000000
+"#;
+
+    fn options() -> ScrapeOptions {
+        ScrapeOptions {
+            endpoint: "127.0.0.1:9898".to_string(),
+            interval: Duration::from_secs(1),
+            series: vec!["rpc_requests_total".to_string()],
+        }
+    }
+
+    #[test]
+    fn parses_the_text_exposition() {
+        let values = parse_exposition(EXPOSITION).unwrap();
+        assert_eq!(values.len(), 6);
+        assert_eq!(
+            values[&(RESIDENT_MEMORY.to_string(), String::new())],
+            104_857_600.0
+        );
+        let labels = r#"method="getSlot",note="a \"quoted\", value""#;
+        assert_eq!(
+            values[&("rpc_requests_total".to_string(), labels.to_string())],
+            7.0
+        );
+        assert!(parse_exposition("broken{label=1} 2").is_err());
+
+        assert_eq!(options().url(), "http://127.0.0.1:9898/metrics");
+        let mut custom = options();
+        custom.endpoint = "http://node:9000/prom".to_string();
+        assert_eq!(custom.url(), "http://node:9000/prom");
+    }
+
+    #[test]
+    fn derives_rates_and_ratios_between_scrapes() {
+        let first = Scrape {
+            elapsed: Duration::ZERO,
+            values: parse_exposition(EXPOSITION).unwrap(),
+        };
+        let second = Scrape {
+            elapsed: Duration::from_secs(2),
+            values: parse_exposition(
+                &EXPOSITION
+                    .replace("12.5", "15.5")
+                    .replace("depth 3", "depth 9")
+                    .replace("} 90", "} 140")
+                    .replace("} 10", "} 60"),
+            )
+            .unwrap(),
+        };
+
+        let metrics = summarize(&options(), &[first, second], 1);
+        assert_eq!((metrics.scrapes, metrics.failed_scrapes), (2, 1));
+        assert_eq!(metrics.samples[0].cpu_cores, None);
+        assert_eq!(metrics.samples[1].cpu_cores, Some(1.5));
+        assert_eq!(
+            metrics.samples[1].cache_hit_ratios[r#"account_cache{shard="0"}"#],
+            0.5
+        );
+        assert_eq!(metrics.cache_hit_ratios[r#"account_cache{shard="0"}"#], 0.5);
+        assert_eq!(
+            metrics.queue_depths["ingest_delta_queue_depth"],
+            GaugeSummary {
+                min: 3.0,
+                mean: 6.0,
+                max: 9.0
+            }
+        );
+        assert_eq!(metrics.resident_memory_bytes.unwrap().max, 104_857_600.0);
+        assert_eq!(metrics.samples[1].series.len(), 1);
+    }
+}
@@ -0,0 +1,372 @@
+// Numan Thabit 2025
+use std::time::Duration;
+
+use anyhow::Result;
+use humantime::format_duration;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{
+    client::RpcClient,
+    compare::{median, percent_change},
+    load::{self, LoadPlan, RunStats},
+    profile::read_rss,
+    workload::Workload,
+};
+
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    pub checkpoint: Duration,
+    /// Leading checkpoints whose medians later ones are measured against.
+    pub baseline_checkpoints: usize,
+    /// Largest tolerated p99 latency rise over the baseline, in percent.
+    pub max_latency_creep: f64,
+    /// Largest tolerated server RSS growth over the baseline, in percent.
+    pub max_rss_growth: f64,
+    /// Largest tolerated throughput drop below the baseline, in percent.
+    pub max_throughput_drop: f64,
+    /// Launched server process whose RSS is sampled at every checkpoint.
+    pub server_pid: Option<u32>,
+}
+
+/// Throughput and latency of one checkpoint window.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Checkpoint {
+    pub index: u32,
+    /// Time since the soak started, at the end of the window.
+    pub elapsed_ms: u64,
+    pub window_ms: u64,
+    pub requests: u64,
+    pub errors: u64,
+    pub dropped: u64,
+    pub achieved_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_ns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_ns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p999_ns: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_rss_bytes: Option<u64>,
+    /// Whether the window drifted past a limit against the baseline.
+    pub degraded: bool,
+}
+
+impl Checkpoint {
+    fn new(index: u32, elapsed: Duration, window: &RunStats, rss: Option<u64>) -> Self {
+        let total = window.total();
+        let seconds = window.elapsed.as_secs_f64();
+        let percentile = |target: f64| {
+            (!total.latency.is_empty()).then(|| total.latency.value_at_percentile(target))
+        };
+        Self {
+            index,
+            elapsed_ms: millis(elapsed),
+            window_ms: millis(window.elapsed),
+            requests: total.requests,
+            errors: total.rpc_errors + total.failures + total.timeouts,
+            dropped: total.dropped,
+            achieved_rate: if seconds > 0.0 {
+                (total.ok + total.rpc_errors) as f64 / seconds
+            } else {
+                0.0
+            },
+            p50_ns: percentile(50.0),
+            p99_ns: percentile(99.0),
+            p999_ns: percentile(99.9),
+            server_rss_bytes: rss,
+            degraded: false,
+        }
+    }
+}
+
+/// How the late checkpoints moved against the baseline ones.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Drift {
+    pub throughput_change_pct: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_change_pct: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_growth_pct: Option<f64>,
+    /// Least-squares RSS trend over every checkpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rss_slope_bytes_per_hour: Option<f64>,
+}
+
+impl Drift {
+    fn between(baseline: &Level, current: &Level) -> Self {
+        let change =
+            |before: Option<f64>, after: Option<f64>| Some(percent_change(before?, after?));
+        Self {
+            throughput_change_pct: percent_change(baseline.rate, current.rate),
+            p99_change_pct: change(baseline.p99_ns, current.p99_ns),
+            rss_growth_pct: change(baseline.rss_bytes, current.rss_bytes),
+            rss_slope_bytes_per_hour: None,
+        }
+    }
+
+    fn violations(&self, options: &SoakOptions) -> Vec<String> {
+        let mut violations = Vec::new();
+        if self.throughput_change_pct < -options.max_throughput_drop {
+            violations.push(format!(
+                "throughput changed {:.1}% (limit -{}%)",
+                self.throughput_change_pct, options.max_throughput_drop
+            ));
+        }
+        if let Some(creep) = self
+            .p99_change_pct
+            .filter(|&c| c > options.max_latency_creep)
+        {
+            violations.push(format!(
+                "p99 latency crept {creep:.1}% (limit {}%)",
+                options.max_latency_creep
+            ));
+        }
+        if let Some(growth) = self.rss_growth_pct.filter(|&g| g > options.max_rss_growth) {
+            violations.push(format!(
+                "server RSS grew {growth:.1}% (limit {}%)",
+                options.max_rss_growth
+            ));
+        }
+        violations
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SoakReport {
+    pub checkpoint_ms: u64,
+    pub baseline_checkpoints: usize,
+    pub checkpoints: Vec<Checkpoint>,
+    /// Last checkpoints against the baseline; absent when the soak was too
+    /// short to hold both.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drift: Option<Drift>,
+    /// Limits the drift exceeded.
+    pub degraded: Vec<String>,
+}
+
+impl SoakReport {
+    fn new(options: &SoakOptions, checkpoints: Vec<Checkpoint>) -> Self {
+        let window = options.baseline_checkpoints;
+        let drift = (window > 0 && checkpoints.len() >= window * 2).then(|| {
+            let baseline = Level::of(&checkpoints[..window]);
+            let current = Level::of(&checkpoints[checkpoints.len() - window..]);
+            Drift {
+                rss_slope_bytes_per_hour: rss_slope(&checkpoints),
+                ..Drift::between(&baseline, &current)
+            }
+        });
+        Self {
+            checkpoint_ms: millis(options.checkpoint),
+            baseline_checkpoints: window,
+            degraded: drift
+                .as_ref()
+                .map(|drift| drift.violations(options))
+                .unwrap_or_default(),
+            drift,
+            checkpoints,
+        }
+    }
+}
+
+/// Medians over a run of checkpoints.
+struct Level {
+    rate: f64,
+    p99_ns: Option<f64>,
+    rss_bytes: Option<f64>,
+}
+
+impl Level {
+    fn of(checkpoints: &[Checkpoint]) -> Self {
+        Self {
+            rate: median(checkpoints.iter().map(|c| c.achieved_rate)).unwrap_or(0.0),
+            p99_ns: median(
+                checkpoints
+                    .iter()
+                    .filter_map(|c| c.p99_ns)
+                    .map(|ns| ns as f64),
+            ),
+            rss_bytes: median(
+                checkpoints
+                    .iter()
+                    .filter_map(|c| c.server_rss_bytes)
+                    .map(|bytes| bytes as f64),
+            ),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().min(u128::from(u64::MAX)) as u64
+}
+
+/// Least-squares slope of server RSS against time.
+fn rss_slope(checkpoints: &[Checkpoint]) -> Option<f64> {
+    let points: Vec<(f64, f64)> = checkpoints
+        .iter()
+        .filter_map(|c| {
+            let hours = c.elapsed_ms as f64 / 3_600_000.0;
+            Some((hours, c.server_rss_bytes? as f64))
+        })
+        .collect();
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    (variance > 0.0).then(|| covariance / variance)
+}
+
+/// Runs one long load, logging a checkpoint every window and flagging
+/// windows that drift from the baseline as they arrive.
+pub async fn run(
+    client: &RpcClient,
+    workload: &Workload,
+    plan: &LoadPlan,
+    options: &SoakOptions,
+) -> Result<(RunStats, SoakReport)> {
+    info!(
+        duration = %format_duration(plan.duration),
+        checkpoint = %format_duration(options.checkpoint),
+        "starting soak"
+    );
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let load = load::run_with_checkpoints(client, workload, plan, options.checkpoint, tx);
+    let watch = async {
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        let mut baseline: Option<Level> = None;
+        let mut elapsed = Duration::ZERO;
+        while let Some(window) = rx.recv().await {
+            elapsed += window.elapsed;
+            let rss = options.server_pid.and_then(read_rss);
+            let mut checkpoint =
+                Checkpoint::new(checkpoints.len() as u32 + 1, elapsed, &window, rss);
+            if let Some(baseline) = &baseline {
+                let violations =
+                    Drift::between(baseline, &Level::of(std::slice::from_ref(&checkpoint)))
+                        .violations(options);
+                if !violations.is_empty() {
+                    checkpoint.degraded = true;
+                    warn!(
+                        checkpoint = checkpoint.index,
+                        ?violations,
+                        "soak checkpoint drifted from baseline"
+                    );
+                }
+            }
+            log_checkpoint(&checkpoint);
+            checkpoints.push(checkpoint);
+            if baseline.is_none() && checkpoints.len() == options.baseline_checkpoints {
+                baseline = Some(Level::of(&checkpoints));
+                info!(checkpoints = checkpoints.len(), "soak baseline established");
+            }
+        }
+        checkpoints
+    };
+
+    let (stats, checkpoints) = tokio::join!(load, watch);
+    let report = SoakReport::new(options, checkpoints);
+    if report.drift.is_none() {
+        warn!(
+            checkpoints = report.checkpoints.len(),
+            needed = options.baseline_checkpoints * 2,
+            "soak too short to measure drift"
+        );
+    }
+    Ok((stats?, report))
+}
+
+fn log_checkpoint(checkpoint: &Checkpoint) {
+    let latency = |ns: Option<u64>| {
+        ns.map(|ns| format_duration(Duration::from_nanos(ns)).to_string())
+            .unwrap_or_else(|| "<n/a>".to_string())
+    };
+    info!(
+        checkpoint = checkpoint.index,
+        elapsed = %format_duration(Duration::from_millis(checkpoint.elapsed_ms)),
+        achieved_rate = checkpoint.achieved_rate,
+        errors = checkpoint.errors,
+        dropped = checkpoint.dropped,
+        p50_latency = %latency(checkpoint.p50_ns),
+        p99_latency = %latency(checkpoint.p99_ns),
+        p999_latency = %latency(checkpoint.p999_ns),
+        server_rss_bytes = ?checkpoint.server_rss_bytes,
+        "soak checkpoint"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> SoakOptions {
+        SoakOptions {
+            checkpoint: Duration::from_secs(60),
+            baseline_checkpoints: 2,
+            max_latency_creep: 20.0,
+            max_rss_growth: 10.0,
+            max_throughput_drop: 5.0,
+            server_pid: None,
+        }
+    }
+
+    fn checkpoint(minute: u64, rate: f64, p99_ns: u64, rss_bytes: u64) -> Checkpoint {
+        Checkpoint {
+            index: minute as u32,
+            elapsed_ms: minute * 60_000,
+            window_ms: 60_000,
+            requests: (rate * 60.0) as u64,
+            errors: 0,
+            dropped: 0,
+            achieved_rate: rate,
+            p50_ns: Some(p99_ns / 2),
+            p99_ns: Some(p99_ns),
+            p999_ns: Some(p99_ns * 2),
+            server_rss_bytes: Some(rss_bytes),
+            degraded: false,
+        }
+    }
+
+    #[test]
+    fn steady_soak_has_no_drift() {
+        let checkpoints = (1..=6)
+            .map(|minute| checkpoint(minute, 1_000.0, 2_000_000, 100 << 20))
+            .collect();
+        let report = SoakReport::new(&options(), checkpoints);
+        let drift = report.drift.expect("enough checkpoints");
+        assert_eq!(drift.p99_change_pct, Some(0.0));
+        assert_eq!(drift.rss_slope_bytes_per_hour, Some(0.0));
+        assert!(report.degraded.is_empty());
+    }
+
+    #[test]
+    fn flags_latency_creep_and_rss_growth() {
+        // RSS grows 1 MiB a minute and p99 climbs from 2ms to 3ms
+        let checkpoints = (1..=6)
+            .map(|minute| {
+                checkpoint(
+                    minute,
+                    1_000.0,
+                    2_000_000 + (minute - 1) * 200_000,
+                    (100 + minute) << 20,
+                )
+            })
+            .collect();
+        let report = SoakReport::new(&options(), checkpoints);
+        let drift = report.drift.as_ref().unwrap();
+        let slope = drift.rss_slope_bytes_per_hour.unwrap();
+        assert!((slope - 60.0 * 1_048_576.0).abs() < 1.0, "slope {slope}");
+        assert_eq!(report.degraded.len(), 1, "{:?}", report.degraded);
+        assert!(report.degraded[0].starts_with("p99 latency crept"));
+
+        let short = SoakReport::new(&options(), report.checkpoints[..3].to_vec());
+        assert!(short.drift.is_none() && short.degraded.is_empty());
+    }
+}
@@ -0,0 +1,48 @@
+// Numan Thabit 2025
+//! Fixtures shared by the unit tests.
+
+use std::net::SocketAddr;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use crate::client::{ClientOptions, TransportKind};
+
+/// A JSON-RPC over HTTP stub answering the `n`th request (from 0) with
+/// `body(n)`, one request per connection.
+pub async fn http_stub<F>(body: F) -> SocketAddr
+where
+    F: Fn(usize) -> &'static str + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut served = 0;
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let body = body(served);
+            served += 1;
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    addr
+}
+
+pub fn http_options(addr: SocketAddr) -> ClientOptions {
+    ClientOptions {
+        transport: TransportKind::Http,
+        endpoint: addr.to_string(),
+        connections: 1,
+        ca_cert: None,
+        server_name: None,
+    }
+}
@@ -0,0 +1,529 @@
+// Numan Thabit 2025
+use std::{collections::HashMap, fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Context, Result};
+use rand::{
+    distributions::{Distribution, WeightedIndex},
+    Rng,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Params string replaced by one key drawn from the entry's key set.
+const KEY_PLACEHOLDER: &str = "$key";
+/// Params string replaced by an array of `batch` keys.
+const KEYS_PLACEHOLDER: &str = "$keys";
+
+/// One JSON-RPC method and the params sent with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcCall {
+    pub method: String,
+    pub params: Value,
+}
+
+impl FromStr for RpcCall {
+    type Err = anyhow::Error;
+
+    /// Parses `METHOD` or `METHOD=PARAMS`, where `PARAMS` is a JSON array.
+    fn from_str(spec: &str) -> Result<Self> {
+        let (method, params) = match spec.split_once('=') {
+            Some((method, params)) => {
+                let params: Value = serde_json::from_str(params)
+                    .with_context(|| format!("invalid params for rpc call '{spec}'"))?;
+                if !params.is_array() {
+                    bail!("params for rpc call '{spec}' must be a JSON array");
+                }
+                (method, params)
+            }
+            None => (spec, json!([])),
+        };
+        let method = method.trim();
+        if method.is_empty() {
+            bail!("rpc call '{spec}' is missing a method");
+        }
+        Ok(Self {
+            method: method.to_string(),
+            params,
+        })
+    }
+}
+
+/// A declarative mix of requests, loaded from TOML.
+///
+/// ```toml
+/// seed = 7
+///
+/// [keys.hot]
+/// file = "hot-accounts.txt"
+/// distribution = "zipfian"
+///
+/// [[requests]]
+/// method = "getAccountInfo"
+/// weight = 70
+/// keys = "hot"
+/// params = ["$key", { encoding = "base64" }]
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkloadProfile {
+    /// Seed for request selection and key draws, for repeatable runs.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub keys: HashMap<String, KeySetConfig>,
+    pub requests: Vec<RequestConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeySetConfig {
+    /// Keys listed inline.
+    #[serde(default)]
+    pub values: Vec<String>,
+    /// File with one key per line, relative to the profile. Blank lines and
+    /// `#` comments are skipped.
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub distribution: KeyDistribution,
+    /// Skew of the zipfian distribution; the key at rank `r` is drawn with
+    /// weight `1 / r^exponent`, so earlier keys are hotter.
+    #[serde(default = "default_zipf_exponent")]
+    pub exponent: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyDistribution {
+    #[default]
+    Uniform,
+    Zipfian,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestConfig {
+    /// Name the entry is reported under; defaults to the method.
+    #[serde(default)]
+    pub name: Option<String>,
+    pub method: String,
+    /// Relative share of requests; weights need not add up to 100.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+    /// Params array, where `"$key"` and `"$keys"` are filled from `keys`.
+    #[serde(default = "default_params")]
+    pub params: Value,
+    /// Key set the placeholders draw from.
+    #[serde(default)]
+    pub keys: Option<String>,
+    /// Number of keys a `"$keys"` placeholder expands to.
+    #[serde(default = "default_batch")]
+    pub batch: usize,
+}
+
+fn default_zipf_exponent() -> f64 {
+    1.0
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_params() -> Value {
+    json!([])
+}
+
+fn default_batch() -> usize {
+    1
+}
+
+impl WorkloadProfile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read workload profile {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse workload profile {}", path.display()))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct KeySet {
+    keys: Vec<Value>,
+    /// Cumulative draw weights, for zipfian sets.
+    cumulative: Option<Vec<f64>>,
+}
+
+impl KeySet {
+    fn new(name: &str, config: &KeySetConfig, base: &Path) -> Result<Self> {
+        let mut keys: Vec<Value> = config.values.iter().cloned().map(Value::from).collect();
+        if let Some(file) = &config.file {
+            let path = base.join(file);
+            let text = fs::read_to_string(&path).with_context(|| {
+                format!("failed to read key set {name} from {}", path.display())
+            })?;
+            keys.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(Value::from),
+            );
+        }
+        if keys.is_empty() {
+            bail!("key set {name} has no keys");
+        }
+        let cumulative = match config.distribution {
+            KeyDistribution::Uniform => None,
+            KeyDistribution::Zipfian => {
+                if !(config.exponent.is_finite() && config.exponent > 0.0) {
+                    bail!(
+                        "key set {name} needs a positive zipfian exponent, got {}",
+                        config.exponent
+                    );
+                }
+                let mut total = 0.0;
+                Some(
+                    (1..=keys.len())
+                        .map(|rank| {
+                            total += 1.0 / (rank as f64).powf(config.exponent);
+                            total
+                        })
+                        .collect(),
+                )
+            }
+        };
+        Ok(Self { keys, cumulative })
+    }
+
+    fn draw<R: Rng>(&self, rng: &mut R) -> &Value {
+        let index = match &self.cumulative {
+            None => rng.gen_range(0..self.keys.len()),
+            Some(cumulative) => {
+                let target = rng.gen::<f64>() * cumulative[cumulative.len() - 1];
+                cumulative
+                    .partition_point(|&weight| weight <= target)
+                    .min(self.keys.len() - 1)
+            }
+        };
+        &self.keys[index]
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    label: String,
+    method: String,
+    params: Value,
+    keys: Option<usize>,
+    batch: usize,
+}
+
+/// The calls a load run draws its requests from.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    entries: Vec<Entry>,
+    key_sets: Vec<KeySet>,
+    chooser: WeightedIndex<f64>,
+    seed: Option<u64>,
+}
+
+impl Workload {
+    /// An even mix of fixed calls.
+    pub fn new(calls: Vec<RpcCall>) -> Result<Self> {
+        if calls.is_empty() {
+            bail!("workload needs at least one rpc call");
+        }
+        let chooser = WeightedIndex::new(vec![1.0; calls.len()])?;
+        Ok(Self {
+            entries: calls
+                .into_iter()
+                .map(|call| Entry {
+                    label: call.method.clone(),
+                    method: call.method,
+                    params: call.params,
+                    keys: None,
+                    batch: 1,
+                })
+                .collect(),
+            key_sets: Vec::new(),
+            chooser,
+            seed: None,
+        })
+    }
+
+    /// Builds the mix a profile describes. Key files resolve against `base`.
+    pub fn from_profile(profile: &WorkloadProfile, base: &Path) -> Result<Self> {
+        if profile.requests.is_empty() {
+            bail!("workload profile lists no requests");
+        }
+        let mut names: Vec<&String> = profile.keys.keys().collect();
+        names.sort();
+        let key_sets = names
+            .iter()
+            .map(|name| KeySet::new(name, &profile.keys[*name], base))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut entries = Vec::with_capacity(profile.requests.len());
+        for request in &profile.requests {
+            let label = request
+                .name
+                .clone()
+                .unwrap_or_else(|| request.method.clone());
+            if !(request.weight.is_finite() && request.weight >= 0.0) {
+                bail!("request {label} has invalid weight {}", request.weight);
+            }
+            if !request.params.is_array() {
+                bail!("params of request {label} must be an array");
+            }
+            if request.batch == 0 {
+                bail!("request {label} needs a batch of at least 1");
+            }
+            let keys = match &request.keys {
+                Some(set) => Some(
+                    names
+                        .iter()
+                        .position(|name| *name == set)
+                        .with_context(|| format!("request {label} uses unknown key set {set}"))?,
+                ),
+                None if has_placeholder(&request.params) => {
+                    bail!("request {label} has key placeholders but no key set")
+                }
+                None => None,
+            };
+            entries.push(Entry {
+                label,
+                method: request.method.clone(),
+                params: request.params.clone(),
+                keys,
+                batch: request.batch,
+            });
+        }
+        let chooser = WeightedIndex::new(profile.requests.iter().map(|request| request.weight))
+            .context("workload profile needs a request with positive weight")?;
+
+        Ok(Self {
+            entries,
+            key_sets,
+            chooser,
+            seed: profile.seed,
+        })
+    }
+
+    /// Label of each entry, indexed like the requests returned by
+    /// [`Workload::next_request`].
+    pub fn labels(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| entry.label.clone())
+            .collect()
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Picks the next entry by weight and encodes it with request `id`.
+    pub fn next_request<R: Rng>(&self, rng: &mut R, id: u64) -> (usize, Vec<u8>) {
+        let index = self.chooser.sample(rng);
+        let entry = &self.entries[index];
+        let body = match entry.keys {
+            None => encode_request(id, &entry.method, &entry.params),
+            Some(set) => {
+                let mut params = entry.params.clone();
+                fill_keys(&mut params, &self.key_sets[set], entry.batch, rng);
+                encode_request(id, &entry.method, &params)
+            }
+        };
+        (index, body)
+    }
+}
+
+fn has_placeholder(value: &Value) -> bool {
+    match value {
+        Value::String(text) => text == KEY_PLACEHOLDER || text == KEYS_PLACEHOLDER,
+        Value::Array(items) => items.iter().any(has_placeholder),
+        Value::Object(fields) => fields.values().any(has_placeholder),
+        _ => false,
+    }
+}
+
+fn fill_keys<R: Rng>(value: &mut Value, keys: &KeySet, batch: usize, rng: &mut R) {
+    match value {
+        Value::String(text) if text == KEY_PLACEHOLDER => *value = keys.draw(rng).clone(),
+        Value::String(text) if text == KEYS_PLACEHOLDER => {
+            *value = Value::Array((0..batch).map(|_| keys.draw(rng).clone()).collect());
+        }
+        Value::Array(items) => {
+            for item in items {
+                fill_keys(item, keys, batch, rng);
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                fill_keys(field, keys, batch, rng);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn encode_request(id: u64, method: &str, params: &Value) -> Vec<u8> {
+    serde_json::to_vec(&json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": params,
+    }))
+    .expect("json values always serialize")
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn parses_calls_with_and_without_params() {
+        let call: RpcCall = "getSlot".parse().expect("bare method");
+        assert_eq!(call.params, json!([]));
+
+        let call: RpcCall = r#"getAccountInfo=["Vote111111111111111111111111111111111111111",{"encoding":"base64"}]"#
+            .parse()
+            .expect("method with params");
+        assert_eq!(call.method, "getAccountInfo");
+        assert_eq!(call.params[1]["encoding"], "base64");
+
+        assert!("=[]".parse::<RpcCall>().is_err());
+        assert!("getSlot={}".parse::<RpcCall>().is_err());
+        assert!("getSlot=[".parse::<RpcCall>().is_err());
+    }
+
+    #[test]
+    fn encodes_json_rpc_requests() {
+        let workload = Workload::new(vec!["getSlot".parse().unwrap()]).unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let (index, body) = workload.next_request(&mut rng, 42);
+        assert_eq!(index, 0);
+        let value: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            value,
+            json!({"jsonrpc": "2.0", "id": 42, "method": "getSlot", "params": []})
+        );
+        assert!(Workload::new(Vec::new()).is_err());
+    }
+
+    fn profile(text: &str) -> Result<Workload> {
+        let profile: WorkloadProfile = toml::from_str(text)?;
+        Workload::from_profile(&profile, Path::new("."))
+    }
+
+    #[test]
+    fn mixes_requests_by_weight_and_fills_keys() {
+        let workload = profile(
+            r#"
+            seed = 1
+
+            [keys.hot]
+            values = ["a", "b", "c", "d"]
+            distribution = "zipfian"
+            exponent = 1.2
+
+            [[requests]]
+            method = "getAccountInfo"
+            weight = 70
+            keys = "hot"
+            params = ["$key", { encoding = "base64" }]
+
+            [[requests]]
+            method = "getMultipleAccounts"
+            weight = 20
+            keys = "hot"
+            batch = 3
+            params = ["$keys"]
+
+            [[requests]]
+            name = "program-accounts"
+            method = "getProgramAccounts"
+            weight = 10
+            params = ["Vote111111111111111111111111111111111111111"]
+            "#,
+        )
+        .expect("valid profile");
+        assert_eq!(workload.seed(), Some(1));
+        assert_eq!(
+            workload.labels(),
+            ["getAccountInfo", "getMultipleAccounts", "program-accounts"]
+        );
+
+        let mut rng = StdRng::seed_from_u64(workload.seed().unwrap());
+        let mut picks = [0u32; 3];
+        let mut hot = HashMap::<String, u32>::new();
+        for id in 0..10_000 {
+            let (index, body) = workload.next_request(&mut rng, id);
+            picks[index] += 1;
+            let request: Value = serde_json::from_slice(&body).unwrap();
+            match index {
+                0 => {
+                    assert_eq!(request["params"][1]["encoding"], "base64");
+                    let key = request["params"][0].as_str().unwrap().to_string();
+                    *hot.entry(key).or_default() += 1;
+                }
+                1 => assert_eq!(request["params"][0].as_array().unwrap().len(), 3),
+                _ => assert_eq!(request["method"], "getProgramAccounts"),
+            }
+        }
+        assert!((6_700..7_300).contains(&picks[0]), "{picks:?}");
+        assert!((1_700..2_300).contains(&picks[1]), "{picks:?}");
+        // Zipfian: the first key is the hottest
+        assert!(hot["a"] > hot["b"] && hot["b"] > hot["d"], "{hot:?}");
+    }
+
+    #[test]
+    fn example_profile_parses() {
+        let example: WorkloadProfile = toml::from_str(include_str!(
+            "../../../ops/ultra-rpc-bench.workload.example.toml"
+        ))
+        .expect("example profile parses");
+        assert_eq!(example.requests.len(), 3);
+        assert_eq!(
+            example.keys["hot-accounts"].distribution,
+            KeyDistribution::Zipfian
+        );
+    }
+
+    #[test]
+    fn rejects_inconsistent_profiles() {
+        let unknown_set = r#"
+            [[requests]]
+            method = "getAccountInfo"
+            keys = "missing"
+            params = ["$key"]
+        "#;
+        assert!(profile(unknown_set).is_err());
+
+        let no_set = r#"
+            [[requests]]
+            method = "getAccountInfo"
+            params = ["$key"]
+        "#;
+        assert!(profile(no_set).is_err());
+
+        let empty_set = r#"
+            [keys.hot]
+            values = []
+
+            [[requests]]
+            method = "getAccountInfo"
+            keys = "hot"
+            params = ["$key"]
+        "#;
+        assert!(profile(empty_set).is_err());
+
+        let zero_weight = r#"
+            [[requests]]
+            method = "getSlot"
+            weight = 0
+        "#;
+        assert!(profile(zero_weight).is_err());
+    }
+}
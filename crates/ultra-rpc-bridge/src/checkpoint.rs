@@ -0,0 +1,160 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/checkpoint.rs
+//
+// Bridge state persisted across restarts. With `--checkpoint-path`, every
+// `--checkpoint-interval-secs` once startup is over the bridge writes the set
+// of pubkeys it knows with the last slot it saw for each, plus the snapshot
+// slot. After a restart the startup stream is diffed against it: accounts at
+// or below their checkpointed slot are unchanged, newer or unknown ones
+// changed, and checkpointed pubkeys missing from the stream were deleted. The
+// changes then go out on the delta stream right after the `SnapshotComplete`
+// marker, so a client that still holds the checkpointed state is brought up to
+// date without reloading a full snapshot.
+//
+// The file is little-endian: a magic, the slot, the pubkey count, then one
+// pubkey and slot per entry. It is written to a temporary file and renamed
+// into place, so a crash mid-write leaves the previous checkpoint. The bridge
+// only hands the writer what changed since the last checkpoint, so the pubkey
+// set is never copied on the event loop.
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 8] = b"URBCKPT1";
+const HEADER_LEN: u64 = 24;
+const ENTRY_LEN: u64 = 40;
+
+/// Changes to the known pubkeys since the last checkpoint: the last slot seen,
+/// or `None` for a deleted account.
+pub type Changes = HashMap<[u8; 32], Option<u64>>;
+
+/// Known pubkeys and the last slot seen for each, as of `slot`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub slot: u64,
+    pub slots: HashMap<[u8; 32], u64>,
+}
+
+impl Checkpoint {
+    /// The checkpoint at `path`, or `None` if there is none yet.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("open checkpoint {}", path.display())),
+        };
+        let len = file
+            .metadata()
+            .with_context(|| format!("stat checkpoint {}", path.display()))?
+            .len();
+        let mut r = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)
+            .with_context(|| format!("read checkpoint {}", path.display()))?;
+        if &magic != MAGIC {
+            bail!("{} is not a bridge checkpoint", path.display());
+        }
+        let slot = read_u64(&mut r)?;
+        let count = read_u64(&mut r)?;
+        // Size the map from what the file holds, not from what it claims
+        if count > len.saturating_sub(HEADER_LEN) / ENTRY_LEN {
+            bail!(
+                "truncated checkpoint {}: {count} entries in {len} bytes",
+                path.display()
+            );
+        }
+        let mut slots = HashMap::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut pubkey = [0u8; 32];
+            r.read_exact(&mut pubkey)
+                .with_context(|| format!("truncated checkpoint {}", path.display()))?;
+            slots.insert(pubkey, read_u64(&mut r)?);
+        }
+        Ok(Some(Self { slot, slots }))
+    }
+
+    /// Bring this checkpoint up to `slot` with the changes made since it was taken.
+    pub fn apply(&mut self, slot: u64, changes: Changes) {
+        self.slot = slot;
+        for (pubkey, last) in changes {
+            match last {
+                Some(last) => self.slots.insert(pubkey, last),
+                None => self.slots.remove(&pubkey),
+            };
+        }
+    }
+
+    /// Replace the checkpoint at `path` with this one.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        let file =
+            File::create(&tmp).with_context(|| format!("create checkpoint {}", tmp.display()))?;
+        let mut w = BufWriter::new(file);
+        w.write_all(MAGIC)?;
+        w.write_all(&self.slot.to_le_bytes())?;
+        w.write_all(&(self.slots.len() as u64).to_le_bytes())?;
+        for (pubkey, slot) in &self.slots {
+            w.write_all(pubkey)?;
+            w.write_all(&slot.to_le_bytes())?;
+        }
+        let file = w.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+            .with_context(|| format!("write checkpoint {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("replace checkpoint {}", path.display()))
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf).context("truncated checkpoint")?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_and_tolerates_a_missing_file() {
+        let path = std::env::temp_dir().join(format!(
+            "ultra-rpc-bridge-checkpoint-{}.bin",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(Checkpoint::load(&path).unwrap(), None);
+
+        let checkpoint = Checkpoint {
+            slot: 42,
+            slots: (0..10u8).map(|n| ([n; 32], n as u64 + 30)).collect(),
+        };
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), Some(checkpoint));
+
+        std::fs::write(&path, b"garbage!and more").unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+
+        // A header claiming more entries than follow is an error, not an allocation
+        let mut short = MAGIC.to_vec();
+        short.extend_from_slice(&7u64.to_le_bytes());
+        short.extend_from_slice(&u64::MAX.to_le_bytes());
+        short.extend_from_slice(&[0; 40]);
+        std::fs::write(&path, &short).unwrap();
+        assert!(Checkpoint::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn applies_changes_since_the_last_checkpoint() {
+        let mut checkpoint = Checkpoint {
+            slot: 5,
+            slots: [([1; 32], 5), ([2; 32], 4)].into(),
+        };
+        checkpoint.apply(9, [([1; 32], None), ([3; 32], Some(9))].into());
+        assert_eq!(checkpoint.slot, 9);
+        let expected: HashMap<[u8; 32], u64> = [([2; 32], 4), ([3; 32], 9)].into();
+        assert_eq!(checkpoint.slots, expected);
+    }
+}
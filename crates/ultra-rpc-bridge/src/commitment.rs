@@ -0,0 +1,182 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/commitment.rs
+//
+// `--commitment confirmed|rooted`: hold live account updates per slot and only
+// release them to the delta socket once a Slot record reports that slot at the
+// chosen level, so the RPC cache never serves state that is later rolled back.
+// When a slot reaches the level, it and every held ancestor (followed through
+// the `parent` of earlier Slot records) are released in slot order; held slots
+// below it that are not ancestors were on an abandoned fork and are dropped,
+// as are slots reported dead. Updates for slots at or below the last released
+// one are already settled and pass straight through. The startup snapshot is
+// never held. The default, `processed`, forwards everything as it arrives.
+use clap::ValueEnum;
+use faststreams::AccountDelta;
+use metrics::{counter, gauge};
+use std::collections::BTreeMap;
+
+// faststreams Record::Slot status codes, as the plugin maps SlotStatus
+const STATUS_CONFIRMED: u8 = 1;
+const STATUS_ROOTED: u8 = 2;
+const STATUS_DEAD: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Commitment {
+    Processed,
+    Confirmed,
+    Rooted,
+}
+
+impl Commitment {
+    fn reached_by(self, status: u8) -> bool {
+        match self {
+            Commitment::Processed => true,
+            Commitment::Confirmed => matches!(status, STATUS_CONFIRMED | STATUS_ROOTED),
+            Commitment::Rooted => status == STATUS_ROOTED,
+        }
+    }
+}
+
+pub struct CommitGate {
+    level: Commitment,
+    held: BTreeMap<u64, Vec<AccountDelta>>,
+    held_updates: usize,
+    parents: BTreeMap<u64, u64>,
+    // Highest slot released at the level
+    settled: u64,
+}
+
+impl CommitGate {
+    pub fn new(level: Commitment) -> Self {
+        Self {
+            level,
+            held: BTreeMap::new(),
+            held_updates: 0,
+            parents: BTreeMap::new(),
+            settled: 0,
+        }
+    }
+
+    /// Pass `d` through, or hold it until its slot reaches the level.
+    pub fn admit(&mut self, d: AccountDelta) -> Option<AccountDelta> {
+        if self.level == Commitment::Processed || d.slot <= self.settled {
+            return Some(d);
+        }
+        self.held.entry(d.slot).or_default().push(d);
+        self.held_updates += 1;
+        gauge!("rpc_bridge_held_updates").set(self.held_updates as f64);
+        None
+    }
+
+    /// Apply a Slot record; updates it releases are appended to `out` in slot order.
+    pub fn on_slot(
+        &mut self,
+        slot: u64,
+        parent: Option<u64>,
+        status: u8,
+        out: &mut Vec<AccountDelta>,
+    ) {
+        if self.level == Commitment::Processed {
+            return;
+        }
+        if let Some(parent) = parent {
+            if slot > self.settled {
+                self.parents.insert(slot, parent);
+            }
+        }
+        if status == STATUS_DEAD {
+            if let Some(dead) = self.held.remove(&slot) {
+                self.drop_updates(dead.len());
+            }
+            return;
+        }
+        if !self.level.reached_by(status) || slot <= self.settled {
+            return;
+        }
+
+        // The slot's held ancestors. Below the first missing parent link the
+        // lineage is unknown, so those slots are released rather than dropped.
+        let rest = self.held.split_off(&(slot + 1));
+        let below = std::mem::replace(&mut self.held, rest);
+        let mut chain = vec![slot];
+        let mut floor = self.settled;
+        let mut cur = slot;
+        loop {
+            match self.parents.get(&cur) {
+                Some(&p) if p > self.settled => {
+                    chain.push(p);
+                    cur = p;
+                }
+                Some(_) => break,
+                None => {
+                    floor = cur;
+                    break;
+                }
+            }
+        }
+        let (mut released, mut dropped) = (0, 0);
+        for (s, updates) in below {
+            if s < floor || chain.contains(&s) {
+                released += updates.len();
+                out.extend(updates);
+            } else {
+                dropped += updates.len();
+            }
+        }
+        self.held_updates -= released;
+        if dropped > 0 {
+            self.drop_updates(dropped);
+        }
+        counter!("rpc_bridge_released_updates_total").increment(released as u64);
+        gauge!("rpc_bridge_held_updates").set(self.held_updates as f64);
+        self.settled = slot;
+        self.parents = self.parents.split_off(&(slot + 1));
+    }
+
+    fn drop_updates(&mut self, n: usize) {
+        self.held_updates -= n;
+        counter!("rpc_bridge_rolled_back_updates_total").increment(n as u64);
+        gauge!("rpc_bridge_held_updates").set(self.held_updates as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(slot: u64) -> AccountDelta {
+        AccountDelta {
+            pubkey: [slot as u8; 32],
+            slot,
+            account: None,
+        }
+    }
+
+    fn slots(out: &[AccountDelta]) -> Vec<u64> {
+        out.iter().map(|d| d.slot).collect()
+    }
+
+    #[test]
+    fn releases_the_rooted_chain_and_drops_forks() {
+        let mut gate = CommitGate::new(Commitment::Rooted);
+        let mut out = Vec::new();
+        for (slot, parent) in [(10, 9), (11, 10), (12, 10), (13, 11)] {
+            gate.on_slot(slot, Some(parent), 0, &mut out);
+            assert!(gate.admit(update(slot)).is_none());
+        }
+        // Confirmed is not enough in rooted mode
+        gate.on_slot(13, Some(11), STATUS_CONFIRMED, &mut out);
+        assert!(out.is_empty());
+
+        // 13 roots 11 and 10; 12 was a fork
+        gate.on_slot(13, Some(11), STATUS_ROOTED, &mut out);
+        assert_eq!(slots(&out), vec![10, 11, 13]);
+        assert_eq!(gate.held_updates, 0);
+
+        // Late updates for settled slots pass; dead slots are dropped
+        assert!(gate.admit(update(11)).is_some());
+        assert!(gate.admit(update(14)).is_none());
+        gate.on_slot(14, Some(13), STATUS_DEAD, &mut out);
+        assert_eq!(gate.held_updates, 0);
+    }
+}
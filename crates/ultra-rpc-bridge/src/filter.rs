@@ -0,0 +1,68 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/filter.rs
+//
+// Owner-program filters, so one bridge can feed an RPC cache specialized to a
+// few programs. `--include-owner` keeps only accounts owned by the listed
+// programs, `--exclude-owner` drops accounts owned by the listed ones; exclusion
+// wins. Producer readers apply the filter as they decode, so filtered startup
+// accounts never reach the snapshot and filtered live updates never reach the
+// delta stream. A live update that moves an account the RPC already holds out
+// of the filter (closed, or reassigned to another program) is turned into a
+// deletion by the bridge, so the cache does not keep a stale copy.
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+/// Parse a base58 pubkey from the command line.
+pub fn parse_pubkey(s: &str) -> Result<[u8; 32]> {
+    bs58::decode(s)
+        .into_vec()
+        .ok()
+        .and_then(|v| v.try_into().ok())
+        .ok_or_else(|| anyhow!("invalid pubkey {s}"))
+}
+
+#[derive(Debug, Default)]
+pub struct OwnerFilter {
+    include: HashSet<[u8; 32]>,
+    exclude: HashSet<[u8; 32]>,
+}
+
+impl OwnerFilter {
+    pub fn new(include: &[[u8; 32]], exclude: &[[u8; 32]]) -> Self {
+        Self {
+            include: include.iter().copied().collect(),
+            exclude: exclude.iter().copied().collect(),
+        }
+    }
+
+    /// Whether accounts owned by `owner` pass.
+    pub fn admits(&self, owner: &[u8; 32]) -> bool {
+        (self.include.is_empty() || self.include.contains(owner)) && !self.exclude.contains(owner)
+    }
+
+    pub fn is_active(&self) -> bool {
+        !(self.include.is_empty() && self.exclude.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusion_wins_over_inclusion() {
+        let token = parse_pubkey("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+        let system = [0u8; 32];
+        assert!(parse_pubkey("not-base58!").is_err());
+
+        assert!(OwnerFilter::default().admits(&token));
+        assert!(!OwnerFilter::default().is_active());
+        let only_token = OwnerFilter::new(&[token], &[]);
+        assert!(only_token.admits(&token));
+        assert!(!only_token.admits(&system));
+        let no_system = OwnerFilter::new(&[], &[system]);
+        assert!(no_system.admits(&token));
+        assert!(!no_system.admits(&system));
+        assert!(!OwnerFilter::new(&[token], &[token]).admits(&token));
+    }
+}
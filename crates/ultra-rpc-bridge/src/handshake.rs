@@ -0,0 +1,140 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/handshake.rs
+//
+// Delta socket handshake. Before anything else the bridge sends a `Hello` with
+// its CacheFeed protocol version, whether slot and transaction statuses follow,
+// the last snapshot it served and the compressions it offers; the client
+// answers with a `HelloAck` carrying its own version and the compression it
+// picked, which the delta writer then applies to every large frame. A client on another version, or one that does not answer
+// within the handshake timeout, is dropped at connect time instead of failing
+// on the first message it cannot decode.
+use crate::output::{OutputStream, HANDSHAKE_TIMEOUT};
+use anyhow::{anyhow, bail, Result};
+use bytes::BytesMut;
+use faststreams::{
+    decode_cache_feed_body, encode_cache_feed, CacheFeed, FeedCompression, FrameHeader,
+    CACHE_FEED_VERSION, FRAME_HEADER_LEN,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+// A HelloAck is a few bytes; anything much larger is not one
+const MAX_ACK_BYTES: usize = 4096;
+
+/// What the bridge forwards besides account updates, and how it may compress.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub slots: bool,
+    pub transactions: bool,
+    pub compression: Vec<FeedCompression>,
+}
+
+impl Capabilities {
+    pub fn hello(&self, base_slot: Option<u64>) -> CacheFeed {
+        CacheFeed::Hello {
+            version: CACHE_FEED_VERSION,
+            slots: self.slots,
+            transactions: self.transactions,
+            base_slot,
+            compression: self.compression.clone(),
+        }
+    }
+}
+
+/// Send `hello` and wait for a matching `HelloAck`; the compression picked.
+pub async fn greet(sock: &mut OutputStream, hello: &CacheFeed) -> Result<FeedCompression> {
+    sock.write_all(&encode_cache_feed(hello)?).await?;
+    sock.flush().await?;
+    let ack = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_message(sock))
+        .await
+        .map_err(|_| anyhow!("no HelloAck within {HANDSHAKE_TIMEOUT:?}"))??;
+    let offered = match hello {
+        CacheFeed::Hello { compression, .. } => compression.as_slice(),
+        _ => &[],
+    };
+    match ack {
+        CacheFeed::HelloAck {
+            version,
+            compression,
+        } if version == CACHE_FEED_VERSION => {
+            if compression != FeedCompression::None && !offered.contains(&compression) {
+                bail!("client picked {compression:?}, which was not offered");
+            }
+            Ok(compression)
+        }
+        CacheFeed::HelloAck { version, .. } => {
+            bail!("client speaks cache feed v{version}, bridge v{CACHE_FEED_VERSION}")
+        }
+        _ => bail!("client answered the hello with something other than HelloAck"),
+    }
+}
+
+async fn read_message(sock: &mut OutputStream) -> Result<CacheFeed> {
+    let mut buf = BytesMut::with_capacity(256);
+    loop {
+        if let Some(hdr) = FrameHeader::parse(&buf)? {
+            if hdr.frame_len() > MAX_ACK_BYTES {
+                bail!("{} byte handshake frame", hdr.frame_len());
+            }
+            if buf.len() >= hdr.frame_len() {
+                let body = &buf[FRAME_HEADER_LEN..hdr.frame_len()];
+                return Ok(decode_cache_feed_body(&hdr, body, &mut Vec::new())?);
+            }
+        }
+        if sock.read_buf(&mut buf).await? == 0 {
+            bail!("client closed the connection during the handshake");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn answer(ack: CacheFeed) -> Result<FeedCompression> {
+        let (ours, mut theirs) = tokio::io::duplex(1024);
+        let mut ours: OutputStream = Box::pin(ours);
+        let client = tokio::spawn(async move {
+            let mut buf = BytesMut::new();
+            theirs.read_buf(&mut buf).await.unwrap();
+            let hdr = FrameHeader::parse(&buf).unwrap().unwrap();
+            assert_eq!(hdr.record_type, faststreams::RECORD_TYPE_FEED_HELLO);
+            theirs
+                .write_all(&encode_cache_feed(&ack).unwrap())
+                .await
+                .unwrap();
+            theirs
+        });
+        let hello = Capabilities {
+            slots: true,
+            transactions: false,
+            compression: vec![FeedCompression::Lz4],
+        }
+        .hello(Some(7));
+        let res = greet(&mut ours, &hello).await;
+        drop(client.await.unwrap());
+        res
+    }
+
+    #[tokio::test]
+    async fn accepts_only_a_matching_ack() {
+        let ack = |version, compression| CacheFeed::HelloAck {
+            version,
+            compression,
+        };
+        for picked in [FeedCompression::None, FeedCompression::Lz4] {
+            let got = answer(ack(CACHE_FEED_VERSION, picked)).await.unwrap();
+            assert_eq!(got, picked);
+        }
+        let err = answer(ack(CACHE_FEED_VERSION, FeedCompression::Zstd))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not offered"));
+        let err = answer(ack(CACHE_FEED_VERSION + 1, FeedCompression::None))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("cache feed v"));
+        assert!(answer(CacheFeed::SnapshotComplete { slot: 1 })
+            .await
+            .is_err());
+    }
+}
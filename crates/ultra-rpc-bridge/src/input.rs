@@ -0,0 +1,178 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/input.rs
+//
+// Producer side of the bridge. Every connection to the input UDS gets its own
+// reader task, so a sharded plugin (one socket per writer) or several
+// ys-consumers can feed one bridge at once. Readers decode faststreams frames
+// and hand the records, tagged with the producer, to the single task that owns
+// the snapshot/delta state. Producers are named `uds#<seq>` in that state and
+// in the logs only; the counts go to
+// `rpc_bridge_producer_records_total{listener}` and
+// `rpc_bridge_producer_decode_errors_total{listener}` under the input socket
+// path, so reconnecting producers do not add series. Accounts outside the
+// owner filter (see filter.rs) are dropped here, counted in
+// `rpc_bridge_producer_filtered_total{listener}`.
+use crate::filter::OwnerFilter;
+use anyhow::{Context, Result};
+use bytes::{Buf, BytesMut};
+use faststreams::{decode_record_body, FrameHeader, Record, FRAME_HEADER_LEN};
+use metrics::{counter, gauge};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UnixListener;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+static PRODUCER_SEQ: AtomicU64 = AtomicU64::new(0);
+static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+/// What a producer connection reports to the bridge task.
+#[derive(Debug)]
+pub enum Event {
+    Connected,
+    Record(Record),
+    /// A live update to an account outside the owner filter
+    Excluded {
+        pubkey: [u8; 32],
+        slot: u64,
+    },
+    Disconnected,
+}
+
+#[derive(Debug)]
+pub struct Input {
+    pub producer: Arc<str>,
+    pub event: Event,
+    // When the event was decoded, for the bridge's decode-to-send latency
+    pub at: Instant,
+}
+
+/// Bind the input UDS and spawn a reader for every producer that connects.
+pub fn listen(path: &str, filter: Arc<OwnerFilter>, tx: mpsc::Sender<Input>) -> Result<()> {
+    if std::path::Path::new(path).exists() {
+        let _ = std::fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path).with_context(|| format!("bind {path} failed"))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660));
+    }
+    info!(uds = %path, "bridge input listening");
+    let path: Arc<str> = path.into();
+    tokio::spawn(async move {
+        loop {
+            let sock = match listener.accept().await {
+                Ok((sock, _)) => sock,
+                Err(e) => {
+                    warn!(%e, "producer accept failed");
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            #[cfg(unix)]
+            {
+                let _ = socket2::SockRef::from(&sock).set_recv_buffer_size(32 * 1024 * 1024);
+            }
+            let producer: Arc<str> =
+                format!("uds#{}", PRODUCER_SEQ.fetch_add(1, Ordering::Relaxed)).into();
+            let task = read_producer(sock, producer, path.clone(), filter.clone(), tx.clone());
+            tokio::spawn(task);
+        }
+    });
+    Ok(())
+}
+
+/// Forward one producer's records until it disconnects, counted under the
+/// `listener` it connected to.
+async fn read_producer<R: AsyncRead + Unpin>(
+    mut sock: R,
+    producer: Arc<str>,
+    listener: Arc<str>,
+    filter: Arc<OwnerFilter>,
+    tx: mpsc::Sender<Input>,
+) {
+    let connected = CONNECTED.fetch_add(1, Ordering::Relaxed) + 1;
+    gauge!("rpc_bridge_producers_connected").set(connected as f64);
+    info!(%producer, "bridge accepted producer connection");
+    let send = |event| {
+        tx.send(Input {
+            producer: producer.clone(),
+            event,
+            at: Instant::now(),
+        })
+    };
+    let records = counter!("rpc_bridge_producer_records_total", "listener" => listener.to_string());
+    let bad =
+        counter!("rpc_bridge_producer_decode_errors_total", "listener" => listener.to_string());
+    let filtered =
+        counter!("rpc_bridge_producer_filtered_total", "listener" => listener.to_string());
+
+    if send(Event::Connected).await.is_ok() {
+        let mut buf = BytesMut::with_capacity(1 << 20);
+        let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+        'read: loop {
+            match sock.read_buf(&mut buf).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(%e, %producer, "producer read failed");
+                    break;
+                }
+            }
+            while let Some(frame) = next_frame(&mut buf, &mut scratch) {
+                let event = match frame {
+                    Ok(Record::Account(a)) if !filter.admits(&a.owner) => {
+                        records.increment(1);
+                        filtered.increment(1);
+                        if a.is_startup {
+                            continue;
+                        }
+                        Event::Excluded {
+                            pubkey: a.pubkey,
+                            slot: a.slot,
+                        }
+                    }
+                    Ok(rec) => {
+                        records.increment(1);
+                        Event::Record(rec)
+                    }
+                    Err(()) => {
+                        bad.increment(1);
+                        continue;
+                    }
+                };
+                if send(event).await.is_err() {
+                    break 'read;
+                }
+            }
+        }
+        let _ = send(Event::Disconnected).await;
+    }
+    let connected = CONNECTED.fetch_sub(1, Ordering::Relaxed) - 1;
+    gauge!("rpc_bridge_producers_connected").set(connected as f64);
+    info!(%producer, "producer disconnected");
+}
+
+/// The next record in `buf`: `None` until a whole frame is buffered, `Err` for
+/// a frame that was skipped (bad header byte or undecodable body).
+fn next_frame(buf: &mut BytesMut, scratch: &mut Vec<u8>) -> Option<Result<Record, ()>> {
+    let hdr = match FrameHeader::parse(buf) {
+        Ok(Some(hdr)) => hdr,
+        Ok(None) => return None,
+        Err(_) => {
+            counter!("rpc_bridge_bad_header_total").increment(1);
+            buf.advance(1);
+            return Some(Err(()));
+        }
+    };
+    let total = hdr.frame_len();
+    if buf.len() < total {
+        return None;
+    }
+    let rec = decode_record_body(&hdr, &buf[FRAME_HEADER_LEN..total], scratch);
+    buf.advance(total);
+    Some(rec.map_err(|_| ()))
+}
@@ -1,23 +1,40 @@
 // Numan Thabit 2025
 // crates/ultra-rpc-bridge/src/main.rs
 #![forbid(unsafe_code)]
+mod checkpoint;
+mod commitment;
+mod filter;
+mod handshake;
+mod input;
+mod output;
+mod snapshot;
+mod spill;
+
 use anyhow::{anyhow, Context, Result};
-use bytes::{Buf, Bytes, BytesMut};
+use bytes::Bytes;
+use checkpoint::Checkpoint;
 use clap::Parser;
-use faststreams::{decode_record_from_slice, Record};
+use commitment::{CommitGate, Commitment};
+use faststreams::{
+    compress_cache_feed_frame, encode_cache_feed, AccountDelta, AccountState, CacheFeed,
+    FeedCompression, Record, SlotStatus, TxUpdate,
+};
+use filter::OwnerFilter;
 use futures_util::SinkExt;
-use metrics::{counter, gauge};
+use handshake::Capabilities;
+use input::{Event, Input};
+use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::PrometheusBuilder;
-use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
-use std::io::ErrorKind;
+use output::{Accepted, OutputListener, OutputOpts, TlsFiles};
+use snapshot::{SnapshotRequest, SnapshotStore};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
-use tokio::io::AsyncReadExt;
-use tokio::net::UnixListener;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio::time;
-use tokio_util::codec::{FramedWrite, LengthDelimitedCodec};
+use tokio_util::codec::{BytesCodec, FramedWrite};
 use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 
@@ -29,7 +46,7 @@ use tracing_subscriber::EnvFilter;
     rename_all = "kebab-case"
 )]
 struct Args {
-    /// Aggregator UDS input (faststreams frames)
+    /// Aggregator UDS input (faststreams frames); any number of producers may connect
     #[arg(long, default_value = "/tmp/ultra-geyser.sock")]
     input_uds: String,
 
@@ -41,10 +58,54 @@ struct Args {
     #[arg(long, default_value = "/tmp/ultra-aggregator.sock")]
     delta_uds: String,
 
+    /// Serve the snapshot on this TCP address instead of the snapshot UDS
+    #[arg(long)]
+    snapshot_tcp: Option<String>,
+
+    /// Serve deltas on this TCP address instead of the delta UDS
+    #[arg(long)]
+    delta_tcp: Option<String>,
+
+    /// TLS certificate chain (PEM) for the TCP outputs
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// TLS private key (PEM) for the TCP outputs
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// Require TCP clients to present a certificate issued by this CA bundle (PEM)
+    #[arg(long, requires = "tls_cert")]
+    tls_client_ca: Option<String>,
+
+    /// TCP keepalive idle time and probe interval in seconds; 0 disables it
+    #[arg(long, default_value_t = 30)]
+    tcp_keepalive_secs: u64,
+
+    /// Socket send buffer requested for each snapshot/delta client
+    #[arg(long, default_value_t = 16 * 1024 * 1024)]
+    output_send_buf_bytes: usize,
+
     /// Max accounts per snapshot segment
     #[arg(long, default_value_t = 10_000)]
     snapshot_segment_accounts: usize,
 
+    /// Spill the snapshot to disk once its in-memory accounts exceed this many bytes
+    #[arg(long)]
+    snapshot_memory_budget_bytes: Option<usize>,
+
+    /// Directory for snapshot spill files [default: <tmp>/ultra-rpc-bridge-<pid>]
+    #[arg(long)]
+    snapshot_spill_dir: Option<PathBuf>,
+
+    /// Persist known pubkeys and their last slots here, and resume from it on restart
+    #[arg(long)]
+    checkpoint_path: Option<PathBuf>,
+
+    /// Seconds between checkpoints
+    #[arg(long, default_value_t = 30)]
+    checkpoint_interval_secs: u64,
+
     /// Flush interval for delta batches in milliseconds
     #[arg(long, default_value_t = 5u64)]
     delta_flush_ms: u64,
@@ -53,48 +114,72 @@ struct Args {
     #[arg(long, default_value_t = 2048)]
     delta_batch_max: usize,
 
-    /// Optional Prometheus metrics listen address
-    #[arg(long)]
-    metrics_addr: Option<String>,
-}
+    /// Max delta batches held for a slow or absent RPC client before the oldest are dropped
+    #[arg(long, default_value_t = 100_000)]
+    delta_pending_max_batches: usize,
 
-#[derive(Clone, Serialize)]
-struct AccountWire {
-    pubkey: [u8; 32],
-    lamports: u64,
-    owner: [u8; 32],
-    executable: bool,
-    rent_epoch: u64,
-    data: Vec<u8>,
-}
+    /// Max bytes of delta batches held for a slow or absent RPC client
+    #[arg(long, default_value_t = 1 << 30)]
+    delta_pending_max_bytes: usize,
 
-#[derive(Clone, Serialize)]
-struct SnapshotWireSegment {
-    base_slot: u64,
-    accounts: Vec<AccountWire>,
-}
+    /// Forward slot status changes to the RPC
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    forward_slots: bool,
 
-#[derive(Clone, Serialize)]
-struct DeltaWire {
-    pubkey: [u8; 32],
-    slot: u64,
-    account: Option<AccountWire>,
-}
+    /// Forward transaction statuses to the RPC
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    forward_transactions: bool,
+
+    /// Compressions offered to the delta client, preferred first (none, lz4, zstd);
+    /// the client picks one it supports
+    #[arg(long, value_delimiter = ',', default_value = "none")]
+    delta_compression: Vec<FeedCompression>,
+
+    /// Delta frames smaller than this are sent uncompressed
+    #[arg(long, default_value_t = 4096)]
+    delta_compress_min_bytes: usize,
+
+    /// Only forward accounts owned by these programs (base58, comma separated)
+    #[arg(long, value_delimiter = ',', value_parser = filter::parse_pubkey)]
+    include_owner: Vec<[u8; 32]>,
+
+    /// Drop accounts owned by these programs (base58, comma separated)
+    #[arg(long, value_delimiter = ',', value_parser = filter::parse_pubkey)]
+    exclude_owner: Vec<[u8; 32]>,
+
+    /// Forward live account updates only once their slot reaches this commitment
+    #[arg(long, value_enum, default_value_t = Commitment::Processed)]
+    commitment: Commitment,
 
-#[derive(Clone, Serialize)]
-struct DeltaWireBatch {
-    updates: Vec<DeltaWire>,
+    /// Optional Prometheus metrics listen address
+    #[arg(long)]
+    metrics_addr: Option<String>,
 }
 
-#[derive(Clone, Serialize)]
-enum DeltaStreamMessage {
-    SnapshotComplete { slot: u64 },
-    Updates(DeltaWireBatch),
+/// Keep only the latest update per pubkey (highest slot, later arrival on a
+/// tie), in the position of its first update; returns how many were dropped.
+/// The RPC applies a batch in order, so the result is the same state.
+fn coalesce(updates: &mut Vec<AccountDelta>, index: &mut HashMap<[u8; 32], usize>) -> usize {
+    index.clear();
+    let before = updates.len();
+    let mut kept: Vec<AccountDelta> = Vec::with_capacity(before);
+    for d in updates.drain(..) {
+        match index.get(&d.pubkey) {
+            Some(&i) if kept[i].slot <= d.slot => kept[i] = d,
+            Some(_) => {}
+            None => {
+                index.insert(d.pubkey, kept.len());
+                kept.push(d);
+            }
+        }
+    }
+    *updates = kept;
+    before - updates.len()
 }
 
 async fn send_snapshot_complete(delta_tx: &mpsc::Sender<Vec<u8>>, slot: u64) -> Result<()> {
-    let message = DeltaStreamMessage::SnapshotComplete { slot };
-    let bytes = bincode::serialize(&message)
+    let message = CacheFeed::SnapshotComplete { slot };
+    let bytes = encode_cache_feed(&message)
         .with_context(|| format!("failed to serialize snapshot-complete marker for slot {slot}"))?;
     delta_tx
         .send(bytes)
@@ -102,13 +187,16 @@ async fn send_snapshot_complete(delta_tx: &mpsc::Sender<Vec<u8>>, slot: u64) ->
         .map_err(|e| anyhow!("delta channel send failed: {e}"))
 }
 
-async fn send_delta_updates(delta_tx: &mpsc::Sender<Vec<u8>>, batch: DeltaWireBatch) -> Result<()> {
-    let message = DeltaStreamMessage::Updates(batch);
-    let bytes = bincode::serialize(&message).context("failed to serialize delta batch message")?;
+/// Send one delta stream message: a batch of account updates, slots or transactions.
+/// Serialize `message` onto the delta channel; its size in bytes.
+async fn send_delta_message(delta_tx: &mpsc::Sender<Vec<u8>>, message: CacheFeed) -> Result<usize> {
+    let bytes = encode_cache_feed(&message).context("failed to serialize delta stream message")?;
+    let len = bytes.len();
     delta_tx
         .send(bytes)
         .await
-        .map_err(|e| anyhow!("delta channel send failed: {e}"))
+        .map_err(|e| anyhow!("delta channel send failed: {e}"))?;
+    Ok(len)
 }
 
 #[tokio::main]
@@ -129,340 +217,1009 @@ async fn main() -> Result<()> {
     }
 
     // Prepare output listeners (bridge acts as server for RPC to connect)
-    let (snapshot_tx, snapshot_rx) = mpsc::channel::<Vec<u8>>(16);
+    let (snapshot_tx, snapshot_rx) = mpsc::channel::<SnapshotRequest>(16);
     let (delta_tx, delta_rx) = mpsc::channel::<Vec<u8>>(8192);
+    let (served_tx, served_rx) = watch::channel(None);
+
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(output::tls_acceptor(&TlsFiles {
+            cert,
+            key,
+            client_ca: args.tls_client_ca.as_deref(),
+        })?),
+        _ => None,
+    };
+    let opts = OutputOpts {
+        send_buf_bytes: args.output_send_buf_bytes,
+        keepalive: (args.tcp_keepalive_secs > 0)
+            .then(|| Duration::from_secs(args.tcp_keepalive_secs)),
+        tls,
+    };
+    let snapshot_listener = OutputListener::bind(
+        "snapshot",
+        &args.snapshot_uds,
+        args.snapshot_tcp.as_deref(),
+        &opts,
+    )
+    .await?;
+    let delta_listener =
+        OutputListener::bind("delta", &args.delta_uds, args.delta_tcp.as_deref(), &opts).await?;
 
     // Start writers
-    tokio::spawn(run_snapshot_writer(args.snapshot_uds.clone(), snapshot_rx));
-    tokio::spawn(run_delta_writer(args.delta_uds.clone(), delta_rx));
+    tokio::spawn(snapshot::run_snapshot_writer(
+        snapshot_listener,
+        opts.clone(),
+        snapshot_tx,
+        served_tx,
+    ));
+    let pending = PendingBatches::new(args.delta_pending_max_batches, args.delta_pending_max_bytes);
+    let caps = Capabilities {
+        slots: args.forward_slots,
+        transactions: args.forward_transactions,
+        compression: args.delta_compression.clone(),
+    };
+    tokio::spawn(run_delta_writer(
+        delta_listener,
+        opts,
+        caps,
+        args.delta_compress_min_bytes,
+        delta_rx,
+        served_rx,
+        pending,
+    ));
 
     // Start reader and converter
-    run_bridge(args, snapshot_tx, delta_tx).await
+    run_bridge(args, snapshot_rx, delta_tx).await
 }
 
-async fn run_snapshot_writer(path: String, mut rx: mpsc::Receiver<Vec<u8>>) {
-    if let Err(e) = std::fs::remove_file(&path) {
-        if e.kind() != ErrorKind::NotFound {
-            warn!(%e, uds = %path, "failed to remove existing snapshot socket");
+/// Delta batches waiting for the RPC client, bounded by count and bytes;
+/// over either bound the oldest batches are dropped. A client that then
+/// reconnects is hydrated from the re-served snapshot, which already has them;
+/// a connected client that falls that far behind is disconnected so it does
+/// the same rather than miss them.
+struct PendingBatches {
+    batches: VecDeque<Bytes>,
+    bytes: usize,
+    max_batches: usize,
+    max_bytes: usize,
+    dropping: bool,
+    // Batches were dropped since the last `take_overflow`
+    overflowed: bool,
+}
+
+impl PendingBatches {
+    fn new(max_batches: usize, max_bytes: usize) -> Self {
+        Self {
+            batches: VecDeque::new(),
+            bytes: 0,
+            max_batches: max_batches.max(1),
+            max_bytes,
+            dropping: false,
+            overflowed: false,
         }
     }
-    let listener = match UnixListener::bind(&path) {
-        Ok(l) => l,
-        Err(e) => {
-            error!(%e, uds = %path, "snapshot bind failed");
-            return;
+
+    fn push_back(&mut self, batch: Bytes) {
+        self.bytes += batch.len();
+        self.batches.push_back(batch);
+        let (mut dropped, mut dropped_bytes) = (0u64, 0usize);
+        while self.batches.len() > 1
+            && (self.batches.len() > self.max_batches || self.bytes > self.max_bytes)
+        {
+            if let Some(old) = self.batches.pop_front() {
+                self.bytes -= old.len();
+                dropped += 1;
+                dropped_bytes += old.len();
+            }
         }
-    };
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o660)) {
-            warn!(%e, uds = %path, "failed to set snapshot socket permissions");
-        }
-    }
-    info!(uds = %path, "snapshot writer listening");
-
-    // Accept a single client and stream segments, then close.
-    match listener.accept().await {
-        Ok((sock, _addr)) => {
-            let mut framed = FramedWrite::new(sock, LengthDelimitedCodec::new());
-            while let Some(seg) = rx.recv().await {
-                let bytes = Bytes::from(seg);
-                if let Err(e) = framed.send(bytes).await {
-                    error!(%e, "snapshot write error");
-                    break;
-                }
+        if dropped > 0 {
+            self.overflowed = true;
+            counter!("rpc_bridge_delta_pending_dropped_batches_total").increment(dropped);
+            counter!("rpc_bridge_delta_pending_dropped_bytes_total")
+                .increment(dropped_bytes as u64);
+            if !self.dropping {
+                warn!(
+                    batches = self.batches.len(),
+                    bytes = self.bytes,
+                    "delta backlog full; dropping oldest batches"
+                );
+                self.dropping = true;
             }
-            // Drop framed to close the stream; solana-ultra-rpc will complete snapshot.
-            info!("snapshot stream closed");
         }
-        Err(e) => error!(%e, "snapshot accept failed"),
+        self.publish();
     }
-}
 
-async fn run_delta_writer(path: String, mut rx: mpsc::Receiver<Vec<u8>>) {
-    if let Err(e) = std::fs::remove_file(&path) {
-        if e.kind() != ErrorKind::NotFound {
-            warn!(%e, uds = %path, "failed to remove existing delta socket");
-        }
+    /// Put a batch back at the head (a failed send, or a marker that must go first).
+    fn push_front(&mut self, batch: Bytes) {
+        self.bytes += batch.len();
+        self.batches.push_front(batch);
+        self.publish();
     }
-    let listener = match UnixListener::bind(&path) {
-        Ok(l) => l,
-        Err(e) => {
-            error!(%e, uds = %path, "delta bind failed");
-            return;
-        }
-    };
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        if let Err(e) = std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o660)) {
-            warn!(%e, uds = %path, "failed to set delta socket permissions");
+
+    fn pop_front(&mut self) -> Option<Bytes> {
+        let batch = self.batches.pop_front()?;
+        self.bytes -= batch.len();
+        if self.batches.is_empty() {
+            self.dropping = false;
         }
+        self.publish();
+        Some(batch)
     }
-    info!(uds = %path, "delta writer listening");
 
-    // Accept one client and keep streaming forever. If client disconnects, re-accept.
-    let mut pending_batches: VecDeque<Bytes> = VecDeque::new();
-    loop {
-        match listener.accept().await {
-            Ok((sock, _)) => {
-                #[cfg(unix)]
-                {
-                    use socket2::SockRef;
-                    let _ = SockRef::from(&sock).set_send_buffer_size(16 * 1024 * 1024);
-                }
-                let mut framed = FramedWrite::new(sock, LengthDelimitedCodec::new());
-                info!("delta client connected");
-                loop {
-                    if pending_batches.is_empty() {
-                        if rx.is_closed() {
-                            info!("delta channel closed; shutting down writer");
-                            return;
-                        }
-                        match rx.recv().await {
-                            Some(batch) => pending_batches.push_back(Bytes::from(batch)),
-                            None => {
-                                info!("delta channel closed; shutting down writer");
-                                return;
-                            }
-                        }
-                    }
+    fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
 
-                    while let Ok(batch) = rx.try_recv() {
-                        pending_batches.push_back(Bytes::from(batch));
-                    }
+    /// Whether batches were dropped since the last call.
+    fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
 
-                    let Some(bytes) = pending_batches.pop_front() else {
-                        continue;
-                    };
+    fn publish(&self) {
+        gauge!("rpc_bridge_delta_pending_bytes").set(self.bytes as f64);
+        gauge!("rpc_bridge_delta_pending_batches").set(self.batches.len() as f64);
+    }
+}
 
-                    let to_send = bytes.clone();
-                    if let Err(e) = framed.send(to_send).await {
-                        warn!(%e, "delta write error; waiting for new client");
-                        pending_batches.push_front(bytes);
-                        break;
-                    }
+async fn run_delta_writer(
+    listener: OutputListener,
+    opts: OutputOpts,
+    caps: Capabilities,
+    compress_min_bytes: usize,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    served: watch::Receiver<Option<u64>>,
+    mut pending: PendingBatches,
+) {
+    // Accept one client and keep streaming forever. If client disconnects, re-accept.
+    loop {
+        // Between clients, keep draining the channel into the bounded backlog so
+        // the bridge itself never stalls on a missing RPC.
+        let client: Accepted = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!(%e, "delta accept failed; retrying");
+                    time::sleep(Duration::from_millis(200)).await;
+                    continue;
                 }
-
-                if rx.is_closed() && pending_batches.is_empty() {
+            },
+            batch = rx.recv() => match batch {
+                Some(batch) => {
+                    pending.push_back(Bytes::from(batch));
+                    continue;
+                }
+                None => {
                     info!("delta channel closed; shutting down writer");
                     return;
                 }
+            },
+        };
+        let mut sock = match client.establish(&opts).await {
+            Ok(sock) => sock,
+            Err(e) => {
+                warn!(%e, "delta client setup failed");
+                continue;
             }
+        };
+        let hello = caps.hello(*served.borrow());
+        let compression = match handshake::greet(&mut sock, &hello).await {
+            Ok(compression) => compression,
             Err(e) => {
-                warn!(%e, "delta accept failed; retrying");
-                time::sleep(Duration::from_millis(200)).await;
+                counter!("rpc_bridge_delta_handshake_failures_total").increment(1);
+                warn!(%e, "delta client handshake failed; dropping it");
+                continue;
+            }
+        };
+        let mut framed = FramedWrite::new(sock, BytesCodec::new());
+        info!(?compression, "delta client connected");
+        // What was dropped with no client connected is in the snapshot it hydrated from.
+        pending.take_overflow();
+        // The client hydrated from a served snapshot; tell it so before the
+        // deltas it has not seen.
+        if let Some(slot) = *served.borrow() {
+            let marker = CacheFeed::SnapshotComplete { slot };
+            match encode_cache_feed(&marker) {
+                Ok(bytes) => pending.push_front(Bytes::from(bytes)),
+                Err(e) => error!(%e, slot, "failed to serialize snapshot-complete marker"),
             }
         }
+        loop {
+            if pending.is_empty() {
+                match rx.recv().await {
+                    Some(batch) => pending.push_back(Bytes::from(batch)),
+                    None => {
+                        info!("delta channel closed; shutting down writer");
+                        return;
+                    }
+                }
+            }
+
+            while let Ok(batch) = rx.try_recv() {
+                pending.push_back(Bytes::from(batch));
+            }
+            if pending.take_overflow() {
+                counter!("rpc_bridge_delta_overflow_disconnects_total").increment(1);
+                warn!("delta client fell behind the backlog bound; disconnecting it to resync");
+                break;
+            }
+
+            let Some(bytes) = pending.pop_front() else {
+                continue;
+            };
+
+            // Pending batches stay uncompressed, since the next client may
+            // pick another compression
+            let to_send = compress(&bytes, compression, compress_min_bytes);
+            if let Err(e) = framed.send(to_send).await {
+                warn!(%e, "delta write error; waiting for new client");
+                pending.push_front(bytes);
+                break;
+            }
+        }
+    }
+}
+
+/// `frame` compressed for the delta client, or as is when that does not pay.
+fn compress(frame: &Bytes, compression: FeedCompression, min_bytes: usize) -> Bytes {
+    match compress_cache_feed_frame(frame, compression, min_bytes) {
+        Ok(Some(packed)) => {
+            counter!("rpc_bridge_delta_compression_input_bytes_total")
+                .increment(frame.len() as u64);
+            counter!("rpc_bridge_delta_compression_output_bytes_total")
+                .increment(packed.len() as u64);
+            Bytes::from(packed)
+        }
+        Ok(None) => frame.clone(),
+        Err(e) => {
+            warn!(%e, "delta frame compression failed; sending it uncompressed");
+            frame.clone()
+        }
     }
 }
 
 async fn run_bridge(
     args: Args,
-    snapshot_tx: mpsc::Sender<Vec<u8>>,
+    mut snapshot_rx: mpsc::Receiver<SnapshotRequest>,
     delta_tx: mpsc::Sender<Vec<u8>>,
 ) -> Result<()> {
-    // Bind input UDS and accept producers (e.g., ys-consumer or load generator)
-    if std::path::Path::new(&args.input_uds).exists() {
-        let _ = std::fs::remove_file(&args.input_uds);
-    }
-    let listener = UnixListener::bind(&args.input_uds)
-        .with_context(|| format!("bind {} failed", &args.input_uds))?;
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        let _ = std::fs::set_permissions(&args.input_uds, std::fs::Permissions::from_mode(0o660));
-    }
-    info!(uds = %args.input_uds, "bridge input listening");
-
-    // Snapshot and batching state lives across client connections
-    let mut snapshot_accounts: HashMap<[u8; 32], AccountWire> = HashMap::new();
-    let mut snapshot_active = true;
-    let mut snapshot_last_slot: u64 = 0;
-    let mut snapshot_sender: Option<mpsc::Sender<Vec<u8>>> = Some(snapshot_tx);
-    let mut snapshot_complete_sent = false;
-    let mut delta_batch: Vec<DeltaWire> = Vec::with_capacity(args.delta_batch_max);
-    let mut last_flush = Instant::now();
-    let base_flush = Duration::from_millis(args.delta_flush_ms);
-    let mut cur_flush = base_flush;
-    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+    // Bind input UDS; each producer (ys-consumer, plugin writer, load generator)
+    // gets its own reader task feeding this one
+    let (input_tx, mut input_rx) = mpsc::channel::<Input>(INPUT_QUEUE);
+    let filter = OwnerFilter::new(&args.include_owner, &args.exclude_owner);
+    if filter.is_active() {
+        info!(
+            include = args.include_owner.len(),
+            exclude = args.exclude_owner.len(),
+            "filtering accounts by owner"
+        );
+    }
+    input::listen(&args.input_uds, Arc::new(filter), input_tx)?;
 
+    let mut bridge = Bridge::new(&args, delta_tx);
+    bridge.load_checkpoint()?;
+    let mut checkpoint_tick =
+        time::interval(Duration::from_secs(args.checkpoint_interval_secs.max(1)));
+    let mut inputs: Vec<Input> = Vec::with_capacity(1024);
     loop {
-        let (mut sock, _) = listener.accept().await?;
-        #[cfg(unix)]
-        {
-            let _ = socket2::SockRef::from(&sock).set_recv_buffer_size(32 * 1024 * 1024);
+        tokio::select! {
+            n = input_rx.recv_many(&mut inputs, 1024) => {
+                if n == 0 {
+                    bridge.final_checkpoint().await;
+                    return Ok(());
+                }
+                for input in inputs.drain(..) {
+                    bridge.on_input(input).await?;
+                }
+            }
+            Some(reply) = snapshot_rx.recv() => bridge.serve_snapshot(reply)?,
+            _ = checkpoint_tick.tick(), if bridge.checkpoint_path.is_some() => bridge.checkpoint(),
+            _ = time::sleep(bridge.cur_flush) => {}
         }
-        info!("bridge accepted producer connection");
-        let mut buf = BytesMut::with_capacity(1 << 20);
-        loop {
-            let n = sock.read_buf(&mut buf).await?;
-            if n == 0 {
-                info!("producer disconnected");
-                break;
+        bridge.maybe_flush(input_rx.len()).await?;
+    }
+}
+
+/// Records queued from producers before the adaptive flush counts as under pressure.
+const INPUT_QUEUE: usize = 8192;
+
+/// Snapshot and batching state, shared by every producer connection.
+struct Bridge {
+    snapshot_segment_accounts: usize,
+    delta_batch_max: usize,
+    // Startup accounts, then kept current with every delta batch sent
+    snapshot: SnapshotStore,
+    snapshot_active: bool,
+    snapshot_last_slot: u64,
+    // Snapshot clients waiting for startup to finish
+    snapshot_waiting: Vec<SnapshotRequest>,
+    snapshot_complete_sent: bool,
+    // Producers still streaming startup accounts; the snapshot waits for all
+    in_startup: HashSet<Arc<str>>,
+    saw_live: bool,
+    checkpoint_path: Option<PathBuf>,
+    // Last slot per pubkey changed since the last checkpoint (`None` once
+    // deleted); only tracked with a checkpoint path
+    known_changes: checkpoint::Changes,
+    // What the checkpoint writer last saved; only its task touches it
+    written: Arc<Mutex<Checkpoint>>,
+    // Checkpoint being resumed from; startup accounts are taken out of it as
+    // they arrive, so what is left at the end was deleted
+    resume: Option<Checkpoint>,
+    resume_changes: Vec<AccountDelta>,
+    checkpoint_task: Option<tokio::task::JoinHandle<()>>,
+    // Pubkeys forwarded so far, kept only with an owner filter so an account
+    // leaving the filter can be deleted
+    admitted: Option<HashSet<[u8; 32]>>,
+    delta_tx: mpsc::Sender<Vec<u8>>,
+    delta_batch: Vec<AccountDelta>,
+    // Slot and transaction statuses, sent after the account updates of a flush
+    slot_batch: Vec<SlotStatus>,
+    tx_batch: Vec<TxUpdate>,
+    forward_slots: bool,
+    forward_transactions: bool,
+    gate: CommitGate,
+    coalesce_index: HashMap<[u8; 32], usize>,
+    last_flush: Instant,
+    base_flush: Duration,
+    cur_flush: Duration,
+    // Decode time of the oldest record waiting in a batch, for latency
+    oldest_queued: Option<Instant>,
+    last_delta: Instant,
+}
+
+impl Bridge {
+    fn new(args: &Args, delta_tx: mpsc::Sender<Vec<u8>>) -> Self {
+        let base_flush = Duration::from_millis(args.delta_flush_ms).max(Duration::from_millis(1));
+        Self {
+            snapshot_segment_accounts: args.snapshot_segment_accounts,
+            delta_batch_max: args.delta_batch_max,
+            snapshot: SnapshotStore::new(
+                args.snapshot_memory_budget_bytes,
+                args.snapshot_spill_dir.clone().unwrap_or_else(|| {
+                    std::env::temp_dir().join(format!("ultra-rpc-bridge-{}", std::process::id()))
+                }),
+            ),
+            snapshot_active: true,
+            snapshot_last_slot: 0,
+            snapshot_waiting: Vec::new(),
+            snapshot_complete_sent: false,
+            in_startup: HashSet::new(),
+            saw_live: false,
+            checkpoint_path: args.checkpoint_path.clone(),
+            known_changes: HashMap::new(),
+            written: Arc::default(),
+            resume: None,
+            resume_changes: Vec::new(),
+            checkpoint_task: None,
+            admitted: (!args.include_owner.is_empty() || !args.exclude_owner.is_empty())
+                .then(HashSet::new),
+            delta_tx,
+            delta_batch: Vec::with_capacity(args.delta_batch_max),
+            slot_batch: Vec::new(),
+            tx_batch: Vec::new(),
+            forward_slots: args.forward_slots,
+            forward_transactions: args.forward_transactions,
+            gate: CommitGate::new(args.commitment),
+            coalesce_index: HashMap::new(),
+            last_flush: Instant::now(),
+            oldest_queued: None,
+            last_delta: Instant::now(),
+            base_flush,
+            cur_flush: base_flush,
+        }
+    }
+
+    async fn on_input(&mut self, input: Input) -> Result<()> {
+        let Input {
+            producer,
+            event,
+            at,
+        } = input;
+        match event {
+            Event::Connected => {
+                if self.snapshot_active {
+                    self.in_startup.insert(producer);
+                }
             }
-            // decode frames
-            loop {
-                match decode_record_from_slice(&buf[..], &mut scratch) {
-                    Ok((rec, consumed)) => {
-                        buf.advance(consumed);
-                        match rec {
-                            Record::Account(a) => {
-                                let wire = AccountWire {
-                                    pubkey: a.pubkey,
-                                    lamports: a.lamports,
-                                    owner: a.owner,
-                                    executable: a.executable,
-                                    rent_epoch: a.rent_epoch,
-                                    data: a.data,
-                                };
-                                if snapshot_active && a.is_startup {
-                                    snapshot_last_slot = snapshot_last_slot.max(a.slot);
-                                    snapshot_accounts.insert(a.pubkey, wire);
-                                    gauge!("rpc_bridge_snapshot_accounts")
-                                        .set(snapshot_accounts.len() as f64);
-                                } else {
-                                    if snapshot_active {
-                                        snapshot_active = false;
-                                        if let Some(tx) = snapshot_sender.take() {
-                                            if let Err(e) = emit_snapshot_segments(
-                                                snapshot_last_slot,
-                                                args.snapshot_segment_accounts,
-                                                &snapshot_accounts,
-                                                &tx,
-                                            )
-                                            .await
-                                            {
-                                                error!(%e, slot = snapshot_last_slot, "snapshot emission failed");
-                                                return Err(e);
-                                            }
-                                            // drop tx to close snapshot stream
-                                        }
-                                        if !snapshot_complete_sent {
-                                            if let Err(e) = send_snapshot_complete(
-                                                &delta_tx,
-                                                snapshot_last_slot,
-                                            )
-                                            .await
-                                            {
-                                                error!(%e, slot = snapshot_last_slot, "failed to notify snapshot completion");
-                                                return Err(e);
-                                            }
-                                            snapshot_complete_sent = true;
-                                        }
-                                        info!(
-                                            accounts = snapshot_accounts.len(),
-                                            slot = snapshot_last_slot,
-                                            "snapshot emitted"
-                                        );
-                                    } else if !snapshot_complete_sent {
-                                        if let Err(e) =
-                                            send_snapshot_complete(&delta_tx, snapshot_last_slot)
-                                                .await
-                                        {
-                                            error!(%e, slot = snapshot_last_slot, "failed to notify snapshot completion");
-                                            return Err(e);
-                                        }
-                                        snapshot_complete_sent = true;
-                                    }
-                                    delta_batch.push(DeltaWire {
-                                        pubkey: a.pubkey,
-                                        slot: a.slot,
-                                        account: Some(wire),
-                                    });
-                                }
-                            }
-                            Record::Slot { .. } => {}
-                            _ => {}
-                        }
+            Event::Disconnected => self.leave_startup(&producer, false).await?,
+            Event::Record(Record::Account(a)) => {
+                let wire = AccountState {
+                    pubkey: a.pubkey,
+                    lamports: a.lamports,
+                    owner: a.owner,
+                    executable: a.executable,
+                    rent_epoch: a.rent_epoch,
+                    data: a.data,
+                };
+                if let Some(admitted) = &mut self.admitted {
+                    admitted.insert(a.pubkey);
+                }
+                if self.snapshot_active && a.is_startup {
+                    self.snapshot_last_slot = self.snapshot_last_slot.max(a.slot);
+                    if self.checkpoint_path.is_some() {
+                        self.known_changes.insert(a.pubkey, Some(a.slot));
                     }
-                    Err(faststreams::StreamError::De(_)) => break,
-                    Err(faststreams::StreamError::BadHeader) => {
-                        counter!("rpc_bridge_bad_header_total").increment(1);
-                        buf.advance(1);
-                        break;
+                    if let Some(resume) = &mut self.resume {
+                        match resume.slots.remove(&a.pubkey) {
+                            Some(slot) if slot >= a.slot => {}
+                            _ => self.resume_changes.push(AccountDelta {
+                                pubkey: a.pubkey,
+                                slot: a.slot,
+                                account: Some(wire.clone()),
+                            }),
+                        }
                     }
-                    Err(_) => {
-                        buf.advance(1);
-                        break;
+                    self.snapshot.insert(a.pubkey, Some(wire))?;
+                    self.snapshot.publish();
+                    return Ok(());
+                }
+                self.leave_startup(&producer, true).await?;
+                // While another producer is still in startup, live updates wait
+                // here until the snapshot is out.
+                let delta = AccountDelta {
+                    pubkey: a.pubkey,
+                    slot: a.slot,
+                    account: Some(wire),
+                };
+                if let Some(delta) = self.gate.admit(delta) {
+                    self.delta_batch.push(delta);
+                }
+            }
+            Event::Excluded { pubkey, slot } => {
+                self.leave_startup(&producer, true).await?;
+                if self.admitted.as_mut().is_some_and(|a| a.remove(&pubkey)) {
+                    let delta = AccountDelta {
+                        pubkey,
+                        slot,
+                        account: None,
+                    };
+                    if let Some(delta) = self.gate.admit(delta) {
+                        self.delta_batch.push(delta);
                     }
                 }
             }
-
-            // Adaptive flush: shrink delay under pressure, restore slowly when low
-            if delta_batch.len() >= args.delta_batch_max * 3 / 4 || buf.len() >= (1 << 18) {
-                cur_flush = base_flush / 2;
-                if cur_flush < Duration::from_millis(1) {
-                    cur_flush = Duration::from_millis(1);
+            Event::Record(Record::Slot {
+                slot,
+                parent,
+                status,
+            }) => {
+                self.gate
+                    .on_slot(slot, parent, status, &mut self.delta_batch);
+                if self.forward_slots {
+                    self.slot_batch.push(SlotStatus {
+                        slot,
+                        parent,
+                        status,
+                    });
+                }
+            }
+            Event::Record(Record::Tx(tx)) => {
+                if self.forward_transactions {
+                    self.tx_batch.push(tx);
                 }
-            } else if cur_flush < base_flush {
-                cur_flush = (cur_flush + Duration::from_millis(1)).min(base_flush);
             }
+            Event::Record(Record::EndOfStartup) => self.leave_startup(&producer, true).await?,
+            Event::Record(Record::Block(_)) => {}
+        }
+        let queued = !(self.delta_batch.is_empty()
+            && self.slot_batch.is_empty()
+            && self.tx_batch.is_empty());
+        if queued && self.oldest_queued.is_none() {
+            self.oldest_queued = Some(at);
+        }
+        Ok(())
+    }
 
-            // Flush deltas periodically
-            if !delta_batch.is_empty()
-                && (delta_batch.len() >= args.delta_batch_max
-                    || last_flush.elapsed() >= cur_flush)
-            {
-                if !snapshot_complete_sent {
-                    if let Err(e) = send_snapshot_complete(&delta_tx, snapshot_last_slot).await {
-                        error!(%e, slot = snapshot_last_slot, "failed to notify snapshot completion");
-                        return Err(e);
-                    }
-                    snapshot_complete_sent = true;
+    /// `producer` is done with startup (`live`) or gone; emit the snapshot once
+    /// no connected producer is still sending startup accounts.
+    async fn leave_startup(&mut self, producer: &str, live: bool) -> Result<()> {
+        self.saw_live |= live;
+        if !self.snapshot_active {
+            return self.ensure_snapshot_complete().await;
+        }
+        self.in_startup.remove(producer);
+        if self.saw_live && self.in_startup.is_empty() {
+            self.finish_snapshot().await?;
+        }
+        Ok(())
+    }
+
+    async fn finish_snapshot(&mut self) -> Result<()> {
+        self.snapshot_active = false;
+        for reply in std::mem::take(&mut self.snapshot_waiting) {
+            self.serve_snapshot(reply)?;
+        }
+        self.ensure_snapshot_complete().await?;
+        self.send_resume_changes().await?;
+        info!(
+            resident = self.snapshot.resident(),
+            spilled = self.snapshot.is_spilled(),
+            slot = self.snapshot_last_slot,
+            "snapshot emitted"
+        );
+        Ok(())
+    }
+
+    /// Load the checkpoint to resume from, if there is one.
+    fn load_checkpoint(&mut self) -> Result<()> {
+        let Some(path) = &self.checkpoint_path else {
+            return Ok(());
+        };
+        self.resume = Checkpoint::load(path)?;
+        if let Some(resume) = &self.resume {
+            info!(
+                path = %path.display(),
+                slot = resume.slot,
+                accounts = resume.slots.len(),
+                "resuming from checkpoint"
+            );
+        }
+        Ok(())
+    }
+
+    /// After a resumed startup, send what changed since the checkpoint as an
+    /// incremental snapshot: changed accounts and deletions, as deltas.
+    async fn send_resume_changes(&mut self) -> Result<()> {
+        let Some(resume) = self.resume.take() else {
+            return Ok(());
+        };
+        let mut changes = std::mem::take(&mut self.resume_changes);
+        let slot = self.snapshot_last_slot;
+        let removed = resume.slots.len();
+        changes.extend(resume.slots.into_keys().map(|pubkey| AccountDelta {
+            pubkey,
+            slot,
+            account: None,
+        }));
+        coalesce(&mut changes, &mut self.coalesce_index);
+        let changed = changes.len() - removed;
+        counter!("rpc_bridge_resume_changed_accounts_total").increment(changed as u64);
+        counter!("rpc_bridge_resume_removed_accounts_total").increment(removed as u64);
+        info!(
+            since = resume.slot,
+            slot, changed, removed, "sending incremental snapshot since checkpoint"
+        );
+        let mut changes = changes.into_iter().peekable();
+        while changes.peek().is_some() {
+            let batch: Vec<AccountDelta> =
+                changes.by_ref().take(self.delta_batch_max.max(1)).collect();
+            self.send(CacheFeed::Deltas(batch)).await?;
+        }
+        Ok(())
+    }
+
+    /// Write a checkpoint in the background, unless startup is still running or
+    /// the previous one is still being written.
+    fn checkpoint(&mut self) {
+        let Some(path) = self.checkpoint_path.clone() else {
+            return;
+        };
+        if self.snapshot_active
+            || self
+                .checkpoint_task
+                .as_ref()
+                .is_some_and(|t| !t.is_finished())
+        {
+            return;
+        }
+        // Only the changes move here; the writer applies them to its own copy
+        let slot = self.snapshot_last_slot;
+        let changes = std::mem::take(&mut self.known_changes);
+        let written = self.written.clone();
+        self.checkpoint_task = Some(tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let mut checkpoint = written.lock().unwrap_or_else(|p| p.into_inner());
+            checkpoint.apply(slot, changes);
+            match checkpoint.save(&path) {
+                Ok(()) => {
+                    counter!("rpc_bridge_checkpoints_total").increment(1);
+                    histogram!("rpc_bridge_checkpoint_seconds")
+                        .record(started.elapsed().as_secs_f64());
+                    gauge!("rpc_bridge_checkpoint_slot").set(checkpoint.slot as f64);
                 }
-                let batch = DeltaWireBatch {
-                    updates: std::mem::take(&mut delta_batch),
-                };
-                if let Err(e) = send_delta_updates(&delta_tx, batch).await {
-                    error!(%e, "delta channel send failed");
-                    return Err(e);
+                Err(e) => {
+                    counter!("rpc_bridge_checkpoint_failures_total").increment(1);
+                    warn!(%e, path = %path.display(), "bridge checkpoint failed");
                 }
-                counter!("rpc_bridge_delta_batches").increment(1);
-                last_flush = Instant::now();
             }
+        }));
+    }
+
+    /// Wait for any checkpoint in flight, then write one last one.
+    async fn final_checkpoint(&mut self) {
+        if let Some(task) = self.checkpoint_task.take() {
+            let _ = task.await;
+        }
+        self.checkpoint();
+        if let Some(task) = self.checkpoint_task.take() {
+            let _ = task.await;
         }
     }
+
+    /// Hand a snapshot client the current image, or park it until startup is over.
+    fn serve_snapshot(&mut self, reply: SnapshotRequest) -> Result<()> {
+        if self.snapshot_active {
+            self.snapshot_waiting.push(reply);
+            return Ok(());
+        }
+        let image = self
+            .snapshot
+            .image(self.snapshot_last_slot, self.snapshot_segment_accounts)
+            .inspect_err(
+                |e| error!(%e, slot = self.snapshot_last_slot, "snapshot emission failed"),
+            )?;
+        // The client may have gone away meanwhile
+        let _ = reply.send(image);
+        Ok(())
+    }
+
+    async fn ensure_snapshot_complete(&mut self) -> Result<()> {
+        if !self.snapshot_complete_sent {
+            if let Err(e) = send_snapshot_complete(&self.delta_tx, self.snapshot_last_slot).await {
+                error!(%e, slot = self.snapshot_last_slot, "failed to notify snapshot completion");
+                return Err(e);
+            }
+            self.snapshot_complete_sent = true;
+        }
+        Ok(())
+    }
+
+    /// Send the pending delta batch if it is full or due; `backlog` is the
+    /// number of records still queued from producers.
+    async fn maybe_flush(&mut self, backlog: usize) -> Result<()> {
+        // Adaptive flush: shrink delay under pressure, restore slowly when low
+        if self.delta_batch.len() >= self.delta_batch_max * 3 / 4 || backlog >= INPUT_QUEUE / 4 {
+            self.cur_flush = (self.base_flush / 2).max(Duration::from_millis(1));
+        } else if self.cur_flush < self.base_flush {
+            self.cur_flush = (self.cur_flush + Duration::from_millis(1)).min(self.base_flush);
+        }
+
+        gauge!("rpc_bridge_seconds_since_last_delta").set(self.last_delta.elapsed().as_secs_f64());
+
+        // Flush deltas periodically, never ahead of the snapshot
+        let queued = self.delta_batch.len() + self.tx_batch.len();
+        if self.snapshot_active
+            || (queued == 0 && self.slot_batch.is_empty())
+            || (queued < self.delta_batch_max && self.last_flush.elapsed() < self.cur_flush)
+        {
+            return Ok(());
+        }
+        self.ensure_snapshot_complete().await?;
+        self.flush_updates().await?;
+        // Slots go after the updates they settle, so a client never sees a
+        // slot confirmed before its accounts
+        if !self.slot_batch.is_empty() {
+            let slots = std::mem::take(&mut self.slot_batch);
+            counter!("rpc_bridge_slot_updates_total").increment(slots.len() as u64);
+            self.send(CacheFeed::Slots(slots)).await?;
+        }
+        if !self.tx_batch.is_empty() {
+            let txs = std::mem::take(&mut self.tx_batch);
+            counter!("rpc_bridge_tx_statuses_total").increment(txs.len() as u64);
+            self.send(CacheFeed::Transactions(txs)).await?;
+        }
+        if let Some(at) = self.oldest_queued.take() {
+            histogram!("rpc_bridge_decode_to_send_seconds").record(at.elapsed().as_secs_f64());
+        }
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+
+    async fn flush_updates(&mut self) -> Result<()> {
+        if self.delta_batch.is_empty() {
+            return Ok(());
+        }
+        let mut batch = std::mem::take(&mut self.delta_batch);
+        let coalesced = coalesce(&mut batch, &mut self.coalesce_index);
+        if coalesced > 0 {
+            counter!("rpc_bridge_delta_coalesced_total").increment(coalesced as u64);
+        }
+        counter!("rpc_bridge_delta_updates_total").increment(batch.len() as u64);
+        histogram!("rpc_bridge_delta_batch_updates").record(batch.len() as f64);
+        // Keep the snapshot current for clients that connect later
+        for d in &batch {
+            self.snapshot_last_slot = self.snapshot_last_slot.max(d.slot);
+            self.snapshot.insert(d.pubkey, d.account.clone())?;
+            if self.checkpoint_path.is_some() {
+                let last = d.account.as_ref().map(|_| d.slot);
+                self.known_changes.insert(d.pubkey, last);
+            }
+        }
+        self.snapshot.publish();
+        let bytes = self.send(CacheFeed::Deltas(batch)).await?;
+        histogram!("rpc_bridge_delta_batch_bytes").record(bytes as f64);
+        counter!("rpc_bridge_delta_batches").increment(1);
+        self.last_delta = Instant::now();
+        Ok(())
+    }
+
+    async fn send(&self, message: CacheFeed) -> Result<usize> {
+        send_delta_message(&self.delta_tx, message)
+            .await
+            .inspect_err(|e| error!(%e, "delta channel send failed"))
+    }
 }
 
-async fn emit_snapshot_segments(
-    base_slot: u64,
-    chunk_size: usize,
-    accounts: &HashMap<[u8; 32], AccountWire>,
-    tx: &mpsc::Sender<Vec<u8>>,
-) -> Result<()> {
-    if accounts.is_empty() {
-        return Ok(());
-    }
-    let mut current: Vec<AccountWire> = Vec::with_capacity(chunk_size);
-    for (_k, v) in accounts.iter() {
-        current.push(v.clone());
-        if current.len() >= chunk_size {
-            let accounts = std::mem::take(&mut current);
-            let seg = SnapshotWireSegment {
-                base_slot,
-                accounts,
-            };
-            let bytes = bincode::serialize(&seg).with_context(|| {
-                format!("failed to serialize snapshot segment for slot {base_slot}")
-            })?;
-            tx.send(bytes)
-                .await
-                .map_err(|e| anyhow!("snapshot channel send failed: {e}"))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::AccountUpdate;
+
+    fn account(n: u8, slot: u64, is_startup: bool) -> Event {
+        Event::Record(Record::Account(AccountUpdate {
+            slot,
+            is_startup,
+            pubkey: [n; 32],
+            lamports: 1,
+            owner: [0; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![n],
+        }))
+    }
+
+    #[test]
+    fn pending_batches_drop_the_oldest_over_either_bound() {
+        let mut pending = PendingBatches::new(3, 10);
+        for n in 0..4u8 {
+            pending.push_back(Bytes::from(vec![n; 2]));
         }
+        assert_eq!(pending.batches.len(), 3);
+        assert_eq!(pending.pop_front().unwrap()[0], 1);
+        pending.push_back(Bytes::from(vec![9; 8]));
+        // 2 + 2 + 8 bytes is over 10, so batch 2 goes
+        assert_eq!(pending.bytes, 10);
+        assert_eq!(pending.pop_front().unwrap()[0], 3);
+        // A batch over the byte bound on its own is still kept
+        pending.push_back(Bytes::from(vec![7; 20]));
+        assert_eq!(pending.batches.len(), 1);
+        assert_eq!(pending.bytes, 20);
+        assert!(pending.take_overflow());
+        assert!(!pending.take_overflow());
+        pending.push_back(Bytes::from(vec![1; 2]));
+        assert!(pending.take_overflow());
     }
-    if !current.is_empty() {
-        let seg = SnapshotWireSegment {
-            base_slot,
-            accounts: current,
+
+    #[test]
+    fn coalesces_to_the_latest_update_per_pubkey() {
+        let update = |n: u8, slot: u64, lamports: u64| AccountDelta {
+            pubkey: [n; 32],
+            slot,
+            account: Some(AccountState {
+                pubkey: [n; 32],
+                lamports,
+                owner: [0; 32],
+                executable: false,
+                rent_epoch: 0,
+                data: Vec::new(),
+            }),
+        };
+        let mut updates = vec![
+            update(1, 10, 1),
+            update(2, 10, 1),
+            update(1, 11, 2),
+            update(1, 9, 3), // late and older: dropped
+            update(2, 10, 4),
+        ];
+        let dropped = coalesce(&mut updates, &mut HashMap::new());
+        assert_eq!(dropped, 3);
+        let got: Vec<_> = updates
+            .iter()
+            .map(|d| (d.pubkey[0], d.slot, d.account.as_ref().unwrap().lamports))
+            .collect();
+        assert_eq!(got, vec![(1, 11, 2), (2, 10, 4)]);
+    }
+
+    #[tokio::test]
+    async fn forwards_slots_and_transactions_after_updates() {
+        let args = Args::parse_from(["ultra-rpc-bridge", "--commitment", "confirmed"]);
+        let (delta_tx, mut delta_rx) = mpsc::channel(16);
+        let mut bridge = Bridge::new(&args, delta_tx);
+        let input = |event| Input {
+            producer: "a".into(),
+            event,
+            at: Instant::now(),
+        };
+        let slot = |status| {
+            Event::Record(Record::Slot {
+                slot: 5,
+                parent: Some(4),
+                status,
+            })
         };
-        let bytes = bincode::serialize(&seg).with_context(|| {
-            format!("failed to serialize tail snapshot segment for slot {base_slot}")
-        })?;
-        tx.send(bytes)
+        let tx = TxUpdate {
+            slot: 5,
+            signature: [9; 64],
+            err: None,
+            vote: false,
+        };
+
+        bridge.on_input(input(slot(0))).await.unwrap();
+        bridge.on_input(input(account(1, 5, false))).await.unwrap();
+        bridge
+            .on_input(input(Event::Record(Record::Tx(tx.clone()))))
             .await
-            .map_err(|e| anyhow!("snapshot channel send failed: {e}"))?;
+            .unwrap();
+        bridge.on_input(input(slot(1))).await.unwrap();
+        bridge.last_flush -= Duration::from_secs(1);
+        bridge.maybe_flush(0).await.unwrap();
+
+        let mut sent = Vec::new();
+        while let Ok(bytes) = delta_rx.try_recv() {
+            let hdr = faststreams::FrameHeader::parse(&bytes).unwrap().unwrap();
+            let body = &bytes[faststreams::FRAME_HEADER_LEN..];
+            sent.push(faststreams::decode_cache_feed_body(&hdr, body, &mut Vec::new()).unwrap());
+        }
+        assert!(matches!(sent[0], CacheFeed::SnapshotComplete { slot: 0 }));
+        assert!(matches!(&sent[1], CacheFeed::Deltas(d) if d.len() == 1));
+        let CacheFeed::Slots(slots) = &sent[2] else {
+            panic!("expected slots, got {:?}", sent[2]);
+        };
+        assert_eq!(
+            slots.iter().map(|s| s.status).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(sent[3], CacheFeed::Transactions(vec![tx]));
+    }
+
+    #[tokio::test]
+    async fn snapshot_waits_for_every_producer() {
+        let args = Args::parse_from(["ultra-rpc-bridge", "--delta-batch-max", "1"]);
+        let (delta_tx, mut delta_rx) = mpsc::channel(16);
+        let mut bridge = Bridge::new(&args, delta_tx);
+        let input = |producer: &str, event| Input {
+            producer: producer.into(),
+            event,
+            at: Instant::now(),
+        };
+
+        bridge.on_input(input("a", Event::Connected)).await.unwrap();
+        bridge.on_input(input("b", Event::Connected)).await.unwrap();
+        bridge
+            .on_input(input("a", account(1, 10, true)))
+            .await
+            .unwrap();
+        bridge
+            .on_input(input("b", account(2, 11, true)))
+            .await
+            .unwrap();
+        // a is live, but b is still streaming startup accounts
+        bridge
+            .on_input(input("a", account(1, 12, false)))
+            .await
+            .unwrap();
+        bridge
+            .on_input(input("b", account(3, 11, true)))
+            .await
+            .unwrap();
+        let (reply, mut first) = tokio::sync::oneshot::channel();
+        bridge.serve_snapshot(reply).unwrap();
+        bridge.maybe_flush(0).await.unwrap();
+        assert!(first.try_recv().is_err());
+        assert!(delta_rx.try_recv().is_err());
+
+        bridge
+            .on_input(input("b", Event::Record(Record::EndOfStartup)))
+            .await
+            .unwrap();
+        bridge.maybe_flush(0).await.unwrap();
+        assert_eq!(first.try_recv().unwrap().slot, 11);
+        let complete = delta_rx.try_recv().unwrap();
+        assert_eq!(
+            complete,
+            encode_cache_feed(&CacheFeed::SnapshotComplete { slot: 11 }).unwrap()
+        );
+        assert!(delta_rx.try_recv().is_ok());
+
+        // A client connecting now gets the snapshot with the delta folded in
+        let (reply, mut later) = tokio::sync::oneshot::channel();
+        bridge.serve_snapshot(reply).unwrap();
+        let image = later.try_recv().unwrap();
+        assert_eq!(image.slot, 12);
+        assert_eq!(image.segments.len(), 1);
+        assert_eq!(bridge.snapshot.resident(), 3);
+    }
+
+    #[tokio::test]
+    async fn resumes_with_only_what_changed_since_the_checkpoint() {
+        let path = std::env::temp_dir().join(format!(
+            "ultra-rpc-bridge-resume-{}.bin",
+            std::process::id()
+        ));
+        Checkpoint {
+            slot: 10,
+            slots: [([1; 32], 10), ([2; 32], 10), ([3; 32], 9)].into(),
+        }
+        .save(&path)
+        .unwrap();
+        let args = Args::parse_from([
+            "ultra-rpc-bridge",
+            "--checkpoint-path",
+            path.to_str().unwrap(),
+        ]);
+        let (delta_tx, mut delta_rx) = mpsc::channel(16);
+        let mut bridge = Bridge::new(&args, delta_tx);
+        bridge.load_checkpoint().unwrap();
+        let input = |event| Input {
+            producer: "a".into(),
+            event,
+            at: Instant::now(),
+        };
+
+        // 1 is unchanged, 2 changed, 3 is gone and 4 is new
+        for (n, slot) in [(1, 10), (2, 12), (4, 11)] {
+            bridge
+                .on_input(input(account(n, slot, true)))
+                .await
+                .unwrap();
+        }
+        bridge
+            .on_input(input(Event::Record(Record::EndOfStartup)))
+            .await
+            .unwrap();
+
+        let mut sent = Vec::new();
+        while let Ok(bytes) = delta_rx.try_recv() {
+            let hdr = faststreams::FrameHeader::parse(&bytes).unwrap().unwrap();
+            let body = &bytes[faststreams::FRAME_HEADER_LEN..];
+            sent.push(faststreams::decode_cache_feed_body(&hdr, body, &mut Vec::new()).unwrap());
+        }
+        assert!(matches!(sent[0], CacheFeed::SnapshotComplete { slot: 12 }));
+        let CacheFeed::Deltas(changes) = &sent[1] else {
+            panic!("expected deltas, got {:?}", sent[1]);
+        };
+        let got: Vec<_> = changes
+            .iter()
+            .map(|d| (d.pubkey[0], d.account.is_some()))
+            .collect();
+        assert_eq!(got, vec![(2, true), (4, true), (3, false)]);
+
+        bridge.final_checkpoint().await;
+        let saved = Checkpoint::load(&path).unwrap().unwrap();
+        assert_eq!(saved.slot, 12);
+        let expected: HashMap<[u8; 32], u64> = [([1; 32], 10), ([2; 32], 12), ([4; 32], 11)].into();
+        assert_eq!(saved.slots, expected);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deletes_accounts_that_leave_the_owner_filter() {
+        let args = Args::parse_from([
+            "ultra-rpc-bridge",
+            "--exclude-owner",
+            "11111111111111111111111111111111",
+        ]);
+        let (delta_tx, mut delta_rx) = mpsc::channel(16);
+        let mut bridge = Bridge::new(&args, delta_tx);
+        let input = |event| Input {
+            producer: "a".into(),
+            event,
+            at: Instant::now(),
+        };
+        let excluded = |n: u8| Event::Excluded {
+            pubkey: [n; 32],
+            slot: 6,
+        };
+
+        bridge.on_input(input(account(1, 5, false))).await.unwrap();
+        bridge.on_input(input(excluded(1))).await.unwrap();
+        // Never forwarded, so there is nothing to delete
+        bridge.on_input(input(excluded(2))).await.unwrap();
+        bridge.last_flush -= Duration::from_secs(1);
+        bridge.maybe_flush(0).await.unwrap();
+
+        let _complete = delta_rx.try_recv().unwrap();
+        let bytes = delta_rx.try_recv().unwrap();
+        let hdr = faststreams::FrameHeader::parse(&bytes).unwrap().unwrap();
+        let body = &bytes[faststreams::FRAME_HEADER_LEN..];
+        let CacheFeed::Deltas(deltas) =
+            faststreams::decode_cache_feed_body(&hdr, body, &mut Vec::new()).unwrap()
+        else {
+            panic!("expected deltas");
+        };
+        assert_eq!(deltas.len(), 1);
+        assert_eq!((deltas[0].pubkey, deltas[0].slot), ([1; 32], 6));
+        assert!(deltas[0].account.is_none());
+        assert_eq!(bridge.snapshot.resident(), 0);
     }
-    Ok(())
 }
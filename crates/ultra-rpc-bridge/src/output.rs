@@ -0,0 +1,241 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/output.rs
+//
+// Listeners for the snapshot and delta outputs. Each is a Unix socket by
+// default; with `--snapshot-tcp` / `--delta-tcp` it listens on TCP instead, with
+// TLS when `--tls-cert` and `--tls-key` are given (mutual TLS with
+// `--tls-client-ca`), so solana-ultra-rpc can run on another host. Every client
+// gets `--output-send-buf-bytes` of socket send buffer, and TCP clients
+// keepalive probes. A snapshot client's TLS handshake runs on its own task, and
+// every handshake is bounded by a timeout, so a slow peer cannot hold up a
+// listener for long.
+use anyhow::{anyhow, Context, Result};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use socket2::{SockRef, TcpKeepalive};
+use std::io::ErrorKind;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+// A client that has not finished its TLS (or delta) handshake by then is dropped
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connected client; the delta socket also reads the client's handshake.
+pub trait OutputIo: AsyncRead + AsyncWrite + Send {}
+impl<T: AsyncRead + AsyncWrite + Send> OutputIo for T {}
+
+pub type OutputStream = Pin<Box<dyn OutputIo>>;
+
+/// Socket options and TLS shared by both outputs.
+#[derive(Clone)]
+pub struct OutputOpts {
+    pub send_buf_bytes: usize,
+    pub keepalive: Option<Duration>,
+    pub tls: Option<TlsAcceptor>,
+}
+
+pub struct TlsFiles<'a> {
+    pub cert: &'a str,
+    pub key: &'a str,
+    pub client_ca: Option<&'a str>,
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader =
+        std::io::BufReader::new(std::fs::File::open(path).with_context(|| format!("open {path}"))?);
+    rustls_pemfile::certs(&mut reader)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("read certificates from {path}"))
+}
+
+fn read_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader =
+        std::io::BufReader::new(std::fs::File::open(path).with_context(|| format!("open {path}"))?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| anyhow!("no private key in {path}"))
+}
+
+pub fn tls_acceptor(files: &TlsFiles) -> Result<TlsAcceptor> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match files.client_ca {
+        Some(ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca)? {
+                roots.add(cert)?;
+            }
+            let verifier =
+                WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_single_cert(read_certs(files.cert)?, read_key(files.key)?)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub enum OutputListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// A client accepted but not yet set up.
+pub enum Accepted {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl OutputListener {
+    /// Listen on `tcp` if given, else on the Unix socket `uds`.
+    pub async fn bind(name: &str, uds: &str, tcp: Option<&str>, opts: &OutputOpts) -> Result<Self> {
+        if let Some(addr) = tcp {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("{name} bind tcp {addr} failed"))?;
+            info!(bind = %addr, tls = opts.tls.is_some(), "{name} writer listening");
+            return Ok(Self::Tcp(listener));
+        }
+        if let Err(e) = std::fs::remove_file(uds) {
+            if e.kind() != ErrorKind::NotFound {
+                warn!(%e, uds = %uds, "failed to remove existing {name} socket");
+            }
+        }
+        let listener =
+            UnixListener::bind(uds).with_context(|| format!("{name} bind {uds} failed"))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Err(e) = std::fs::set_permissions(uds, std::fs::Permissions::from_mode(0o660)) {
+                warn!(%e, uds = %uds, "failed to set {name} socket permissions");
+            }
+        }
+        info!(uds = %uds, "{name} writer listening");
+        Ok(Self::Unix(listener))
+    }
+
+    pub async fn accept(&self) -> std::io::Result<Accepted> {
+        match self {
+            Self::Unix(l) => l.accept().await.map(|(sock, _)| Accepted::Unix(sock)),
+            Self::Tcp(l) => l.accept().await.map(|(sock, _)| Accepted::Tcp(sock)),
+        }
+    }
+}
+
+impl Accepted {
+    /// Apply socket options and, for TLS, complete the handshake.
+    pub async fn establish(self, opts: &OutputOpts) -> Result<OutputStream> {
+        match self {
+            Self::Unix(sock) => {
+                let _ = SockRef::from(&sock).set_send_buffer_size(opts.send_buf_bytes);
+                Ok(Box::pin(sock))
+            }
+            Self::Tcp(sock) => {
+                let _ = sock.set_nodelay(true);
+                let sr = SockRef::from(&sock);
+                let _ = sr.set_send_buffer_size(opts.send_buf_bytes);
+                if let Some(idle) = opts.keepalive {
+                    let keepalive = TcpKeepalive::new().with_time(idle).with_interval(idle);
+                    let _ = sr.set_tcp_keepalive(&keepalive);
+                }
+                let peer = sock.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                match &opts.tls {
+                    Some(acceptor) => {
+                        let handshake =
+                            tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(sock))
+                                .await
+                                .map_err(|_| std::io::Error::from(ErrorKind::TimedOut))
+                                .and_then(|res| res);
+                        let stream = handshake
+                            .inspect_err(|_| {
+                                metrics::counter!("rpc_bridge_tls_handshake_errors_total")
+                                    .increment(1)
+                            })
+                            .with_context(|| format!("tls handshake with {peer} failed"))?;
+                        Ok(Box::pin(stream))
+                    }
+                    None => Ok(Box::pin(sock)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn tls_output_reaches_the_client() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ultra-rpc-bridge-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        let (cert_path, key_path) = (
+            cert_path.display().to_string(),
+            key_path.display().to_string(),
+        );
+
+        let opts = OutputOpts {
+            send_buf_bytes: 1 << 20,
+            keepalive: Some(Duration::from_secs(30)),
+            tls: Some(
+                tls_acceptor(&TlsFiles {
+                    cert: &cert_path,
+                    key: &key_path,
+                    client_ca: None,
+                })
+                .unwrap(),
+            ),
+        };
+        let listener = OutputListener::bind("delta", "", Some("127.0.0.1:0"), &opts)
+            .await
+            .unwrap();
+        let OutputListener::Tcp(tcp) = &listener else {
+            panic!("expected a TCP listener");
+        };
+        let addr = tcp.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut sock = listener
+                .accept()
+                .await
+                .unwrap()
+                .establish(&opts)
+                .await
+                .unwrap();
+            sock.write_all(b"deltas").await.unwrap();
+            sock.shutdown().await.unwrap();
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(read_certs(&cert_path).unwrap()[0].clone())
+            .unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let mut stream = tokio_rustls::TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        let mut got = Vec::new();
+        stream.read_to_end(&mut got).await.unwrap();
+        assert_eq!(got, b"deltas");
+        server.await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
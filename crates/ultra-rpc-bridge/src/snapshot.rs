@@ -0,0 +1,259 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/snapshot.rs
+//
+// Snapshot side of the bridge. Once startup is over the bridge keeps the
+// snapshot materialized in memory, folding every delta it forwards into it, so
+// any snapshot client that connects later (a restarted solana-ultra-rpc, a
+// second RPC node) gets the current state instead of the startup one. Each
+// client asks the bridge task for an image, which is cut between two delta
+// batches; the RPC then replays the delta stream from wherever its connection
+// picks up, and since every delta up to the cut is already in the image,
+// replaying them again leaves the same state. Clients that connect before the
+// first snapshot is ready wait for it.
+//
+// The delta writer opens every client with a `SnapshotComplete` marker for the
+// last image served, since the bridge's own marker goes out only once.
+//
+// With `--snapshot-memory-budget-bytes`, accounts beyond the budget are spilled
+// to disk (see spill.rs) and only the newest changes stay in memory. An image
+// of a spilled snapshot first spills whatever is still in memory, then streams
+// shard by shard from disk.
+//
+// Emission progress is published as accounts emitted against accounts in the
+// image; for a spilled image the spilled accounts are only counted as each
+// shard is read, so the total grows while it streams.
+use crate::output::{OutputListener, OutputOpts, OutputStream};
+use crate::spill::{shard_segments, ShardView, Spill};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use faststreams::{encode_cache_feed, AccountState, CacheFeed};
+use futures_util::SinkExt;
+use metrics::{counter, gauge, histogram};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_util::codec::{BytesCodec, FramedWrite};
+use tracing::{error, info, warn};
+
+/// A serialized snapshot as of `slot`; spilled shards are serialized as they
+/// are streamed.
+pub struct SnapshotImage {
+    pub slot: u64,
+    pub segments: Vec<Bytes>,
+    // Accounts in `segments`
+    pub accounts: usize,
+    pub spilled: Vec<ShardView>,
+    pub chunk_size: usize,
+}
+
+pub type SnapshotRequest = oneshot::Sender<SnapshotImage>;
+
+// Rough in-memory cost of one account besides its data
+const ENTRY_OVERHEAD: usize = 160;
+
+/// The bridge's materialized snapshot: in memory up to the budget, spilled to
+/// disk beyond it.
+pub struct SnapshotStore {
+    // `None` is a deletion not yet spilled; only kept once there is a spill
+    accounts: HashMap<[u8; 32], Option<AccountState>>,
+    bytes: usize,
+    budget: Option<usize>,
+    spill_dir: PathBuf,
+    spill: Option<Spill>,
+}
+
+fn entry_bytes(account: &Option<AccountState>) -> usize {
+    ENTRY_OVERHEAD + account.as_ref().map_or(0, |a| a.data.len())
+}
+
+impl SnapshotStore {
+    pub fn new(budget: Option<usize>, spill_dir: PathBuf) -> Self {
+        Self {
+            accounts: HashMap::new(),
+            bytes: 0,
+            budget,
+            spill_dir,
+            spill: None,
+        }
+    }
+
+    /// Set (`Some`) or delete (`None`) an account, spilling if over budget.
+    pub fn insert(&mut self, pubkey: [u8; 32], account: Option<AccountState>) -> Result<()> {
+        let old = if account.is_none() && self.spill.is_none() {
+            self.accounts.remove(&pubkey)
+        } else {
+            self.bytes += entry_bytes(&account);
+            self.accounts.insert(pubkey, account)
+        };
+        if let Some(old) = old {
+            self.bytes -= entry_bytes(&old);
+        }
+        if self.budget.is_some_and(|budget| self.bytes > budget) {
+            self.spill_resident()?;
+        }
+        Ok(())
+    }
+
+    /// Accounts (and pending deletions) held in memory.
+    pub fn resident(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        self.spill.is_some()
+    }
+
+    fn spill_resident(&mut self) -> Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(Spill::create(self.spill_dir.clone())?);
+        }
+        if let Some(spill) = &mut self.spill {
+            spill.write(self.accounts.drain())?;
+        }
+        self.bytes = 0;
+        self.publish();
+        Ok(())
+    }
+
+    pub fn publish(&self) {
+        gauge!("rpc_bridge_snapshot_accounts").set(self.accounts.len() as f64);
+        gauge!("rpc_bridge_snapshot_memory_bytes").set(self.bytes as f64);
+    }
+
+    /// An image as of `base_slot` with segments of at most `chunk_size` accounts.
+    pub fn image(&mut self, base_slot: u64, chunk_size: usize) -> Result<SnapshotImage> {
+        if self.spill.is_some() {
+            self.spill_resident()?;
+        }
+        let mut segments = Vec::with_capacity(self.accounts.len().div_ceil(chunk_size.max(1)));
+        let values: Vec<&AccountState> = self.accounts.values().flatten().collect();
+        for chunk in values.chunks(chunk_size.max(1)) {
+            let seg = CacheFeed::SnapshotSegment {
+                base_slot,
+                accounts: chunk.iter().map(|&a| a.clone()).collect(),
+            };
+            let bytes = encode_cache_feed(&seg).with_context(|| {
+                format!("failed to serialize snapshot segment for slot {base_slot}")
+            })?;
+            segments.push(Bytes::from(bytes));
+        }
+        Ok(SnapshotImage {
+            slot: base_slot,
+            segments,
+            accounts: values.len(),
+            spilled: self.spill.as_ref().map(Spill::view).unwrap_or_default(),
+            chunk_size,
+        })
+    }
+}
+
+/// Serve an image to every client of the snapshot output, then close its
+/// stream; the slot of each image served is published on `served`.
+pub async fn run_snapshot_writer(
+    listener: OutputListener,
+    opts: OutputOpts,
+    requests: mpsc::Sender<SnapshotRequest>,
+    served: watch::Sender<Option<u64>>,
+) {
+    loop {
+        let client = match listener.accept().await {
+            Ok(client) => client,
+            Err(e) => {
+                warn!(%e, "snapshot accept failed; retrying");
+                tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                continue;
+            }
+        };
+        let (opts, requests, served) = (opts.clone(), requests.clone(), served.clone());
+        tokio::spawn(async move {
+            let res = match client.establish(&opts).await {
+                Ok(sock) => serve_client(sock, &requests, &served).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                error!(%e, "snapshot write error");
+            }
+        });
+    }
+}
+
+/// Accounts emitted to the snapshot client being served, against the image total.
+struct EmitProgress {
+    emitted: usize,
+    total: usize,
+}
+
+impl EmitProgress {
+    fn new(total: usize) -> Self {
+        let progress = Self { emitted: 0, total };
+        progress.publish();
+        progress
+    }
+
+    fn found(&mut self, accounts: usize) {
+        self.total += accounts;
+        self.publish();
+    }
+
+    fn emitted(&mut self, accounts: usize) {
+        self.emitted += accounts;
+        counter!("rpc_bridge_snapshot_emitted_accounts_total").increment(accounts as u64);
+        self.publish();
+    }
+
+    fn publish(&self) {
+        gauge!("rpc_bridge_snapshot_emit_accounts").set(self.emitted as f64);
+        gauge!("rpc_bridge_snapshot_emit_total_accounts").set(self.total as f64);
+        let ratio = match self.total {
+            0 => 1.0,
+            total => self.emitted as f64 / total as f64,
+        };
+        gauge!("rpc_bridge_snapshot_emit_progress").set(ratio);
+    }
+}
+
+async fn serve_client(
+    sock: OutputStream,
+    requests: &mpsc::Sender<SnapshotRequest>,
+    served: &watch::Sender<Option<u64>>,
+) -> Result<()> {
+    let (reply, image) = oneshot::channel();
+    requests
+        .send(reply)
+        .await
+        .map_err(|_| anyhow!("bridge stopped"))?;
+    let image = image.await.map_err(|_| anyhow!("bridge stopped"))?;
+    let started = Instant::now();
+    let mut framed = FramedWrite::new(sock, BytesCodec::new());
+    let chunk_size = image.chunk_size.max(1);
+    let mut progress = EmitProgress::new(image.accounts);
+    let mut left = image.accounts;
+    for seg in image.segments {
+        framed.send(seg).await?;
+        let n = chunk_size.min(left);
+        left -= n;
+        progress.emitted(n);
+    }
+    for view in image.spilled {
+        let slot = image.slot;
+        let (segments, mut left) =
+            tokio::task::spawn_blocking(move || shard_segments(&view, slot, chunk_size)).await??;
+        progress.found(left);
+        for seg in segments {
+            framed.send(seg).await?;
+            let n = chunk_size.min(left);
+            left -= n;
+            progress.emitted(n);
+        }
+    }
+    // Published before the stream closes, so the delta client that follows
+    // sees it; closing the stream (with a TLS close_notify over TCP) then lets
+    // solana-ultra-rpc complete the snapshot.
+    served.send_replace(Some(image.slot));
+    SinkExt::<Bytes>::close(&mut framed).await?;
+    counter!("rpc_bridge_snapshot_serves_total").increment(1);
+    histogram!("rpc_bridge_snapshot_serve_seconds").record(started.elapsed().as_secs_f64());
+    info!(slot = image.slot, "snapshot stream closed");
+    Ok(())
+}
@@ -0,0 +1,279 @@
+// Numan Thabit 2025
+// crates/ultra-rpc-bridge/src/spill.rs
+//
+// On-disk shards for a snapshot that outgrows `--snapshot-memory-budget-bytes`.
+// Accounts are split over `SHARDS` append-only files by pubkey; each spill
+// appends faststreams `CacheFeed::Deltas` frames, where a `None` account is a
+// tombstone and a later entry for a pubkey wins over an earlier one. An image
+// captures, per shard, an open read handle and the length written so far, so a
+// snapshot client streams straight from disk, one shard in memory at a time,
+// while the bridge keeps appending. A shard that has grown to several times its
+// live size is compacted into a fresh file; handles already captured keep the
+// old one. The spill directory is removed on exit.
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use faststreams::{
+    decode_cache_feed_body, encode_cache_feed, AccountDelta, AccountState, CacheFeed, FrameHeader,
+    FRAME_HEADER_LEN,
+};
+use metrics::{counter, gauge};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::FileExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+pub const SHARDS: usize = 64;
+const RECORDS_PER_FRAME: usize = 1024;
+// Shards smaller than this are never compacted
+const COMPACT_MIN_BYTES: u64 = 64 << 20;
+
+/// One shard as of an image: a read handle and how much of it is covered.
+pub type ShardView = (Arc<File>, u64);
+
+struct Shard {
+    path: PathBuf,
+    writer: File,
+    reader: Arc<File>,
+    len: u64,
+    // Length right after the last compaction
+    live: u64,
+}
+
+pub struct Spill {
+    dir: PathBuf,
+    shards: Vec<Shard>,
+}
+
+fn shard_of(pubkey: &[u8; 32]) -> usize {
+    pubkey[0] as usize % SHARDS
+}
+
+/// Create (or truncate) a shard file; a writer and a shared read handle.
+fn open_shard(path: &PathBuf) -> Result<(File, Arc<File>)> {
+    let writer = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("open snapshot spill shard {}", path.display()))?;
+    let reader = File::open(path)
+        .with_context(|| format!("open snapshot spill shard {}", path.display()))?;
+    Ok((writer, Arc::new(reader)))
+}
+
+impl Spill {
+    pub fn create(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("create snapshot spill dir {}", dir.display()))?;
+        let shards = (0..SHARDS)
+            .map(|i| {
+                let path = dir.join(format!("shard-{i}.bin"));
+                let (writer, reader) = open_shard(&path)?;
+                Ok(Shard {
+                    path,
+                    writer,
+                    reader,
+                    len: 0,
+                    live: 0,
+                })
+            })
+            .collect::<Result<_>>()?;
+        info!(dir = %dir.display(), "spilling snapshot to disk");
+        Ok(Self { dir, shards })
+    }
+
+    /// Append `entries` (pubkey, account or tombstone) to their shards.
+    pub fn write(
+        &mut self,
+        entries: impl IntoIterator<Item = ([u8; 32], Option<AccountState>)>,
+    ) -> Result<()> {
+        let mut by_shard: Vec<Vec<AccountDelta>> = (0..SHARDS).map(|_| Vec::new()).collect();
+        for (pubkey, account) in entries {
+            by_shard[shard_of(&pubkey)].push(AccountDelta {
+                pubkey,
+                slot: 0,
+                account,
+            });
+        }
+        for (i, deltas) in by_shard.into_iter().enumerate() {
+            if deltas.is_empty() {
+                continue;
+            }
+            append(&mut self.shards[i], deltas)?;
+            let shard = &self.shards[i];
+            if shard.len > COMPACT_MIN_BYTES && shard.len > 4 * shard.live {
+                self.compact(i)?;
+            }
+        }
+        counter!("rpc_bridge_snapshot_spills_total").increment(1);
+        gauge!("rpc_bridge_snapshot_spill_bytes").set(self.bytes() as f64);
+        Ok(())
+    }
+
+    /// Every non-empty shard as written so far.
+    pub fn view(&self) -> Vec<ShardView> {
+        self.shards
+            .iter()
+            .filter(|s| s.len > 0)
+            .map(|s| (s.reader.clone(), s.len))
+            .collect()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.shards.iter().map(|s| s.len).sum()
+    }
+
+    /// Rewrite shard `i` with only its live entries.
+    fn compact(&mut self, i: usize) -> Result<()> {
+        let shard = &mut self.shards[i];
+        let accounts = read_shard(&(shard.reader.clone(), shard.len))?;
+        let tmp = shard.path.with_extension("compact");
+        let (writer, reader) = open_shard(&tmp)?;
+        let before = shard.len;
+        let mut fresh = Shard {
+            path: shard.path.clone(),
+            writer,
+            reader,
+            len: 0,
+            live: 0,
+        };
+        let deltas = accounts
+            .into_iter()
+            .map(|(pubkey, a)| AccountDelta {
+                pubkey,
+                slot: 0,
+                account: Some(a),
+            })
+            .collect();
+        append(&mut fresh, deltas)?;
+        std::fs::rename(&tmp, &fresh.path)
+            .with_context(|| format!("replace snapshot spill shard {}", fresh.path.display()))?;
+        fresh.live = fresh.len;
+        *shard = fresh;
+        counter!("rpc_bridge_snapshot_spill_compactions_total").increment(1);
+        info!(
+            shard = i,
+            before,
+            after = shard.len,
+            "compacted snapshot spill shard"
+        );
+        Ok(())
+    }
+}
+
+fn append(shard: &mut Shard, deltas: Vec<AccountDelta>) -> Result<()> {
+    let mut deltas = deltas.into_iter().peekable();
+    while deltas.peek().is_some() {
+        let chunk: Vec<AccountDelta> = deltas.by_ref().take(RECORDS_PER_FRAME).collect();
+        let frame = encode_cache_feed(&CacheFeed::Deltas(chunk))?;
+        shard
+            .writer
+            .write_all(&frame)
+            .with_context(|| format!("write snapshot spill shard {}", shard.path.display()))?;
+        shard.len += frame.len() as u64;
+    }
+    Ok(())
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!(%e, dir = %self.dir.display(), "failed to remove snapshot spill dir");
+        }
+    }
+}
+
+/// Load a shard as of `view`, latest entry per pubkey winning.
+pub fn read_shard((file, len): &ShardView) -> Result<HashMap<[u8; 32], AccountState>> {
+    let mut buf = vec![0u8; *len as usize];
+    file.read_exact_at(&mut buf, 0)
+        .context("read snapshot spill shard")?;
+    let mut accounts = HashMap::new();
+    let mut scratch = Vec::new();
+    let mut at = 0;
+    while at < buf.len() {
+        let hdr = FrameHeader::parse(&buf[at..])?
+            .filter(|h| at + h.frame_len() <= buf.len())
+            .ok_or_else(|| anyhow!("truncated frame in snapshot spill shard"))?;
+        let body = &buf[at + FRAME_HEADER_LEN..at + hdr.frame_len()];
+        if let CacheFeed::Deltas(deltas) = decode_cache_feed_body(&hdr, body, &mut scratch)? {
+            for d in deltas {
+                match d.account {
+                    Some(a) => accounts.insert(d.pubkey, a),
+                    None => accounts.remove(&d.pubkey),
+                };
+            }
+        }
+        at += hdr.frame_len();
+    }
+    Ok(accounts)
+}
+
+/// Snapshot segments of at most `chunk_size` accounts for one spilled shard,
+/// and how many accounts they hold.
+pub fn shard_segments(
+    view: &ShardView,
+    base_slot: u64,
+    chunk_size: usize,
+) -> Result<(Vec<Bytes>, usize)> {
+    let accounts: Vec<AccountState> = read_shard(view)?.into_values().collect();
+    let segments = accounts
+        .chunks(chunk_size.max(1))
+        .map(|chunk| {
+            let seg = CacheFeed::SnapshotSegment {
+                base_slot,
+                accounts: chunk.to_vec(),
+            };
+            Ok(Bytes::from(encode_cache_feed(&seg)?))
+        })
+        .collect::<Result<_>>()?;
+    Ok((segments, accounts.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(n: u8, lamports: u64) -> AccountState {
+        AccountState {
+            pubkey: [n; 32],
+            lamports,
+            owner: [0; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: vec![n; 16],
+        }
+    }
+
+    #[test]
+    fn later_spills_win_and_views_stay_fixed() {
+        let dir =
+            std::env::temp_dir().join(format!("ultra-rpc-bridge-spill-{}", std::process::id()));
+        let mut spill = Spill::create(dir.clone()).unwrap();
+        spill
+            .write((0..=255u8).map(|n| ([n; 32], Some(state(n, 1)))))
+            .unwrap();
+        let before = spill.view();
+        // pubkey 0 and 64 share a shard
+        spill
+            .write([([0; 32], Some(state(0, 2))), ([64; 32], None)])
+            .unwrap();
+        spill.compact(0).unwrap();
+
+        let old = read_shard(&before[0]).unwrap();
+        assert_eq!(old.len(), 4);
+        assert_eq!(old[&[0; 32]].lamports, 1);
+        let now = read_shard(&spill.view()[0]).unwrap();
+        assert_eq!(now.len(), 3);
+        assert_eq!(now[&[0; 32]].lamports, 2);
+        assert!(!now.contains_key(&[64; 32]));
+        let (segments, accounts) = shard_segments(&spill.view()[0], 7, 2).unwrap();
+        assert_eq!((segments.len(), accounts), (2, 3));
+
+        drop(spill);
+        assert!(!dir.exists());
+    }
+}
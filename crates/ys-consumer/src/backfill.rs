@@ -0,0 +1,440 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/backfill.rs
+//
+// Fill slot gaps (reconnects, server-side drops) from a JSON-RPC `getBlock`.
+// Synthesized records are forwarded with `FLAG_BACKFILL` set on the frame.
+// Account state is not part of `getBlock`, so only block and tx records are
+// reconstructed.
+use anyhow::{anyhow, Context, Result};
+use faststreams::{BlockMeta, Record, TxUpdate};
+use metrics::counter;
+use reqwest::{header::CONTENT_TYPE, Client};
+use serde::Deserialize;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+const VOTE_PROGRAM_ID: &str = "Vote111111111111111111111111111111111111111";
+
+// JSON-RPC error codes for slots that will never have a block.
+const SLOT_SKIPPED: i64 = -32007;
+const LONG_TERM_STORAGE_SLOT_SKIPPED: i64 = -32009;
+// Block exists but is not yet available at the requested commitment.
+const BLOCK_NOT_AVAILABLE: i64 = -32004;
+
+#[derive(Debug, Clone)]
+pub struct BackfillConfig {
+    pub rpc_url: String,
+    /// Largest gap fetched in one go; older slots beyond it are counted and skipped.
+    pub max_slots: u64,
+    /// `confirmed` or `finalized`; `getBlock` does not serve processed blocks.
+    pub commitment: String,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    pub timeout: Duration,
+    pub include_blocks: bool,
+    pub include_txs: bool,
+}
+
+impl BackfillConfig {
+    /// `None` unless `YS_BACKFILL_RPC_URL` is set.
+    pub fn from_env(include_blocks: bool, include_txs: bool) -> Result<Option<Self>> {
+        let Some(rpc_url) = std::env::var("YS_BACKFILL_RPC_URL")
+            .ok()
+            .filter(|s| !s.is_empty())
+        else {
+            return Ok(None);
+        };
+        let num = |name: &str, default: u64| -> Result<u64> {
+            match std::env::var(name) {
+                Ok(v) if !v.is_empty() => {
+                    v.parse().with_context(|| format!("invalid {}={}", name, v))
+                }
+                _ => Ok(default),
+            }
+        };
+        let commitment = std::env::var("YS_BACKFILL_COMMITMENT")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "confirmed".to_string())
+            .to_ascii_lowercase();
+        if commitment != "confirmed" && commitment != "finalized" {
+            return Err(anyhow!(
+                "invalid YS_BACKFILL_COMMITMENT={} (confirmed|finalized)",
+                commitment
+            ));
+        }
+        Ok(Some(Self {
+            rpc_url,
+            max_slots: num("YS_BACKFILL_MAX_SLOTS", 512)?.max(1),
+            commitment,
+            retries: num("YS_BACKFILL_RETRIES", 5)? as u32,
+            retry_delay: Duration::from_millis(num("YS_BACKFILL_RETRY_DELAY_MS", 2_000)?),
+            timeout: Duration::from_millis(num("YS_BACKFILL_TIMEOUT_MS", 10_000)?),
+            include_blocks,
+            include_txs,
+        }))
+    }
+}
+
+/// Tracks the highest slot seen on the live stream and reports jumps past it.
+/// Lives outside the connection loop so an outage shows up as one gap on reconnect.
+#[derive(Debug)]
+pub struct GapTracker {
+    highest: Option<u64>,
+    max_slots: u64,
+}
+
+impl GapTracker {
+    pub fn new(max_slots: u64) -> Self {
+        Self {
+            highest: None,
+            max_slots: max_slots.max(1),
+        }
+    }
+
+    /// Returns the missing slots before `slot`, capped to the most recent `max_slots`.
+    pub fn observe(&mut self, slot: u64) -> Option<RangeInclusive<u64>> {
+        let prev = match self.highest {
+            Some(h) if slot <= h => return None,
+            prev => prev,
+        };
+        self.highest = Some(slot);
+        let prev = prev?;
+        if slot - prev <= 1 {
+            return None;
+        }
+        let end = slot - 1;
+        let start = (prev + 1).max(end.saturating_sub(self.max_slots - 1));
+        let truncated = start - (prev + 1);
+        if truncated > 0 {
+            counter!("ys_consumer_backfill_truncated_slots_total").increment(truncated);
+            warn!(
+                target = "ys.consumer",
+                "slot gap {}..={} exceeds YS_BACKFILL_MAX_SLOTS; skipping {} oldest slots",
+                prev + 1,
+                end,
+                truncated
+            );
+        }
+        Some(start..=end)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse {
+    result: Option<RpcBlock>,
+    error: Option<RpcError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcBlock {
+    blockhash: String,
+    parent_slot: u64,
+    block_time: Option<i64>,
+    block_height: Option<u64>,
+    #[serde(default)]
+    rewards: Vec<RpcReward>,
+    #[serde(default)]
+    transactions: Vec<RpcTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcReward {
+    pubkey: String,
+    reward_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcTransaction {
+    transaction: RpcTxAccounts,
+    meta: Option<RpcTxMeta>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcTxAccounts {
+    signatures: Vec<String>,
+    #[serde(default)]
+    account_keys: Vec<RpcAccountKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcAccountKey {
+    pubkey: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcTxMeta {
+    err: Option<serde_json::Value>,
+}
+
+enum Fetch {
+    Block(Box<RpcBlock>),
+    Skipped,
+}
+
+fn decode_fixed<const N: usize>(s: &str) -> Option<[u8; N]> {
+    bs58::decode(s)
+        .into_vec()
+        .ok()
+        .and_then(|v| v.try_into().ok())
+}
+
+fn records_from_block(slot: u64, block: &RpcBlock, cfg: &BackfillConfig) -> Vec<Record> {
+    let mut out = Vec::with_capacity(block.transactions.len() + 1);
+    if cfg.include_blocks {
+        out.push(Record::Block(BlockMeta {
+            slot,
+            blockhash: decode_fixed(&block.blockhash),
+            parent_slot: Some(block.parent_slot),
+            rewards_len: block.rewards.len() as u32,
+            block_time_unix: block.block_time,
+            leader: block
+                .rewards
+                .iter()
+                .find(|r| r.reward_type.as_deref() == Some("Fee"))
+                .and_then(|r| decode_fixed(&r.pubkey)),
+            block_height: block.block_height,
+        }));
+    }
+    if cfg.include_txs {
+        for tx in &block.transactions {
+            let Some(signature) = tx
+                .transaction
+                .signatures
+                .first()
+                .and_then(|s| decode_fixed::<64>(s))
+            else {
+                continue;
+            };
+            out.push(Record::Tx(TxUpdate {
+                slot,
+                signature,
+                err: tx
+                    .meta
+                    .as_ref()
+                    .and_then(|m| m.err.as_ref())
+                    .filter(|e| !e.is_null())
+                    .map(|e| e.to_string()),
+                vote: tx
+                    .transaction
+                    .account_keys
+                    .iter()
+                    .any(|k| k.pubkey == VOTE_PROGRAM_ID),
+            }));
+        }
+    }
+    out
+}
+
+async fn get_block(client: &Client, cfg: &BackfillConfig, slot: u64) -> Result<Fetch> {
+    let details = if cfg.include_txs { "accounts" } else { "none" };
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "getBlock",
+        "params": [slot, {
+            "commitment": cfg.commitment,
+            "encoding": "json",
+            "transactionDetails": details,
+            "rewards": true,
+            "maxSupportedTransactionVersion": 0,
+        }],
+    });
+    for attempt in 0..=cfg.retries {
+        if attempt > 0 {
+            tokio::time::sleep(cfg.retry_delay).await;
+        }
+        let resp = match client
+            .post(&cfg.rpc_url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+        {
+            Ok(r) if r.status().is_success() => r,
+            Ok(r) => {
+                debug!(target = "ys.consumer", slot, status = %r.status(), "getBlock http error");
+                continue;
+            }
+            Err(e) => {
+                debug!(
+                    target = "ys.consumer",
+                    slot, "getBlock request failed: {}", e
+                );
+                continue;
+            }
+        };
+        let parsed: RpcResponse = resp.json().await.context("decode getBlock response")?;
+        match (parsed.result, parsed.error) {
+            (Some(block), _) => return Ok(Fetch::Block(Box::new(block))),
+            (None, Some(e))
+                if e.code == SLOT_SKIPPED || e.code == LONG_TERM_STORAGE_SLOT_SKIPPED =>
+            {
+                return Ok(Fetch::Skipped)
+            }
+            (None, Some(e)) if e.code == BLOCK_NOT_AVAILABLE => continue,
+            (None, Some(e)) => {
+                return Err(anyhow!("getBlock {}: {} ({})", slot, e.message, e.code))
+            }
+            // `null` result: the node has no block for this slot.
+            (None, None) => return Ok(Fetch::Skipped),
+        }
+    }
+    Err(anyhow!(
+        "getBlock {} unavailable after {} attempts",
+        slot,
+        cfg.retries + 1
+    ))
+}
+
+/// Main-loop handle: feeds observed slots to the tracker and queues gaps for the worker.
+pub struct Backfill {
+    tracker: GapTracker,
+    gaps: mpsc::Sender<RangeInclusive<u64>>,
+}
+
+impl Backfill {
+    pub fn observe_slot(&mut self, slot: u64) {
+        if let Some(range) = self.tracker.observe(slot) {
+            let n = range.end() - range.start() + 1;
+            if self.gaps.try_send(range).is_err() {
+                counter!("ys_consumer_backfill_dropped_slots_total").increment(n);
+            }
+        }
+    }
+}
+
+/// Start the backfill worker. Synthesized records come back through the receiver so
+/// the main loop stays the only producer into the writer queues.
+pub fn spawn(cfg: BackfillConfig) -> Result<(Backfill, mpsc::Receiver<Record>)> {
+    let client = Client::builder()
+        .timeout(cfg.timeout)
+        .build()
+        .context("build backfill http client")?;
+    let (gap_tx, mut gap_rx) = mpsc::channel::<RangeInclusive<u64>>(64);
+    let (rec_tx, rec_rx) = mpsc::channel::<Record>(4_096);
+    info!(
+        target = "ys.consumer",
+        "slot gap backfill enabled via {} ({})", cfg.rpc_url, cfg.commitment
+    );
+    let handle = Backfill {
+        tracker: GapTracker::new(cfg.max_slots),
+        gaps: gap_tx,
+    };
+    tokio::spawn(async move {
+        while let Some(range) = gap_rx.recv().await {
+            for slot in range {
+                match get_block(&client, &cfg, slot).await {
+                    Ok(Fetch::Block(block)) => {
+                        counter!("ys_consumer_backfill_slots_total", "result" => "filled")
+                            .increment(1);
+                        for rec in records_from_block(slot, &block, &cfg) {
+                            if rec_tx.send(rec).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                    Ok(Fetch::Skipped) => {
+                        counter!("ys_consumer_backfill_slots_total", "result" => "skipped")
+                            .increment(1);
+                    }
+                    Err(e) => {
+                        counter!("ys_consumer_backfill_slots_total", "result" => "failed")
+                            .increment(1);
+                        warn!(
+                            target = "ys.consumer",
+                            "backfill of slot {} failed: {:#}", slot, e
+                        );
+                    }
+                }
+            }
+        }
+    });
+    Ok((handle, rec_rx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gap_tracker_reports_missing_slots_once() {
+        let mut t = GapTracker::new(4);
+        assert_eq!(t.observe(10), None);
+        assert_eq!(t.observe(11), None);
+        assert_eq!(t.observe(11), None);
+        assert_eq!(t.observe(14), Some(12..=13));
+        assert_eq!(t.observe(12), None, "late slots below the high-water mark");
+        // Long outage: only the newest `max_slots` are requested.
+        assert_eq!(t.observe(100), Some(96..=99));
+    }
+
+    #[test]
+    fn records_from_block_takes_fee_leader_and_marks_votes() {
+        let raw = serde_json::json!({
+            "blockhash": bs58::encode([3u8; 32]).into_string(),
+            "parentSlot": 41,
+            "blockTime": 1_700_000_000,
+            "blockHeight": 30,
+            "rewards": [
+                {"pubkey": bs58::encode([8u8; 32]).into_string(), "lamports": 5000, "rewardType": "Fee"}
+            ],
+            "transactions": [
+                {
+                    "transaction": {
+                        "signatures": [bs58::encode([1u8; 64]).into_string()],
+                        "accountKeys": [{"pubkey": VOTE_PROGRAM_ID, "signer": false}]
+                    },
+                    "meta": {"err": null}
+                },
+                {
+                    "transaction": {
+                        "signatures": [bs58::encode([2u8; 64]).into_string()],
+                        "accountKeys": []
+                    },
+                    "meta": {"err": {"InstructionError": [0, "Custom"]}}
+                }
+            ]
+        });
+        let block: RpcBlock = serde_json::from_value(raw).unwrap();
+        let cfg = BackfillConfig {
+            rpc_url: String::new(),
+            max_slots: 1,
+            commitment: "confirmed".into(),
+            retries: 0,
+            retry_delay: Duration::ZERO,
+            timeout: Duration::from_secs(1),
+            include_blocks: true,
+            include_txs: true,
+        };
+        let recs = records_from_block(42, &block, &cfg);
+        assert_eq!(recs.len(), 3);
+        match &recs[0] {
+            Record::Block(b) => {
+                assert_eq!(b.slot, 42);
+                assert_eq!(b.leader, Some([8u8; 32]));
+                assert_eq!(b.block_height, Some(30));
+            }
+            other => panic!("unexpected record: {other:?}"),
+        }
+        match (&recs[1], &recs[2]) {
+            (Record::Tx(vote), Record::Tx(failed)) => {
+                assert!(vote.vote && vote.err.is_none());
+                assert!(!failed.vote && failed.err.is_some());
+                assert_eq!(failed.signature, [2u8; 64]);
+            }
+            other => panic!("unexpected records: {other:?}"),
+        }
+    }
+}
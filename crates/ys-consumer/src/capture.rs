@@ -0,0 +1,317 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/capture.rs
+//
+// Recording and replay of faststreams capture files.
+//
+// `YS_OUTPUT=capture:<path>` records every written frame with its write time;
+// `ys-consumer replay-capture --file <path>` sends a capture to the configured
+// output at the recorded pace (or scaled by `--speed`), without a gRPC source.
+// A capture cut short mid-entry (the recorder was killed) replays up to the
+// cut with a warning.
+use crate::dlq_replay::Output;
+use crate::OutputTarget;
+use anyhow::{anyhow, bail, Context, Result};
+use faststreams::{frame_flags, CaptureReader, CaptureWriter, CAPTURE_MAGIC};
+use metrics::counter;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+const FRAME_HEADER_LEN: usize = 12;
+
+/// Total length of the frame starting at `bytes`, once its header is complete.
+fn frame_len(bytes: &[u8]) -> Option<usize> {
+    let hdr = bytes.get(..FRAME_HEADER_LEN)?;
+    let payload = u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]) as usize;
+    Some(FRAME_HEADER_LEN + payload)
+}
+
+/// Byte-stream sink for the writer loop: splits the stream back into frames
+/// and appends each to the capture file stamped with the current time.
+pub struct CaptureSink {
+    writer: CaptureWriter<BufWriter<File>>,
+    pending: Vec<u8>,
+}
+
+impl CaptureSink {
+    /// Open `path` for appending; an empty or new file gets the capture magic,
+    /// an existing one must already be a capture.
+    pub fn open(path: &str, buf_capacity: usize) -> std::io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let writer = if file.metadata()?.len() == 0 {
+            CaptureWriter::new(BufWriter::with_capacity(buf_capacity, file))?
+        } else {
+            let mut magic = [0u8; 8];
+            file.read_exact(&mut magic)?;
+            if magic != CAPTURE_MAGIC {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{path} is not a faststreams capture"),
+                ));
+            }
+            CaptureWriter::resume(BufWriter::with_capacity(buf_capacity, file))
+        };
+        Ok(Self {
+            writer,
+            pending: Vec::new(),
+        })
+    }
+}
+
+impl Write for CaptureSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let now = crate::unix_nanos(SystemTime::now());
+        // Writers hand over whole frames, so this skips the copy in practice.
+        if self.pending.is_empty() && frame_len(buf) == Some(buf.len()) {
+            self.writer.append(now, buf)?;
+            return Ok(buf.len());
+        }
+        self.pending.extend_from_slice(buf);
+        let mut start = 0;
+        while let Some(len) = frame_len(&self.pending[start..]) {
+            if self.pending.len() - start < len {
+                break;
+            }
+            self.writer.append(now, &self.pending[start..start + len])?;
+            start += len;
+        }
+        self.pending.drain(..start);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayArgs {
+    pub file: PathBuf,
+    /// Pace multiplier over the recorded timing; 0 sends as fast as the output accepts.
+    pub speed: f64,
+    /// Passes over the file; 0 repeats until the process is stopped.
+    pub repeat: u64,
+    pub output: OutputTarget,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub replayed: u64,
+    pub invalid: u64,
+}
+
+impl ReplayArgs {
+    /// Parse `replay-capture` flags; `output` comes from the regular env config.
+    pub fn parse(args: &[String], output: OutputTarget) -> Result<Self> {
+        let mut file: Option<PathBuf> = None;
+        let mut speed: f64 = 1.0;
+        let mut repeat: u64 = 1;
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            let mut value = || it.next().ok_or_else(|| anyhow!("{arg} requires a value"));
+            match arg.as_str() {
+                "--file" => file = Some(PathBuf::from(value()?)),
+                "--speed" => {
+                    let v = value()?;
+                    speed = v
+                        .parse()
+                        .ok()
+                        .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+                        .ok_or_else(|| anyhow!("invalid --speed value {v}"))?;
+                }
+                "--max-speed" => speed = 0.0,
+                "--repeat" => {
+                    let v = value()?;
+                    repeat = v
+                        .parse()
+                        .with_context(|| format!("invalid --repeat value {v}"))?;
+                }
+                other => bail!("unknown replay-capture argument: {other}"),
+            }
+        }
+        let file = file.ok_or_else(|| anyhow!("replay-capture requires --file <path>"))?;
+        Ok(Self {
+            file,
+            speed,
+            repeat,
+            output,
+        })
+    }
+}
+
+fn replay_pass(args: &ReplayArgs, out: &mut Output, stats: &mut ReplayStats) -> Result<()> {
+    let file = File::open(&args.file).with_context(|| format!("open {}", args.file.display()))?;
+    let mut reader = CaptureReader::new(BufReader::with_capacity(1 << 20, file))
+        .with_context(|| format!("{} is not a faststreams capture", args.file.display()))?;
+    let mut frame = Vec::with_capacity(64 * 1024);
+    let mut origin: Option<(u64, Instant)> = None;
+    loop {
+        let ts = match reader.next_into(&mut frame) {
+            Ok(Some(ts)) => ts,
+            Ok(None) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                counter!("ys_consumer_capture_replay_truncated_total").increment(1);
+                warn!(
+                    target = "ys.consumer",
+                    "{} ends in a truncated entry; replayed up to it",
+                    args.file.display()
+                );
+                break;
+            }
+            Err(e) => return Err(e).with_context(|| format!("read {}", args.file.display())),
+        };
+        if frame_flags(&frame).is_none() {
+            counter!("ys_consumer_capture_replay_invalid_total").increment(1);
+            stats.invalid += 1;
+            continue;
+        }
+        if args.speed > 0.0 {
+            let (ts0, start) = *origin.get_or_insert((ts, Instant::now()));
+            let offset = Duration::from_nanos(ts.saturating_sub(ts0)).div_f64(args.speed);
+            let now = Instant::now();
+            if start + offset > now {
+                std::thread::sleep(start + offset - now);
+            }
+        }
+        out.send(&frame).context("replay output failed")?;
+        counter!("ys_consumer_capture_replay_total").increment(1);
+        stats.replayed += 1;
+    }
+    Ok(())
+}
+
+pub fn run(args: &ReplayArgs) -> Result<ReplayStats> {
+    info!(
+        target = "ys.consumer",
+        "replaying capture {} at {}",
+        args.file.display(),
+        if args.speed > 0.0 {
+            format!("{}x recorded pace", args.speed)
+        } else {
+            "full speed".to_string()
+        }
+    );
+    let mut out = Output::open(&args.output)?;
+    let mut stats = ReplayStats::default();
+    let mut pass = 0u64;
+    while args.repeat == 0 || pass < args.repeat {
+        replay_pass(args, &mut out, &mut stats)?;
+        pass += 1;
+    }
+    if stats.invalid > 0 {
+        warn!(
+            target = "ys.consumer",
+            invalid = stats.invalid,
+            "skipped frames with bad headers"
+        );
+    }
+    info!(
+        target = "ys.consumer",
+        replayed = stats.replayed,
+        passes = pass,
+        "capture replay finished"
+    );
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use faststreams::{encode_record, write_all_vectored, Record, MAX_CAPTURE_FRAME_LEN};
+
+    #[test]
+    fn capture_records_frames_and_replays_them() {
+        let root = std::env::temp_dir().join(format!("ys-capture-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let path = root.join("slots.fscap").display().to_string();
+        let frames: Vec<Vec<u8>> = (1..=3)
+            .map(|slot| {
+                encode_record(&Record::Slot {
+                    slot,
+                    parent: Some(slot - 1),
+                    status: 1,
+                })
+                .unwrap()
+            })
+            .collect();
+        // Two batches, the second reopening the file the way a writer reconnect would.
+        let mut sink = CaptureSink::open(&path, 4096).unwrap();
+        write_all_vectored(&mut sink, &frames[..2]).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+        let mut sink = CaptureSink::open(&path, 4096).unwrap();
+        sink.write_all(&frames[2][..5]).unwrap();
+        sink.write_all(&frames[2][5..]).unwrap();
+        sink.flush().unwrap();
+        drop(sink);
+
+        let sock = root.join("out.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+        let args = ReplayArgs::parse(
+            &[
+                "--file".to_string(),
+                path.clone(),
+                "--max-speed".to_string(),
+                "--repeat".to_string(),
+                "2".to_string(),
+            ],
+            OutputTarget::Uds(sock.display().to_string()),
+        )
+        .unwrap();
+        let stats = run(&args).unwrap();
+        assert_eq!(stats.replayed, 6);
+
+        let (mut conn, _) = listener.accept().unwrap();
+        let expected: Vec<u8> = frames.concat().repeat(2);
+        let mut got = vec![0u8; expected.len()];
+        conn.read_exact(&mut got).unwrap();
+        assert_eq!(got, expected);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn replay_stops_at_a_truncated_entry_and_refuses_oversized_ones() {
+        let root = std::env::temp_dir().join(format!("ys-capture-cut-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let frame = encode_record(&Record::Slot {
+            slot: 7,
+            parent: None,
+            status: 1,
+        })
+        .unwrap();
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer.append(1, &frame).unwrap();
+        writer.append(2, &frame).unwrap();
+        let file = writer.into_inner();
+        let path = root.join("cut.fscap");
+        std::fs::write(&path, &file[..file.len() - 3]).unwrap();
+
+        let sock = root.join("out.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+        let mut args = ReplayArgs {
+            file: path.clone(),
+            speed: 0.0,
+            repeat: 1,
+            output: OutputTarget::Uds(sock.display().to_string()),
+        };
+        assert_eq!(run(&args).unwrap().replayed, 1);
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut got = vec![0u8; frame.len()];
+        conn.read_exact(&mut got).unwrap();
+        assert_eq!(got, frame);
+
+        let mut corrupt = CAPTURE_MAGIC.to_vec();
+        corrupt.extend_from_slice(&1u64.to_le_bytes());
+        corrupt.extend_from_slice(&(MAX_CAPTURE_FRAME_LEN as u32 + 1).to_le_bytes());
+        args.file = root.join("corrupt.fscap");
+        std::fs::write(&args.file, corrupt).unwrap();
+        assert!(run(&args).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,297 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/dlq_replay.rs
+//
+// `ys-consumer replay-dlq --dir <path>`: re-read frames parked by `DlqSink`,
+// re-validate them and forward them to the configured output (UDS or SHM),
+// routed and sharded the way the live writers would have sent them.
+use crate::{capture, record_shard, shm_ring, tls_out, KindRoutes, OutputTarget};
+use anyhow::{anyhow, bail, Context, Result};
+use faststreams::decode_record_from_slice;
+use metrics::counter;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnSuccess {
+    Delete,
+    Archive(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayArgs {
+    pub dir: PathBuf,
+    /// Maximum frames per second; 0 disables rate limiting.
+    pub rate_per_sec: u64,
+    pub on_success: OnSuccess,
+    pub outputs: ReplayOutputs,
+}
+
+/// The live writer layout: each frame goes to the output group its kind is
+/// routed to and the shard its key hashes to, so per-shard SHM rings and
+/// capture files get the frames their writer would have written.
+#[derive(Debug, Clone)]
+pub struct ReplayOutputs {
+    pub targets: Vec<OutputTarget>,
+    pub routes: KindRoutes,
+    /// `YS_WRITERS` of the live consumer.
+    pub writers: usize,
+}
+
+impl ReplayOutputs {
+    /// One unrouted, unsharded output.
+    pub fn single(target: OutputTarget) -> Self {
+        Self {
+            targets: vec![target],
+            routes: KindRoutes::default(),
+            writers: 1,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayStats {
+    pub replayed: u64,
+    pub invalid: u64,
+    pub failed: u64,
+}
+
+impl ReplayArgs {
+    /// Parse `replay-dlq` flags; `outputs` comes from the regular env config.
+    pub fn parse(args: &[String], outputs: ReplayOutputs) -> Result<Self> {
+        let mut dir: Option<PathBuf> = None;
+        let mut rate_per_sec: u64 = 1_000;
+        let mut on_success = OnSuccess::Delete;
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--dir" => {
+                    dir = Some(PathBuf::from(
+                        it.next().ok_or_else(|| anyhow!("--dir requires a value"))?,
+                    ))
+                }
+                "--rate" => {
+                    let v = it
+                        .next()
+                        .ok_or_else(|| anyhow!("--rate requires a value"))?;
+                    rate_per_sec = v
+                        .parse()
+                        .with_context(|| format!("invalid --rate value {v}"))?;
+                }
+                "--archive-dir" => {
+                    on_success = OnSuccess::Archive(PathBuf::from(
+                        it.next()
+                            .ok_or_else(|| anyhow!("--archive-dir requires a value"))?,
+                    ))
+                }
+                "--delete" => on_success = OnSuccess::Delete,
+                other => bail!("unknown replay-dlq argument: {other}"),
+            }
+        }
+        let dir = dir.ok_or_else(|| anyhow!("replay-dlq requires --dir <path>"))?;
+        Ok(Self {
+            dir,
+            rate_per_sec,
+            on_success,
+            outputs,
+        })
+    }
+}
+
+/// One blocking connection to an output target, shared by the replay subcommands.
+pub(crate) enum Output {
+    Uds(std::os::unix::net::UnixStream),
+    Shm(shm_ring::ShmRingWriter),
+    Tls(Box<tls_out::TlsStream>),
+    Capture(capture::CaptureSink),
+}
+
+impl Output {
+    pub(crate) fn open(cfg: &OutputTarget) -> Result<Self> {
+        match cfg {
+            OutputTarget::Uds(path) => Ok(Output::Uds(
+                crate::uds_connect(path).with_context(|| format!("connect {path}"))?,
+            )),
+            OutputTarget::Shm {
+                path,
+                capacity_bytes,
+                ..
+            } => Ok(Output::Shm(
+                shm_ring::ShmRingWriter::open_or_create(path, *capacity_bytes)
+                    .with_context(|| format!("open SHM ring {path}"))?,
+            )),
+            OutputTarget::Tls(cfg) => {
+                let client = cfg.client_config().context("tls client config")?;
+                Ok(Output::Tls(Box::new(
+                    cfg.connect(&client, 256 * 1024)
+                        .with_context(|| format!("connect {}", cfg.addr))?,
+                )))
+            }
+            OutputTarget::Capture(path) => Ok(Output::Capture(
+                capture::CaptureSink::open(path, 256 * 1024)
+                    .with_context(|| format!("open capture {path}"))?,
+            )),
+        }
+    }
+
+    pub(crate) fn send(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        match self {
+            Output::Uds(stream) => stream.write_all(frame),
+            Output::Tls(stream) => stream.write_all(frame).and_then(|_| stream.flush()),
+            Output::Capture(sink) => sink.write_all(frame).and_then(|_| sink.flush()),
+            // Give the reader a bounded window to free space before giving up.
+            Output::Shm(ring) => {
+                if ring.push_timeout(frame, Duration::from_secs(2)) {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "shm ring full",
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Collect `.fstr` files in name order; DLQ names embed the write timestamp.
+fn list_frames(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map(|e| e == "fstr").unwrap_or(false))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Output group and writer shard for a frame, or `None` if it does not decode cleanly.
+fn route_frame(
+    frame: &[u8],
+    scratch: &mut Vec<u8>,
+    outputs: &ReplayOutputs,
+) -> Option<(usize, usize)> {
+    let route = match decode_record_from_slice(frame, scratch) {
+        Ok((rec, used)) if used == frame.len() => Some((
+            outputs.routes.group_for(&rec),
+            record_shard(&rec, outputs.writers),
+        )),
+        _ => None,
+    };
+    scratch.clear();
+    route
+}
+
+fn finish(data_path: &Path, on_success: &OnSuccess) -> std::io::Result<()> {
+    let meta_path = data_path.with_extension("meta");
+    match on_success {
+        OnSuccess::Delete => {
+            std::fs::remove_file(data_path)?;
+            if meta_path.exists() {
+                std::fs::remove_file(&meta_path)?;
+            }
+        }
+        OnSuccess::Archive(dir) => {
+            for p in [data_path, meta_path.as_path()] {
+                if let Some(name) = p.file_name() {
+                    if p.exists() {
+                        std::fs::rename(p, dir.join(name))?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &ReplayArgs) -> Result<ReplayStats> {
+    if let OnSuccess::Archive(dir) = &args.on_success {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir.display()))?;
+    }
+    let files =
+        list_frames(&args.dir).with_context(|| format!("read DLQ dir {}", args.dir.display()))?;
+    info!(
+        target = "ys.consumer",
+        "replaying {} DLQ frames from {}",
+        files.len(),
+        args.dir.display()
+    );
+    // Opened on first use, one per (group, shard) like the live writers.
+    let writers = args.outputs.writers;
+    let mut outs: Vec<Option<Output>> = std::iter::repeat_with(|| None)
+        .take(args.outputs.targets.len() * writers)
+        .collect();
+    let interval = 1_000_000_000u64
+        .checked_div(args.rate_per_sec)
+        .map(Duration::from_nanos)
+        .unwrap_or(Duration::ZERO);
+    let mut stats = ReplayStats::default();
+    let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
+    let mut next_send = Instant::now();
+    for path in files {
+        let frame = match std::fs::read(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!(
+                    target = "ys.consumer",
+                    "read {} failed: {}",
+                    path.display(),
+                    e
+                );
+                stats.failed += 1;
+                continue;
+            }
+        };
+        let Some((group, shard)) = route_frame(&frame, &mut scratch, &args.outputs) else {
+            warn!(
+                target = "ys.consumer",
+                "skipping invalid DLQ frame {}",
+                path.display()
+            );
+            counter!("ys_consumer_dlq_replay_invalid_total").increment(1);
+            stats.invalid += 1;
+            continue;
+        };
+        let out = match &mut outs[group * writers + shard] {
+            Some(out) => out,
+            slot => slot.insert(Output::open(
+                &args.outputs.targets[group].for_shard(shard, writers),
+            )?),
+        };
+        let now = Instant::now();
+        if next_send > now {
+            std::thread::sleep(next_send - now);
+        }
+        next_send = next_send.max(now) + interval;
+        if let Err(e) = out.send(&frame) {
+            counter!("ys_consumer_dlq_replay_fail_total").increment(1);
+            stats.failed += 1;
+            // The output is unusable; stop and leave the remaining files in place.
+            warn!(
+                target = "ys.consumer",
+                "replay of {} failed: {}",
+                path.display(),
+                e
+            );
+            break;
+        }
+        counter!("ys_consumer_dlq_replay_total").increment(1);
+        stats.replayed += 1;
+        if let Err(e) = finish(&path, &args.on_success) {
+            warn!(
+                target = "ys.consumer",
+                "post-replay cleanup of {} failed: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    info!(
+        target = "ys.consumer",
+        replayed = stats.replayed,
+        invalid = stats.invalid,
+        failed = stats.failed,
+        "DLQ replay finished"
+    );
+    Ok(stats)
+}
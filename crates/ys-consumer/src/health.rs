@@ -0,0 +1,175 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/health.rs
+//
+// /healthz and /readyz for orchestrator probes.
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info};
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub struct Health {
+    started_ms: u64,
+    grpc_connected: AtomicBool,
+    last_update_ms: AtomicU64,
+    writers: Vec<AtomicBool>,
+    queue_capacity: usize,
+    stale_after: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub live: bool,
+    pub ready: bool,
+    pub grpc_connected: bool,
+    pub last_update_age_ms: Option<u64>,
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub writers_connected: usize,
+    pub writers_total: usize,
+}
+
+impl Health {
+    pub fn new(writers: usize, queue_capacity: usize, stale_after: Duration) -> Self {
+        Self {
+            started_ms: now_ms(),
+            grpc_connected: AtomicBool::new(false),
+            last_update_ms: AtomicU64::new(0),
+            writers: (0..writers).map(|_| AtomicBool::new(false)).collect(),
+            queue_capacity,
+            stale_after,
+        }
+    }
+
+    pub fn set_grpc_connected(&self, connected: bool) {
+        self.grpc_connected.store(connected, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn record_update(&self) {
+        self.last_update_ms.store(now_ms(), Ordering::Relaxed);
+    }
+
+    pub fn set_writer_connected(&self, shard: usize, connected: bool) {
+        if let Some(w) = self.writers.get(shard) {
+            w.store(connected, Ordering::Relaxed);
+        }
+        crate::meters::writer_alive(shard, connected);
+    }
+
+    pub fn report(&self, queue_depth: usize) -> HealthReport {
+        self.report_at(now_ms(), queue_depth)
+    }
+
+    fn report_at(&self, now: u64, queue_depth: usize) -> HealthReport {
+        let stale_ms = self.stale_after.as_millis() as u64;
+        let last = self.last_update_ms.load(Ordering::Relaxed);
+        let last_update_age_ms = (last != 0).then(|| now.saturating_sub(last));
+        let fresh = matches!(last_update_age_ms, Some(age) if age <= stale_ms);
+        // Allow one stale window after start before reporting the process dead.
+        let in_grace = now.saturating_sub(self.started_ms) <= stale_ms;
+        let grpc_connected = self.grpc_connected.load(Ordering::Relaxed);
+        let writers_connected = self
+            .writers
+            .iter()
+            .filter(|w| w.load(Ordering::Relaxed))
+            .count();
+        let queue_ok = queue_depth < self.queue_capacity.saturating_mul(9) / 10;
+        HealthReport {
+            live: fresh || in_grace,
+            ready: grpc_connected && fresh && writers_connected == self.writers.len() && queue_ok,
+            grpc_connected,
+            last_update_age_ms,
+            queue_depth,
+            queue_capacity: self.queue_capacity,
+            writers_connected,
+            writers_total: self.writers.len(),
+        }
+    }
+}
+
+type DepthFn = Arc<dyn Fn() -> usize + Send + Sync>;
+
+async fn healthz(
+    State((health, depth)): State<(Arc<Health>, DepthFn)>,
+) -> (StatusCode, Json<HealthReport>) {
+    let report = health.report(depth());
+    let code = if report.live {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report))
+}
+
+async fn readyz(
+    State((health, depth)): State<(Arc<Health>, DepthFn)>,
+) -> (StatusCode, Json<HealthReport>) {
+    let report = health.report(depth());
+    let code = if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(report))
+}
+
+pub fn spawn(
+    addr: std::net::SocketAddr,
+    health: Arc<Health>,
+    queue_depth: impl Fn() -> usize + Send + Sync + 'static,
+) {
+    let depth: DepthFn = Arc::new(queue_depth);
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state((health, depth));
+    tokio::spawn(async move {
+        let listener = match tokio::net::TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                error!("health listener bind {} failed: {}", addr, e);
+                return;
+            }
+        };
+        info!("health endpoints on http://{}", addr);
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("health server error: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_requires_connection_fresh_updates_and_writers() {
+        let health = Health::new(2, 100, Duration::from_secs(5));
+        let now = health.started_ms + 1_000;
+        let r = health.report_at(now, 0);
+        assert!(r.live, "startup grace keeps the process live");
+        assert!(!r.ready);
+
+        health.set_grpc_connected(true);
+        health.last_update_ms.store(now, Ordering::Relaxed);
+        health.set_writer_connected(0, true);
+        assert!(!health.report_at(now, 0).ready, "one writer still down");
+        health.set_writer_connected(1, true);
+        assert!(health.report_at(now, 0).ready);
+        assert!(!health.report_at(now, 95).ready, "queue nearly full");
+
+        let later = now + 10_000;
+        let r = health.report_at(later, 0);
+        assert!(!r.live && !r.ready);
+        assert_eq!(r.last_update_age_ms, Some(10_000));
+    }
+}
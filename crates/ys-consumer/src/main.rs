@@ -1,22 +1,31 @@
 // Numan Thabit 2025
 // crates/ys-consumer/src/main.rs
 #![deny(unsafe_code)]
+mod backfill;
+mod capture;
+mod dlq_replay;
+mod health;
+mod meters;
+mod mints;
 mod shm_ring;
+mod tls_out;
 use anyhow::{Context, Result};
 use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
 use crossbeam_queue::ArrayQueue;
 use event_listener::{Event, Listener};
 use faststreams::{
-    decode_record_from_slice, encode_into_with, encode_record_ref_into_with, write_all_vectored,
-    AccountUpdateRef, BlockMeta, EncodeOptions, Record, RecordRef, TxUpdate,
+    decode_record_from_slice, encode_into_with, encode_record_ref_into_with, set_frame_flags,
+    write_all_vectored, AccountUpdateRef, BlockMeta, EncodeOptions, Record, RecordRef, TxUpdate,
+    FLAG_BACKFILL,
 };
 use futures::{SinkExt, StreamExt};
 use metrics::{counter, gauge, histogram};
 use metrics_exporter_prometheus::PrometheusBuilder;
 use std::collections::{HashMap, VecDeque};
+use std::io::Write;
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::signal;
@@ -24,38 +33,69 @@ use tracing::{error, info, warn};
 use tracing_subscriber::EnvFilter;
 use yellowstone_grpc_client::GeyserGrpcClient;
 use yellowstone_grpc_proto::prelude::{
-    subscribe_update, CommitmentLevel, SubscribeRequest, SubscribeRequestFilterAccounts,
-    SubscribeRequestFilterBlocks, SubscribeRequestFilterBlocksMeta, SubscribeRequestFilterSlots,
-    SubscribeRequestFilterTransactions, SubscribeRequestPing,
+    subscribe_update, CommitmentLevel, RewardType, Rewards, SubscribeRequest,
+    SubscribeRequestFilterAccounts, SubscribeRequestFilterBlocks, SubscribeRequestFilterBlocksMeta,
+    SubscribeRequestFilterSlots, SubscribeRequestFilterTransactions, SubscribeRequestPing,
 };
 
 fn uds_connect(path: &str) -> std::io::Result<UnixStream> {
-    let s = UnixStream::connect(path)?;
+    let mut s = UnixStream::connect(path)?;
     s.set_nonblocking(false)?;
     s.set_write_timeout(Some(std::time::Duration::from_secs(2)))?;
+    send_auth(&mut s)?;
     Ok(s)
 }
 
+/// `YS_AUTH_TOKEN`: auth frame sent first on every UDS and TLS connection, for
+/// aggregators that require a `producer_auth` token.
+static AUTH_FRAME: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+fn send_auth(w: &mut impl Write) -> std::io::Result<()> {
+    match AUTH_FRAME.get() {
+        Some(frame) => w.write_all(frame),
+        None => Ok(()),
+    }
+}
+
+// Encoded frame plus the metadata writers need for lag accounting.
+struct QueuedFrame {
+    buf: Vec<u8>,
+    meta: FrameMeta,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FrameMeta {
+    kind: &'static str,
+    // Yellowstone `created_at` as unix nanos; 0 when the update carried none.
+    created_at_ns: u64,
+}
+
+// Outcome of waiting on a shard queue.
+enum Pop {
+    Frame(QueuedFrame),
+    // Nothing arrived in time; the writer gets a turn to check shutdown and failback.
+    Idle,
+    // Source drained and closed.
+    Closed,
+}
+
 trait BatchSource {
-    fn blocking_pop(&self, flush_interval: Duration) -> Option<Vec<u8>>;
-    fn try_pop(&self) -> Option<Vec<u8>>;
+    fn pop_timeout(&self, timeout: Duration) -> Pop;
+    fn try_pop(&self) -> Option<QueuedFrame>;
     fn approx_len(&self) -> usize;
 }
 
-impl BatchSource for Receiver<Vec<u8>> {
+impl BatchSource for Receiver<QueuedFrame> {
     #[inline]
-    fn blocking_pop(&self, flush_interval: Duration) -> Option<Vec<u8>> {
-        // Block with timeout to support adaptive flush cadence; keep waiting on timeouts.
-        loop {
-            match self.recv_timeout(flush_interval) {
-                Ok(v) => return Some(v),
-                Err(RecvTimeoutError::Timeout) => continue,
-                Err(RecvTimeoutError::Disconnected) => return None,
-            }
+    fn pop_timeout(&self, timeout: Duration) -> Pop {
+        match self.recv_timeout(timeout) {
+            Ok(v) => Pop::Frame(v),
+            Err(RecvTimeoutError::Timeout) => Pop::Idle,
+            Err(RecvTimeoutError::Disconnected) => Pop::Closed,
         }
     }
     #[inline]
-    fn try_pop(&self) -> Option<Vec<u8>> {
+    fn try_pop(&self) -> Option<QueuedFrame> {
         self.try_recv().ok()
     }
     #[inline]
@@ -64,28 +104,95 @@ impl BatchSource for Receiver<Vec<u8>> {
     }
 }
 
+// Lets a failover writer borrow the shard's queue without taking ownership.
+impl<S: BatchSource + ?Sized> BatchSource for &S {
+    #[inline]
+    fn pop_timeout(&self, timeout: Duration) -> Pop {
+        (**self).pop_timeout(timeout)
+    }
+    #[inline]
+    fn try_pop(&self) -> Option<QueuedFrame> {
+        (**self).try_pop()
+    }
+    #[inline]
+    fn approx_len(&self) -> usize {
+        (**self).approx_len()
+    }
+}
+
 // Lightweight buffer pool to reuse Vec<u8> allocations in the fast path.
+// Bounded by item count and by the total capacity it retains, so a burst of
+// multi-megabyte account frames does not stay resident after the burst.
 #[derive(Debug)]
 struct BufPool {
     q: ArrayQueue<Vec<u8>>,
     default_capacity: usize,
+    max_buf_capacity: usize,
+    max_bytes: usize,
+    bytes: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BufPoolStats {
+    items: usize,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
 }
 
 impl BufPool {
-    fn new(max_items: usize, default_capacity: usize) -> Self {
+    fn new(
+        max_items: usize,
+        default_capacity: usize,
+        max_buf_capacity: usize,
+        max_bytes: usize,
+    ) -> Self {
         Self {
             q: ArrayQueue::new(max_items),
             default_capacity,
+            max_buf_capacity: max_buf_capacity.max(default_capacity),
+            max_bytes,
+            bytes: AtomicUsize::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
         }
     }
     fn get(&self) -> Vec<u8> {
-        self.q
-            .pop()
-            .unwrap_or_else(|| Vec::with_capacity(self.default_capacity))
+        match self.q.pop() {
+            Some(buf) => {
+                self.bytes.fetch_sub(buf.capacity(), Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(self.default_capacity)
+            }
+        }
     }
     fn put(&self, mut buf: Vec<u8>) {
         buf.clear();
-        let _ = self.q.push(buf);
+        if buf.capacity() > self.max_buf_capacity {
+            buf.shrink_to(self.max_buf_capacity);
+        }
+        let cap = buf.capacity();
+        if self.bytes.fetch_add(cap, Ordering::Relaxed) + cap > self.max_bytes {
+            self.bytes.fetch_sub(cap, Ordering::Relaxed);
+            return;
+        }
+        if self.q.push(buf).is_err() {
+            self.bytes.fetch_sub(cap, Ordering::Relaxed);
+        }
+    }
+    fn stats(&self) -> BufPoolStats {
+        BufPoolStats {
+            items: self.q.len(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
     }
 }
 
@@ -155,8 +262,8 @@ const BACKPRESSURE_SPIN_LIMIT: usize = 32;
 const BACKPRESSURE_SLEEP_MICROS: u64 = 50;
 
 fn enqueue_with_backpressure(
-    tx: &Sender<Vec<u8>>,
-    mut buf: Vec<u8>,
+    tx: &Sender<QueuedFrame>,
+    mut buf: QueuedFrame,
     shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>,
     pool: &std::sync::Arc<BufPool>,
 ) -> bool {
@@ -165,14 +272,14 @@ fn enqueue_with_backpressure(
             Ok(()) => return true,
             Err(TrySendError::Full(b)) => {
                 if shutdown.load(Ordering::Relaxed) {
-                    pool.put(b);
+                    pool.put(b.buf);
                     return false;
                 }
                 std::thread::yield_now();
                 buf = b;
             }
             Err(TrySendError::Disconnected(b)) => {
-                pool.put(b);
+                pool.put(b.buf);
                 return false;
             }
         }
@@ -183,48 +290,177 @@ fn enqueue_with_backpressure(
             Ok(()) => return true,
             Err(TrySendError::Full(b)) => {
                 if shutdown.load(Ordering::Relaxed) {
-                    pool.put(b);
+                    pool.put(b.buf);
                     return false;
                 }
                 std::thread::sleep(Duration::from_micros(BACKPRESSURE_SLEEP_MICROS));
                 buf = b;
             }
             Err(TrySendError::Disconnected(b)) => {
-                pool.put(b);
+                pool.put(b.buf);
                 return false;
             }
         }
     }
 }
 
+// Producer half of a writer shard's queue.
+#[derive(Clone)]
+enum ShardSender {
+    Channel(Sender<QueuedFrame>),
+    Spsc(SpscSender),
+}
+
+// Read-only view of a shard's queue; holding it does not keep a channel open.
+#[derive(Clone)]
+enum QueueProbe {
+    Channel(Receiver<QueuedFrame>),
+    Spsc(std::sync::Arc<ArrayQueue<QueuedFrame>>),
+}
+
+impl QueueProbe {
+    fn len(&self) -> usize {
+        match self {
+            QueueProbe::Channel(rx) => rx.len(),
+            QueueProbe::Spsc(q) => q.len(),
+        }
+    }
+}
+
+// FNV-1a, matching the geyser plugin's writer sharding.
+fn shard_index(bytes: &[u8], modulo: usize) -> usize {
+    if modulo <= 1 {
+        return 0;
+    }
+    let mut hash = std::num::Wrapping(0xcbf29ce484222325u64);
+    for byte in bytes {
+        hash ^= std::num::Wrapping(*byte as u64);
+        hash *= std::num::Wrapping(0x100000001b3);
+    }
+    (hash.0 as usize) % modulo
+}
+
+fn shard_from_u64(value: u64, modulo: usize) -> usize {
+    if modulo <= 1 {
+        return 0;
+    }
+    shard_index(&value.to_le_bytes(), modulo)
+}
+
+/// Writer shard the live path picks for `rec`: accounts by pubkey, transactions by
+/// signature, blocks and slots by slot.
+fn record_shard(rec: &Record, modulo: usize) -> usize {
+    match rec {
+        Record::Account(a) => shard_index(&a.pubkey, modulo),
+        Record::Tx(t) => shard_index(&t.signature, modulo),
+        Record::Block(b) => shard_from_u64(b.slot, modulo),
+        Record::Slot { slot, .. } => shard_from_u64(*slot, modulo),
+        Record::EndOfStartup => 0,
+    }
+}
+
+/// `YS_STAMP_ORIGIN=1`: prefix frames with their `created_at` (`FLAG_ORIGIN_TS`) so the
+/// aggregator can report producer-to-sink lag. Aggregators older than the flag cannot
+/// decode stamped frames, so it stays off until they are upgraded.
+static STAMP_ORIGIN: AtomicBool = AtomicBool::new(false);
+
+/// Encode options for a frame seen at `created_at_ns`, stamped with it when enabled so the
+/// timestamp is written in place rather than spliced in after encoding.
+fn encode_opts(profile: fn() -> EncodeOptions, created_at_ns: u64) -> EncodeOptions {
+    let opts = profile();
+    if STAMP_ORIGIN.load(Ordering::Relaxed) && created_at_ns != 0 {
+        opts.with_origin(created_at_ns)
+    } else {
+        opts
+    }
+}
+
 fn forward_frame(
-    buf: Vec<u8>,
-    txq_opt: &Option<Sender<Vec<u8>>>,
-    spsc_opt: &Option<SpscSender>,
+    frame: QueuedFrame,
+    shards: &[ShardSender],
+    shard: usize,
     shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>,
     pool: &std::sync::Arc<BufPool>,
 ) -> bool {
-    if let Some(tx) = txq_opt {
-        return enqueue_with_backpressure(tx, buf, shutdown, pool);
-    }
-    if let Some(sender) = spsc_opt {
-        match sender.push_with_backpressure(buf, shutdown) {
+    let kind = frame.meta.kind;
+    let ok = match shards.get(shard) {
+        Some(ShardSender::Channel(tx)) => enqueue_with_backpressure(tx, frame, shutdown, pool),
+        Some(ShardSender::Spsc(sender)) => match sender.push_with_backpressure(frame, shutdown) {
             Ok(()) => true,
-            Err(b) => {
-                pool.put(b);
+            Err(f) => {
+                pool.put(f.buf);
                 false
             }
+        },
+        None => {
+            pool.put(frame.buf);
+            false
         }
+    };
+    if ok {
+        meters::enqueued(kind);
     } else {
-        pool.put(buf);
-        false
+        meters::dropped(kind, "queue_full", shard);
     }
+    ok
 }
 
 static FRAMES_PROCESSED: AtomicU64 = AtomicU64::new(0);
 static FRAMES_DROPPED_OVERSIZE: AtomicU64 = AtomicU64::new(0);
 static FRAMES_DLQ: AtomicU64 = AtomicU64::new(0);
-static SAMPLE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Picks which encodes get timed. The rate is rounded up to a power of two so
+/// the hot-path check stays a single mask; 1 times every encode, 0 disables.
+struct EncodeSampler {
+    seq: AtomicU64,
+    mask: Option<u64>,
+}
+
+impl EncodeSampler {
+    fn new(every: u64) -> Self {
+        let mask = match every {
+            0 => None,
+            n => Some(n.checked_next_power_of_two().unwrap_or(1 << 63) - 1),
+        };
+        Self {
+            seq: AtomicU64::new(0),
+            mask,
+        }
+    }
+
+    /// `YS_ENCODE_SAMPLE_EVERY` (default 256) sets the rate;
+    /// `YS_ENCODE_SAMPLE_ALL=1` overrides it for debugging sessions.
+    fn from_env() -> Self {
+        let all = matches!(
+            std::env::var("YS_ENCODE_SAMPLE_ALL").as_deref(),
+            Ok("1" | "true" | "TRUE" | "yes" | "y")
+        );
+        let every = if all {
+            1
+        } else {
+            std::env::var("YS_ENCODE_SAMPLE_EVERY")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(256)
+        };
+        Self::new(every)
+    }
+
+    fn every(&self) -> u64 {
+        self.mask.map_or(0, |m| m + 1)
+    }
+
+    #[inline]
+    fn start(&self) -> Option<Instant> {
+        let mask = self.mask?;
+        let v = self.seq.fetch_add(1, Ordering::Relaxed);
+        if (v & mask) == 0 {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+}
 
 #[derive(Clone)]
 struct DlqSink {
@@ -307,6 +543,85 @@ impl DlqSink {
     }
 }
 
+fn unix_nanos(t: SystemTime) -> u64 {
+    t.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+fn created_at_nanos(ts: Option<&yellowstone_grpc_proto::prost_types::Timestamp>) -> u64 {
+    match ts {
+        Some(ts) if ts.seconds > 0 => (ts.seconds as u64)
+            .saturating_mul(1_000_000_000)
+            .saturating_add(ts.nanos.max(0) as u64),
+        _ => 0,
+    }
+}
+
+// `stage` separates network lag ("receive") from local queueing ("write").
+#[inline]
+fn record_lag(stage: &'static str, meta: FrameMeta, now_ns: u64) {
+    if meta.created_at_ns == 0 {
+        return;
+    }
+    let lag_ms = now_ns.saturating_sub(meta.created_at_ns) as f64 / 1e6;
+    histogram!("ys_consumer_e2e_lag_ms", "stage" => stage, "kind" => meta.kind).record(lag_ms);
+}
+
+fn record_lag_batch(stage: &'static str, metas: &[FrameMeta]) {
+    let now_ns = unix_nanos(SystemTime::now());
+    for meta in metas {
+        record_lag(stage, *meta, now_ns);
+    }
+}
+
+async fn recv_backfill(rx: &mut Option<tokio::sync::mpsc::Receiver<Record>>) -> Option<Record> {
+    match rx {
+        Some(rx) => rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn decode_pubkey(s: &str) -> Option<[u8; 32]> {
+    if s.is_empty() {
+        return None;
+    }
+    bs58::decode(s)
+        .into_vec()
+        .ok()
+        .and_then(|v| v.try_into().ok())
+}
+
+// The proto carries no explicit leader; the block producer is the recipient
+// of the fee reward.
+fn leader_from_rewards(rewards: Option<&Rewards>) -> Option<[u8; 32]> {
+    rewards?
+        .rewards
+        .iter()
+        .find(|r| r.reward_type == RewardType::Fee as i32)
+        .and_then(|r| decode_pubkey(&r.pubkey))
+}
+
+/// Shared by `Block` and `BlockMeta` updates, which carry the same header fields.
+fn block_meta_record(
+    slot: u64,
+    blockhash: &str,
+    parent_slot: u64,
+    rewards: Option<&Rewards>,
+    block_time: Option<&yellowstone_grpc_proto::prelude::UnixTimestamp>,
+    block_height: Option<&yellowstone_grpc_proto::prelude::BlockHeight>,
+) -> BlockMeta {
+    BlockMeta {
+        slot,
+        blockhash: decode_pubkey(blockhash),
+        parent_slot: Some(parent_slot),
+        rewards_len: rewards.map(|r| r.rewards.len()).unwrap_or(0) as u32,
+        block_time_unix: block_time.map(|ts| ts.timestamp).filter(|&ts| ts != 0),
+        leader: leader_from_rewards(rewards),
+        block_height: block_height.map(|h| h.block_height),
+    }
+}
+
 fn update_ratios() {
     let processed = FRAMES_PROCESSED.load(Ordering::Relaxed);
     let dropped = FRAMES_DROPPED_OVERSIZE.load(Ordering::Relaxed);
@@ -402,16 +717,16 @@ fn drop_to_dlq(
 // Event-driven SPSC queue wrapper: producers notify, consumer blocks on event.
 #[derive(Clone)]
 struct SpscSender {
-    q: std::sync::Arc<ArrayQueue<Vec<u8>>>,
+    q: std::sync::Arc<ArrayQueue<QueuedFrame>>,
     ev: std::sync::Arc<Event>,
 }
 
 impl SpscSender {
     fn push_with_backpressure(
         &self,
-        mut v: Vec<u8>,
+        mut v: QueuedFrame,
         shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>,
-    ) -> Result<(), Vec<u8>> {
+    ) -> Result<(), QueuedFrame> {
         for _ in 0..BACKPRESSURE_SPIN_LIMIT {
             match self.q.push(v) {
                 Ok(()) => {
@@ -443,33 +758,34 @@ impl SpscSender {
 
         Err(v)
     }
-    fn len(&self) -> usize {
-        self.q.len()
-    }
 }
 
 struct SpscQueue {
-    q: std::sync::Arc<ArrayQueue<Vec<u8>>>,
+    q: std::sync::Arc<ArrayQueue<QueuedFrame>>,
     ev: std::sync::Arc<Event>,
+    // Set once producers have stopped; an empty queue then means end of stream.
+    draining: std::sync::Arc<std::sync::atomic::AtomicBool>,
 }
 
 impl BatchSource for SpscQueue {
     #[inline]
-    fn blocking_pop(&self, flush_interval: Duration) -> Option<Vec<u8>> {
+    fn pop_timeout(&self, timeout: Duration) -> Pop {
         // Double-checked wait with timeout to support flush cadence.
-        loop {
-            if let Some(v) = self.q.pop() {
-                return Some(v);
-            }
-            let listener = self.ev.listen();
-            if let Some(v) = self.q.pop() {
-                return Some(v);
-            }
-            let _ = listener.wait_timeout(flush_interval);
+        if let Some(v) = self.q.pop() {
+            return Pop::Frame(v);
+        }
+        if self.draining.load(Ordering::Acquire) {
+            return self.q.pop().map_or(Pop::Closed, Pop::Frame);
         }
+        let listener = self.ev.listen();
+        if let Some(v) = self.q.pop() {
+            return Pop::Frame(v);
+        }
+        let _ = listener.wait_timeout(timeout);
+        self.q.pop().map_or(Pop::Idle, Pop::Frame)
     }
     #[inline]
-    fn try_pop(&self) -> Option<Vec<u8>> {
+    fn try_pop(&self) -> Option<QueuedFrame> {
         self.q.pop()
     }
     #[inline]
@@ -485,6 +801,18 @@ struct WriterLimits {
     frame_bytes_max: usize,
 }
 
+// Per-shard state shared by the UDS and SHM writer loops.
+#[derive(Clone)]
+struct WriterCtx {
+    shard: usize,
+    shutdown: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    limits: WriterLimits,
+    flush_interval: Duration,
+    buf_pool: std::sync::Arc<BufPool>,
+    dlq: Option<DlqSink>,
+    health: std::sync::Arc<health::Health>,
+}
+
 #[inline]
 fn scale_duration(base: Duration, denom: u32) -> Duration {
     if denom <= 1 {
@@ -524,36 +852,70 @@ fn effective_flush_interval(base: Duration, prev_len: usize, curr_len: usize) ->
     }
 }
 
-fn writer_loop_generic<S: BatchSource>(
-    uds_path: String,
+// Why a writer loop handed its shard back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriterExit {
+    /// Shutdown, or the source drained and closed.
+    Finished,
+    /// Could not reach the output for longer than the outage limit.
+    Outage,
+    /// `yield_to` asked for the shard back.
+    Yielded,
+}
+
+// Stream writer shared by the UDS and TLS outputs; `connect` is retried with backoff.
+// With an `outage_limit`, gives up once the output has been unreachable that long.
+// `yield_to` is polled between batches; returning true ends the loop.
+// `pending_frame` holds a frame taken off the queue but not yet written: it goes
+// out first, and unless the loop finishes it is left there for the next writer.
+fn writer_loop_generic<S: BatchSource, W: Write>(
+    endpoint: &str,
+    mut connect: impl FnMut() -> std::io::Result<W>,
     src: S,
-    shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>,
-    limits: WriterLimits,
-    flush_interval: Duration,
-    buf_pool: std::sync::Arc<BufPool>,
-    dlq: Option<DlqSink>,
-) {
+    ctx: &WriterCtx,
+    pending_frame: &mut Option<QueuedFrame>,
+    outage_limit: Option<Duration>,
+    mut yield_to: impl FnMut() -> bool,
+) -> WriterExit {
+    let WriterCtx {
+        shard,
+        ref shutdown,
+        limits,
+        flush_interval,
+        ref buf_pool,
+        ref dlq,
+        ref health,
+    } = *ctx;
     let WriterLimits {
         batch_max,
         batch_bytes_max,
         frame_bytes_max,
     } = limits;
+    let shard_label = shard.to_string();
     let mut backoff = Duration::from_millis(50);
-    let mut pending_frame: Option<Vec<u8>> = None;
     let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
     let mut prev_queue_len: usize = 0;
-    loop {
+    let mut down_since: Option<Instant> = None;
+    let exit = 'conn: loop {
         if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
-            break;
+            break WriterExit::Finished;
         }
-        match uds_connect(&uds_path) {
+        // Cleared here too so a `break 'conn` from a live connection is reflected.
+        health.set_writer_connected(shard, false);
+        match connect() {
             Ok(mut stream) => {
-                let _ = socket2::SockRef::from(&stream).set_send_buffer_size(batch_bytes_max);
+                down_since = None;
+                meters::connect_result(&shard_label, endpoint, true);
+                health.set_writer_connected(shard, true);
                 let mut batch: Vec<Vec<u8>> = Vec::with_capacity(batch_max);
+                let mut metas: Vec<FrameMeta> = Vec::with_capacity(batch_max);
                 loop {
                     if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
                         break;
                     }
+                    if yield_to() {
+                        break 'conn WriterExit::Yielded;
+                    }
                     // Adapt flush interval when queue depth is rising to bound tail
                     let curr_len = src.approx_len();
                     let eff_flush =
@@ -562,23 +924,28 @@ fn writer_loop_generic<S: BatchSource>(
 
                     let (first, retried_singleton) = match pending_frame.take() {
                         Some(frame) => (frame, true),
-                        None => match src.blocking_pop(eff_flush) {
-                            Some(frame) => (frame, false),
-                            None => break,
+                        None => match src.pop_timeout(eff_flush) {
+                            Pop::Frame(frame) => (frame, false),
+                            // Go round again so shutdown and failback are checked
+                            // while the queue is quiet.
+                            Pop::Idle => continue,
+                            // Source drained and closed: nothing left to write.
+                            Pop::Closed => break 'conn WriterExit::Finished,
                         },
                     };
-                    if first.len() > frame_bytes_max {
+                    if first.buf.len() > frame_bytes_max {
                         drop_to_dlq(
-                            first,
+                            first.buf,
                             "frame_oversize",
                             frame_bytes_max,
-                            &buf_pool,
+                            buf_pool,
                             &mut scratch,
                             dlq.as_ref(),
                         );
                         continue;
                     }
-                    batch.push(first);
+                    metas.push(first.meta);
+                    batch.push(first.buf);
                     let mut batch_bytes = batch.last().map(|b| b.len()).unwrap_or(0);
                     let salvaged_singleton = retried_singleton;
                     let start = Instant::now();
@@ -588,25 +955,26 @@ fn writer_loop_generic<S: BatchSource>(
                         }
                         match src.try_pop() {
                             Some(next) => {
-                                if next.len() > frame_bytes_max {
+                                if next.buf.len() > frame_bytes_max {
                                     drop_to_dlq(
-                                        next,
+                                        next.buf,
                                         "frame_oversize",
                                         frame_bytes_max,
-                                        &buf_pool,
+                                        buf_pool,
                                         &mut scratch,
                                         dlq.as_ref(),
                                     );
                                     continue;
                                 }
-                                if batch_bytes + next.len() > batch_bytes_max {
-                                    pending_frame = Some(next);
-                                    counter!("ys_consumer_oversized_batch_split_count")
+                                if batch_bytes + next.buf.len() > batch_bytes_max {
+                                    *pending_frame = Some(next);
+                                    counter!("ys_consumer_oversized_batch_split_count", "shard" => shard_label.clone())
                                         .increment(1);
                                     break;
                                 }
-                                batch_bytes += next.len();
-                                batch.push(next);
+                                batch_bytes += next.buf.len();
+                                metas.push(next.meta);
+                                batch.push(next.buf);
                             }
                             None => break,
                         }
@@ -623,23 +991,34 @@ fn writer_loop_generic<S: BatchSource>(
                         batch_bytes_total,
                         batch_bytes_max
                     );
-                    match write_all_vectored(&mut stream, &batch) {
+                    match write_all_vectored(&mut stream, &batch).and_then(|_| stream.flush()) {
                         Ok(()) => {
-                            counter!("ys_consumer_write_batches_total").increment(1);
-                            counter!("ys_consumer_write_bytes_total")
+                            counter!("ys_consumer_write_batches_total", "shard" => shard_label.clone()).increment(1);
+                            counter!("ys_consumer_write_bytes_total", "shard" => shard_label.clone())
                                 .increment(batch_bytes_total as u64);
-                            histogram!("ys_consumer_write_batch_frames")
+                            histogram!("ys_consumer_write_batch_frames", "shard" => shard_label.clone())
                                 .record(batch_frames as f64);
-                            histogram!("ys_consumer_write_batch_bytes")
+                            histogram!("ys_consumer_write_batch_bytes", "shard" => shard_label.clone())
                                 .record(batch_bytes_total as f64);
+                            meters::batch_sent(
+                                &shard_label,
+                                endpoint,
+                                batch_frames,
+                                batch_bytes_total,
+                            );
                             FRAMES_PROCESSED.fetch_add(batch_frames as u64, Ordering::Relaxed);
+                            record_lag_batch("write", &metas);
                             update_ratios();
                             if salvaged_singleton {
-                                counter!("ys_consumer_singleton_salvaged_count").increment(1);
+                                counter!("ys_consumer_singleton_salvaged_count", "shard" => shard_label.clone()).increment(1);
                             }
                         }
                         Err(e) => {
-                            error!(target = "ys.consumer", "write error: {}", e);
+                            error!(target = "ys.consumer", shard, "write error: {}", e);
+                            meters::write_error(&shard_label, endpoint);
+                            for m in &metas {
+                                meters::dropped(m.kind, "write_error", shard);
+                            }
                             for frame in batch.drain(..) {
                                 buf_pool.put(frame);
                             }
@@ -649,48 +1028,75 @@ fn writer_loop_generic<S: BatchSource>(
                     for frame in batch.drain(..) {
                         buf_pool.put(frame);
                     }
+                    metas.clear();
+                }
+                health.set_writer_connected(shard, false);
+                if !shutdown.load(Ordering::Relaxed) {
+                    meters::reconnect(&shard_label, endpoint);
+                    down_since = Some(Instant::now());
                 }
                 thread::sleep(Duration::from_millis(100));
                 backoff = Duration::from_millis(50);
             }
             Err(err) => {
+                meters::connect_result(&shard_label, endpoint, false);
                 error!(
                     target = "ys.consumer",
-                    "connect {} failed: {}", uds_path, err
+                    shard, "connect {} failed: {}", endpoint, err
                 );
+                let since = *down_since.get_or_insert_with(Instant::now);
+                if outage_limit.is_some_and(|limit| since.elapsed() >= limit) {
+                    break WriterExit::Outage;
+                }
                 thread::sleep(backoff);
                 backoff = (backoff * 2).min(Duration::from_secs(2));
                 continue;
             }
         }
+    };
+    if exit == WriterExit::Finished {
+        if let Some(frame) = pending_frame.take() {
+            buf_pool.put(frame.buf);
+        }
     }
-    if let Some(frame) = pending_frame.take() {
-        buf_pool.put(frame);
-    }
+    exit
 }
 
 fn writer_loop_shm<S: BatchSource>(
+    endpoint: &str,
     mut ring: shm_ring::ShmRingWriter,
     src: S,
-    shutdown: &std::sync::Arc<std::sync::atomic::AtomicBool>,
-    limits: WriterLimits,
-    flush_interval: Duration,
-    buf_pool: std::sync::Arc<BufPool>,
-    dlq: Option<DlqSink>,
-) {
+    ctx: &WriterCtx,
+    pending_frame: &mut Option<QueuedFrame>,
+    max_wait: Duration,
+    mut yield_to: impl FnMut() -> bool,
+) -> WriterExit {
+    let WriterCtx {
+        shard,
+        ref shutdown,
+        limits,
+        flush_interval,
+        ref buf_pool,
+        ref dlq,
+        health: _,
+    } = *ctx;
     let WriterLimits {
         batch_max,
         batch_bytes_max,
         frame_bytes_max,
     } = limits;
-    let mut pending_frame: Option<Vec<u8>> = None;
+    let shard_label = shard.to_string();
     let mut scratch: Vec<u8> = Vec::with_capacity(8 * 1024);
     let mut prev_queue_len: usize = 0;
-    loop {
+    let exit = loop {
         if shutdown.load(std::sync::atomic::Ordering::Relaxed) {
-            break;
+            break WriterExit::Finished;
+        }
+        if yield_to() {
+            break WriterExit::Yielded;
         }
         let mut batch: Vec<Vec<u8>> = Vec::with_capacity(batch_max);
+        let mut metas: Vec<FrameMeta> = Vec::with_capacity(batch_max);
         // Similar batching to UDS to amortize per-iteration overhead
         let curr_len = src.approx_len();
         let eff_flush = effective_flush_interval(flush_interval, prev_queue_len, curr_len);
@@ -698,23 +1104,25 @@ fn writer_loop_shm<S: BatchSource>(
 
         let (first, retried_singleton) = match pending_frame.take() {
             Some(frame) => (frame, true),
-            None => match src.blocking_pop(eff_flush) {
-                Some(frame) => (frame, false),
-                None => break,
+            None => match src.pop_timeout(eff_flush) {
+                Pop::Frame(frame) => (frame, false),
+                Pop::Idle => continue,
+                Pop::Closed => break WriterExit::Finished,
             },
         };
-        if first.len() > frame_bytes_max {
+        if first.buf.len() > frame_bytes_max {
             drop_to_dlq(
-                first,
+                first.buf,
                 "frame_oversize",
                 frame_bytes_max,
-                &buf_pool,
+                buf_pool,
                 &mut scratch,
                 dlq.as_ref(),
             );
             continue;
         }
-        batch.push(first);
+        metas.push(first.meta);
+        batch.push(first.buf);
         let mut batch_bytes = batch.last().map(|b| b.len()).unwrap_or(0);
         let salvaged_singleton = retried_singleton;
         let start = Instant::now();
@@ -724,24 +1132,25 @@ fn writer_loop_shm<S: BatchSource>(
             }
             match src.try_pop() {
                 Some(next) => {
-                    if next.len() > frame_bytes_max {
+                    if next.buf.len() > frame_bytes_max {
                         drop_to_dlq(
-                            next,
+                            next.buf,
                             "frame_oversize",
                             frame_bytes_max,
-                            &buf_pool,
+                            buf_pool,
                             &mut scratch,
                             dlq.as_ref(),
                         );
                         continue;
                     }
-                    if batch_bytes + next.len() > batch_bytes_max {
-                        pending_frame = Some(next);
-                        counter!("ys_consumer_oversized_batch_split_count").increment(1);
+                    if batch_bytes + next.buf.len() > batch_bytes_max {
+                        *pending_frame = Some(next);
+                        counter!("ys_consumer_oversized_batch_split_count", "shard" => shard_label.clone()).increment(1);
                         break;
                     }
-                    batch_bytes += next.len();
-                    batch.push(next);
+                    batch_bytes += next.buf.len();
+                    metas.push(next.meta);
+                    batch.push(next.buf);
                 }
                 None => break,
             }
@@ -750,29 +1159,43 @@ fn writer_loop_shm<S: BatchSource>(
             continue;
         }
         let mut wrote = 0usize;
+        // One wait budget per batch so a stalled reader cannot hold it for
+        // `max_wait` per frame.
+        let wait_deadline = Instant::now() + max_wait;
         for frame in &batch {
-            if ring.try_push(frame) {
+            let budget = wait_deadline.saturating_duration_since(Instant::now());
+            if ring.push_timeout(frame, budget) {
                 wrote += 1;
             } else {
-                counter!("ys_consumer_shm_backpressure_drops_total").increment(1);
+                counter!("ys_consumer_shm_backpressure_drops_total", "shard" => shard_label.clone()).increment(1);
                 // On failure, stop and requeue remaining into pool to avoid reordering
                 break;
             }
         }
         if wrote == batch.len() {
-            counter!("ys_consumer_write_batches_total").increment(1);
-            counter!("ys_consumer_write_bytes_total").increment(batch_bytes as u64);
-            histogram!("ys_consumer_write_batch_frames").record(batch.len() as f64);
-            histogram!("ys_consumer_write_batch_bytes").record(batch_bytes as f64);
+            meters::batch_sent(&shard_label, endpoint, batch.len(), batch_bytes);
+            counter!("ys_consumer_write_batches_total", "shard" => shard_label.clone())
+                .increment(1);
+            counter!("ys_consumer_write_bytes_total", "shard" => shard_label.clone())
+                .increment(batch_bytes as u64);
+            histogram!("ys_consumer_write_batch_frames", "shard" => shard_label.clone())
+                .record(batch.len() as f64);
+            histogram!("ys_consumer_write_batch_bytes", "shard" => shard_label.clone())
+                .record(batch_bytes as f64);
             FRAMES_PROCESSED.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            record_lag_batch("write", &metas);
             update_ratios();
             if salvaged_singleton {
-                counter!("ys_consumer_singleton_salvaged_count").increment(1);
+                counter!("ys_consumer_singleton_salvaged_count", "shard" => shard_label.clone())
+                    .increment(1);
             }
             for frame in batch.drain(..) {
                 buf_pool.put(frame);
             }
         } else {
+            for m in &metas[wrote..] {
+                meters::dropped(m.kind, "ring_full", shard);
+            }
             // Put back un-sent frames (including the first failure and any remaining)
             for frame in batch.drain(..) {
                 buf_pool.put(frame);
@@ -780,9 +1203,505 @@ fn writer_loop_shm<S: BatchSource>(
             // Back off very briefly to allow reader to advance
             std::thread::sleep(Duration::from_micros(100));
         }
+    };
+    if exit == WriterExit::Finished {
+        if let Some(frame) = pending_frame.take() {
+            buf_pool.put(frame.buf);
+        }
     }
-    if let Some(frame) = pending_frame.take() {
-        buf_pool.put(frame);
+    exit
+}
+
+fn default_shm_path() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "/dev/shm/ultra-faststreams.ring"
+    } else {
+        "/tmp/ultra-faststreams.ring"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OutputTarget {
+    Uds(String),
+    Shm {
+        path: String,
+        capacity_bytes: usize,
+        /// How long a writer waits for the reader to free space before dropping.
+        max_wait: Duration,
+    },
+    Tls(tls_out::TlsOutputConfig),
+    /// Append frames to a faststreams capture file for later `replay-capture`.
+    Capture(String),
+}
+
+impl OutputTarget {
+    fn from_env() -> std::io::Result<Self> {
+        let output_mode = std::env::var("YS_OUTPUT").unwrap_or_else(|_| "uds".to_string());
+        Self::from_spec(&output_mode)
+    }
+
+    /// `mode[:path]`, e.g. `shm:/dev/shm/accounts` or `uds:/run/indexer.sock`.
+    /// Without a path, and for everything else about the mode, the usual env vars apply.
+    fn from_spec(spec: &str) -> std::io::Result<Self> {
+        let (mode, path) = match spec.split_once(':') {
+            Some((mode, path)) => (mode, Some(path.to_string()).filter(|p| !p.is_empty())),
+            None => (spec, None),
+        };
+        Ok(match mode {
+            "tls" | "tcp-tls" => OutputTarget::Tls(tls_out::TlsOutputConfig::from_env()?),
+            "capture" => OutputTarget::Capture(
+                path.or_else(|| std::env::var("YS_CAPTURE_PATH").ok())
+                    .unwrap_or_else(|| "ys-consumer.fscap".to_string()),
+            ),
+            "shm" | "ring" | "shmem" => OutputTarget::Shm {
+                path: path
+                    .or_else(|| std::env::var("YS_SHM_PATH").ok())
+                    .unwrap_or_else(|| default_shm_path().to_string()),
+                capacity_bytes: std::env::var("YS_SHM_CAP_BYTES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(64 * 1024 * 1024),
+                max_wait: Duration::from_micros(
+                    std::env::var("YS_SHM_MAX_WAIT_US")
+                        .ok()
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(0),
+                ),
+            },
+            "" | "uds" | "unix" => OutputTarget::Uds(
+                path.or_else(|| std::env::var("ULTRA_UDS").ok())
+                    .unwrap_or_else(|| "/var/run/ultra-geyser.sock".to_string()),
+            ),
+            other => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "unknown output mode {other:?} in {spec:?} (uds|unix|shm|ring|shmem|tls|tcp-tls|capture)"
+                    ),
+                ))
+            }
+        })
+    }
+
+    /// Local hops favour latency; the remote TLS hop compresses to save bandwidth.
+    fn encode_profile(&self) -> fn() -> EncodeOptions {
+        match self {
+            OutputTarget::Tls(_) => EncodeOptions::throughput_lz4_low,
+            _ => EncodeOptions::latency_uds,
+        }
+    }
+
+    /// UDS shards share one listener; SHM rings are single-producer, so each
+    /// shard gets its own `<path>.<shard>` ring (or capture file) when there is
+    /// more than one.
+    fn for_shard(&self, shard: usize, count: usize) -> Self {
+        match self {
+            OutputTarget::Shm {
+                path,
+                capacity_bytes,
+                max_wait,
+            } if count > 1 => OutputTarget::Shm {
+                path: format!("{}.{}", path, shard),
+                capacity_bytes: *capacity_bytes,
+                max_wait: *max_wait,
+            },
+            OutputTarget::Capture(path) if count > 1 => {
+                OutputTarget::Capture(format!("{}.{}", path, shard))
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+/// Index into the output groups for each record kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct KindRoutes {
+    accounts: usize,
+    transactions: usize,
+    blocks: usize,
+    slots: usize,
+}
+
+impl KindRoutes {
+    /// Output group the live path sends `rec` to.
+    fn group_for(&self, rec: &Record) -> usize {
+        match rec {
+            Record::Account(_) => self.accounts,
+            Record::Tx(_) => self.transactions,
+            Record::Block(_) => self.blocks,
+            Record::Slot { .. } => self.slots,
+            Record::EndOfStartup => 0,
+        }
+    }
+}
+
+/// Resolve `YS_ROUTE_{ACCOUNTS,TRANSACTIONS,BLOCKS,SLOTS}` against the default output.
+/// Kinds without a route use the default; identical targets share one output group.
+fn resolve_routes(
+    default: &OutputTarget,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> std::io::Result<(Vec<OutputTarget>, KindRoutes)> {
+    let mut targets = vec![default.clone()];
+    let mut route = |var: &str| -> std::io::Result<usize> {
+        let Some(spec) = lookup(var).filter(|s| !s.is_empty() && s != "default") else {
+            return Ok(0);
+        };
+        let target = OutputTarget::from_spec(&spec)?;
+        Ok(match targets.iter().position(|t| *t == target) {
+            Some(i) => i,
+            None => {
+                targets.push(target);
+                targets.len() - 1
+            }
+        })
+    };
+    let routes = KindRoutes {
+        accounts: route("YS_ROUTE_ACCOUNTS")?,
+        transactions: route("YS_ROUTE_TRANSACTIONS")?,
+        blocks: route("YS_ROUTE_BLOCKS")?,
+        slots: route("YS_ROUTE_SLOTS")?,
+    };
+    Ok((targets, routes))
+}
+
+// Writer shards for one output target.
+struct OutputGroup {
+    shards: Vec<ShardSender>,
+    encode_profile: fn() -> EncodeOptions,
+}
+
+/// Where a UDS shard writes while its consumer is away, and when to switch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Failover {
+    target: OutputTarget,
+    after: Duration,
+    probe_every: Duration,
+}
+
+impl Failover {
+    /// `YS_FAILOVER_OUTPUT` (an `shm[:path]` or `capture[:path]` spec) enables failover
+    /// once a UDS output has been unreachable for `YS_FAILOVER_AFTER_MS`; the socket is
+    /// probed every `YS_FAILOVER_PROBE_MS` to fail back.
+    fn from_env() -> std::io::Result<Option<Self>> {
+        let Some(spec) = std::env::var("YS_FAILOVER_OUTPUT")
+            .ok()
+            .filter(|s| !s.is_empty())
+        else {
+            return Ok(None);
+        };
+        let target = OutputTarget::from_spec(&spec)?;
+        if !matches!(target, OutputTarget::Shm { .. } | OutputTarget::Capture(_)) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("YS_FAILOVER_OUTPUT must be an shm or capture output, got {spec}"),
+            ));
+        }
+        let ms = |var: &str, default: u64| {
+            Duration::from_millis(
+                std::env::var(var)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default),
+            )
+        };
+        Ok(Some(Self {
+            target,
+            after: ms("YS_FAILOVER_AFTER_MS", 5_000),
+            probe_every: ms("YS_FAILOVER_PROBE_MS", 1_000),
+        }))
+    }
+
+    fn for_shard(&self, shard: usize, count: usize) -> Self {
+        Self {
+            target: self.target.for_shard(shard, count),
+            ..self.clone()
+        }
+    }
+}
+
+// Drain the shard into the failover output until `primary_back` reports the
+// primary reachable again (`Yielded`).
+fn run_failover<S: BatchSource>(
+    target: &OutputTarget,
+    src: S,
+    ctx: &WriterCtx,
+    pending: &mut Option<QueuedFrame>,
+    primary_back: impl FnMut() -> bool,
+) -> WriterExit {
+    match target {
+        OutputTarget::Shm {
+            path,
+            capacity_bytes,
+            max_wait,
+        } => match shm_ring::ShmRingWriter::open_or_create(path, *capacity_bytes) {
+            Ok(ring) => {
+                ctx.health.set_writer_connected(ctx.shard, true);
+                let exit = writer_loop_shm(path, ring, src, ctx, pending, *max_wait, primary_back);
+                ctx.health.set_writer_connected(ctx.shard, false);
+                exit
+            }
+            Err(e) => {
+                // Go back to retrying the primary; the next outage tries again.
+                error!(
+                    shard = ctx.shard,
+                    "failover shm open {} failed: {}", path, e
+                );
+                WriterExit::Yielded
+            }
+        },
+        OutputTarget::Capture(path) => {
+            let buf_capacity = ctx.limits.batch_bytes_max;
+            let connect = || capture::CaptureSink::open(path, buf_capacity);
+            writer_loop_generic(path, connect, src, ctx, pending, None, primary_back)
+        }
+        OutputTarget::Uds(_) | OutputTarget::Tls(_) => {
+            unreachable!("Failover::from_env only accepts shm and capture targets")
+        }
+    }
+}
+
+fn spawn_writer<S: BatchSource + Send + 'static>(
+    src: S,
+    ctx: WriterCtx,
+    target: OutputTarget,
+    failover: Option<Failover>,
+) -> std::io::Result<thread::JoinHandle<()>> {
+    // Build TLS config before spawning so bad certificates fail startup.
+    let tls_client = match &target {
+        OutputTarget::Tls(cfg) => Some(cfg.client_config()?),
+        _ => None,
+    };
+    thread::Builder::new()
+        .name(format!("ys-writer-{}", ctx.shard))
+        .spawn(move || match target {
+            OutputTarget::Uds(path) => {
+                let send_buf = ctx.limits.batch_bytes_max;
+                let connect = || {
+                    let stream = uds_connect(&path)?;
+                    let _ = socket2::SockRef::from(&stream).set_send_buffer_size(send_buf);
+                    Ok(stream)
+                };
+                let Some(failover) = failover else {
+                    writer_loop_generic(&path, connect, src, &ctx, &mut None, None, || false);
+                    return;
+                };
+                let shard_label = ctx.shard.to_string();
+                let outage = Some(failover.after);
+                // A frame already off the queue, carried across each switch.
+                let mut held = None;
+                while writer_loop_generic(&path, &connect, &src, &ctx, &mut held, outage, || false)
+                    == WriterExit::Outage
+                {
+                    warn!(
+                        shard = ctx.shard,
+                        "{} unreachable for {:?}; failing over to {:?}",
+                        path,
+                        failover.after,
+                        failover.target
+                    );
+                    counter!("ys_consumer_failover_total", "shard" => shard_label.clone(), "direction" => "failover")
+                        .increment(1);
+                    let mut last_probe = Instant::now();
+                    // The probe connection is closed right away; the writer loop reconnects.
+                    let primary_back = || {
+                        if last_probe.elapsed() < failover.probe_every {
+                            return false;
+                        }
+                        last_probe = Instant::now();
+                        uds_connect(&path).is_ok()
+                    };
+                    if run_failover(&failover.target, &src, &ctx, &mut held, primary_back)
+                        != WriterExit::Yielded
+                    {
+                        break;
+                    }
+                    info!(shard = ctx.shard, "{} reachable again; failing back", path);
+                    counter!("ys_consumer_failover_total", "shard" => shard_label.clone(), "direction" => "failback")
+                        .increment(1);
+                }
+            }
+            OutputTarget::Tls(cfg) => {
+                let Some(client) = tls_client else { return };
+                let send_buf = ctx.limits.batch_bytes_max;
+                // Coalesce each batch into few TLS records instead of one per frame.
+                let connect = || {
+                    cfg.connect(&client, send_buf)
+                        .map(|s| std::io::BufWriter::with_capacity(send_buf, s))
+                };
+                writer_loop_generic(&cfg.addr, connect, src, &ctx, &mut None, None, || false);
+            }
+            OutputTarget::Capture(path) => {
+                let buf_capacity = ctx.limits.batch_bytes_max;
+                let connect = || capture::CaptureSink::open(&path, buf_capacity);
+                writer_loop_generic(&path, connect, src, &ctx, &mut None, None, || false);
+            }
+            OutputTarget::Shm {
+                path,
+                capacity_bytes,
+                max_wait,
+            } => {
+                let mut backoff = Duration::from_millis(50);
+                while !ctx.shutdown.load(Ordering::Relaxed) {
+                    match shm_ring::ShmRingWriter::open_or_create(&path, capacity_bytes) {
+                        Ok(ring) => {
+                            info!(shard = ctx.shard, "writing to SHM ring {}", path);
+                            ctx.health.set_writer_connected(ctx.shard, true);
+                            let held = &mut None;
+                            writer_loop_shm(&path, ring, src, &ctx, held, max_wait, || false);
+                            ctx.health.set_writer_connected(ctx.shard, false);
+                            break;
+                        }
+                        Err(e) => {
+                            error!(shard = ctx.shard, "shm open {} failed: {}", path, e);
+                            std::thread::sleep(backoff);
+                            backoff = (backoff * 2).min(Duration::from_secs(2));
+                        }
+                    }
+                }
+            }
+        })
+}
+
+// Commitment per stream type; `None` means the stream is not subscribed.
+#[derive(Debug, Clone, Copy, Default)]
+struct StreamSelection {
+    slots: Option<CommitmentLevel>,
+    accounts: Option<CommitmentLevel>,
+    transactions: Option<CommitmentLevel>,
+    blocks: Option<CommitmentLevel>,
+    blocks_meta: Option<CommitmentLevel>,
+}
+
+fn parse_commitment(value: &str) -> Option<CommitmentLevel> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "processed" => Some(CommitmentLevel::Processed),
+        "confirmed" => Some(CommitmentLevel::Confirmed),
+        "finalized" => Some(CommitmentLevel::Finalized),
+        _ => None,
+    }
+}
+
+/// Resolve a stream's commitment: per-stream override, then `YS_COMMITMENT`, then processed.
+fn stream_commitment(enabled: bool, override_var: &str) -> Result<Option<CommitmentLevel>> {
+    if !enabled {
+        return Ok(None);
+    }
+    for name in [override_var, "YS_COMMITMENT"] {
+        if let Some(v) = std::env::var(name).ok().filter(|v| !v.is_empty()) {
+            return parse_commitment(&v).map(Some).ok_or_else(|| {
+                anyhow::anyhow!("invalid {}={} (processed|confirmed|finalized)", name, v)
+            });
+        }
+    }
+    Ok(Some(CommitmentLevel::Processed))
+}
+
+/// Yellowstone applies one commitment per subscription, so streams are grouped
+/// by commitment and each group becomes its own `SubscribeRequest`. Full blocks
+/// and block meta both encode as `Record::Block`, so block meta is only
+/// subscribed when full blocks are off.
+fn build_subscribe_requests(
+    sel: &StreamSelection,
+    account_filters: &HashMap<String, SubscribeRequestFilterAccounts>,
+) -> Vec<SubscribeRequest> {
+    let mut reqs: Vec<SubscribeRequest> = Vec::new();
+    for level in [
+        CommitmentLevel::Processed,
+        CommitmentLevel::Confirmed,
+        CommitmentLevel::Finalized,
+    ] {
+        let want = |c: Option<CommitmentLevel>| c == Some(level);
+        let mut req = SubscribeRequest {
+            commitment: Some(level as i32),
+            accounts_data_slice: vec![],
+            ping: Some(SubscribeRequestPing { id: 0 }),
+            ..Default::default()
+        };
+        if want(sel.slots) {
+            req.slots.insert(
+                "".to_string(),
+                SubscribeRequestFilterSlots {
+                    // Only emit slot updates once they reach the requested level.
+                    filter_by_commitment: (level != CommitmentLevel::Processed).then_some(true),
+                    ..Default::default()
+                },
+            );
+        }
+        if want(sel.accounts) {
+            req.accounts = account_filters.clone();
+        }
+        if want(sel.transactions) {
+            req.transactions.insert(
+                "".to_string(),
+                SubscribeRequestFilterTransactions::default(),
+            );
+        }
+        if want(sel.blocks) {
+            req.blocks
+                .insert("".to_string(), SubscribeRequestFilterBlocks::default());
+        }
+        if want(sel.blocks_meta) && sel.blocks.is_none() {
+            req.blocks_meta
+                .insert("".to_string(), SubscribeRequestFilterBlocksMeta::default());
+        }
+        let empty = req.slots.is_empty()
+            && req.accounts.is_empty()
+            && req.transactions.is_empty()
+            && req.blocks.is_empty()
+            && req.blocks_meta.is_empty();
+        if !empty {
+            reqs.push(req);
+        }
+    }
+    reqs
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DrainReport {
+    flushed: u64,
+    dropped: u64,
+    timed_out: bool,
+}
+
+/// Wait for the writers to flush the queue, forcing shutdown once `deadline` passes.
+fn drain_writer(
+    writers: Vec<thread::JoinHandle<()>>,
+    queue_depth: impl Fn() -> usize,
+    draining: &std::sync::atomic::AtomicBool,
+    shutdown: &std::sync::atomic::AtomicBool,
+    deadline: Duration,
+) -> DrainReport {
+    let processed_before = FRAMES_PROCESSED.load(Ordering::Relaxed);
+    info!(
+        queued = queue_depth(),
+        "draining writer queue (deadline {:?})", deadline
+    );
+    draining.store(true, Ordering::Release);
+    let start = Instant::now();
+    let all_finished = |w: &[thread::JoinHandle<()>]| w.iter().all(|h| h.is_finished());
+    while !all_finished(&writers) && start.elapsed() < deadline {
+        thread::sleep(Duration::from_millis(5));
+    }
+    let timed_out = !all_finished(&writers);
+    let dropped = if timed_out {
+        let left = queue_depth() as u64;
+        shutdown.store(true, Ordering::Relaxed);
+        left
+    } else {
+        0
+    };
+    for writer in writers {
+        let _ = writer.join();
+    }
+    let flushed = FRAMES_PROCESSED
+        .load(Ordering::Relaxed)
+        .saturating_sub(processed_before);
+    counter!("ys_consumer_drain_flushed_total").increment(flushed);
+    counter!("ys_consumer_drain_dropped_total").increment(dropped);
+    DrainReport {
+        flushed,
+        dropped,
+        timed_out,
     }
 }
 
@@ -792,14 +1711,48 @@ async fn main() -> Result<()> {
         .with_env_filter(EnvFilter::from_default_env().add_directive("info".parse()?))
         .init();
 
+    if let Some(token) = std::env::var("YS_AUTH_TOKEN")
+        .ok()
+        .filter(|t| !t.is_empty())
+    {
+        let _ = AUTH_FRAME.set(faststreams::encode_auth_frame(token.as_bytes()));
+    }
+    let cli_args: Vec<String> = std::env::args().skip(1).collect();
+    if cli_args.first().map(|s| s.as_str()) == Some("replay-dlq") {
+        let (targets, routes) =
+            resolve_routes(&OutputTarget::from_env()?, |k| std::env::var(k).ok())?;
+        let outputs = dlq_replay::ReplayOutputs {
+            targets,
+            routes,
+            writers: env_usize("YS_WRITERS", 1).max(1),
+        };
+        let args = dlq_replay::ReplayArgs::parse(&cli_args[1..], outputs)?;
+        let stats = tokio::task::spawn_blocking(move || dlq_replay::run(&args)).await??;
+        if stats.failed > 0 {
+            anyhow::bail!("{} DLQ frames failed to replay", stats.failed);
+        }
+        return Ok(());
+    }
+    if cli_args.first().map(|s| s.as_str()) == Some("replay-capture") {
+        let args = capture::ReplayArgs::parse(&cli_args[1..], OutputTarget::from_env()?)?;
+        tokio::task::spawn_blocking(move || capture::run(&args)).await??;
+        return Ok(());
+    }
+
     let endpoint = std::env::var("YS_ENDPOINT").expect("YS_ENDPOINT");
     let x_token = std::env::var("YS_X_TOKEN").ok();
-    let uds_path =
-        std::env::var("ULTRA_UDS").unwrap_or_else(|_| "/var/run/ultra-geyser.sock".to_string());
+    STAMP_ORIGIN.store(
+        matches!(
+            std::env::var("YS_STAMP_ORIGIN").as_deref(),
+            Ok("1" | "true" | "TRUE" | "yes" | "y")
+        ),
+        Ordering::Relaxed,
+    );
     let metrics_addr = std::env::var("YS_METRICS_ADDR").ok();
 
     if let Some(addr) = metrics_addr.as_deref() {
         let _ = PrometheusBuilder::new()
+            .add_global_label("producer", meters::PRODUCER)
             .with_http_listener(addr.parse::<std::net::SocketAddr>().unwrap())
             .install();
     }
@@ -826,53 +1779,50 @@ async fn main() -> Result<()> {
             .unwrap_or(default)
     }
 
-    let sub_slots = env_bool("YS_SUB_SLOTS", true);
-    let sub_accounts = env_bool("YS_SUB_ACCOUNTS", true);
-    let sub_transactions = env_bool("YS_SUB_TRANSACTIONS", true);
-    let sub_blocks = env_bool("YS_SUB_BLOCKS", true);
-    let sub_blocks_meta = env_bool("YS_SUB_BLOCKS_META", true);
-
-    let mut slots = HashMap::new();
-    if sub_slots {
-        slots.insert("".to_string(), SubscribeRequestFilterSlots::default());
-    }
-    let mut accounts = HashMap::new();
-    if sub_accounts {
-        accounts.insert("".to_string(), SubscribeRequestFilterAccounts::default());
+    let streams = StreamSelection {
+        slots: stream_commitment(env_bool("YS_SUB_SLOTS", true), "YS_COMMITMENT_SLOTS")?,
+        accounts: stream_commitment(env_bool("YS_SUB_ACCOUNTS", true), "YS_COMMITMENT_ACCOUNTS")?,
+        transactions: stream_commitment(
+            env_bool("YS_SUB_TRANSACTIONS", true),
+            "YS_COMMITMENT_TRANSACTIONS",
+        )?,
+        blocks: stream_commitment(env_bool("YS_SUB_BLOCKS", true), "YS_COMMITMENT_BLOCKS")?,
+        blocks_meta: stream_commitment(
+            env_bool("YS_SUB_BLOCKS_META", true),
+            "YS_COMMITMENT_BLOCKS_META",
+        )?,
+    };
+    if streams.blocks.is_some() && streams.blocks_meta.is_some() {
+        info!("full blocks subscribed; block meta stream skipped to avoid duplicate block records");
     }
-    let mut transactions = HashMap::new();
-    if sub_transactions {
-        transactions.insert(
-            "".to_string(),
-            SubscribeRequestFilterTransactions::default(),
-        );
+    let account_filters = mints::account_filters_from_env()?;
+    if !account_filters.contains_key("") {
+        info!("account stream narrowed to filters {:?}", {
+            let mut names: Vec<_> = account_filters.keys().collect();
+            names.sort();
+            names
+        });
     }
-    let mut blocks = HashMap::new();
-    if sub_blocks {
-        blocks.insert("".to_string(), SubscribeRequestFilterBlocks::default());
-    }
-    let mut blocks_meta = HashMap::new();
-    if sub_blocks_meta {
-        blocks_meta.insert("".to_string(), SubscribeRequestFilterBlocksMeta::default());
-    }
-
-    let req = SubscribeRequest {
-        slots,
-        accounts,
-        transactions,
-        blocks,
-        blocks_meta,
-        commitment: Some(CommitmentLevel::Processed as i32),
-        accounts_data_slice: vec![],
-        ping: Some(SubscribeRequestPing { id: 0 }),
-        ..Default::default()
-    };
+    let reqs = build_subscribe_requests(&streams, &account_filters);
+    info!(
+        "subscribing with {} request(s): {:?}",
+        reqs.len(),
+        reqs.iter()
+            .map(
+                |r| CommitmentLevel::try_from(r.commitment.unwrap_or_default())
+                    .map(|c| c.as_str_name())
+                    .unwrap_or("unknown")
+            )
+            .collect::<Vec<_>>()
+    );
     let backoff_min = Duration::from_millis(env_u64("YS_BACKOFF_MIN_MS", 250));
     let backoff_max = Duration::from_millis(env_u64("YS_BACKOFF_MAX_MS", 10_000));
     let idle_timeout = Duration::from_millis(env_u64("YS_IDLE_TIMEOUT_MS", 3_000));
     let mut reconnect_backoff = backoff_min;
 
     let shutdown = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let draining = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let drain_timeout = Duration::from_millis(env_u64("YS_DRAIN_TIMEOUT_MS", 5_000));
     let queue_cap = env_usize("YS_QUEUE_CAP", 65_536);
     let batch_max = env_usize("YS_BATCH_MAX", 1024);
     let batch_bytes_max = env_usize("YS_BATCH_BYTES_MAX", 2 * 1024 * 1024);
@@ -886,20 +1836,25 @@ async fn main() -> Result<()> {
     let flush_interval_ms = env_u64("YS_FLUSH_INTERVAL_MS", 1);
     let flush_interval = Duration::from_millis(std::cmp::max(1, flush_interval_ms));
     let use_spsc = env_bool("YS_SPSC", false);
-    let output_mode = std::env::var("YS_OUTPUT").unwrap_or_else(|_| "uds".to_string());
-    let use_shm = matches!(output_mode.as_str(), "shm" | "ring" | "shmem");
-    let shm_path_default = if cfg!(target_os = "linux") {
-        "/dev/shm/ultra-faststreams.ring"
-    } else {
-        "/tmp/ultra-faststreams.ring"
-    };
-    let shm_path = std::env::var("YS_SHM_PATH").unwrap_or_else(|_| shm_path_default.to_string());
-    let shm_cap_bytes = env_usize("YS_SHM_CAP_BYTES", 64 * 1024 * 1024);
+    let output = OutputTarget::from_env()?;
 
     // Buffer pool config
     let buf_pool_cap = env_usize("YS_BUF_POOL_CAP", queue_cap);
     let buf_default_cap = env_usize("YS_BUF_DEFAULT_CAP", 4096);
-    let buf_pool = std::sync::Arc::new(BufPool::new(buf_pool_cap, buf_default_cap));
+    let buf_max_retain = env_usize("YS_BUF_MAX_RETAIN_BYTES", 256 * 1024);
+    let buf_pool_max_bytes = env_usize("YS_BUF_POOL_MAX_BYTES", 64 * 1024 * 1024);
+    let buf_pool = std::sync::Arc::new(BufPool::new(
+        buf_pool_cap,
+        buf_default_cap,
+        buf_max_retain,
+        buf_pool_max_bytes,
+    ));
+
+    let encode_sampler = EncodeSampler::from_env();
+    match encode_sampler.every() {
+        0 => info!("encode timing disabled"),
+        n => info!("encode timing sampled 1/{}", n),
+    }
 
     let pubkey_cache_cap = env_usize("YS_PUBKEY_CACHE_CAP", 8_192);
     let mut address_cache = AddressCache::new(pubkey_cache_cap);
@@ -916,155 +1871,124 @@ async fn main() -> Result<()> {
         None => None,
     };
 
-    // queue and writer
-    let mut txq_opt: Option<crossbeam_channel::Sender<Vec<u8>>> = None;
-    let mut spsc_send_opt: Option<SpscSender> = None;
-    if use_spsc {
-        let inner_q = std::sync::Arc::new(ArrayQueue::<Vec<u8>>::new(queue_cap));
-        let ev = std::sync::Arc::new(Event::new());
-        spsc_send_opt = Some(SpscSender {
-            q: inner_q.clone(),
-            ev: ev.clone(),
-        });
-        let uds_path_clone = uds_path.clone();
-        let sd = shutdown.clone();
-        let src = SpscQueue { q: inner_q, ev };
-        let pool = buf_pool.clone();
-        let dlq_clone = dlq_sink.clone();
-        if use_shm {
-            let sd2 = shutdown.clone();
-            let pool2 = buf_pool.clone();
-            let dlq2 = dlq_sink.clone();
-            let shm_path2 = shm_path.clone();
-            thread::Builder::new()
-                .name("ys-writer".into())
-                .spawn(move || {
-                    let mut backoff = Duration::from_millis(50);
-                    loop {
-                        if sd2.load(Ordering::Relaxed) {
-                            break;
-                        }
-                        match shm_ring::ShmRingWriter::open_or_create(&shm_path2, shm_cap_bytes) {
-                            Ok(ring) => {
-                                info!("writing to SHM ring {}", shm_path2);
-                                writer_loop_shm(
-                                    ring,
-                                    src,
-                                    &sd,
-                                    writer_limits,
-                                    flush_interval,
-                                    pool2.clone(),
-                                    dlq2.clone(),
-                                );
-                                break;
-                            }
-                            Err(e) => {
-                                error!("shm open {} failed: {}", shm_path2, e);
-                                std::thread::sleep(backoff);
-                                backoff = (backoff * 2).min(Duration::from_secs(2));
-                            }
-                        }
-                    }
-                })?;
-        } else {
-            thread::Builder::new()
-                .name("ys-writer".into())
-                .spawn(move || {
-                    writer_loop_generic(
-                        uds_path_clone,
-                        src,
-                        &sd,
-                        writer_limits,
-                        flush_interval,
-                        pool,
-                        dlq_clone,
-                    );
-                })?;
-        }
-    } else {
-        let (txq, rxq) = bounded::<Vec<u8>>(queue_cap);
-        let uds_path_clone = uds_path.clone();
-        let sd = shutdown.clone();
-        let pool = buf_pool.clone();
-        let dlq_clone = dlq_sink.clone();
-        if use_shm {
-            let sd2 = shutdown.clone();
-            let pool2 = buf_pool.clone();
-            let dlq2 = dlq_sink.clone();
-            let shm_path2 = shm_path.clone();
-            thread::Builder::new()
-                .name("ys-writer".into())
-                .spawn(move || {
-                    let mut backoff = Duration::from_millis(50);
-                    loop {
-                        if sd2.load(Ordering::Relaxed) {
-                            break;
-                        }
-                        match shm_ring::ShmRingWriter::open_or_create(&shm_path2, shm_cap_bytes) {
-                            Ok(ring) => {
-                                info!("writing to SHM ring {}", shm_path2);
-                                writer_loop_shm(
-                                    ring,
-                                    rxq,
-                                    &sd,
-                                    writer_limits,
-                                    flush_interval,
-                                    pool2.clone(),
-                                    dlq2.clone(),
-                                );
-                                break;
-                            }
-                            Err(e) => {
-                                error!("shm open {} failed: {}", shm_path2, e);
-                                std::thread::sleep(backoff);
-                                backoff = (backoff * 2).min(Duration::from_secs(2));
-                            }
-                        }
-                    }
-                })?;
-        } else {
-            thread::Builder::new()
-                .name("ys-writer".into())
-                .spawn(move || {
-                    writer_loop_generic(
-                        uds_path_clone,
-                        rxq,
-                        &sd,
-                        writer_limits,
-                        flush_interval,
-                        pool,
-                        dlq_clone,
-                    );
-                })?;
+    // One output group per distinct routed target, one queue + writer thread per
+    // shard within it; each writer owns its socket or SHM ring.
+    let (targets, routes) = resolve_routes(&output, |k| std::env::var(k).ok())?;
+    let failover = Failover::from_env()?;
+    let writer_count = env_usize("YS_WRITERS", 1).max(1);
+    let total_writers = writer_count * targets.len();
+    let shard_queue_cap = (queue_cap / total_writers).max(1);
+    let mut groups: Vec<OutputGroup> = Vec::with_capacity(targets.len());
+    let mut probes: Vec<QueueProbe> = Vec::with_capacity(total_writers);
+    let mut writer_handles: Vec<thread::JoinHandle<()>> = Vec::with_capacity(total_writers);
+    let health = std::sync::Arc::new(health::Health::new(
+        total_writers,
+        shard_queue_cap * total_writers,
+        Duration::from_millis(env_u64("YS_HEALTH_STALE_MS", 30_000)),
+    ));
+    for (group_idx, group_target) in targets.iter().enumerate() {
+        let mut shards: Vec<ShardSender> = Vec::with_capacity(writer_count);
+        for shard in 0..writer_count {
+            let ctx = WriterCtx {
+                // Globally unique so thread names, metric labels and health slots don't collide.
+                shard: group_idx * writer_count + shard,
+                shutdown: shutdown.clone(),
+                limits: writer_limits,
+                flush_interval,
+                buf_pool: buf_pool.clone(),
+                dlq: dlq_sink.clone(),
+                health: health.clone(),
+            };
+            let target = group_target.for_shard(shard, writer_count);
+            // Indexed over all writers: groups must not share a failover ring.
+            let failover = failover
+                .as_ref()
+                .filter(|_| matches!(target, OutputTarget::Uds(_)))
+                .map(|f| f.for_shard(ctx.shard, total_writers));
+            let handle = if use_spsc {
+                let inner_q = std::sync::Arc::new(ArrayQueue::<QueuedFrame>::new(shard_queue_cap));
+                let ev = std::sync::Arc::new(Event::new());
+                shards.push(ShardSender::Spsc(SpscSender {
+                    q: inner_q.clone(),
+                    ev: ev.clone(),
+                }));
+                probes.push(QueueProbe::Spsc(inner_q.clone()));
+                let src = SpscQueue {
+                    q: inner_q,
+                    ev,
+                    draining: draining.clone(),
+                };
+                spawn_writer(src, ctx, target, failover)?
+            } else {
+                let (txq, rxq) = bounded::<QueuedFrame>(shard_queue_cap);
+                shards.push(ShardSender::Channel(txq));
+                probes.push(QueueProbe::Channel(rxq.clone()));
+                spawn_writer(rxq, ctx, target, failover)?
+            };
+            writer_handles.push(handle);
         }
-        txq_opt = Some(txq);
+        info!(
+            "started {} writer shard(s) for {:?}",
+            writer_count, group_target
+        );
+        groups.push(OutputGroup {
+            shards,
+            encode_profile: group_target.encode_profile(),
+        });
     }
 
-    // writer spawned above in both branches
-    // info is logged after a successful subscribe in the loop below
+    if let Some(addr) = std::env::var("YS_HEALTH_ADDR")
+        .ok()
+        .filter(|s| !s.is_empty())
+    {
+        let addr: std::net::SocketAddr = addr
+            .parse()
+            .with_context(|| format!("invalid YS_HEALTH_ADDR {}", addr))?;
+        let probes = probes.clone();
+        health::spawn(addr, health.clone(), move || {
+            probes.iter().map(QueueProbe::len).sum::<usize>()
+        });
+    }
+
+    // Optional slot-gap backfill over JSON-RPC `getBlock`.
+    let (mut backfill, mut backfill_rx) = match backfill::BackfillConfig::from_env(
+        streams.blocks.is_some() || streams.blocks_meta.is_some(),
+        streams.transactions.is_some(),
+    )? {
+        Some(cfg) => {
+            let (handle, rx) = backfill::spawn(cfg)?;
+            (Some(handle), Some(rx))
+        }
+        None => (None, None),
+    };
 
     // metrics: queue depth sampler
     if metrics_addr.is_some() {
-        if let Some(txq) = &txq_opt {
-            let txq = txq.clone();
-            tokio::spawn(async move {
-                let mut tick = tokio::time::interval(Duration::from_millis(250));
-                loop {
-                    tick.tick().await;
-                    gauge!("ys_consumer_queue_depth").set(txq.len() as f64);
+        let probes = probes.clone();
+        let pool = buf_pool.clone();
+        tokio::spawn(async move {
+            let labels: Vec<String> = (0..probes.len()).map(|i| i.to_string()).collect();
+            let mut tick = tokio::time::interval(Duration::from_millis(250));
+            let mut prev = pool.stats();
+            loop {
+                tick.tick().await;
+                for (probe, shard) in probes.iter().zip(labels.iter()) {
+                    let depth = probe.len();
+                    gauge!("ys_consumer_queue_depth", "shard" => shard.clone()).set(depth as f64);
+                    meters::queue_len(shard, depth);
                 }
-            });
-        }
-        if let Some(s) = &spsc_send_opt {
-            let s = s.clone();
-            tokio::spawn(async move {
-                let mut tick = tokio::time::interval(Duration::from_millis(250));
-                loop {
-                    tick.tick().await;
-                    gauge!("ys_consumer_queue_depth").set(s.len() as f64);
+                let stats = pool.stats();
+                gauge!("ys_consumer_buf_pool_bytes").set(stats.bytes as f64);
+                gauge!("ys_consumer_buf_pool_items").set(stats.items as f64);
+                // Hit rate over the last sampling window rather than since start.
+                let hits = stats.hits - prev.hits;
+                let total = hits + (stats.misses - prev.misses);
+                if total > 0 {
+                    gauge!("ys_consumer_buf_pool_hit_rate").set(hits as f64 / total as f64);
                 }
-            });
-        }
+                prev = stats;
+            }
+        });
     }
 
     // Simple time-based jitter without external RNG
@@ -1121,30 +2045,57 @@ async fn main() -> Result<()> {
             Err(e) => {
                 error!("connect error: {e}");
                 counter!("ys_connect_fail_total").increment(1);
-                tokio::time::sleep(jitter(reconnect_backoff)).await;
+                tokio::select! {
+                    _ = &mut shutdown_sig => { info!("shutting down"); break 'outer; }
+                    _ = tokio::time::sleep(jitter(reconnect_backoff)) => {}
+                }
                 reconnect_backoff = (reconnect_backoff * 2).min(backoff_max);
                 continue;
             }
         };
-        let (mut tx, mut rx) = match client.subscribe().await {
-            Ok(sr) => sr,
-            Err(e) => {
-                error!("subscribe error: {e}");
-                counter!("ys_subscribe_fail_total").increment(1);
-                tokio::time::sleep(jitter(reconnect_backoff)).await;
-                reconnect_backoff = (reconnect_backoff * 2).min(backoff_max);
-                continue;
+        // One subscription per commitment group, merged into a single update stream.
+        // Sinks are kept alive for the lifetime of the connection.
+        let mut sinks = Vec::with_capacity(reqs.len());
+        let mut update_streams = Vec::with_capacity(reqs.len());
+        let mut subscribed = true;
+        for r in &reqs {
+            match client.subscribe().await {
+                Ok((mut tx, rx)) => {
+                    if let Err(e) = tx.send(r.clone()).await {
+                        error!("send subscribe request failed: {e}");
+                        counter!("ys_send_fail_total").increment(1);
+                        subscribed = false;
+                        break;
+                    }
+                    sinks.push(tx);
+                    // Trailing `None` marks the end of one subscription so the merged
+                    // stream reconnects instead of silently running on the others.
+                    update_streams.push(
+                        rx.map(Some)
+                            .chain(futures::stream::once(async { None }))
+                            .boxed(),
+                    );
+                }
+                Err(e) => {
+                    error!("subscribe error: {e}");
+                    counter!("ys_subscribe_fail_total").increment(1);
+                    subscribed = false;
+                    break;
+                }
+            }
+        }
+        if !subscribed {
+            tokio::select! {
+                _ = &mut shutdown_sig => { info!("shutting down"); break 'outer; }
+                _ = tokio::time::sleep(jitter(reconnect_backoff)) => {}
             }
-        };
-        if let Err(e) = tx.send(req.clone()).await {
-            error!("send subscribe request failed: {e}");
-            counter!("ys_send_fail_total").increment(1);
-            tokio::time::sleep(jitter(reconnect_backoff)).await;
             reconnect_backoff = (reconnect_backoff * 2).min(backoff_max);
             continue;
         }
+        let mut rx = futures::stream::select_all(update_streams);
+        health.set_grpc_connected(true);
         reconnect_backoff = backoff_min;
-        info!("connected to Yellowstone; forwarding to {}", uds_path);
+        info!("connected to Yellowstone; forwarding to {:?}", targets);
 
         loop {
             let next_fut = rx.next();
@@ -1153,11 +2104,34 @@ async fn main() -> Result<()> {
             tokio::select! {
                 _ = &mut shutdown_sig => { info!("shutting down"); break 'outer; }
                 _ = &mut idle_timer => { counter!("ys_idle_timeouts_total").increment(1); error!("idle timeout (no updates for {:?})", idle_timeout); break; }
+                Some(rec) = recv_backfill(&mut backfill_rx) => {
+                    let (kind, shard, out) = match &rec {
+                        Record::Tx(t) => ("tx", shard_index(&t.signature, writer_count), &groups[routes.transactions]),
+                        Record::Block(b) => ("block", shard_from_u64(b.slot, writer_count), &groups[routes.blocks]),
+                        _ => ("other", 0, &groups[0]),
+                    };
+                    let mut buf = buf_pool.get();
+                    if encode_into_with(&rec, &mut buf, (out.encode_profile)()).is_ok()
+                        && set_frame_flags(&mut buf, FLAG_BACKFILL).is_ok()
+                    {
+                        counter!("ys_consumer_backfill_records_total", "kind" => kind).increment(1);
+                        // No created_at: synthesized frames stay out of the lag histograms.
+                        let meta = FrameMeta { kind, created_at_ns: 0 };
+                        if !forward_frame(QueuedFrame { buf, meta }, &out.shards, shard, &shutdown, &buf_pool) {
+                            counter!("ys_consumer_dropped_total").increment(1);
+                        }
+                    } else {
+                        buf_pool.put(buf);
+                    }
+                }
                 res = next_fut => {
-                    match res {
+                    match res.flatten() {
                         Some(Ok(upd)) => {
+                            health.record_update();
+                            let created_at_ns = created_at_nanos(upd.created_at.as_ref());
                             match upd.update_oneof {
             Some(subscribe_update::UpdateOneof::Transaction(t)) => {
+                meters::received("tx");
                 let mut sig = [0u8; 64];
                 // Extract signature from transaction if available
                 if let Some(tx_data) = &t.transaction {
@@ -1171,21 +2145,27 @@ async fn main() -> Result<()> {
                     err: t.transaction.as_ref().and_then(|tx| tx.meta.as_ref()).and_then(|m| m.err.as_ref().cloned()).map(|e| format!("{:?}", e)),
                     vote: false, // is_vote not available in new structure
                 });
+                let shard = shard_index(&sig, writer_count);
                 let mut buf = buf_pool.get();
-                let v = SAMPLE_SEQ.fetch_add(1, Ordering::Relaxed);
-                let maybe_t0 = if (v & 0xFF) == 0 { Some(Instant::now()) } else { None };
-                if encode_into_with(&rec, &mut buf, EncodeOptions::latency_uds()).is_ok() {
+                let maybe_t0 = encode_sampler.start();
+                if encode_into_with(&rec, &mut buf, encode_opts(groups[routes.transactions].encode_profile, created_at_ns)).is_ok() {
                     if let Some(t0) = maybe_t0 {
                         histogram!("ys_consumer_encode_us", "kind" => "tx").record(t0.elapsed().as_secs_f64() * 1e6);
+                        meters::encode_ns("tx", t0.elapsed().as_nanos() as f64);
                     }
-                    if !forward_frame(buf, &txq_opt, &spsc_send_opt, &shutdown, &buf_pool) {
+                    let meta = FrameMeta { kind: "tx", created_at_ns };
+                    meters::encoded(meta.kind, buf.len());
+                    record_lag("receive", meta, unix_nanos(SystemTime::now()));
+                    if !forward_frame(QueuedFrame { buf, meta }, &groups[routes.transactions].shards, shard, &shutdown, &buf_pool) {
                         counter!("ys_consumer_dropped_total").increment(1);
                     }
                 } else {
+                    meters::encode_error("tx");
                     buf_pool.put(buf);
                 }
             }
             Some(subscribe_update::UpdateOneof::Account(a)) => {
+                meters::received("account");
                 if let Some(acc) = &a.account {
                     let pubkey = address_cache.decode(&acc.pubkey);
                     let owner = address_cache.decode(&acc.owner);
@@ -1199,59 +2179,112 @@ async fn main() -> Result<()> {
                         rent_epoch: acc.rent_epoch,
                         data: &acc.data,
                     });
+                    let shard = shard_index(&pubkey, writer_count);
                     let mut buf = buf_pool.get();
-                    let v = SAMPLE_SEQ.fetch_add(1, Ordering::Relaxed);
-                    let maybe_t0 = if (v & 0xFF) == 0 { Some(Instant::now()) } else { None };
-                    if encode_record_ref_into_with(&aref, &mut buf, EncodeOptions::latency_uds()).is_ok() {
+                    let maybe_t0 = encode_sampler.start();
+                    if encode_record_ref_into_with(&aref, &mut buf, encode_opts(groups[routes.accounts].encode_profile, created_at_ns)).is_ok() {
                         if let Some(t0) = maybe_t0 {
                             histogram!("ys_consumer_encode_us", "kind" => "account").record(t0.elapsed().as_secs_f64() * 1e6);
+                            meters::encode_ns("account", t0.elapsed().as_nanos() as f64);
                         }
-                        if !forward_frame(buf, &txq_opt, &spsc_send_opt, &shutdown, &buf_pool) {
+                        let meta = FrameMeta { kind: "account", created_at_ns };
+                        meters::encoded(meta.kind, buf.len());
+                        record_lag("receive", meta, unix_nanos(SystemTime::now()));
+                        if !forward_frame(QueuedFrame { buf, meta }, &groups[routes.accounts].shards, shard, &shutdown, &buf_pool) {
                             counter!("ys_consumer_dropped_total").increment(1);
                         }
                     } else {
+                        meters::encode_error("account");
                         buf_pool.put(buf);
                     }
                 }
             }
             Some(subscribe_update::UpdateOneof::Block(b)) => {
-                let bh = if !b.blockhash.is_empty() {
-                    bs58::decode(&b.blockhash).into_vec().ok().and_then(|v| v.try_into().ok())
-                } else { None };
-                // leader field not available in new proto version, set to None
-                let ld = None;
-                let block_time = b.block_time.as_ref().and_then(|ts| if ts.timestamp != 0 { Some(ts.timestamp) } else { None });
-                let rec = Record::Block(BlockMeta {
-                    slot: b.slot,
-                    blockhash: bh,
-                    parent_slot: Some(b.parent_slot),
-                    rewards_len: b.rewards.as_ref().map(|r| r.rewards.len()).unwrap_or(0) as u32,
-                    block_time_unix: block_time,
-                    leader: ld,
-                });
+                meters::received("block");
+                if let Some(bf) = backfill.as_mut() {
+                    bf.observe_slot(b.slot);
+                }
+                let rec = Record::Block(block_meta_record(
+                    b.slot,
+                    &b.blockhash,
+                    b.parent_slot,
+                    b.rewards.as_ref(),
+                    b.block_time.as_ref(),
+                    b.block_height.as_ref(),
+                ));
+                let shard = shard_from_u64(b.slot, writer_count);
                 let mut buf = buf_pool.get();
-                let v = SAMPLE_SEQ.fetch_add(1, Ordering::Relaxed);
-                let maybe_t0 = if (v & 0xFF) == 0 { Some(Instant::now()) } else { None };
-                if encode_into_with(&rec, &mut buf, EncodeOptions::latency_uds()).is_ok() {
-                    if let Some(t0) = maybe_t0 { histogram!("ys_consumer_encode_us", "kind" => "block").record(t0.elapsed().as_secs_f64() * 1e6); }
-                    if !forward_frame(buf, &txq_opt, &spsc_send_opt, &shutdown, &buf_pool) {
+                let maybe_t0 = encode_sampler.start();
+                if encode_into_with(&rec, &mut buf, encode_opts(groups[routes.blocks].encode_profile, created_at_ns)).is_ok() {
+                    if let Some(t0) = maybe_t0 {
+                        histogram!("ys_consumer_encode_us", "kind" => "block").record(t0.elapsed().as_secs_f64() * 1e6);
+                        meters::encode_ns("block", t0.elapsed().as_nanos() as f64);
+                    }
+                    let meta = FrameMeta { kind: "block", created_at_ns };
+                    meters::encoded(meta.kind, buf.len());
+                    record_lag("receive", meta, unix_nanos(SystemTime::now()));
+                    if !forward_frame(QueuedFrame { buf, meta }, &groups[routes.blocks].shards, shard, &shutdown, &buf_pool) {
                         counter!("ys_consumer_dropped_total").increment(1);
                     }
                 } else {
+                    meters::encode_error("block");
+                    buf_pool.put(buf);
+                }
+            }
+            Some(subscribe_update::UpdateOneof::BlockMeta(b)) => {
+                meters::received("block_meta");
+                if let Some(bf) = backfill.as_mut() {
+                    bf.observe_slot(b.slot);
+                }
+                let rec = Record::Block(block_meta_record(
+                    b.slot,
+                    &b.blockhash,
+                    b.parent_slot,
+                    b.rewards.as_ref(),
+                    b.block_time.as_ref(),
+                    b.block_height.as_ref(),
+                ));
+                let shard = shard_from_u64(b.slot, writer_count);
+                let mut buf = buf_pool.get();
+                let maybe_t0 = encode_sampler.start();
+                if encode_into_with(&rec, &mut buf, encode_opts(groups[routes.blocks].encode_profile, created_at_ns)).is_ok() {
+                    if let Some(t0) = maybe_t0 {
+                        histogram!("ys_consumer_encode_us", "kind" => "block_meta").record(t0.elapsed().as_secs_f64() * 1e6);
+                        meters::encode_ns("block_meta", t0.elapsed().as_nanos() as f64);
+                    }
+                    let meta = FrameMeta { kind: "block_meta", created_at_ns };
+                    meters::encoded(meta.kind, buf.len());
+                    record_lag("receive", meta, unix_nanos(SystemTime::now()));
+                    if !forward_frame(QueuedFrame { buf, meta }, &groups[routes.blocks].shards, shard, &shutdown, &buf_pool) {
+                        counter!("ys_consumer_dropped_total").increment(1);
+                    }
+                } else {
+                    meters::encode_error("block_meta");
                     buf_pool.put(buf);
                 }
             }
             Some(subscribe_update::UpdateOneof::Slot(s)) => {
+                meters::received("slot");
+                if let Some(bf) = backfill.as_mut() {
+                    bf.observe_slot(s.slot);
+                }
                 let rec = Record::Slot { slot: s.slot, parent: s.parent, status: s.status as u8 };
+                let shard = shard_from_u64(s.slot, writer_count);
                 let mut buf = buf_pool.get();
-                let v = SAMPLE_SEQ.fetch_add(1, Ordering::Relaxed);
-                let maybe_t0 = if (v & 0xFF) == 0 { Some(Instant::now()) } else { None };
-                if encode_into_with(&rec, &mut buf, EncodeOptions::latency_uds()).is_ok() {
-                    if let Some(t0) = maybe_t0 { histogram!("ys_consumer_encode_us", "kind" => "slot").record(t0.elapsed().as_secs_f64() * 1e6); }
-                    if !forward_frame(buf, &txq_opt, &spsc_send_opt, &shutdown, &buf_pool) {
+                let maybe_t0 = encode_sampler.start();
+                if encode_into_with(&rec, &mut buf, encode_opts(groups[routes.slots].encode_profile, created_at_ns)).is_ok() {
+                    if let Some(t0) = maybe_t0 {
+                        histogram!("ys_consumer_encode_us", "kind" => "slot").record(t0.elapsed().as_secs_f64() * 1e6);
+                        meters::encode_ns("slot", t0.elapsed().as_nanos() as f64);
+                    }
+                    let meta = FrameMeta { kind: "slot", created_at_ns };
+                    meters::encoded(meta.kind, buf.len());
+                    record_lag("receive", meta, unix_nanos(SystemTime::now()));
+                    if !forward_frame(QueuedFrame { buf, meta }, &groups[routes.slots].shards, shard, &shutdown, &buf_pool) {
                         counter!("ys_consumer_dropped_total").increment(1);
                     }
                 } else {
+                    meters::encode_error("slot");
                     buf_pool.put(buf);
                 }
             }
@@ -1264,10 +2297,34 @@ async fn main() -> Result<()> {
                 }
             }
         }
+        health.set_grpc_connected(false);
         counter!("ys_reconnects_total").increment(1);
-        tokio::time::sleep(jitter(reconnect_backoff)).await;
+        tokio::select! {
+            _ = &mut shutdown_sig => { info!("shutting down"); break 'outer; }
+            _ = tokio::time::sleep(jitter(reconnect_backoff)) => {}
+        }
         reconnect_backoff = (reconnect_backoff * 2).min(backoff_max);
     }
+
+    // The gRPC stream is gone; stop producing and let the writers flush what is queued.
+    drop(groups);
+    let queue_depth = move || probes.iter().map(QueueProbe::len).sum::<usize>();
+    let report = tokio::task::spawn_blocking(move || {
+        drain_writer(
+            writer_handles,
+            queue_depth,
+            &draining,
+            &shutdown,
+            drain_timeout,
+        )
+    })
+    .await?;
+    info!(
+        flushed = report.flushed,
+        dropped = report.dropped,
+        timed_out = report.timed_out,
+        "drain complete"
+    );
     Ok(())
 }
 
@@ -1307,7 +2364,7 @@ mod tests {
 
     #[test]
     fn buf_pool_recycles_large_buffers() {
-        let pool = std::sync::Arc::new(BufPool::new(2, 16));
+        let pool = std::sync::Arc::new(BufPool::new(2, 16, 1024, 4096));
         let mut buf = pool.get();
         buf.extend_from_slice(&[1, 2, 3, 4]);
         pool.put(buf);
@@ -1316,6 +2373,42 @@ mod tests {
         assert!(reused.capacity() >= 16);
     }
 
+    #[test]
+    fn encode_sampler_rounds_rate_to_power_of_two() {
+        let sampled = |s: &EncodeSampler| (0..64).filter(|_| s.start().is_some()).count();
+        let every = EncodeSampler::new(12);
+        assert_eq!(every.every(), 16);
+        assert_eq!(sampled(&every), 4);
+        assert_eq!(sampled(&EncodeSampler::new(1)), 64);
+        let off = EncodeSampler::new(0);
+        assert_eq!((off.every(), sampled(&off)), (0, 0));
+    }
+
+    #[test]
+    fn buf_pool_shrinks_oversized_buffers_and_caps_bytes() {
+        let pool = BufPool::new(8, 16, 1024, 2048);
+        pool.put(Vec::with_capacity(1 << 20));
+        let stats = pool.stats();
+        assert_eq!(stats.items, 1);
+        assert!(
+            stats.bytes <= 1024,
+            "oversized buffer shrunk before pooling"
+        );
+
+        pool.put(Vec::with_capacity(1024));
+        pool.put(Vec::with_capacity(1024));
+        let stats = pool.stats();
+        assert!(stats.bytes <= 2048, "retained bytes stay within budget");
+        assert_eq!(stats.items, 2);
+
+        let _ = pool.get();
+        let _ = pool.get();
+        let _ = pool.get();
+        let stats = pool.stats();
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert_eq!((stats.items, stats.bytes), (0, 0));
+    }
+
     #[test]
     fn frame_kind_detection_matches_variant() {
         let record = Record::Slot {
@@ -1328,4 +2421,541 @@ mod tests {
         let kind = frame_kind_from_bytes(&encoded, &mut scratch);
         assert_eq!(kind, "slot");
     }
+
+    #[test]
+    fn dlq_replay_forwards_valid_frames_and_deletes_them() {
+        use std::io::Read;
+        let root = std::env::temp_dir().join(format!("ys-dlq-replay-{}", std::process::id()));
+        let dlq_dir = root.join("dlq");
+        std::fs::create_dir_all(&dlq_dir).unwrap();
+        let frame = faststreams::encode_record(&Record::Slot {
+            slot: 7,
+            parent: Some(6),
+            status: 1,
+        })
+        .expect("encode");
+        let record = DlqRecord {
+            data: frame.clone(),
+            reason: "frame_oversize",
+            kind: "slot",
+            frame_len: frame.len(),
+            frame_limit: 1,
+            timestamp: SystemTime::now(),
+        };
+        DlqSink::write_record(&dlq_dir, &record, 0).unwrap();
+        std::fs::write(dlq_dir.join("dlq-0-000000000-000001.fstr"), b"garbage").unwrap();
+
+        let sock = root.join("out.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+        let args = dlq_replay::ReplayArgs::parse(
+            &["--dir".to_string(), dlq_dir.display().to_string()],
+            dlq_replay::ReplayOutputs::single(OutputTarget::Uds(sock.display().to_string())),
+        )
+        .unwrap();
+        let stats = dlq_replay::run(&args).unwrap();
+        assert_eq!(stats.replayed, 1);
+        assert_eq!(stats.invalid, 1);
+
+        let (mut conn, _) = listener.accept().unwrap();
+        let mut got = vec![0u8; frame.len()];
+        conn.read_exact(&mut got).unwrap();
+        assert_eq!(got, frame);
+        let remaining: Vec<_> = std::fs::read_dir(&dlq_dir).unwrap().collect();
+        assert_eq!(remaining.len(), 1, "only the invalid frame should remain");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn dlq_replay_routes_frames_to_their_live_shard() {
+        let root = std::env::temp_dir().join(format!("ys-dlq-shards-{}", std::process::id()));
+        let dlq_dir = root.join("dlq");
+        std::fs::create_dir_all(&dlq_dir).unwrap();
+        for slot in 0..8u64 {
+            let frame = faststreams::encode_record(&Record::Slot {
+                slot,
+                parent: None,
+                status: 1,
+            })
+            .unwrap();
+            let record = DlqRecord {
+                data: frame.clone(),
+                reason: "frame_oversize",
+                kind: "slot",
+                frame_len: frame.len(),
+                frame_limit: 1,
+                timestamp: SystemTime::now(),
+            };
+            DlqSink::write_record(&dlq_dir, &record, slot).unwrap();
+        }
+
+        let path = root.join("out.fscap").display().to_string();
+        let outputs = dlq_replay::ReplayOutputs {
+            targets: vec![OutputTarget::Capture(path.clone())],
+            routes: KindRoutes::default(),
+            writers: 2,
+        };
+        let args = dlq_replay::ReplayArgs::parse(
+            &["--dir".to_string(), dlq_dir.display().to_string()],
+            outputs,
+        )
+        .unwrap();
+        assert_eq!(dlq_replay::run(&args).unwrap().replayed, 8);
+
+        let mut seen = 0;
+        for shard in 0..2 {
+            let Ok(file) = std::fs::read(format!("{}.{}", path, shard)) else {
+                continue;
+            };
+            let mut reader = faststreams::CaptureReader::new(&file[..]).unwrap();
+            let mut frame = Vec::new();
+            while reader.next_into(&mut frame).unwrap().is_some() {
+                let (rec, _) = decode_record_from_slice(&frame, &mut Vec::new()).unwrap();
+                assert_eq!(
+                    record_shard(&rec, 2),
+                    shard,
+                    "{rec:?} replayed to the wrong shard"
+                );
+                seen += 1;
+            }
+        }
+        assert_eq!(seen, 8);
+        assert!(!std::path::Path::new(&path).exists(), "no unsharded output");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn uds_writer_fails_over_to_capture_and_back() {
+        use std::io::Read;
+        let root = std::env::temp_dir().join(format!("ys-failover-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let sock = root.join("out.sock");
+        let spill = root.join("spill.fscap").display().to_string();
+        let frame = |slot| {
+            faststreams::encode_record(&Record::Slot {
+                slot,
+                parent: None,
+                status: 1,
+            })
+            .unwrap()
+        };
+        let queued = |slot| QueuedFrame {
+            buf: frame(slot),
+            meta: FrameMeta {
+                kind: "slot",
+                created_at_ns: 0,
+            },
+        };
+        let spilled = || -> usize {
+            let Ok(file) = std::fs::File::open(&spill) else {
+                return 0;
+            };
+            let Ok(mut reader) = faststreams::CaptureReader::new(file) else {
+                return 0;
+            };
+            let mut buf = Vec::new();
+            std::iter::from_fn(|| reader.next_into(&mut buf).ok().flatten()).count()
+        };
+        let wait_for = |what: &str, cond: &dyn Fn() -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !cond() {
+                assert!(Instant::now() < deadline, "timed out waiting for {what}");
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        let (tx, rx) = bounded::<QueuedFrame>(16);
+        let ctx = WriterCtx {
+            shard: 0,
+            shutdown: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            limits: WriterLimits {
+                batch_max: 8,
+                batch_bytes_max: 1 << 20,
+                frame_bytes_max: 1 << 20,
+            },
+            flush_interval: Duration::from_millis(1),
+            buf_pool: std::sync::Arc::new(BufPool::new(8, 64, 1024, 1 << 20)),
+            dlq: None,
+            health: std::sync::Arc::new(health::Health::new(1, 16, Duration::from_secs(1))),
+        };
+        let failover = Failover {
+            target: OutputTarget::Capture(spill.clone()),
+            after: Duration::ZERO,
+            probe_every: Duration::from_millis(10),
+        };
+        let handle = spawn_writer(
+            rx,
+            ctx,
+            OutputTarget::Uds(sock.display().to_string()),
+            Some(failover),
+        )
+        .unwrap();
+
+        // No listener yet: the frame lands in the spill file.
+        tx.send(queued(1)).unwrap();
+        wait_for("failover spill", &|| spilled() == 1);
+
+        // Once the socket is back the writer fails back on its own, with
+        // nothing queued: first the probe, which closes at once, then the
+        // writer's own connection.
+        let listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let accept = || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                match listener.accept() {
+                    Ok((conn, _)) => break conn,
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        assert!(Instant::now() < deadline, "timed out waiting for failback");
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                    Err(e) => panic!("accept failed: {e}"),
+                }
+            }
+        };
+        drop(accept());
+        let mut conn = accept();
+        conn.set_nonblocking(false).unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        tx.send(queued(2)).unwrap();
+        let expected = frame(2);
+        let mut got = vec![0u8; expected.len()];
+        conn.read_exact(&mut got).unwrap();
+        drop(tx);
+        handle.join().unwrap();
+        assert_eq!(got, expected);
+        assert_eq!(spilled(), 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn frames_held_back_at_a_switch_go_to_the_next_writer() {
+        use std::io::Read;
+        // Accepts a connection whose every write fails, as after the peer went away.
+        struct Broken;
+        impl Write for Broken {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let root = std::env::temp_dir().join(format!("ys-held-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let spill = root.join("spill.fscap").display().to_string();
+        let frame = |slot| {
+            faststreams::encode_record(&Record::Slot {
+                slot,
+                parent: None,
+                status: 1,
+            })
+            .unwrap()
+        };
+        let queued = |slot| QueuedFrame {
+            buf: frame(slot),
+            meta: FrameMeta {
+                kind: "slot",
+                created_at_ns: 0,
+            },
+        };
+        let spilled = || -> Vec<Vec<u8>> {
+            let file = std::fs::File::open(&spill).unwrap();
+            let mut reader = faststreams::CaptureReader::new(file).unwrap();
+            let mut buf = Vec::new();
+            std::iter::from_fn(|| reader.next_into(&mut buf).unwrap().map(|_| buf.clone()))
+                .collect()
+        };
+        let ctx = WriterCtx {
+            shard: 0,
+            shutdown: std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            // One frame per batch: a second queued frame is held back as pending.
+            limits: WriterLimits {
+                batch_max: 8,
+                batch_bytes_max: frame(1).len(),
+                frame_bytes_max: 1 << 20,
+            },
+            flush_interval: Duration::from_millis(50),
+            buf_pool: std::sync::Arc::new(BufPool::new(8, 64, 1024, 1 << 20)),
+            dlq: None,
+            health: std::sync::Arc::new(health::Health::new(1, 16, Duration::from_secs(1))),
+        };
+        let (tx, rx) = bounded::<QueuedFrame>(16);
+        for slot in 1..=3 {
+            tx.send(queued(slot)).unwrap();
+        }
+        let mut held = None;
+
+        // Frame 1 fails to write while 2 is held back; the output then stays down.
+        let mut attempts = 0;
+        let connect = || {
+            attempts += 1;
+            if attempts == 1 {
+                Ok(Broken)
+            } else {
+                Err(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            }
+        };
+        let exit = writer_loop_generic(
+            "primary",
+            connect,
+            &rx,
+            &ctx,
+            &mut held,
+            Some(Duration::ZERO),
+            || false,
+        );
+        assert_eq!(exit, WriterExit::Outage);
+        assert_eq!(held.as_ref().map(|f| &f.buf), Some(&frame(2)));
+
+        // The failover spill writes 2 and holds 3 back when the primary returns.
+        let connect = || capture::CaptureSink::open(&spill, 4096);
+        let back = || std::fs::metadata(&spill).is_ok_and(|m| m.len() > 8);
+        let exit = writer_loop_generic(&spill, connect, &rx, &ctx, &mut held, None, back);
+        assert_eq!(exit, WriterExit::Yielded);
+        assert_eq!(spilled(), [frame(2)]);
+        assert_eq!(held.as_ref().map(|f| &f.buf), Some(&frame(3)));
+
+        // Back on the primary, the held frame goes out ahead of the queue.
+        tx.send(queued(4)).unwrap();
+        drop(tx);
+        let (primary, mut peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let mut primary = Some(primary);
+        let connect = || {
+            primary
+                .take()
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+        };
+        let exit = writer_loop_generic("primary", connect, &rx, &ctx, &mut held, None, || false);
+        assert_eq!(exit, WriterExit::Finished);
+        assert!(held.is_none());
+        let mut got = Vec::new();
+        peer.read_to_end(&mut got).unwrap();
+        assert_eq!(got, [frame(3), frame(4)].concat());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn spsc_queue_ends_once_draining_and_empty() {
+        let q = std::sync::Arc::new(ArrayQueue::<QueuedFrame>::new(4));
+        let draining = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let src = SpscQueue {
+            q: q.clone(),
+            ev: std::sync::Arc::new(Event::new()),
+            draining: draining.clone(),
+        };
+        let meta = FrameMeta {
+            kind: "slot",
+            created_at_ns: 0,
+        };
+        assert!(q.push(QueuedFrame { buf: vec![1], meta }).is_ok());
+        draining.store(true, Ordering::Release);
+        assert!(matches!(
+            src.pop_timeout(Duration::from_millis(1)),
+            Pop::Frame(QueuedFrame { ref buf, .. }) if buf == &[1]
+        ));
+        assert!(matches!(
+            src.pop_timeout(Duration::from_millis(1)),
+            Pop::Closed
+        ));
+    }
+
+    #[test]
+    fn drain_writer_forces_shutdown_after_deadline() {
+        let (tx, rx) = bounded::<QueuedFrame>(8);
+        let meta = FrameMeta {
+            kind: "slot",
+            created_at_ns: 0,
+        };
+        for _ in 0..2 {
+            assert!(tx
+                .send(QueuedFrame {
+                    buf: vec![0u8; 4],
+                    meta
+                })
+                .is_ok());
+        }
+        let draining = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let shutdown = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        // A writer that never makes progress until told to stop.
+        let sd = shutdown.clone();
+        let writer = thread::spawn(move || {
+            while !sd.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let probe = rx.clone();
+        let report = drain_writer(
+            vec![writer],
+            move || probe.len(),
+            &draining,
+            &shutdown,
+            Duration::from_millis(20),
+        );
+        assert!(report.timed_out);
+        assert_eq!(report.dropped, 2);
+        assert!(draining.load(Ordering::Relaxed));
+        drop(tx);
+    }
+
+    #[test]
+    fn shard_index_is_stable_and_in_range() {
+        let key = [9u8; 32];
+        assert_eq!(shard_index(&key, 1), 0);
+        let a = shard_index(&key, 4);
+        assert_eq!(a, shard_index(&key, 4));
+        assert!(a < 4);
+        assert_eq!(shard_from_u64(42, 8), shard_index(&42u64.to_le_bytes(), 8));
+    }
+
+    #[test]
+    fn output_target_suffixes_shm_path_per_shard() {
+        let shm = OutputTarget::Shm {
+            path: "/dev/shm/ring".into(),
+            capacity_bytes: 1024,
+            max_wait: Duration::ZERO,
+        };
+        assert_eq!(shm.for_shard(0, 1), shm);
+        assert_eq!(
+            shm.for_shard(2, 4),
+            OutputTarget::Shm {
+                path: "/dev/shm/ring.2".into(),
+                capacity_bytes: 1024,
+                max_wait: Duration::ZERO,
+            }
+        );
+        let uds = OutputTarget::Uds("/tmp/x.sock".into());
+        assert_eq!(uds.for_shard(3, 4), uds);
+    }
+
+    #[test]
+    fn routes_share_groups_for_identical_targets() {
+        let default = OutputTarget::Uds("/tmp/default.sock".into());
+        let env: HashMap<&str, &str> = [
+            ("YS_ROUTE_ACCOUNTS", "shm:/dev/shm/accounts"),
+            ("YS_ROUTE_TRANSACTIONS", "uds:/tmp/indexer.sock"),
+            ("YS_ROUTE_BLOCKS", "uds:/tmp/indexer.sock"),
+            ("YS_ROUTE_SLOTS", "default"),
+        ]
+        .into_iter()
+        .collect();
+        let (targets, routes) =
+            resolve_routes(&default, |k| env.get(k).map(|v| v.to_string())).unwrap();
+        assert_eq!(targets.len(), 3);
+        assert_eq!(targets[0], default);
+        assert!(
+            matches!(&targets[routes.accounts], OutputTarget::Shm { path, .. } if path == "/dev/shm/accounts")
+        );
+        assert_eq!(
+            targets[routes.transactions],
+            OutputTarget::Uds("/tmp/indexer.sock".into())
+        );
+        assert_eq!(routes.blocks, routes.transactions);
+        assert_eq!(routes.slots, 0);
+
+        let (targets, routes) = resolve_routes(&default, |_| None).unwrap();
+        assert_eq!(targets, vec![default]);
+        assert_eq!(routes, KindRoutes::default());
+
+        assert_eq!(
+            OutputTarget::from_spec("unix:/tmp/indexer.sock").unwrap(),
+            OutputTarget::Uds("/tmp/indexer.sock".into())
+        );
+        for typo in ["sHm:/dev/shm/accounts", "/tmp/indexer.sock", "tpc"] {
+            let err = OutputTarget::from_spec(typo).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput, "{typo}");
+        }
+    }
+
+    #[test]
+    fn created_at_nanos_handles_missing_and_negative() {
+        use yellowstone_grpc_proto::prost_types::Timestamp;
+        assert_eq!(created_at_nanos(None), 0);
+        let neg = Timestamp {
+            seconds: -1,
+            nanos: 0,
+        };
+        assert_eq!(created_at_nanos(Some(&neg)), 0);
+        let ts = Timestamp {
+            seconds: 2,
+            nanos: 5,
+        };
+        assert_eq!(created_at_nanos(Some(&ts)), 2_000_000_005);
+    }
+
+    #[test]
+    fn block_meta_takes_leader_from_fee_reward() {
+        use yellowstone_grpc_proto::prelude::{BlockHeight, Reward, UnixTimestamp};
+        let leader = [4u8; 32];
+        let reward = |pubkey: [u8; 32], reward_type: RewardType| Reward {
+            pubkey: bs58::encode(pubkey).into_string(),
+            lamports: 5_000,
+            reward_type: reward_type as i32,
+            ..Default::default()
+        };
+        let rewards = Rewards {
+            rewards: vec![
+                reward([1u8; 32], RewardType::Voting),
+                reward(leader, RewardType::Fee),
+            ],
+            num_partitions: None,
+        };
+        let meta = block_meta_record(
+            10,
+            &bs58::encode([9u8; 32]).into_string(),
+            9,
+            Some(&rewards),
+            Some(&UnixTimestamp { timestamp: 0 }),
+            Some(&BlockHeight { block_height: 7 }),
+        );
+        assert_eq!(meta.leader, Some(leader));
+        assert_eq!(meta.block_height, Some(7));
+        assert_eq!(meta.blockhash, Some([9u8; 32]));
+        assert_eq!(meta.rewards_len, 2);
+        assert_eq!(meta.block_time_unix, None);
+
+        let bare = block_meta_record(10, "", 9, None, None, None);
+        assert_eq!(bare.leader, None);
+        assert_eq!(bare.block_height, None);
+        assert_eq!(bare.blockhash, None);
+    }
+
+    #[test]
+    fn subscribe_requests_group_streams_by_commitment() {
+        let sel = StreamSelection {
+            slots: Some(CommitmentLevel::Confirmed),
+            accounts: Some(CommitmentLevel::Processed),
+            transactions: Some(CommitmentLevel::Confirmed),
+            blocks: None,
+            blocks_meta: Some(CommitmentLevel::Processed),
+        };
+        let reqs = build_subscribe_requests(&sel, &mints::account_filters(&[], &[]));
+        assert_eq!(reqs.len(), 2);
+        assert_eq!(reqs[0].commitment, Some(CommitmentLevel::Processed as i32));
+        assert!(reqs[0].accounts.contains_key("") && reqs[0].blocks_meta.contains_key(""));
+        assert!(reqs[0].slots.is_empty() && reqs[0].blocks.is_empty());
+        assert_eq!(reqs[1].commitment, Some(CommitmentLevel::Confirmed as i32));
+        assert_eq!(reqs[1].slots[""].filter_by_commitment, Some(true));
+        assert!(reqs[1].transactions.contains_key(""));
+
+        let both = StreamSelection {
+            blocks: Some(CommitmentLevel::Processed),
+            ..sel
+        };
+        let reqs = build_subscribe_requests(&both, &mints::account_filters(&[], &[]));
+        assert!(reqs[0].blocks.contains_key(""));
+        assert!(reqs.iter().all(|r| r.blocks_meta.is_empty()));
+    }
+
+    #[test]
+    fn parse_commitment_accepts_known_levels() {
+        assert_eq!(
+            parse_commitment("Confirmed"),
+            Some(CommitmentLevel::Confirmed)
+        );
+        assert_eq!(
+            parse_commitment(" finalized "),
+            Some(CommitmentLevel::Finalized)
+        );
+        assert_eq!(parse_commitment("rooted"), None);
+    }
 }
@@ -0,0 +1,114 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/meters.rs
+//
+// `ultra_*` meters under the same names and label values as geyser-plugin-ultra,
+// so one dashboard covers either producer path. The exporter's global
+// `producer` label tells the two apart.
+use metrics::{counter, gauge, histogram};
+
+pub const PRODUCER: &str = "ys-consumer";
+
+/// Map a frame kind onto the plugin's `kind` values (account, tx, block, slot).
+#[inline]
+pub fn kind_label(kind: &'static str) -> &'static str {
+    match kind {
+        "block_meta" => "block",
+        other => other,
+    }
+}
+
+#[inline]
+pub fn received(kind: &'static str) {
+    counter!("ultra_received_total", "kind" => kind_label(kind)).increment(1);
+}
+
+#[inline]
+pub fn encoded(kind: &'static str, bytes: usize) {
+    let kind = kind_label(kind);
+    counter!("ultra_encoded_total", "kind" => kind).increment(1);
+    histogram!("ultra_record_bytes", "kind" => kind).record(bytes as f64);
+}
+
+#[inline]
+pub fn encode_ns(kind: &'static str, ns: f64) {
+    histogram!("ultra_encode_ns", "kind" => kind_label(kind)).record(ns);
+}
+
+#[inline]
+pub fn encode_error(kind: &'static str) {
+    counter!("ultra_encode_error_total", "kind" => kind_label(kind)).increment(1);
+}
+
+#[inline]
+pub fn enqueued(kind: &'static str) {
+    counter!("ultra_enqueued_total", "kind" => kind_label(kind)).increment(1);
+}
+
+#[inline]
+pub fn dropped(kind: &'static str, reason: &'static str, shard: usize) {
+    counter!(
+        "ultra_dropped_total",
+        "reason" => reason,
+        "shard" => shard.to_string(),
+        "kind" => kind_label(kind)
+    )
+    .increment(1);
+}
+
+pub fn batch_sent(shard: &str, endpoint: &str, frames: usize, bytes: usize) {
+    let labels = [
+        ("shard", shard.to_string()),
+        ("endpoint", endpoint.to_string()),
+    ];
+    counter!("ultra_processed_total", &labels).increment(frames as u64);
+    counter!("ultra_bytes_sent_total", &labels).increment(bytes as u64);
+    counter!("ultra_batches_sent_total", &labels).increment(1);
+    histogram!("ultra_batch_len", &labels).record(frames as f64);
+    histogram!("ultra_batch_bytes", &labels).record(bytes as f64);
+}
+
+pub fn write_error(shard: &str, endpoint: &str) {
+    counter!("ultra_write_errors_total", "shard" => shard.to_string(), "endpoint" => endpoint.to_string())
+        .increment(1);
+}
+
+pub fn connect_result(shard: &str, endpoint: &str, ok: bool) {
+    let labels = [
+        ("shard", shard.to_string()),
+        ("endpoint", endpoint.to_string()),
+    ];
+    counter!("ultra_connect_attempts_total", &labels).increment(1);
+    if ok {
+        counter!("ultra_connect_success_total", &labels).increment(1);
+    } else {
+        counter!("ultra_connect_errors_total", &labels).increment(1);
+    }
+}
+
+pub fn writer_alive(shard: usize, alive: bool) {
+    gauge!("ultra_writer_alive", "shard" => shard.to_string()).set(if alive { 1.0 } else { 0.0 });
+}
+
+pub fn queue_len(shard: &str, depth: usize) {
+    gauge!("ultra_queue_len", "shard" => shard.to_string()).set(depth as f64);
+}
+
+/// A writer lost its downstream connection and will reconnect (same meaning as
+/// in the plugin; upstream gRPC reconnects stay on `ys_reconnects_total`).
+pub fn reconnect(shard: &str, endpoint: &str) {
+    counter!("ultra_reconnects_total", "shard" => shard.to_string(), "endpoint" => endpoint.to_string())
+        .increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_labels_match_plugin_values() {
+        for kind in ["account", "tx", "block", "slot"] {
+            assert_eq!(kind_label(kind), kind);
+        }
+        assert_eq!(kind_label("block_meta"), "block");
+    }
+}
@@ -0,0 +1,139 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/mints.rs
+//
+// `YS_MINTS=<mint>,...` narrows the account stream to SPL token accounts of the
+// listed mints: one filter per mint, owned by either token program, with a
+// memcmp on the mint field. Adding `YS_MINT_WALLETS=<wallet>,...` instead
+// subscribes only those wallets' associated token accounts, derived at startup.
+use anyhow::{bail, Context, Result};
+use solana_pubkey::{pubkey, Pubkey};
+use std::collections::HashMap;
+use std::str::FromStr;
+use yellowstone_grpc_proto::prelude::{
+    subscribe_request_filter_accounts_filter::Filter,
+    subscribe_request_filter_accounts_filter_memcmp::Data, SubscribeRequestFilterAccounts,
+    SubscribeRequestFilterAccountsFilter, SubscribeRequestFilterAccountsFilterMemcmp,
+};
+
+pub const TOKEN_PROGRAM: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const ASSOCIATED_TOKEN_PROGRAM: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+/// The mint is the first field of an SPL token account (Token-2022 keeps the layout).
+const MINT_OFFSET: u64 = 0;
+
+fn parse_keys(var: &str, raw: &str) -> Result<Vec<Pubkey>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| Pubkey::from_str(s).with_context(|| format!("{var}: invalid pubkey {s}")))
+        .collect()
+}
+
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM,
+    )
+    .0
+}
+
+/// Account filters keyed by filter name; without mints, the usual catch-all.
+pub fn account_filters(
+    mints: &[Pubkey],
+    wallets: &[Pubkey],
+) -> HashMap<String, SubscribeRequestFilterAccounts> {
+    if mints.is_empty() {
+        return HashMap::from([(String::new(), SubscribeRequestFilterAccounts::default())]);
+    }
+    let programs = [TOKEN_PROGRAM, TOKEN_2022_PROGRAM];
+    if !wallets.is_empty() {
+        // Which program a mint belongs to is unknown here; an ATA under the
+        // other program just never exists, so subscribe to both.
+        let account = wallets
+            .iter()
+            .flat_map(|w| mints.iter().map(move |m| (w, m)))
+            .flat_map(|(w, m)| {
+                programs
+                    .iter()
+                    .map(move |p| associated_token_address(w, m, p))
+            })
+            .map(|ata| ata.to_string())
+            .collect();
+        return HashMap::from([(
+            "mint_atas".to_string(),
+            SubscribeRequestFilterAccounts {
+                account,
+                ..Default::default()
+            },
+        )]);
+    }
+    mints
+        .iter()
+        .map(|mint| {
+            let filter = SubscribeRequestFilterAccounts {
+                owner: programs.iter().map(|p| p.to_string()).collect(),
+                filters: vec![
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(Filter::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                            offset: MINT_OFFSET,
+                            data: Some(Data::Bytes(mint.to_bytes().to_vec())),
+                        })),
+                    },
+                    // Excludes mint and multisig accounts owned by the same programs.
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(Filter::TokenAccountState(true)),
+                    },
+                ],
+                ..Default::default()
+            };
+            (format!("mint:{mint}"), filter)
+        })
+        .collect()
+}
+
+pub fn account_filters_from_env() -> Result<HashMap<String, SubscribeRequestFilterAccounts>> {
+    let var = |name: &str| std::env::var(name).unwrap_or_default();
+    let mints = parse_keys("YS_MINTS", &var("YS_MINTS"))?;
+    let wallets = parse_keys("YS_MINT_WALLETS", &var("YS_MINT_WALLETS"))?;
+    if mints.is_empty() && !wallets.is_empty() {
+        bail!("YS_MINT_WALLETS needs YS_MINTS to resolve associated token accounts");
+    }
+    Ok(account_filters(&mints, &wallets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qFJEzfJpmjT66nNGPBwSq9jXhN");
+
+    #[test]
+    fn associated_token_addresses_are_per_program_pdas() {
+        let wallet = pubkey!("9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM");
+        let classic = associated_token_address(&wallet, &USDC, &TOKEN_PROGRAM);
+        let t22 = associated_token_address(&wallet, &USDC, &TOKEN_2022_PROGRAM);
+        assert_ne!(classic, t22);
+        assert!(!classic.is_on_curve() && !t22.is_on_curve());
+    }
+
+    #[test]
+    fn mints_expand_to_owner_and_memcmp_filters() {
+        let filters = account_filters(&[USDC], &[]);
+        let f = &filters[&format!("mint:{USDC}")];
+        assert_eq!(
+            f.owner,
+            vec![TOKEN_PROGRAM.to_string(), TOKEN_2022_PROGRAM.to_string()]
+        );
+        assert!(matches!(
+            &f.filters[0].filter,
+            Some(Filter::Memcmp(m)) if m.offset == 0
+                && m.data == Some(Data::Bytes(USDC.to_bytes().to_vec()))
+        ));
+
+        let wallets = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let atas = account_filters(&[USDC], &wallets);
+        assert_eq!(atas["mint_atas"].account.len(), 4);
+        assert!(account_filters(&[], &[]).contains_key(""));
+    }
+}
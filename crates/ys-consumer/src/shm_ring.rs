@@ -2,10 +2,11 @@
 // crates/ys-consumer/src/shm_ring.rs
 #![deny(unsafe_code)]
 use memmap2::{MmapMut, MmapOptions};
-use metrics::counter;
+use metrics::{counter, histogram};
 use std::fs::OpenOptions;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 const HDR_LEN: usize = 64;
 const MAGIC: u32 = 0x59534D52; // 'YSMR'
@@ -18,6 +19,12 @@ const VERSION: u32 = 1;
 // 16..24 head (u64) - writer offset into body (0..capacity)
 // 24..32 tail (u64) - reader offset into body (0..capacity)
 // 32..64 reserved
+//
+// A writer waiting for space sleeps on the low 32 bits of `tail` as a shared
+// futex word. Readers may `FUTEX_WAKE` that address after advancing `tail` to
+// resume it immediately; readers that don't are picked up by a short poll.
+const TAIL_OFF: usize = 24;
+const WAIT_POLL: Duration = Duration::from_micros(500);
 
 fn read_u32_le(buf: &[u8], off: usize) -> u32 {
     u32::from_le_bytes([buf[off], buf[off + 1], buf[off + 2], buf[off + 3]])
@@ -114,7 +121,7 @@ impl ShmRingWriter {
 
     fn tail(&self) -> usize {
         // Reader-owned; writer only reads
-        read_u64_le(&self.mmap, 24) as usize
+        read_u64_le(&self.mmap, TAIL_OFF) as usize
     }
 
     #[inline]
@@ -132,17 +139,81 @@ impl ShmRingWriter {
         self.cap.saturating_sub(self.used_bytes(head, tail) + 1)
     }
 
-    /// Try to push a frame into the ring. Returns true on success, false if insufficient space.
-    pub fn try_push(&mut self, frame: &[u8]) -> bool {
+    /// Push a frame, waiting up to `max_wait` for the reader to free space
+    /// (`Duration::ZERO` never waits). Returns false if the frame was dropped.
+    /// Waits that end in a write are counted separately from true drops.
+    pub fn push_timeout(&mut self, frame: &[u8], max_wait: Duration) -> bool {
         let need = 4usize + frame.len();
         if need > self.cap {
             counter!("ys_consumer_shm_drop_oversized_total").increment(1);
             return false;
         }
+        if self.push_if_space(frame, need) {
+            return true;
+        }
+        if !max_wait.is_zero() {
+            let t0 = Instant::now();
+            let resumed = self.wait_for_space(need, t0 + max_wait);
+            histogram!("ys_consumer_shm_wait_us").record(t0.elapsed().as_secs_f64() * 1e6);
+            if resumed && self.push_if_space(frame, need) {
+                counter!("ys_consumer_shm_wait_total", "outcome" => "resumed").increment(1);
+                return true;
+            }
+            counter!("ys_consumer_shm_wait_total", "outcome" => "timeout").increment(1);
+        }
+        counter!("ys_consumer_shm_dropped_total", "reason" => "no_space").increment(1);
+        false
+    }
+
+    fn wait_for_space(&self, need: usize, deadline: Instant) -> bool {
+        loop {
+            let tail = self.tail();
+            if self.free_bytes(self.head(), tail) >= need {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            self.wait_tail_change(tail as u32, (deadline - now).min(WAIT_POLL));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[allow(unsafe_code)]
+    fn wait_tail_change(&self, seen: u32, timeout: Duration) {
+        let ts = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        // Shared (non-private) futex: the word lives in a MAP_SHARED mapping the
+        // reader process also maps.
+        let word = self.mmap[TAIL_OFF..TAIL_OFF + 4].as_ptr() as *const u32;
+        // SAFETY: `word` is 4-byte aligned (page-aligned mapping + 24) and stays
+        // mapped for the duration of the call. FUTEX_WAIT only reads it; EAGAIN,
+        // EINTR and ETIMEDOUT all fall through to the caller's re-check.
+        unsafe {
+            libc::syscall(
+                libc::SYS_futex,
+                word,
+                libc::FUTEX_WAIT,
+                seen,
+                &ts as *const libc::timespec,
+                std::ptr::null::<u32>(),
+                0u32,
+            );
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wait_tail_change(&self, _seen: u32, timeout: Duration) {
+        std::thread::sleep(timeout);
+    }
+
+    fn push_if_space(&mut self, frame: &[u8], need: usize) -> bool {
         let mut head = self.head();
         let tail = self.tail();
         if self.free_bytes(head, tail) < need {
-            counter!("ys_consumer_shm_dropped_total", "reason" => "no_space").increment(1);
             return false;
         }
         // Ensure contiguous space at end; if not, write wrap marker (len=0) and wrap to 0
@@ -165,3 +236,37 @@ impl ShmRingWriter {
         true
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_timeout_resumes_once_reader_frees_space() {
+        let path = std::env::temp_dir().join(format!("ys-shm-wait-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut ring = ShmRingWriter::open_or_create(&path, 64).unwrap();
+        let frame = [7u8; 20];
+        assert!(ring.push_timeout(&frame, Duration::ZERO));
+        assert!(ring.push_timeout(&frame, Duration::ZERO));
+        assert!(!ring.push_timeout(&frame, Duration::ZERO), "ring is full");
+        assert!(!ring.push_timeout(&frame, Duration::from_millis(5)));
+
+        // Stand-in reader: consume everything from a separate mapping.
+        let reader_path = path.clone();
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&reader_path)
+                .unwrap();
+            let mut map = map_writable_with_len(&file, HDR_LEN + 64).unwrap();
+            let head = read_u64_le(&map, 16);
+            write_u64_le(&mut map, TAIL_OFF, head);
+        });
+        assert!(ring.push_timeout(&frame, Duration::from_secs(5)));
+        reader.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+}
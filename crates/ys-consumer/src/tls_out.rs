@@ -0,0 +1,205 @@
+// Numan Thabit 2025
+// crates/ys-consumer/src/tls_out.rs
+//
+// TLS-over-TCP output for feeding a remote aggregator directly.
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsOutputConfig {
+    /// Remote `host:port`.
+    pub addr: String,
+    /// SNI / certificate name; defaults to the host part of `addr`.
+    pub server_name: String,
+    /// PEM bundle of trusted roots; the system store is used when unset.
+    pub ca_file: Option<PathBuf>,
+    /// Optional client certificate chain and key for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub connect_timeout: Duration,
+}
+
+impl TlsOutputConfig {
+    pub fn from_env() -> io::Result<Self> {
+        let addr = std::env::var("YS_TLS_ADDR").map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "YS_TLS_ADDR is required for the tls output",
+            )
+        })?;
+        let server_name = std::env::var("YS_TLS_SERVER_NAME")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| host_of(&addr).to_string());
+        let path_var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(PathBuf::from)
+        };
+        let connect_timeout_ms = std::env::var("YS_TLS_CONNECT_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(3_000);
+        Ok(Self {
+            addr,
+            server_name,
+            ca_file: path_var("YS_TLS_CA_FILE"),
+            client_cert: path_var("YS_TLS_CLIENT_CERT"),
+            client_key: path_var("YS_TLS_CLIENT_KEY"),
+            connect_timeout: Duration::from_millis(connect_timeout_ms),
+        })
+    }
+
+    pub fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let mut roots = RootCertStore::empty();
+        match &self.ca_file {
+            Some(path) => {
+                for cert in read_certs(path)? {
+                    roots.add(cert).map_err(invalid_data)?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs();
+                for cert in native.certs {
+                    let _ = roots.add(cert);
+                }
+            }
+        }
+        if roots.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no trusted root certificates for tls output",
+            ));
+        }
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(invalid_data)?
+                .with_root_certificates(roots);
+        let config = match (&self.client_cert, &self.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+                .map_err(invalid_data)?,
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "YS_TLS_CLIENT_CERT and YS_TLS_CLIENT_KEY must be set together",
+                ))
+            }
+        };
+        Ok(Arc::new(config))
+    }
+
+    pub fn connect(&self, client: &Arc<ClientConfig>, send_buf: usize) -> io::Result<TlsStream> {
+        let addr = self.addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("resolve {}", self.addr))
+        })?;
+        let tcp = TcpStream::connect_timeout(&addr, self.connect_timeout)?;
+        tcp.set_nodelay(true)?;
+        tcp.set_write_timeout(Some(Duration::from_secs(2)))?;
+        let _ = socket2::SockRef::from(&tcp).set_send_buffer_size(send_buf);
+        let name = ServerName::try_from(self.server_name.clone()).map_err(invalid_data)?;
+        let conn = ClientConnection::new(client.clone(), name).map_err(invalid_data)?;
+        let mut stream = StreamOwned::new(conn, tcp);
+        // Finish the handshake up front so certificate errors surface as connect failures.
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        crate::send_auth(&mut stream)?;
+        io::Write::flush(&mut stream)?;
+        Ok(stream)
+    }
+}
+
+fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map(|(h, _)| h).unwrap_or(addr);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn read_certs(path: &PathBuf) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    rustls_pemfile::certs(&mut reader).collect()
+}
+
+fn read_key(path: &PathBuf) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no private key in {}", path.display()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn host_of_strips_port_and_brackets() {
+        assert_eq!(host_of("collector.example:9000"), "collector.example");
+        assert_eq!(host_of("[::1]:9000"), "::1");
+        assert_eq!(host_of("localhost"), "localhost");
+    }
+
+    #[test]
+    fn tls_round_trip_to_local_listener() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ys-tls-out-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, cert.serialize_pem().unwrap()).unwrap();
+
+        let cert_der = CertificateDer::from(cert.serialize_der().unwrap());
+        let key_der = PrivateKeyDer::try_from(cert.serialize_private_key_der()).unwrap();
+        let server_cfg = Arc::new(
+            rustls::ServerConfig::builder_with_provider(Arc::new(
+                rustls::crypto::ring::default_provider(),
+            ))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der], key_der)
+            .unwrap(),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let conn = rustls::ServerConnection::new(server_cfg).unwrap();
+            let mut tls = StreamOwned::new(conn, sock);
+            let mut buf = [0u8; 5];
+            tls.read_exact(&mut buf).unwrap();
+            buf
+        });
+
+        let cfg = TlsOutputConfig {
+            addr: format!("127.0.0.1:{port}"),
+            server_name: "localhost".into(),
+            ca_file: Some(ca_path),
+            client_cert: None,
+            client_key: None,
+            connect_timeout: Duration::from_secs(2),
+        };
+        let client = cfg.client_config().unwrap();
+        let mut stream = cfg.connect(&client, 64 * 1024).unwrap();
+        stream.write_all(b"hello").unwrap();
+        stream.flush().unwrap();
+        assert_eq!(&server.join().unwrap(), b"hello");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}